@@ -1,6 +0,0 @@
-use dotenv;
-
-fn main() {
-    dotenv::dotenv().ok();
-    println!("cargo:rustc-env=IMAP_PASSWORD={}", std::env::var("IMAP_PASSWORD").unwrap());
-}
\ No newline at end of file
@@ -1,4 +1,9 @@
 pub use mailiner_core::ids::AccountId;
+use mailiner_core::platform::SecureStorage;
+use mailiner_core::{AccountConfig, Result};
+
+/// The backend of demo accounts, with generated mail and no server.
+pub const DEMO_BACKEND: &str = "mock://";
 
 pub struct Account {
     pub id: AccountId,
@@ -6,3 +11,25 @@ pub struct Account {
     pub email: String,
 }
 
+impl From<&AccountConfig> for Account {
+    fn from(config: &AccountConfig) -> Self {
+        Self {
+            id: config.account_id.clone(),
+            name: config.name.clone(),
+            email: config.email.clone(),
+        }
+    }
+}
+
+fn secret_key(account_id: &AccountId) -> String {
+    format!("password/{}", account_id)
+}
+
+/// The password the account logs in with, if it doesn't use OAuth.
+pub async fn load_password(storage: &dyn SecureStorage, account_id: &AccountId) -> Result<Option<String>> {
+    storage.get_secret(&secret_key(account_id)).await
+}
+
+pub async fn save_password(storage: &dyn SecureStorage, account_id: &AccountId, password: &str) -> Result<()> {
+    storage.set_secret(&secret_key(account_id), password).await
+}
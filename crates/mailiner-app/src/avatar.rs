@@ -0,0 +1,33 @@
+//! Looking up the pictures of senders, if the user allows it. Looking them
+//! up tells Gravatar and the DNS resolver who mailed the user, so it is off
+//! by default.
+
+use mailiner_core::avatar::{bimi_record_name, gravatar_url, parse_bimi_record};
+use serde_json::Value;
+
+use crate::http::get;
+
+/// The DNS-over-HTTPS resolver the BIMI records are looked up with.
+const RESOLVER: &str = "https://dns.google/resolve";
+
+/// The picture to try for `email`: the logo of its domain if it has a BIMI
+/// record, or else its Gravatar, which may not exist either.
+pub async fn remote_avatar(email: &str, size: u32) -> String {
+    match bimi_logo(email).await {
+        Some(logo) => logo,
+        None => gravatar_url(email, size),
+    }
+}
+
+async fn bimi_logo(email: &str) -> Option<String> {
+    let name = bimi_record_name(email)?;
+    let response = get(&format!("{}?name={}&type=TXT", RESOLVER, name)).await.ok()?;
+    let response: Value = serde_json::from_slice(&response).ok()?;
+    response["Answer"]
+        .as_array()?
+        .iter()
+        .filter_map(|answer| answer["data"].as_str())
+        // TXT records come quoted, and long ones split into several strings.
+        .map(|data| data.trim_matches('"').split("\" \"").collect::<String>())
+        .find_map(|record| parse_bimi_record(&record))
+}
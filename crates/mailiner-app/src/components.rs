@@ -1,8 +1,33 @@
+mod accountsetup;
+mod avatar;
+mod composer;
+mod contacts;
+mod contextmenu;
+mod conversationview;
+mod recipients;
+mod diagnostics;
+mod invitation;
 mod emailnavigation;
-mod messageview;
+mod shortcuts;
+mod signin;
+mod splitter;
+mod tabs;
+mod toast;
 mod sidebar;
 pub mod virtual_scroll;
 
+pub use accountsetup::AccountSetup;
+pub use avatar::Avatar;
+pub use composer::Composer;
+pub use contacts::ContactsView;
+pub use contextmenu::{ContextMenu, Menu, MenuAction, MenuItem};
+pub use conversationview::ConversationView;
+pub use diagnostics::Diagnostics;
 pub use emailnavigation::EmailNavigation;
-pub use messageview::MessageView;
+pub use invitation::InvitationCard;
+pub use shortcuts::{handle_key, ShortcutsOverlay, SEARCH_INPUT_ID};
 pub use sidebar::Sidebar;
+pub use signin::SignIn;
+pub use splitter::Splitter;
+pub use tabs::{activate_tab, close_tab, open_tab, TabBar};
+pub use toast::ToastHost;
@@ -0,0 +1,158 @@
+use dioxus::prelude::*;
+use mailiner_core::accounts::validate;
+use mailiner_core::{AccountConfig, ConnectionSecurity, ServerSettings};
+
+use crate::account::{AccountId, DEMO_BACKEND};
+use crate::context::AppContext;
+use crate::core_event::{UiBus, UiCommand};
+
+/// Asks the user for the settings of their account, when none is set up.
+#[component]
+pub fn AccountSetup() -> Element {
+    let bus = use_context::<UiBus>();
+    let ctx = use_context::<AppContext>();
+    let mut name = use_signal(String::new);
+    let mut email = use_signal(String::new);
+    let mut host = use_signal(String::new);
+    let mut port = use_signal(|| "993".to_string());
+    let mut starttls = use_signal(|| false);
+    let mut username = use_signal(String::new);
+    let mut password = use_signal(String::new);
+    let mut demo = use_signal(|| false);
+    let mut error = use_signal(|| None::<String>);
+
+    if !(ctx.account_setup)() {
+        return rsx! {};
+    }
+
+    let save = move |_| {
+        let email = email.read().trim().to_string();
+        let username = username.read().trim().to_string();
+        // Most servers log in with the address.
+        let username = if username.is_empty() { email.clone() } else { username };
+        let incoming = if demo() {
+            // The demo's mail is generated, the server is never connected.
+            ServerSettings {
+                host: "localhost".to_string(),
+                port: 993,
+                security: ConnectionSecurity::Tls,
+                username,
+                auth_method: None,
+            }
+        } else {
+            ServerSettings {
+                host: host.read().trim().to_string(),
+                port: port.read().trim().parse().unwrap_or(0),
+                security: if starttls() { ConnectionSecurity::StartTls } else { ConnectionSecurity::Tls },
+                username,
+                auth_method: None,
+            }
+        };
+        let config = AccountConfig {
+            account_id: AccountId::new(uuid::Uuid::new_v4().to_string()),
+            name: name.read().trim().to_string(),
+            email,
+            incoming,
+            outgoing: None,
+        };
+        match validate(&config) {
+            Ok(()) => {
+                error.set(None);
+                bus.send(UiCommand::SetUpAccount {
+                    config,
+                    backend: demo().then(|| DEMO_BACKEND.to_string()),
+                    password: if demo() { String::new() } else { password() },
+                });
+            }
+            Err(e) => error.set(Some(e.to_string())),
+        }
+    };
+
+    rsx! {
+        div {
+            id: "account-setup",
+
+            div {
+                class: "account-setup-panel",
+
+                h2 { "Set up your account" }
+
+                label {
+                    "Name"
+                    input {
+                        value: "{name}",
+                        oninput: move |e| name.set(e.value()),
+                    }
+                }
+                label {
+                    "Address"
+                    input {
+                        r#type: "email",
+                        value: "{email}",
+                        oninput: move |e| email.set(e.value()),
+                    }
+                }
+                label {
+                    "Mail from"
+                    select {
+                        onchange: move |e| demo.set(e.value() == "demo"),
+                        option { value: "imap", selected: !demo(), "IMAP server" }
+                        option { value: "demo", selected: demo(), "Demo mailbox" }
+                    }
+                }
+                if !demo() {
+                    label {
+                        "IMAP server"
+                        input {
+                            value: "{host}",
+                            oninput: move |e| host.set(e.value()),
+                        }
+                    }
+                    label {
+                        "Port"
+                        input {
+                            r#type: "number",
+                            value: "{port}",
+                            oninput: move |e| port.set(e.value()),
+                        }
+                    }
+                    label {
+                        "Security"
+                        select {
+                            onchange: move |e| starttls.set(e.value() == "starttls"),
+                            option { value: "tls", selected: !starttls(), "TLS" }
+                            option { value: "starttls", selected: starttls(), "STARTTLS" }
+                        }
+                    }
+                    label {
+                        "User name, if not the address"
+                        input {
+                            value: "{username}",
+                            oninput: move |e| username.set(e.value()),
+                        }
+                    }
+                    label {
+                        "Password"
+                        input {
+                            r#type: "password",
+                            value: "{password}",
+                            oninput: move |e| password.set(e.value()),
+                        }
+                    }
+                }
+
+                if let Some(error) = error() {
+                    p {
+                        class: "error",
+                        "{error}"
+                    }
+                }
+
+                button {
+                    onclick: save,
+                    "Add account"
+                }
+            }
+        }
+    }
+}
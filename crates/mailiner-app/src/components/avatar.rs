@@ -0,0 +1,58 @@
+use dioxus::prelude::*;
+use mailiner_core::avatar::{color, initials};
+
+use crate::avatar::remote_avatar;
+use crate::context::AppContext;
+
+/// The picture of a sender: their initials on a color of their own, or, if
+/// the user allows looking them up, their Gravatar or their domain's logo.
+#[component]
+pub fn Avatar(name: Option<String>, email: String, #[props(default = 32)] size: u32) -> Element {
+    let ctx = use_context::<AppContext>();
+    let mut avatars = ctx.avatars;
+    let settings = ctx.settings;
+    let remote = settings.read().remote_avatars;
+
+    let key = email.trim().to_lowercase();
+    let lookup_key = key.clone();
+    use_effect(move || {
+        if !settings.read().remote_avatars || lookup_key.is_empty() || avatars.peek().contains_key(&lookup_key) {
+            return;
+        }
+        // Cached right away, so that the other messages of the sender don't
+        // look it up again.
+        avatars.write().insert(lookup_key.clone(), None);
+        let email = lookup_key.clone();
+        spawn(async move {
+            let url = remote_avatar(&email, size * 2).await;
+            avatars.write().insert(email, Some(url));
+        });
+    });
+
+    let picture = if remote { avatars.read().get(&key).cloned().flatten() } else { None };
+    let letters = initials(name.as_deref(), &email);
+    let style = format!("width: {size}px; height: {size}px; font-size: {}px;", size * 2 / 5);
+    let background = format!("{style} background-color: {};", color(&email));
+
+    rsx! {
+        if let Some(picture) = picture {
+            img {
+                class: "avatar",
+                style,
+                src: picture,
+                alt: letters,
+                // There is no Gravatar, or the logo failed to load.
+                onerror: move |_| {
+                    avatars.write().insert(key.clone(), None);
+                },
+            }
+        } else {
+            span {
+                class: "avatar",
+                style: background,
+                aria_hidden: true,
+                "{letters}"
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use dioxus::prelude::*;
+use mailiner_core::compose::markdown::markdown_to_html;
+use mailiner_core::EmailAddr;
+use web_sys::HtmlTextAreaElement;
+use web_sys::wasm_bindgen::JsCast;
+
+use crate::components::recipients::{is_valid, RecipientField};
+use crate::context::AppContext;
+use crate::core_event::{UiBus, UiCommand};
+use crate::mailbox::find_by_name;
+
+const BODY_ID: &str = "composer-body";
+
+/// What the toolbar buttons do to the selected text of the body.
+#[derive(PartialEq, Clone, Copy)]
+enum Format {
+    Bold,
+    Italic,
+    Link,
+    List,
+    NumberedList,
+    Quote,
+}
+
+impl Format {
+    const ALL: [(Format, &'static str); 6] = [
+        (Format::Bold, "Bold"),
+        (Format::Italic, "Italic"),
+        (Format::Link, "Link"),
+        (Format::List, "List"),
+        (Format::NumberedList, "Numbered list"),
+        (Format::Quote, "Quote"),
+    ];
+
+    /// Applies the format to the bytes `start..end` of `text`.
+    fn apply(self, text: &str, start: usize, end: usize) -> String {
+        match self {
+            Format::Bold => wrap(text, start, end, "**", "**", "bold text"),
+            Format::Italic => wrap(text, start, end, "*", "*", "italic text"),
+            Format::Link => wrap(text, start, end, "[", "](https://)", "link text"),
+            Format::List => prefix_lines(text, start, end, |_| "- ".to_string()),
+            Format::NumberedList => prefix_lines(text, start, end, |i| format!("{}. ", i + 1)),
+            Format::Quote => prefix_lines(text, start, end, |_| "> ".to_string()),
+        }
+    }
+}
+
+fn wrap(text: &str, start: usize, end: usize, before: &str, after: &str, placeholder: &str) -> String {
+    let selected = if start == end { placeholder } else { &text[start..end] };
+    format!("{}{}{}{}{}", &text[..start], before, selected, after, &text[end..])
+}
+
+fn prefix_lines(text: &str, start: usize, end: usize, prefix: impl Fn(usize) -> String) -> String {
+    let line_start = text[..start].rfind('\n').map_or(0, |pos| pos + 1);
+    let lines: Vec<String> = text[line_start..end]
+        .split('\n')
+        .enumerate()
+        .map(|(i, line)| format!("{}{}", prefix(i), line))
+        .collect();
+    format!("{}{}{}", &text[..line_start], lines.join("\n"), &text[end..])
+}
+
+/// The selection in the body, as byte offsets into `text`.
+fn selection(text: &str) -> Option<(usize, usize)> {
+    let element = web_sys::window()?.document()?.get_element_by_id(BODY_ID)?;
+    let textarea: HtmlTextAreaElement = element.dyn_into().ok()?;
+    let start = textarea.selection_start().ok()??;
+    let end = textarea.selection_end().ok()??;
+    Some((byte_offset(text, start as usize), byte_offset(text, end as usize)))
+}
+
+/// Converts an offset in UTF-16 code units, as the DOM counts, to bytes.
+fn byte_offset(text: &str, utf16_offset: usize) -> usize {
+    let mut units = 0;
+    for (pos, c) in text.char_indices() {
+        if units >= utf16_offset {
+            return pos;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+/// Writes a new message. In rich text mode the body is Markdown, shown
+/// rendered next to it and sent as HTML with a plain text alternative.
+#[component]
+pub fn Composer() -> Element {
+    let bus = use_context::<UiBus>();
+    let ctx = use_context::<AppContext>();
+    let mailboxes = ctx.mailbox_nodes;
+    let toasts = ctx.toasts;
+    let tabs = ctx.tabs;
+    let mut composition = ctx.composition;
+    let initial = composition.peek().clone().unwrap_or_default();
+
+    let to = use_signal(|| initial.to.clone());
+    let cc = use_signal(|| initial.cc.clone());
+    let bcc = use_signal(|| initial.bcc.clone());
+    let mut subject = use_signal(|| initial.subject.clone());
+    let mut body = use_signal(|| initial.body_text.clone());
+    let mut rich_text = use_signal(|| initial.send_plain_text != Some(true));
+
+    let preview = use_memo(move || markdown_to_html(&body.read()));
+
+    let mut format = move |format: Format| {
+        let text = body.peek().clone();
+        let (start, end) = selection(&text).unwrap_or((text.len(), text.len()));
+        body.set(format.apply(&text, start, end));
+    };
+
+    // The edits are kept in the composition, and in its tab, so that they
+    // survive switching to another tab and back. The body stays Markdown
+    // until the draft is saved.
+    let mut edited = move || {
+        let mut draft = composition.peek().clone().unwrap_or_default();
+        draft.to = to.peek().clone();
+        draft.cc = cc.peek().clone();
+        draft.bcc = bcc.peek().clone();
+        draft.subject = subject.peek().clone();
+        draft.body_text = body.peek().clone();
+        draft.body_html = None;
+        draft.send_plain_text = if rich_text() { None } else { Some(true) };
+        tabs.edit_draft(draft.clone());
+        composition.set(Some(draft));
+    };
+
+    let save = move |_| {
+        let Some(mailbox_id) = find_by_name(&mailboxes.read(), &["drafts"]) else {
+            toasts.error("There is no Drafts folder to save the message in");
+            return;
+        };
+        let invalid: Vec<EmailAddr> = [to, cc, bcc]
+            .iter()
+            .flat_map(|field| field.read().clone())
+            .filter(|addr| !is_valid(addr))
+            .collect();
+        if let Some(addr) = invalid.first() {
+            toasts.error(format!("{} is not a valid address", addr.to_string()));
+            return;
+        }
+        let mut draft = composition.peek().clone().unwrap_or_default();
+        draft.to = to.read().clone();
+        draft.cc = cc.read().clone();
+        draft.bcc = bcc.read().clone();
+        draft.subject = subject.read().clone();
+        if rich_text() {
+            draft.set_markdown(&body.read());
+            draft.send_plain_text = None;
+        } else {
+            draft.body_text = body.read().clone();
+            draft.body_html = None;
+            draft.send_plain_text = Some(true);
+        }
+        bus.send(UiCommand::SaveDraft { mailbox_id, composition: draft });
+    };
+
+    rsx! {
+        section {
+            id: "composer",
+
+            div {
+                class: "composer-fields",
+
+                RecipientField {
+                    label: "To",
+                    recipients: to,
+                    onchange: move |_| edited(),
+                }
+                RecipientField {
+                    label: "Cc",
+                    recipients: cc,
+                    onchange: move |_| edited(),
+                }
+                RecipientField {
+                    label: "Bcc",
+                    recipients: bcc,
+                    onchange: move |_| edited(),
+                }
+                input {
+                    placeholder: "Subject",
+                    value: "{subject}",
+                    oninput: move |e| {
+                        subject.set(e.value());
+                        edited();
+                    },
+                }
+            }
+
+            div {
+                class: "composer-toolbar",
+
+                label {
+                    input {
+                        r#type: "checkbox",
+                        checked: rich_text(),
+                        onchange: move |e| {
+                            rich_text.set(e.checked());
+                            edited();
+                        },
+                    }
+                    "Rich text"
+                }
+
+                if rich_text() {
+                    for (action, label) in Format::ALL {
+                        button {
+                            key: "{label}",
+                            onclick: move |_| {
+                                format(action);
+                                edited();
+                            },
+                            "{label}"
+                        }
+                    }
+                }
+            }
+
+            div {
+                class: "composer-body",
+
+                textarea {
+                    id: BODY_ID,
+                    value: "{body}",
+                    oninput: move |e| {
+                        body.set(e.value());
+                        edited();
+                    },
+                }
+
+                if rich_text() {
+                    // The Markdown conversion escapes any HTML the user typed.
+                    div {
+                        class: "composer-preview",
+                        dangerous_inner_html: "{preview}",
+                    }
+                }
+            }
+
+            div {
+                class: "composer-actions",
+
+                button {
+                    onclick: save,
+                    "Save draft"
+                }
+                button {
+                    onclick: move |_| composition.set(None),
+                    "Discard"
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use chrono::Utc;
+use dioxus::prelude::*;
+use mailiner_core::contacts::{self, duplicates, parse_address, Contact};
+use mailiner_core::ContactId;
+
+use crate::components::{Avatar, ToastHost};
+use crate::context::AppContext;
+use crate::core_event::{UiBus, UiCommand};
+use crate::Route;
+
+/// The address book: the contacts, a search over them, and the selected
+/// one to edit.
+#[component]
+pub fn ContactsView() -> Element {
+    let bus = use_context::<UiBus>();
+    let ctx = use_context::<AppContext>();
+    let contacts = ctx.contacts;
+    let mut query = use_signal(String::new);
+    let mut selected = use_signal(|| None::<ContactId>);
+
+    let list_bus = bus.clone();
+    use_hook(move || list_bus.send(UiCommand::ListContacts));
+
+    let all = contacts.read().clone();
+    let groups: Vec<Vec<Contact>> = duplicates(&all)
+        .into_iter()
+        .map(|group| group.into_iter().map(|i| all[i].clone()).collect())
+        .collect();
+    // The most frecent first, also without a query.
+    let found = contacts::search(all.clone(), &query.read(), usize::MAX);
+    let contact = selected.read().as_ref().and_then(|id| all.iter().find(|contact| contact.id == *id).cloned());
+
+    let merge_bus = bus.clone();
+
+    rsx! {
+        div {
+            id: "contacts-view",
+
+            header {
+                Link {
+                    to: Route::MainView {},
+                    "← Mail"
+                }
+                input {
+                    r#type: "search",
+                    placeholder: "Search contacts",
+                    value: "{query}",
+                    oninput: move |e| query.set(e.value()),
+                }
+                button {
+                    onclick: move |_| selected.set(Some(ContactId::new(String::new()))),
+                    "Add contact"
+                }
+            }
+
+            div {
+                id: "contacts-panes",
+
+                ul {
+                    id: "contact-list",
+
+                    for contact in found {
+                        li {
+                            key: "{contact.id}",
+                            class: if selected.read().as_ref() == Some(&contact.id) { "selected" },
+                            onclick: {
+                                let id = contact.id.clone();
+                                move |_| selected.set(Some(id.clone()))
+                            },
+                            Avatar {
+                                name: contact.name().map(str::to_string),
+                                email: contact.addresses.first().cloned().unwrap_or_default(),
+                            }
+                            div {
+                                class: "contact-name",
+                                "{contact.name().unwrap_or_default()}"
+                            }
+                            div {
+                                class: "contact-address",
+                                {contact.addresses.join(", ")}
+                            }
+                        }
+                    }
+                }
+
+                section {
+                    id: "contact-detail",
+
+                    // A new id is selected to add a contact.
+                    if let Some(id) = selected() {
+                        ContactEditor {
+                            key: "{id}",
+                            contact: contact.unwrap_or_else(|| Contact::new("", Utc::now())),
+                            selected,
+                        }
+                    }
+
+                    if !groups.is_empty() {
+                        div {
+                            class: "contact-duplicates",
+                            h3 { "Possible duplicates" }
+                            for group in groups {
+                                div {
+                                    key: "{group[0].id}",
+                                    class: "duplicate-group",
+                                    span {
+                                        {group.iter().map(describe).collect::<Vec<_>>().join(" · ")}
+                                    }
+                                    button {
+                                        title: "Merge into the first one",
+                                        onclick: {
+                                            let bus = merge_bus.clone();
+                                            let ids: Vec<ContactId> = group.iter().map(|contact| contact.id.clone()).collect();
+                                            move |_| bus.send(UiCommand::MergeContacts(ids.clone()))
+                                        },
+                                        "Merge"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            ToastHost {
+            }
+        }
+    }
+}
+
+/// A contact's name and addresses, e.g. `Ana Lima <ana@example.com>`.
+fn describe(contact: &Contact) -> String {
+    match contact.name() {
+        Some(name) => format!("{} <{}>", name, contact.addresses.join(", ")),
+        None => contact.addresses.join(", "),
+    }
+}
+
+/// Edits a contact, or a new one, whose addresses are then empty.
+#[component]
+fn ContactEditor(contact: Contact, selected: Signal<Option<ContactId>>) -> Element {
+    let bus = use_context::<UiBus>();
+    let toasts = use_context::<AppContext>().toasts;
+    let is_new = contact.addresses.iter().all(String::is_empty);
+    let mut name = use_signal(|| contact.name().unwrap_or_default().to_string());
+    let mut addresses = use_signal(|| contact.addresses.join("\n"));
+
+    let save_bus = bus.clone();
+    let original = contact.clone();
+    let save = move |_| {
+        let typed: Vec<String> = addresses
+            .read()
+            .lines()
+            .map(str::trim)
+            .filter(|address| !address.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        if typed.is_empty() {
+            toasts.error("A contact needs an address");
+            return;
+        }
+        if let Some(invalid) = typed.iter().find(|address| parse_address(address).is_none()) {
+            toasts.error(format!("{} is not a valid address", invalid));
+            return;
+        }
+        let mut contact = if is_new { Contact::new(&typed[0], Utc::now()) } else { original.clone() };
+        // The name shown is the first one, the others were seen in mail.
+        let name = name.read().trim().to_string();
+        contact.names.retain(|known| *known != name);
+        if !name.is_empty() {
+            contact.names.insert(0, name);
+        }
+        contact.addresses = typed;
+        selected.set(Some(contact.id.clone()));
+        save_bus.send(UiCommand::SaveContact(contact));
+    };
+
+    let delete_bus = bus.clone();
+    let delete_id = contact.id.clone();
+    let search_address = contact.addresses.first().cloned().unwrap_or_default();
+
+    rsx! {
+        div {
+            class: "contact-editor",
+
+            label {
+                "Name"
+                input {
+                    value: "{name}",
+                    oninput: move |e| name.set(e.value()),
+                }
+            }
+            label {
+                "Addresses, one per line"
+                textarea {
+                    value: "{addresses}",
+                    oninput: move |e| addresses.set(e.value()),
+                }
+            }
+
+            div {
+                class: "contact-actions",
+
+                button {
+                    onclick: save,
+                    "Save"
+                }
+                if !is_new {
+                    button {
+                        onclick: move |_| {
+                            // The search lists the mail of the account, so
+                            // the view goes back to it.
+                            bus.send(UiCommand::Search(format!("from:{}", search_address)));
+                            navigator().push(Route::MainView {});
+                        },
+                        "Show all mail"
+                    }
+                    button {
+                        onclick: move |_| {
+                            delete_bus.send(UiCommand::DeleteContact(delete_id.clone()));
+                            selected.set(None);
+                        },
+                        "Delete"
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use dioxus::prelude::*;
+
+use crate::core_event::{UiBus, UiCommand};
+
+/// An open context menu: where it was opened and what is in it.
+#[derive(Clone)]
+pub struct Menu {
+    pub x: f64,
+    pub y: f64,
+    pub items: Vec<MenuItem>,
+}
+
+impl Menu {
+    /// A menu at the pointer of `event`, the `contextmenu` event that opens it.
+    pub fn at(event: &MouseEvent, items: Vec<MenuItem>) -> Self {
+        let point = event.client_coordinates();
+        Self { x: point.x, y: point.y, items }
+    }
+}
+
+#[derive(Clone)]
+pub struct MenuItem {
+    pub label: String,
+    pub action: MenuAction,
+}
+
+impl MenuItem {
+    pub fn new(label: impl Into<String>, action: MenuAction) -> Self {
+        Self { label: label.into(), action }
+    }
+}
+
+/// What choosing a menu item does.
+#[derive(Clone)]
+pub enum MenuAction {
+    Command(UiCommand),
+    /// Sends the command once the user confirmed `question`.
+    Confirm { question: String, command: UiCommand },
+    /// Asks for a name, proposing `default`, and sends the command `command`
+    /// makes of it.
+    Ask {
+        question: String,
+        default: String,
+        command: Arc<dyn Fn(String) -> UiCommand>,
+    },
+    Submenu(Vec<MenuItem>),
+}
+
+/// Shows the menu in `menu` while there is one. Choosing an item or
+/// clicking anywhere else closes it.
+#[component]
+pub fn ContextMenu(menu: Signal<Option<Menu>>) -> Element {
+    let mut menu = menu;
+    let bus = use_context::<UiBus>();
+    let select = use_callback(move |action: MenuAction| {
+        menu.set(None);
+        run(&bus, action);
+    });
+
+    let Some(open) = menu.read().clone() else {
+        return rsx! {};
+    };
+
+    rsx! {
+        div {
+            class: "context-menu-backdrop",
+            onclick: move |_| menu.set(None),
+            oncontextmenu: move |e| {
+                e.prevent_default();
+                menu.set(None);
+            },
+        }
+        ul {
+            class: "context-menu",
+            style: "left: {open.x}px; top: {open.y}px;",
+            oncontextmenu: move |e| e.prevent_default(),
+
+            {menu_items(&open.items, select)}
+        }
+    }
+}
+
+fn menu_items(items: &[MenuItem], select: Callback<MenuAction>) -> Element {
+    rsx! {
+        for MenuItem { label, action } in items.iter().cloned() {
+            if let MenuAction::Submenu(submenu) = &action {
+                li {
+                    class: "submenu",
+                    "{label}"
+
+                    ul {
+                        class: "context-menu",
+                        {menu_items(submenu, select)}
+                    }
+                }
+            } else {
+                li {
+                    onclick: move |e| {
+                        e.stop_propagation();
+                        select.call(action.clone());
+                    },
+                    "{label}"
+                }
+            }
+        }
+    }
+}
+
+fn run(bus: &UiBus, action: MenuAction) {
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    match action {
+        MenuAction::Command(command) => bus.send(command),
+        MenuAction::Confirm { question, command } => {
+            if window.confirm_with_message(&question).unwrap_or(false) {
+                bus.send(command);
+            }
+        }
+        MenuAction::Ask { question, default, command } => {
+            let answer = window.prompt_with_message_and_default(&question, &default);
+            if let Ok(Some(name)) = answer {
+                let name = name.trim();
+                if !name.is_empty() {
+                    bus.send(command(name.to_string()));
+                }
+            }
+        }
+        // Submenus open on hover.
+        MenuAction::Submenu(_) => {}
+    }
+}
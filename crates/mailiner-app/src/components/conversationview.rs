@@ -0,0 +1,202 @@
+use std::sync::Arc;
+
+use dioxus::prelude::*;
+use mailiner_core::MessageContent;
+
+use crate::components::{Avatar, InvitationCard};
+use crate::context::AppContext;
+use crate::core_event::{UiBus, UiCommand};
+use crate::message::Message;
+
+/// The conversation of the selected message, oldest message first. The
+/// newest and the unread messages are expanded, the others show only who
+/// sent them and when.
+#[component]
+pub fn ConversationView() -> Element {
+    let ctx = use_context::<AppContext>();
+    let conversation = ctx.conversation.read();
+    let subject = conversation.first().map(|message| message.subject.clone());
+    let newest = conversation.len().saturating_sub(1);
+    let messages: Vec<(String, Arc<Message>, bool)> = conversation
+        .iter()
+        .enumerate()
+        .map(|(i, message)| (message.id.to_string(), Arc::clone(message), i == newest || !message.is_read))
+        .collect();
+
+    rsx! {
+        section {
+            id: "conversationview",
+
+            if let Some(subject) = subject {
+                h2 {
+                    class: "conversation-subject",
+                    "{subject}"
+                }
+            }
+
+            for (key, message, expanded) in messages {
+                ConversationMessage {
+                    key: "{key}",
+                    message,
+                    expanded,
+                }
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Props)]
+pub struct ConversationMessageProps {
+    pub message: Arc<Message>,
+    /// Whether the message starts expanded.
+    pub expanded: bool,
+}
+
+#[component]
+pub fn ConversationMessage(props: ConversationMessageProps) -> Element {
+    let bus = use_context::<UiBus>();
+    let ctx = use_context::<AppContext>();
+    let mut expanded = use_signal(|| props.expanded);
+    let message = props.message;
+
+    // The body is loaded the first time the message is expanded.
+    let mut requested = use_signal(|| false);
+    let load_bus = bus.clone();
+    let load_id = message.id.clone();
+    use_effect(move || {
+        if expanded() && !*requested.peek() && !ctx.bodies.peek().contains_key(&load_id) {
+            requested.set(true);
+            load_bus.send(UiCommand::LoadBody(load_id.clone()));
+        }
+    });
+
+    let body = ctx.bodies.read().get(&message.id).cloned();
+    let trackers_blocked = ctx.trackers_blocked.read().get(&message.id).copied();
+    let invitation = ctx.invitations.read().get(&message.id).cloned();
+
+    let action = move |command: UiCommand| {
+        let bus = bus.clone();
+        move |e: MouseEvent| {
+            e.stop_propagation();
+            bus.send(command.clone());
+        }
+    };
+
+    rsx! {
+        article {
+            class: "conversation-message",
+            class: if expanded() { "expanded" },
+            class: if !message.is_read { "unread" },
+
+            header {
+                onclick: move |_| expanded.set(!expanded()),
+
+                Avatar {
+                    name: message.sender_name.clone(),
+                    email: message.sender_email.clone(),
+                    size: 40,
+                }
+                div {
+                    class: "message-from",
+                    "{message.from}"
+                }
+                div {
+                    class: "message-date",
+                    "{message.date}"
+                }
+            }
+
+            if expanded() {
+                div {
+                    class: "message-recipients",
+                    "To: {message.to}"
+                    if let Some(cc) = &message.cc {
+                        br {}
+                        "Cc: {cc}"
+                    }
+                }
+
+                if let Some(unsubscribe) = &message.unsubscribe {
+                    div {
+                        class: "unsubscribe-banner",
+                        "This message is from a mailing list."
+                        // Without one click or an address to write to, the
+                        // list's page has to be opened.
+                        if let (false, None, Some(url)) = (unsubscribe.one_click, &unsubscribe.mailto, &unsubscribe.url) {
+                            a {
+                                href: "{url}",
+                                target: "_blank",
+                                rel: "noopener noreferrer",
+                                "Unsubscribe"
+                            }
+                        } else {
+                            button {
+                                onclick: action(UiCommand::Unsubscribe(message.id.clone())),
+                                "Unsubscribe"
+                            }
+                        }
+                    }
+                }
+
+                if let Some(trackers) = trackers_blocked {
+                    div {
+                        class: "trackers-banner",
+                        if trackers == 1 { "1 tracker blocked" } else { "{trackers} trackers blocked" }
+                        button {
+                            title: "Load the message with its tracking images and links",
+                            onclick: action(UiCommand::AllowTrackers(message.id.clone())),
+                            "Allow"
+                        }
+                    }
+                }
+
+                if let Some((invitation, answer)) = invitation {
+                    InvitationCard {
+                        message_id: message.id.clone(),
+                        invitation,
+                        answer,
+                    }
+                }
+
+                if let Some(MessageContent::Html(html)) = &body {
+                    // Sanitized by the core before it was sent to the UI.
+                    div {
+                        class: "message-body html",
+                        dangerous_inner_html: "{html}",
+                    }
+                } else if let Some(MessageContent::Text(text)) = &body {
+                    div {
+                        class: "message-body",
+                        "{text}"
+                    }
+                } else {
+                    div {
+                        class: "message-body loading",
+                        "Loading…"
+                    }
+                }
+
+                div {
+                    class: "message-actions",
+
+                    button {
+                        onclick: action(UiCommand::Reply { message_id: message.id.clone(), all: false }),
+                        "Reply"
+                    }
+                    button {
+                        onclick: action(UiCommand::Reply { message_id: message.id.clone(), all: true }),
+                        "Reply all"
+                    }
+                    button {
+                        onclick: action(UiCommand::Forward(message.id.clone())),
+                        "Forward"
+                    }
+                    button {
+                        onclick: action(UiCommand::ExportMessage(message.id.clone())),
+                        "Save as .eml"
+                    }
+                }
+            }
+        }
+    }
+}
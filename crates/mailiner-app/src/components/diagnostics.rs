@@ -0,0 +1,50 @@
+use dioxus::prelude::*;
+use mailiner_core::metrics::{Histogram, COMMAND_LATENCY_MS, FETCH_BYTES, SYNC_DURATION_MS};
+
+use crate::context::AppContext;
+
+/// Performance figures of this session, to diagnose slow accounts.
+#[component]
+pub fn Diagnostics() -> Element {
+    let ctx = use_context::<AppContext>();
+    let snapshot = ctx.diagnostics.read().clone();
+    let histogram = |name: &str| snapshot.histograms.get(name).copied().unwrap_or_default();
+    let latency = histogram(COMMAND_LATENCY_MS);
+    let rows = [
+        ("Server requests", latency.count.to_string()),
+        ("Request latency", durations(&latency)),
+        ("Folder syncs", durations(&histogram(SYNC_DURATION_MS))),
+        ("Downloaded", format!("{:.1} KiB", histogram(FETCH_BYTES).sum / 1024.0)),
+        (
+            "Cache hit rate",
+            snapshot
+                .cache_hit_rate()
+                .map(|rate| format!("{:.0} %", rate * 100.0))
+                .unwrap_or_else(|| "-".to_string()),
+        ),
+    ];
+
+    rsx! {
+        details {
+            id: "diagnostics",
+
+            summary { "Diagnostics" }
+            table {
+                for (label, value) in rows {
+                    tr {
+                        key: "{label}",
+                        td { "{label}" }
+                        td { "{value}" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn durations(histogram: &Histogram) -> String {
+    match histogram.mean() {
+        Some(mean) => format!("{:.0} ms average, {:.0} ms max", mean, histogram.max),
+        None => "-".to_string(),
+    }
+}
@@ -1,26 +1,67 @@
+use std::rc::Rc;
+
 use dioxus::prelude::*;
 
 mod navigationheader;
 mod mailboxtreeview;
 mod messagelist;
 mod messagelist_virtual;
+mod searchbar;
 
 pub use navigationheader::NavigationHeader;
 pub use mailboxtreeview::MailboxTreeView;
 pub use messagelist::MessageList;
 pub use messagelist_virtual::{VirtualMessageList, MessageListWithRealData};
+pub use searchbar::{SearchBar, SearchResults};
 
 use crate::{components::emailnavigation::navigationheader::Mode, context::AppContext};
+use crate::core_event::{UiBus, UiCommand};
+
+/// How close to the end of the message list, in pixels, the next page of
+/// the mailbox is listed.
+const LOAD_MORE_DISTANCE: f64 = 400.0;
 
 #[component]
 pub fn EmailNavigation() -> Element {
+    let bus = use_context::<UiBus>();
     let ctx = use_context::<AppContext>();
+    let searching = ctx.search.read().is_some();
+    let loading_more = *ctx.loading_more_messages.read();
+    let mut navigation = use_signal(|| None::<Rc<MountedData>>);
+
+    // A page may not fill the pane, in which case there is nothing to
+    // scroll and the next one is listed right away.
+    let effect_bus = bus.clone();
+    let effect_ctx = ctx.clone();
+    use_effect(move || {
+        let _ = effect_ctx.messages.read().len();
+        if let Some(navigation) = navigation() {
+            spawn(load_more_if_near_end(effect_ctx.clone(), effect_bus.clone(), navigation));
+        }
+    });
+
+    let scroll_ctx = ctx.clone();
+
     rsx! {
         section {
             id: "emailnavigation",
+            onmounted: move |e| navigation.set(Some(e.data())),
+            onscroll: move |_| {
+                if let Some(navigation) = navigation() {
+                    spawn(load_more_if_near_end(scroll_ctx.clone(), bus.clone(), navigation));
+                }
+            },
+
+            SearchBar {
+            }
+
+            if searching {
+                SearchResults {
+                }
+            }
 
            div {
-                display: if ctx.selected_mailbox.read().is_none() { "block" } else { "none" },
+                display: if !searching && ctx.selected_mailbox.read().is_none() { "block" } else { "none" },
 
                 NavigationHeader {
                     mode: Mode::MailboxTreeView,
@@ -31,16 +72,51 @@ pub fn EmailNavigation() -> Element {
             }
 
             div {
-                display: if ctx.selected_mailbox.read().is_some() { "block" } else { "none" },
+                display: if !searching && ctx.selected_mailbox.read().is_some() { "block" } else { "none" },
 
                 NavigationHeader {
                     mode: Mode::MessageList,
                 }
 
-                // Use VirtualMessageList for demo, or MessageListWithRealData for production
-                VirtualMessageList {
+                MessageList {
+                }
+
+                if loading_more {
+                    div {
+                        class: "message-list-loading",
+                        "Loading more messages…"
+                    }
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Asks for the next page of the selected mailbox when `navigation` is
+/// scrolled to within [`LOAD_MORE_DISTANCE`] of its end, unless a page is
+/// being listed already or there is none left.
+async fn load_more_if_near_end(ctx: AppContext, bus: UiBus, navigation: Rc<MountedData>) {
+    let idle = |ctx: &AppContext| {
+        ctx.search.peek().is_none() && !*ctx.loading_more_messages.peek() && !*ctx.all_messages_loaded.peek()
+    };
+    if !idle(&ctx) {
+        return;
+    }
+    let (Ok(offset), Ok(size), Ok(rect)) = (
+        navigation.get_scroll_offset().await,
+        navigation.get_scroll_size().await,
+        navigation.get_client_rect().await,
+    ) else {
+        return;
+    };
+    // Another call may have asked while the sizes were measured.
+    if offset.y + rect.height() + LOAD_MORE_DISTANCE < size.height || !idle(&ctx) {
+        return;
+    }
+    let Some(mailbox_id) = ctx.selected_mailbox.peek().clone() else {
+        return;
+    };
+    let mut loading = ctx.loading_more_messages;
+    loading.set(true);
+    bus.send(UiCommand::LoadMoreEnvelopes(mailbox_id));
+}
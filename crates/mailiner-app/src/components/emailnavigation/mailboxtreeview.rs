@@ -1,10 +1,13 @@
+use std::sync::Arc;
+
 use dioxus::logger::tracing::info;
 use dioxus::prelude::*;
 use dioxus_heroicons::IconButton;
 use dioxus_heroicons::solid::Shape;
 
+use crate::components::{Menu, MenuAction, MenuItem};
 use crate::context::AppContext;
-use crate::core_event::CoreEvent;
+use crate::core_event::{UiBus, UiCommand};
 use crate::mailbox::MailboxId;
 
 #[component]
@@ -33,17 +36,23 @@ pub struct MailboxTreeViewItemProps {
 #[component]
 pub fn MailboxTreeViewItem(props: MailboxTreeViewItemProps) -> Element {
     let ctx = use_context::<AppContext>();
-    let core_tx = use_coroutine_handle::<CoreEvent>();
+    let bus = use_context::<UiBus>();
     let mailboxes = ctx.mailbox_nodes.read();
     let mailbox = mailboxes.get(&props.mailbox_id).unwrap();
     let mut children_visible = use_signal(|| false);
+    let mut context_menu = ctx.context_menu;
+    let menu = mailbox_menu(&props.mailbox_id, &mailbox.name);
     rsx! {
         div {
             class: "mailbox-tree-view-item",
 
             div {
                 onclick: move |_| {
-                    let _ = core_tx.send(CoreEvent::SelectMailbox(props.mailbox_id.clone()));
+                    bus.send(UiCommand::SelectMailbox(props.mailbox_id.clone()));
+                },
+                oncontextmenu: move |e| {
+                    e.prevent_default();
+                    context_menu.set(Some(Menu::at(&e, menu.clone())));
                 },
 
                 if mailbox.children.len() > 0 {
@@ -83,3 +92,35 @@ pub fn MailboxTreeViewItem(props: MailboxTreeViewItemProps) -> Element {
         }
     }
 }
+
+/// The context menu of a mailbox in the tree.
+fn mailbox_menu(mailbox_id: &MailboxId, name: &str) -> Vec<MenuItem> {
+    let rename_id = mailbox_id.clone();
+    let parent_id = mailbox_id.clone();
+    vec![
+        MenuItem::new("Mark all as read", MenuAction::Command(UiCommand::MarkMailboxRead(mailbox_id.clone()))),
+        MenuItem::new(
+            "Rename…",
+            MenuAction::Ask {
+                question: "New name".to_string(),
+                default: name.to_string(),
+                command: Arc::new(move |name| UiCommand::RenameMailbox { mailbox_id: rename_id.clone(), name }),
+            },
+        ),
+        MenuItem::new(
+            "Delete",
+            MenuAction::Confirm {
+                question: format!("Delete the folder \"{}\" and its messages?", name),
+                command: UiCommand::DeleteMailbox(mailbox_id.clone()),
+            },
+        ),
+        MenuItem::new(
+            "New subfolder…",
+            MenuAction::Ask {
+                question: "Name of the new folder".to_string(),
+                default: String::new(),
+                command: Arc::new(move |name| UiCommand::CreateMailbox { parent: Some(parent_id.clone()), name }),
+            },
+        ),
+    ]
+}
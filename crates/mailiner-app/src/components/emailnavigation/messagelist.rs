@@ -1,23 +1,40 @@
 use std::sync::Arc;
 
 use dioxus::prelude::*;
+use mailiner_core::grouping::sections;
 
+use crate::components::{open_tab, Avatar, Menu, MenuAction, MenuItem};
 use crate::context::AppContext;
-use crate::core_event::CoreEvent;
+use crate::core_event::{UiBus, UiCommand};
+use crate::mailbox::MailboxId;
 use crate::message::Message;
+use crate::tabs::TabContent;
 
 #[component]
 pub fn MessageList() -> Element {
     let ctx = use_context::<AppContext>();
     let messages = ctx.messages.read();
+    let sections = sections(messages.iter().map(|message| message.date_group));
 
     rsx! {
         div {
             id: "messagelist",
 
-            for message in messages.iter() {
-                MessageListItem {
-                    message: Arc::clone(&message),
+            for (group, range) in sections {
+                div {
+                    key: "{range.start}",
+                    class: "date-group",
+
+                    h3 {
+                        class: "date-group-header",
+                        "{group}"
+                    }
+
+                    for message in messages[range].iter() {
+                        MessageListItem {
+                            message: Arc::clone(&message),
+                        }
+                    }
                 }
             }
         }
@@ -31,9 +48,14 @@ pub struct MessageListItemProps {
 
 #[component]
 pub fn MessageListItem(props: MessageListItemProps) -> Element {
-    let core_tx = use_coroutine_handle::<CoreEvent>();
+    let bus = use_context::<UiBus>();
     let ctx = use_context::<AppContext>();
     let selected_message = ctx.selected_message.read();
+    let mut context_menu = ctx.context_menu;
+    let message = Arc::clone(&props.message);
+    let tab_message = Arc::clone(&props.message);
+    let tab_bus = bus.clone();
+    let tab_ctx = ctx.clone();
     rsx! {
         div {
             class: "message-list-item",
@@ -43,10 +65,80 @@ pub fn MessageListItem(props: MessageListItemProps) -> Element {
             },
 
             onclick: move |_| {
-                let _ = core_tx.send(CoreEvent::SelectMessage(props.message.id.clone()));
+                bus.send(UiCommand::SelectMessage(props.message.id.clone()));
+            },
+            // Opens the message in a tab of its own, to keep it at hand.
+            ondoubleclick: move |_| {
+                let content = TabContent::Message {
+                    message_id: tab_message.id.clone(),
+                    subject: tab_message.subject.clone(),
+                };
+                open_tab(&tab_ctx, &tab_bus, content);
             },
+            oncontextmenu: move |e| {
+                e.prevent_default();
+                context_menu.set(Some(Menu::at(&e, message_menu(&ctx, &message))));
+            },
+
+            Avatar {
+                name: props.message.sender_name.clone(),
+                email: props.message.sender_email.clone(),
+            }
 
             "{props.message.subject}"
         }
     }
 }
+
+/// The context menu of a message in the list.
+pub fn message_menu(ctx: &AppContext, message: &Message) -> Vec<MenuItem> {
+    let selected_mailbox = ctx.selected_mailbox.read().clone();
+    let mut targets: Vec<(String, MailboxId)> = ctx
+        .mailbox_nodes
+        .read()
+        .values()
+        .filter(|node| Some(&node.id) != selected_mailbox.as_ref())
+        .map(|node| (node.name.clone(), node.id.clone()))
+        .collect();
+    targets.sort();
+    let move_to = targets
+        .into_iter()
+        .map(|(name, mailbox_id)| {
+            let command = UiCommand::MoveMessage { message_id: message.id.clone(), mailbox_id };
+            MenuItem::new(name, MenuAction::Command(command))
+        })
+        .collect();
+    let label_id = message.id.clone();
+
+    vec![
+        MenuItem::new(
+            "Reply",
+            MenuAction::Command(UiCommand::Reply { message_id: message.id.clone(), all: false }),
+        ),
+        MenuItem::new(
+            "Reply all",
+            MenuAction::Command(UiCommand::Reply { message_id: message.id.clone(), all: true }),
+        ),
+        MenuItem::new("Forward", MenuAction::Command(UiCommand::Forward(message.id.clone()))),
+        MenuItem::new("Move to", MenuAction::Submenu(move_to)),
+        MenuItem::new(
+            "Label…",
+            MenuAction::Ask {
+                question: "Label".to_string(),
+                default: String::new(),
+                command: Arc::new(move |label| UiCommand::LabelMessage { message_id: label_id.clone(), label }),
+            },
+        ),
+        MenuItem::new(
+            if message.is_read { "Mark as unread" } else { "Mark as read" },
+            MenuAction::Command(UiCommand::MarkRead { message_id: message.id.clone(), read: !message.is_read }),
+        ),
+        MenuItem::new(
+            "Delete",
+            MenuAction::Confirm {
+                question: format!("Delete \"{}\" for good?", message.subject),
+                command: UiCommand::DeleteMessage(message.id.clone()),
+            },
+        ),
+    ]
+}
@@ -4,12 +4,16 @@ use std::pin::Pin;
 
 use dioxus::prelude::*;
 use mailiner_core::connector::EmailConnector;
+use mailiner_core::grouping::DateGroup;
 
+use crate::components::{Avatar, Menu};
 use crate::components::virtual_scroll::{VirtualScroll, VirtualScrollProps, prepend_message, VirtualScrollState};
 use crate::context::AppContext;
-use crate::core_event::CoreEvent;
+use crate::core_event::{UiBus, UiCommand};
 use crate::message::Message;
 
+use super::messagelist::message_menu;
+
 #[component]
 pub fn VirtualMessageList() -> Element {
     let ctx = use_context::<AppContext>();
@@ -30,9 +34,15 @@ pub fn VirtualMessageList() -> Element {
                 id: format!("msg-{}", i).into(),
                 subject: format!("Message {} - Important email about project updates", i),
                 from: format!("sender{}@example.com", i % 10),
+                sender_name: None,
+                sender_email: format!("sender{}@example.com", i % 10),
                 to: "you@example.com".to_string(),
                 cc: if i % 3 == 0 { Some("team@example.com".to_string()) } else { None },
                 bcc: None,
+                date: String::new(),
+                date_group: DateGroup::Today,
+                is_read: i % 4 != 0,
+                unsubscribe: None,
             });
             messages.push(message);
         }
@@ -41,19 +51,30 @@ pub fn VirtualMessageList() -> Element {
 
     let render_item = move |args: (usize, &Arc<Message>)| -> Element {
         let (index, message) = args;
-        let core_tx = use_coroutine_handle::<CoreEvent>();
+        let bus = use_context::<UiBus>();
         let ctx = use_context::<AppContext>();
         let selected_message = ctx.selected_message.read();
         let is_selected = selected_message.as_ref().map(|id| *id == message.id).unwrap_or(false);
+        let mut context_menu = ctx.context_menu;
+        let row = Arc::clone(message);
 
         rsx! {
             div {
                 class: "message-list-item",
                 class: if is_selected { "selected" },
                 onclick: move |_| {
-                    let _ = core_tx.send(CoreEvent::SelectMessage(message.id.clone()));
+                    bus.send(UiCommand::SelectMessage(message.id.clone()));
+                },
+                oncontextmenu: move |e| {
+                    e.prevent_default();
+                    context_menu.set(Some(Menu::at(&e, message_menu(&ctx, &row))));
                 },
 
+                Avatar {
+                    name: message.sender_name.clone(),
+                    email: message.sender_email.clone(),
+                }
+
                 div {
                     class: "message-list-item-content",
                     style: "padding: 12px; border-bottom: 1px solid #e0e0e0; cursor: pointer;",
@@ -132,7 +153,7 @@ pub fn MessageListWithRealData() -> Element {
 
     let render_item = move |args: (usize, &Arc<Message>)| -> Element {
         let (index, message) = args;
-        let core_tx = use_coroutine_handle::<CoreEvent>();
+        let bus = use_context::<UiBus>();
         let ctx = use_context::<AppContext>();
         let selected_message = ctx.selected_message.read();
         let is_selected = selected_message.as_ref().map(|id| *id == message.id).unwrap_or(false);
@@ -144,7 +165,7 @@ pub fn MessageListWithRealData() -> Element {
                 style: "padding: 12px; border-bottom: 1px solid #e0e0e0; cursor: pointer; background-color: {if is_selected { \"#e3f2fd\" } else { \"white\" }};",
 
                 onclick: move |_| {
-                    let _ = core_tx.send(CoreEvent::SelectMessage(message.id.clone()));
+                    bus.send(UiCommand::SelectMessage(message.id.clone()));
                 },
 
                 div {
@@ -0,0 +1,104 @@
+use dioxus::prelude::*;
+use dioxus_heroicons::solid::Shape;
+use dioxus_heroicons::{Icon, IconButton};
+
+use crate::components::{open_tab, SEARCH_INPUT_ID};
+use crate::context::AppContext;
+use crate::core_event::{UiBus, UiCommand};
+use crate::tabs::TabContent;
+
+use super::MessageList;
+
+/// The search field. Enter searches all folders of the account.
+#[component]
+pub fn SearchBar() -> Element {
+    let bus = use_context::<UiBus>();
+    let mut text = use_signal(String::new);
+
+    rsx! {
+        div {
+            id: "searchbar",
+
+            Icon {
+                size: 20,
+                icon: Shape::MagnifyingGlass,
+            }
+
+            input {
+                id: SEARCH_INPUT_ID,
+                r#type: "search",
+                placeholder: "Search, e.g. from:alice has:attachment after:2024-03-01",
+                value: "{text}",
+                oninput: move |e| text.set(e.value()),
+                onkeydown: move |e| {
+                    if e.key() == Key::Enter && !text.read().trim().is_empty() {
+                        bus.send(UiCommand::Search(text.read().trim().to_string()));
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// The messages found by the last search, listed like a mailbox.
+#[component]
+pub fn SearchResults() -> Element {
+    let bus = use_context::<UiBus>();
+    let ctx = use_context::<AppContext>();
+    let query = ctx.search.read().clone().unwrap_or_default();
+    let found = ctx.messages.read().len();
+
+    let tab_bus = bus.clone();
+    let tab_ctx = ctx.clone();
+    let tab_query = query.clone();
+    let mut search = ctx.search;
+    let mut messages = ctx.messages;
+    let selected_mailbox = ctx.selected_mailbox;
+    let close = move |_: MouseEvent| {
+        search.set(None);
+        messages.set(Vec::new());
+        // Back to the mailbox that was open before the search.
+        if let Some(mailbox_id) = selected_mailbox() {
+            bus.send(UiCommand::SelectMailbox(mailbox_id));
+        }
+    };
+
+    rsx! {
+        div {
+            id: "searchresults",
+
+            header {
+                id: "navigation-header",
+
+                IconButton {
+                    class: "back-button",
+                    onclick: close,
+                    size: 24,
+                    icon: Shape::ChevronLeft,
+                }
+
+                div {
+                    "Results for “{query}” ({found})"
+                }
+
+                IconButton {
+                    class: "open-tab-button",
+                    title: "Open in a tab",
+                    onclick: move |_| open_tab(&tab_ctx, &tab_bus, TabContent::Search(tab_query.clone())),
+                    size: 24,
+                    icon: Shape::ArrowTopRightOnSquare,
+                }
+            }
+
+            if found == 0 {
+                div {
+                    class: "no-results",
+                    "No messages found"
+                }
+            } else {
+                MessageList {
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use dioxus::prelude::*;
+use mailiner_core::calendar::{Attendee, Invitation, ParticipationStatus};
+
+use crate::core_event::{UiBus, UiCommand};
+use crate::message::MessageId;
+
+/// The answers offered to an invitation, with their button labels.
+const ANSWERS: [(ParticipationStatus, &str); 3] = [
+    (ParticipationStatus::Accepted, "Accept"),
+    (ParticipationStatus::Tentative, "Maybe"),
+    (ParticipationStatus::Declined, "Decline"),
+];
+
+/// An attendee's name, or their address without one.
+fn describe(attendee: &Attendee) -> String {
+    attendee.name.clone().unwrap_or_else(|| attendee.email.clone())
+}
+
+/// The event of a calendar invitation, with buttons to answer it when the
+/// user is invited. `answer` is the user's answer so far.
+#[component]
+pub fn InvitationCard(message_id: MessageId, invitation: Invitation, answer: Option<ParticipationStatus>) -> Element {
+    let bus = use_context::<UiBus>();
+    let cancelled = invitation.method.as_deref() == Some("CANCEL");
+    let attendees = invitation.attendees.iter().map(describe).collect::<Vec<_>>().join(", ");
+
+    rsx! {
+        div {
+            class: "invitation-card",
+            class: if cancelled { "cancelled" },
+
+            h3 {
+                if cancelled { "Cancelled: " }
+                {invitation.summary.clone().unwrap_or_else(|| "Event".to_string())}
+            }
+            dl {
+                if let Some(when) = &invitation.when {
+                    dt { "When" }
+                    dd { "{when}" }
+                }
+                if let Some(location) = &invitation.location {
+                    dt { "Where" }
+                    dd { "{location}" }
+                }
+                if let Some(organizer) = &invitation.organizer {
+                    dt { "Organizer" }
+                    dd { title: "{organizer.email}", {describe(organizer)} }
+                }
+                if !attendees.is_empty() {
+                    dt { "Attendees" }
+                    dd { "{attendees}" }
+                }
+            }
+
+            // Only the user's own answer can be sent, so only to invitations
+            // they're on.
+            if let (true, Some(answer)) = (invitation.is_request(), answer) {
+                div {
+                    class: "invitation-answers",
+
+                    for (status, label) in ANSWERS {
+                        button {
+                            key: "{label}",
+                            class: if answer == status { "selected" },
+                            onclick: {
+                                let bus = bus.clone();
+                                let message_id = message_id.clone();
+                                move |e: MouseEvent| {
+                                    e.stop_propagation();
+                                    bus.send(UiCommand::Rsvp { message_id: message_id.clone(), status });
+                                }
+                            },
+                            "{label}"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,11 +0,0 @@
-use dioxus::prelude::*;
-
-#[component]
-pub fn MessageView() -> Element {
-    rsx! {
-        section {
-            id: "messageview",
-            "MessageView"
-        }
-    }
-}
\ No newline at end of file
@@ -0,0 +1,189 @@
+use dioxus::prelude::*;
+use mailiner_core::contacts::{parse_address, Contact};
+use mailiner_core::EmailAddr;
+
+use crate::context::AppContext;
+use crate::core_event::{UiBus, UiCommand};
+
+/// Whether a recipient's address has a valid syntax.
+pub fn is_valid(addr: &EmailAddr) -> bool {
+    addr.email.as_deref().and_then(parse_address).is_some()
+}
+
+/// The text typed as a recipient, parsed if valid, else kept as it is to
+/// be shown as invalid.
+fn recipient(text: &str) -> EmailAddr {
+    parse_address(text).unwrap_or_else(|| EmailAddr { name: None, email: Some(text.to_string()) })
+}
+
+/// A To, Cc or Bcc field: the recipients as chips, followed by a field
+/// completing addresses from the contacts as they are typed.
+#[component]
+pub fn RecipientField(label: &'static str, recipients: Signal<Vec<EmailAddr>>, onchange: EventHandler<()>) -> Element {
+    let bus = use_context::<UiBus>();
+    let mut suggestions = use_context::<AppContext>().contact_suggestions;
+    let mut text = use_signal(String::new);
+    let mut focused = use_signal(|| false);
+    let mut highlighted = use_signal(|| 0usize);
+
+    // The suggestions are shared by the fields, so only the focused one
+    // shows them, and only while they match what it holds.
+    let shown: Vec<Contact> = {
+        let (prefix, contacts) = &*suggestions.read();
+        let typed = text.read().trim().to_string();
+        if focused() && !typed.is_empty() && *prefix == typed {
+            let added = recipients.read();
+            contacts
+                .iter()
+                .filter(|contact| {
+                    !added.iter().any(|addr| {
+                        addr.email.as_deref().is_some_and(|email| contact.addresses.contains(&email.to_lowercase()))
+                    })
+                })
+                .cloned()
+                .collect()
+        } else {
+            Vec::new()
+        }
+    };
+
+    let mut add = move |addrs: Vec<EmailAddr>| {
+        if addrs.is_empty() {
+            return;
+        }
+        recipients.write().extend(addrs);
+        text.set(String::new());
+        highlighted.set(0);
+        onchange.call(());
+    };
+
+    let suggest_bus = bus.clone();
+    let oninput = move |e: FormEvent| {
+        // Pasted lists become chips, except for what follows the last
+        // separator, which may still be typed on.
+        let value = e.value();
+        let mut parts: Vec<&str> = value.split([',', ';']).collect();
+        let rest = parts.pop().unwrap_or_default().to_string();
+        add(parts.into_iter().map(str::trim).filter(|part| !part.is_empty()).map(recipient).collect());
+        highlighted.set(0);
+        if !rest.trim().is_empty() {
+            suggest_bus.send(UiCommand::SuggestContacts(rest.trim().to_string()));
+        }
+        text.set(rest);
+    };
+
+    let keyboard_shown = shown.clone();
+    let onkeydown = move |e: KeyboardEvent| {
+        let typed = text.peek().trim().to_string();
+        let count = keyboard_shown.len();
+        match e.key() {
+            Key::ArrowDown if count > 0 => highlighted.set((highlighted() + 1) % count),
+            Key::ArrowUp if count > 0 => highlighted.set((highlighted() + count - 1) % count),
+            Key::Enter | Key::Tab if count > 0 => {
+                add(vec![keyboard_shown[highlighted().min(count - 1)].addr()]);
+            }
+            Key::Enter | Key::Tab if !typed.is_empty() => {
+                let tab = e.key() == Key::Tab;
+                add(vec![recipient(&typed)]);
+                if tab {
+                    // Moves on to the next field as usual.
+                    return;
+                }
+            }
+            Key::Escape if count > 0 => suggestions.set((String::new(), Vec::new())),
+            Key::Backspace if text.peek().is_empty() && !recipients.peek().is_empty() => {
+                recipients.write().pop();
+                onchange.call(());
+            }
+            _ => return,
+        }
+        e.prevent_default();
+        e.stop_propagation();
+    };
+
+    let chips: Vec<(usize, String, bool)> = recipients
+        .read()
+        .iter()
+        .enumerate()
+        .map(|(i, addr)| (i, addr.to_string(), is_valid(addr)))
+        .collect();
+
+    rsx! {
+        div {
+            class: "recipient-field",
+
+            label {
+                "{label}"
+            }
+
+            for (i, chip, valid) in chips {
+                span {
+                    key: "{i}-{chip}",
+                    class: "recipient-chip",
+                    class: if !valid { "invalid" },
+                    title: if !valid { "This is not a valid address" },
+                    "{chip}"
+                    button {
+                        title: "Remove",
+                        onclick: move |_| {
+                            recipients.write().remove(i);
+                            onchange.call(());
+                        },
+                        "×"
+                    }
+                }
+            }
+
+            div {
+                class: "recipient-input",
+
+                input {
+                    value: "{text}",
+                    oninput,
+                    onkeydown,
+                    onfocus: move |_| focused.set(true),
+                    onblur: move |_| {
+                        focused.set(false);
+                        // What was typed isn't lost when leaving the field.
+                        let typed = text.peek().trim().to_string();
+                        if !typed.is_empty() {
+                            add(vec![recipient(&typed)]);
+                        }
+                    },
+                }
+
+                if !shown.is_empty() {
+                    ul {
+                        class: "recipient-suggestions",
+                        role: "listbox",
+
+                        for (i, contact) in shown.into_iter().enumerate() {
+                            li {
+                                key: "{contact.id}",
+                                title: contact.addresses.join(", "),
+                                role: "option",
+                                class: if i == highlighted() { "highlighted" },
+                                // Before the field loses the focus to the click.
+                                onmousedown: {
+                                    let addr = contact.addr();
+                                    move |e: MouseEvent| {
+                                        e.prevent_default();
+                                        add(vec![addr.clone()]);
+                                    }
+                                },
+                                span {
+                                    class: "suggestion-name",
+                                    "{contact.name().unwrap_or_default()}"
+                                }
+                                span {
+                                    class: "suggestion-address",
+                                    "{contact.addresses.first().cloned().unwrap_or_default()}"
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use dioxus::prelude::*;
+use web_sys::HtmlElement;
+use web_sys::wasm_bindgen::JsCast;
+
+use crate::components::{activate_tab, close_tab};
+use crate::context::AppContext;
+use crate::core_event::{UiBus, UiCommand};
+use crate::mailbox::find_by_name;
+use crate::shortcuts::{Action, ShortcutContext, is_typing, key_name};
+
+/// The id of the search field, focused by [`Action::Search`].
+pub const SEARCH_INPUT_ID: &str = "search-input";
+
+/// Runs the shortcut of the key pressed in `event`, if it has one.
+pub fn handle_key(event: KeyboardEvent, mut ctx: AppContext, bus: &UiBus, mut overlay: Signal<bool>) {
+    let context = if ctx.composition.read().is_some() {
+        ShortcutContext::Composer
+    } else if ctx.selected_message.read().is_some() {
+        ShortcutContext::Reader
+    } else {
+        ShortcutContext::List
+    };
+    let key = key_name(&event);
+    let Some(action) = ctx.settings.read().shortcuts.action(&key, context, is_typing()) else {
+        return;
+    };
+    event.prevent_default();
+
+    let selected = ctx.selected_message.read().clone();
+    match action {
+        Action::NextMessage => step(&ctx, bus, 1),
+        Action::PreviousMessage => step(&ctx, bus, -1),
+        Action::Archive => {
+            let archive = find_by_name(&ctx.mailbox_nodes.read(), &["archive", "archives", "all mail"]);
+            match (selected, archive) {
+                (Some(message_id), Some(mailbox_id)) => {
+                    bus.send(UiCommand::MoveMessage { message_id, mailbox_id })
+                }
+                (Some(_), None) => ctx.toasts.error("There is no Archive folder to move the message to"),
+                (None, _) => {}
+            }
+        }
+        Action::Reply | Action::ReplyAll => {
+            if let Some(message_id) = selected {
+                bus.send(UiCommand::Reply { message_id, all: action == Action::ReplyAll });
+            }
+        }
+        Action::Forward => {
+            if let Some(message_id) = selected {
+                bus.send(UiCommand::Forward(message_id));
+            }
+        }
+        Action::Search => {
+            let input = web_sys::window()
+                .and_then(|window| window.document())
+                .and_then(|document| document.get_element_by_id(SEARCH_INPUT_ID))
+                .and_then(|element| element.dyn_into::<HtmlElement>().ok());
+            if let Some(input) = input {
+                let _ = input.focus();
+            }
+        }
+        Action::Compose => ctx.composition.set(Some(Default::default())),
+        Action::Close => {
+            if ctx.context_menu.read().is_some() {
+                ctx.context_menu.set(None);
+            } else if overlay() {
+                overlay.set(false);
+            } else {
+                ctx.selected_message.set(None);
+                ctx.conversation.set(Vec::new());
+            }
+        }
+        Action::ShowShortcuts => overlay.set(!overlay()),
+        Action::NextTab | Action::PreviousTab => {
+            let offset = if action == Action::NextTab { 1 } else { -1 };
+            if let Some(id) = ctx.tabs.neighbour(offset) {
+                activate_tab(&ctx, bus, id);
+            }
+        }
+        Action::CloseTab => {
+            if let Some(tab) = ctx.tabs.active() {
+                close_tab(&ctx, bus, tab.id);
+            }
+        }
+    }
+}
+
+/// Selects the message `offset` rows away from the selected one.
+fn step(ctx: &AppContext, bus: &UiBus, offset: isize) {
+    let messages = ctx.messages.read();
+    let selected = ctx.selected_message.read();
+    let position = selected
+        .as_ref()
+        .and_then(|id| messages.iter().position(|message| message.id == *id));
+    let next = match position {
+        Some(position) => position.saturating_add_signed(offset).min(messages.len().saturating_sub(1)),
+        None => 0,
+    };
+    if let Some(message) = messages.get(next).filter(|_| position != Some(next)) {
+        bus.send(UiCommand::SelectMessage(message.id.clone()));
+    }
+}
+
+/// Lists the shortcuts. Pressing a key in the field of a shortcut makes it
+/// the shortcut's key.
+#[component]
+pub fn ShortcutsOverlay(visible: Signal<bool>) -> Element {
+    let mut visible = visible;
+    let bus = use_context::<UiBus>();
+    let mut settings = use_context::<AppContext>().settings;
+    let rows: Vec<(Action, &str, String)> = {
+        let keymap = &settings.read().shortcuts;
+        Action::ALL
+            .into_iter()
+            .map(|action| (action, action.description(), keymap.key(action).unwrap_or_default().to_string()))
+            .collect()
+    };
+
+    let rebind = use_callback(move |(action, event): (Action, KeyboardEvent)| {
+        event.stop_propagation();
+        if matches!(event.key(), Key::Tab | Key::Shift | Key::Control | Key::Alt | Key::Meta) {
+            return;
+        }
+        event.prevent_default();
+        let mut changed = settings.peek().clone();
+        changed.shortcuts.bind(key_name(&event), action);
+        settings.set(changed.clone());
+        bus.send(UiCommand::SaveSettings(changed));
+    });
+
+    rsx! {
+        div {
+            id: "shortcuts-overlay",
+            onclick: move |_| visible.set(false),
+
+            div {
+                class: "shortcuts",
+                onclick: move |e| e.stop_propagation(),
+
+                h2 { "Keyboard shortcuts" }
+
+                table {
+                    for (action, description, key) in rows {
+                        tr {
+                            key: "{description}",
+
+                            td { "{description}" }
+                            td {
+                                input {
+                                    readonly: true,
+                                    value: "{key}",
+                                    onkeydown: move |e| rebind.call((action, e)),
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,11 +1,69 @@
 use dioxus::prelude::*;
 
+use crate::components::Diagnostics;
+use crate::context::AppContext;
+use crate::core_event::{UiBus, UiCommand};
+use crate::layout::ReadingPane;
+use crate::Route;
+
 #[component]
 pub fn Sidebar() -> Element {
+    let bus = use_context::<UiBus>();
+    let ctx = use_context::<AppContext>();
+    let mut composition = ctx.composition;
+    let mut settings = ctx.settings;
+    let bottom = settings.read().layout.reading_pane == ReadingPane::Bottom;
+    let layout_bus = bus.clone();
+
     rsx! {
         section {
             id: "sidebar",
             "Sidebar"
+
+            button {
+                id: "compose-button",
+                onclick: move |_| composition.set(Some(Default::default())),
+                "Compose"
+            }
+
+            Link {
+                id: "contacts-link",
+                to: Route::ContactsView {},
+                "Contacts"
+            }
+
+            label {
+                class: "setting",
+                title: "Looks up pictures on Gravatar and logos of the senders' domains, which tells those services who mailed you",
+                input {
+                    r#type: "checkbox",
+                    checked: settings.read().remote_avatars,
+                    onchange: move |e| {
+                        let mut changed = settings.peek().clone();
+                        changed.remote_avatars = e.checked();
+                        settings.set(changed.clone());
+                        bus.send(UiCommand::SaveSettings(changed));
+                    },
+                }
+                "Show sender pictures from the web"
+            }
+
+            label {
+                class: "setting",
+                input {
+                    r#type: "checkbox",
+                    checked: bottom,
+                    onchange: move |e| {
+                        let mut changed = settings.peek().clone();
+                        changed.layout.reading_pane = if e.checked() { ReadingPane::Bottom } else { ReadingPane::Right };
+                        settings.set(changed.clone());
+                        layout_bus.send(UiCommand::SaveSettings(changed));
+                    },
+                }
+                "Reading pane below the messages"
+            }
+
+            Diagnostics {}
         }
     }
-}
\ No newline at end of file
+}
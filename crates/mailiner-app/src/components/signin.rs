@@ -0,0 +1,76 @@
+use dioxus::prelude::*;
+
+use crate::context::AppContext;
+use crate::core_event::{UiBus, UiCommand};
+use crate::oauth::{finish, open_consent};
+
+/// Asks the user to sign in with the account's OAuth provider, in a popup.
+#[component]
+pub fn SignIn() -> Element {
+    let bus = use_context::<UiBus>();
+    let ctx = use_context::<AppContext>();
+    let mut sign_in = ctx.sign_in;
+    let selected_account = ctx.selected_account;
+    let mut waiting = use_signal(|| false);
+    let mut error = use_signal(|| None::<String>);
+
+    let Some(client) = sign_in() else {
+        return rsx! {};
+    };
+
+    let start = move |_: MouseEvent| {
+        error.set(None);
+        // The popup has to open right away, while handling the click.
+        let (authorization, popup) = match open_consent(&client) {
+            Ok(opened) => opened,
+            Err(e) => {
+                error.set(Some(e.to_string()));
+                return;
+            }
+        };
+        waiting.set(true);
+        let client = client.clone();
+        let bus = bus.clone();
+        spawn(async move {
+            let result = finish(&client, &authorization, popup).await;
+            waiting.set(false);
+            match result {
+                Ok(token) => {
+                    sign_in.set(None);
+                    bus.send(UiCommand::SignedIn(token));
+                    // Commands sent before signing in were dropped.
+                    if let Some(account_id) = selected_account() {
+                        bus.send(UiCommand::SelectAccount(account_id));
+                    }
+                }
+                Err(e) => error.set(Some(e.to_string())),
+            }
+        });
+    };
+
+    rsx! {
+        div {
+            id: "sign-in",
+
+            div {
+                class: "sign-in-panel",
+
+                h2 { "Sign in" }
+                p { "Your account uses your provider's sign-in. It opens in a new window." }
+
+                if let Some(error) = error() {
+                    p {
+                        class: "error",
+                        "{error}"
+                    }
+                }
+
+                button {
+                    disabled: waiting(),
+                    onclick: start,
+                    if waiting() { "Waiting for the sign-in…" } else { "Sign in" }
+                }
+            }
+        }
+    }
+}
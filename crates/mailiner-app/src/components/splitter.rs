@@ -0,0 +1,23 @@
+use dioxus::prelude::*;
+
+use crate::layout::Pane;
+
+/// The handle on the edge of `pane` which resizes it when dragged. The
+/// dragging itself is followed by the main view, since the mouse leaves
+/// the handle as soon as it moves.
+#[component]
+pub fn Splitter(pane: Pane, dragging: Signal<Option<Pane>>) -> Element {
+    let mut dragging = dragging;
+
+    rsx! {
+        div {
+            class: "splitter",
+            class: if dragging() == Some(pane) { "dragging" },
+            role: "separator",
+            onmousedown: move |e| {
+                e.prevent_default();
+                dragging.set(Some(pane));
+            },
+        }
+    }
+}
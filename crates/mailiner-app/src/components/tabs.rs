@@ -0,0 +1,130 @@
+use dioxus::prelude::*;
+
+use crate::context::AppContext;
+use crate::core_event::{UiBus, UiCommand};
+use crate::tabs::TabContent;
+
+/// Opens a tab showing `content`, or switches to the one already open.
+pub fn open_tab(ctx: &AppContext, bus: &UiBus, content: TabContent) {
+    let id = ctx.tabs.open(content);
+    activate_tab(ctx, bus, id);
+}
+
+/// Shows the tab `id` in the reading pane.
+pub fn activate_tab(ctx: &AppContext, bus: &UiBus, id: u64) {
+    let Some(tab) = ctx.tabs.get(id) else {
+        return;
+    };
+    // Set first, so that clearing the composition below doesn't close a
+    // draft tab that was active.
+    ctx.tabs.set_active(Some(id));
+    let mut composition = ctx.composition;
+    match tab.content {
+        TabContent::Message { message_id, .. } => {
+            composition.set(None);
+            bus.send(UiCommand::SelectMessage(message_id));
+        }
+        TabContent::Search(query) => {
+            composition.set(None);
+            bus.send(UiCommand::Search(query));
+        }
+        TabContent::Draft { composition: draft, .. } => composition.set(Some(draft)),
+    }
+}
+
+/// Closes the tab `id`, once the user agreed to lose the changes if it is
+/// a draft that wasn't saved.
+pub fn close_tab(ctx: &AppContext, bus: &UiBus, id: u64) {
+    let Some(tab) = ctx.tabs.get(id) else {
+        return;
+    };
+    if let TabContent::Draft { unsaved: true, .. } = tab.content {
+        let discard = web_sys::window()
+            .and_then(|window| window.confirm_with_message("Discard the unsaved draft?").ok())
+            .unwrap_or(false);
+        if !discard {
+            return;
+        }
+    }
+    let was_active = ctx.tabs.active().is_some_and(|active| active.id == id);
+    ctx.tabs.close(id);
+    if was_active {
+        let mut composition = ctx.composition;
+        composition.set(None);
+        if let Some(next) = ctx.tabs.neighbour(0) {
+            activate_tab(ctx, bus, next);
+        }
+    }
+}
+
+/// The tabs above the reading pane. Writing a message opens a tab for the
+/// draft, which keeps its edits while other tabs are shown.
+#[component]
+pub fn TabBar() -> Element {
+    let bus = use_context::<UiBus>();
+    let ctx = use_context::<AppContext>();
+    let tabs = ctx.tabs;
+    let composition = ctx.composition;
+
+    use_effect(move || {
+        let draft = composition.read().clone();
+        let active = tabs.active();
+        let draft_active = active.as_ref().is_some_and(|tab| matches!(tab.content, TabContent::Draft { .. }));
+        match (draft, active) {
+            // A new message was started, from a reply or the Compose button.
+            (Some(draft), _) if !draft_active => {
+                let id = tabs.open(TabContent::Draft { composition: draft, unsaved: false });
+                tabs.set_active(Some(id));
+            }
+            // The draft was saved or discarded.
+            (None, Some(tab)) if draft_active => tabs.close(tab.id),
+            _ => {}
+        }
+    });
+
+    let activate = use_callback({
+        let ctx = ctx.clone();
+        let bus = bus.clone();
+        move |id: u64| activate_tab(&ctx, &bus, id)
+    });
+    let close = use_callback(move |id: u64| close_tab(&ctx, &bus, id));
+
+    let list = tabs.list();
+    if list.is_empty() {
+        return rsx! {};
+    }
+    let active = tabs.active().map(|tab| tab.id);
+
+    rsx! {
+        div {
+            id: "tabs",
+            role: "tablist",
+
+            for tab in list {
+                div {
+                    key: "{tab.id}",
+                    class: "tab",
+                    class: if active == Some(tab.id) { "active" },
+                    class: if matches!(tab.content, TabContent::Draft { unsaved: true, .. }) { "unsaved" },
+                    role: "tab",
+                    title: tab.content.title(),
+                    onclick: move |_| activate(tab.id),
+
+                    span {
+                        class: "tab-title",
+                        {tab.content.title()}
+                    }
+                    button {
+                        class: "tab-close",
+                        title: "Close",
+                        onclick: move |e| {
+                            e.stop_propagation();
+                            close(tab.id);
+                        },
+                        "×"
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use dioxus::prelude::*;
+use mailiner_core::platform::web::WebTimer;
+use mailiner_core::platform::Timer;
+
+use crate::context::AppContext;
+use crate::core_event::UiBus;
+use crate::toast::{Toast, ToastKind};
+
+/// Shows the toasts of the app.
+#[component]
+pub fn ToastHost() -> Element {
+    let toasts = use_context::<AppContext>().toasts.list();
+
+    rsx! {
+        div {
+            id: "toasts",
+
+            for toast in toasts {
+                ToastView {
+                    key: "{toast.id}",
+                    toast,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Props)]
+pub struct ToastViewProps {
+    pub toast: Toast,
+}
+
+impl PartialEq for ToastViewProps {
+    fn eq(&self, other: &Self) -> bool {
+        // Toasts don't change once shown.
+        self.toast.id == other.toast.id
+    }
+}
+
+#[component]
+pub fn ToastView(props: ToastViewProps) -> Element {
+    let bus = use_context::<UiBus>();
+    let toasts = use_context::<AppContext>().toasts;
+    let toast = props.toast;
+    let id = toast.id;
+
+    let duration = toast.duration();
+    use_future(move || async move {
+        WebTimer.sleep(duration).await;
+        toasts.dismiss(id);
+    });
+
+    let class = match toast.kind {
+        ToastKind::Success => "toast success",
+        ToastKind::Error => "toast error",
+        ToastKind::Info => "toast info",
+    };
+
+    rsx! {
+        div {
+            class,
+            role: if toast.kind == ToastKind::Error { "alert" } else { "status" },
+
+            span {
+                class: "toast-message",
+                "{toast.message}"
+            }
+
+            if let Some(action) = toast.action {
+                button {
+                    class: "toast-action",
+                    onclick: move |_| {
+                        bus.send(action.command.clone());
+                        toasts.dismiss(id);
+                    },
+                    "{action.label}"
+                }
+            }
+
+            button {
+                class: "toast-close",
+                title: "Dismiss",
+                onclick: move |_| toasts.dismiss(id),
+                "×"
+            }
+        }
+    }
+}
@@ -2,10 +2,19 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use dioxus::prelude::*;
+use mailiner_core::calendar::{Invitation, ParticipationStatus};
+use mailiner_core::compose::Composition;
+use mailiner_core::contacts::Contact;
+use mailiner_core::oauth::OAuthClient;
+use mailiner_core::{MessageContent, MetricsSnapshot};
 
 use crate::account::{Account, AccountId};
+use crate::components::Menu;
 use crate::mailbox::{MailboxId, MailboxNode};
 use crate::message::{Message, MessageId};
+use crate::settings::Settings;
+use crate::tabs::Tabs;
+use crate::toast::Toasts;
 
 #[derive(Clone)]
 pub struct AppContext {
@@ -13,8 +22,46 @@ pub struct AppContext {
     pub mailbox_nodes: Signal<HashMap<MailboxId, MailboxNode>>,
     pub mailbox_roots: Signal<Vec<MailboxId>>,
     pub messages: Signal<Vec<Arc<Message>>>,
+    /// Set while the next page of the selected mailbox is being listed.
+    pub loading_more_messages: Signal<bool>,
+    /// Whether `messages` holds all of the selected mailbox.
+    pub all_messages_loaded: Signal<bool>,
 
     pub selected_account: Signal<Option<AccountId>>,
     pub selected_mailbox: Signal<Option<MailboxId>>,
     pub selected_message: Signal<Option<MessageId>>,
+    /// The search whose results are listed instead of a mailbox.
+    pub search: Signal<Option<String>>,
+    /// The messages of the selected message's conversation, oldest first.
+    pub conversation: Signal<Vec<Arc<Message>>>,
+    /// The bodies of the messages loaded so far in the selected mailbox.
+    pub bodies: Signal<HashMap<MessageId, MessageContent>>,
+    /// How many trackers were removed from the bodies, where any were.
+    pub trackers_blocked: Signal<HashMap<MessageId, usize>>,
+    /// The calendar invitations in the loaded messages, with the user's
+    /// answer if they are invited.
+    pub invitations: Signal<HashMap<MessageId, (Invitation, Option<ParticipationStatus>)>>,
+    /// The message being written, shown instead of the message view.
+    pub composition: Signal<Option<Composition>>,
+    /// The contacts suggested for the address being typed, and what was
+    /// typed.
+    pub contact_suggestions: Signal<(String, Vec<Contact>)>,
+    /// The address book, listed when the contacts are shown.
+    pub contacts: Signal<Vec<Contact>>,
+    /// The messages, searches and drafts open in tabs above the reading pane.
+    pub tabs: Tabs,
+    /// The context menu opened by a right click, if any.
+    pub context_menu: Signal<Option<Menu>>,
+    /// The pictures looked up for senders by address, `None` where there
+    /// is none and the initials are shown.
+    pub avatars: Signal<HashMap<String, Option<String>>>,
+
+    /// Set while the user has to set up an account before anything can be
+    /// shown.
+    pub account_setup: Signal<bool>,
+    /// Set while the user has to sign in with the account's OAuth provider.
+    pub sign_in: Signal<Option<OAuthClient>>,
+    pub diagnostics: Signal<MetricsSnapshot>,
+    pub settings: Signal<Settings>,
+    pub toasts: Toasts,
 }
@@ -1,70 +1,1130 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
 use std::sync::Arc;
 
 use dioxus::prelude::*;
-use dioxus::logger::tracing::{info, error};
-use futures_util::StreamExt;
-use mailiner_core::{Folder, FolderId};
-use mailiner_core::connector::EmailConnector;
-use mailiner_imap_connector::ImapConnector;
+use dioxus::logger::tracing::{info, error, warn};
+use chrono::Utc;
+use mailiner_core::{
+    AccountConfig, AccountManager, AccountPreferences, BackendUri, ConnectorRegistry, ContactId,
+    EmailAddr, EmailConnector, Envelope, FileStorage, Folder, FolderId, InMemoryStorage,
+    MailinerError, MemoryMetrics, MessageContent, MessageStructure, Metrics, MetricsSnapshot,
+    MockConnector, SearchQuery, Storage,
+};
+use mailiner_core::calendar::{Calendar, Invitation, ParticipationStatus};
+use mailiner_core::compose::plaintext::html_to_text;
+use mailiner_core::compose::reply::{forward, reply};
+use mailiner_core::compose::Composition;
+use mailiner_core::contacts::{self, Contact};
+use mailiner_core::metrics::{COMMAND_LATENCY_MS, FETCH_BYTES};
+use mailiner_core::oauth::{OAuthClient, OAuthToken, TokenProvider};
+use mailiner_core::platform::{Platform, SecureStorage};
+use mailiner_core::sanitize::{
+    content_ids, image_data_url, inline_images, sanitize_html, sanitize_html_without_trackers,
+};
+use mailiner_core::search::syntax::parse_query;
+use mailiner_core::storage::file::STORAGE_DIR;
+use mailiner_core::threads;
+use mailiner_core::unsubscribe::{UnsubscribeMethod, ONE_CLICK_BODY};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
 
-use crate::account::AccountId;
+use crate::account::{load_password, save_password, Account, AccountId};
 use crate::context::AppContext;
+use crate::download::download;
+use crate::http::post_form_no_cors;
 use crate::mailbox::{MailboxId, MailboxNode};
-use crate::message::MessageId;
-use crate::websocket_stream::WebSocketStream;
+use crate::message::{Message, MessageId};
+use crate::oauth::{self, load_token, save_token, FetchRefresher};
+use crate::outgoing;
+use crate::platform::{platform, Stream};
+use crate::settings::{load_settings, save_settings, Settings};
+use crate::toast::{ToastAction, ToastKind};
 
-pub enum CoreEvent {
+/// How many commands or events can be in flight before the sender waits.
+const BUS_CAPACITY: usize = 64;
+
+/// The most messages a search lists, since each is fetched on its own.
+const SEARCH_LIMIT: usize = 200;
+
+/// The most contacts suggested for an address being typed.
+const SUGGESTION_LIMIT: usize = 8;
+
+/// How many envelopes of a mailbox are listed at once, as the message list
+/// is scrolled.
+const PAGE_SIZE: usize = 50;
+
+/// What the UI asks the core to do.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum UiCommand {
     SelectAccount(AccountId),
     SelectMailbox(MailboxId),
+    /// Lists the next page of the selected mailbox's envelopes.
+    LoadMoreEnvelopes(MailboxId),
+    /// Selects a message and opens its conversation.
     SelectMessage(MessageId),
+    /// Loads the body of a message of the open conversation.
+    LoadBody(MessageId),
+    /// Loads the body of a message again, with the trackers that were
+    /// removed from it.
+    AllowTrackers(MessageId),
+    /// Answers the invitation in a message, by mail to the organizer.
+    Rsvp { message_id: MessageId, status: ParticipationStatus },
+    Reply { message_id: MessageId, all: bool },
+    Forward(MessageId),
+    /// Saves the source of a message as an `.eml` file.
+    ExportMessage(MessageId),
+    /// Saves the message being written to the Drafts folder `mailbox_id`.
+    SaveDraft { mailbox_id: MailboxId, composition: Composition },
+    MoveMessage { message_id: MessageId, mailbox_id: MailboxId },
+    /// Moves a message back to the mailbox it was moved out of, and lists
+    /// that mailbox again.
+    UndoMove { message_id: MessageId, mailbox_id: MailboxId },
+    MarkRead { message_id: MessageId, read: bool },
+    LabelMessage { message_id: MessageId, label: String },
+    DeleteMessage(MessageId),
+    /// Leaves the mailing list a message came from, with one click or by
+    /// writing to the list.
+    Unsubscribe(MessageId),
+    /// Marks all messages of a mailbox as read.
+    MarkMailboxRead(MailboxId),
+    RenameMailbox { mailbox_id: MailboxId, name: String },
+    DeleteMailbox(MailboxId),
+    CreateMailbox { parent: Option<MailboxId>, name: String },
+    SaveSettings(Settings),
+    /// Searches all folders of the selected account, with a query in the
+    /// syntax of the search bar.
+    Search(String),
+    /// The user signed in, as asked by [`CoreEvent::SignInRequired`].
+    SignedIn(OAuthToken),
+    /// Looks up the contacts to complete an address starting with the text.
+    SuggestContacts(String),
+    ListContacts,
+    /// Adds a contact, or replaces the one with the same id.
+    SaveContact(Contact),
+    DeleteContact(ContactId),
+    /// Merges contacts that are the same person into the first one.
+    MergeContacts(Vec<ContactId>),
+    /// Adds the first account, when the core asked for one. `backend` is
+    /// the URI of where its mail is, its IMAP server if `None`.
+    SetUpAccount { config: AccountConfig, backend: Option<String>, password: String },
 }
 
-pub async fn core_loop(mut core_rx: UnboundedReceiver<CoreEvent>, mut ctx: AppContext) {
-    let password = env!("IMAP_PASSWORD").to_string();
-    let websocket_stream = WebSocketStream::new("ws://localhost:9400/proxy?token=testtoken&remote=dvratil.cz:993");
-    let connector = ImapConnector::new(
-        "dvratil.cz".to_string(),
-        8081,
-        "me@dvratil.cz".to_string(),
-        password.clone(),
-    );
-
-    info!("Connecting to IMAP server...");
-    connector.connect(websocket_stream).await.or_else(|e| {
-        error!("Failed to connect to IMAP server: {}", e);
-        Err(e)
-    }).expect("Failed to connect to IMAP server");
-    info!("Connected to IMAP server");
-
-    connector.authenticate(password.as_str()).await.expect("Failed to authenticate with IMAP server");
-    info!("Authenticated with IMAP server");
-    
-
-    while let Some(event) = core_rx.next().await {
+/// What the core reports back. The UI changes its state only in reaction to
+/// these, so the core can run anywhere it can send them from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CoreEvent {
+    /// The accounts set up, by name. The first one is connected.
+    AccountsLoaded(Vec<AccountConfig>),
+    /// No account is set up yet, and the user has to add one before the
+    /// core can connect.
+    AccountSetupRequired,
+    AccountSelected { account_id: AccountId, folders: Vec<Folder> },
+    /// Sent before the envelopes of the mailbox are loaded.
+    MailboxSelected(MailboxId),
+    /// The first envelopes of a mailbox, or all of them when `complete`.
+    EnvelopesLoaded { mailbox_id: MailboxId, envelopes: Vec<Envelope>, complete: bool },
+    /// The next page of a mailbox's envelopes, the last one when `complete`.
+    MoreEnvelopesLoaded { mailbox_id: MailboxId, envelopes: Vec<Envelope>, complete: bool },
+    MessageSelected(MessageId),
+    /// The conversation of the selected message, oldest message first.
+    ConversationLoaded { message_id: MessageId, envelopes: Vec<Envelope> },
+    /// The body of a message, and how many trackers were removed from it.
+    BodyLoaded { message_id: MessageId, content: MessageContent, trackers_blocked: usize },
+    /// Opens the composer with a reply or forward.
+    Compose(Composition),
+    /// The calendar invitation in a message, and the user's answer so far,
+    /// if they are invited.
+    InvitationLoaded { message_id: MessageId, invitation: Invitation, answer: Option<ParticipationStatus> },
+    InvitationAnswered { message_id: MessageId, status: ParticipationStatus },
+    MessageExported { message_id: MessageId, source: Vec<u8> },
+    DraftSaved(MailboxId),
+    /// The message is no longer in the selected mailbox.
+    MessageRemoved(MessageId),
+    /// Something was done that `undo` reverts, e.g. a message was moved.
+    Undoable { message: String, undo: UiCommand },
+    MessageRead { message_id: MessageId, read: bool },
+    MessageLabeled { message_id: MessageId, label: String },
+    /// The list the message came from was told to unsubscribe the user.
+    Unsubscribed(MessageId),
+    MailboxRead(MailboxId),
+    /// The folders of the selected account, after one was added, renamed or
+    /// deleted.
+    MailboxesChanged(Vec<Folder>),
+    /// The messages found by a search, newest first.
+    SearchResults { query: String, envelopes: Vec<Envelope> },
+    SettingsLoaded(Settings),
+    /// The account logs in with OAuth, and the user has to sign in with the
+    /// provider before the core can connect.
+    SignInRequired(OAuthClient),
+    /// The contacts matching what was typed, the most frecent first.
+    ContactSuggestions { prefix: String, contacts: Vec<Contact> },
+    /// All contacts, after they were listed or changed.
+    ContactsLoaded(Vec<Contact>),
+    Diagnostics(MetricsSnapshot),
+    Error(String),
+}
+
+/// The UI's end of the bus, provided as context to the components.
+#[derive(Clone)]
+pub struct UiBus(mpsc::Sender<UiCommand>);
+
+impl UiBus {
+    pub fn send(&self, command: UiCommand) {
+        // Event handlers can't wait, and a full bus means the core is stuck
+        // anyway.
+        if let Err(e) = self.0.try_send(command) {
+            error!("Dropped command: {}", e);
+        }
+    }
+}
+
+/// Starts the core, and the loop applying its events to `ctx`.
+pub fn start(ctx: AppContext) -> UiBus {
+    let (commands, commands_rx) = mpsc::channel(BUS_CAPACITY);
+    let (events, events_rx) = mpsc::channel(BUS_CAPACITY);
+    spawn(core_loop(commands_rx, events));
+    spawn(ui_loop(events_rx, ctx, UiBus(commands.clone())));
+    UiBus(commands)
+}
+
+async fn core_loop(mut commands: mpsc::Receiver<UiCommand>, events: mpsc::Sender<CoreEvent>) {
+    let platform = platform();
+    // Keeps the accounts set up, the contacts collected from the senders
+    // and recipients of the listed messages, and the ones the user edited.
+    let storage: Arc<dyn Storage> = match FileStorage::open(platform.fs.clone(), STORAGE_DIR).await {
+        Ok(storage) => Arc::new(storage),
+        Err(e) => {
+            let message = format!("Failed to open the storage, accounts and contacts won't be saved: {}", e);
+            if events.send(CoreEvent::Error(message)).await.is_err() {
+                return;
+            }
+            Arc::new(InMemoryStorage::new())
+        }
+    };
+    let accounts = match AccountManager::load(storage.clone()).await {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            let _ = events.send(CoreEvent::Error(format!("Failed to load the accounts: {}", e))).await;
+            return;
+        }
+    };
+    let settings = load_settings(platform.fs.as_ref()).await;
+    if events.send(CoreEvent::SettingsLoaded(settings)).await.is_err() {
+        return;
+    }
+    let secure_storage = platform.secure_storage.as_ref();
+    let Some(config) = account_config(&accounts, storage.as_ref(), secure_storage, &mut commands, &events).await else {
+        return;
+    };
+    if events.send(CoreEvent::AccountsLoaded(accounts.configs().await)).await.is_err() {
+        return;
+    }
+    // With OAuth the password is an access token, and the user may have to
+    // sign in for it first.
+    let password = match oauth::client(&config) {
+        Some(client) => {
+            match oauth_credentials(secure_storage, &config.account_id, client, &mut commands, &events).await {
+                Some(access_token) => access_token,
+                None => return,
+            }
+        }
+        None => match load_password(secure_storage, &config.account_id).await {
+            Ok(password) => password.unwrap_or_default(),
+            Err(e) => {
+                let error = format!("Failed to read the password: {}", e);
+                if events.send(CoreEvent::Error(error)).await.is_err() {
+                    return;
+                }
+                String::new()
+            }
+        },
+    };
+    let mut registry = ConnectorRegistry::new();
+    mailiner_imap_connector::register(&mut registry);
+    registry.register("mock", |_: &BackendUri| {
+        Ok(Arc::new(MockConnector::new()) as Arc<dyn EmailConnector<Stream>>)
+    });
+    // Connected on the first command, and again on the next one after the
+    // connection failed.
+    let mut connection: Option<Arc<dyn EmailConnector<Stream>>> = None;
+
+    let metrics = MemoryMetrics::new();
+    let me = EmailAddr { name: Some(config.name.clone()), email: Some(config.email.clone()) };
+    // The envelopes listed, of the selected mailbox or a search, for
+    // conversations and replies.
+    let mut envelopes: Vec<Envelope> = Vec::new();
+    let mut listed_mailbox: Option<MailboxId> = None;
+    // How many of the listed mailbox's messages, counted from the newest,
+    // the server has listed, where the next page starts.
+    let mut listed_count = 0;
+    let mut selected_account: Option<AccountId> = None;
+
+    while let Some(command) = commands.recv().await {
+        let connector = match &connection {
+            Some(connector) => connector.clone(),
+            None => match connect(&platform, storage.as_ref(), &registry, &config, &password).await {
+                Ok(connector) => connection.insert(connector).clone(),
+                Err(e) => {
+                    let error = format!("Failed to connect to {}: {}", config.incoming.host, e);
+                    if events.send(CoreEvent::Error(error)).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            },
+        };
+        let event = match command {
+            UiCommand::SelectAccount(account_id) => {
+                match timed(&metrics, connector.list_folders(&account_id)).await {
+                    Ok(folders) => {
+                        selected_account = Some(account_id.clone());
+                        CoreEvent::AccountSelected { account_id, folders }
+                    }
+                    Err(e) => CoreEvent::Error(format!("Failed to list folders: {}", e)),
+                }
+            }
+            UiCommand::SelectMailbox(mailbox_id) => {
+                let _ = events.send(CoreEvent::MailboxSelected(mailbox_id.clone())).await;
+                let folder_id = FolderId::new(mailbox_id.to_string());
+                match timed(&metrics, connector.list_envelopes_range(&folder_id, 0..PAGE_SIZE)).await {
+                    Ok(loaded) => {
+                        collect_contacts(&storage, &loaded, &config.email).await;
+                        envelopes = loaded.clone();
+                        listed_mailbox = Some(mailbox_id.clone());
+                        listed_count = loaded.len();
+                        let complete = loaded.len() < PAGE_SIZE;
+                        CoreEvent::EnvelopesLoaded { mailbox_id, envelopes: loaded, complete }
+                    }
+                    Err(e) => CoreEvent::Error(format!("Failed to list messages: {}", e)),
+                }
+            }
+            UiCommand::LoadMoreEnvelopes(mailbox_id) => {
+                let folder_id = FolderId::new(mailbox_id.to_string());
+                // Counted on the server rather than in the list, which may
+                // have less after a page of messages that were listed already.
+                let range = listed_count..listed_count + PAGE_SIZE;
+                if listed_mailbox.as_ref() != Some(&mailbox_id) {
+                    // Another mailbox or a search was listed since the UI
+                    // asked, and it has dropped this one's envelopes.
+                    CoreEvent::MoreEnvelopesLoaded { mailbox_id, envelopes: Vec::new(), complete: false }
+                } else {
+                    match timed(&metrics, connector.list_envelopes_range(&folder_id, range)).await {
+                        Ok(loaded) => {
+                            let complete = loaded.len() < PAGE_SIZE;
+                            listed_count += loaded.len();
+                            // New mail pushes older envelopes down into the
+                            // page, so some may already be listed.
+                            let listed: HashSet<&mailiner_core::MessageId> =
+                                envelopes.iter().map(|envelope| &envelope.id).collect();
+                            let loaded: Vec<Envelope> = loaded
+                                .into_iter()
+                                .filter(|envelope| !listed.contains(&envelope.id))
+                                .collect();
+                            collect_contacts(&storage, &loaded, &config.email).await;
+                            envelopes.extend(loaded.iter().cloned());
+                            CoreEvent::MoreEnvelopesLoaded { mailbox_id, envelopes: loaded, complete }
+                        }
+                        Err(e) => {
+                            // Scrolling further tries again.
+                            let envelopes = Vec::new();
+                            let _ = events.send(CoreEvent::MoreEnvelopesLoaded { mailbox_id, envelopes, complete: false }).await;
+                            CoreEvent::Error(format!("Failed to list more messages: {}", e))
+                        }
+                    }
+                }
+            }
+            UiCommand::SelectMessage(message_id) => {
+                let _ = events.send(CoreEvent::MessageSelected(message_id.clone())).await;
+                let id = mailiner_core::MessageId::new(message_id.to_string());
+                CoreEvent::ConversationLoaded {
+                    envelopes: threads::conversation(&envelopes, &id),
+                    message_id,
+                }
+            }
+            UiCommand::LoadBody(message_id) => {
+                let id = mailiner_core::MessageId::new(message_id.to_string());
+                match timed(&metrics, load_invitation(connector.as_ref(), &id)).await {
+                    Ok(Some(calendar)) => {
+                        if let Some(invitation) = Invitation::new(&calendar) {
+                            let answer = invitation.attendee(&config.email).map(|attendee| attendee.status);
+                            let message_id = message_id.clone();
+                            let _ = events.send(CoreEvent::InvitationLoaded { message_id, invitation, answer }).await;
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("Failed to load the invitation in message {}: {}", id, e),
+                }
+                match timed(&metrics, load_body(connector.as_ref(), &id, true)).await {
+                    Ok((content, trackers_blocked)) => CoreEvent::BodyLoaded { message_id, content, trackers_blocked },
+                    Err(e) => CoreEvent::Error(format!("Failed to load message {}: {}", id, e)),
+                }
+            }
+            UiCommand::AllowTrackers(message_id) => {
+                let id = mailiner_core::MessageId::new(message_id.to_string());
+                match timed(&metrics, load_body(connector.as_ref(), &id, false)).await {
+                    Ok((content, trackers_blocked)) => CoreEvent::BodyLoaded { message_id, content, trackers_blocked },
+                    Err(e) => CoreEvent::Error(format!("Failed to load message {}: {}", id, e)),
+                }
+            }
+            UiCommand::Rsvp { message_id, status } => {
+                let id = mailiner_core::MessageId::new(message_id.to_string());
+                match timed(&metrics, load_invitation(connector.as_ref(), &id)).await {
+                    Ok(Some(calendar)) => {
+                        let reply = calendar.events().into_iter().next().and_then(|event| {
+                            let organizer = event.organizer()?.email;
+                            Some(event.reply_message(&me, status).map(|message| (organizer, message)))
+                        });
+                        match reply {
+                            Some(Ok((organizer, message))) => {
+                                match outgoing::send(&platform, &config, &password, &[organizer], &message).await {
+                                    Ok(()) => CoreEvent::InvitationAnswered { message_id, status },
+                                    Err(e) => CoreEvent::Error(format!("Failed to send the answer: {}", e)),
+                                }
+                            }
+                            Some(Err(e)) => CoreEvent::Error(format!("Failed to answer the invitation: {}", e)),
+                            None => CoreEvent::Error(format!("The invitation in message {} has no organizer", id)),
+                        }
+                    }
+                    Ok(None) => CoreEvent::Error(format!("Message {} has no invitation", id)),
+                    Err(e) => CoreEvent::Error(format!("Failed to load the invitation in message {}: {}", id, e)),
+                }
+            }
+            UiCommand::Reply { message_id, all } => {
+                respond(&metrics, connector.as_ref(), &envelopes, &message_id, |original, text| {
+                    reply(original, text, &me, all)
+                })
+                .await
+            }
+            UiCommand::Forward(message_id) => {
+                respond(&metrics, connector.as_ref(), &envelopes, &message_id, |original, text| {
+                    forward(original, text, &me, Vec::new())
+                })
+                .await
+            }
+            UiCommand::ExportMessage(message_id) => {
+                let id = mailiner_core::MessageId::new(message_id.to_string());
+                match timed(&metrics, connector.fetch_raw_message(&id)).await {
+                    Ok(source) => {
+                        metrics.record(FETCH_BYTES, source.len() as f64);
+                        CoreEvent::MessageExported { message_id, source }
+                    }
+                    Err(e) => CoreEvent::Error(format!("Failed to fetch message {}: {}", id, e)),
+                }
+            }
+            UiCommand::SaveDraft { mailbox_id, composition } => {
+                let folder_id = FolderId::new(mailbox_id.to_string());
+                match composition.to_draft(None).await {
+                    Ok(draft) => {
+                        let flags = ["\\Draft", "\\Seen"];
+                        match timed(&metrics, connector.append_message(&folder_id, &flags, &draft)).await {
+                            Ok(_) => CoreEvent::DraftSaved(mailbox_id),
+                            Err(e) => CoreEvent::Error(format!("Failed to save draft: {}", e)),
+                        }
+                    }
+                    Err(e) => CoreEvent::Error(format!("Failed to build draft: {}", e)),
+                }
+            }
+            UiCommand::MoveMessage { message_id, mailbox_id } => {
+                let id = mailiner_core::MessageId::new(message_id.to_string());
+                let folder_id = FolderId::new(mailbox_id.to_string());
+                let source = envelopes
+                    .iter()
+                    .find(|envelope| envelope.id == id)
+                    .map(|envelope| MailboxId::from(envelope.folder_id.clone()));
+                match timed(&metrics, connector.move_message(&id, &folder_id)).await {
+                    Ok(moved) => {
+                        forget_envelope(&mut envelopes, &mut listed_count, &id);
+                        // Without the new id the message can't be found to
+                        // move it back.
+                        if let (Some(moved), Some(source)) = (moved, source) {
+                            let undo = UiCommand::UndoMove {
+                                message_id: MessageId::from(moved.to_string()),
+                                mailbox_id: source,
+                            };
+                            let message = "Message moved".to_string();
+                            let _ = events.send(CoreEvent::Undoable { message, undo }).await;
+                        }
+                        CoreEvent::MessageRemoved(message_id)
+                    }
+                    Err(e) => CoreEvent::Error(format!("Failed to move message {}: {}", id, e)),
+                }
+            }
+            UiCommand::UndoMove { message_id, mailbox_id } => {
+                let id = mailiner_core::MessageId::new(message_id.to_string());
+                let folder_id = FolderId::new(mailbox_id.to_string());
+                let moved = async {
+                    connector.move_message(&id, &folder_id).await?;
+                    connector.list_envelopes(&folder_id).await
+                };
+                match timed(&metrics, moved).await {
+                    Ok(loaded) => {
+                        if listed_mailbox.as_ref() == Some(&mailbox_id) {
+                            envelopes = loaded.clone();
+                            listed_count = loaded.len();
+                        }
+                        CoreEvent::EnvelopesLoaded { mailbox_id, envelopes: loaded, complete: true }
+                    }
+                    Err(e) => CoreEvent::Error(format!("Failed to move message {} back: {}", id, e)),
+                }
+            }
+            UiCommand::MarkRead { message_id, read } => {
+                let id = mailiner_core::MessageId::new(message_id.to_string());
+                match timed(&metrics, connector.update_envelope_flags(&id, &[("is_read", read)])).await {
+                    Ok(()) => {
+                        if let Some(envelope) = envelopes.iter_mut().find(|envelope| envelope.id == id) {
+                            envelope.is_read = read;
+                        }
+                        CoreEvent::MessageRead { message_id, read }
+                    }
+                    Err(e) => CoreEvent::Error(format!("Failed to mark message {}: {}", id, e)),
+                }
+            }
+            UiCommand::LabelMessage { message_id, label } => {
+                let id = mailiner_core::MessageId::new(message_id.to_string());
+                match timed(&metrics, connector.update_envelope_labels(&id, &[(label.as_str(), true)])).await {
+                    Ok(()) => {
+                        if let Some(envelope) = envelopes.iter_mut().find(|envelope| envelope.id == id) {
+                            envelope.labels.insert(label.clone());
+                        }
+                        CoreEvent::MessageLabeled { message_id, label }
+                    }
+                    Err(e) => CoreEvent::Error(format!("Failed to label message {}: {}", id, e)),
+                }
+            }
+            UiCommand::DeleteMessage(message_id) => {
+                let id = mailiner_core::MessageId::new(message_id.to_string());
+                match timed(&metrics, connector.delete_message(&id)).await {
+                    Ok(()) => {
+                        forget_envelope(&mut envelopes, &mut listed_count, &id);
+                        CoreEvent::MessageRemoved(message_id)
+                    }
+                    Err(e) => CoreEvent::Error(format!("Failed to delete message {}: {}", id, e)),
+                }
+            }
+            UiCommand::Unsubscribe(message_id) => {
+                let id = mailiner_core::MessageId::new(message_id.to_string());
+                let method = envelopes
+                    .iter()
+                    .find(|envelope| envelope.id == id)
+                    .and_then(|envelope| envelope.unsubscribe.as_ref())
+                    .and_then(|unsubscribe| unsubscribe.method(&me));
+                match method {
+                    Some(UnsubscribeMethod::OneClick(url)) => match post_form_no_cors(&url, ONE_CLICK_BODY).await {
+                        Ok(()) => {
+                            for envelope in envelopes.iter_mut().filter(|envelope| envelope.id == id) {
+                                envelope.unsubscribe = None;
+                            }
+                            CoreEvent::Unsubscribed(message_id)
+                        }
+                        Err(e) => CoreEvent::Error(format!("Failed to unsubscribe at {}: {}", url, e)),
+                    },
+                    // The message is sent from the composer, like any other.
+                    Some(UnsubscribeMethod::Mail(composition)) => CoreEvent::Compose(*composition),
+                    // Web pages are opened by the UI, since browsers only
+                    // open windows in reaction to the user.
+                    Some(UnsubscribeMethod::Link(url)) => {
+                        CoreEvent::Error(format!("Unsubscribe from message {} at {}", id, url))
+                    }
+                    None => CoreEvent::Error(format!("Message {} is not from a mailing list", id)),
+                }
+            }
+            UiCommand::MarkMailboxRead(mailbox_id) => {
+                let folder_id = FolderId::new(mailbox_id.to_string());
+                match mark_folder_read(&metrics, connector.as_ref(), &folder_id).await {
+                    Ok(()) => {
+                        for envelope in envelopes.iter_mut().filter(|envelope| envelope.folder_id == folder_id) {
+                            envelope.is_read = true;
+                        }
+                        CoreEvent::MailboxRead(mailbox_id)
+                    }
+                    Err(e) => CoreEvent::Error(format!("Failed to mark folder {} read: {}", folder_id, e)),
+                }
+            }
+            UiCommand::RenameMailbox { mailbox_id, name } => {
+                let folder_id = FolderId::new(mailbox_id.to_string());
+                match timed(&metrics, connector.rename_folder(&folder_id, &name)).await {
+                    Ok(_) => reload_folders(&metrics, connector.as_ref(), selected_account.as_ref()).await,
+                    Err(e) => CoreEvent::Error(format!("Failed to rename folder {}: {}", folder_id, e)),
+                }
+            }
+            UiCommand::DeleteMailbox(mailbox_id) => {
+                let folder_id = FolderId::new(mailbox_id.to_string());
+                match timed(&metrics, connector.delete_folder(&folder_id)).await {
+                    Ok(()) => reload_folders(&metrics, connector.as_ref(), selected_account.as_ref()).await,
+                    Err(e) => CoreEvent::Error(format!("Failed to delete folder {}: {}", folder_id, e)),
+                }
+            }
+            UiCommand::CreateMailbox { parent, name } => match &selected_account {
+                Some(account_id) => {
+                    let parent = parent.map(|mailbox_id| FolderId::new(mailbox_id.to_string()));
+                    match timed(&metrics, connector.create_folder(account_id, &name, parent.as_ref())).await {
+                        Ok(_) => reload_folders(&metrics, connector.as_ref(), Some(account_id)).await,
+                        Err(e) => CoreEvent::Error(format!("Failed to create folder {}: {}", name, e)),
+                    }
+                }
+                None => CoreEvent::Error("No account is selected".to_string()),
+            },
+            UiCommand::Search(query) => {
+                let criteria = parse_query(&query);
+                match search(&metrics, connector.as_ref(), selected_account.as_ref(), &criteria).await {
+                    Ok(found) => {
+                        collect_contacts(&storage, &found, &config.email).await;
+                        envelopes = found.clone();
+                        listed_mailbox = None;
+                        CoreEvent::SearchResults { query, envelopes: found }
+                    }
+                    Err(e) => CoreEvent::Error(format!("Failed to search for {}: {}", query, e)),
+                }
+            }
+            UiCommand::SignedIn(_) => CoreEvent::Error("Already signed in".to_string()),
+            UiCommand::SetUpAccount { .. } => CoreEvent::Error("An account is set up already".to_string()),
+            UiCommand::SuggestContacts(prefix) => match storage.search_contacts(&prefix, SUGGESTION_LIMIT).await {
+                Ok(contacts) => CoreEvent::ContactSuggestions { prefix, contacts },
+                Err(e) => CoreEvent::Error(format!("Failed to look up contacts: {}", e)),
+            },
+            UiCommand::ListContacts => listed_contacts(&storage, Ok(())).await,
+            UiCommand::SaveContact(contact) => {
+                let saved = storage.save_contact(&contact).await;
+                listed_contacts(&storage, saved).await
+            }
+            UiCommand::DeleteContact(contact_id) => {
+                let deleted = storage.delete_contact(&contact_id).await;
+                listed_contacts(&storage, deleted).await
+            }
+            UiCommand::MergeContacts(contact_ids) => {
+                let merged = merge_contacts(&storage, &contact_ids).await;
+                listed_contacts(&storage, merged).await
+            }
+            UiCommand::SaveSettings(settings) => {
+                match save_settings(platform.fs.as_ref(), &settings).await {
+                    Ok(()) => CoreEvent::SettingsLoaded(settings),
+                    Err(e) => CoreEvent::Error(format!("Failed to save settings: {}", e)),
+                }
+            }
+        };
+        if events.send(event).await.is_err()
+            || events.send(CoreEvent::Diagnostics(metrics.snapshot())).await.is_err()
+        {
+            // The UI is gone.
+            break;
+        }
+    }
+}
+
+async fn ui_loop(mut events: mpsc::Receiver<CoreEvent>, mut ctx: AppContext, bus: UiBus) {
+    while let Some(event) = events.recv().await {
         match event {
-            CoreEvent::SelectAccount(account_id) => {
-                ctx.selected_account.set(Some(account_id.clone()));
-                let mboxes = connector.list_folders(&account_id).await.unwrap();
-                let (root_ids, mboxes) = build_mailbox_tree(mboxes);
+            CoreEvent::AccountsLoaded(configs) => {
+                ctx.account_setup.set(false);
+                let selected = configs.first().map(|config| config.account_id.clone());
+                ctx.accounts.set(
+                    configs
+                        .iter()
+                        .map(|config| (config.account_id.clone(), Account::from(config)))
+                        .collect(),
+                );
+                if let Some(account_id) = selected {
+                    ctx.selected_account.set(Some(account_id.clone()));
+                    bus.send(UiCommand::SelectAccount(account_id));
+                }
+            }
+            CoreEvent::AccountSetupRequired => ctx.account_setup.set(true),
+            CoreEvent::AccountSelected { account_id, folders } => {
+                ctx.selected_account.set(Some(account_id));
+                let (root_ids, mboxes) = build_mailbox_tree(folders);
                 ctx.mailbox_nodes.set(mboxes);
                 ctx.mailbox_roots.set(root_ids);
                 ctx.selected_mailbox.set(None);
             }
-            CoreEvent::SelectMailbox(mailbox_id) => {
+            CoreEvent::MailboxSelected(mailbox_id) => {
+                ctx.search.set(None);
                 ctx.messages.set(Vec::new());
-                ctx.selected_mailbox.set(Some(mailbox_id.clone()));
-                let folder_id = FolderId::new(mailbox_id.to_string());
-                let messages = connector.list_envelopes(&folder_id).await.unwrap();
-                ctx.messages.set(messages.into_iter().map(|e| Arc::new(e.into())).collect());
+                ctx.loading_more_messages.set(false);
+                // Until the first page tells otherwise.
+                ctx.all_messages_loaded.set(true);
+                ctx.conversation.set(Vec::new());
+                ctx.bodies.write().clear();
+                ctx.trackers_blocked.write().clear();
+                ctx.invitations.write().clear();
+                ctx.selected_mailbox.set(Some(mailbox_id));
             }
-            CoreEvent::SelectMessage(message_id) => {
+            CoreEvent::EnvelopesLoaded { mailbox_id, envelopes, complete } => {
+                // The user may have moved on to another mailbox meanwhile.
+                if ctx.selected_mailbox.read().as_ref() == Some(&mailbox_id) {
+                    ctx.messages.set(envelopes.into_iter().map(|e| Arc::new(e.into())).collect());
+                    ctx.all_messages_loaded.set(complete);
+                }
+            }
+            CoreEvent::MoreEnvelopesLoaded { mailbox_id, envelopes, complete } => {
+                if ctx.selected_mailbox.read().as_ref() == Some(&mailbox_id) && ctx.search.read().is_none() {
+                    // Only a longer list may need the next page, after a
+                    // failure that's up to scrolling.
+                    if !envelopes.is_empty() {
+                        ctx.messages.write().extend(envelopes.into_iter().map(|e| Arc::new(e.into())));
+                    }
+                    ctx.all_messages_loaded.set(complete);
+                    ctx.loading_more_messages.set(false);
+                }
+            }
+            CoreEvent::MessageSelected(message_id) => {
                 ctx.selected_message.set(Some(message_id));
             }
+            CoreEvent::ConversationLoaded { message_id, envelopes } => {
+                if ctx.selected_message.read().as_ref() == Some(&message_id) {
+                    ctx.conversation.set(envelopes.into_iter().map(|e| Arc::new(e.into())).collect());
+                }
+            }
+            CoreEvent::BodyLoaded { message_id, content, trackers_blocked } => {
+                if trackers_blocked > 0 {
+                    ctx.trackers_blocked.write().insert(message_id.clone(), trackers_blocked);
+                } else {
+                    ctx.trackers_blocked.write().remove(&message_id);
+                }
+                ctx.bodies.write().insert(message_id, content);
+            }
+            CoreEvent::Compose(composition) => ctx.composition.set(Some(composition)),
+            CoreEvent::InvitationLoaded { message_id, invitation, answer } => {
+                ctx.invitations.write().insert(message_id, (invitation, answer));
+            }
+            CoreEvent::InvitationAnswered { message_id, status } => {
+                if let Some((_, answer)) = ctx.invitations.write().get_mut(&message_id) {
+                    *answer = Some(status);
+                }
+                ctx.toasts.success("Answer sent to the organizer");
+            }
+            CoreEvent::MessageExported { message_id, source } => {
+                if let Err(e) = download("message.eml", "message/rfc822", &source) {
+                    error!("Failed to save message {}: {:?}", message_id.to_string(), e);
+                    ctx.toasts.error("Failed to save the message");
+                }
+            }
+            CoreEvent::DraftSaved(_) => {
+                ctx.composition.set(None);
+                ctx.toasts.success("Draft saved");
+            }
+            CoreEvent::Undoable { message, undo } => {
+                let action = ToastAction { label: "Undo".to_string(), command: undo };
+                ctx.toasts.show(ToastKind::Info, message, Some(action));
+            }
+            CoreEvent::MessageRemoved(message_id) => {
+                ctx.messages.write().retain(|message| message.id != message_id);
+                ctx.conversation.write().retain(|message| message.id != message_id);
+                if ctx.selected_message.read().as_ref() == Some(&message_id) {
+                    ctx.selected_message.set(None);
+                }
+            }
+            CoreEvent::MessageRead { message_id, read } => {
+                let mark = |messages: &mut Vec<Arc<Message>>| {
+                    for message in messages.iter_mut().filter(|message| message.id == message_id) {
+                        *message = Arc::new(Message { is_read: read, ..(**message).clone() });
+                    }
+                };
+                mark(&mut ctx.messages.write());
+                mark(&mut ctx.conversation.write());
+            }
+            // Labels aren't shown in the message list yet.
+            CoreEvent::MessageLabeled { .. } => {}
+            CoreEvent::Unsubscribed(message_id) => {
+                let forget = |messages: &mut Vec<Arc<Message>>| {
+                    for message in messages.iter_mut().filter(|message| message.id == message_id) {
+                        *message = Arc::new(Message { unsubscribe: None, ..(**message).clone() });
+                    }
+                };
+                forget(&mut ctx.messages.write());
+                forget(&mut ctx.conversation.write());
+                ctx.toasts.success("Unsubscribed");
+            }
+            CoreEvent::MailboxRead(mailbox_id) => {
+                if let Some(node) = ctx.mailbox_nodes.write().get_mut(&mailbox_id) {
+                    node.unread_count = 0;
+                }
+                if ctx.selected_mailbox.read().as_ref() == Some(&mailbox_id) {
+                    let read = ctx
+                        .messages
+                        .read()
+                        .iter()
+                        .map(|message| Arc::new(Message { is_read: true, ..(**message).clone() }))
+                        .collect();
+                    ctx.messages.set(read);
+                }
+            }
+            CoreEvent::MailboxesChanged(folders) => {
+                let (root_ids, mboxes) = build_mailbox_tree(folders);
+                let selected = ctx.selected_mailbox.read().clone();
+                if selected.is_some_and(|mailbox_id| !mboxes.contains_key(&mailbox_id)) {
+                    ctx.messages.set(Vec::new());
+                    ctx.conversation.set(Vec::new());
+                    ctx.selected_mailbox.set(None);
+                }
+                ctx.mailbox_nodes.set(mboxes);
+                ctx.mailbox_roots.set(root_ids);
+            }
+            CoreEvent::SearchResults { query, envelopes } => {
+                ctx.search.set(Some(query));
+                ctx.loading_more_messages.set(false);
+                ctx.messages.set(envelopes.into_iter().map(|e| Arc::new(e.into())).collect());
+                ctx.conversation.set(Vec::new());
+                ctx.selected_message.set(None);
+            }
+            CoreEvent::SettingsLoaded(settings) => ctx.settings.set(settings),
+            CoreEvent::SignInRequired(client) => ctx.sign_in.set(Some(client)),
+            CoreEvent::ContactSuggestions { prefix, contacts } => {
+                ctx.contact_suggestions.set((prefix, contacts));
+            }
+            CoreEvent::ContactsLoaded(contacts) => ctx.contacts.set(contacts),
+            CoreEvent::Diagnostics(snapshot) => ctx.diagnostics.set(snapshot),
+            CoreEvent::Error(e) => {
+                error!("{}", e);
+                ctx.toasts.error(e);
+            }
         }
     }
 }
 
+/// The body of a message: its HTML alternative if it has one, sanitized
+/// for rendering and with its inline images embedded, else its plain text.
+/// Never binary. With `block_trackers` the trackers are removed from HTML
+/// too, and counted.
+async fn load_body(
+    connector: &dyn EmailConnector<Stream>,
+    message_id: &mailiner_core::MessageId,
+    block_trackers: bool,
+) -> mailiner_core::Result<(MessageContent, usize)> {
+    let structure = connector.get_message_structure(message_id).await?;
+    let Some(part_id) = structure
+        .find("text/html")
+        .or_else(|| structure.find("text/plain"))
+        .and_then(|part| part.id.clone())
+    else {
+        return Ok((MessageContent::Text(String::new()), 0));
+    };
+    Ok(match connector.get_message_part(message_id, &part_id).await?.content {
+        MessageContent::Html(html) => {
+            let (html, trackers) = if block_trackers {
+                sanitize_html_without_trackers(&html)
+            } else {
+                (sanitize_html(&html), 0)
+            };
+            let html = embed_inline_parts(connector, message_id, &structure, html).await;
+            (MessageContent::Html(html), trackers)
+        }
+        MessageContent::Binary(data) => (MessageContent::Text(String::from_utf8_lossy(&data).into_owned()), 0),
+        content => (content, 0),
+    })
+}
+
+/// Sanitized `html` with the `cid:` images of the message's own parts
+/// fetched and turned into `data:` URLs. The ones that can't be loaded stay
+/// broken images, like remote images that fail.
+async fn embed_inline_parts(
+    connector: &dyn EmailConnector<Stream>,
+    message_id: &mailiner_core::MessageId,
+    structure: &MessageStructure,
+    html: String,
+) -> String {
+    let mut urls = HashMap::new();
+    for content_id in content_ids(&html) {
+        let Some(part) = structure.find_content_id(&content_id) else {
+            continue;
+        };
+        let Some(part_id) = &part.id else {
+            continue;
+        };
+        match connector.get_message_part(message_id, part_id).await {
+            Ok(fetched) => {
+                let MessageContent::Binary(data) = fetched.content else {
+                    continue;
+                };
+                if let Some(url) = image_data_url(&part.content_type, &data) {
+                    urls.insert(content_id, url);
+                }
+            }
+            Err(e) => warn!("Failed to load inline part {} of message {}: {}", part_id, message_id, e),
+        }
+    }
+    if urls.is_empty() { html } else { inline_images(&html, &urls) }
+}
+
+/// The calendar in the `text/calendar` part of a message, if it has one.
+async fn load_invitation(
+    connector: &dyn EmailConnector<Stream>,
+    message_id: &mailiner_core::MessageId,
+) -> mailiner_core::Result<Option<Calendar>> {
+    let structure = connector.get_message_structure(message_id).await?;
+    let Some(part_id) = structure.find("text/calendar").and_then(|part| part.id.clone()) else {
+        return Ok(None);
+    };
+    match connector.get_message_part(message_id, &part_id).await?.content {
+        MessageContent::Text(text) | MessageContent::Html(text) => Calendar::parse(&text).map(Some),
+        MessageContent::Binary(data) => Calendar::parse(&String::from_utf8_lossy(&data)).map(Some),
+    }
+}
+
+/// Starts a reply to or a forward of a message of the selected mailbox,
+/// with its body as text.
+async fn respond(
+    metrics: &MemoryMetrics,
+    connector: &dyn EmailConnector<Stream>,
+    envelopes: &[Envelope],
+    message_id: &MessageId,
+    compose: impl FnOnce(&Envelope, &str) -> Composition,
+) -> CoreEvent {
+    let id = mailiner_core::MessageId::new(message_id.to_string());
+    let Some(original) = envelopes.iter().find(|envelope| envelope.id == id) else {
+        return CoreEvent::Error(format!("Message {} is not in the selected mailbox", id));
+    };
+    match timed(metrics, load_body(connector, &id, true)).await {
+        Ok((MessageContent::Html(html), _)) => CoreEvent::Compose(compose(original, &html_to_text(&html))),
+        Ok((MessageContent::Text(text), _)) => CoreEvent::Compose(compose(original, &text)),
+        Ok((MessageContent::Binary(_), _)) => CoreEvent::Compose(compose(original, "")),
+        Err(e) => CoreEvent::Error(format!("Failed to load message {}: {}", id, e)),
+    }
+}
+
+/// Removes a moved or deleted message from the listed envelopes. It's gone
+/// from the server too, so the next page starts one message earlier.
+fn forget_envelope(envelopes: &mut Vec<Envelope>, listed_count: &mut usize, id: &mailiner_core::MessageId) {
+    let count = envelopes.len();
+    envelopes.retain(|envelope| envelope.id != *id);
+    if envelopes.len() < count {
+        *listed_count = listed_count.saturating_sub(1);
+    }
+}
+
+/// Adds the senders and recipients of `envelopes` to the contacts, and
+/// saves the ones that changed. Failing to save only loses suggestions.
+async fn collect_contacts(storage: &dyn Storage, envelopes: &[Envelope], own: &str) {
+    if let Err(e) = record_contacts(storage, envelopes, own).await {
+        warn!("Failed to save contacts: {}", e);
+    }
+}
+
+async fn record_contacts(storage: &dyn Storage, envelopes: &[Envelope], own: &str) -> mailiner_core::Result<()> {
+    let mut contacts = storage.list_contacts().await?;
+    for i in contacts::record(&mut contacts, contacts::sightings(envelopes, Some(own))) {
+        storage.save_contact(&contacts[i]).await?;
+    }
+    Ok(())
+}
+
+/// Merges the contacts into the first of `contact_ids`, which is kept.
+async fn merge_contacts(storage: &dyn Storage, contact_ids: &[ContactId]) -> mailiner_core::Result<()> {
+    let Some((into_id, duplicate_ids)) = contact_ids.split_first() else {
+        return Ok(());
+    };
+    let mut into = storage.get_contact(into_id).await?;
+    for duplicate_id in duplicate_ids {
+        into.merge(storage.get_contact(duplicate_id).await?);
+    }
+    storage.save_contact(&into).await?;
+    for duplicate_id in duplicate_ids {
+        storage.delete_contact(duplicate_id).await?;
+    }
+    Ok(())
+}
+
+/// Lists the contacts again after the user changed them, or the error if
+/// the change failed.
+async fn listed_contacts(storage: &dyn Storage, changed: mailiner_core::Result<()>) -> CoreEvent {
+    match changed {
+        Ok(()) => match storage.list_contacts().await {
+            Ok(contacts) => CoreEvent::ContactsLoaded(contacts),
+            Err(e) => CoreEvent::Error(format!("Failed to list contacts: {}", e)),
+        },
+        Err(e) => CoreEvent::Error(format!("Failed to save contacts: {}", e)),
+    }
+}
+
+/// Marks the unread messages of a folder as read, all at once where the
+/// server allows it.
+async fn mark_folder_read(
+    metrics: &MemoryMetrics,
+    connector: &dyn EmailConnector<Stream>,
+    folder_id: &FolderId,
+) -> mailiner_core::Result<()> {
+    let unread: Vec<mailiner_core::MessageId> = timed(metrics, connector.list_envelopes(folder_id))
+        .await?
+        .into_iter()
+        .filter(|envelope| !envelope.is_read)
+        .map(|envelope| envelope.id)
+        .collect();
+    if unread.is_empty() {
+        return Ok(());
+    }
+    timed(metrics, connector.bulk_update_envelope_flags(&unread, &[("is_read", true)])).await
+}
+
+/// Searches every folder of the account, returning at most
+/// [`SEARCH_LIMIT`] messages.
+async fn search(
+    metrics: &MemoryMetrics,
+    connector: &dyn EmailConnector<Stream>,
+    account_id: Option<&AccountId>,
+    query: &SearchQuery,
+) -> mailiner_core::Result<Vec<Envelope>> {
+    let Some(account_id) = account_id else {
+        return Ok(Vec::new());
+    };
+    let mut found = Vec::new();
+    'folders: for folder in timed(metrics, connector.list_folders(account_id)).await? {
+        for id in timed(metrics, connector.search(&folder.id, query)).await? {
+            if found.len() == SEARCH_LIMIT {
+                break 'folders;
+            }
+            found.push(timed(metrics, connector.get_envelope(&id)).await?);
+        }
+    }
+    found.sort_by_key(|envelope| std::cmp::Reverse(envelope.date));
+    Ok(found)
+}
+
+/// Lists the folders of the account again, after they changed.
+async fn reload_folders(
+    metrics: &MemoryMetrics,
+    connector: &dyn EmailConnector<Stream>,
+    account_id: Option<&AccountId>,
+) -> CoreEvent {
+    let Some(account_id) = account_id else {
+        return CoreEvent::Error("No account is selected".to_string());
+    };
+    match timed(metrics, connector.list_folders(account_id)).await {
+        Ok(folders) => CoreEvent::MailboxesChanged(folders),
+        Err(e) => CoreEvent::Error(format!("Failed to list folders: {}", e)),
+    }
+}
+
+/// The account to connect, the first one by name. Without any, asks the
+/// user to set one up and waits for them to. `None` if the UI is gone.
+async fn account_config(
+    accounts: &AccountManager,
+    storage: &dyn Storage,
+    secure_storage: &dyn SecureStorage,
+    commands: &mut mpsc::Receiver<UiCommand>,
+    events: &mpsc::Sender<CoreEvent>,
+) -> Option<AccountConfig> {
+    if let Some(config) = accounts.configs().await.into_iter().next() {
+        return Some(config);
+    }
+    events.send(CoreEvent::AccountSetupRequired).await.ok()?;
+    loop {
+        match commands.recv().await? {
+            UiCommand::SetUpAccount { config, backend, password } => {
+                match set_up_account(accounts, storage, secure_storage, &config, backend, &password).await {
+                    Ok(()) => return Some(config),
+                    Err(e) => {
+                        let error = format!("Failed to save the account: {}", e);
+                        events.send(CoreEvent::Error(error)).await.ok()?;
+                    }
+                }
+            }
+            command => warn!("Ignoring {:?} until an account is set up", command),
+        }
+    }
+}
+
+/// Saves a new account, where its mail is and the password it logs in with.
+async fn set_up_account(
+    accounts: &AccountManager,
+    storage: &dyn Storage,
+    secure_storage: &dyn SecureStorage,
+    config: &AccountConfig,
+    backend: Option<String>,
+    password: &str,
+) -> mailiner_core::Result<()> {
+    if let Some(backend) = &backend {
+        backend.parse::<BackendUri>()?;
+    }
+    accounts.save(config.clone()).await?;
+    if backend.is_some() {
+        let mut preferences = match storage.get_account_preferences(&config.account_id).await {
+            Ok(preferences) => preferences,
+            Err(MailinerError::NotFound(_)) => AccountPreferences::new(config.account_id.clone()),
+            Err(e) => return Err(e),
+        };
+        preferences.backend = backend;
+        storage.save_account_preferences(&preferences).await?;
+    }
+    if !password.is_empty() {
+        save_password(secure_storage, &config.account_id, password).await?;
+    }
+    Ok(())
+}
+
+/// Connects to where the account's mail is, the backend chosen when it was
+/// set up or its IMAP server, and logs in. Local backends, e.g. the demo
+/// one, have no server to connect to.
+async fn connect(
+    platform: &Platform<Stream>,
+    storage: &dyn Storage,
+    registry: &ConnectorRegistry<Stream>,
+    config: &AccountConfig,
+    password: &str,
+) -> mailiner_core::Result<Arc<dyn EmailConnector<Stream>>> {
+    let backend = match storage.get_account_preferences(&config.account_id).await {
+        Ok(AccountPreferences { backend: Some(backend), .. }) => backend.parse()?,
+        Ok(_) | Err(MailinerError::NotFound(_)) => BackendUri::imap(&config.incoming)?,
+        Err(e) => return Err(e),
+    };
+    let connector = registry.create(&backend.to_string())?;
+    if backend.host.is_empty() {
+        return Ok(connector);
+    }
+    info!("Connecting to {}...", backend.host);
+    let stream = platform.network.connect(&config.incoming.host, config.incoming.port).await?;
+    connector.connect(stream).await?;
+    connector.authenticate(password).await?;
+    info!("Connected to {}", backend.host);
+    Ok(connector)
+}
+
+/// An access token of the account, refreshed if needed. Without saved
+/// tokens, or if they can't be refreshed, asks the user to sign in and waits
+/// for them to. `None` if the UI is gone.
+async fn oauth_credentials(
+    storage: &dyn SecureStorage,
+    account_id: &AccountId,
+    client: OAuthClient,
+    commands: &mut mpsc::Receiver<UiCommand>,
+    events: &mpsc::Sender<CoreEvent>,
+) -> Option<String> {
+    let mut token = load_token(storage, account_id).await;
+    loop {
+        let Some(saved) = token.take() else {
+            events.send(CoreEvent::SignInRequired(client.clone())).await.ok()?;
+            token = loop {
+                match commands.recv().await? {
+                    UiCommand::SignedIn(token) => break Some(token),
+                    command => warn!("Ignoring {:?} until signed in", command),
+                }
+            };
+            continue;
+        };
+        let provider = TokenProvider::new(saved, Arc::new(FetchRefresher(client.clone())));
+        match provider.access_token(false).await {
+            Ok(access_token) => {
+                if let Err(e) = save_token(storage, account_id, &provider.token().await).await {
+                    warn!("Failed to save the OAuth tokens: {}", e);
+                }
+                return Some(access_token);
+            }
+            Err(e) => {
+                let error = format!("Failed to refresh the access token, signing in again: {}", e);
+                events.send(CoreEvent::Error(error)).await.ok()?;
+            }
+        }
+    }
+}
+
+/// Runs a request to the server, recording how long it took for the
+/// diagnostics.
+async fn timed<T>(metrics: &MemoryMetrics, request: impl Future<Output = T>) -> T {
+    let started = Utc::now();
+    let result = request.await;
+    metrics.record(COMMAND_LATENCY_MS, (Utc::now() - started).num_milliseconds() as f64);
+    result
+}
+
 fn build_mailbox_tree(folders: Vec<Folder>) -> (Vec<MailboxId>, HashMap<MailboxId, MailboxNode>) {
     let mut root_ids = Vec::new();
     let mut mboxes = HashMap::<MailboxId, MailboxNode>::new();
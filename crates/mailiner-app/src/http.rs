@@ -0,0 +1,66 @@
+//! Requests to web services, with the browser's fetch.
+
+use mailiner_core::{MailinerError, Result};
+use web_sys::wasm_bindgen::{JsCast, JsValue};
+use web_sys::{Headers, RequestInit, RequestMode, Response};
+
+/// Gets the body of `url`, if the server answers with success.
+pub async fn get(url: &str) -> Result<Vec<u8>> {
+    let init = RequestInit::new();
+    init.set_method("GET");
+    let response = fetch(url, &init).await?;
+    if !response.ok() {
+        return Err(MailinerError::Connector(format!("{} answered {}", url, response.status())));
+    }
+    text(&response).await
+}
+
+/// Posts a form and returns the body of the response, whatever its status:
+/// token endpoints explain errors in the body.
+pub async fn post_form(url: &str, form: &str) -> Result<Vec<u8>> {
+    let headers = Headers::new().map_err(js_error)?;
+    headers
+        .set("Content-Type", "application/x-www-form-urlencoded")
+        .map_err(js_error)?;
+    let init = RequestInit::new();
+    init.set_method("POST");
+    init.set_headers(&headers);
+    init.set_body(&JsValue::from_str(form));
+    let response = fetch(url, &init).await?;
+    text(&response).await
+}
+
+/// Posts a form to a server that doesn't allow reading its answer, e.g. to
+/// unsubscribe from a mailing list. Only failing to send it is an error.
+pub async fn post_form_no_cors(url: &str, form: &str) -> Result<()> {
+    let headers = Headers::new().map_err(js_error)?;
+    headers
+        .set("Content-Type", "application/x-www-form-urlencoded")
+        .map_err(js_error)?;
+    let init = RequestInit::new();
+    init.set_method("POST");
+    init.set_mode(RequestMode::NoCors);
+    init.set_headers(&headers);
+    init.set_body(&JsValue::from_str(form));
+    fetch(url, &init).await?;
+    Ok(())
+}
+
+async fn fetch(url: &str, init: &RequestInit) -> Result<Response> {
+    let window = web_sys::window().ok_or_else(|| js_error("No window".into()))?;
+    let response = wasm_bindgen_futures::JsFuture::from(window.fetch_with_str_and_init(url, init))
+        .await
+        .map_err(js_error)?;
+    response.dyn_into().map_err(js_error)
+}
+
+async fn text(response: &Response) -> Result<Vec<u8>> {
+    let text = wasm_bindgen_futures::JsFuture::from(response.text().map_err(js_error)?)
+        .await
+        .map_err(js_error)?;
+    Ok(text.as_string().unwrap_or_default().into_bytes())
+}
+
+pub fn js_error(error: JsValue) -> MailinerError {
+    MailinerError::Connector(format!("{:?}", error))
+}
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+
+/// Where the message being read is shown.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReadingPane {
+    /// Right of the message list, in a third column.
+    #[default]
+    Right,
+    /// Below the message list.
+    Bottom,
+}
+
+/// A pane the user can resize by dragging its edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pane {
+    Sidebar,
+    MessageList,
+}
+
+/// The sizes of the panes of the main window, in pixels.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Layout {
+    pub reading_pane: ReadingPane,
+    pub sidebar_width: f64,
+    /// The width of the message list, or its height with the reading pane
+    /// at the bottom.
+    pub message_list_size: f64,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            reading_pane: ReadingPane::Right,
+            sidebar_width: 200.0,
+            message_list_size: 350.0,
+        }
+    }
+}
+
+/// No pane can be made smaller than this, so that its edge can still be
+/// grabbed.
+const MIN_SIZE: f64 = 120.0;
+
+impl Layout {
+    /// Moves the edge of `pane` to the mouse at `x`, `y` in the window.
+    pub fn drag(&mut self, pane: Pane, x: f64, y: f64) {
+        match pane {
+            Pane::Sidebar => self.sidebar_width = x.max(MIN_SIZE),
+            Pane::MessageList => {
+                self.message_list_size = match self.reading_pane {
+                    ReadingPane::Right => x - self.sidebar_width,
+                    ReadingPane::Bottom => y,
+                }
+                .max(MIN_SIZE)
+            }
+        }
+    }
+
+    /// The sizes as CSS variables, for the style of the main window.
+    pub fn style(&self) -> String {
+        format!(
+            "--sidebar-width: {}px; --message-list-size: {}px;",
+            self.sidebar_width, self.message_list_size
+        )
+    }
+}
@@ -1,6 +1,9 @@
+use std::collections::HashMap;
+
 use mailiner_core::{Folder, FolderId};
+use serde::{Deserialize, Serialize};
 
-#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
 pub struct MailboxId(String);
 
 impl From<String> for MailboxId {
@@ -42,4 +45,16 @@ impl From<Folder> for MailboxNode {
             total_count: 0,
         }
     }
-}
\ No newline at end of file
+}
+
+/// The mailbox with one of `names`, in lowercase, ignoring the path of its
+/// parents, e.g. `INBOX.Drafts` or `[Gmail]/Drafts` for `drafts`.
+pub fn find_by_name(mailboxes: &HashMap<MailboxId, MailboxNode>, names: &[&str]) -> Option<MailboxId> {
+    mailboxes
+        .values()
+        .find(|node| {
+            let name = node.name.rsplit(['/', '.']).next().unwrap_or_default();
+            names.contains(&name.to_lowercase().as_str())
+        })
+        .map(|node| node.id.clone())
+}
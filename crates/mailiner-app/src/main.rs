@@ -3,20 +3,39 @@ use std::sync::Arc;
 
 use dioxus::logger::tracing::Level;
 use dioxus::prelude::*;
+use mailiner_core::MetricsSnapshot;
 
-use crate::account::{Account, AccountId};
-use crate::components::{EmailNavigation, MessageView, Sidebar};
+use crate::components::{
+    handle_key, AccountSetup, Composer, ContactsView, ContextMenu, ConversationView, EmailNavigation,
+    ShortcutsOverlay, Sidebar, SignIn, Splitter, TabBar, ToastHost,
+};
 use crate::context::AppContext;
-use crate::core_event::{core_loop, CoreEvent};
+use crate::core_event::{UiBus, UiCommand};
+use crate::layout::{Pane, ReadingPane};
 use crate::mailbox::{MailboxId, MailboxNode};
 use crate::message::{Message, MessageId};
+use crate::settings::Settings;
+use crate::tabs::Tabs;
+use crate::toast::Toasts;
 
 mod account;
+mod avatar;
 mod components;
 mod context;
 mod core_event;
+mod download;
+mod http;
+mod layout;
 mod mailbox;
 mod message;
+mod oauth;
+mod outgoing;
+mod platform;
+mod settings;
+mod shortcuts;
+mod tabs;
+mod toast;
+#[cfg(target_arch = "wasm32")]
 mod websocket_stream;
 
 #[derive(Debug, Clone, Routable, PartialEq)]
@@ -25,6 +44,8 @@ enum Route {
     #[layout(MainLayout)]
     #[route("/")]
     MainView {},
+    #[route("/contacts")]
+    ContactsView {},
 }
 
 const FAVICON: Asset = asset!("/assets/favicon.ico");
@@ -47,45 +68,75 @@ fn MainLayout() -> Element {
 
 #[component]
 fn App() -> Element {
-    let dummy_account_id = AccountId::new("1");
-
-    let selected_account = use_signal(|| Some(dummy_account_id.clone()));
-    let accounts = use_signal(|| {
-        HashMap::from([(
-            dummy_account_id.clone(),
-            Account {
-                id: dummy_account_id.clone(),
-                name: "Valhalla".to_string(),
-                email: "me@dvratil.cz".to_string(),
-            },
-        )])
-    });
+    // The OAuth popup only waits for the app to read its URL and close it.
+    if oauth::is_callback() {
+        return rsx! {
+            p { "Signing in…" }
+        };
+    }
+
+    let selected_account = use_signal(|| None);
+    let accounts = use_signal(|| HashMap::new());
 
     let mailbox_nodes = use_signal(|| HashMap::new());
     let mailbox_roots = use_signal(|| { Vec::new() });
     let selected_mailbox = use_signal(|| None);
 
     let messages = use_signal(|| Vec::new());
+    let loading_more_messages = use_signal(|| false);
+    let all_messages_loaded = use_signal(|| true);
     let selected_message = use_signal(|| None);
+    let search = use_signal(|| None);
+    let conversation = use_signal(|| Vec::new());
+    let bodies = use_signal(|| HashMap::new());
+    let trackers_blocked = use_signal(|| HashMap::new());
+    let invitations = use_signal(|| HashMap::new());
+    let composition = use_signal(|| None);
+    let contact_suggestions = use_signal(|| (String::new(), Vec::new()));
+    let contacts = use_signal(|| Vec::new());
+    let tabs = use_hook(Tabs::default);
+    let context_menu = use_signal(|| None);
+    let avatars = use_signal(|| HashMap::new());
+
+    let account_setup = use_signal(|| false);
+    let sign_in = use_signal(|| None);
+    let diagnostics = use_signal(MetricsSnapshot::default);
+    let settings = use_signal(Settings::default);
+    let toasts = use_hook(Toasts::default);
 
     let ctx = AppContext {
         accounts,
         mailbox_nodes,
         mailbox_roots,
         messages,
+        loading_more_messages,
+        all_messages_loaded,
 
         selected_mailbox,
         selected_account,
         selected_message,
+        search,
+        conversation,
+        bodies,
+        trackers_blocked,
+        invitations,
+        composition,
+        contact_suggestions,
+        contacts,
+        tabs,
+        context_menu,
+        avatars,
+
+        account_setup,
+        sign_in,
+        diagnostics,
+        settings,
+        toasts,
     };
     let ctx_clone = ctx.clone();
 
     use_context_provider(|| ctx);
-    let tx = use_coroutine(move |core_rx| {
-        let ctx = ctx_clone.clone();
-        async move { core_loop(core_rx, ctx).await }
-    });
-    tx.send(CoreEvent::SelectAccount(dummy_account_id.clone()));
+    use_context_provider(|| core_event::start(ctx_clone.clone()));
 
     rsx! {
         document::Link { rel: "icon", href: FAVICON }
@@ -97,17 +148,95 @@ fn App() -> Element {
 
 #[component]
 fn MainView() -> Element {
+    let ctx = use_context::<AppContext>();
+    let bus = use_context::<UiBus>();
+    let composing = ctx.composition.read().is_some();
+    // Each draft gets its own composer, which starts from the draft's tab.
+    let draft_tab = ctx.tabs.active().map_or(0, |tab| tab.id);
+    let context_menu = ctx.context_menu;
+    let mut settings = ctx.settings;
+    let shortcuts_overlay = use_signal(|| false);
+    let mut dragging = use_signal(|| None::<Pane>);
+
+    let layout = settings.read().layout.clone();
+    let key_bus = bus.clone();
+
     rsx! {
         div {
             id: "app",
+            class: if dragging().is_some() { "resizing" },
+            style: layout.style(),
+            // Focusable, to get the keys pressed anywhere in the app.
+            tabindex: 0,
+            autofocus: true,
+            onkeydown: move |e| handle_key(e, ctx.clone(), &key_bus, shortcuts_overlay),
+            onmousemove: move |e| {
+                if let Some(pane) = dragging() {
+                    let point = e.client_coordinates();
+                    settings.write().layout.drag(pane, point.x, point.y);
+                }
+            },
+            // The sizes are saved once the user lets go.
+            onmouseup: move |_| {
+                if dragging.take().is_some() {
+                    bus.send(UiCommand::SaveSettings(settings.peek().clone()));
+                }
+            },
 
             Sidebar {
             }
 
-            EmailNavigation {
+            Splitter {
+                pane: Pane::Sidebar,
+                dragging,
+            }
+
+            div {
+                id: "panes",
+                class: if layout.reading_pane == ReadingPane::Bottom { "bottom" } else { "right" },
+
+                EmailNavigation {
+                }
+
+                Splitter {
+                    pane: Pane::MessageList,
+                    dragging,
+                }
+
+                div {
+                    id: "reading-pane",
+
+                    TabBar {
+                    }
+
+                    if composing {
+                        Composer {
+                            key: "{draft_tab}",
+                        }
+                    } else {
+                        ConversationView {
+                        }
+                    }
+                }
+            }
+
+            ContextMenu {
+                menu: context_menu,
+            }
+
+            AccountSetup {
+            }
+
+            SignIn {
+            }
+
+            ToastHost {
             }
 
-            MessageView {
+            if shortcuts_overlay() {
+                ShortcutsOverlay {
+                    visible: shortcuts_overlay,
+                }
             }
         }
     }
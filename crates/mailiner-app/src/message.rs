@@ -1,6 +1,10 @@
+use chrono::Local;
+use mailiner_core::grouping::DateGroup;
+use mailiner_core::unsubscribe::Unsubscribe;
 use mailiner_core::{EmailAddress, Envelope};
+use serde::{Deserialize, Serialize};
 
-#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
 pub struct MessageId(String);
 
 impl From<String> for MessageId {
@@ -9,18 +13,37 @@ impl From<String> for MessageId {
     }
 }
 
-#[derive(PartialEq, Debug)]
+impl ToString for MessageId {
+    fn to_string(&self) -> String {
+        self.0.clone()
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
 pub struct Message {
     pub id: MessageId,
     pub subject: String,
     pub from: String,
+    /// The name and the address of the first sender, for the avatar.
+    pub sender_name: Option<String>,
+    pub sender_email: String,
     pub to: String,
     pub cc: Option<String>,
     pub bcc: Option<String>,
+    pub date: String,
+    /// The section of the message list the message is listed in.
+    pub date_group: DateGroup,
+    pub is_read: bool,
+    /// How to leave the mailing list the message came from.
+    pub unsubscribe: Option<Unsubscribe>,
 }
 
 impl From<Envelope> for Message {
     fn from(envelope: Envelope) -> Self {
+        let sender = envelope
+            .from
+            .as_ref()
+            .and_then(|from| from.addrs().first().map(|addr| (*addr).clone()));
         Self {
             id: MessageId::from(envelope.id.to_string()),
             subject: envelope.subject.unwrap_or_default(),
@@ -29,6 +52,8 @@ impl From<Envelope> for Message {
                 .as_ref()
                 .map(EmailAddress::to_string)
                 .unwrap_or_default(),
+            sender_name: sender.as_ref().and_then(|addr| addr.name.clone()),
+            sender_email: sender.and_then(|addr| addr.email).unwrap_or_default(),
             to: envelope
                 .to
                 .as_ref()
@@ -36,6 +61,10 @@ impl From<Envelope> for Message {
                 .unwrap_or_default(),
             cc: envelope.cc.as_ref().map(EmailAddress::to_string),
             bcc: envelope.bcc.as_ref().map(EmailAddress::to_string),
+            date: envelope.date.format("%a, %-d %b %Y %H:%M").to_string(),
+            date_group: DateGroup::of(envelope.date, &Local::now()),
+            is_read: envelope.is_read,
+            unsubscribe: envelope.unsubscribe,
         }
     }
 }
@@ -0,0 +1,119 @@
+//! Signing in with OAuth in the browser. The provider's consent page opens
+//! in a popup, and once it redirects the popup back to the app, the code in
+//! the URL is exchanged for the tokens.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use dioxus::logger::tracing::warn;
+use mailiner_core::oauth::{parse_token_response, Authorization, OAuthClient, OAuthToken, TokenRefresher};
+use mailiner_core::platform::web::WebTimer;
+use mailiner_core::platform::{SecureStorage, Timer};
+use mailiner_core::{AccountConfig, AccountId, AuthMethod, MailinerError, Result};
+use send_wrapper::SendWrapper;
+use web_sys::Window;
+
+use crate::http::{js_error, post_form};
+
+/// Where the provider redirects the popup to.
+const CALLBACK_PATH: &str = "/oauth/callback";
+
+/// The app's registration with the provider, set when building the app.
+const CLIENT_ID: Option<&str> = option_env!("OAUTH_CLIENT_ID");
+const CLIENT_SECRET: Option<&str> = option_env!("OAUTH_CLIENT_SECRET");
+
+/// How often the popup is checked for the redirect.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The OAuth client for the account, if it logs in with OAuth to a provider
+/// the app is registered with.
+pub fn client(config: &AccountConfig) -> Option<OAuthClient> {
+    if !matches!(config.incoming.auth_method, Some(AuthMethod::XOAuth2 | AuthMethod::OAuthBearer)) {
+        return None;
+    }
+    let Some(client_id) = CLIENT_ID else {
+        warn!("The app was built without OAUTH_CLIENT_ID, so it can't sign in with OAuth");
+        return None;
+    };
+    let host = config.incoming.host.to_lowercase();
+    if host.ends_with("gmail.com") || host.ends_with("googlemail.com") {
+        Some(OAuthClient::google(client_id, CLIENT_SECRET.map(String::from)))
+    } else if host.ends_with("office365.com") || host.ends_with("outlook.com") {
+        Some(OAuthClient::microsoft(client_id))
+    } else {
+        warn!("No OAuth provider is known for {}", host);
+        None
+    }
+}
+
+/// Whether the app was opened as the popup the provider redirected back.
+pub fn is_callback() -> bool {
+    web_sys::window()
+        .and_then(|window| window.location().pathname().ok())
+        .is_some_and(|path| path == CALLBACK_PATH)
+}
+
+fn secret_key(account_id: &AccountId) -> String {
+    format!("oauth/{}", account_id)
+}
+
+/// The tokens saved when the user last signed in.
+pub async fn load_token(storage: &dyn SecureStorage, account_id: &AccountId) -> Option<OAuthToken> {
+    let secret = storage.get_secret(&secret_key(account_id)).await.ok()??;
+    serde_json::from_str(&secret)
+        .inspect_err(|e| warn!("Ignoring invalid OAuth tokens of {}: {}", account_id, e))
+        .ok()
+}
+
+pub async fn save_token(storage: &dyn SecureStorage, account_id: &AccountId, token: &OAuthToken) -> Result<()> {
+    storage.set_secret(&secret_key(account_id), &serde_json::to_string(token)?).await
+}
+
+/// Opens the consent page in a popup. Browsers only allow popups in
+/// reaction to the user, so this has to be called from an event handler.
+pub fn open_consent(client: &OAuthClient) -> Result<(Authorization, Window)> {
+    let window = web_sys::window().ok_or_else(|| js_error("No window".into()))?;
+    let origin = window.location().origin().map_err(js_error)?;
+    let authorization = client.authorize(&format!("{}{}", origin, CALLBACK_PATH))?;
+    let popup = window
+        .open_with_url_and_target_and_features(&authorization.url, "mailiner-oauth", "popup,width=500,height=650")
+        .map_err(js_error)?
+        .ok_or_else(|| MailinerError::Authentication("The sign-in window was blocked".to_string()))?;
+    Ok((authorization, popup))
+}
+
+/// Waits for the provider to redirect the popup back, closes it, and
+/// exchanges the code for the tokens.
+pub async fn finish(client: &OAuthClient, authorization: &Authorization, popup: Window) -> Result<OAuthToken> {
+    let redirect = loop {
+        WebTimer.sleep(POLL_INTERVAL).await;
+        if popup.closed().unwrap_or(true) {
+            return Err(MailinerError::Authentication("The sign-in window was closed".to_string()));
+        }
+        // Reading the location fails while the popup shows the provider's
+        // page, which is on another origin.
+        if let Ok(href) = popup.location().href() {
+            if href.starts_with(&authorization.redirect_uri) {
+                break href;
+            }
+        }
+    };
+    let _ = popup.close();
+    let code = authorization.code(&redirect)?;
+    let response = post_form(&client.token_url, &client.token_request(authorization, &code)).await?;
+    parse_token_response(&response, Utc::now())
+}
+
+/// Refreshes access tokens at the provider's token endpoint.
+pub struct FetchRefresher(pub OAuthClient);
+
+#[async_trait]
+impl TokenRefresher for FetchRefresher {
+    async fn refresh(&self, refresh_token: &str) -> Result<OAuthToken> {
+        // JS futures are not Send, but the browser only has a single thread.
+        let request = post_form(&self.0.token_url, &self.0.refresh_request(refresh_token));
+        let response = SendWrapper::new(request).await?;
+        parse_token_response(&response, Utc::now())
+    }
+}
@@ -0,0 +1,37 @@
+//! Sending mail, over a connection to the account's SMTP server opened for
+//! each message.
+
+use mailiner_core::platform::Platform;
+use mailiner_core::{AccountConfig, MailinerError, MessageSender, Result};
+use mailiner_smtp_connector::SmtpConnector;
+
+use crate::platform::Stream;
+
+/// Submits `message`, a complete RFC 5322 message from the account, for
+/// delivery to `recipients`. `credentials` are those of the incoming server,
+/// which the outgoing one shares.
+pub async fn send(
+    platform: &Platform<Stream>,
+    config: &AccountConfig,
+    credentials: &str,
+    recipients: &[String],
+    message: &[u8],
+) -> Result<()> {
+    let Some(server) = &config.outgoing else {
+        return Err(MailinerError::NotFound(format!("Outgoing server of account {}", config.account_id)));
+    };
+    let mut sender = SmtpConnector::new(server.host.clone(), server.port, server.username.clone())
+        .with_security(server.security);
+    if let Some(auth_method) = server.auth_method {
+        sender = sender.with_auth_method(auth_method);
+    }
+    let stream = platform.network.connect(&server.host, server.port).await?;
+    sender.connect(stream).await?;
+    let result = match sender.authenticate(credentials).await {
+        Ok(()) => sender.send(&config.email, recipients, message).await,
+        Err(e) => Err(e),
+    };
+    // The message went out or not, either way the connection is done.
+    let _ = sender.disconnect().await;
+    result
+}
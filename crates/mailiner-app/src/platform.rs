@@ -0,0 +1,57 @@
+use mailiner_core::platform::Platform;
+
+#[cfg(target_arch = "wasm32")]
+pub use web::{platform, Stream};
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::{platform, Stream};
+
+/// In the browser, connections are WebSockets to the proxy, and TLS runs on
+/// top of them in the connectors.
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use std::sync::Arc;
+
+    use mailiner_core::platform::web::{
+        LocalStorageFileSystem, LocalStorageSecureStorage, WebNotifier, WebTimer,
+    };
+
+    use super::Platform;
+    use crate::websocket_stream::{WebSocketNetwork, WebSocketStream};
+
+    const STORAGE_PREFIX: &str = "mailiner";
+    const PROXY_URL: &str = "ws://localhost:9400/proxy";
+    const PROXY_TOKEN: &str = "testtoken";
+
+    pub type Stream = WebSocketStream;
+
+    pub fn platform() -> Platform<Stream> {
+        Platform {
+            fs: Arc::new(LocalStorageFileSystem::new(STORAGE_PREFIX)),
+            secure_storage: Arc::new(LocalStorageSecureStorage::new(STORAGE_PREFIX)),
+            notifier: Arc::new(WebNotifier),
+            timer: Arc::new(WebTimer),
+            network: Arc::new(WebSocketNetwork::new(PROXY_URL, PROXY_TOKEN)),
+        }
+    }
+}
+
+/// On the desktop, connections are plain TCP to the servers, with TLS in the
+/// connectors, and the files are shared with the daemon.
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::env;
+
+    use tokio::net::TcpStream;
+
+    use super::Platform;
+
+    /// The daemon's default, relative to the working directory.
+    const DEFAULT_DATA_DIR: &str = ".mailiner";
+
+    pub type Stream = TcpStream;
+
+    pub fn platform() -> Platform<Stream> {
+        let data_dir = env::var("MAILINER_DATA_DIR").unwrap_or_else(|_| DEFAULT_DATA_DIR.to_string());
+        mailiner_core::platform::native::platform(data_dir)
+    }
+}
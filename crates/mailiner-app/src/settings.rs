@@ -0,0 +1,38 @@
+use dioxus::logger::tracing::warn;
+use mailiner_core::platform::FileSystem;
+use serde::{Deserialize, Serialize};
+
+use crate::layout::Layout;
+use crate::shortcuts::Keymap;
+
+/// Where the settings of the app are kept.
+const SETTINGS_PATH: &str = "settings.json";
+
+/// The user's preferences for the app itself, as opposed to those of an
+/// account.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    pub shortcuts: Keymap,
+    /// Whether the pictures of senders are looked up on Gravatar and in
+    /// BIMI records, which tells those services who mailed the user.
+    #[serde(default)]
+    pub remote_avatars: bool,
+    #[serde(default)]
+    pub layout: Layout,
+}
+
+/// The settings saved before, or the defaults on the first start.
+pub async fn load_settings(fs: &dyn FileSystem) -> Settings {
+    let Ok(data) = fs.read(SETTINGS_PATH).await else {
+        return Settings::default();
+    };
+    serde_json::from_slice(&data).unwrap_or_else(|e| {
+        warn!("Ignoring invalid {}: {}", SETTINGS_PATH, e);
+        Settings::default()
+    })
+}
+
+pub async fn save_settings(fs: &dyn FileSystem, settings: &Settings) -> mailiner_core::Result<()> {
+    fs.write(SETTINGS_PATH, &serde_json::to_vec(settings)?).await
+}
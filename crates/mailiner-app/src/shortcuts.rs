@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Where the keyboard focus is, which decides what the keys do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShortcutContext {
+    /// Browsing the messages of a mailbox.
+    List,
+    /// Reading a message.
+    Reader,
+    Composer,
+}
+
+/// What a keyboard shortcut does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Action {
+    NextMessage,
+    PreviousMessage,
+    Archive,
+    Reply,
+    ReplyAll,
+    Forward,
+    Search,
+    Compose,
+    Close,
+    ShowShortcuts,
+    NextTab,
+    PreviousTab,
+    CloseTab,
+}
+
+impl Action {
+    pub const ALL: [Action; 13] = [
+        Action::NextMessage,
+        Action::PreviousMessage,
+        Action::Archive,
+        Action::Reply,
+        Action::ReplyAll,
+        Action::Forward,
+        Action::Search,
+        Action::Compose,
+        Action::Close,
+        Action::ShowShortcuts,
+        Action::NextTab,
+        Action::PreviousTab,
+        Action::CloseTab,
+    ];
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::NextMessage => "Next message",
+            Action::PreviousMessage => "Previous message",
+            Action::Archive => "Archive",
+            Action::Reply => "Reply",
+            Action::ReplyAll => "Reply all",
+            Action::Forward => "Forward",
+            Action::Search => "Search",
+            Action::Compose => "Compose",
+            Action::Close => "Close",
+            Action::ShowShortcuts => "Show shortcuts",
+            Action::NextTab => "Next tab",
+            Action::PreviousTab => "Previous tab",
+            Action::CloseTab => "Close tab",
+        }
+    }
+
+    /// Whether the action can be done in `context`.
+    pub fn applies_in(self, context: ShortcutContext) -> bool {
+        match self {
+            Action::Archive | Action::Reply | Action::ReplyAll | Action::Forward => {
+                context == ShortcutContext::Reader
+            }
+            Action::NextTab | Action::PreviousTab | Action::CloseTab => true,
+            _ => context != ShortcutContext::Composer,
+        }
+    }
+}
+
+/// The keys of the shortcuts, named as by [`key_name`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Keymap(BTreeMap<String, Action>);
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self(BTreeMap::from([
+            ("j".to_string(), Action::NextMessage),
+            ("k".to_string(), Action::PreviousMessage),
+            ("e".to_string(), Action::Archive),
+            ("r".to_string(), Action::Reply),
+            ("a".to_string(), Action::ReplyAll),
+            ("f".to_string(), Action::Forward),
+            ("/".to_string(), Action::Search),
+            ("c".to_string(), Action::Compose),
+            ("Escape".to_string(), Action::Close),
+            ("?".to_string(), Action::ShowShortcuts),
+            ("Alt+PageDown".to_string(), Action::NextTab),
+            ("Alt+PageUp".to_string(), Action::PreviousTab),
+            ("Alt+w".to_string(), Action::CloseTab),
+        ]))
+    }
+}
+
+impl Keymap {
+    /// The action of `key` in `context`. While the user is typing, only keys
+    /// with a modifier and Escape are shortcuts.
+    pub fn action(&self, key: &str, context: ShortcutContext, typing: bool) -> Option<Action> {
+        if typing && !key.contains('+') && key != "Escape" {
+            return None;
+        }
+        self.0
+            .get(key)
+            .copied()
+            .filter(|action| action.applies_in(context))
+    }
+
+    pub fn key(&self, action: Action) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(_, bound)| **bound == action)
+            .map(|(key, _)| key.as_str())
+    }
+
+    /// Makes `key` trigger `action`, instead of the key it had before.
+    pub fn bind(&mut self, key: String, action: Action) {
+        self.0.retain(|_, bound| *bound != action);
+        self.0.insert(key, action);
+    }
+}
+
+/// The name of the key pressed, e.g. `j`, `?`, `Escape` or `Ctrl+Enter`.
+/// Shift is part of the character, so it is only named for other keys.
+pub fn key_name(event: &KeyboardEvent) -> String {
+    let modifiers = event.modifiers();
+    let key = event.key();
+    let mut name = String::new();
+    for (modifier, prefix) in [
+        (Modifiers::CONTROL, "Ctrl+"),
+        (Modifiers::ALT, "Alt+"),
+        (Modifiers::META, "Meta+"),
+    ] {
+        if modifiers.contains(modifier) {
+            name.push_str(prefix);
+        }
+    }
+    if modifiers.contains(Modifiers::SHIFT) && !matches!(key, Key::Character(_)) {
+        name.push_str("Shift+");
+    }
+    name.push_str(&key.to_string());
+    name
+}
+
+/// Whether the focus is in a text field, where keys type text.
+pub fn is_typing() -> bool {
+    web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.active_element())
+        .is_some_and(|element| {
+            matches!(element.tag_name().as_str(), "INPUT" | "TEXTAREA" | "SELECT")
+                || element.has_attribute("contenteditable")
+        })
+}
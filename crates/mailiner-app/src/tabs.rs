@@ -0,0 +1,127 @@
+use dioxus::prelude::*;
+use mailiner_core::compose::Composition;
+
+use crate::message::MessageId;
+
+/// What a tab above the reading pane shows.
+#[derive(Debug, Clone)]
+pub enum TabContent {
+    Message { message_id: MessageId, subject: String },
+    Search(String),
+    /// A message being written, kept here while another tab is shown.
+    Draft { composition: Composition, unsaved: bool },
+}
+
+impl TabContent {
+    pub fn title(&self) -> String {
+        let title = match self {
+            TabContent::Message { subject, .. } => subject.as_str(),
+            TabContent::Search(query) => return format!("Search: {}", query),
+            TabContent::Draft { composition, .. } => composition.subject.as_str(),
+        };
+        if title.trim().is_empty() {
+            "(no subject)".to_string()
+        } else {
+            title.to_string()
+        }
+    }
+
+    /// Whether the tab shows the same as `other`, so that opening it again
+    /// switches to it. Every draft is its own.
+    fn same(&self, other: &TabContent) -> bool {
+        match (self, other) {
+            (TabContent::Message { message_id: a, .. }, TabContent::Message { message_id: b, .. }) => a == b,
+            (TabContent::Search(a), TabContent::Search(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tab {
+    pub id: u64,
+    pub content: TabContent,
+}
+
+/// The tabs open above the reading pane, in the order they were opened.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Tabs {
+    tabs: Signal<Vec<Tab>>,
+    active: Signal<Option<u64>>,
+    next_id: Signal<u64>,
+}
+
+impl Default for Tabs {
+    fn default() -> Self {
+        Self {
+            tabs: Signal::new(Vec::new()),
+            active: Signal::new(None),
+            next_id: Signal::new(0),
+        }
+    }
+}
+
+impl Tabs {
+    /// Opens a tab showing `content`, or the one already showing it, and
+    /// returns its id. The caller makes it the active one.
+    pub fn open(&self, content: TabContent) -> u64 {
+        let (mut tabs, mut next_id) = (self.tabs, self.next_id);
+        if let Some(tab) = tabs.read().iter().find(|tab| tab.content.same(&content)) {
+            return tab.id;
+        }
+        let id = next_id();
+        next_id.set(id + 1);
+        tabs.write().push(Tab { id, content });
+        id
+    }
+
+    pub fn close(&self, id: u64) {
+        let (mut tabs, mut active) = (self.tabs, self.active);
+        tabs.write().retain(|tab| tab.id != id);
+        if active() == Some(id) {
+            active.set(None);
+        }
+    }
+
+    pub fn set_active(&self, id: Option<u64>) {
+        let mut active = self.active;
+        active.set(id);
+    }
+
+    pub fn active(&self) -> Option<Tab> {
+        let active = (self.active)()?;
+        self.get(active)
+    }
+
+    pub fn get(&self, id: u64) -> Option<Tab> {
+        self.tabs.read().iter().find(|tab| tab.id == id).cloned()
+    }
+
+    /// Keeps the edits of the draft in the active tab, if it shows one.
+    pub fn edit_draft(&self, draft: Composition) {
+        let Some(active) = (self.active)() else {
+            return;
+        };
+        let mut tabs = self.tabs;
+        if let Some(tab) = tabs.write().iter_mut().find(|tab| tab.id == active) {
+            if let TabContent::Draft { .. } = tab.content {
+                tab.content = TabContent::Draft { composition: draft, unsaved: true };
+            }
+        }
+    }
+
+    /// The tab `offset` tabs away from the active one, wrapping around.
+    pub fn neighbour(&self, offset: isize) -> Option<u64> {
+        let tabs = self.tabs.read();
+        let position = (self.active)().and_then(|id| tabs.iter().position(|tab| tab.id == id));
+        let next = match position {
+            Some(position) => (position as isize + offset).rem_euclid(tabs.len() as isize) as usize,
+            None => 0,
+        };
+        tabs.get(next).map(|tab| tab.id)
+    }
+
+    pub fn list(&self) -> Vec<Tab> {
+        self.tabs.read().clone()
+    }
+}
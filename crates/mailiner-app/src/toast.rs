@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use dioxus::prelude::*;
+
+use crate::core_event::UiCommand;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToastKind {
+    Success,
+    Error,
+    Info,
+}
+
+/// A short message shown at the bottom of the app until it times out or
+/// the user dismisses it.
+#[derive(Debug, Clone)]
+pub struct Toast {
+    pub id: u64,
+    pub kind: ToastKind,
+    pub message: String,
+    pub action: Option<ToastAction>,
+}
+
+impl Toast {
+    /// How long the toast is shown. Errors and toasts with an action stay
+    /// longer, to give time to read or react to them.
+    pub fn duration(&self) -> Duration {
+        if self.kind == ToastKind::Error || self.action.is_some() {
+            Duration::from_secs(8)
+        } else {
+            Duration::from_secs(4)
+        }
+    }
+}
+
+/// A button on a toast, e.g. "Undo".
+#[derive(Debug, Clone)]
+pub struct ToastAction {
+    pub label: String,
+    pub command: UiCommand,
+}
+
+/// The toasts being shown, newest last.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Toasts {
+    toasts: Signal<Vec<Toast>>,
+    next_id: Signal<u64>,
+}
+
+impl Default for Toasts {
+    fn default() -> Self {
+        Self {
+            toasts: Signal::new(Vec::new()),
+            next_id: Signal::new(0),
+        }
+    }
+}
+
+impl Toasts {
+    pub fn success(&self, message: impl Into<String>) {
+        self.show(ToastKind::Success, message, None);
+    }
+
+    pub fn error(&self, message: impl Into<String>) {
+        self.show(ToastKind::Error, message, None);
+    }
+
+    pub fn info(&self, message: impl Into<String>) {
+        self.show(ToastKind::Info, message, None);
+    }
+
+    pub fn show(&self, kind: ToastKind, message: impl Into<String>, action: Option<ToastAction>) {
+        let (mut toasts, mut next_id) = (self.toasts, self.next_id);
+        let id = next_id();
+        next_id.set(id + 1);
+        toasts.write().push(Toast { id, kind, message: message.into(), action });
+    }
+
+    pub fn dismiss(&self, id: u64) {
+        let mut toasts = self.toasts;
+        toasts.write().retain(|toast| toast.id != id);
+    }
+
+    pub fn list(&self) -> Vec<Toast> {
+        self.toasts.read().clone()
+    }
+}
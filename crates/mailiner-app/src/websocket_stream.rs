@@ -3,7 +3,10 @@ use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll, Waker};
 
+use async_trait::async_trait;
 use dioxus::logger::tracing::{info, error};
+use mailiner_core::platform::Network;
+use mailiner_core::Result as MailinerResult;
 use send_wrapper::SendWrapper;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use web_sys::wasm_bindgen::prelude::*;
@@ -199,3 +202,30 @@ impl AsyncWrite for WebSocketStream {
         }
     }
 }
+
+
+/// Connects to remote servers through a WebSocket-to-TCP proxy, since browsers
+/// cannot open raw sockets.
+pub struct WebSocketNetwork {
+    proxy_url: String,
+    token: String,
+}
+
+impl WebSocketNetwork {
+    pub fn new(proxy_url: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            proxy_url: proxy_url.into(),
+            token: token.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl Network<WebSocketStream> for WebSocketNetwork {
+    async fn connect(&self, host: &str, port: u16) -> MailinerResult<WebSocketStream> {
+        Ok(WebSocketStream::new(&format!(
+            "{}?token={}&remote={}:{}",
+            self.proxy_url, self.token, host, port
+        )))
+    }
+}
@@ -0,0 +1,293 @@
+//! The accounts set up by the user and their server settings.
+//!
+//! [`AccountManager`] loads the saved [`AccountConfig`]s at startup and is
+//! what the settings UI reads and edits them through, so that changes are
+//! validated and persisted in one place. It also keeps the health of each
+//! account's connection, for the UI to show.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{watch, RwLock};
+
+use crate::error::{MailinerError, Result};
+use crate::ids::AccountId;
+use crate::models::{Account, AccountConfig, ConnectionSecurity, ServerSettings};
+use crate::storage::Storage;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnectionStatus {
+    Disconnected,
+    Connecting,
+    Authenticating,
+    Connected,
+    /// The server asked to slow down.
+    RateLimited,
+    /// The server didn't accept the credentials; the user has to fix them.
+    AuthFailed(String),
+    /// The TLS handshake failed or the certificate isn't trusted.
+    TlsError(String),
+    Error(String),
+}
+
+impl ConnectionStatus {
+    /// The status an operation failing with `error` leaves the connection in.
+    pub fn from_error(error: &MailinerError) -> Self {
+        match error {
+            MailinerError::Authentication(msg) => ConnectionStatus::AuthFailed(msg.clone()),
+            MailinerError::RateLimited(_) => ConnectionStatus::RateLimited,
+            MailinerError::Tls(msg) => ConnectionStatus::TlsError(msg.clone()),
+            MailinerError::UntrustedCertificate(certificate) => {
+                ConnectionStatus::TlsError(certificate.reason.clone())
+            }
+            error => ConnectionStatus::Error(error.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountHealth {
+    pub status: ConnectionStatus,
+    /// When the status last changed.
+    pub since: DateTime<Utc>,
+    pub last_sync: Option<DateTime<Utc>>,
+}
+
+impl AccountHealth {
+    fn new() -> Self {
+        Self {
+            status: ConnectionStatus::Disconnected,
+            since: Utc::now(),
+            last_sync: None,
+        }
+    }
+
+    fn set_status(&mut self, status: ConnectionStatus) {
+        if self.status != status {
+            self.status = status;
+            self.since = Utc::now();
+        }
+    }
+}
+
+pub struct AccountManager {
+    storage: Arc<dyn Storage>,
+    configs: RwLock<HashMap<AccountId, AccountConfig>>,
+    health: watch::Sender<HashMap<AccountId, AccountHealth>>,
+}
+
+impl AccountManager {
+    /// Loads the accounts saved in `storage`.
+    pub async fn load(storage: Arc<dyn Storage>) -> Result<Self> {
+        let configs: HashMap<_, _> = storage
+            .list_account_configs()
+            .await?
+            .into_iter()
+            .map(|config| (config.account_id.clone(), config))
+            .collect();
+        let health = configs
+            .keys()
+            .map(|account_id| (account_id.clone(), AccountHealth::new()))
+            .collect();
+        Ok(Self {
+            storage,
+            configs: RwLock::new(configs),
+            health: watch::channel(health).0,
+        })
+    }
+
+    /// The accounts, by name.
+    pub async fn configs(&self) -> Vec<AccountConfig> {
+        let mut configs: Vec<_> = self.configs.read().await.values().cloned().collect();
+        configs.sort_by(|a, b| a.name.cmp(&b.name));
+        configs
+    }
+
+    pub async fn get(&self, account_id: &AccountId) -> Result<AccountConfig> {
+        self.configs
+            .read()
+            .await
+            .get(account_id)
+            .cloned()
+            .ok_or_else(|| MailinerError::NotFound(format!("Account config {}", account_id)))
+    }
+
+    /// Adds an account or changes its settings. The [`Account`] is kept in
+    /// step with the name and address.
+    pub async fn save(&self, config: AccountConfig) -> Result<()> {
+        validate(&config)?;
+        let account = match self.storage.get_account(&config.account_id).await {
+            Ok(account) => Account {
+                name: config.name.clone(),
+                email: config.email.clone(),
+                updated_at: Utc::now(),
+                ..account
+            },
+            Err(MailinerError::NotFound(_)) => Account {
+                id: config.account_id.clone(),
+                name: config.name.clone(),
+                email: config.email.clone(),
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            },
+            Err(e) => return Err(e),
+        };
+        self.storage.save_account(&account).await?;
+        self.storage.save_account_config(&config).await?;
+        self.configs
+            .write()
+            .await
+            .insert(config.account_id.clone(), config);
+        Ok(())
+    }
+
+    /// Removes the settings of an account. Its cached mail stays until the
+    /// account itself is deleted.
+    pub async fn remove(&self, account_id: &AccountId) -> Result<()> {
+        self.storage.delete_account_config(account_id).await?;
+        self.configs.write().await.remove(account_id);
+        self.health.send_modify(|health| {
+            health.remove(account_id);
+        });
+        Ok(())
+    }
+
+    /// The health of every account, updated as it changes.
+    pub fn health(&self) -> watch::Receiver<HashMap<AccountId, AccountHealth>> {
+        self.health.subscribe()
+    }
+
+    pub fn set_status(&self, account_id: &AccountId, status: ConnectionStatus) {
+        self.health.send_if_modified(|health| {
+            let account = health
+                .entry(account_id.clone())
+                .or_insert_with(AccountHealth::new);
+            let before = account.clone();
+            account.set_status(status);
+            *account != before
+        });
+    }
+
+    /// Takes in the outcome of a sync of the account: a successful one means
+    /// it's connected, a failed one why not.
+    pub fn report_sync<T>(&self, account_id: &AccountId, result: &Result<T>) {
+        match result {
+            Ok(_) => self.health.send_modify(|health| {
+                let account = health
+                    .entry(account_id.clone())
+                    .or_insert_with(AccountHealth::new);
+                account.set_status(ConnectionStatus::Connected);
+                account.last_sync = Some(Utc::now());
+            }),
+            Err(error) => self.set_status(account_id, ConnectionStatus::from_error(error)),
+        }
+    }
+}
+
+/// Checks that `config` has what's needed to connect.
+pub fn validate(config: &AccountConfig) -> Result<()> {
+    if config.name.trim().is_empty() {
+        return Err(MailinerError::InvalidData(
+            "The account has no name".to_string(),
+        ));
+    }
+    if !config.email.contains('@') {
+        return Err(MailinerError::InvalidData(format!(
+            "Invalid email address: {}",
+            config.email
+        )));
+    }
+    validate_server(&config.incoming)?;
+    if let Some(outgoing) = &config.outgoing {
+        validate_server(outgoing)?;
+    }
+    Ok(())
+}
+
+fn validate_server(server: &ServerSettings) -> Result<()> {
+    if server.host.trim().is_empty() || server.host.contains(char::is_whitespace) {
+        return Err(MailinerError::InvalidData(format!(
+            "Invalid server host: {:?}",
+            server.host
+        )));
+    }
+    if server.port == 0 {
+        return Err(MailinerError::InvalidData(format!(
+            "No port set for {}",
+            server.host
+        )));
+    }
+    if server.security == ConnectionSecurity::None {
+        return Err(MailinerError::InvalidData(format!(
+            "Unencrypted connections to {} are not supported, use TLS or STARTTLS",
+            server.host
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::CertificateInfo;
+
+    fn server(host: &str, port: u16) -> ServerSettings {
+        ServerSettings {
+            host: host.to_string(),
+            port,
+            security: ConnectionSecurity::Tls,
+            username: "jan@example.com".to_string(),
+            auth_method: None,
+        }
+    }
+
+    #[test]
+    fn validates_servers() {
+        let mut config = AccountConfig {
+            account_id: AccountId::new("imap-jan"),
+            name: "Work".to_string(),
+            email: "jan@example.com".to_string(),
+            incoming: server("imap.example.com", 993),
+            outgoing: Some(server("smtp.example.com", 465)),
+        };
+        assert!(validate(&config).is_ok());
+
+        config.outgoing = Some(server("smtp.example.com", 0));
+        assert!(validate(&config).is_err());
+        config.outgoing = None;
+        assert!(validate(&config).is_ok());
+        config.incoming = server(" ", 993);
+        assert!(validate(&config).is_err());
+        config.incoming = ServerSettings {
+            security: ConnectionSecurity::None,
+            ..server("imap.example.com", 143)
+        };
+        assert!(validate(&config).is_err());
+    }
+
+    #[test]
+    fn status_from_errors() {
+        let untrusted = MailinerError::UntrustedCertificate(CertificateInfo {
+            fingerprint: "AB:CD".to_string(),
+            chain: Vec::new(),
+            reason: "self-signed".to_string(),
+        });
+        assert_eq!(
+            ConnectionStatus::from_error(&untrusted),
+            ConnectionStatus::TlsError("self-signed".to_string())
+        );
+        assert_eq!(
+            ConnectionStatus::from_error(&MailinerError::RateLimited("429".to_string())),
+            ConnectionStatus::RateLimited
+        );
+
+        let mut health = AccountHealth::new();
+        let since = health.since;
+        health.set_status(ConnectionStatus::Disconnected);
+        assert_eq!(health.since, since);
+        health.set_status(ConnectionStatus::Connected);
+        assert_eq!(health.status, ConnectionStatus::Connected);
+    }
+}
@@ -0,0 +1,17 @@
+//! A log of what the app did on its own, e.g. through a
+//! [`RetentionPolicy`](crate::RetentionPolicy), for the user to look up.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::ids::{AccountId, FolderId, MessageId};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActivityEntry {
+    pub account_id: AccountId,
+    pub at: DateTime<Utc>,
+    pub folder_id: FolderId,
+    pub message_id: MessageId,
+    /// What was done, e.g. "Moved to Archive".
+    pub action: String,
+}
@@ -0,0 +1,103 @@
+//! Pictures of senders: initials on a color derived from the address, or,
+//! if the user allows looking them up, the sender's Gravatar or their
+//! domain's BIMI logo.
+
+use ring::digest::{digest, SHA256};
+
+/// Background colors for initials, dark enough for white text.
+const COLORS: &[&str] = &[
+    "#c62828", "#ad1457", "#6a1b9a", "#4527a0", "#283593", "#1565c0", "#00838f", "#00695c",
+    "#2e7d32", "#558b2f", "#ef6c00", "#d84315", "#4e342e", "#37474f",
+];
+
+/// Up to two letters for the sender: the first letters of the first and
+/// last word of the name, or else the first letter of the address.
+pub fn initials(name: Option<&str>, email: &str) -> String {
+    let words: Vec<&str> = name
+        .unwrap_or_default()
+        .split(|c: char| c.is_whitespace() || c == ',' || c == '"')
+        .filter(|word| word.starts_with(char::is_alphanumeric))
+        .collect();
+    let first_letter = |word: &str| word.chars().next().into_iter().flat_map(char::to_uppercase);
+    match words.as_slice() {
+        [] => email.chars().take(1).flat_map(char::to_uppercase).collect(),
+        [word] => first_letter(word).collect(),
+        [first, .., last] => first_letter(first).chain(first_letter(last)).collect(),
+    }
+}
+
+/// The background color for the initials of `email`, the same every time.
+pub fn color(email: &str) -> &'static str {
+    // FNV-1a, which unlike the std hasher doesn't change between releases.
+    let hash = email
+        .trim()
+        .to_lowercase()
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    COLORS[(hash % COLORS.len() as u64) as usize]
+}
+
+/// The Gravatar of `email`, `size` pixels wide. Gravatar answers 404 if
+/// there is none.
+pub fn gravatar_url(email: &str, size: u32) -> String {
+    let hash = digest(&SHA256, email.trim().to_lowercase().as_bytes());
+    let hex: String = hash.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("https://www.gravatar.com/avatar/{}?s={}&d=404", hex, size)
+}
+
+/// The DNS name of the BIMI record of the domain of `email`.
+pub fn bimi_record_name(email: &str) -> Option<String> {
+    let (_, domain) = email.trim().rsplit_once('@')?;
+    (!domain.is_empty()).then(|| format!("default._bimi.{}", domain.to_lowercase()))
+}
+
+/// The logo in a BIMI record, e.g. `v=BIMI1; l=https://example.com/logo.svg`.
+/// Only HTTPS logos are used, as the specification requires.
+pub fn parse_bimi_record(record: &str) -> Option<String> {
+    let mut tags = record.split(';').filter_map(|tag| {
+        let (name, value) = tag.split_once('=')?;
+        Some((name.trim(), value.trim()))
+    });
+    if tags.next()? != ("v", "BIMI1") {
+        return None;
+    }
+    tags.find(|(name, _)| *name == "l")
+        .map(|(_, logo)| logo)
+        .filter(|logo| logo.starts_with("https://"))
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initials_and_colors() {
+        assert_eq!(initials(Some("Jan van der Berg"), "jan@example.com"), "JB");
+        assert_eq!(initials(Some("\"Doe, Jane\""), "jane@example.com"), "DJ");
+        assert_eq!(initials(Some("ärzte"), "info@example.com"), "Ä");
+        assert_eq!(initials(None, "info@example.com"), "I");
+        assert_eq!(initials(Some("  "), ""), "");
+
+        assert_eq!(color("Jan@Example.com "), color("jan@example.com"));
+        assert!(COLORS.contains(&color("")));
+    }
+
+    #[test]
+    fn remote_avatars() {
+        assert_eq!(
+            gravatar_url(" MyEmailAddress@example.com ", 64),
+            "https://www.gravatar.com/avatar/\
+             84059b07d4be67b806386c0aad8070a23f18836bbaae342275dc0a83414c32ee?s=64&d=404"
+        );
+
+        assert_eq!(bimi_record_name("news@Shop.Example.com").as_deref(), Some("default._bimi.shop.example.com"));
+        assert_eq!(bimi_record_name("nobody"), None);
+        assert_eq!(
+            parse_bimi_record("v=BIMI1; l=https://example.com/logo.svg; a=https://example.com/vmc.pem").as_deref(),
+            Some("https://example.com/logo.svg")
+        );
+        assert_eq!(parse_bimi_record("v=BIMI1; l=http://example.com/logo.svg"), None);
+        assert_eq!(parse_bimi_record("v=spf1 -all"), None);
+    }
+}
@@ -0,0 +1,208 @@
+//! Encrypted export and import of the user's configuration.
+//!
+//! A backup contains the configuration needed to set Mailiner up on another
+//! machine. Passwords and other secrets from the platform's `SecureStorage`
+//! are never included, the user has to enter them again after restoring.
+//!
+//! The archive is the JSON-serialized [`SettingsBackup`] encrypted with
+//! AES-256-GCM, using a key derived from the passphrase with PBKDF2:
+//!
+//! ```text
+//! MAGIC (8 bytes) | salt (16 bytes) | nonce (12 bytes) | ciphertext + tag
+//! ```
+
+use std::num::NonZeroU32;
+
+use chrono::{DateTime, Utc};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{MailinerError, Result};
+use crate::models::{Account, AccountConfig, AccountPreferences};
+use crate::rules::Rule;
+use crate::storage::Storage;
+
+const MAGIC: &[u8; 8] = b"MLNRBAK1";
+const SALT_LEN: usize = 16;
+const PBKDF2_ITERATIONS: u32 = 600_000;
+
+/// Version of the [`SettingsBackup`] format, bumped when sections are added
+/// or changed.
+pub const BACKUP_VERSION: u32 = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingsBackup {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub accounts: Vec<Account>,
+    #[serde(default)]
+    pub account_preferences: Vec<AccountPreferences>,
+    #[serde(default)]
+    pub account_configs: Vec<AccountConfig>,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+impl SettingsBackup {
+    /// Collects the configuration from `storage`.
+    pub async fn collect(storage: &dyn Storage) -> Result<Self> {
+        let accounts = storage.list_accounts().await?;
+        let mut account_preferences = Vec::new();
+        let mut account_configs = Vec::new();
+        let mut rules = Vec::new();
+        for account in &accounts {
+            rules.extend(storage.list_rules(&account.id).await?);
+            match storage.get_account_preferences(&account.id).await {
+                Ok(preferences) => account_preferences.push(preferences),
+                Err(MailinerError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+            match storage.get_account_config(&account.id).await {
+                Ok(config) => account_configs.push(config),
+                Err(MailinerError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Self {
+            version: BACKUP_VERSION,
+            created_at: Utc::now(),
+            accounts,
+            account_preferences,
+            account_configs,
+            rules,
+        })
+    }
+
+    /// Writes the configuration back into `storage`, overwriting existing
+    /// entries with the same ids.
+    pub async fn restore(&self, storage: &dyn Storage) -> Result<()> {
+        for account in &self.accounts {
+            storage.save_account(account).await?;
+        }
+        for preferences in &self.account_preferences {
+            storage.save_account_preferences(preferences).await?;
+        }
+        for config in &self.account_configs {
+            storage.save_account_config(config).await?;
+        }
+        for rule in &self.rules {
+            storage.save_rule(rule).await?;
+        }
+        Ok(())
+    }
+
+    pub fn encrypt(&self, passphrase: &str) -> Result<Vec<u8>> {
+        let rng = SystemRandom::new();
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rng.fill(&mut salt)
+            .and_then(|_| rng.fill(&mut nonce))
+            .map_err(|_| MailinerError::Storage("Failed to generate random data".to_string()))?;
+
+        let mut data = serde_json::to_vec(self)?;
+        derive_key(passphrase, &salt)
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(MAGIC),
+                &mut data,
+            )
+            .map_err(|_| MailinerError::Storage("Failed to encrypt backup".to_string()))?;
+
+        let mut archive = Vec::with_capacity(MAGIC.len() + SALT_LEN + NONCE_LEN + data.len());
+        archive.extend_from_slice(MAGIC);
+        archive.extend_from_slice(&salt);
+        archive.extend_from_slice(&nonce);
+        archive.extend_from_slice(&data);
+        Ok(archive)
+    }
+
+    pub fn decrypt(archive: &[u8], passphrase: &str) -> Result<Self> {
+        let rest = archive
+            .strip_prefix(MAGIC.as_slice())
+            .filter(|rest| rest.len() >= SALT_LEN + NONCE_LEN)
+            .ok_or_else(|| MailinerError::InvalidData("Not a Mailiner backup".to_string()))?;
+        let (salt, rest) = rest.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| MailinerError::InvalidData("Invalid backup nonce".to_string()))?;
+
+        let mut data = ciphertext.to_vec();
+        let plaintext = derive_key(passphrase, salt)
+            .open_in_place(nonce, Aad::from(MAGIC), &mut data)
+            .map_err(|_| {
+                MailinerError::InvalidData("Wrong passphrase or corrupted backup".to_string())
+            })?;
+
+        let backup: Self = serde_json::from_slice(plaintext)?;
+        if backup.version > BACKUP_VERSION {
+            return Err(MailinerError::InvalidData(format!(
+                "Backup version {} is newer than supported version {}",
+                backup.version, BACKUP_VERSION
+            )));
+        }
+        Ok(backup)
+    }
+}
+
+/// Exports the configuration from `storage` as an encrypted archive.
+pub async fn export_settings(storage: &dyn Storage, passphrase: &str) -> Result<Vec<u8>> {
+    SettingsBackup::collect(storage).await?.encrypt(passphrase)
+}
+
+/// Decrypts `archive` and restores the configuration into `storage`.
+pub async fn import_settings(
+    storage: &dyn Storage,
+    archive: &[u8],
+    passphrase: &str,
+) -> Result<SettingsBackup> {
+    let backup = SettingsBackup::decrypt(archive, passphrase)?;
+    backup.restore(storage).await?;
+    Ok(backup)
+}
+
+pub(crate) fn derive_key(passphrase: &str, salt: &[u8]) -> LessSafeKey {
+    let mut key = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    // The key length always matches AES-256.
+    LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &key).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::AccountId;
+
+    #[test]
+    fn roundtrip() {
+        let backup = SettingsBackup {
+            version: BACKUP_VERSION,
+            created_at: Utc::now(),
+            accounts: vec![Account {
+                id: AccountId::new("imap-jan"),
+                name: "Work".to_string(),
+                email: "jan@example.com".to_string(),
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            }],
+            account_preferences: Vec::new(),
+            account_configs: Vec::new(),
+            rules: Vec::new(),
+        };
+
+        let archive = backup.encrypt("correct horse").unwrap();
+        assert!(SettingsBackup::decrypt(&archive, "wrong horse").is_err());
+
+        let restored = SettingsBackup::decrypt(&archive, "correct horse").unwrap();
+        assert_eq!(restored.accounts.len(), 1);
+        assert_eq!(restored.accounts[0].email, "jan@example.com");
+    }
+}
@@ -0,0 +1,635 @@
+//! iCalendar (RFC 5545) parsing and iTIP (RFC 5546) replies to invitations
+//! received by email (iMIP, RFC 6047).
+
+use std::fmt;
+
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::compose::mime::{encode_address, encode_header};
+use crate::error::{MailinerError, Result};
+use crate::models::EmailAddr;
+
+const PRODID: &str = "-//Mailiner//Mailiner//EN";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParticipationStatus {
+    NeedsAction,
+    Accepted,
+    Tentative,
+    Declined,
+}
+
+impl ParticipationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NeedsAction => "NEEDS-ACTION",
+            Self::Accepted => "ACCEPTED",
+            Self::Tentative => "TENTATIVE",
+            Self::Declined => "DECLINED",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value.to_ascii_uppercase().as_str() {
+            "ACCEPTED" => Self::Accepted,
+            "TENTATIVE" => Self::Tentative,
+            "DECLINED" => Self::Declined,
+            _ => Self::NeedsAction,
+        }
+    }
+}
+
+/// A single content line, e.g. `ATTENDEE;CN=Jan;PARTSTAT=ACCEPTED:mailto:jan@example.com`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Property {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    /// The raw (still escaped) value.
+    pub value: String,
+}
+
+impl Property {
+    pub fn new(name: &str, value: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            params: Vec::new(),
+            value: value.into(),
+        }
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        // The value starts at the first colon that is not inside a quoted parameter value.
+        let mut in_quotes = false;
+        let colon = line.char_indices().find_map(|(pos, c)| match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                None
+            }
+            ':' if !in_quotes => Some(pos),
+            _ => None,
+        })?;
+        let (head, value) = (&line[..colon], &line[colon + 1..]);
+
+        let mut parts = split_unquoted(head, ';').into_iter();
+        let name = parts.next()?.to_ascii_uppercase();
+        let params = parts
+            .filter_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                Some((
+                    key.to_ascii_uppercase(),
+                    value.trim_matches('"').to_string(),
+                ))
+            })
+            .collect();
+
+        Some(Self {
+            name,
+            params,
+            value: value.to_string(),
+        })
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_param(&mut self, name: &str, value: &str) {
+        match self
+            .params
+            .iter_mut()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        {
+            Some((_, existing)) => *existing = value.to_string(),
+            None => self.params.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// The value with TEXT escapes resolved.
+    pub fn text(&self) -> String {
+        let mut text = String::with_capacity(self.value.len());
+        let mut chars = self.value.chars();
+        while let Some(c) = chars.next() {
+            match (c, chars.clone().next()) {
+                ('\\', Some('n' | 'N')) => {
+                    text.push('\n');
+                    chars.next();
+                }
+                ('\\', Some(escaped)) => {
+                    text.push(escaped);
+                    chars.next();
+                }
+                (c, _) => text.push(c),
+            }
+        }
+        text
+    }
+
+    /// The email address of a `CAL-ADDRESS` value (`mailto:` URI).
+    fn address(&self) -> &str {
+        let value = self.value.as_str();
+        match value.get(..7) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("mailto:") => &value[7..],
+            _ => value,
+        }
+    }
+}
+
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut line = self.name.clone();
+        for (key, value) in &self.params {
+            if value.contains([':', ';', ',']) {
+                line.push_str(&format!(";{}=\"{}\"", key, value));
+            } else {
+                line.push_str(&format!(";{}={}", key, value));
+            }
+        }
+        line.push(':');
+        line.push_str(&self.value);
+        write_folded(f, &line)
+    }
+}
+
+fn split_unquoted(input: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (pos, c) in input.char_indices() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        } else if c == separator && !in_quotes {
+            parts.push(&input[start..pos]);
+            start = pos + 1;
+        }
+    }
+    parts.push(&input[start..]);
+    parts
+}
+
+/// Writes a content line folded to at most 75 octets per line.
+fn write_folded(f: &mut fmt::Formatter<'_>, line: &str) -> fmt::Result {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            f.write_str("\r\n ")?;
+            width = 1;
+        }
+        width += c.len_utf8();
+        write!(f, "{}", c)?;
+    }
+    f.write_str("\r\n")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attendee {
+    pub email: String,
+    pub name: Option<String>,
+    pub status: ParticipationStatus,
+    /// Whether the organizer asked for a reply.
+    pub rsvp: bool,
+}
+
+impl Attendee {
+    fn from_property(property: &Property) -> Self {
+        Self {
+            email: property.address().to_string(),
+            name: property.param("CN").map(str::to_string),
+            status: property
+                .param("PARTSTAT")
+                .map(ParticipationStatus::parse)
+                .unwrap_or(ParticipationStatus::NeedsAction),
+            rsvp: property
+                .param("RSVP")
+                .is_some_and(|rsvp| rsvp.eq_ignore_ascii_case("TRUE")),
+        }
+    }
+}
+
+/// A `VEVENT` component.
+#[derive(Debug, Clone)]
+pub struct Event {
+    properties: Vec<Property>,
+}
+
+impl Event {
+    fn property(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|p| p.name == name)
+    }
+
+    pub fn uid(&self) -> Option<&str> {
+        self.property("UID").map(|p| p.value.as_str())
+    }
+
+    pub fn summary(&self) -> Option<String> {
+        self.property("SUMMARY").map(Property::text)
+    }
+
+    pub fn location(&self) -> Option<String> {
+        self.property("LOCATION").map(Property::text)
+    }
+
+    pub fn sequence(&self) -> u32 {
+        self.property("SEQUENCE")
+            .and_then(|p| p.value.parse().ok())
+            .unwrap_or(0)
+    }
+
+    pub fn start(&self) -> Option<&Property> {
+        self.property("DTSTART")
+    }
+
+    pub fn end(&self) -> Option<&Property> {
+        self.property("DTEND")
+    }
+
+    /// When the event takes place, e.g. `Tue 20 Oct 2026 10:00–11:00
+    /// (Europe/Prague)`, in the time zone of the invitation.
+    pub fn when(&self) -> Option<String> {
+        let (start, zone) = parse_time(self.start()?)?;
+        let end = self.end().and_then(parse_time).map(|(end, _)| end);
+        let mut when = match (start, end) {
+            (Time::Date(start), Some(Time::Date(end))) if end > start.succ_opt()? => {
+                // The end of all-day events is the day after.
+                format!("{} – {}", start.format("%a %-d %b %Y"), end.pred_opt()?.format("%a %-d %b %Y"))
+            }
+            (Time::Date(start), _) => start.format("%a %-d %b %Y").to_string(),
+            (Time::DateTime(start), Some(Time::DateTime(end))) if end.date() == start.date() => {
+                format!("{}–{}", start.format("%a %-d %b %Y %H:%M"), end.format("%H:%M"))
+            }
+            (Time::DateTime(start), Some(Time::DateTime(end))) => {
+                format!("{} – {}", start.format("%a %-d %b %Y %H:%M"), end.format("%a %-d %b %Y %H:%M"))
+            }
+            (Time::DateTime(start), _) => start.format("%a %-d %b %Y %H:%M").to_string(),
+        };
+        if let Some(zone) = zone {
+            when.push_str(&format!(" ({})", zone));
+        }
+        Some(when)
+    }
+
+    pub fn organizer(&self) -> Option<Attendee> {
+        self.property("ORGANIZER").map(Attendee::from_property)
+    }
+
+    pub fn attendees(&self) -> Vec<Attendee> {
+        self.properties
+            .iter()
+            .filter(|p| p.name == "ATTENDEE")
+            .map(Attendee::from_property)
+            .collect()
+    }
+
+    pub fn attendee(&self, email: &str) -> Option<Attendee> {
+        self.attendees()
+            .into_iter()
+            .find(|a| a.email.eq_ignore_ascii_case(email))
+    }
+
+    /// Builds the iTIP `REPLY` with which `attendee` answers this event.
+    pub fn reply(&self, attendee: &EmailAddr, status: ParticipationStatus) -> Result<Calendar> {
+        let email = attendee.email.as_deref().unwrap_or_default();
+        let mut attendee = self
+            .properties
+            .iter()
+            .find(|p| p.name == "ATTENDEE" && p.address().eq_ignore_ascii_case(email))
+            .cloned()
+            .ok_or_else(|| {
+                MailinerError::NotFound(format!("Attendee {} in event {:?}", email, self.uid()))
+            })?;
+        attendee.params.retain(|(key, _)| key == "CN");
+        attendee.set_param("PARTSTAT", status.as_str());
+
+        let mut properties = vec![
+            Property::new("BEGIN", "VCALENDAR"),
+            Property::new("PRODID", PRODID),
+            Property::new("VERSION", "2.0"),
+            Property::new("METHOD", "REPLY"),
+            Property::new("BEGIN", "VEVENT"),
+        ];
+        // The organizer matches the reply to the event by UID, RECURRENCE-ID and SEQUENCE.
+        for name in [
+            "UID",
+            "RECURRENCE-ID",
+            "SEQUENCE",
+            "ORGANIZER",
+            "SUMMARY",
+            "DTSTART",
+            "DTEND",
+        ] {
+            properties.extend(self.property(name).cloned());
+        }
+        properties.push(Property::new(
+            "DTSTAMP",
+            Utc::now().format("%Y%m%dT%H%M%SZ").to_string(),
+        ));
+        properties.push(attendee);
+        properties.push(Property::new("END", "VEVENT"));
+        properties.push(Property::new("END", "VCALENDAR"));
+
+        Ok(Calendar { properties })
+    }
+
+    /// Builds the email carrying the iTIP reply to the organizer (iMIP).
+    pub fn reply_message(
+        &self,
+        attendee: &EmailAddr,
+        status: ParticipationStatus,
+    ) -> Result<Vec<u8>> {
+        let organizer = self
+            .organizer()
+            .ok_or_else(|| MailinerError::InvalidData("Invitation has no organizer".to_string()))?;
+        let reply = self.reply(attendee, status)?;
+
+        let summary = self.summary().unwrap_or_default();
+        let (verb, subject_prefix) = match status {
+            ParticipationStatus::Accepted => ("accepted", "Accepted"),
+            ParticipationStatus::Tentative => ("tentatively accepted", "Tentative"),
+            ParticipationStatus::Declined => ("declined", "Declined"),
+            ParticipationStatus::NeedsAction => ("not yet answered", "Invitation"),
+        };
+        let who = attendee
+            .name
+            .clone()
+            .or_else(|| attendee.email.clone())
+            .unwrap_or_default();
+        let boundary = format!("mailiner-{}", uuid::Uuid::new_v4().simple());
+
+        let mut message = String::new();
+        message.push_str(&format!("From: {}\r\n", encode_address(attendee)));
+        message.push_str(&format!(
+            "To: {}\r\n",
+            encode_address(&EmailAddr {
+                name: organizer.name,
+                email: Some(organizer.email),
+            })
+        ));
+        message.push_str(&format!(
+            "Subject: {}\r\n",
+            encode_header(&format!("{}: {}", subject_prefix, summary))
+        ));
+        message.push_str(&format!("Date: {}\r\n", Utc::now().to_rfc2822()));
+        message.push_str(&format!(
+            "Message-ID: <{}@mailiner>\r\n",
+            uuid::Uuid::new_v4()
+        ));
+        message.push_str("MIME-Version: 1.0\r\n");
+        message.push_str(&format!(
+            "Content-Type: multipart/alternative; boundary=\"{}\"\r\n\r\n",
+            boundary
+        ));
+        message.push_str(&format!("--{}\r\n", boundary));
+        message.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+        message.push_str("Content-Transfer-Encoding: 8bit\r\n\r\n");
+        message.push_str(&format!(
+            "{} has {} the invitation: {}\r\n",
+            who, verb, summary
+        ));
+        message.push_str(&format!("--{}\r\n", boundary));
+        message.push_str("Content-Type: text/calendar; charset=utf-8; method=REPLY\r\n");
+        message.push_str("Content-Transfer-Encoding: 8bit\r\n\r\n");
+        message.push_str(&reply.to_string());
+        message.push_str(&format!("--{}--\r\n", boundary));
+
+        Ok(message.into_bytes())
+    }
+}
+
+enum Time {
+    Date(NaiveDate),
+    DateTime(NaiveDateTime),
+}
+
+/// The time of a `DTSTART` or `DTEND`, and its time zone: the `TZID`, UTC,
+/// or none for floating times and dates.
+fn parse_time(property: &Property) -> Option<(Time, Option<String>)> {
+    let value = property.value.trim();
+    if let Some(utc) = value.strip_suffix(['Z', 'z']) {
+        let time = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+        return Some((Time::DateTime(time), Some("UTC".to_string())));
+    }
+    let zone = property.param("TZID").map(str::to_string);
+    match NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+        Ok(time) => Some((Time::DateTime(time), zone)),
+        Err(_) => Some((Time::Date(NaiveDate::parse_from_str(value, "%Y%m%d").ok()?), None)),
+    }
+}
+
+/// What the message view shows of an invitation, or of another iTIP
+/// message like a cancellation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Invitation {
+    /// The iTIP method, e.g. `REQUEST` for invitations or `CANCEL`.
+    pub method: Option<String>,
+    pub summary: Option<String>,
+    pub location: Option<String>,
+    /// See [`Event::when`].
+    pub when: Option<String>,
+    pub organizer: Option<Attendee>,
+    pub attendees: Vec<Attendee>,
+}
+
+impl Invitation {
+    /// The first event of `calendar`, if it has one.
+    pub fn new(calendar: &Calendar) -> Option<Self> {
+        let event = calendar.events().into_iter().next()?;
+        Some(Self {
+            method: calendar.method().map(str::to_ascii_uppercase),
+            summary: event.summary(),
+            location: event.location(),
+            when: event.when(),
+            organizer: event.organizer(),
+            attendees: event.attendees(),
+        })
+    }
+
+    /// Whether the organizer asks the attendees to answer.
+    pub fn is_request(&self) -> bool {
+        self.method.as_deref() == Some("REQUEST")
+    }
+
+    /// The attendee with the address `email`, ignoring case.
+    pub fn attendee(&self, email: &str) -> Option<&Attendee> {
+        self.attendees.iter().find(|attendee| attendee.email.eq_ignore_ascii_case(email))
+    }
+}
+
+/// A parsed iCalendar object.
+#[derive(Debug, Clone)]
+pub struct Calendar {
+    properties: Vec<Property>,
+}
+
+impl Calendar {
+    pub fn parse(data: &str) -> Result<Self> {
+        // Unfold continuation lines (starting with a space or tab) first.
+        let mut lines: Vec<String> = Vec::new();
+        for line in data.split('\n') {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+                (Some(continuation), Some(last)) => last.push_str(continuation),
+                _ if line.is_empty() => {}
+                _ => lines.push(line.to_string()),
+            }
+        }
+
+        let properties: Vec<Property> = lines.iter().filter_map(|l| Property::parse(l)).collect();
+        match properties.first() {
+            Some(p) if p.name == "BEGIN" && p.value.eq_ignore_ascii_case("VCALENDAR") => {
+                Ok(Self { properties })
+            }
+            _ => Err(MailinerError::InvalidData(
+                "Not an iCalendar object".to_string(),
+            )),
+        }
+    }
+
+    /// The iTIP method, e.g. `REQUEST` for invitations.
+    pub fn method(&self) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|p| p.name == "METHOD")
+            .map(|p| p.value.as_str())
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut current: Option<Vec<Property>> = None;
+        // Nesting depth of sub-components (e.g. VALARM) inside the current event.
+        let mut depth = 0;
+        for property in &self.properties {
+            match (property.name.as_str(), current.as_mut()) {
+                ("BEGIN", None) if property.value.eq_ignore_ascii_case("VEVENT") => {
+                    current = Some(Vec::new());
+                }
+                ("BEGIN", Some(_)) => depth += 1,
+                ("END", Some(_)) if depth > 0 => depth -= 1,
+                ("END", Some(_)) => {
+                    events.extend(current.take().map(|properties| Event { properties }));
+                }
+                (_, Some(properties)) if depth == 0 => properties.push(property.clone()),
+                _ => {}
+            }
+        }
+        events
+    }
+
+    /// Records `status` as the participation status of `email` in all events,
+    /// so that the answer shows up when the invitation is displayed again.
+    /// Returns `false` when `email` is not an attendee.
+    pub fn set_participation_status(&mut self, email: &str, status: ParticipationStatus) -> bool {
+        let mut found = false;
+        for property in &mut self.properties {
+            if property.name == "ATTENDEE" && property.address().eq_ignore_ascii_case(email) {
+                property.set_param("PARTSTAT", status.as_str());
+                property.params.retain(|(key, _)| key != "RSVP");
+                found = true;
+            }
+        }
+        found
+    }
+}
+
+impl fmt::Display for Calendar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.properties.iter().try_for_each(|p| write!(f, "{}", p))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn when(start: &str, end: Option<&str>) -> Option<String> {
+        let mut properties = vec![Property::parse(start).unwrap()];
+        properties.extend(end.and_then(Property::parse));
+        Event { properties }.when()
+    }
+
+    #[test]
+    fn event_times() {
+        assert_eq!(
+            when("DTSTART;TZID=Europe/Prague:20261020T100000", Some("DTEND;TZID=Europe/Prague:20261020T113000")).as_deref(),
+            Some("Tue 20 Oct 2026 10:00–11:30 (Europe/Prague)")
+        );
+        assert_eq!(
+            when("DTSTART:20261020T220000Z", Some("DTEND:20261021T010000Z")).as_deref(),
+            Some("Tue 20 Oct 2026 22:00 – Wed 21 Oct 2026 01:00 (UTC)")
+        );
+        assert_eq!(
+            when("DTSTART;VALUE=DATE:20261020", Some("DTEND;VALUE=DATE:20261021")).as_deref(),
+            Some("Tue 20 Oct 2026")
+        );
+        assert_eq!(
+            when("DTSTART;VALUE=DATE:20261020", Some("DTEND;VALUE=DATE:20261023")).as_deref(),
+            Some("Tue 20 Oct 2026 – Thu 22 Oct 2026")
+        );
+        assert_eq!(when("DTSTART:tomorrow", None), None);
+    }
+
+    const INVITATION: &str = "BEGIN:VCALENDAR\r\n\
+PRODID:-//Example//EN\r\n\
+VERSION:2.0\r\n\
+METHOD:REQUEST\r\n\
+BEGIN:VEVENT\r\n\
+UID:1234@example.com\r\n\
+SEQUENCE:2\r\n\
+DTSTART;TZID=Europe/Prague:20261020T100000\r\n\
+SUMMARY:Planning\\, Q4\r\n\
+ORGANIZER;CN=\"Boss: Big\":mailto:boss@example.com\r\n\
+ATTENDEE;CN=Jan;PARTSTAT=NEEDS-ACTION;RSVP=TRUE:mailto:jan@exam\r\n ple.com\r\n\
+BEGIN:VALARM\r\n\
+ACTION:DISPLAY\r\n\
+END:VALARM\r\n\
+END:VEVENT\r\n\
+END:VCALENDAR\r\n";
+
+    #[test]
+    fn reply_to_invitation() {
+        let mut calendar = Calendar::parse(INVITATION).unwrap();
+        assert_eq!(calendar.method(), Some("REQUEST"));
+
+        let events = calendar.events();
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.summary().as_deref(), Some("Planning, Q4"));
+        assert_eq!(
+            event.organizer().unwrap().name.as_deref(),
+            Some("Boss: Big")
+        );
+        let attendee = event.attendee("JAN@example.com").unwrap();
+        assert_eq!(attendee.status, ParticipationStatus::NeedsAction);
+        assert!(attendee.rsvp);
+
+        let me = EmailAddr {
+            name: Some("Jan".to_string()),
+            email: Some("jan@example.com".to_string()),
+        };
+        let reply = Calendar::parse(
+            &event
+                .reply(&me, ParticipationStatus::Accepted)
+                .unwrap()
+                .to_string(),
+        )
+        .unwrap();
+        assert_eq!(reply.method(), Some("REPLY"));
+        let replied = &reply.events()[0];
+        assert_eq!(replied.uid(), Some("1234@example.com"));
+        assert_eq!(replied.sequence(), 2);
+        assert_eq!(replied.attendees().len(), 1);
+        assert_eq!(replied.attendees()[0].status, ParticipationStatus::Accepted);
+
+        let invitation = Invitation::new(&calendar).unwrap();
+        assert!(invitation.is_request());
+        assert_eq!(invitation.when.as_deref(), Some("Tue 20 Oct 2026 10:00 (Europe/Prague)"));
+        assert_eq!(invitation.attendee("jan@example.com").map(|jan| jan.rsvp), Some(true));
+
+        assert!(calendar.set_participation_status("jan@example.com", ParticipationStatus::Declined));
+        let attendee = calendar.events()[0].attendee("jan@example.com").unwrap();
+        assert_eq!(attendee.status, ParticipationStatus::Declined);
+        assert!(!attendee.rsvp);
+    }
+}
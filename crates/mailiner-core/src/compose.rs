@@ -0,0 +1,97 @@
+//! Message composition.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::models::{AccountPreferences, EmailAddr};
+use crate::platform::FileSystem;
+
+pub mod attachments;
+pub mod checks;
+pub mod markdown;
+pub mod mime;
+pub mod plaintext;
+pub mod reply;
+
+pub use checks::{PreSendCheck, PreSendChecks, PreSendWarning};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub data: AttachmentData,
+}
+
+impl Attachment {
+    pub fn size(&self) -> u64 {
+        match &self.data {
+            AttachmentData::Memory(data) => data.len() as u64,
+            AttachmentData::File { size, .. } => *size,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AttachmentData {
+    Memory(Vec<u8>),
+    /// Kept in the platform's [`FileSystem`] at `path`, for large files.
+    File { path: String, size: u64 },
+}
+
+/// A message being written by the user.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Composition {
+    pub from: Option<EmailAddr>,
+    pub to: Vec<EmailAddr>,
+    pub cc: Vec<EmailAddr>,
+    pub bcc: Vec<EmailAddr>,
+    pub subject: String,
+    pub body_text: String,
+    pub body_html: Option<String>,
+    pub attachments: Vec<Attachment>,
+    /// Overrides the account's "send as plain text" preference for this message.
+    pub send_plain_text: Option<bool>,
+    /// The Message-ID of the message this one replies to.
+    #[serde(default)]
+    pub in_reply_to: Option<String>,
+    /// The thread the message belongs to, oldest message first.
+    #[serde(default)]
+    pub references: Vec<String>,
+}
+
+impl Composition {
+    pub fn recipients(&self) -> impl Iterator<Item = &EmailAddr> {
+        self.to.iter().chain(&self.cc).chain(&self.bcc)
+    }
+
+    pub fn is_plain_text(&self, preferences: &AccountPreferences) -> bool {
+        self.send_plain_text.unwrap_or(preferences.send_plain_text)
+    }
+
+    /// Sets the body from the composer's Markdown: the HTML it converts to,
+    /// and the plain text alternative generated from that.
+    pub fn set_markdown(&mut self, markdown: &str) {
+        let html = markdown::markdown_to_html(markdown);
+        self.body_text = plaintext::html_to_text(&html);
+        self.body_html = Some(html);
+    }
+
+    /// Total size of the attachments, before encoding.
+    pub fn attachments_size(&self) -> u64 {
+        self.attachments.iter().map(Attachment::size).sum()
+    }
+
+    /// Serializes the composition into the message to submit, see [`mime::build_message`].
+    pub async fn to_message(
+        &self,
+        preferences: &AccountPreferences,
+        files: Option<&dyn FileSystem>,
+    ) -> Result<Vec<u8>> {
+        mime::build_message(self, self.is_plain_text(preferences), files).await
+    }
+
+    /// Serializes the composition for the Drafts folder, see [`mime::build_draft`].
+    pub async fn to_draft(&self, files: Option<&dyn FileSystem>) -> Result<Vec<u8>> {
+        mime::build_draft(self, files).await
+    }
+}
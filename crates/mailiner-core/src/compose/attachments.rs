@@ -0,0 +1,120 @@
+//! Content type detection and size limits of attachments.
+
+use super::Composition;
+use crate::error::{MailinerError, Result};
+use crate::models::AttachmentLimits;
+
+/// File signatures of common formats, which are more reliable than the
+/// file name.
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"%PDF-", "application/pdf"),
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"BEGIN:VCALENDAR", "text/calendar"),
+    (b"BEGIN:VCARD", "text/vcard"),
+];
+
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("svg", "image/svg+xml"),
+    ("txt", "text/plain"),
+    ("csv", "text/csv"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("ics", "text/calendar"),
+    ("vcf", "text/vcard"),
+    ("eml", "message/rfc822"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("json", "application/json"),
+    ("doc", "application/msword"),
+    (
+        "docx",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    ("xls", "application/vnd.ms-excel"),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+];
+
+/// Guesses the content type of a file from its first bytes, falling back
+/// to the extension of `filename`.
+pub fn content_type(filename: &str, data: &[u8]) -> &'static str {
+    if let Some((_, content_type)) = SIGNATURES
+        .iter()
+        .find(|(signature, _)| data.starts_with(signature))
+    {
+        return content_type;
+    }
+    let extension = filename
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+    EXTENSIONS
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, content_type)| *content_type)
+        .unwrap_or("application/octet-stream")
+}
+
+/// Fails if attaching `size` more bytes to `composition` exceeds `limits`.
+pub fn check_size(composition: &Composition, size: u64, limits: &AttachmentLimits) -> Result<()> {
+    if size > limits.max_size {
+        return Err(MailinerError::InvalidData(format!(
+            "The attachment is larger than {} MB",
+            limits.max_size / (1024 * 1024)
+        )));
+    }
+    if composition.attachments_size() + size > limits.max_total_size {
+        return Err(MailinerError::InvalidData(format!(
+            "The attachments are larger than {} MB together",
+            limits.max_total_size / (1024 * 1024)
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compose::{Attachment, AttachmentData};
+
+    #[test]
+    fn detection_and_limits() {
+        assert_eq!(content_type("scan", b"%PDF-1.7\n"), "application/pdf");
+        assert_eq!(content_type("photo.PNG", b""), "image/png");
+        assert_eq!(content_type("notes.txt.pdf", b"plain"), "application/pdf");
+        assert_eq!(content_type("archive", b"\x00\x01"), "application/octet-stream");
+
+        let limits = AttachmentLimits {
+            max_size: 10,
+            max_total_size: 15,
+            memory_threshold: 5,
+        };
+        let mut composition = Composition::default();
+        assert!(check_size(&composition, 11, &limits).is_err());
+        composition.attachments.push(Attachment {
+            filename: "a".to_string(),
+            content_type: "text/plain".to_string(),
+            data: AttachmentData::File {
+                path: "attachments/a".to_string(),
+                size: 10,
+            },
+        });
+        assert!(check_size(&composition, 5, &limits).is_ok());
+        assert!(check_size(&composition, 6, &limits).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+//! Checks run on a composition before it is sent.
+//!
+//! Each check inspects the [`Composition`] and may produce a warning that the
+//! UI shows to the user, who can then fix the message or send it anyway.
+
+use super::Composition;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreSendWarning {
+    /// Identifies the check that produced the warning, e.g. `missing-attachment`.
+    pub check: &'static str,
+    pub message: String,
+}
+
+pub trait PreSendCheck: Send + Sync {
+    fn check(&self, composition: &Composition) -> Option<PreSendWarning>;
+}
+
+/// The pipeline of checks to run before sending.
+pub struct PreSendChecks {
+    checks: Vec<Box<dyn PreSendCheck>>,
+}
+
+impl PreSendChecks {
+    pub fn new() -> Self {
+        Self { checks: Vec::new() }
+    }
+
+    pub fn add(&mut self, check: impl PreSendCheck + 'static) -> &mut Self {
+        self.checks.push(Box::new(check));
+        self
+    }
+
+    pub fn run(&self, composition: &Composition) -> Vec<PreSendWarning> {
+        self.checks
+            .iter()
+            .filter_map(|check| check.check(composition))
+            .collect()
+    }
+}
+
+impl Default for PreSendChecks {
+    /// All the built-in checks with their default settings.
+    fn default() -> Self {
+        let mut checks = Self::new();
+        checks
+            .add(MissingAttachmentCheck::default())
+            .add(EmptySubjectCheck)
+            .add(TooManyRecipientsCheck::default());
+        checks
+    }
+}
+
+/// Warns when the text mentions an attachment but nothing is attached.
+pub struct MissingAttachmentCheck {
+    /// Lowercase phrases (or word stems) that indicate an attachment.
+    phrases: Vec<String>,
+}
+
+impl MissingAttachmentCheck {
+    pub fn new(phrases: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            phrases: phrases
+                .into_iter()
+                .map(|phrase| phrase.into().to_lowercase())
+                .collect(),
+        }
+    }
+}
+
+impl Default for MissingAttachmentCheck {
+    fn default() -> Self {
+        Self::new([
+            // English
+            "attached",
+            "attachment",
+            "enclosed",
+            // Czech
+            "přílo",
+            "přikládám",
+            // German
+            "anhang",
+            "angehängt",
+            "anbei",
+            // French
+            "pièce jointe",
+            "pièces jointes",
+            "ci-joint",
+            // Spanish
+            "adjunto",
+            "adjunta",
+            // Italian
+            "allegato",
+        ])
+    }
+}
+
+impl PreSendCheck for MissingAttachmentCheck {
+    fn check(&self, composition: &Composition) -> Option<PreSendWarning> {
+        if !composition.attachments.is_empty() {
+            return None;
+        }
+
+        // Quoted text of the message being replied to doesn't count.
+        let own_text = composition
+            .body_text
+            .lines()
+            .take_while(|line| !is_quote_header(line))
+            .filter(|line| !line.trim_start().starts_with('>'))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let text = format!("{}\n{}", composition.subject, own_text).to_lowercase();
+
+        let phrase = self
+            .phrases
+            .iter()
+            .find(|phrase| text.contains(phrase.as_str()))?;
+        Some(PreSendWarning {
+            check: "missing-attachment",
+            message: format!(
+                "The message mentions \"{}\", but there are no attachments.",
+                phrase
+            ),
+        })
+    }
+}
+
+/// Recognizes the `On <date>, <someone> wrote:` line above a quoted message.
+fn is_quote_header(line: &str) -> bool {
+    let line = line.trim_end();
+    line.starts_with("On ") && line.ends_with("wrote:")
+}
+
+pub struct EmptySubjectCheck;
+
+impl PreSendCheck for EmptySubjectCheck {
+    fn check(&self, composition: &Composition) -> Option<PreSendWarning> {
+        composition
+            .subject
+            .trim()
+            .is_empty()
+            .then(|| PreSendWarning {
+                check: "empty-subject",
+                message: "The message has no subject.".to_string(),
+            })
+    }
+}
+
+/// Warns about messages sent to many recipients at once, which are often
+/// a mistake (e.g. a reply-all to a mailing list).
+pub struct TooManyRecipientsCheck {
+    pub limit: usize,
+}
+
+impl Default for TooManyRecipientsCheck {
+    fn default() -> Self {
+        Self { limit: 50 }
+    }
+}
+
+impl PreSendCheck for TooManyRecipientsCheck {
+    fn check(&self, composition: &Composition) -> Option<PreSendWarning> {
+        let count = composition.recipients().count();
+        (count > self.limit).then(|| PreSendWarning {
+            check: "too-many-recipients",
+            message: format!("The message is addressed to {} recipients.", count),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compose::{Attachment, AttachmentData};
+
+    #[test]
+    fn missing_attachment() {
+        let check = MissingAttachmentCheck::default();
+        let mut composition = Composition {
+            subject: "Report".to_string(),
+            body_text: "Hi,\n\nsee the report.\n\n> I've attached the draft.\n".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(check.check(&composition), None);
+
+        composition.body_text = "Ahoj,\n\nv příloze posílám report.".to_string();
+        assert_eq!(
+            check.check(&composition).map(|w| w.check),
+            Some("missing-attachment")
+        );
+
+        composition.attachments.push(Attachment {
+            filename: "report.pdf".to_string(),
+            content_type: "application/pdf".to_string(),
+            data: AttachmentData::Memory(Vec::new()),
+        });
+        assert_eq!(check.check(&composition), None);
+    }
+}
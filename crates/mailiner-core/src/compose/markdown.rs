@@ -0,0 +1,207 @@
+//! Markdown, the source of the composer's rich text mode.
+//!
+//! Only the subset the composer's toolbar writes is understood: paragraphs,
+//! headings, lists, block quotes, emphasis, code and links. HTML in the
+//! source is escaped rather than passed through, so the result is safe to
+//! preview and to send.
+
+/// Converts Markdown to the HTML body of a message.
+pub fn markdown_to_html(markdown: &str) -> String {
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut html = String::with_capacity(markdown.len() + markdown.len() / 4);
+    write_blocks(&lines, &mut html);
+    html
+}
+
+fn write_blocks(lines: &[&str], html: &mut String) {
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim_end();
+        if line.trim_start().is_empty() {
+            i += 1;
+            continue;
+        }
+
+        if is_quote(line) {
+            let end = i + lines[i..].iter().take_while(|line| is_quote(line)).count();
+            let quoted: Vec<&str> = lines[i..end].iter().map(|line| strip_quote(line)).collect();
+            html.push_str("<blockquote>");
+            write_blocks(&quoted, html);
+            html.push_str("</blockquote>");
+            i = end;
+        } else if let Some((level, text)) = heading(line) {
+            html.push_str(&format!("<h{}>{}</h{}>", level, inline(text), level));
+            i += 1;
+        } else if let Some((tag, _)) = list_item(line) {
+            html.push_str(&format!("<{}>", tag));
+            while i < lines.len() {
+                match list_item(lines[i]) {
+                    Some((item_tag, text)) if item_tag == tag => {
+                        html.push_str(&format!("<li>{}</li>", inline(text.trim_end())));
+                        i += 1;
+                    }
+                    _ => break,
+                }
+            }
+            html.push_str(&format!("</{}>", tag));
+        } else {
+            // Single line breaks within a paragraph are kept, as users of a
+            // mail composer expect.
+            let start = i;
+            i += 1;
+            while i < lines.len() && !lines[i].trim_start().is_empty() && !starts_block(lines[i]) {
+                i += 1;
+            }
+            let text: Vec<String> = lines[start..i].iter().map(|line| inline(line.trim())).collect();
+            html.push_str(&format!("<p>{}</p>", text.join("<br>")));
+        }
+    }
+}
+
+fn starts_block(line: &str) -> bool {
+    is_quote(line) || heading(line).is_some() || list_item(line).is_some()
+}
+
+fn is_quote(line: &str) -> bool {
+    line.trim_start().starts_with('>')
+}
+
+fn strip_quote(line: &str) -> &str {
+    let line = &line.trim_start()[1..];
+    line.strip_prefix(' ').unwrap_or(line)
+}
+
+/// The level and text of a `#` heading.
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    let text = line[level..].strip_prefix(' ')?;
+    (1..=6).contains(&level).then_some((level, text.trim()))
+}
+
+/// The list tag and the text of a `-` or `1.` list item.
+fn list_item(line: &str) -> Option<(&'static str, &str)> {
+    let line = line.trim_start();
+    if let Some(text) = ["- ", "* ", "+ "].iter().find_map(|bullet| line.strip_prefix(bullet)) {
+        return Some(("ul", text));
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let text = line[digits..].strip_prefix(". ").filter(|_| digits > 0)?;
+    Some(("ol", text))
+}
+
+fn inline(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut after_word = false;
+    while let Some(c) = rest.chars().next() {
+        match span(rest, after_word) {
+            Some((span, next)) => {
+                html.push_str(&span);
+                rest = next;
+            }
+            None => {
+                html.push_str(&escape(&rest[..c.len_utf8()]));
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        after_word = c.is_alphanumeric();
+    }
+    html
+}
+
+/// The HTML of the span `rest` starts with, and the text after it.
+fn span(rest: &str, after_word: bool) -> Option<(String, &str)> {
+    if let Some(inner) = rest.strip_prefix('\\') {
+        let c = inner.chars().next().filter(char::is_ascii_punctuation)?;
+        return Some((escape(&inner[..c.len_utf8()]), &inner[c.len_utf8()..]));
+    }
+    if let Some(inner) = rest.strip_prefix('`') {
+        let end = inner.find('`')?;
+        return Some((format!("<code>{}</code>", escape(&inner[..end])), &inner[end + 1..]));
+    }
+    if let Some(inner) = rest.strip_prefix("**") {
+        let end = inner.find("**").filter(|end| *end > 0)?;
+        return Some((format!("<strong>{}</strong>", inline(&inner[..end])), &inner[end + 2..]));
+    }
+    if let Some(marker @ ('*' | '_')) = rest.chars().next() {
+        // Underscores within words, as in snake_case, are no emphasis.
+        let inner = &rest[1..];
+        if !inner.starts_with(|c: char| !c.is_whitespace()) {
+            return None;
+        }
+        let end = inner.find(marker).filter(|end| *end > 0)?;
+        let after = &inner[end + 1..];
+        if marker == '_' && (after_word || after.starts_with(char::is_alphanumeric)) {
+            return None;
+        }
+        return Some((format!("<em>{}</em>", inline(&inner[..end])), after));
+    }
+    if let Some(inner) = rest.strip_prefix('[') {
+        let text_end = inner.find("](")?;
+        let target = &inner[text_end + 2..];
+        let url_end = target.find(')')?;
+        let url = target[..url_end].trim();
+        let text = inline(&inner[..text_end]);
+        let html = if is_safe_url(url) {
+            format!("<a href=\"{}\">{}</a>", escape(url), text)
+        } else {
+            text
+        };
+        return Some((html, &target[url_end + 1..]));
+    }
+    None
+}
+
+/// Links may only lead to web pages and addresses, not run scripts.
+fn is_safe_url(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    ["http://", "https://", "mailto:"].iter().any(|scheme| url.starts_with(scheme))
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compose::plaintext::html_to_text;
+
+    #[test]
+    fn converts_markdown() {
+        let markdown = "# Notes\n\
+            Hi **all**,\nthe *agenda* is [here](https://example.com/a?b=1&c=2).\n\n\
+            - one\n- two with snake_case\n\n\
+            1. first\n2. second\n\n\
+            > quoted `<code>`\n> > nested\n\n\
+            <script>alert(1)</script> [x](javascript:void)";
+        let html = markdown_to_html(markdown);
+        assert_eq!(
+            html,
+            "<h1>Notes</h1>\
+             <p>Hi <strong>all</strong>,<br>the <em>agenda</em> is \
+             <a href=\"https://example.com/a?b=1&amp;c=2\">here</a>.</p>\
+             <ul><li>one</li><li>two with snake_case</li></ul>\
+             <ol><li>first</li><li>second</li></ol>\
+             <blockquote><p>quoted <code>&lt;code&gt;</code></p>\
+             <blockquote><p>nested</p></blockquote></blockquote>\
+             <p>&lt;script&gt;alert(1)&lt;/script&gt; x</p>"
+        );
+
+        assert_eq!(
+            html_to_text(&markdown_to_html("Hi **all**,\n\n- one\n- two")),
+            "Hi *all*,\n\n* one\n* two"
+        );
+    }
+}
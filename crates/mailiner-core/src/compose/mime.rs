@@ -0,0 +1,274 @@
+//! Serialization of compositions into RFC 5322 / MIME messages.
+
+use std::borrow::Cow;
+
+use base64::prelude::*;
+use chrono::Utc;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use super::plaintext::{format_flowed, html_to_text};
+use super::{Attachment, AttachmentData, Composition};
+use crate::error::{MailinerError, Result};
+use crate::models::EmailAddr;
+use crate::platform::FileSystem;
+
+/// Maximum length of the encoded text of a single RFC 2047 encoded word,
+/// so that the whole word fits into 75 characters.
+const ENCODED_WORD_TEXT_LEN: usize = 60;
+
+/// Bytes of attachment data encoded at a time, a multiple of the 57 bytes
+/// that make up a full 76 character line of base64.
+const BASE64_CHUNK_LEN: usize = 57 * 1024;
+
+/// Encodes a header value as RFC 2047 encoded words if it isn't plain ASCII.
+pub fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        return value.to_string();
+    }
+
+    let mut words = Vec::new();
+    let mut word = String::new();
+    for c in value.chars() {
+        let mut buf = [0u8; 4];
+        let encoded: String = c
+            .encode_utf8(&mut buf)
+            .bytes()
+            .map(|byte| match byte {
+                b' ' => "_".to_string(),
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'-' => (byte as char).to_string(),
+                _ => format!("={:02X}", byte),
+            })
+            .collect();
+        // Encoded words must not split a multi-byte character.
+        if word.len() + encoded.len() > ENCODED_WORD_TEXT_LEN {
+            words.push(std::mem::take(&mut word));
+        }
+        word.push_str(&encoded);
+    }
+    words.push(word);
+
+    words
+        .iter()
+        .map(|word| format!("=?utf-8?q?{}?=", word))
+        .collect::<Vec<_>>()
+        .join("\r\n ")
+}
+
+pub fn encode_address(addr: &EmailAddr) -> String {
+    let email = addr.email.as_deref().unwrap_or_default();
+    match addr.name.as_deref() {
+        Some(name) if name.is_ascii() => {
+            format!(
+                "\"{}\" <{}>",
+                name.replace('\\', "\\\\").replace('"', "\\\""),
+                email
+            )
+        }
+        Some(name) => format!("{} <{}>", encode_header(name), email),
+        None => email.to_string(),
+    }
+}
+
+fn encode_address_list(addrs: &[EmailAddr]) -> String {
+    addrs
+        .iter()
+        .map(encode_address)
+        .collect::<Vec<_>>()
+        .join(",\r\n ")
+}
+
+fn new_boundary() -> String {
+    format!("mailiner-{}", uuid::Uuid::new_v4().simple())
+}
+
+fn base64_lines(data: &[u8]) -> String {
+    let encoded = BASE64_STANDARD.encode(data);
+    let mut lines = String::with_capacity(encoded.len() + encoded.len() / 38);
+    // Base64 output is ASCII, so splitting by bytes is safe.
+    for chunk in encoded.as_bytes().chunks(76) {
+        lines.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        lines.push_str("\r\n");
+    }
+    lines
+}
+
+fn text_part(text: &str) -> String {
+    let encoding = if text.is_ascii() { "7bit" } else { "8bit" };
+    format!(
+        "Content-Type: text/plain; charset=utf-8; format=flowed\r\n\
+         Content-Transfer-Encoding: {}\r\n\r\n{}",
+        encoding,
+        format_flowed(text).replace('\n', "\r\n")
+    )
+}
+
+fn html_part(html: &str) -> String {
+    format!(
+        "Content-Type: text/html; charset=utf-8\r\n\
+         Content-Transfer-Encoding: base64\r\n\r\n{}",
+        base64_lines(html.as_bytes())
+    )
+}
+
+/// The headers of an attachment part, up to the empty line before the content.
+fn attachment_headers(attachment: &Attachment) -> String {
+    let filename = if attachment.filename.is_ascii() {
+        format!("filename=\"{}\"", attachment.filename.replace('"', "\\\""))
+    } else {
+        // RFC 2231 parameter value encoding.
+        let encoded: String = attachment
+            .filename
+            .bytes()
+            .map(|byte| match byte {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'-' | b'_' => {
+                    (byte as char).to_string()
+                }
+                _ => format!("%{:02X}", byte),
+            })
+            .collect();
+        format!("filename*=utf-8''{}", encoded)
+    };
+    format!(
+        "Content-Type: {}\r\n\
+         Content-Disposition: attachment; {}\r\n\
+         Content-Transfer-Encoding: base64\r\n\r\n",
+        attachment.content_type, filename
+    )
+}
+
+fn multipart(subtype: &str, parts: &[String]) -> String {
+    let boundary = new_boundary();
+    let mut body = format!(
+        "Content-Type: multipart/{}; boundary=\"{}\"\r\n\r\n",
+        subtype, boundary
+    );
+    for part in parts {
+        body.push_str(&format!("--{}\r\n{}", boundary, part));
+        if !part.ends_with("\r\n") {
+            body.push_str("\r\n");
+        }
+    }
+    body.push_str(&format!("--{}--\r\n", boundary));
+    body
+}
+
+/// Builds the message to submit, reading attachments kept on disk from
+/// `files`.
+///
+/// In plain-text mode the rich text is converted to `format=flowed` plain text
+/// and no HTML alternative is included.
+pub async fn build_message(
+    composition: &Composition,
+    plain_text: bool,
+    files: Option<&dyn FileSystem>,
+) -> Result<Vec<u8>> {
+    let mut message = Vec::new();
+    write_message(composition, plain_text, false, files, &mut message).await?;
+    Ok(message)
+}
+
+/// Builds the message to save in the Drafts folder, which unlike the sent
+/// message keeps the Bcc recipients.
+pub async fn build_draft(
+    composition: &Composition,
+    files: Option<&dyn FileSystem>,
+) -> Result<Vec<u8>> {
+    let mut message = Vec::new();
+    write_message(composition, false, true, files, &mut message).await?;
+    Ok(message)
+}
+
+/// Writes the message to `out`. Attachments are encoded in chunks, one at a
+/// time, so that no encoded copy of all of them is held in memory.
+pub async fn write_message<W>(
+    composition: &Composition,
+    plain_text: bool,
+    include_bcc: bool,
+    files: Option<&dyn FileSystem>,
+    out: &mut W,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    let mut message = String::new();
+    if let Some(from) = &composition.from {
+        message.push_str(&format!("From: {}\r\n", encode_address(from)));
+    }
+    if !composition.to.is_empty() {
+        message.push_str(&format!("To: {}\r\n", encode_address_list(&composition.to)));
+    }
+    if !composition.cc.is_empty() {
+        message.push_str(&format!("Cc: {}\r\n", encode_address_list(&composition.cc)));
+    }
+    if include_bcc && !composition.bcc.is_empty() {
+        message.push_str(&format!("Bcc: {}\r\n", encode_address_list(&composition.bcc)));
+    }
+    message.push_str(&format!(
+        "Subject: {}\r\n",
+        encode_header(&composition.subject)
+    ));
+    message.push_str(&format!("Date: {}\r\n", Utc::now().to_rfc2822()));
+    message.push_str(&format!(
+        "Message-ID: <{}@mailiner>\r\n",
+        uuid::Uuid::new_v4()
+    ));
+    if let Some(in_reply_to) = &composition.in_reply_to {
+        message.push_str(&format!("In-Reply-To: <{}>\r\n", in_reply_to));
+    }
+    if !composition.references.is_empty() {
+        let references: Vec<_> = composition
+            .references
+            .iter()
+            .map(|id| format!("<{}>", id))
+            .collect();
+        message.push_str(&format!("References: {}\r\n", references.join("\r\n ")));
+    }
+    message.push_str("MIME-Version: 1.0\r\n");
+
+    let text = match &composition.body_html {
+        Some(html) => html_to_text(html),
+        None => composition.body_text.clone(),
+    };
+    let body = match (&composition.body_html, plain_text) {
+        (Some(html), false) => multipart("alternative", &[text_part(&text), html_part(html)]),
+        _ => text_part(&text),
+    };
+
+    if composition.attachments.is_empty() {
+        message.push_str(&body);
+        return Ok(out.write_all(message.as_bytes()).await?);
+    }
+
+    let boundary = new_boundary();
+    message.push_str(&format!(
+        "Content-Type: multipart/mixed; boundary=\"{}\"\r\n\r\n--{}\r\n{}",
+        boundary, boundary, body
+    ));
+    if !body.ends_with("\r\n") {
+        message.push_str("\r\n");
+    }
+    out.write_all(message.as_bytes()).await?;
+
+    for attachment in &composition.attachments {
+        let header = format!("--{}\r\n{}", boundary, attachment_headers(attachment));
+        out.write_all(header.as_bytes()).await?;
+        let data = match &attachment.data {
+            AttachmentData::Memory(data) => Cow::Borrowed(data),
+            AttachmentData::File { path, .. } => {
+                let files = files.ok_or_else(|| {
+                    MailinerError::InvalidData(format!(
+                        "No file system to read attachment {} from",
+                        attachment.filename
+                    ))
+                })?;
+                Cow::Owned(files.read(path).await?)
+            }
+        };
+        for chunk in data.chunks(BASE64_CHUNK_LEN) {
+            out.write_all(base64_lines(chunk).as_bytes()).await?;
+        }
+    }
+    out.write_all(format!("--{}--\r\n", boundary).as_bytes())
+        .await?;
+    Ok(())
+}
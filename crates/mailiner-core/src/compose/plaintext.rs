@@ -0,0 +1,418 @@
+//! Conversion of rich-text (HTML) compositions to plain text.
+
+/// Preferred length of `format=flowed` lines, as recommended by RFC 3676.
+const FLOWED_WIDTH: usize = 72;
+
+/// Converts HTML to readable plain text.
+///
+/// Paragraphs and line breaks are kept, list items are bulleted, quotes are
+/// prefixed with `>`, emphasis is marked with `*bold*`, `/italic/` and
+/// `_underline_`, and link targets are kept next to the link text.
+pub fn html_to_text(html: &str) -> String {
+    let mut writer = TextWriter::default();
+    // Stack of open links, with the position of the link text in the output.
+    let mut links: Vec<(String, usize)> = Vec::new();
+    let mut skip_depth = 0;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            if skip_depth == 0 {
+                writer.text(&decode_entities(rest));
+            }
+            break;
+        };
+        if skip_depth == 0 {
+            writer.text(&decode_entities(&rest[..start]));
+        }
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = Tag::parse(&rest[1..end]);
+        rest = &rest[end + 1..];
+
+        if matches!(tag.name.as_str(), "script" | "style" | "head" | "title") {
+            if tag.closing {
+                skip_depth = usize::saturating_sub(skip_depth, 1);
+            } else {
+                skip_depth += 1;
+            }
+            continue;
+        }
+        if skip_depth > 0 {
+            continue;
+        }
+
+        match (tag.name.as_str(), tag.closing) {
+            ("br", _) => writer.line_break(),
+            ("p" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "ul" | "ol" | "table", _) => {
+                writer.paragraph_break()
+            }
+            ("div" | "tr" | "li", true) => writer.line_break(),
+            ("div" | "tr", false) => writer.ensure_line_start(),
+            ("li", false) => {
+                writer.ensure_line_start();
+                writer.text("* ");
+            }
+            ("td" | "th", false) => writer.text(" "),
+            ("hr", _) => {
+                writer.ensure_line_start();
+                writer.text("----------");
+                writer.line_break();
+            }
+            ("pre", closing) => {
+                writer.paragraph_break();
+                writer.preformatted = !closing;
+            }
+            ("blockquote", closing) => {
+                writer.paragraph_break();
+                if closing {
+                    writer.quote_depth = writer.quote_depth.saturating_sub(1);
+                } else {
+                    writer.quote_depth += 1;
+                }
+            }
+            ("b" | "strong", _) => writer.text("*"),
+            ("i" | "em", _) => writer.text("/"),
+            ("u", _) => writer.text("_"),
+            ("a", false) => {
+                links.push((tag.href.unwrap_or_default(), writer.out.len()));
+            }
+            ("a", true) => {
+                if let Some((href, text_start)) = links.pop() {
+                    let text = writer.out[text_start..].trim().to_string();
+                    let target = href.strip_prefix("mailto:").unwrap_or(&href);
+                    if !href.is_empty() && text != target && text != href {
+                        writer.text(&format!(" <{}>", href));
+                    }
+                }
+            }
+            ("img", _) => {
+                if let Some(alt) = tag.alt.filter(|alt| !alt.is_empty()) {
+                    writer.text(&format!("[{}]", alt));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    writer.finish()
+}
+
+/// Wraps plain text as `format=flowed` (RFC 3676).
+///
+/// Long lines are split at spaces, with a trailing space marking the soft
+/// breaks. Words longer than a line, such as URLs, are never broken. The
+/// input is expected to use `\n` line endings.
+pub fn format_flowed(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + text.len() / FLOWED_WIDTH);
+    for line in text.split('\n') {
+        let depth = line.chars().take_while(|c| *c == '>').count();
+        let content = &line[depth..];
+        let content = if depth > 0 {
+            content.strip_prefix(' ').unwrap_or(content)
+        } else {
+            content
+        };
+
+        // The signature separator is the only line that keeps its trailing space.
+        if depth == 0 && content == "-- " {
+            out.push_str("-- \n");
+            continue;
+        }
+        let content = content.trim_end_matches(' ');
+
+        let prefix = ">".repeat(depth);
+        let mut current = String::new();
+        for word in content.split(' ') {
+            if !current.is_empty()
+                && prefix.len() + 1 + current.len() + 1 + word.len() > FLOWED_WIDTH
+            {
+                push_flowed_line(&mut out, &prefix, &current, true);
+                current.clear();
+            } else if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(word);
+        }
+        push_flowed_line(&mut out, &prefix, &current, false);
+    }
+    out.pop();
+    out
+}
+
+/// Joins the soft line breaks of `format=flowed` text, the reverse of
+/// [`format_flowed`].
+pub fn unflow(text: &str) -> String {
+    // Quote depth, content and whether the line continues on the next one.
+    let mut lines: Vec<(usize, String, bool)> = Vec::new();
+    for line in text.lines() {
+        let depth = line.chars().take_while(|c| *c == '>').count();
+        let content = &line[depth..];
+        let content = content.strip_prefix(' ').unwrap_or(content);
+        let flowed = content.ends_with(' ') && content != "-- ";
+        match lines.last_mut() {
+            Some((last_depth, last, continues)) if *continues && *last_depth == depth => {
+                last.push_str(content);
+                *continues = flowed;
+            }
+            _ => lines.push((depth, content.to_string(), flowed)),
+        }
+    }
+
+    lines
+        .into_iter()
+        .map(|(depth, content, _)| match depth {
+            0 => content,
+            _ => format!("{} {}", ">".repeat(depth), content),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn push_flowed_line(out: &mut String, prefix: &str, content: &str, soft_break: bool) {
+    out.push_str(prefix);
+    // Space-stuffing, so that the line isn't mistaken for a quote or a
+    // mbox separator, and a space after the quote marks for readability.
+    if !prefix.is_empty() || content.starts_with([' ', '>']) || content.starts_with("From ") {
+        out.push(' ');
+    }
+    out.push_str(content);
+    if soft_break {
+        out.push(' ');
+    }
+    out.push('\n');
+}
+
+#[derive(Default)]
+struct TextWriter {
+    out: String,
+    quote_depth: usize,
+    preformatted: bool,
+    at_line_start: bool,
+}
+
+impl TextWriter {
+    fn text(&mut self, text: &str) {
+        if self.preformatted {
+            for (i, line) in text.split('\n').enumerate() {
+                if i > 0 {
+                    self.line_break();
+                }
+                self.write(line);
+            }
+            return;
+        }
+
+        let mut collapsed = String::with_capacity(text.len());
+        for c in text.chars() {
+            if c.is_whitespace() && c != '\u{a0}' {
+                let at_start = self.at_line_start && collapsed.is_empty();
+                if !at_start && !collapsed.ends_with(' ') && !self.out.ends_with(' ') {
+                    collapsed.push(' ');
+                }
+            } else {
+                collapsed.push(if c == '\u{a0}' { ' ' } else { c });
+            }
+        }
+        if !collapsed.is_empty() {
+            self.write(&collapsed);
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        if self.out.is_empty() || self.at_line_start {
+            if self.quote_depth > 0 {
+                self.out.push_str(&">".repeat(self.quote_depth));
+                self.out.push(' ');
+            }
+            self.at_line_start = false;
+        }
+        self.out.push_str(text);
+    }
+
+    fn line_break(&mut self) {
+        let trimmed = self.out.trim_end_matches(' ').len();
+        self.out.truncate(trimmed);
+        self.out.push('\n');
+        self.at_line_start = true;
+    }
+
+    fn ensure_line_start(&mut self) {
+        if !self.out.is_empty() && !self.at_line_start {
+            self.line_break();
+        }
+    }
+
+    fn paragraph_break(&mut self) {
+        if self.out.is_empty() {
+            return;
+        }
+        self.ensure_line_start();
+        if !self.last_line_blank() {
+            if self.quote_depth > 0 {
+                self.out.push_str(&">".repeat(self.quote_depth));
+            }
+            self.line_break();
+        }
+    }
+
+    fn last_line_blank(&self) -> bool {
+        let Some(text) = self.out.strip_suffix('\n') else {
+            return false;
+        };
+        let last_line = text.rsplit('\n').next().unwrap_or_default();
+        last_line.trim_start_matches('>').trim().is_empty()
+    }
+
+    fn finish(self) -> String {
+        let mut text = String::with_capacity(self.out.len());
+        let mut blank_lines = 0;
+        for line in self.out.trim().lines() {
+            let line = line.trim_end();
+            if line.is_empty() {
+                blank_lines += 1;
+                if blank_lines > 1 {
+                    continue;
+                }
+            } else {
+                blank_lines = 0;
+            }
+            text.push_str(line);
+            text.push('\n');
+        }
+        // Drop trailing blank lines, including empty quote lines.
+        text.truncate(text.trim_end_matches(['>', ' ', '\n']).len());
+        text
+    }
+}
+
+struct Tag {
+    name: String,
+    closing: bool,
+    href: Option<String>,
+    alt: Option<String>,
+}
+
+impl Tag {
+    fn parse(tag: &str) -> Self {
+        let (closing, tag) = match tag.strip_prefix('/') {
+            Some(tag) => (true, tag),
+            None => (false, tag),
+        };
+        let tag = tag.trim_end_matches('/');
+        let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+        Self {
+            name: tag[..name_end].to_ascii_lowercase(),
+            closing,
+            href: attribute(&tag[name_end..], "href"),
+            alt: attribute(&tag[name_end..], "alt"),
+        }
+    }
+}
+
+fn attribute(attributes: &str, name: &str) -> Option<String> {
+    let lower = attributes.to_ascii_lowercase();
+    let mut search = 0;
+    while let Some(pos) = lower[search..].find(name) {
+        let start = search + pos;
+        search = start + name.len();
+        let preceded_by_space = lower[..start].ends_with(|c: char| c.is_whitespace());
+        let rest = attributes[search..].trim_start();
+        let Some(value) = rest.strip_prefix('=').filter(|_| preceded_by_space) else {
+            continue;
+        };
+        let value = value.trim_start();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next().unwrap_or_default(),
+            _ => value.split(char::is_whitespace).next().unwrap_or_default(),
+        };
+        return Some(decode_entities(value));
+    }
+    None
+}
+
+pub(crate) fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .map(|end| &rest[1..end + 1]);
+        let c = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => {
+                let code = match entity
+                    .strip_prefix("#x")
+                    .or_else(|| entity.strip_prefix("#X"))
+                {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+                };
+                code.and_then(char::from_u32)
+            }
+        });
+        match (c, entity) {
+            (Some(c), Some(entity)) => {
+                decoded.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_to_flowed_text() {
+        let html = "<html><head><style>p { color: red }</style></head><body>\
+            <p>Hi <b>all</b>,</p>\
+            <p>the notes are <a href=\"https://example.com/notes\">here</a> &amp; the \
+            agenda follows, it is a bit longer than a single line of plain text can hold.</p>\
+            <ul><li>one</li><li>two</li></ul>\
+            <blockquote><p>quoted</p></blockquote>\
+            </body></html>";
+        let text = html_to_text(html);
+        assert_eq!(
+            text,
+            "Hi *all*,\n\n\
+             the notes are here <https://example.com/notes> & the agenda follows, it is a bit longer than a single line of plain text can hold.\n\n\
+             * one\n\
+             * two\n\n\
+             > quoted"
+        );
+
+        assert_eq!(
+            format_flowed(&text),
+            "Hi *all*,\n\n\
+             the notes are here <https://example.com/notes> & the agenda follows, it \n\
+             is a bit longer than a single line of plain text can hold.\n\n\
+             * one\n\
+             * two\n\n\
+             > quoted"
+        );
+        assert_eq!(unflow(&format_flowed(&text)), text);
+    }
+}
@@ -0,0 +1,242 @@
+//! Replies to and forwards of received messages.
+
+use super::{Attachment, Composition};
+use crate::models::{EmailAddr, EmailAddress, Envelope};
+
+/// Starts a reply to `original`, whose plain text is `text`, sent as
+/// `from`. A reply to all also goes to the other recipients of the
+/// original, except `from`.
+pub fn reply(original: &Envelope, text: &str, from: &EmailAddr, all: bool) -> Composition {
+    let author = original.reply_to.as_ref().or(original.from.as_ref());
+    let mut to = addrs(author);
+    // Replying to a message we sent ourselves continues the conversation
+    // with its recipients.
+    if !to.is_empty() && to.iter().all(|addr| same_address(addr, from)) {
+        to = addrs(original.to.as_ref());
+    }
+    let mut cc = Vec::new();
+    if all {
+        to.extend(addrs(original.to.as_ref()));
+        cc = addrs(original.cc.as_ref());
+    }
+
+    let mut seen = vec![from.clone()];
+    let to = unique(to, &mut seen);
+    let cc = unique(cc, &mut seen);
+
+    let sender = original
+        .from
+        .as_ref()
+        .and_then(|from| from.addrs().first().map(|addr| display_name(addr)))
+        .unwrap_or_else(|| "Unknown".to_string());
+    let body_text = format!(
+        "\n\nOn {}, {} wrote:\n{}",
+        original.date.format("%a, %-d %b %Y %H:%M"),
+        sender,
+        quote(text)
+    );
+
+    let mut references = original.references.clone();
+    // Without References, the thread is known from In-Reply-To only if it
+    // names a single message (RFC 5322, section 3.6.4).
+    if references.is_empty() && original.in_reply_to.len() == 1 {
+        references = original.in_reply_to.clone();
+    }
+    references.extend(original.message_id.clone());
+
+    Composition {
+        from: Some(from.clone()),
+        to,
+        cc,
+        subject: reply_subject(original.subject.as_deref().unwrap_or_default()),
+        body_text,
+        in_reply_to: original.message_id.clone(),
+        references,
+        ..Default::default()
+    }
+}
+
+/// Starts a forward of `original`, whose plain text is `text`, with its
+/// `attachments`.
+pub fn forward(
+    original: &Envelope,
+    text: &str,
+    from: &EmailAddr,
+    attachments: Vec<Attachment>,
+) -> Composition {
+    let mut header = String::from("\n\n-------- Forwarded Message --------\n");
+    let mut field = |name: &str, value: String| {
+        if !value.is_empty() {
+            header.push_str(&format!("{}: {}\n", name, value));
+        }
+    };
+    field("Subject", original.subject.clone().unwrap_or_default());
+    field("Date", original.date.to_rfc2822());
+    field("From", address_list(original.from.as_ref()));
+    field("To", address_list(original.to.as_ref()));
+    field("Cc", address_list(original.cc.as_ref()));
+
+    Composition {
+        from: Some(from.clone()),
+        subject: forward_subject(original.subject.as_deref().unwrap_or_default()),
+        body_text: format!("{}\n{}", header, text.trim_end()),
+        attachments,
+        ..Default::default()
+    }
+}
+
+/// `Re: ` followed by the subject, without stacking up prefixes.
+pub fn reply_subject(subject: &str) -> String {
+    let mut rest = subject.trim();
+    while let Some(stripped) = strip_prefix_ignore_case(rest, "re:") {
+        rest = stripped.trim_start();
+    }
+    format!("Re: {}", rest)
+}
+
+/// `Fwd: ` followed by the subject, unless it is a forward already.
+pub fn forward_subject(subject: &str) -> String {
+    let subject = subject.trim();
+    if strip_prefix_ignore_case(subject, "fwd:").is_some()
+        || strip_prefix_ignore_case(subject, "fw:").is_some()
+    {
+        subject.to_string()
+    } else {
+        format!("Fwd: {}", subject)
+    }
+}
+
+/// Quotes every line of `text` with `>`, nesting quotes that are there
+/// already the way `format=flowed` expects.
+pub fn quote(text: &str) -> String {
+    text.trim_end()
+        .lines()
+        .map(|line| {
+            if line.is_empty() || line.starts_with('>') {
+                format!(">{}", line)
+            } else {
+                format!("> {}", line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = text.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix)
+        .then(|| &text[prefix.len()..])
+}
+
+fn addrs(address: Option<&EmailAddress>) -> Vec<EmailAddr> {
+    address
+        .map(|address| address.addrs().into_iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+fn address_list(address: Option<&EmailAddress>) -> String {
+    addrs(address)
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn display_name(addr: &EmailAddr) -> String {
+    addr.name
+        .clone()
+        .or_else(|| addr.email.clone())
+        .unwrap_or_default()
+}
+
+fn same_address(a: &EmailAddr, b: &EmailAddr) -> bool {
+    match (&a.email, &b.email) {
+        (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+        _ => false,
+    }
+}
+
+/// Drops the addresses in `seen` and duplicates, adding the rest to `seen`.
+fn unique(addrs: Vec<EmailAddr>, seen: &mut Vec<EmailAddr>) -> Vec<EmailAddr> {
+    let mut result = Vec::new();
+    for addr in addrs {
+        if addr.email.is_none() || seen.iter().any(|other| same_address(&addr, other)) {
+            continue;
+        }
+        seen.push(addr.clone());
+        result.push(addr);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::ids::{AccountId, FolderId, MessageId};
+
+    fn addr(name: &str, email: &str) -> EmailAddr {
+        EmailAddr {
+            name: Some(name.to_string()),
+            email: Some(email.to_string()),
+        }
+    }
+
+    #[test]
+    fn reply_to_all() {
+        let me = addr("Jan", "jan@example.com");
+        let original = Envelope {
+            id: MessageId::new("1"),
+            account_id: AccountId::new("jan@example.com"),
+            folder_id: FolderId::new("INBOX"),
+            subject: Some("RE: re: Plans".to_string()),
+            from: Some(EmailAddress::List(vec![addr("Eva", "eva@example.com")])),
+            to: Some(EmailAddress::List(vec![
+                addr("Jan", "JAN@example.com"),
+                addr("Ola", "ola@example.com"),
+            ])),
+            cc: Some(EmailAddress::List(vec![addr("Eva", "eva@example.com")])),
+            bcc: None,
+            reply_to: None,
+            date: Utc.with_ymd_and_hms(2024, 5, 3, 9, 30, 0).unwrap(),
+            is_read: true,
+            is_starred: false,
+            is_flagged: false,
+            is_draft: false,
+            is_deleted: false,
+            has_attachments: false,
+            size: None,
+            keywords: Default::default(),
+            labels: Default::default(),
+            snoozed_until: None,
+            thread_id: None,
+            remote_id: None,
+            message_id: Some("b@example.com".to_string()),
+            in_reply_to: vec!["a@example.com".to_string()],
+            references: Vec::new(),
+            unsubscribe: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+
+        let reply = reply(&original, "Let's meet.\n> Earlier\n", &me, true);
+        let emails = |list: &[EmailAddr]| -> Vec<String> {
+            list.iter().filter_map(|addr| addr.email.clone()).collect()
+        };
+        assert_eq!(emails(&reply.to), ["eva@example.com", "ola@example.com"]);
+        assert!(reply.cc.is_empty());
+        assert_eq!(reply.subject, "Re: Plans");
+        assert_eq!(reply.in_reply_to.as_deref(), Some("b@example.com"));
+        assert_eq!(reply.references, ["a@example.com", "b@example.com"]);
+        assert_eq!(
+            reply.body_text,
+            "\n\nOn Fri, 3 May 2024 09:30, Eva wrote:\n> Let's meet.\n>> Earlier"
+        );
+
+        let forward = forward(&original, "Let's meet.", &me, Vec::new());
+        assert_eq!(forward.subject, "Fwd: RE: re: Plans");
+        assert!(forward.to.is_empty());
+        assert!(forward.body_text.contains("From: Eva <eva@example.com>\n"));
+    }
+}
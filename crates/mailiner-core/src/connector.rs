@@ -1,13 +1,18 @@
 use std::fmt::Debug;
 use std::ops::Range;
+use std::time::Duration;
 
 use async_trait::async_trait;
+#[cfg(feature = "mock")]
 use chrono::Utc;
 use tokio::io::{AsyncRead, AsyncWrite};
 
 use crate::error::Result;
 use crate::ids::{AccountId, FolderId, MessageId, MessagePartId};
-use crate::models::{Account, Envelope, Folder, MessagePart};
+use crate::models::{
+    Account, Envelope, FlagUpdate, Folder, FolderDelta, FolderEvent, FolderSyncState, Label,
+    MessagePart, MessageStructure, SearchQuery,
+};
 
 #[async_trait]
 pub trait EmailConnector<S>: Send + Sync 
@@ -29,36 +34,138 @@ where
         parent_id: Option<&FolderId>,
     ) -> Result<Folder>;
     async fn delete_folder(&self, folder_id: &FolderId) -> Result<()>;
+    /// Renames a folder, keeping it under the same parent. Returns the new id.
+    async fn rename_folder(&self, folder_id: &FolderId, new_name: &str) -> Result<FolderId>;
 
     // Envelope operations
     async fn list_envelopes(&self, folder_id: &FolderId) -> Result<Vec<Envelope>>;
+    /// Returns a window of a folder's envelopes, newest first. `range` counts
+    /// from the newest message, so `0..50` is the first page of a message list.
     async fn list_envelopes_range(&self, folder_id: &FolderId, range: Range<usize>) -> Result<Vec<Envelope>>;
     async fn get_envelope(&self, message_id: &MessageId) -> Result<Envelope>;
+    /// Returns the changes in a folder since `state`, or all envelopes when
+    /// `state` is `None` or no longer valid. `known` are the ids of the cached
+    /// envelopes, used to detect removed messages when the server can't
+    /// report them.
+    async fn sync_folder(
+        &self,
+        folder_id: &FolderId,
+        state: Option<&FolderSyncState>,
+        known: &[MessageId],
+    ) -> Result<FolderDelta>;
+    /// Returns the flags of the messages whose flags changed since
+    /// `since_modseq`, or of all messages if the server can't tell.
+    async fn resync_flags(&self, folder_id: &FolderId, since_modseq: u64) -> Result<Vec<FlagUpdate>>;
     async fn update_envelope_flags(
         &self,
         message_id: &MessageId,
         flags: &[(&str, bool)],
     ) -> Result<()>;
+    /// Sets or removes the same flags on several messages, e.g. to mark a
+    /// folder as read. By default the messages are updated one by one;
+    /// servers that can update many at once override it.
+    async fn bulk_update_envelope_flags(
+        &self,
+        message_ids: &[MessageId],
+        flags: &[(&str, bool)],
+    ) -> Result<()> {
+        for message_id in message_ids {
+            self.update_envelope_flags(message_id, flags).await?;
+        }
+        Ok(())
+    }
+    /// Sets or removes keywords (user-defined flags) of a message.
+    async fn update_envelope_keywords(
+        &self,
+        message_id: &MessageId,
+        keywords: &[(&str, bool)],
+    ) -> Result<()>;
+    /// Lists the labels of an account. Servers whose labels are just the
+    /// keywords in use, e.g. most IMAP servers, have none to list.
+    async fn list_labels(&self, _account_id: &AccountId) -> Result<Vec<Label>> {
+        Ok(Vec::new())
+    }
+    /// Puts labels on or takes them off a message, by name. Labels are
+    /// keywords unless the server has labels of its own.
+    async fn update_envelope_labels(
+        &self,
+        message_id: &MessageId,
+        labels: &[(&str, bool)],
+    ) -> Result<()> {
+        self.update_envelope_keywords(message_id, labels).await
+    }
+    /// Moves a message to another folder, returning its id in the target
+    /// folder if the server reports it.
+    async fn move_message(&self, message_id: &MessageId, target_folder_id: &FolderId) -> Result<Option<MessageId>>;
+    /// Copies a message to another folder, returning the id of the copy if
+    /// the server reports it.
+    async fn copy_message(&self, message_id: &MessageId, target_folder_id: &FolderId) -> Result<Option<MessageId>>;
+    /// Marks a message as junk or not, and moves it to `target_folder_id`:
+    /// into the Junk folder or out of it. By default this sets the `$Junk`
+    /// or `$NotJunk` keyword; servers that learn from spam reports their own
+    /// way override it. Returns the new id as
+    /// [`move_message`](Self::move_message) does.
+    async fn mark_junk(&self, message_id: &MessageId, junk: bool, target_folder_id: &FolderId) -> Result<Option<MessageId>> {
+        self.update_envelope_keywords(message_id, &[("$Junk", junk), ("$NotJunk", !junk)]).await?;
+        self.move_message(message_id, target_folder_id).await
+    }
+    /// Adds a message to a folder, e.g. a draft or a copy of a sent message.
+    /// `flags` are IMAP system flags like `\Draft`. Returns the id of the new
+    /// message if the server reports it.
+    async fn append_message(&self, folder_id: &FolderId, flags: &[&str], content: &[u8]) -> Result<Option<MessageId>>;
+    /// Removes a message from its folder permanently.
+    async fn delete_message(&self, message_id: &MessageId) -> Result<()>;
+    /// Searches a folder on the server, returning the ids of matching messages.
+    async fn search(&self, folder_id: &FolderId, query: &SearchQuery) -> Result<Vec<MessageId>>;
 
     // Message part operations
+    /// Returns the MIME tree of a message, so that only the parts needed can
+    /// be fetched.
+    async fn get_message_structure(&self, message_id: &MessageId) -> Result<MessageStructure>;
+    /// Returns the whole message as it is on the server, e.g. to save it as
+    /// an `.eml` file.
+    async fn fetch_raw_message(&self, message_id: &MessageId) -> Result<Vec<u8>>;
     async fn get_message_part(
         &self,
         message_id: &MessageId,
         part_id: &MessagePartId,
     ) -> Result<MessagePart>;
+    /// Like `get_message_part`, calling `progress` with the number of bytes
+    /// downloaded so far and the total size of the part, if known.
+    async fn get_message_part_with_progress(
+        &self,
+        message_id: &MessageId,
+        part_id: &MessagePartId,
+        progress: &(dyn Fn(u64, Option<u64>) + Send + Sync),
+    ) -> Result<MessagePart> {
+        let part = self.get_message_part(message_id, part_id).await?;
+        progress(part.size, Some(part.size));
+        Ok(part)
+    }
+
+    // Push notifications
+    /// Waits until the server reports changes in the folder or until `timeout`
+    /// elapses, in which case no events are returned.
+    ///
+    /// The connection is busy while waiting, so watching a folder is best done
+    /// on a connector dedicated to it.
+    async fn idle(&self, folder_id: &FolderId, timeout: Duration) -> Result<Vec<FolderEvent>>;
 }
 
 // Mock implementation for testing
+#[cfg(feature = "mock")]
 pub struct MockConnector {
     connected: bool,
 }
 
+#[cfg(feature = "mock")]
 impl MockConnector {
     pub fn new() -> Self {
         Self { connected: false }
     }
 }
 
+#[cfg(feature = "mock")]
 #[async_trait]
 impl<S> EmailConnector<S> for MockConnector 
 where
@@ -89,6 +196,7 @@ where
                 account_id: account_id.clone(),
                 name: "Inbox".to_string(),
                 parent_id: None,
+                rights: None,
                 created_at: Utc::now(),
                 updated_at: Utc::now(),
             },
@@ -97,6 +205,7 @@ where
                 account_id: account_id.clone(),
                 name: "Sent".to_string(),
                 parent_id: None,
+                rights: None,
                 created_at: Utc::now(),
                 updated_at: Utc::now(),
             },
@@ -114,6 +223,7 @@ where
             account_id: account_id.clone(),
             name: name.to_string(),
             parent_id: parent_id.cloned(),
+            rights: None,
             created_at: Utc::now(),
             updated_at: Utc::now(),
         })
@@ -123,6 +233,10 @@ where
         Ok(())
     }
 
+    async fn rename_folder(&self, _folder_id: &FolderId, new_name: &str) -> Result<FolderId> {
+        Ok(FolderId::new(format!("folder-{}", new_name.to_lowercase())))
+    }
+
     async fn list_envelopes(&self, folder_id: &FolderId) -> Result<Vec<Envelope>> {
         // Call the range version with default range
         let mut envelopes = Vec::new();
@@ -147,6 +261,7 @@ where
                 ])),
                 cc: None,
                 bcc: None,
+                reply_to: None,
                 date: Utc::now(),
                 is_read: i % 3 == 0,
                 is_starred: i % 5 == 0,
@@ -154,6 +269,16 @@ where
                 is_draft: false,
                 is_deleted: false,
                 has_attachments: i % 2 == 0,
+                size: None,
+                keywords: Default::default(),
+                labels: Default::default(),
+                snoozed_until: None,
+                thread_id: None,
+                remote_id: None,
+                message_id: None,
+                in_reply_to: Vec::new(),
+                references: Vec::new(),
+                unsubscribe: None,
                 created_at: Utc::now(),
                 updated_at: Utc::now(),
             });
@@ -184,6 +309,7 @@ where
                 ])),
                 cc: None,
                 bcc: None,
+                reply_to: None,
                 date: Utc::now(),
                 is_read: i % 3 == 0,
                 is_starred: i % 5 == 0,
@@ -191,6 +317,16 @@ where
                 is_draft: false,
                 is_deleted: false,
                 has_attachments: i % 2 == 0,
+                size: None,
+                keywords: Default::default(),
+                labels: Default::default(),
+                snoozed_until: None,
+                thread_id: None,
+                remote_id: None,
+                message_id: None,
+                in_reply_to: Vec::new(),
+                references: Vec::new(),
+                unsubscribe: None,
                 created_at: Utc::now(),
                 updated_at: Utc::now(),
             });
@@ -198,6 +334,26 @@ where
         Ok(envelopes)
     }
 
+    async fn sync_folder(
+        &self,
+        folder_id: &FolderId,
+        _state: Option<&FolderSyncState>,
+        known: &[MessageId],
+    ) -> Result<FolderDelta> {
+        let changed = <Self as EmailConnector<S>>::list_envelopes(self, folder_id).await?;
+        let vanished = known
+            .iter()
+            .filter(|id| !changed.iter().any(|e| e.id == **id))
+            .cloned()
+            .collect();
+        Ok(FolderDelta {
+            sync_state: None,
+            changed,
+            vanished,
+            read_only: false,
+        })
+    }
+
     async fn get_envelope(&self, message_id: &MessageId) -> Result<Envelope> {
         Ok(Envelope {
             id: message_id.clone(),
@@ -218,6 +374,7 @@ where
             ])),
             cc: None,
             bcc: None,
+            reply_to: None,
             date: Utc::now(),
             is_read: false,
             is_starred: false,
@@ -225,11 +382,25 @@ where
             is_draft: false,
             is_deleted: false,
             has_attachments: true,
+            size: None,
+            keywords: Default::default(),
+            labels: Default::default(),
+            snoozed_until: None,
+            thread_id: None,
+            remote_id: None,
+            message_id: Some("test-message@example.com".to_string()),
+            in_reply_to: Vec::new(),
+            references: Vec::new(),
+            unsubscribe: None,
             created_at: Utc::now(),
             updated_at: Utc::now(),
         })
     }
 
+    async fn resync_flags(&self, _folder_id: &FolderId, _since_modseq: u64) -> Result<Vec<FlagUpdate>> {
+        Ok(Vec::new())
+    }
+
     async fn update_envelope_flags(
         &self,
         _message_id: &MessageId,
@@ -238,6 +409,59 @@ where
         Ok(())
     }
 
+    async fn update_envelope_keywords(
+        &self,
+        _message_id: &MessageId,
+        _keywords: &[(&str, bool)],
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn move_message(&self, _message_id: &MessageId, _target_folder_id: &FolderId) -> Result<Option<MessageId>> {
+        Ok(None)
+    }
+
+    async fn copy_message(&self, _message_id: &MessageId, _target_folder_id: &FolderId) -> Result<Option<MessageId>> {
+        Ok(None)
+    }
+
+    async fn append_message(&self, _folder_id: &FolderId, _flags: &[&str], _content: &[u8]) -> Result<Option<MessageId>> {
+        Ok(None)
+    }
+
+    async fn delete_message(&self, _message_id: &MessageId) -> Result<()> {
+        Ok(())
+    }
+
+    async fn search(&self, folder_id: &FolderId, query: &SearchQuery) -> Result<Vec<MessageId>> {
+        let envelopes = <Self as EmailConnector<S>>::list_envelopes(self, folder_id).await?;
+        Ok(envelopes
+            .into_iter()
+            .filter(|envelope| query.matches(envelope))
+            .map(|envelope| envelope.id)
+            .collect())
+    }
+
+    async fn get_message_structure(&self, _message_id: &MessageId) -> Result<MessageStructure> {
+        Ok(MessageStructure {
+            id: Some(MessagePartId::new("1")),
+            content_type: "text/plain".to_string(),
+            filename: None,
+            size: Some(100),
+            is_attachment: false,
+            content_id: None,
+            children: Vec::new(),
+        })
+    }
+
+    async fn fetch_raw_message(&self, message_id: &MessageId) -> Result<Vec<u8>> {
+        Ok(format!(
+            "Message-ID: <{}@mock>\r\nSubject: Test\r\nContent-Type: text/plain\r\n\r\nThis is a test message.\r\n",
+            message_id
+        )
+        .into_bytes())
+    }
+
     async fn get_message_part(
         &self,
         _message_id: &MessageId,
@@ -255,4 +479,9 @@ where
             updated_at: Utc::now(),
         })
     }
+
+    async fn idle(&self, _folder_id: &FolderId, timeout: Duration) -> Result<Vec<FolderEvent>> {
+        tokio::time::sleep(timeout).await;
+        Ok(Vec::new())
+    }
 }
@@ -0,0 +1,325 @@
+//! The people the user exchanges mail with, collected from sent and received
+//! messages for completing addresses in the composer.
+//!
+//! Contacts are ranked by frecency: every message adds to the score of its
+//! contacts, and the score halves every [`HALF_LIFE_DAYS`], so that people
+//! written to often and lately come first.
+
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::ids::ContactId;
+use crate::models::{EmailAddr, EmailAddress, Envelope};
+use crate::search::tokenize;
+
+/// What a message the user sent adds to the score of its recipients.
+pub const SENT_WEIGHT: f64 = 1.0;
+/// What a received message adds to the score of its sender. Less than a sent
+/// one, as anybody can write to the user.
+pub const RECEIVED_WEIGHT: f64 = 0.25;
+pub const HALF_LIFE_DAYS: f64 = 30.0;
+
+/// An address seen in a message: when, and what it adds to the score.
+pub type Sighting = (EmailAddr, DateTime<Utc>, f64);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Contact {
+    pub id: ContactId,
+    /// The display names seen for the contact, the first one is shown.
+    pub names: Vec<String>,
+    /// The addresses of the contact, lowercase.
+    pub addresses: Vec<String>,
+    pub last_contacted: DateTime<Utc>,
+    /// The frecency score as of `last_contacted`, see [`Contact::frecency`].
+    pub score: f64,
+}
+
+impl Contact {
+    pub fn new(address: &str, at: DateTime<Utc>) -> Self {
+        Self {
+            id: ContactId::new(uuid::Uuid::new_v4().to_string()),
+            names: Vec::new(),
+            addresses: vec![address.to_lowercase()],
+            last_contacted: at,
+            score: 0.0,
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.names.first().map(String::as_str)
+    }
+
+    /// The name and first address of the contact, to write to.
+    pub fn addr(&self) -> EmailAddr {
+        EmailAddr {
+            name: self.name().map(str::to_string),
+            email: self.addresses.first().cloned(),
+        }
+    }
+
+    /// Counts a message exchanged with the contact at `at`, which may be
+    /// older than the last one when old mail is synced.
+    pub fn record(&mut self, name: Option<&str>, at: DateTime<Utc>, weight: f64) {
+        if at >= self.last_contacted {
+            self.score = self.score * decay(self.last_contacted, at) + weight;
+            self.last_contacted = at;
+        } else {
+            self.score += weight * decay(at, self.last_contacted);
+        }
+        if let Some(name) = name.map(str::trim).filter(|name| !name.is_empty()) {
+            if !self.names.iter().any(|known| known == name) {
+                self.names.push(name.to_string());
+            }
+        }
+    }
+
+    /// Takes over the names, addresses and score of `other`, which is the
+    /// same person.
+    pub fn merge(&mut self, other: Contact) {
+        let at = self.last_contacted.max(other.last_contacted);
+        self.score = self.frecency(at) + other.frecency(at);
+        self.last_contacted = at;
+        for name in other.names {
+            if !self.names.contains(&name) {
+                self.names.push(name);
+            }
+        }
+        for address in other.addresses {
+            if !self.addresses.contains(&address) {
+                self.addresses.push(address);
+            }
+        }
+    }
+
+    /// The score of the contact at `now`.
+    pub fn frecency(&self, now: DateTime<Utc>) -> f64 {
+        self.score * decay(self.last_contacted, now)
+    }
+
+    /// Whether one of the names or addresses, or a word of them, starts with
+    /// `prefix`, ignoring case.
+    pub fn matches_prefix(&self, prefix: &str) -> bool {
+        let prefix = prefix.trim().to_lowercase();
+        self.addresses.iter().chain(&self.names).any(|text| {
+            text.to_lowercase().starts_with(&prefix)
+                || tokenize(text).any(|word| word.starts_with(&prefix))
+        })
+    }
+}
+
+/// How much of a score is left from `from` to `to`.
+fn decay(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    let days = (to - from).num_seconds().max(0) as f64 / 86_400.0;
+    0.5f64.powf(days / HALF_LIFE_DAYS)
+}
+
+/// The addresses in `envelopes`: the senders of received messages, and the
+/// recipients of the ones sent from `own`.
+pub fn sightings(envelopes: &[Envelope], own: Option<&str>) -> Vec<Sighting> {
+    let mut seen = Vec::new();
+    for envelope in envelopes {
+        let senders = envelope
+            .from
+            .as_ref()
+            .map(EmailAddress::addrs)
+            .unwrap_or_default();
+        let sent = senders.iter().any(|addr| is_own(addr, own));
+        if sent {
+            let recipients = [&envelope.to, &envelope.cc, &envelope.bcc]
+                .into_iter()
+                .flatten()
+                .flat_map(EmailAddress::addrs);
+            seen.extend(recipients.map(|addr| (addr.clone(), envelope.date, SENT_WEIGHT)));
+        } else {
+            seen.extend(
+                senders
+                    .into_iter()
+                    .map(|addr| (addr.clone(), envelope.date, RECEIVED_WEIGHT)),
+            );
+        }
+    }
+    seen.retain(|(addr, _, _)| !is_own(addr, own));
+    seen
+}
+
+fn is_own(addr: &EmailAddr, own: Option<&str>) -> bool {
+    matches!((addr.email.as_deref(), own), (Some(email), Some(own)) if email.eq_ignore_ascii_case(own))
+}
+
+/// Adds what was `seen` to `contacts`, creating ones for new addresses.
+/// Returns the positions of the contacts that changed, to save them.
+pub fn record(contacts: &mut Vec<Contact>, seen: Vec<Sighting>) -> BTreeSet<usize> {
+    let mut by_address: HashMap<String, usize> = contacts
+        .iter()
+        .enumerate()
+        .flat_map(|(i, contact)| {
+            contact
+                .addresses
+                .iter()
+                .map(move |address| (address.clone(), i))
+        })
+        .collect();
+    let mut changed = BTreeSet::new();
+    for (addr, at, weight) in seen {
+        let Some(address) = addr.email.filter(|email| email.contains('@')) else {
+            continue;
+        };
+        let i = *by_address.entry(address.to_lowercase()).or_insert_with(|| {
+            contacts.push(Contact::new(&address, at));
+            contacts.len() - 1
+        });
+        contacts[i].record(addr.name.as_deref(), at, weight);
+        changed.insert(i);
+    }
+    changed
+}
+
+/// Groups of `contacts` that are likely the same person, because they go
+/// by the same name, ignoring case. Each group lists the positions of its
+/// contacts in `contacts`.
+pub fn duplicates(contacts: &[Contact]) -> Vec<Vec<usize>> {
+    let mut by_name: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, contact) in contacts.iter().enumerate() {
+        let names: BTreeSet<String> = contact.names.iter().map(|name| name.to_lowercase()).collect();
+        for name in names {
+            by_name.entry(name).or_default().push(i);
+        }
+    }
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for mut group in by_name.into_values().filter(|group| group.len() > 1) {
+        // Contacts with several names can be in several groups, which then
+        // are one.
+        let (overlapping, mut rest): (Vec<_>, Vec<_>) = groups
+            .into_iter()
+            .partition(|known| group.iter().any(|i| known.contains(i)));
+        group.extend(overlapping.into_iter().flatten());
+        group.sort_unstable();
+        group.dedup();
+        rest.push(group);
+        groups = rest;
+    }
+    groups.sort();
+    groups
+}
+
+/// Parses an address typed by the user, `jan@example.com` or
+/// `Jan Novák <jan@example.com>`, if its syntax is valid.
+pub fn parse_address(text: &str) -> Option<EmailAddr> {
+    let text = text.trim();
+    let (name, email) = match text.strip_suffix('>').and_then(|rest| rest.rsplit_once('<')) {
+        Some((name, email)) => {
+            let name = name.trim().trim_matches('"').trim();
+            ((!name.is_empty()).then(|| name.to_string()), email.trim())
+        }
+        None => (None, text),
+    };
+    is_valid_address(email).then(|| EmailAddr { name, email: Some(email.to_string()) })
+}
+
+/// Whether `email` is a `local@domain` address with a domain name of at
+/// least two labels. Quoted local parts aren't accepted.
+fn is_valid_address(email: &str) -> bool {
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return false;
+    };
+    let local_ok = !local.is_empty()
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..")
+        && local
+            .chars()
+            .all(|c| c.is_alphanumeric() || "!#$%&'*+-/=?^_`{|}~.".contains(c));
+    let labels: Vec<&str> = domain.split('.').collect();
+    let domain_ok = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        });
+    local_ok && domain_ok
+}
+
+/// The `contacts` matching `prefix`, best first, at most `limit` of them.
+pub fn search(contacts: Vec<Contact>, prefix: &str, limit: usize) -> Vec<Contact> {
+    let now = Utc::now();
+    let mut found: Vec<(f64, Contact)> = contacts
+        .into_iter()
+        .filter(|contact| contact.matches_prefix(prefix))
+        .map(|contact| (contact.frecency(now), contact))
+        .collect();
+    found.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    found
+        .into_iter()
+        .take(limit)
+        .map(|(_, contact)| contact)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+
+    #[test]
+    fn ranks_by_frecency() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 30, 12, 0, 0).unwrap();
+        let mut ana = Contact::new("Ana@Example.com", now);
+        ana.record(Some("Ana Lima"), now - Duration::days(30), SENT_WEIGHT);
+        ana.record(Some("Ana Lima"), now, SENT_WEIGHT);
+        assert_eq!(ana.addresses, ["ana@example.com"]);
+        assert_eq!(ana.names, ["Ana Lima"]);
+        assert!((ana.frecency(now) - 1.5).abs() < 1e-9);
+        assert!((ana.frecency(now + Duration::days(30)) - 0.75).abs() < 1e-9);
+
+        assert!(ana.matches_prefix("an"));
+        assert!(ana.matches_prefix("LIM"));
+        assert!(ana.matches_prefix("ana l"));
+        assert!(!ana.matches_prefix("bo"));
+
+        let mut anton = Contact::new("anton@example.com", now);
+        anton.record(None, now, RECEIVED_WEIGHT);
+        let found = search(vec![anton.clone(), ana.clone()], "an", 10);
+        assert_eq!(found, [ana.clone(), anton]);
+        assert!(search(vec![ana], "an", 0).is_empty());
+    }
+
+    #[test]
+    fn merges_duplicates() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 30, 12, 0, 0).unwrap();
+        let mut work = Contact::new("ana.lima@work.example", now - Duration::days(30));
+        work.record(Some("Ana Lima"), now - Duration::days(30), SENT_WEIGHT);
+        let mut home = Contact::new("ana@home.example", now);
+        home.record(Some("ana lima"), now, SENT_WEIGHT);
+        home.record(Some("Ana"), now, RECEIVED_WEIGHT);
+        let mut bob = Contact::new("bob@example.com", now);
+        bob.record(Some("Bob"), now, SENT_WEIGHT);
+        let mut ana = Contact::new("ana@example.org", now);
+        ana.record(Some("ANA"), now, RECEIVED_WEIGHT);
+
+        let contacts = [work.clone(), bob, home.clone(), ana];
+        assert_eq!(duplicates(&contacts), vec![vec![0, 2, 3]]);
+
+        work.merge(home);
+        assert_eq!(work.addresses, ["ana.lima@work.example", "ana@home.example"]);
+        assert_eq!(work.names, ["Ana Lima", "ana lima", "Ana"]);
+        assert_eq!(work.last_contacted, now);
+        assert!((work.frecency(now) - 1.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parses_typed_addresses() {
+        let addr = parse_address(" \"Jan Novák\" <jan.novak@example.cz> ").unwrap();
+        assert_eq!(addr.name.as_deref(), Some("Jan Novák"));
+        assert_eq!(addr.email.as_deref(), Some("jan.novak@example.cz"));
+        let addr = parse_address("ana+news@mail.example.com").unwrap();
+        assert_eq!((addr.name, addr.email.as_deref()), (None, Some("ana+news@mail.example.com")));
+
+        for invalid in ["ana", "ana@localhost", "@example.com", "ana..b@example.com", "ana@-x.com", "a b@example.com", "Jan <jan>"] {
+            assert!(parse_address(invalid).is_none(), "{}", invalid);
+        }
+    }
+}
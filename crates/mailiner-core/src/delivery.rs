@@ -0,0 +1,198 @@
+//! Delivery status notifications (RFC 3464), e.g. the bounce a server sends
+//! back when a message couldn't be delivered.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{MailinerError, Result};
+use crate::ids::{AccountId, MessageId};
+use crate::models::Envelope;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryAction {
+    Failed,
+    Delayed,
+    Delivered,
+    Relayed,
+    Expanded,
+}
+
+impl DeliveryAction {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "failed" => Some(Self::Failed),
+            "delayed" => Some(Self::Delayed),
+            "delivered" => Some(Self::Delivered),
+            "relayed" => Some(Self::Relayed),
+            "expanded" => Some(Self::Expanded),
+            _ => None,
+        }
+    }
+}
+
+/// What happened to the message for one recipient.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecipientStatus {
+    pub recipient: String,
+    pub action: DeliveryAction,
+    /// The enhanced status code, e.g. `5.1.1` for an unknown mailbox.
+    pub status: String,
+    /// What the remote server said, e.g. `550 5.1.1 No such user`.
+    pub diagnostic: Option<String>,
+}
+
+/// A delivery status notification, linked to the message it reports on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeliveryReport {
+    /// The notification itself.
+    pub id: MessageId,
+    pub account_id: AccountId,
+    /// The Message-ID of the message that was sent, if the notification
+    /// includes its headers.
+    pub original_message_id: Option<String>,
+    pub recipients: Vec<RecipientStatus>,
+    pub received_at: DateTime<Utc>,
+}
+
+impl DeliveryReport {
+    /// The recipients the message couldn't be delivered to.
+    pub fn failed(&self) -> impl Iterator<Item = &RecipientStatus> {
+        self.recipients
+            .iter()
+            .filter(|recipient| recipient.action == DeliveryAction::Failed)
+    }
+}
+
+/// Parses the `message/delivery-status` part of a notification: a block of
+/// fields about the message, followed by a block per recipient.
+pub fn parse_delivery_status(text: &str) -> Result<Vec<RecipientStatus>> {
+    let normalized = text.replace("\r\n", "\n");
+    let mut recipients = Vec::new();
+    for block in normalized.split("\n\n").skip(1) {
+        let recipient = header_value(block, "Final-Recipient")
+            .or_else(|| header_value(block, "Original-Recipient"));
+        let action =
+            header_value(block, "Action").and_then(|action| DeliveryAction::parse(&action));
+        let (Some(recipient), Some(action)) = (recipient, action) else {
+            continue;
+        };
+        recipients.push(RecipientStatus {
+            recipient: typed_value(&recipient),
+            action,
+            status: header_value(block, "Status").unwrap_or_default(),
+            diagnostic: header_value(block, "Diagnostic-Code").map(|code| typed_value(&code)),
+        });
+    }
+    if recipients.is_empty() {
+        return Err(MailinerError::InvalidData(
+            "The delivery status names no recipients".to_string(),
+        ));
+    }
+    Ok(recipients)
+}
+
+/// Returns the unfolded value of the first header field `name` in
+/// `headers`, which ends at the first empty line.
+pub fn header_value(headers: &str, name: &str) -> Option<String> {
+    let mut value: Option<String> = None;
+    for line in headers.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some(value) = &mut value {
+                if !value.is_empty() {
+                    value.push(' ');
+                }
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        if value.is_some() {
+            break;
+        }
+        if let Some((field, rest)) = line.split_once(':') {
+            if field.trim().eq_ignore_ascii_case(name) {
+                value = Some(rest.trim().to_string());
+            }
+        }
+    }
+    value
+}
+
+/// Whether a message is likely a delivery status notification and worth
+/// fetching the structure of. Notifications come from the mail system, not
+/// from a person.
+pub fn looks_like_report(envelope: &Envelope) -> bool {
+    let from_mail_system = envelope.from.as_ref().is_some_and(|from| {
+        from.addrs().iter().any(|addr| {
+            let local = addr
+                .email
+                .as_deref()
+                .and_then(|email| email.split('@').next())
+                .unwrap_or_default();
+            local.eq_ignore_ascii_case("mailer-daemon") || local.eq_ignore_ascii_case("postmaster")
+        })
+    });
+    let subject = envelope
+        .subject
+        .as_deref()
+        .unwrap_or_default()
+        .to_lowercase();
+    from_mail_system
+        || [
+            "undeliver",
+            "delivery status notification",
+            "returned mail",
+            "delivery failure",
+        ]
+        .iter()
+        .any(|phrase| subject.contains(phrase))
+}
+
+/// Drops the address type of a field like `rfc822; jan@example.com`.
+fn typed_value(value: &str) -> String {
+    value
+        .split_once(';')
+        .map_or(value, |(_, value)| value)
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounce() {
+        // From RFC 3464, appendix A.
+        let status = "Reporting-MTA: dns; cs.utk.edu\r\n\
+            \r\n\
+            Original-Recipient: rfc822;louisl@larry.slip.umd.edu\r\n\
+            Final-Recipient: rfc822;louisl@larry.slip.umd.edu\r\n\
+            Action: failed\r\n\
+            Status: 4.0.0\r\n\
+            Diagnostic-Code: smtp; 426 connection timed out\r\n\
+            Last-Attempt-Date: Thu, 7 Jul 1994 17:15:49 -0400\r\n";
+        let recipients = parse_delivery_status(status).unwrap();
+        assert_eq!(
+            recipients,
+            [RecipientStatus {
+                recipient: "louisl@larry.slip.umd.edu".to_string(),
+                action: DeliveryAction::Failed,
+                status: "4.0.0".to_string(),
+                diagnostic: Some("426 connection timed out".to_string()),
+            }]
+        );
+        assert!(parse_delivery_status("Reporting-MTA: dns; cs.utk.edu\r\n").is_err());
+
+        let headers =
+            "From: Jan <jan@example.com>\r\nMessage-ID:\r\n <1234@example.com>\r\n\r\nBody: no";
+        assert_eq!(
+            header_value(headers, "message-id").as_deref(),
+            Some("<1234@example.com>")
+        );
+        assert_eq!(header_value(headers, "Body"), None);
+    }
+}
@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::models::CertificateInfo;
+
 #[derive(Error, Debug)]
 pub enum MailinerError {
     #[error("Storage error: {0}")]
@@ -8,6 +10,27 @@ pub enum MailinerError {
     #[error("Connector error: {0}")]
     Connector(String),
     
+    /// The server refused the request for good, e.g. a recipient that
+    /// doesn't exist. Unlike with `Connector`, retrying won't help.
+    #[error("Rejected: {0}")]
+    Rejected(String),
+    
+    /// The server didn't accept the credentials.
+    #[error("Authentication failed: {0}")]
+    Authentication(String),
+    
+    /// The server asks to slow down. Retrying later helps.
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+    
+    /// The TLS handshake failed, for another reason than an untrusted
+    /// certificate.
+    #[error("TLS error: {0}")]
+    Tls(String),
+    
+    #[error("Untrusted certificate {}: {}", .0.fingerprint, .0.reason)]
+    UntrustedCertificate(CertificateInfo),
+    
     #[error("Invalid data: {0}")]
     InvalidData(String),
     
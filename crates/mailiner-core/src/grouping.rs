@@ -0,0 +1,114 @@
+//! Sections of message lists by date: "Today", "Yesterday", "This week" and
+//! then one per month.
+
+use std::fmt;
+use std::ops::Range;
+
+use chrono::{DateTime, Datelike, Days, TimeZone, Utc};
+
+/// The section of a message list a message is listed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DateGroup {
+    Today,
+    Yesterday,
+    /// Earlier in the week, which starts on Monday.
+    ThisWeek,
+    Month { year: i32, month: u32 },
+}
+
+impl DateGroup {
+    /// The group of a message sent at `date`, in the time zone of `now`.
+    /// Messages from the future, i.e. with a wrong clock, count as today.
+    pub fn of<Tz: TimeZone>(date: DateTime<Utc>, now: &DateTime<Tz>) -> Self {
+        let day = date.with_timezone(&now.timezone()).date_naive();
+        let today = now.date_naive();
+        let week_start = today - Days::new(today.weekday().num_days_from_monday() as u64);
+        if day >= today {
+            DateGroup::Today
+        } else if Some(day) == today.pred_opt() {
+            DateGroup::Yesterday
+        } else if day >= week_start {
+            DateGroup::ThisWeek
+        } else {
+            DateGroup::Month { year: day.year(), month: day.month() }
+        }
+    }
+}
+
+impl fmt::Display for DateGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MONTHS: [&str; 12] = [
+            "January", "February", "March", "April", "May", "June", "July", "August", "September",
+            "October", "November", "December",
+        ];
+        match self {
+            DateGroup::Today => write!(f, "Today"),
+            DateGroup::Yesterday => write!(f, "Yesterday"),
+            DateGroup::ThisWeek => write!(f, "This week"),
+            DateGroup::Month { year, month } => {
+                write!(f, "{} {}", MONTHS[(*month as usize).saturating_sub(1) % 12], year)
+            }
+        }
+    }
+}
+
+/// The sections of a list of messages sorted by date, given the group of
+/// each: a new section starts where the group changes.
+///
+/// Since the group of a message only depends on its own date, the sections
+/// stay the same as more pages of the list are loaded; the first new
+/// message only extends the last section if it belongs to it.
+pub fn sections(groups: impl IntoIterator<Item = DateGroup>) -> Vec<(DateGroup, Range<usize>)> {
+    let mut sections: Vec<(DateGroup, Range<usize>)> = Vec::new();
+    for (i, group) in groups.into_iter().enumerate() {
+        match sections.last_mut() {
+            Some((last, range)) if *last == group => range.end = i + 1,
+            _ => sections.push((group, i..i + 1)),
+        }
+    }
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::FixedOffset;
+
+    use super::*;
+
+    #[test]
+    fn date_groups() {
+        // A Thursday, just after midnight in Prague but still Wednesday in UTC.
+        let now = FixedOffset::east_opt(2 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2026, 10, 15, 0, 30, 0)
+            .unwrap();
+        let at = |day, hour| Utc.with_ymd_and_hms(2026, 10, day, hour, 0, 0).unwrap();
+
+        assert_eq!(DateGroup::of(at(14, 23), &now), DateGroup::Today);
+        assert_eq!(DateGroup::of(at(20, 8), &now), DateGroup::Today);
+        assert_eq!(DateGroup::of(at(14, 21), &now), DateGroup::Yesterday);
+        assert_eq!(DateGroup::of(at(12, 8), &now), DateGroup::ThisWeek);
+        assert_eq!(DateGroup::of(at(11, 8), &now), DateGroup::Month { year: 2026, month: 10 });
+        assert_eq!(
+            DateGroup::of(Utc.with_ymd_and_hms(2025, 12, 31, 8, 0, 0).unwrap(), &now).to_string(),
+            "December 2025"
+        );
+    }
+
+    #[test]
+    fn sections_across_pages() {
+        let september = DateGroup::Month { year: 2026, month: 9 };
+        let first_page = [DateGroup::Today, DateGroup::Today, DateGroup::ThisWeek];
+        assert_eq!(
+            sections(first_page),
+            vec![(DateGroup::Today, 0..2), (DateGroup::ThisWeek, 2..3)]
+        );
+
+        let both_pages = first_page.into_iter().chain([DateGroup::ThisWeek, september]);
+        assert_eq!(
+            sections(both_pages),
+            vec![(DateGroup::Today, 0..2), (DateGroup::ThisWeek, 2..4), (september, 4..5)]
+        );
+        assert_eq!(sections([]), vec![]);
+    }
+}
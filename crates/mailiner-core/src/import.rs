@@ -0,0 +1,4 @@
+//! Importers for data from other email clients.
+
+pub mod mbox;
+pub mod thunderbird;
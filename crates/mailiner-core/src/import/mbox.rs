@@ -0,0 +1,48 @@
+/// Splits the contents of an mbox file into individual RFC 822 messages.
+///
+/// Messages are separated by lines starting with `From `. Lines escaped as
+/// `>From ` (mboxrd) are unescaped.
+pub fn split_messages(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    let mut current: Option<Vec<u8>> = None;
+
+    for line in data.split_inclusive(|b| *b == b'\n') {
+        if line.starts_with(b"From ") {
+            if let Some(message) = current.take() {
+                messages.push(message);
+            }
+            current = Some(Vec::new());
+            continue;
+        }
+
+        let Some(message) = current.as_mut() else {
+            // Garbage before the first separator.
+            continue;
+        };
+        message.extend_from_slice(unescape_line(line));
+    }
+
+    if let Some(message) = current {
+        messages.push(message);
+    }
+    messages
+}
+
+/// Unescapes the `>From ` lines of a message taken out of an mbox file
+/// without its `From ` separator line.
+pub fn unescape(message: &[u8]) -> Vec<u8> {
+    message
+        .split_inclusive(|b| *b == b'\n')
+        .flat_map(unescape_line)
+        .copied()
+        .collect()
+}
+
+fn unescape_line(line: &[u8]) -> &[u8] {
+    let quotes = line.iter().take_while(|b| **b == b'>').count();
+    if quotes > 0 && line[quotes..].starts_with(b"From ") {
+        &line[1..]
+    } else {
+        line
+    }
+}
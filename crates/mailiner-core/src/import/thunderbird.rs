@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::Utc;
+
+use crate::error::{MailinerError, Result};
+use crate::ids::AccountId;
+use crate::import::mbox;
+use crate::models::{Account, ConnectionSecurity, ServerSettings};
+use crate::platform::FileSystem;
+use crate::storage::Storage;
+
+const PREFS_FILE: &str = "prefs.js";
+const PROFILE_DIR_PREFIX: &str = "[ProfD]";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrefValue {
+    String(String),
+    Int(i64),
+    Bool(bool),
+}
+
+/// Parses the `user_pref("name", value);` lines of a Thunderbird `prefs.js`.
+pub fn parse_prefs(content: &str) -> HashMap<String, PrefValue> {
+    let mut prefs = HashMap::new();
+    for line in content.lines() {
+        let Some(args) = line
+            .trim()
+            .strip_prefix("user_pref(")
+            .and_then(|rest| rest.strip_suffix(");"))
+        else {
+            continue;
+        };
+        let Some((name, rest)) = parse_js_string(args) else {
+            continue;
+        };
+        let Some(value) = rest.trim_start().strip_prefix(',').map(str::trim) else {
+            continue;
+        };
+
+        let value = if value.starts_with('"') {
+            match parse_js_string(value) {
+                Some((value, _)) => PrefValue::String(value),
+                None => continue,
+            }
+        } else if value == "true" || value == "false" {
+            PrefValue::Bool(value == "true")
+        } else if let Ok(value) = value.parse() {
+            PrefValue::Int(value)
+        } else {
+            continue;
+        };
+        prefs.insert(name, value);
+    }
+    prefs
+}
+
+/// Parses a double-quoted JavaScript string literal at the start of `input`,
+/// returning the unescaped string and the remaining input.
+fn parse_js_string(input: &str) -> Option<(String, &str)> {
+    let mut chars = input.strip_prefix('"')?.char_indices();
+    let mut value = String::new();
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &input[pos + 2..])),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'u' => {
+                    let code: String = (0..4)
+                        .filter_map(|_| chars.next().map(|(_, c)| c))
+                        .collect();
+                    value.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                other => value.push(other),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+#[derive(Debug, Clone)]
+pub struct ThunderbirdAccount {
+    /// The account key in prefs.js, e.g. `account1`.
+    pub key: String,
+    pub name: String,
+    pub email: Option<String>,
+    pub full_name: Option<String>,
+    /// Server type as used by Thunderbird: `imap`, `pop3` or `none` for Local Folders.
+    pub server_type: String,
+    pub incoming: Option<ServerSettings>,
+    pub outgoing: Option<ServerSettings>,
+    /// Mail directory relative to the profile.
+    pub directory: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ThunderbirdProfile {
+    prefs: HashMap<String, PrefValue>,
+}
+
+impl ThunderbirdProfile {
+    pub fn new(prefs: HashMap<String, PrefValue>) -> Self {
+        Self { prefs }
+    }
+
+    fn string(&self, key: &str) -> Option<&str> {
+        match self.prefs.get(key) {
+            Some(PrefValue::String(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn int(&self, key: &str) -> Option<i64> {
+        match self.prefs.get(key) {
+            Some(PrefValue::Int(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn list(&self, key: &str) -> Vec<&str> {
+        self.string(key)
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn accounts(&self) -> Vec<ThunderbirdAccount> {
+        self.list("mail.accountmanager.accounts")
+            .into_iter()
+            .filter_map(|key| self.account(key))
+            .collect()
+    }
+
+    fn account(&self, key: &str) -> Option<ThunderbirdAccount> {
+        let server = self.string(&format!("mail.account.{}.server", key))?;
+        let server_pref = |name: &str| format!("mail.server.{}.{}", server, name);
+        let server_type = self.string(&server_pref("type"))?.to_string();
+
+        let identity = self
+            .list(&format!("mail.account.{}.identities", key))
+            .first()
+            .map(|id| id.to_string());
+        let identity_pref = |name: &str| {
+            identity
+                .as_ref()
+                .and_then(|id| self.string(&format!("mail.identity.{}.{}", id, name)))
+                .map(str::to_string)
+        };
+
+        let incoming = match server_type.as_str() {
+            "imap" | "pop3" => Some(ServerSettings {
+                host: self.string(&server_pref("hostname"))?.to_string(),
+                port: self
+                    .int(&server_pref("port"))
+                    .and_then(|port| u16::try_from(port).ok())
+                    .unwrap_or(default_port(
+                        &server_type,
+                        self.int(&server_pref("socketType")),
+                    )),
+                security: socket_type(self.int(&server_pref("socketType"))),
+                username: self
+                    .string(&server_pref("userName"))
+                    .unwrap_or_default()
+                    .to_string(),
+                auth_method: None,
+            }),
+            _ => None,
+        };
+
+        let outgoing = identity_pref("smtpServer")
+            .or_else(|| self.string("mail.smtp.defaultserver").map(str::to_string))
+            .and_then(|smtp| {
+                let smtp_pref = |name: &str| format!("mail.smtpserver.{}.{}", smtp, name);
+                let security = socket_type(self.int(&smtp_pref("try_ssl")));
+                Some(ServerSettings {
+                    host: self.string(&smtp_pref("hostname"))?.to_string(),
+                    port: self
+                        .int(&smtp_pref("port"))
+                        .and_then(|port| u16::try_from(port).ok())
+                        .unwrap_or(if security == ConnectionSecurity::Tls {
+                            465
+                        } else {
+                            587
+                        }),
+                    security,
+                    username: self
+                        .string(&smtp_pref("username"))
+                        .unwrap_or_default()
+                        .to_string(),
+                    auth_method: None,
+                })
+            });
+
+        let directory = self.string(&server_pref("directory-rel")).map(|dir| {
+            dir.strip_prefix(PROFILE_DIR_PREFIX)
+                .unwrap_or(dir)
+                .to_string()
+        });
+
+        Some(ThunderbirdAccount {
+            key: key.to_string(),
+            name: self
+                .string(&server_pref("name"))
+                .map(str::to_string)
+                .or_else(|| identity_pref("useremail"))
+                .unwrap_or_else(|| key.to_string()),
+            email: identity_pref("useremail"),
+            full_name: identity_pref("fullName"),
+            server_type,
+            incoming,
+            outgoing,
+            directory,
+        })
+    }
+}
+
+fn socket_type(value: Option<i64>) -> ConnectionSecurity {
+    match value {
+        Some(2) => ConnectionSecurity::StartTls,
+        Some(3) => ConnectionSecurity::Tls,
+        _ => ConnectionSecurity::None,
+    }
+}
+
+fn default_port(server_type: &str, socket_type: Option<i64>) -> u16 {
+    match (server_type, socket_type) {
+        ("imap", Some(3)) => 993,
+        ("imap", _) => 143,
+        (_, Some(3)) => 995,
+        _ => 110,
+    }
+}
+
+/// A local mbox folder found in the profile.
+#[derive(Debug, Clone)]
+pub struct ThunderbirdFolder {
+    /// Folder path with `/` separating parent and child folders.
+    pub name: String,
+    /// Path of the mbox file relative to the profile directory.
+    pub path: String,
+}
+
+/// Imports accounts and local mail from a Thunderbird profile.
+///
+/// The importer reads the profile through a [`FileSystem`] rooted at the
+/// profile directory. Address books are stored in SQLite databases, which are
+/// not supported yet.
+pub struct ThunderbirdImporter {
+    fs: Arc<dyn FileSystem>,
+}
+
+impl ThunderbirdImporter {
+    pub fn new(fs: Arc<dyn FileSystem>) -> Self {
+        Self { fs }
+    }
+
+    pub async fn load_profile(&self) -> Result<ThunderbirdProfile> {
+        let prefs = self.fs.read(PREFS_FILE).await?;
+        let prefs = String::from_utf8(prefs)
+            .map_err(|e| MailinerError::InvalidData(format!("Invalid {}: {}", PREFS_FILE, e)))?;
+        Ok(ThunderbirdProfile::new(parse_prefs(&prefs)))
+    }
+
+    /// Creates a Mailiner account for every server account in the profile.
+    pub async fn import_accounts(
+        &self,
+        profile: &ThunderbirdProfile,
+        storage: &dyn Storage,
+    ) -> Result<Vec<Account>> {
+        let mut accounts = Vec::new();
+        for tb_account in profile.accounts() {
+            let Some(incoming) = tb_account.incoming.as_ref() else {
+                continue;
+            };
+            let account = Account {
+                // Same scheme as the connectors use, so that a later sync finds the account.
+                id: AccountId::new(format!("{}-{}", tb_account.server_type, incoming.username)),
+                name: tb_account.name.clone(),
+                email: tb_account
+                    .email
+                    .clone()
+                    .unwrap_or_else(|| incoming.username.clone()),
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            };
+            storage.save_account(&account).await?;
+            accounts.push(account);
+        }
+        Ok(accounts)
+    }
+
+    /// Lists the mbox folders stored in the account's mail directory.
+    pub async fn local_folders(
+        &self,
+        account: &ThunderbirdAccount,
+    ) -> Result<Vec<ThunderbirdFolder>> {
+        let Some(directory) = account.directory.as_ref() else {
+            return Ok(Vec::new());
+        };
+        let mut folders = Vec::new();
+        let mut pending = vec![(directory.trim_end_matches('/').to_string(), String::new())];
+        while let Some((dir, parent)) = pending.pop() {
+            for entry in self.fs.list(&dir).await? {
+                let path = format!("{}/{}", dir, entry);
+                if let Some(subfolder) = entry.strip_suffix(".sbd") {
+                    // Children of folder `X` live in the `X.sbd` directory.
+                    pending.push((path, join_folder(&parent, subfolder)));
+                } else if is_mbox_file(&entry) {
+                    folders.push(ThunderbirdFolder {
+                        name: join_folder(&parent, &entry),
+                        path,
+                    });
+                }
+            }
+        }
+        folders.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(folders)
+    }
+
+    /// Reads the raw RFC 822 messages stored in a local folder.
+    pub async fn read_messages(&self, folder: &ThunderbirdFolder) -> Result<Vec<Vec<u8>>> {
+        Ok(mbox::split_messages(&self.fs.read(&folder.path).await?))
+    }
+}
+
+fn join_folder(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+fn is_mbox_file(name: &str) -> bool {
+    const NON_MBOX_EXTENSIONS: &[&str] = &[".msf", ".dat", ".html", ".json", ".sqlite"];
+    !name.starts_with('.') && !NON_MBOX_EXTENSIONS.iter().any(|ext| name.ends_with(ext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREFS: &str = r#"
+// Mozilla User Preferences
+user_pref("mail.accountmanager.accounts", "account1,account2");
+user_pref("mail.account.account1.identities", "id1");
+user_pref("mail.account.account1.server", "server1");
+user_pref("mail.account.account2.server", "server2");
+user_pref("mail.identity.id1.fullName", "Jan Novák");
+user_pref("mail.identity.id1.smtpServer", "smtp1");
+user_pref("mail.identity.id1.useremail", "jan@example.com");
+user_pref("mail.server.server1.directory-rel", "[ProfD]ImapMail/imap.example.com");
+user_pref("mail.server.server1.hostname", "imap.example.com");
+user_pref("mail.server.server1.name", "Work \"mail\"");
+user_pref("mail.server.server1.socketType", 3);
+user_pref("mail.server.server1.type", "imap");
+user_pref("mail.server.server1.userName", "jan");
+user_pref("mail.server.server2.directory-rel", "[ProfD]Mail/Local Folders");
+user_pref("mail.server.server2.type", "none");
+user_pref("mail.smtpserver.smtp1.hostname", "smtp.example.com");
+user_pref("mail.smtpserver.smtp1.port", 587);
+user_pref("mail.smtpserver.smtp1.try_ssl", 2);
+user_pref("mail.smtpserver.smtp1.username", "jan");
+user_pref("mailnews.start_page.enabled", false);
+"#;
+
+    #[test]
+    fn parses_accounts_from_prefs() {
+        let prefs = parse_prefs(PREFS);
+        assert_eq!(
+            prefs.get("mailnews.start_page.enabled"),
+            Some(&PrefValue::Bool(false))
+        );
+
+        let accounts = ThunderbirdProfile::new(prefs).accounts();
+        assert_eq!(accounts.len(), 2);
+
+        let work = &accounts[0];
+        assert_eq!(work.name, "Work \"mail\"");
+        assert_eq!(work.full_name.as_deref(), Some("Jan Novák"));
+        assert_eq!(work.email.as_deref(), Some("jan@example.com"));
+        assert_eq!(
+            work.incoming,
+            Some(ServerSettings {
+                host: "imap.example.com".to_string(),
+                port: 993,
+                security: ConnectionSecurity::Tls,
+                username: "jan".to_string(),
+                auth_method: None,
+            })
+        );
+        assert_eq!(
+            work.outgoing.as_ref().map(|s| s.security),
+            Some(ConnectionSecurity::StartTls)
+        );
+        assert_eq!(work.directory.as_deref(), Some("ImapMail/imap.example.com"));
+
+        let local = &accounts[1];
+        assert_eq!(local.server_type, "none");
+        assert!(local.incoming.is_none());
+        assert_eq!(local.directory.as_deref(), Some("Mail/Local Folders"));
+    }
+}
@@ -1,18 +1,102 @@
+//! Core types and services of the Mailiner email client.
+//!
+//! This crate has no UI dependencies and can be used as a standalone email
+//! library. The main building blocks are:
+//!
+//! * [`EmailConnector`] - the interface implemented by protocol backends
+//!   (e.g. `mailiner-imap-connector`),
+//! * [`MessageSender`] - the interface for submitting messages (e.g.
+//!   `mailiner-smtp-connector`),
+//! * [`Storage`] - the local cache of accounts, folders and messages, kept
+//!   in files by [`FileStorage`],
+//! * [`EmailService`] - ties a connector and a storage together and provides
+//!   sync and cache-aware read operations,
+//! * [`ConnectorRegistry`] - creates connectors from backend URIs, with the
+//!   factories the connector crates register,
+//! * [`SearchIndex`] - full-text search over the local cache, kept up to date
+//!   by [`IndexedStorage`],
+//! * [`AccountManager`] - the saved server settings of the accounts.
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use mailiner_core::{AccountId, EmailConnector, EmailService, InMemoryStorage};
+//! # async fn example<S>(connector: Arc<dyn EmailConnector<S>>) -> mailiner_core::Result<()>
+//! # where S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + std::fmt::Debug + Send + Sync {
+//! let service = EmailService::new(connector, Arc::new(InMemoryStorage::new()));
+//! service.sync_account(&AccountId::new("me@example.com")).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The `mock` feature (enabled by default) provides [`MockConnector`], an
+//! in-process connector with generated data, and [`MockSender`]. The `mime`
+//! feature provides the `mime` module, for connectors that parse whole
+//! messages, and the `rustls` feature the TLS handshake of the `tls` module.
+
 pub mod error;
 pub mod ids;
 pub mod models;
 pub mod storage;
 pub mod connector;
+pub mod sender;
+pub mod service;
+pub mod platform;
+pub mod import;
+pub mod backup;
+pub mod calendar;
+pub mod delivery;
+pub mod compose;
+pub mod oauth;
+pub mod registry;
+pub mod search;
+pub mod accounts;
+pub mod avatar;
+pub mod contacts;
+pub mod rules;
+pub mod metrics;
+pub mod scheduler;
+pub mod retention;
+pub mod activity;
+pub mod threads;
+pub mod grouping;
+pub mod unsubscribe;
+pub mod sanitize;
+pub mod trackers;
+pub mod tls;
+#[cfg(feature = "mime")]
+pub mod mime;
 
 pub use error::{MailinerError, Result};
-pub use ids::{AccountId, FolderId, MessageId, MessagePartId};
+pub use ids::{AccountId, ContactId, FolderId, MessageId, MessagePartId, OutboxId, RuleId};
 pub use models::{
-    Account, AccountMetadata, Envelope, Folder, FolderMetadata,
-    MessagePart, MessageContent,
+    Account, AccountMetadata, AccountPreferences, AttachmentLimits, DeletePolicy, Envelope, FlagUpdate, Folder, FolderDelta, FolderEvent, FolderMetadata,
+    FolderRights, FolderSyncState, Label, LabelKind, SearchQuery, EnvelopeFilter, EnvelopeQuery, EnvelopeSort,
+    MessagePart, MessageContent, MessageStructure, OutboxMessage, OutboxStatus,
     EmailAddress, EmailAddr, Group,
+    AccountConfig, AuthMethod, CertificateInfo, ConnectionSecurity, ServerSettings,
+    Versioned,
+};
+pub use storage::{Storage, StorageEvent, InMemoryStorage, EncryptedStorage, FileStorage, IndexedStorage, PartCache, StorageKey};
+pub use connector::EmailConnector;
+#[cfg(feature = "mock")]
+pub use connector::MockConnector;
+pub use sender::MessageSender;
+#[cfg(feature = "mock")]
+pub use sender::MockSender;
+pub use service::{
+    EmailService, EnvelopeSummary, MailAction, NewMailEvent, SearchHits, SearchSource, SnoozeEvent,
 };
-pub use storage::{Storage, InMemoryStorage};
-pub use connector::{EmailConnector, MockConnector};
+pub use registry::{BackendUri, ConnectorFactory, ConnectorRegistry};
+pub use search::{MemoryIndex, SearchIndex};
+pub use accounts::{AccountHealth, AccountManager, ConnectionStatus};
+pub use contacts::Contact;
+pub use rules::{Condition, Rule, RuleAction};
+pub use metrics::{MemoryMetrics, Metrics, MetricsSnapshot, NoMetrics};
+pub use scheduler::{SyncLimits, SyncScheduler};
+pub use retention::{RetentionAction, RetentionPolicy};
+pub use activity::ActivityEntry;
+#[cfg(feature = "tantivy")]
+pub use search::TantivyIndex;
 
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
@@ -0,0 +1,136 @@
+//! Opt-in performance metrics, to diagnose slow syncs and fetches on real
+//! accounts.
+//!
+//! [`EmailService`](crate::EmailService) reports to a [`Metrics`] set with
+//! [`with_metrics`](crate::EmailService::with_metrics), by default to
+//! [`NoMetrics`], which drops everything. [`MemoryMetrics`] keeps totals to
+//! show in a diagnostics view.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+/// How long syncing a folder took, in milliseconds.
+pub const SYNC_DURATION_MS: &str = "sync.duration_ms";
+/// How long a request to the server took, in milliseconds.
+pub const COMMAND_LATENCY_MS: &str = "command.latency_ms";
+/// The size of fetched message parts, in bytes.
+pub const FETCH_BYTES: &str = "fetch.bytes";
+pub const CACHE_HITS: &str = "cache.hits";
+pub const CACHE_MISSES: &str = "cache.misses";
+
+pub trait Metrics: Send + Sync {
+    /// Adds `value` to a counter.
+    fn increment(&self, name: &'static str, value: u64);
+    /// Records a measurement, e.g. a duration.
+    fn record(&self, name: &'static str, value: f64);
+}
+
+pub struct NoMetrics;
+
+impl Metrics for NoMetrics {
+    fn increment(&self, _name: &'static str, _value: u64) {}
+
+    fn record(&self, _name: &'static str, _value: f64) {}
+}
+
+/// A summary of the measurements recorded under a name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Histogram {
+    fn add(&mut self, value: f64) {
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        if self.count == 0 || value > self.max {
+            self.max = value;
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum / self.count as f64)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    pub counters: BTreeMap<String, u64>,
+    pub histograms: BTreeMap<String, Histogram>,
+}
+
+impl MetricsSnapshot {
+    /// The share of message parts read from the cache.
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let hits = self.counters.get(CACHE_HITS).copied().unwrap_or(0);
+        let misses = self.counters.get(CACHE_MISSES).copied().unwrap_or(0);
+        (hits + misses > 0).then(|| hits as f64 / (hits + misses) as f64)
+    }
+}
+
+/// Keeps the totals in memory since it was created.
+#[derive(Default)]
+pub struct MemoryMetrics {
+    snapshot: Mutex<MetricsSnapshot>,
+}
+
+impl MemoryMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.snapshot.lock().unwrap().clone()
+    }
+}
+
+impl Metrics for MemoryMetrics {
+    fn increment(&self, name: &'static str, value: u64) {
+        *self
+            .snapshot
+            .lock()
+            .unwrap()
+            .counters
+            .entry(name.to_string())
+            .or_default() += value;
+    }
+
+    fn record(&self, name: &'static str, value: f64) {
+        self.snapshot
+            .lock()
+            .unwrap()
+            .histograms
+            .entry(name.to_string())
+            .or_default()
+            .add(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totals() {
+        let metrics = MemoryMetrics::new();
+        assert_eq!(metrics.snapshot().cache_hit_rate(), None);
+        metrics.increment(CACHE_HITS, 3);
+        metrics.increment(CACHE_MISSES, 1);
+        metrics.record(SYNC_DURATION_MS, 20.0);
+        metrics.record(SYNC_DURATION_MS, 40.0);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.cache_hit_rate(), Some(0.75));
+        let sync = snapshot.histograms[SYNC_DURATION_MS];
+        assert_eq!((sync.count, sync.min, sync.max), (2, 20.0, 40.0));
+        assert_eq!(sync.mean(), Some(30.0));
+    }
+}
@@ -0,0 +1,345 @@
+//! Envelopes and MIME trees of messages parsed with mail-parser, for the
+//! connectors that have whole messages rather than a server's view of
+//! them, e.g. from files.
+
+use chrono::{DateTime, Utc};
+use mail_parser::{Address, HeaderValue, Message, MimeHeaders, PartType};
+
+use crate::ids::{AccountId, FolderId, MessageId, MessagePartId};
+use crate::models::{EmailAddr, EmailAddress, Envelope, Group, MessageContent, MessageStructure};
+use crate::unsubscribe::Unsubscribe;
+
+/// Builds the envelope from the headers of a message. It has no flags, and
+/// the connectors fill in what they know besides the headers, e.g. the flags
+/// and the size.
+pub fn envelope(
+    message: &Message<'_>,
+    id: MessageId,
+    account_id: &AccountId,
+    folder_id: &FolderId,
+) -> Envelope {
+    Envelope {
+        id,
+        account_id: account_id.clone(),
+        folder_id: folder_id.clone(),
+        subject: message.subject().map(|s| s.to_string()),
+        from: email_address(message.from()),
+        to: email_address(message.to()),
+        cc: email_address(message.cc()),
+        bcc: email_address(message.bcc()),
+        reply_to: email_address(message.reply_to()),
+        date: date(message).unwrap_or_else(Utc::now),
+        is_read: false,
+        is_starred: false,
+        is_flagged: false,
+        is_draft: false,
+        is_deleted: false,
+        has_attachments: message.attachment_count() > 0,
+        size: None,
+        keywords: Default::default(),
+        labels: Default::default(),
+        snoozed_until: None,
+        thread_id: None,
+        remote_id: None,
+        message_id: message.message_id().map(|id| id.to_string()),
+        in_reply_to: id_list(message.in_reply_to()),
+        references: id_list(message.references()),
+        unsubscribe: unsubscribe(message),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    }
+}
+
+/// The `Date` header, if there is a valid one.
+pub fn date(message: &Message<'_>) -> Option<DateTime<Utc>> {
+    message
+        .date()
+        .and_then(|date| DateTime::parse_from_rfc3339(&date.to_rfc3339()).ok())
+        .map(|date| date.with_timezone(&Utc))
+}
+
+/// The message ids of an `In-Reply-To` or `References` header.
+pub fn id_list(value: &HeaderValue<'_>) -> Vec<String> {
+    value
+        .as_text_list()
+        .map(|ids| ids.iter().map(|id| id.to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// How to unsubscribe, if the message came from a mailing list.
+pub fn unsubscribe(message: &Message<'_>) -> Option<Unsubscribe> {
+    Unsubscribe::parse(
+        message.header_raw("List-Unsubscribe")?,
+        message.header_raw("List-Unsubscribe-Post"),
+    )
+}
+
+pub fn email_address(address: Option<&Address<'_>>) -> Option<EmailAddress> {
+    let addr = |addr: &mail_parser::Addr<'_>| EmailAddr {
+        name: addr.name.as_ref().map(|s| s.to_string()),
+        email: addr.address.as_ref().map(|s| s.to_string()),
+    };
+    address.map(|address| match address {
+        Address::List(list) => EmailAddress::List(list.iter().map(addr).collect()),
+        Address::Group(groups) => EmailAddress::Group(
+            groups
+                .iter()
+                .map(|group| Group {
+                    name: group.name.as_ref().map(|s| s.to_string()),
+                    members: group.addresses.iter().map(addr).collect(),
+                })
+                .collect(),
+        ),
+    })
+}
+
+/// Whether one of the text bodies contains `needle`, ignoring case.
+pub fn body_contains(message: &Message<'_>, needle: &str) -> bool {
+    let needle = needle.to_lowercase();
+    (0..message.text_body_count())
+        .filter_map(|index| message.body_text(index))
+        .any(|text| text.to_lowercase().contains(&needle))
+}
+
+/// Builds the MIME tree of a message, numbering the parts like IMAP section
+/// numbers (RFC 3501, section 6.4.5), so that part ids look the same as with
+/// a server.
+pub fn structure(message: &Message<'_>) -> MessageStructure {
+    match &message.parts[0].body {
+        // The root of a multipart message has no section number of its own.
+        PartType::Multipart(ids) => node(message, 0, None, children(message, ids, "")),
+        _ => part(message, 0, "1".to_string()),
+    }
+}
+
+fn part(message: &Message<'_>, index: usize, id: String) -> MessageStructure {
+    let children = match &message.parts[index].body {
+        PartType::Multipart(ids) => children(message, ids, &id),
+        // The parts of an attached message are numbered within its body.
+        PartType::Message(nested) => match &nested.parts[0].body {
+            PartType::Multipart(ids) => children(nested, ids, &id),
+            _ => vec![part(nested, 0, format!("{}.1", id))],
+        },
+        _ => Vec::new(),
+    };
+    node(message, index, Some(MessagePartId::new(id)), children)
+}
+
+fn children<I>(message: &Message<'_>, ids: &[I], prefix: &str) -> Vec<MessageStructure>
+where
+    I: Copy + TryInto<usize>,
+{
+    ids.iter()
+        .enumerate()
+        .filter_map(|(number, index)| {
+            let index = (*index).try_into().ok()?;
+            let id = if prefix.is_empty() {
+                (number + 1).to_string()
+            } else {
+                format!("{}.{}", prefix, number + 1)
+            };
+            Some(part(message, index, id))
+        })
+        .collect()
+}
+
+fn node(
+    message: &Message<'_>,
+    index: usize,
+    id: Option<MessagePartId>,
+    children: Vec<MessageStructure>,
+) -> MessageStructure {
+    let part = &message.parts[index];
+    let content_type = content_type(part);
+    let multipart = matches!(part.body, PartType::Multipart(_));
+    MessageStructure {
+        id,
+        content_type,
+        filename: part.attachment_name().map(str::to_string),
+        size: (!multipart)
+            .then(|| (part.offset_end as u64).saturating_sub(part.offset_body as u64)),
+        is_attachment: is_attachment(part),
+        content_id: part.content_id().map(str::to_string),
+        children,
+    }
+}
+
+fn content_type(part: &mail_parser::MessagePart<'_>) -> String {
+    match part.content_type() {
+        Some(content_type) => format!(
+            "{}/{}",
+            content_type.ctype(),
+            content_type.subtype().unwrap_or_default()
+        )
+        .to_ascii_lowercase(),
+        None => match part.body {
+            PartType::Message(_) => "message/rfc822".to_string(),
+            PartType::Multipart(_) => "multipart/mixed".to_string(),
+            _ => "text/plain".to_string(),
+        },
+    }
+}
+
+fn is_attachment(part: &mail_parser::MessagePart<'_>) -> bool {
+    part.content_disposition()
+        .is_some_and(|disposition| disposition.ctype().eq_ignore_ascii_case("attachment"))
+}
+
+/// A part of a message with its transfer encoding and charset decoded.
+pub struct DecodedPart {
+    pub content_type: String,
+    pub filename: Option<String>,
+    pub is_attachment: bool,
+    pub content: MessageContent,
+}
+
+/// Finds a part by the section number that [`structure`] gave it.
+pub fn find_part(message: &Message<'_>, part_id: &MessagePartId) -> Option<DecodedPart> {
+    let mut current = message;
+    let mut index = 0;
+    for (depth, number) in part_id.as_str().split('.').enumerate() {
+        let position = number.parse::<usize>().ok()?.checked_sub(1)?;
+        match &current.parts[index].body {
+            PartType::Multipart(ids) => index = (*ids.get(position)?).try_into().ok()?,
+            PartType::Message(nested) if depth > 0 => {
+                current = nested;
+                index = match &nested.parts[0].body {
+                    PartType::Multipart(ids) => (*ids.get(position)?).try_into().ok()?,
+                    _ if position == 0 => 0,
+                    _ => return None,
+                };
+            }
+            // A message that isn't multipart is part 1.
+            _ if depth == 0 && position == 0 => {}
+            _ => return None,
+        }
+    }
+
+    let part = &current.parts[index];
+    let content = match &part.body {
+        PartType::Text(text) => MessageContent::Text(text.to_string()),
+        PartType::Html(html) => MessageContent::Html(html.to_string()),
+        PartType::Binary(data) | PartType::InlineBinary(data) => {
+            MessageContent::Binary(data.to_vec())
+        }
+        // Attached messages and multiparts are passed on as they are.
+        PartType::Message(nested) => MessageContent::Binary(nested.raw_message().to_vec()),
+        PartType::Multipart(_) => MessageContent::Binary(
+            current
+                .raw_message
+                .get(part.offset_body as usize..part.offset_end as usize)
+                .unwrap_or_default()
+                .to_vec(),
+        ),
+    };
+    Some(DecodedPart {
+        content_type: content_type(part),
+        filename: part.attachment_name().map(str::to_string),
+        is_attachment: is_attachment(part),
+        content,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use mail_parser::MessageParser;
+
+    use super::*;
+
+    const MESSAGE: &[u8] = b"From: Jan <jan@example.com>\r\n\
+        To: ana@example.com\r\n\
+        Subject: =?UTF-8?Q?Gr=C3=BC=C3=9Fe?=\r\n\
+        Message-ID: <b@example.com>\r\n\
+        In-Reply-To: <a@example.com>\r\n\
+        Content-Type: multipart/mixed; boundary=\"outer\"\r\n\
+        \r\n\
+        --outer\r\n\
+        Content-Type: multipart/alternative; boundary=\"inner\"\r\n\
+        \r\n\
+        --inner\r\n\
+        Content-Type: text/plain; charset=iso-8859-1\r\n\
+        Content-Transfer-Encoding: quoted-printable\r\n\
+        \r\n\
+        Gr=FC=DFe\r\n\
+        --inner\r\n\
+        Content-Type: text/html\r\n\
+        \r\n\
+        <p>Hi</p>\r\n\
+        --inner--\r\n\
+        --outer\r\n\
+        Content-Type: application/pdf; name=\"report.pdf\"\r\n\
+        Content-Disposition: attachment; filename=\"report.pdf\"\r\n\
+        Content-Transfer-Encoding: base64\r\n\
+        \r\n\
+        JVBERi0=\r\n\
+        --outer--\r\n";
+
+    #[test]
+    fn envelope_from_headers() {
+        let message = MessageParser::new().parse(MESSAGE).unwrap();
+        let envelope = envelope(
+            &message,
+            MessageId::new("INBOX;1"),
+            &AccountId::new("jan@example.com"),
+            &FolderId::new("INBOX"),
+        );
+        assert_eq!(envelope.subject.as_deref(), Some("Grüße"));
+        assert_eq!(
+            envelope.from.map(|from| from.to_string()).as_deref(),
+            Some("Jan <jan@example.com>")
+        );
+        assert_eq!(envelope.message_id.as_deref(), Some("b@example.com"));
+        assert_eq!(envelope.in_reply_to, ["a@example.com"]);
+        assert!(envelope.has_attachments && !envelope.is_read);
+        // There is no Date header.
+        assert!(date(&message).is_none());
+        assert!(body_contains(&message, "GRÜßE"));
+        assert!(!body_contains(&message, "invoice"));
+    }
+
+    #[test]
+    fn numbers_parts_like_imap() {
+        let message = MessageParser::new().parse(MESSAGE).unwrap();
+        let tree = structure(&message);
+        assert_eq!(tree.id, None);
+        assert_eq!(tree.content_type, "multipart/mixed");
+        let html = tree.find("text/html").unwrap();
+        assert_eq!(html.id, Some(MessagePartId::new("1.2")));
+        let attachments = tree.attachments();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].id, Some(MessagePartId::new("2")));
+        assert_eq!(attachments[0].filename.as_deref(), Some("report.pdf"));
+
+        let text = find_part(&message, &MessagePartId::new("1.1")).unwrap();
+        assert!(matches!(text.content, MessageContent::Text(text) if text.trim() == "Grüße"));
+        let pdf = find_part(&message, &MessagePartId::new("2")).unwrap();
+        assert!(pdf.is_attachment);
+        assert!(matches!(pdf.content, MessageContent::Binary(data) if data == b"%PDF-"));
+        assert!(find_part(&message, &MessagePartId::new("3")).is_none());
+
+        let single = MessageParser::new()
+            .parse(b"Subject: Hi\r\n\r\nHello\r\n")
+            .unwrap();
+        assert_eq!(structure(&single).id, Some(MessagePartId::new("1")));
+        assert!(find_part(&single, &MessagePartId::new("1")).is_some());
+    }
+
+    #[test]
+    fn numbers_parts_of_attached_messages() {
+        let raw = b"Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n\
+            --b\r\nContent-Type: text/plain\r\n\r\nHello\r\n\
+            --b\r\nContent-Type: message/rfc822\r\n\r\n\
+            Subject: Inner\r\n\r\nInner body\r\n\
+            --b--\r\n";
+        let message = MessageParser::new().parse(&raw[..]).unwrap();
+        let tree = structure(&message);
+        let inner = &tree.children[1];
+        assert_eq!(inner.id, Some(MessagePartId::new("2")));
+        assert_eq!(inner.content_type, "message/rfc822");
+        assert_eq!(inner.children[0].id, Some(MessagePartId::new("2.1")));
+
+        let part = find_part(&message, &MessagePartId::new("2.1")).unwrap();
+        assert!(matches!(part.content, MessageContent::Text(text) if text.trim() == "Inner body"));
+        assert!(body_contains(&message, "HELLO"));
+    }
+}
@@ -1,7 +1,18 @@
-use chrono::{DateTime, Utc};
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::ids::{AccountId, FolderId, MessageId, MessagePartId};
+use crate::error::MailinerError;
+use crate::ids::{AccountId, FolderId, MessageId, MessagePartId, OutboxId};
+use crate::retention::RetentionPolicy;
+use crate::unsubscribe::Unsubscribe;
+
+pub mod versioned;
+
+pub use versioned::Versioned;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Account {
@@ -12,16 +23,221 @@ pub struct Account {
     pub updated_at: DateTime<Utc>,
 }
 
+/// User preferences of an account. Unlike [`Account`], which connectors
+/// recreate on every login, these are only ever changed by the user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountPreferences {
+    pub account_id: AccountId,
+    /// Send messages as plain text only, without the HTML alternative.
+    pub send_plain_text: bool,
+    /// SHA-256 fingerprints of server certificates the user trusts although
+    /// they failed validation, e.g. self-signed ones.
+    #[serde(default)]
+    pub trusted_certificates: Vec<String>,
+    #[serde(default)]
+    pub attachment_limits: AttachmentLimits,
+    /// Where the account's mail is, e.g. `imaps://me@imap.example.com`, see
+    /// [`ConnectorRegistry`](crate::ConnectorRegistry).
+    #[serde(default)]
+    pub backend: Option<String>,
+    #[serde(default)]
+    pub delete_policy: DeletePolicy,
+    /// Deleted messages older than this many days are removed for good,
+    /// never if `None`.
+    #[serde(default)]
+    pub purge_after_days: Option<u32>,
+    /// The folders to announce new mail in, see
+    /// [`EmailService::subscribe_new_mail`](crate::EmailService::subscribe_new_mail).
+    #[serde(default)]
+    pub notify_folders: Vec<FolderId>,
+    /// Only announce mail from known [`Contact`](crate::Contact)s.
+    #[serde(default)]
+    pub notify_only_contacts: bool,
+    /// Applied after every sync of the account, see
+    /// [`EmailService::apply_retention`](crate::EmailService::apply_retention).
+    #[serde(default)]
+    pub retention: Vec<RetentionPolicy>,
+    /// Where messages marked as junk go, see
+    /// [`EmailService::mark_junk`](crate::EmailService::mark_junk). `None`
+    /// looks for a folder named Junk or Spam.
+    #[serde(default)]
+    pub junk_folder: Option<FolderId>,
+}
+
+impl AccountPreferences {
+    pub fn new(account_id: AccountId) -> Self {
+        Self {
+            account_id,
+            send_plain_text: false,
+            trusted_certificates: Vec::new(),
+            attachment_limits: AttachmentLimits::default(),
+            backend: None,
+            delete_policy: DeletePolicy::default(),
+            purge_after_days: None,
+            notify_folders: Vec::new(),
+            notify_only_contacts: false,
+            retention: Vec::new(),
+            junk_folder: None,
+        }
+    }
+}
+
+/// What deleting a message does, see
+/// [`EmailService::delete_message`](crate::EmailService::delete_message).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeletePolicy {
+    /// Marks the message `\Deleted` and leaves it in its folder, for
+    /// servers without a Trash folder and clients that expunge later.
+    #[default]
+    MarkDeleted,
+    /// Moves the message to the account's Trash folder.
+    MoveToTrash(FolderId),
+}
+
+/// Size limits for the attachments of outgoing messages, in bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttachmentLimits {
+    /// Largest single attachment.
+    pub max_size: u64,
+    /// Largest total size of the attachments of a message. Base64 makes
+    /// the message about a third larger.
+    pub max_total_size: u64,
+    /// Attachments larger than this are kept on disk while composing.
+    pub memory_threshold: u64,
+}
+
+impl Default for AttachmentLimits {
+    fn default() -> Self {
+        Self {
+            max_size: 20 * 1024 * 1024,
+            max_total_size: 20 * 1024 * 1024,
+            memory_threshold: 1024 * 1024,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Folder {
     pub id: FolderId,
     pub account_id: AccountId,
     pub name: String,
     pub parent_id: Option<FolderId>,
+    /// The user's rights on the folder, `None` if the server doesn't report them.
+    #[serde(default)]
+    pub rights: Option<FolderRights>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// Whether a label comes with the server, e.g. Gmail's `IMPORTANT`, or was
+/// made by the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LabelKind {
+    System,
+    User,
+}
+
+/// A tag that can be put on any number of messages, independent of the
+/// folders they are in. Labels are Gmail's labels, notmuch's tags, or IMAP
+/// keywords on other servers. Envelopes refer to them by name, see
+/// [`Envelope::labels`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Label {
+    pub account_id: AccountId,
+    pub name: String,
+    /// The background color, as `#rrggbb`.
+    pub color: Option<String>,
+    pub kind: LabelKind,
+}
+
+impl Label {
+    pub fn new(account_id: AccountId, name: impl Into<String>) -> Self {
+        Self {
+            account_id,
+            name: name.into(),
+            color: None,
+            kind: LabelKind::User,
+        }
+    }
+
+    pub fn with_color(mut self, color: impl Into<String>) -> Self {
+        self.color = Some(color.into());
+        self
+    }
+}
+
+/// Access rights on a folder, as the RFC 4314 rights string (e.g. `lrswipkxtea`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FolderRights(String);
+
+impl FolderRights {
+    pub fn new(rights: impl Into<String>) -> Self {
+        Self(rights.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn has(&self, right: char) -> bool {
+        self.0.contains(right)
+    }
+
+    pub fn can_read(&self) -> bool {
+        self.has('r')
+    }
+
+    /// Whether the seen flag is kept across sessions.
+    pub fn can_set_seen(&self) -> bool {
+        self.has('s')
+    }
+
+    /// Whether flags other than seen and deleted can be changed.
+    pub fn can_write_flags(&self) -> bool {
+        self.has('w')
+    }
+
+    /// Whether messages can be added to the folder, e.g. moved or copied into it.
+    pub fn can_insert(&self) -> bool {
+        self.has('i')
+    }
+
+    pub fn can_create_folders(&self) -> bool {
+        // "c" is the RFC 2086 predecessor of "k".
+        self.has('k') || self.has('c')
+    }
+
+    pub fn can_delete_folder(&self) -> bool {
+        self.has('x') || self.has('c')
+    }
+
+    pub fn can_delete_messages(&self) -> bool {
+        self.has('t') || self.has('d')
+    }
+
+    pub fn can_expunge(&self) -> bool {
+        self.has('e') || self.has('d')
+    }
+
+    pub fn can_administer(&self) -> bool {
+        self.has('a')
+    }
+
+    /// Whether nothing in the folder can be changed.
+    pub fn is_read_only(&self) -> bool {
+        !(self.can_set_seen()
+            || self.can_write_flags()
+            || self.can_insert()
+            || self.can_delete_messages())
+    }
+}
+
+impl std::fmt::Display for FolderRights {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EmailAddr {
     pub name: Option<String>,
@@ -73,6 +289,16 @@ pub enum EmailAddress {
     Group(Vec<Group>),
 }
 
+impl EmailAddress {
+    /// All addresses, including the members of groups.
+    pub fn addrs(&self) -> Vec<&EmailAddr> {
+        match self {
+            EmailAddress::List(list) => list.iter().collect(),
+            EmailAddress::Group(groups) => groups.iter().flat_map(|g| &g.members).collect(),
+        }
+    }
+}
+
 impl ToString for EmailAddress {
     fn to_string(&self) -> String {
         match self {
@@ -92,6 +318,8 @@ pub struct Envelope {
     pub to: Option<EmailAddress>,
     pub cc: Option<EmailAddress>,
     pub bcc: Option<EmailAddress>,
+    #[serde(default)]
+    pub reply_to: Option<EmailAddress>,
     pub date: DateTime<Utc>,
     pub is_read: bool,
     pub is_starred: bool,
@@ -99,10 +327,50 @@ pub struct Envelope {
     pub is_draft: bool,
     pub is_deleted: bool,
     pub has_attachments: bool,
+    /// The size of the whole message in bytes, if the server tells it.
+    #[serde(default)]
+    pub size: Option<u64>,
+    /// User-defined flags, e.g. `$Forwarded`, `$Junk` or custom labels.
+    #[serde(default)]
+    pub keywords: BTreeSet<String>,
+    /// The names of the [`Label`]s on the message.
+    #[serde(default)]
+    pub labels: BTreeSet<String>,
+    /// Until when the message is hidden from its folder, see
+    /// [`EmailService::snooze`](crate::EmailService::snooze).
+    /// Only kept locally, servers have no standard way to snooze.
+    #[serde(default)]
+    pub snoozed_until: Option<DateTime<Utc>>,
+    /// The conversation the message belongs to, if the server groups them.
+    #[serde(default)]
+    pub thread_id: Option<String>,
+    /// An id that stays the same when the message is moved, if the server
+    /// has one (e.g. Gmail's X-GM-MSGID).
+    #[serde(default)]
+    pub remote_id: Option<String>,
+    /// The Message-ID header, without the angle brackets.
+    #[serde(default)]
+    pub message_id: Option<String>,
+    #[serde(default)]
+    pub in_reply_to: Vec<String>,
+    /// The References header. Not known for envelopes built from the IMAP
+    /// ENVELOPE, which doesn't include it.
+    #[serde(default)]
+    pub references: Vec<String>,
+    /// How to unsubscribe, if the message came from a mailing list.
+    #[serde(default)]
+    pub unsubscribe: Option<Unsubscribe>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+impl Envelope {
+    /// Whether the message is still snoozed at `now`.
+    pub fn is_snoozed(&self, now: DateTime<Utc>) -> bool {
+        self.snoozed_until.is_some_and(|until| until > now)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessagePart {
     pub id: MessagePartId,
@@ -116,6 +384,73 @@ pub struct MessagePart {
     pub updated_at: DateTime<Utc>,
 }
 
+/// A node in the MIME tree of a message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageStructure {
+    /// The id to fetch the part with, `None` for the root of a multipart
+    /// message.
+    pub id: Option<MessagePartId>,
+    pub content_type: String,
+    pub filename: Option<String>,
+    /// The encoded size of the part, `None` for multiparts.
+    pub size: Option<u64>,
+    pub is_attachment: bool,
+    /// The `Content-ID` of the part without its angle brackets, which HTML
+    /// refers to with `cid:` URLs (RFC 2392).
+    #[serde(default)]
+    pub content_id: Option<String>,
+    /// The parts of a multipart or of an attached message.
+    pub children: Vec<MessageStructure>,
+}
+
+impl MessageStructure {
+    /// Finds the first part of the given content type that isn't an
+    /// attachment, e.g. the `text/html` alternative of a message.
+    pub fn find(&self, content_type: &str) -> Option<&MessageStructure> {
+        if self.children.is_empty() {
+            return (!self.is_attachment && self.content_type.eq_ignore_ascii_case(content_type))
+                .then_some(self);
+        }
+        // Attached messages have their own body.
+        if self.content_type.eq_ignore_ascii_case("message/rfc822") {
+            return None;
+        }
+        self.children.iter().find_map(|child| child.find(content_type))
+    }
+
+    /// Finds the part with the given `Content-ID`, with or without its angle
+    /// brackets. Inline images are sometimes marked as attachments, so those
+    /// are searched too.
+    pub fn find_content_id(&self, content_id: &str) -> Option<&MessageStructure> {
+        let content_id = strip_angle_brackets(content_id);
+        if self
+            .content_id
+            .as_deref()
+            .is_some_and(|id| strip_angle_brackets(id) == content_id)
+        {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find_content_id(content_id))
+    }
+
+    /// Returns all attachments, including attached messages.
+    pub fn attachments(&self) -> Vec<&MessageStructure> {
+        if self.is_attachment || self.content_type.eq_ignore_ascii_case("message/rfc822") {
+            return vec![self];
+        }
+        self.children
+            .iter()
+            .flat_map(|child| child.attachments())
+            .collect()
+    }
+}
+
+/// A `Content-ID` or `Message-ID` without the angle brackets around it.
+fn strip_angle_brackets(id: &str) -> &str {
+    let id = id.trim();
+    id.strip_prefix('<').and_then(|id| id.strip_suffix('>')).unwrap_or(id)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MessageContent {
     Text(String),
@@ -129,6 +464,59 @@ pub struct FolderMetadata {
     pub total_messages: u64,
     pub unread_messages: u64,
     pub last_sync: DateTime<Utc>,
+    #[serde(default)]
+    pub sync_state: Option<FolderSyncState>,
+    /// Whether the folder was opened read-only at the last sync.
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// Server-side state of a folder at the last sync, used to only fetch what
+/// changed since then (IMAP CONDSTORE/QRESYNC).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FolderSyncState {
+    pub uid_validity: u32,
+    pub highest_modseq: u64,
+}
+
+/// Changes in a folder since a [`FolderSyncState`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FolderDelta {
+    /// State to pass to the next sync, `None` if the server doesn't support
+    /// incremental sync.
+    pub sync_state: Option<FolderSyncState>,
+    /// New messages and messages whose flags changed.
+    pub changed: Vec<Envelope>,
+    /// Messages that no longer exist in the folder.
+    pub vanished: Vec<MessageId>,
+    /// Whether the folder is opened read-only, so that changes to it fail.
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// The current flags of a message, as returned by
+/// [`EmailConnector::resync_flags`](crate::EmailConnector::resync_flags).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlagUpdate {
+    pub message_id: MessageId,
+    pub is_read: bool,
+    pub is_starred: bool,
+    pub is_flagged: bool,
+    pub is_draft: bool,
+    pub is_deleted: bool,
+    pub keywords: BTreeSet<String>,
+}
+
+impl FlagUpdate {
+    /// Applies the flags to the envelope of the message.
+    pub fn apply(&self, envelope: &mut Envelope) {
+        envelope.is_read = self.is_read;
+        envelope.is_starred = self.is_starred;
+        envelope.is_flagged = self.is_flagged;
+        envelope.is_draft = self.is_draft;
+        envelope.is_deleted = self.is_deleted;
+        envelope.keywords = self.keywords.clone();
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -137,3 +525,459 @@ pub struct AccountMetadata {
     pub last_sync: DateTime<Utc>,
     pub folders: Vec<FolderMetadata>,
 }
+
+/// Criteria for searching messages in a folder. All given criteria must match.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub subject: Option<String>,
+    pub body: Option<String>,
+    /// Messages sent on or after this date.
+    pub since: Option<NaiveDate>,
+    /// Messages sent before this date.
+    pub before: Option<NaiveDate>,
+    pub is_read: Option<bool>,
+    pub is_flagged: Option<bool>,
+    pub is_draft: Option<bool>,
+    pub is_deleted: Option<bool>,
+    #[serde(default)]
+    pub has_attachment: Option<bool>,
+    /// A query in the connector's own search language, e.g. notmuch's or
+    /// Gmail's, added to the other criteria. Connectors without one ignore
+    /// it.
+    #[serde(default)]
+    pub raw: Option<String>,
+}
+
+impl SearchQuery {
+    /// Matches the query against an envelope. The body and raw criteria
+    /// can't be evaluated on envelopes and are ignored.
+    pub fn matches(&self, envelope: &Envelope) -> bool {
+        fn contains(value: Option<String>, needle: &Option<String>) -> bool {
+            match needle {
+                Some(needle) => value
+                    .map(|value| value.to_lowercase().contains(&needle.to_lowercase()))
+                    .unwrap_or(false),
+                None => true,
+            }
+        }
+        fn flag(value: bool, expected: Option<bool>) -> bool {
+            expected.is_none_or(|expected| value == expected)
+        }
+
+        let date = envelope.date.date_naive();
+        contains(envelope.from.as_ref().map(ToString::to_string), &self.from)
+            && contains(envelope.to.as_ref().map(ToString::to_string), &self.to)
+            && contains(envelope.subject.clone(), &self.subject)
+            && self.since.is_none_or(|since| date >= since)
+            && self.before.is_none_or(|before| date < before)
+            && flag(envelope.is_read, self.is_read)
+            && flag(envelope.is_flagged, self.is_flagged)
+            && flag(envelope.is_draft, self.is_draft)
+            && flag(envelope.is_deleted, self.is_deleted)
+            && flag(envelope.has_attachments, self.has_attachment)
+    }
+}
+
+/// The order of the envelopes returned for an [`EnvelopeQuery`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EnvelopeSort {
+    #[default]
+    NewestFirst,
+    OldestFirst,
+    /// By subject, ignoring case, then newest first.
+    Subject,
+    /// By the first sender, ignoring case, then newest first.
+    Sender,
+    /// Largest first, messages of unknown size last.
+    Size,
+    /// Unread messages first, then newest first.
+    UnreadFirst,
+}
+
+impl EnvelopeSort {
+    pub fn compare(&self, a: &Envelope, b: &Envelope) -> Ordering {
+        fn key(value: Option<String>) -> String {
+            value.unwrap_or_default().to_lowercase()
+        }
+        fn sender(envelope: &Envelope) -> Option<String> {
+            let from = envelope.from.as_ref()?;
+            from.addrs().first().map(|addr| addr.to_string())
+        }
+
+        let newest_first = b.date.cmp(&a.date);
+        match self {
+            EnvelopeSort::NewestFirst => newest_first,
+            EnvelopeSort::OldestFirst => a.date.cmp(&b.date),
+            EnvelopeSort::Subject => key(a.subject.clone())
+                .cmp(&key(b.subject.clone()))
+                .then(newest_first),
+            EnvelopeSort::Sender => key(sender(a)).cmp(&key(sender(b))).then(newest_first),
+            EnvelopeSort::Size => b.size.cmp(&a.size).then(newest_first),
+            EnvelopeSort::UnreadFirst => a.is_read.cmp(&b.is_read).then(newest_first),
+        }
+    }
+
+    /// Whether the order depends on the subject or addresses.
+    pub fn uses_headers(&self) -> bool {
+        matches!(self, EnvelopeSort::Subject | EnvelopeSort::Sender)
+    }
+}
+
+/// Criteria for the envelopes returned for an [`EnvelopeQuery`]. All given
+/// criteria must match.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvelopeFilter {
+    pub is_read: Option<bool>,
+    pub is_flagged: Option<bool>,
+    pub has_attachments: Option<bool>,
+    /// Messages sent at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Messages sent before this time.
+    pub before: Option<DateTime<Utc>>,
+    /// Also return snoozed messages, which are left out otherwise.
+    #[serde(default)]
+    pub include_snoozed: bool,
+    /// Messages with the [`Label`] of this name.
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+impl EnvelopeFilter {
+    pub fn matches(&self, envelope: &Envelope) -> bool {
+        fn flag(value: bool, expected: Option<bool>) -> bool {
+            expected.is_none_or(|expected| value == expected)
+        }
+
+        flag(envelope.is_read, self.is_read)
+            && flag(envelope.is_flagged, self.is_flagged)
+            && flag(envelope.has_attachments, self.has_attachments)
+            && self.since.is_none_or(|since| envelope.date >= since)
+            && self.before.is_none_or(|before| envelope.date < before)
+            && self.label.as_ref().is_none_or(|label| envelope.labels.contains(label))
+            && (self.include_snoozed || !envelope.is_snoozed(Utc::now()))
+    }
+}
+
+/// A page of the cached envelopes of a folder, see
+/// [`Storage::query_envelopes`](crate::Storage::query_envelopes).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvelopeQuery {
+    /// How many of the matching envelopes to skip.
+    pub offset: usize,
+    /// How many envelopes to return at most, all if `None`.
+    pub limit: Option<usize>,
+    pub sort: EnvelopeSort,
+    pub filter: EnvelopeFilter,
+}
+
+impl EnvelopeQuery {
+    /// The `limit` newest envelopes after the `offset` newest ones.
+    pub fn page(offset: usize, limit: usize) -> Self {
+        Self {
+            offset,
+            limit: Some(limit),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_sort(mut self, sort: EnvelopeSort) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: EnvelopeFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Filters, sorts and slices envelopes that are all loaded already.
+    pub fn apply(&self, envelopes: impl IntoIterator<Item = Envelope>) -> Vec<Envelope> {
+        let mut envelopes: Vec<Envelope> = envelopes
+            .into_iter()
+            .filter(|envelope| self.filter.matches(envelope))
+            .collect();
+        envelopes.sort_by(|a, b| self.sort.compare(a, b));
+        envelopes
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+/// A change in a folder reported by the server while watching it.
+///
+/// Messages are identified by their sequence number in the folder, consumers
+/// are expected to re-sync the folder to get the details.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FolderEvent {
+    /// The folder now contains `exists` messages; the ones beyond the
+    /// previously known count are new.
+    NewMessages { folder_id: FolderId, exists: u32 },
+    MessageExpunged { folder_id: FolderId, sequence: u32 },
+    FlagsChanged { folder_id: FolderId, sequence: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConnectionSecurity {
+    None,
+    StartTls,
+    Tls,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerSettings {
+    pub host: String,
+    pub port: u16,
+    pub security: ConnectionSecurity,
+    pub username: String,
+    /// `None` picks the strongest password method the server supports.
+    #[serde(default)]
+    pub auth_method: Option<AuthMethod>,
+}
+
+/// The servers of an account, as set up by the user. The password isn't
+/// part of it, it's kept in [`SecureStorage`](crate::platform::SecureStorage).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountConfig {
+    pub account_id: AccountId,
+    /// The name of the account shown to the user, e.g. "Work".
+    pub name: String,
+    pub email: String,
+    pub incoming: ServerSettings,
+    /// The SMTP server, `None` for accounts that only read mail.
+    pub outgoing: Option<ServerSettings>,
+}
+
+/// Where a message in the outbox is at. Messages go from `Draft` or
+/// `Queued` through `Sending` to `Sent` or `Failed`, and back to `Queued`
+/// for a retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutboxStatus {
+    /// Held back until it is released, e.g. while sending can still be
+    /// undone.
+    Draft,
+    /// Waiting to be sent, possibly for a retry after a temporary failure.
+    Queued,
+    Sending,
+    /// Sent, kept for a while so that the UI can show it.
+    Sent,
+    /// The server refused the message, or it failed too often. It stays in
+    /// the outbox until the user retries or cancels it.
+    Failed,
+}
+
+/// A message waiting to be sent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxMessage {
+    pub id: OutboxId,
+    pub account_id: AccountId,
+    /// The envelope sender.
+    pub from: String,
+    pub recipients: Vec<String>,
+    pub subject: String,
+    /// The message to submit.
+    pub message: Vec<u8>,
+    /// The draft the message was written in, deleted once it is sent.
+    pub draft_id: Option<MessageId>,
+    pub status: OutboxStatus,
+    /// The number of failed attempts to send the message.
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    /// When to send the message: when it was scheduled for, or when to
+    /// retry it after a failure.
+    pub next_attempt_at: DateTime<Utc>,
+    #[serde(default)]
+    pub sent_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl OutboxMessage {
+    /// Whether the message is to be sent at `now`. Messages still marked as
+    /// sending were interrupted, e.g. by a crash, and are sent again.
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        matches!(self.status, OutboxStatus::Queued | OutboxStatus::Sending)
+            && self.next_attempt_at <= now
+    }
+}
+
+/// A server certificate that failed validation, for the user to inspect and
+/// possibly trust.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertificateInfo {
+    /// The SHA-256 fingerprint of the server's certificate, as colon
+    /// separated hex bytes.
+    pub fingerprint: String,
+    /// The DER encoded certificates, starting with the server's.
+    pub chain: Vec<Vec<u8>>,
+    /// Why validation failed.
+    pub reason: String,
+}
+
+/// How to log in to a server. With the OAuth mechanisms the credentials are
+/// an access token instead of a password.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuthMethod {
+    Login,
+    Plain,
+    CramMd5,
+    XOAuth2,
+    OAuthBearer,
+}
+
+impl AuthMethod {
+    /// The lowercase SASL name, e.g. `cram-md5`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuthMethod::Login => "login",
+            AuthMethod::Plain => "plain",
+            AuthMethod::CramMd5 => "cram-md5",
+            AuthMethod::XOAuth2 => "xoauth2",
+            AuthMethod::OAuthBearer => "oauthbearer",
+        }
+    }
+}
+
+impl FromStr for AuthMethod {
+    type Err = MailinerError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        [AuthMethod::Login, AuthMethod::Plain, AuthMethod::CramMd5, AuthMethod::XOAuth2, AuthMethod::OAuthBearer]
+            .into_iter()
+            .find(|method| method.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| MailinerError::InvalidData(format!("Unknown authentication method {}", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn envelope(uid: u32, subject: &str, is_read: bool) -> Envelope {
+        Envelope {
+            id: MessageId::new(format!("INBOX;UID={}", uid)),
+            account_id: AccountId::new("imap-jan"),
+            folder_id: FolderId::new("INBOX"),
+            subject: Some(subject.to_string()),
+            from: None,
+            to: None,
+            cc: None,
+            bcc: None,
+            reply_to: None,
+            date: Utc.timestamp_opt(1_700_000_000 + uid as i64 * 60, 0).unwrap(),
+            is_read,
+            is_starred: false,
+            is_flagged: false,
+            is_draft: false,
+            is_deleted: false,
+            has_attachments: false,
+            size: Some(uid as u64 * 1000),
+            keywords: BTreeSet::new(),
+            labels: BTreeSet::new(),
+            snoozed_until: None,
+            thread_id: None,
+            remote_id: None,
+            message_id: None,
+            in_reply_to: Vec::new(),
+            references: Vec::new(),
+            unsubscribe: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn envelope_pages() {
+        let envelopes = || {
+            vec![
+                envelope(1, "beta", false),
+                envelope(2, "Alpha", true),
+                envelope(3, "gamma", false),
+                envelope(4, "alpha", false),
+            ]
+        };
+        let subjects = |envelopes: Vec<Envelope>| {
+            envelopes
+                .into_iter()
+                .map(|e| e.subject.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            subjects(EnvelopeQuery::page(1, 2).apply(envelopes())),
+            ["gamma", "Alpha"]
+        );
+        let unread = EnvelopeFilter {
+            is_read: Some(false),
+            ..Default::default()
+        };
+        let query = EnvelopeQuery::default()
+            .with_sort(EnvelopeSort::Subject)
+            .with_filter(unread.clone());
+        assert_eq!(subjects(query.apply(envelopes())), ["alpha", "beta", "gamma"]);
+        let query = EnvelopeQuery::page(0, 10)
+            .with_sort(EnvelopeSort::OldestFirst)
+            .with_filter(EnvelopeFilter {
+                since: Some(envelopes()[1].date),
+                ..unread
+            });
+        assert_eq!(subjects(query.apply(envelopes())), ["gamma", "alpha"]);
+
+        let mut snoozed = envelopes();
+        snoozed[0].snoozed_until = Some(Utc::now() + chrono::Duration::hours(1));
+        snoozed[1].snoozed_until = Some(Utc::now() - chrono::Duration::hours(1));
+        assert_eq!(
+            subjects(EnvelopeQuery::default().apply(snoozed.clone())),
+            ["alpha", "gamma", "Alpha"]
+        );
+        let all = EnvelopeFilter {
+            include_snoozed: true,
+            ..Default::default()
+        };
+        assert_eq!(EnvelopeQuery::default().with_filter(all).apply(snoozed).len(), 4);
+
+        let mut labelled = envelopes();
+        labelled[2].labels.insert("Work".to_string());
+        labelled[3].size = None;
+        let query = EnvelopeQuery::default().with_sort(EnvelopeSort::Size);
+        assert_eq!(subjects(query.apply(labelled.clone())), ["gamma", "Alpha", "beta", "alpha"]);
+        let query = EnvelopeQuery::default().with_sort(EnvelopeSort::UnreadFirst);
+        assert_eq!(subjects(query.apply(labelled.clone())), ["alpha", "gamma", "beta", "Alpha"]);
+        let work = EnvelopeFilter {
+            label: Some("Work".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(subjects(EnvelopeQuery::default().with_filter(work).apply(labelled)), ["gamma"]);
+    }
+
+    #[test]
+    fn finds_parts_by_content_id() {
+        let part = |id: &str, content_id: Option<&str>| MessageStructure {
+            id: Some(MessagePartId::new(id)),
+            content_type: "image/png".to_string(),
+            filename: None,
+            size: Some(100),
+            is_attachment: false,
+            content_id: content_id.map(str::to_string),
+            children: Vec::new(),
+        };
+        let related = MessageStructure {
+            id: None,
+            content_type: "multipart/related".to_string(),
+            filename: None,
+            size: None,
+            is_attachment: false,
+            content_id: None,
+            children: vec![part("1", None), part("2", Some("<logo@example.com>")), part("3", Some("chart"))],
+        };
+        let id = |content_id| related.find_content_id(content_id).and_then(|part| part.id.clone());
+        assert_eq!(id("logo@example.com"), Some(MessagePartId::new("2")));
+        assert_eq!(id("<chart>"), Some(MessagePartId::new("3")));
+        assert_eq!(id("missing@example.com"), None);
+    }
+}
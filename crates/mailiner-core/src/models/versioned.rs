@@ -0,0 +1,143 @@
+//! Models tagged with the version of their schema, for records that outlive
+//! the build that wrote them: a persistent cache, or messages between the
+//! core and a UI of another version.
+//!
+//! A record is `{"version": 1, "data": {...}}`. Adding an optional field
+//! doesn't need a new version, the `#[serde(default)]` of the field takes
+//! care of older records. Renaming, removing or changing the meaning of one
+//! does: bump [`Versioned::VERSION`] and convert the older records in
+//! [`Versioned::upgrade`].
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{MailinerError, Result};
+use crate::models::{Account, Envelope, Folder, MessagePart};
+
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// The version of the schema the model has in this build.
+    const VERSION: u32;
+
+    /// Converts the data of a record from `version` to `version + 1`.
+    fn upgrade(version: u32, _data: Value) -> Result<Value> {
+        Err(MailinerError::InvalidData(format!(
+            "Can't upgrade from version {}",
+            version
+        )))
+    }
+}
+
+impl Versioned for Account {
+    const VERSION: u32 = 1;
+}
+
+impl Versioned for Folder {
+    const VERSION: u32 = 1;
+}
+
+impl Versioned for Envelope {
+    const VERSION: u32 = 1;
+}
+
+impl Versioned for MessagePart {
+    const VERSION: u32 = 1;
+}
+
+#[derive(Serialize)]
+struct Tagged<'a, T> {
+    version: u32,
+    data: &'a T,
+}
+
+#[derive(Deserialize)]
+struct Record {
+    version: u32,
+    data: Value,
+}
+
+pub fn to_json<T: Versioned>(model: &T) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec(&Tagged {
+        version: T::VERSION,
+        data: model,
+    })?)
+}
+
+/// Reads a record written by [`to_json`], upgrading it from older versions.
+/// Records without a version, written before models had one, are read as
+/// version 1.
+pub fn from_json<T: Versioned>(json: &[u8]) -> Result<T> {
+    let value: Value = serde_json::from_slice(json)?;
+    let Record { version, mut data } =
+        if value.get("version").is_some() && value.get("data").is_some() {
+            serde_json::from_value(value)?
+        } else {
+            Record {
+                version: 1,
+                data: value,
+            }
+        };
+    if version > T::VERSION {
+        return Err(MailinerError::InvalidData(format!(
+            "Record version {} is newer than supported version {}",
+            version,
+            T::VERSION
+        )));
+    }
+    for version in version..T::VERSION {
+        data = T::upgrade(version, data)?;
+    }
+    Ok(serde_json::from_value(data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use serde_json::json;
+
+    use super::*;
+    use crate::ids::{AccountId, FolderId};
+
+    /// A model whose `title` was renamed to `name` in version 2.
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Renamed {
+        name: String,
+    }
+
+    impl Versioned for Renamed {
+        const VERSION: u32 = 2;
+
+        fn upgrade(version: u32, mut data: Value) -> Result<Value> {
+            assert_eq!(version, 1);
+            data["name"] = data["title"].take();
+            Ok(data)
+        }
+    }
+
+    #[test]
+    fn versions() {
+        let folder = Folder {
+            id: FolderId::new("INBOX"),
+            account_id: AccountId::new("imap-jan"),
+            name: "Inbox".to_string(),
+            parent_id: None,
+            rights: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let json = to_json(&folder).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&json).unwrap()["version"],
+            1
+        );
+        assert_eq!(from_json::<Folder>(&json).unwrap().name, "Inbox");
+        // Written before records had a version.
+        let untagged = serde_json::to_vec(&folder).unwrap();
+        assert_eq!(from_json::<Folder>(&untagged).unwrap().name, "Inbox");
+
+        let old = json!({"version": 1, "data": {"title": "Work"}}).to_string();
+        assert_eq!(from_json::<Renamed>(old.as_bytes()).unwrap().name, "Work");
+        let newer = json!({"version": 3, "data": {"name": "Work"}}).to_string();
+        assert!(from_json::<Renamed>(newer.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,392 @@
+//! OAuth 2.0 access tokens for the XOAUTH2 and OAUTHBEARER login methods.
+//!
+//! The tokens are obtained with the authorization code flow and PKCE
+//! (RFC 7636): the user consents on the provider's page, which redirects
+//! back with a code that is exchanged for the tokens. Opening the page and
+//! sending the HTTP requests is up to the application; this module builds
+//! and checks what goes back and forth.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use base64::prelude::*;
+use chrono::{DateTime, Duration, Utc};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::error::{MailinerError, Result};
+use crate::registry::percent_decode;
+
+#[cfg(feature = "native")]
+pub mod loopback;
+
+/// Tokens are refreshed this long before they expire, so that they don't
+/// expire while a command is on its way.
+const EXPIRY_MARGIN_SECS: i64 = 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuthToken {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// `None` if the provider didn't say.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl OAuthToken {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at - Duration::seconds(EXPIRY_MARGIN_SECS) <= now)
+    }
+}
+
+/// Exchanges a refresh token for a new access token at the provider's token
+/// endpoint. Implemented by the application, which has an HTTP client.
+#[async_trait]
+pub trait TokenRefresher: Send + Sync {
+    async fn refresh(&self, refresh_token: &str) -> Result<OAuthToken>;
+}
+
+/// Hands out access tokens, refreshing them when they expire or the server
+/// refuses them.
+pub struct TokenProvider {
+    token: Mutex<OAuthToken>,
+    refresher: Arc<dyn TokenRefresher>,
+}
+
+impl TokenProvider {
+    pub fn new(token: OAuthToken, refresher: Arc<dyn TokenRefresher>) -> Self {
+        Self {
+            token: Mutex::new(token),
+            refresher,
+        }
+    }
+
+    /// Returns a valid access token. With `refresh`, the current token was
+    /// refused by the server and a new one is fetched even if it didn't
+    /// expire yet.
+    pub async fn access_token(&self, refresh: bool) -> Result<String> {
+        let mut token = self.token.lock().await;
+        if refresh || token.is_expired(Utc::now()) {
+            let refresh_token = token.refresh_token.clone().ok_or_else(|| {
+                MailinerError::InvalidData("The access token can't be refreshed".to_string())
+            })?;
+            let mut refreshed = self.refresher.refresh(&refresh_token).await?;
+            // Providers only send a refresh token when it changes.
+            if refreshed.refresh_token.is_none() {
+                refreshed.refresh_token = Some(refresh_token);
+            }
+            *token = refreshed;
+        }
+        Ok(token.access_token.clone())
+    }
+
+    /// The current tokens, e.g. to store them after a refresh.
+    pub async fn token(&self) -> OAuthToken {
+        self.token.lock().await.clone()
+    }
+}
+
+/// An application registered with an OAuth provider.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OAuthClient {
+    pub authorization_url: String,
+    pub token_url: String,
+    pub client_id: String,
+    /// Only for providers that want one even from apps that can't keep it
+    /// secret, like Google.
+    pub client_secret: Option<String>,
+    pub scopes: Vec<String>,
+}
+
+impl OAuthClient {
+    /// Gmail, with access to IMAP and SMTP.
+    pub fn google(client_id: impl Into<String>, client_secret: Option<String>) -> Self {
+        Self {
+            authorization_url: "https://accounts.google.com/o/oauth2/v2/auth".to_string(),
+            token_url: "https://oauth2.googleapis.com/token".to_string(),
+            client_id: client_id.into(),
+            client_secret,
+            scopes: vec!["https://mail.google.com/".to_string()],
+        }
+    }
+
+    /// Outlook and Microsoft 365, with access to IMAP and SMTP.
+    pub fn microsoft(client_id: impl Into<String>) -> Self {
+        Self {
+            authorization_url: "https://login.microsoftonline.com/common/oauth2/v2.0/authorize"
+                .to_string(),
+            token_url: "https://login.microsoftonline.com/common/oauth2/v2.0/token".to_string(),
+            client_id: client_id.into(),
+            client_secret: None,
+            scopes: [
+                "https://outlook.office.com/IMAP.AccessAsUser.All",
+                "https://outlook.office.com/SMTP.Send",
+                "offline_access",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+
+    /// Starts an authorization. The provider will redirect the browser to
+    /// `redirect_uri` once the user has consented.
+    pub fn authorize(&self, redirect_uri: &str) -> Result<Authorization> {
+        let verifier = random_string(32)?;
+        let state = random_string(16)?;
+        let challenge = BASE64_URL_SAFE_NO_PAD.encode(digest(&SHA256, verifier.as_bytes()));
+        let scope = self.scopes.join(" ");
+        let query = form_encode(&[
+            ("response_type", "code"),
+            ("client_id", &self.client_id),
+            ("redirect_uri", redirect_uri),
+            ("scope", &scope),
+            ("state", &state),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+            // Asks Google for a refresh token; others ignore it.
+            ("access_type", "offline"),
+        ]);
+        Ok(Authorization {
+            url: format!("{}?{}", self.authorization_url, query),
+            redirect_uri: redirect_uri.to_string(),
+            state,
+            verifier,
+        })
+    }
+
+    /// The form to post to the token endpoint to exchange `code` for the
+    /// tokens.
+    pub fn token_request(&self, authorization: &Authorization, code: &str) -> String {
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &authorization.redirect_uri),
+            ("client_id", &self.client_id),
+            ("code_verifier", &authorization.verifier),
+        ];
+        if let Some(secret) = &self.client_secret {
+            form.push(("client_secret", secret));
+        }
+        form_encode(&form)
+    }
+
+    /// The form to post to the token endpoint for a new access token.
+    pub fn refresh_request(&self, refresh_token: &str) -> String {
+        let mut form = vec![
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+            ("client_id", &self.client_id),
+        ];
+        if let Some(secret) = &self.client_secret {
+            form.push(("client_secret", secret));
+        }
+        form_encode(&form)
+    }
+}
+
+/// An authorization in progress.
+#[derive(Debug, Clone)]
+pub struct Authorization {
+    /// The provider's consent page, to open in a browser.
+    pub url: String,
+    pub redirect_uri: String,
+    state: String,
+    verifier: String,
+}
+
+impl Authorization {
+    /// The code in `redirect`, the URL the provider redirected to. Fails if
+    /// the user declined, or the redirect isn't the answer to this
+    /// authorization.
+    pub fn code(&self, redirect: &str) -> Result<String> {
+        let query = redirect.split_once('?').map_or("", |(_, query)| query);
+        let query = query.split('#').next().unwrap_or_default();
+        let param = |name: &str| {
+            query.split('&').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                (key == name).then(|| percent_decode(&value.replace('+', " ")))?
+            })
+        };
+        if let Some(error) = param("error") {
+            let description = param("error_description").unwrap_or_default();
+            return Err(MailinerError::Authentication(format!("{} {}", error, description).trim_end().to_string()));
+        }
+        if param("state").as_deref() != Some(self.state.as_str()) {
+            return Err(MailinerError::Authentication(
+                "The authorization was not answered for this request".to_string(),
+            ));
+        }
+        param("code").ok_or_else(|| {
+            MailinerError::Authentication("The provider didn't send an authorization code".to_string())
+        })
+    }
+}
+
+/// Parses the answer of the token endpoint, received at `now`.
+pub fn parse_token_response(body: &[u8], now: DateTime<Utc>) -> Result<OAuthToken> {
+    #[derive(Deserialize)]
+    struct Response {
+        access_token: Option<String>,
+        refresh_token: Option<String>,
+        expires_in: Option<i64>,
+        error: Option<String>,
+        error_description: Option<String>,
+    }
+
+    let response: Response = serde_json::from_slice(body)?;
+    match (response.access_token, response.error) {
+        (Some(access_token), None) => Ok(OAuthToken {
+            access_token,
+            refresh_token: response.refresh_token,
+            expires_at: response.expires_in.map(|secs| now + Duration::seconds(secs)),
+        }),
+        (_, error) => Err(MailinerError::Authentication(
+            response
+                .error_description
+                .or(error)
+                .unwrap_or_else(|| "The provider sent no access token".to_string()),
+        )),
+    }
+}
+
+/// Initial response of Google's and Microsoft's XOAUTH2 mechanism, before
+/// base64 encoding.
+pub fn xoauth2_response(username: &str, token: &str) -> String {
+    format!("user={}\x01auth=Bearer {}\x01\x01", username, token)
+}
+
+/// Initial response of the OAUTHBEARER mechanism (RFC 7628), before base64
+/// encoding.
+pub fn oauthbearer_response(username: &str, host: &str, port: u16, token: &str) -> String {
+    // The authorization identity is a saslname, which escapes "," and "=".
+    let authzid = username.replace('=', "=3D").replace(',', "=2C");
+    format!(
+        "n,a={},\x01host={}\x01port={}\x01auth=Bearer {}\x01\x01",
+        authzid, host, port, token
+    )
+}
+
+/// `len` random bytes, base64url-encoded, which only has characters allowed
+/// in a PKCE verifier.
+fn random_string(len: usize) -> Result<String> {
+    let mut bytes = vec![0u8; len];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| MailinerError::Storage("Failed to generate random data".to_string()))?;
+    Ok(BASE64_URL_SAFE_NO_PAD.encode(bytes))
+}
+
+/// Encodes `application/x-www-form-urlencoded` data, also used for the
+/// query of the consent page.
+fn form_encode(pairs: &[(&str, &str)]) -> String {
+    let encode = |text: &str| {
+        text.bytes()
+            .map(|byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+                b' ' => "+".to_string(),
+                _ => format!("%{:02X}", byte),
+            })
+            .collect::<String>()
+    };
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", encode(name), encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sasl_initial_responses() {
+        assert_eq!(
+            xoauth2_response(
+                "someuser@example.com",
+                "ya29.vF9dft4qmTc2Nvb3RlckBhdHRhdmlzdGEuY29tCg"
+            ),
+            "user=someuser@example.com\x01auth=Bearer ya29.vF9dft4qmTc2Nvb3RlckBhdHRhdmlzdGEuY29tCg\x01\x01"
+        );
+        assert_eq!(
+            oauthbearer_response(
+                "user@example.com",
+                "server.example.com",
+                143,
+                "vF9dft4qmTc2Nvb3RlckBhdHRhdmlzdGEuY29tCg=="
+            ),
+            "n,a=user@example.com,\x01host=server.example.com\x01port=143\x01auth=Bearer vF9dft4qmTc2Nvb3RlckBhdHRhdmlzdGEuY29tCg==\x01\x01"
+        );
+        assert_eq!(
+            oauthbearer_response("a=b,c", "server.example.com", 143, "t0ken"),
+            "n,a=a=3Db=2Cc,\x01host=server.example.com\x01port=143\x01auth=Bearer t0ken\x01\x01"
+        );
+    }
+
+    #[test]
+    fn expiry() {
+        let now = Utc::now();
+        let token = |expires_at| OAuthToken {
+            access_token: "token".to_string(),
+            refresh_token: None,
+            expires_at,
+        };
+        assert!(!token(None).is_expired(now));
+        assert!(!token(Some(now + Duration::minutes(5))).is_expired(now));
+        assert!(token(Some(now + Duration::seconds(30))).is_expired(now));
+        assert!(token(Some(now - Duration::minutes(5))).is_expired(now));
+    }
+
+    #[test]
+    fn authorization_code_flow() {
+        let client = OAuthClient::google("app.example", Some("s3cret".to_string()));
+        let authorization = client.authorize("http://127.0.0.1:8080/").unwrap();
+        let challenge = BASE64_URL_SAFE_NO_PAD.encode(digest(&SHA256, authorization.verifier.as_bytes()));
+        assert_eq!(authorization.verifier.len(), 43);
+        assert!(authorization.url.starts_with(
+            "https://accounts.google.com/o/oauth2/v2/auth?response_type=code&client_id=app.example\
+             &redirect_uri=http%3A%2F%2F127.0.0.1%3A8080%2F&scope=https%3A%2F%2Fmail.google.com%2F&state="
+        ));
+        assert!(authorization.url.contains(&format!("&code_challenge={}&code_challenge_method=S256", challenge)));
+
+        let redirect = |query: &str| format!("http://127.0.0.1:8080/?{}", query);
+        let state = authorization.state.clone();
+        assert_eq!(
+            authorization.code(&redirect(&format!("state={}&code=4%2F0Ab&scope=x", state))).unwrap(),
+            "4/0Ab"
+        );
+        assert!(authorization.code(&redirect("state=other&code=4%2F0Ab")).is_err());
+        let declined = authorization.code(&redirect(&format!("error=access_denied&state={}", state)));
+        assert!(matches!(declined, Err(MailinerError::Authentication(message)) if message == "access_denied"));
+
+        assert_eq!(
+            client.token_request(&authorization, "4/0Ab"),
+            format!(
+                "grant_type=authorization_code&code=4%2F0Ab&redirect_uri=http%3A%2F%2F127.0.0.1%3A8080%2F\
+                 &client_id=app.example&code_verifier={}&client_secret=s3cret",
+                authorization.verifier
+            )
+        );
+    }
+
+    #[test]
+    fn token_response() {
+        let now = Utc::now();
+        let token = parse_token_response(
+            br#"{"access_token": "ya29", "expires_in": 3599, "refresh_token": "1//0g", "token_type": "Bearer"}"#,
+            now,
+        )
+        .unwrap();
+        assert_eq!(token.access_token, "ya29");
+        assert_eq!(token.refresh_token.as_deref(), Some("1//0g"));
+        assert_eq!(token.expires_at, Some(now + Duration::seconds(3599)));
+
+        let error = parse_token_response(
+            br#"{"error": "invalid_grant", "error_description": "Bad Request"}"#,
+            now,
+        );
+        assert!(error.unwrap_err().to_string().contains("Bad Request"));
+    }
+}
@@ -0,0 +1,65 @@
+//! Redirects to the app on the desktop, where the consent page opens in the
+//! system browser and the provider redirects it to a port on the loopback
+//! interface (RFC 8252, section 7.3).
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::error::{MailinerError, Result};
+
+/// The longest request the browser is expected to send.
+const MAX_REQUEST_LEN: usize = 16 * 1024;
+
+const RESPONSE: &str = "HTTP/1.1 200 OK\r\n\
+    Content-Type: text/html; charset=utf-8\r\n\
+    Connection: close\r\n\r\n\
+    <!DOCTYPE html><title>Mailiner</title>\
+    <p>You are signed in. You can close this window and return to Mailiner.</p>";
+
+/// A port waiting for the browser to be redirected to it.
+pub struct LoopbackRedirect {
+    listener: TcpListener,
+    redirect_uri: String,
+}
+
+impl LoopbackRedirect {
+    /// Listens on a free port.
+    pub async fn bind() -> Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let redirect_uri = format!("http://127.0.0.1:{}/", listener.local_addr()?.port());
+        Ok(Self { listener, redirect_uri })
+    }
+
+    /// The redirect URI to authorize with, see
+    /// [`OAuthClient::authorize`](super::OAuthClient::authorize).
+    pub fn redirect_uri(&self) -> &str {
+        &self.redirect_uri
+    }
+
+    /// Waits for the redirect, tells the user they can go back to the app,
+    /// and returns the URL the browser was redirected to.
+    pub async fn wait(self) -> Result<String> {
+        let (mut stream, _) = self.listener.accept().await?;
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            let read = stream.read(&mut buf).await?;
+            if read == 0 || request.len() > MAX_REQUEST_LEN {
+                break;
+            }
+            request.extend_from_slice(&buf[..read]);
+        }
+        stream.write_all(RESPONSE.as_bytes()).await?;
+        stream.shutdown().await?;
+
+        // The request line, e.g. `GET /?code=...&state=... HTTP/1.1`.
+        let request = String::from_utf8_lossy(&request);
+        let target = request
+            .lines()
+            .next()
+            .and_then(|line| line.split(' ').nth(1))
+            .ok_or_else(|| MailinerError::InvalidData("Invalid redirect request".to_string()))?;
+        Ok(format!("{}{}", self.redirect_uri.trim_end_matches('/'), target))
+    }
+}
+
@@ -0,0 +1,132 @@
+//! Abstraction over the services provided by the host platform.
+//!
+//! Code in the core and in the connectors must not branch on the compilation
+//! target. Instead it receives a [`Platform`] whose implementations are chosen
+//! by cargo features: `native` for desktop/server builds and `web` for the
+//! browser (WASM) build.
+
+use std::fmt::Debug;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::error::Result;
+
+#[cfg(feature = "native")]
+pub mod native;
+#[cfg(feature = "web")]
+pub mod web;
+
+/// Byte-oriented file storage. Paths are `/`-separated and relative to an
+/// implementation-defined root (a data directory natively, a key prefix in the
+/// browser).
+#[async_trait]
+pub trait FileSystem: Send + Sync {
+    async fn read(&self, path: &str) -> Result<Vec<u8>>;
+    async fn write(&self, path: &str, data: &[u8]) -> Result<()>;
+    async fn remove(&self, path: &str) -> Result<()>;
+    async fn exists(&self, path: &str) -> Result<bool>;
+    /// Lists names of the entries (files and directories) directly inside `path`.
+    async fn list(&self, path: &str) -> Result<Vec<String>>;
+
+    /// Reads the bytes of a file in `range`, fewer if the file ends before.
+    /// By default the whole file is read, which implementations should
+    /// override for large files.
+    async fn read_range(&self, path: &str, range: Range<u64>) -> Result<Vec<u8>> {
+        let data = self.read(path).await?;
+        let end = range.end.min(data.len() as u64) as usize;
+        let start = range.start.min(end as u64) as usize;
+        Ok(data[start..end].to_vec())
+    }
+
+    /// Moves a file or a directory. By default files are copied, which
+    /// implementations with real directories should override.
+    async fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let data = self.read(from).await?;
+        self.write(to, &data).await?;
+        self.remove(from).await
+    }
+
+    /// Creates a directory and its parents. Directories are implicit by
+    /// default, they exist as long as there are files in them.
+    async fn create_dir(&self, _path: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Removes a directory and everything in it.
+    async fn remove_dir(&self, path: &str) -> Result<()> {
+        for name in self.list(path).await? {
+            let child = format!("{}/{}", path, name);
+            if self.exists(&child).await? {
+                self.remove(&child).await?;
+            } else {
+                self.remove_dir(&child).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Storage for credentials and other secrets.
+#[async_trait]
+pub trait SecureStorage: Send + Sync {
+    async fn get_secret(&self, key: &str) -> Result<Option<String>>;
+    async fn set_secret(&self, key: &str, value: &str) -> Result<()>;
+    async fn delete_secret(&self, key: &str) -> Result<()>;
+}
+
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+}
+
+/// Shows user-facing (desktop or browser) notifications.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, notification: &Notification) -> Result<()>;
+}
+
+#[async_trait]
+pub trait Timer: Send + Sync {
+    async fn sleep(&self, duration: Duration);
+}
+
+/// Opens byte streams to remote servers, e.g. TCP sockets natively or a
+/// WebSocket proxy in the browser.
+#[async_trait]
+pub trait Network<S>: Send + Sync
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    async fn connect(&self, host: &str, port: u16) -> Result<S>;
+}
+
+/// Bundle of all platform services.
+pub struct Platform<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    pub fs: Arc<dyn FileSystem>,
+    pub secure_storage: Arc<dyn SecureStorage>,
+    pub notifier: Arc<dyn Notifier>,
+    pub timer: Arc<dyn Timer>,
+    pub network: Arc<dyn Network<S>>,
+}
+
+impl<S> Clone for Platform<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    fn clone(&self) -> Self {
+        Self {
+            fs: Arc::clone(&self.fs),
+            secure_storage: Arc::clone(&self.secure_storage),
+            notifier: Arc::clone(&self.notifier),
+            timer: Arc::clone(&self.timer),
+            network: Arc::clone(&self.network),
+        }
+    }
+}
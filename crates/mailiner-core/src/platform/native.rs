@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::io::{self, SeekFrom};
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::net::TcpStream;
+use tokio::sync::{Mutex, MutexGuard};
+
+use super::{FileSystem, Network, Notification, Notifier, Platform, SecureStorage, Timer};
+use crate::error::{MailinerError, Result};
+
+/// Creates the native platform with all files stored under `data_dir`.
+pub fn platform(data_dir: impl Into<PathBuf>) -> Platform<TcpStream> {
+    let data_dir = data_dir.into();
+    Platform {
+        secure_storage: Arc::new(FileSecureStorage::new(data_dir.join("secrets.json"))),
+        fs: Arc::new(NativeFileSystem::new(data_dir)),
+        notifier: Arc::new(CommandNotifier),
+        timer: Arc::new(TokioTimer),
+        network: Arc::new(TcpNetwork),
+    }
+}
+
+pub struct NativeFileSystem {
+    root: PathBuf,
+}
+
+impl NativeFileSystem {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let relative = Path::new(path);
+        if relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(MailinerError::InvalidData(format!("Invalid path: {}", path)));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+fn not_found_or_io(path: &str, err: io::Error) -> MailinerError {
+    if err.kind() == io::ErrorKind::NotFound {
+        MailinerError::NotFound(format!("File {}", path))
+    } else {
+        MailinerError::Io(err)
+    }
+}
+
+#[async_trait]
+impl FileSystem for NativeFileSystem {
+    async fn read(&self, path: &str) -> Result<Vec<u8>> {
+        tokio::fs::read(self.resolve(path)?)
+            .await
+            .map_err(|e| not_found_or_io(path, e))
+    }
+
+    async fn write(&self, path: &str, data: &[u8]) -> Result<()> {
+        let target = self.resolve(path)?;
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Write to a temporary file first so that a crash never leaves a truncated file behind.
+        let tmp = target.with_extension("tmp");
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, &target).await?;
+        Ok(())
+    }
+
+    async fn remove(&self, path: &str) -> Result<()> {
+        tokio::fs::remove_file(self.resolve(path)?)
+            .await
+            .map_err(|e| not_found_or_io(path, e))
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool> {
+        Ok(tokio::fs::try_exists(self.resolve(path)?).await?)
+    }
+
+    async fn list(&self, path: &str) -> Result<Vec<String>> {
+        let dir = if path.is_empty() {
+            self.root.clone()
+        } else {
+            self.resolve(path)?
+        };
+        let mut entries = tokio::fs::read_dir(dir)
+            .await
+            .map_err(|e| not_found_or_io(path, e))?;
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+        Ok(names)
+    }
+
+    async fn read_range(&self, path: &str, range: Range<u64>) -> Result<Vec<u8>> {
+        let mut file = tokio::fs::File::open(self.resolve(path)?)
+            .await
+            .map_err(|e| not_found_or_io(path, e))?;
+        file.seek(SeekFrom::Start(range.start)).await?;
+        let mut data = Vec::new();
+        file.take(range.end.saturating_sub(range.start))
+            .read_to_end(&mut data)
+            .await?;
+        Ok(data)
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let target = self.resolve(to)?;
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::rename(self.resolve(from)?, target)
+            .await
+            .map_err(|e| not_found_or_io(from, e))
+    }
+
+    async fn create_dir(&self, path: &str) -> Result<()> {
+        Ok(tokio::fs::create_dir_all(self.resolve(path)?).await?)
+    }
+
+    async fn remove_dir(&self, path: &str) -> Result<()> {
+        tokio::fs::remove_dir_all(self.resolve(path)?)
+            .await
+            .map_err(|e| not_found_or_io(path, e))
+    }
+}
+
+/// Keeps secrets in a JSON file that only the current user can read.
+///
+/// This is a stopgap until the OS keychains are supported.
+pub struct FileSecureStorage {
+    path: PathBuf,
+    secrets: Mutex<Option<HashMap<String, String>>>,
+}
+
+impl FileSecureStorage {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            secrets: Mutex::new(None),
+        }
+    }
+
+    /// Locks the secrets, reading them from disk on first use.
+    async fn loaded(&self) -> Result<MutexGuard<'_, Option<HashMap<String, String>>>> {
+        let mut secrets = self.secrets.lock().await;
+        if secrets.is_none() {
+            *secrets = Some(self.load().await?);
+        }
+        Ok(secrets)
+    }
+
+    async fn load(&self) -> Result<HashMap<String, String>> {
+        match tokio::fs::read(&self.path).await {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn store(&self, secrets: &HashMap<String, String>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&self.path, serde_json::to_vec(secrets)?).await?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(0o600)).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SecureStorage for FileSecureStorage {
+    async fn get_secret(&self, key: &str) -> Result<Option<String>> {
+        let mut secrets = self.loaded().await?;
+        Ok(secrets.get_or_insert_with(HashMap::new).get(key).cloned())
+    }
+
+    async fn set_secret(&self, key: &str, value: &str) -> Result<()> {
+        let mut secrets = self.loaded().await?;
+        let secrets = secrets.get_or_insert_with(HashMap::new);
+        secrets.insert(key.to_string(), value.to_string());
+        self.store(secrets).await
+    }
+
+    async fn delete_secret(&self, key: &str) -> Result<()> {
+        let mut secrets = self.loaded().await?;
+        let secrets = secrets.get_or_insert_with(HashMap::new);
+        if secrets.remove(key).is_some() {
+            self.store(secrets).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Shows desktop notifications through the platform's command line tools.
+pub struct CommandNotifier;
+
+impl Notifier for CommandNotifier {
+    fn notify(&self, notification: &Notification) -> Result<()> {
+        let mut command = if cfg!(target_os = "macos") {
+            let mut command = std::process::Command::new("osascript");
+            command.arg("-e").arg(format!(
+                "display notification {:?} with title {:?}",
+                notification.body, notification.title
+            ));
+            command
+        } else if cfg!(unix) {
+            let mut command = std::process::Command::new("notify-send");
+            command.arg(&notification.title).arg(&notification.body);
+            command
+        } else {
+            return Ok(());
+        };
+
+        let mut child = command.spawn()?;
+        // Reap the child in the background so that we don't leave zombies behind.
+        std::thread::spawn(move || child.wait());
+        Ok(())
+    }
+}
+
+pub struct TokioTimer;
+
+#[async_trait]
+impl Timer for TokioTimer {
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+pub struct TcpNetwork;
+
+#[async_trait]
+impl Network<TcpStream> for TcpNetwork {
+    async fn connect(&self, host: &str, port: u16) -> Result<TcpStream> {
+        Ok(TcpStream::connect((host, port)).await?)
+    }
+}
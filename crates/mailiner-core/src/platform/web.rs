@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::prelude::*;
+use send_wrapper::SendWrapper;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{NotificationOptions, NotificationPermission};
+
+use super::{FileSystem, Notification, Notifier, SecureStorage, Timer};
+use crate::error::{MailinerError, Result};
+
+fn local_storage() -> Result<web_sys::Storage> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .ok_or_else(|| MailinerError::Storage("localStorage is not available".to_string()))
+}
+
+fn js_error(err: wasm_bindgen::JsValue) -> MailinerError {
+    MailinerError::Storage(format!("{:?}", err))
+}
+
+/// File storage backed by `localStorage`, with file contents stored base64-encoded.
+pub struct LocalStorageFileSystem {
+    prefix: String,
+}
+
+impl LocalStorageFileSystem {
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+
+    fn key(&self, path: &str) -> String {
+        format!("{}/fs/{}", self.prefix, path)
+    }
+}
+
+#[async_trait]
+impl FileSystem for LocalStorageFileSystem {
+    async fn read(&self, path: &str) -> Result<Vec<u8>> {
+        let data = local_storage()?
+            .get_item(&self.key(path))
+            .map_err(js_error)?
+            .ok_or_else(|| MailinerError::NotFound(format!("File {}", path)))?;
+        BASE64_STANDARD
+            .decode(data)
+            .map_err(|e| MailinerError::InvalidData(format!("Corrupted file {}: {}", path, e)))
+    }
+
+    async fn write(&self, path: &str, data: &[u8]) -> Result<()> {
+        local_storage()?
+            .set_item(&self.key(path), &BASE64_STANDARD.encode(data))
+            .map_err(js_error)
+    }
+
+    async fn remove(&self, path: &str) -> Result<()> {
+        local_storage()?.remove_item(&self.key(path)).map_err(js_error)
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool> {
+        Ok(local_storage()?
+            .get_item(&self.key(path))
+            .map_err(js_error)?
+            .is_some())
+    }
+
+    async fn list(&self, path: &str) -> Result<Vec<String>> {
+        let storage = local_storage()?;
+        let dir = self.key(path);
+        let dir = if path.is_empty() { dir } else { format!("{}/", dir) };
+
+        let mut names = Vec::new();
+        for index in 0..storage.length().map_err(js_error)? {
+            let Some(key) = storage.key(index).map_err(js_error)? else {
+                continue;
+            };
+            // Directories are implicit, they show up as the first component of nested keys.
+            if let Some(name) = key.strip_prefix(&dir).and_then(|rest| rest.split('/').next()) {
+                if !name.is_empty() && !names.iter().any(|n| n == name) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        Ok(names)
+    }
+}
+
+/// Secret storage backed by `localStorage`.
+///
+/// Browsers don't offer a keychain to web pages, so secrets are only protected
+/// by the same-origin policy.
+pub struct LocalStorageSecureStorage {
+    prefix: String,
+}
+
+impl LocalStorageSecureStorage {
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}/secret/{}", self.prefix, key)
+    }
+}
+
+#[async_trait]
+impl SecureStorage for LocalStorageSecureStorage {
+    async fn get_secret(&self, key: &str) -> Result<Option<String>> {
+        local_storage()?.get_item(&self.key(key)).map_err(js_error)
+    }
+
+    async fn set_secret(&self, key: &str, value: &str) -> Result<()> {
+        local_storage()?
+            .set_item(&self.key(key), value)
+            .map_err(js_error)
+    }
+
+    async fn delete_secret(&self, key: &str) -> Result<()> {
+        local_storage()?.remove_item(&self.key(key)).map_err(js_error)
+    }
+}
+
+/// Shows notifications through the Web Notifications API.
+///
+/// Notifications are silently dropped until the user grants the permission,
+/// see [`WebNotifier::request_permission`].
+pub struct WebNotifier;
+
+impl WebNotifier {
+    pub fn request_permission() {
+        let _ = web_sys::Notification::request_permission();
+    }
+}
+
+impl Notifier for WebNotifier {
+    fn notify(&self, notification: &Notification) -> Result<()> {
+        if web_sys::Notification::permission() != NotificationPermission::Granted {
+            return Ok(());
+        }
+
+        let options = NotificationOptions::new();
+        options.set_body(&notification.body);
+        web_sys::Notification::new_with_options(&notification.title, &options)
+            .map(|_| ())
+            .map_err(js_error)
+    }
+}
+
+/// Timer based on `setTimeout`, which unlike tokio's timers works in the browser.
+pub struct WebTimer;
+
+#[async_trait]
+impl Timer for WebTimer {
+    async fn sleep(&self, duration: Duration) {
+        let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+            if let Some(window) = web_sys::window() {
+                let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+                    &resolve,
+                    duration.as_millis().min(i32::MAX as u128) as i32,
+                );
+            }
+        });
+        // JS futures are not Send, but the browser only has a single thread.
+        let _ = SendWrapper::new(JsFuture::from(promise)).await;
+    }
+}
@@ -0,0 +1,321 @@
+//! Connectors picked by the URI of an account's backend, e.g.
+//! `imaps://me@imap.example.com` or `maildir:///home/me/Mail`.
+//!
+//! Connector crates register a [`ConnectorFactory`] for their schemes, so
+//! the application creates connectors from the URIs stored in
+//! [`AccountPreferences::backend`](crate::AccountPreferences::backend)
+//! without knowing the backends it was built with.
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::connector::EmailConnector;
+use crate::error::{MailinerError, Result};
+use crate::models::{ConnectionSecurity, ServerSettings};
+
+/// A parsed backend URI, `scheme://[user@]host[:port]/path?name=value`.
+/// The host is empty for local backends, as in `maildir:///home/me/Mail`.
+/// The user, path and parameters are percent-decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendUri {
+    /// Lowercase, e.g. `imaps`.
+    pub scheme: String,
+    pub username: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    pub path: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl BackendUri {
+    /// The URI of the IMAP server of an
+    /// [`AccountConfig`](crate::AccountConfig): `imaps://` with TLS or
+    /// `imap://` with STARTTLS. Unencrypted connections are not supported.
+    pub fn imap(server: &ServerSettings) -> Result<Self> {
+        let mut params = Vec::new();
+        if let Some(method) = server.auth_method {
+            params.push(("auth".to_string(), method.name().to_string()));
+        }
+        let scheme = match server.security {
+            ConnectionSecurity::Tls => "imaps",
+            ConnectionSecurity::StartTls => "imap",
+            ConnectionSecurity::None => {
+                return Err(MailinerError::InvalidData(format!(
+                    "Unencrypted connections to {} are not supported",
+                    server.host
+                )));
+            }
+        };
+        Ok(Self {
+            scheme: scheme.to_string(),
+            username: Some(server.username.clone()),
+            host: server.host.clone(),
+            port: Some(server.port),
+            path: String::new(),
+            params,
+        })
+    }
+
+    /// The value of the first parameter called `name`.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl FromStr for BackendUri {
+    type Err = MailinerError;
+
+    fn from_str(uri: &str) -> Result<Self> {
+        let invalid = || MailinerError::InvalidData(format!("Invalid backend URI: {}", uri));
+        let (scheme, rest) = uri.split_once("://").ok_or_else(invalid)?;
+        if scheme.is_empty()
+            || !scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        {
+            return Err(invalid());
+        }
+        let (rest, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let (authority, path) = match rest.find('/') {
+            Some(slash) => rest.split_at(slash),
+            None => (rest, ""),
+        };
+        let (username, host) = match authority.rsplit_once('@') {
+            Some((username, host)) => (Some(percent_decode(username).ok_or_else(invalid)?), host),
+            None => (None, authority),
+        };
+        let (host, port) = match host.rsplit_once(':') {
+            // An IPv6 address without a port, `[::1]`.
+            Some((_, port)) if port.ends_with(']') => (host, None),
+            Some((host, port)) => (host, Some(port.parse().map_err(|_| invalid())?)),
+            None => (host, None),
+        };
+        let params = query
+            .split('&')
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let (name, value) = param.split_once('=').unwrap_or((param, ""));
+                Some((percent_decode(name)?, percent_decode(value)?))
+            })
+            .collect::<Option<_>>()
+            .ok_or_else(invalid)?;
+
+        Ok(Self {
+            scheme: scheme.to_ascii_lowercase(),
+            username,
+            host: host.to_string(),
+            port,
+            path: percent_decode(path).ok_or_else(invalid)?,
+            params,
+        })
+    }
+}
+
+impl fmt::Display for BackendUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}://", self.scheme)?;
+        if let Some(username) = &self.username {
+            write!(f, "{}@", percent_encode(username, "@:/?#&=%"))?;
+        }
+        f.write_str(&self.host)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        f.write_str(&percent_encode(&self.path, "?#%"))?;
+        for (i, (name, value)) in self.params.iter().enumerate() {
+            write!(
+                f,
+                "{}{}={}",
+                if i == 0 { '?' } else { '&' },
+                percent_encode(name, "#&=%"),
+                percent_encode(value, "#&=%")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn percent_encode(text: &str, reserved: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if reserved.contains(c) || c.is_whitespace() {
+                format!("%{:02X}", c as u32)
+            } else {
+                c.to_string()
+            }
+        })
+        .collect()
+}
+
+/// Creates connectors for the URIs of one or more schemes. Functions and
+/// closures with the same signature are factories too.
+pub trait ConnectorFactory<S>: Send + Sync
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    fn create(&self, uri: &BackendUri) -> Result<Arc<dyn EmailConnector<S>>>;
+}
+
+impl<S, F> ConnectorFactory<S> for F
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+    F: Fn(&BackendUri) -> Result<Arc<dyn EmailConnector<S>>> + Send + Sync,
+{
+    fn create(&self, uri: &BackendUri) -> Result<Arc<dyn EmailConnector<S>>> {
+        self(uri)
+    }
+}
+
+/// The connector factories available to the application, by scheme.
+pub struct ConnectorRegistry<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    factories: HashMap<String, Arc<dyn ConnectorFactory<S>>>,
+}
+
+impl<S> Default for ConnectorRegistry<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> ConnectorRegistry<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    pub fn new() -> Self {
+        Self {
+            factories: HashMap::new(),
+        }
+    }
+
+    /// Registers `factory` for `scheme`, replacing the factory registered
+    /// for it before.
+    pub fn register(
+        &mut self,
+        scheme: impl Into<String>,
+        factory: impl ConnectorFactory<S> + 'static,
+    ) {
+        self.factories
+            .insert(scheme.into().to_ascii_lowercase(), Arc::new(factory));
+    }
+
+    /// The registered schemes, sorted.
+    pub fn schemes(&self) -> Vec<&str> {
+        let mut schemes: Vec<&str> = self.factories.keys().map(String::as_str).collect();
+        schemes.sort_unstable();
+        schemes
+    }
+
+    /// Creates the connector for `uri` with the factory of its scheme.
+    pub fn create(&self, uri: &str) -> Result<Arc<dyn EmailConnector<S>>> {
+        let uri: BackendUri = uri.parse()?;
+        let factory = self.factories.get(&uri.scheme).ok_or_else(|| {
+            MailinerError::NotFound(format!("No connector for {}:// URIs", uri.scheme))
+        })?;
+        factory.create(&uri)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::AuthMethod;
+
+    #[test]
+    fn parses_backend_uris() {
+        let uri: BackendUri = "IMAPS://me%40example.com@imap.example.com:1993/?auth=xoauth2"
+            .parse()
+            .unwrap();
+        assert_eq!(uri.scheme, "imaps");
+        assert_eq!(uri.username.as_deref(), Some("me@example.com"));
+        assert_eq!(uri.host, "imap.example.com");
+        assert_eq!(uri.port, Some(1993));
+        assert_eq!(uri.path, "/");
+        assert_eq!(uri.param("auth"), Some("xoauth2"));
+        assert_eq!(
+            uri.to_string(),
+            "imaps://me%40example.com@imap.example.com:1993/?auth=xoauth2"
+        );
+
+        let uri: BackendUri = "maildir:///home/me/My%20Mail?email=me@example.com"
+            .parse()
+            .unwrap();
+        assert_eq!((uri.host.as_str(), uri.port), ("", None));
+        assert_eq!(uri.path, "/home/me/My Mail");
+        assert_eq!(uri.param("email"), Some("me@example.com"));
+        assert_eq!(uri.to_string().parse::<BackendUri>().unwrap(), uri);
+
+        let mut server = ServerSettings {
+            host: "imap.example.com".to_string(),
+            port: 143,
+            security: ConnectionSecurity::StartTls,
+            username: "me@example.com".to_string(),
+            auth_method: Some(AuthMethod::CramMd5),
+        };
+        assert_eq!(
+            BackendUri::imap(&server).unwrap().to_string(),
+            "imap://me%40example.com@imap.example.com:143?auth=cram-md5"
+        );
+        server.security = ConnectionSecurity::None;
+        assert!(BackendUri::imap(&server).is_err());
+        assert_eq!("CRAM-MD5".parse::<AuthMethod>().unwrap(), AuthMethod::CramMd5);
+
+        let uri: BackendUri = "imap://[::1]".parse().unwrap();
+        assert_eq!((uri.host.as_str(), uri.port), ("[::1]", None));
+
+        for invalid in [
+            "imap.example.com",
+            "imap://host:port",
+            "://host",
+            "x://a/%zz",
+        ] {
+            assert!(invalid.parse::<BackendUri>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn creates_connectors_by_scheme() {
+        use crate::connector::MockConnector;
+
+        let mut registry = ConnectorRegistry::<tokio::io::DuplexStream>::new();
+        registry.register("Mock", |_: &BackendUri| {
+            Ok(Arc::new(MockConnector::new()) as Arc<dyn EmailConnector<_>>)
+        });
+        assert_eq!(registry.schemes(), ["mock"]);
+        assert!(registry.create("mock://anything").is_ok());
+        assert!(matches!(
+            registry.create("jmap://jmap.example.com"),
+            Err(MailinerError::NotFound(_))
+        ));
+    }
+}
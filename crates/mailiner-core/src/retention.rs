@@ -0,0 +1,115 @@
+//! Policies that clean up a folder on their own, e.g. archiving read mail
+//! after a month or purging Junk after a week.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{MailinerError, Result};
+use crate::ids::FolderId;
+use crate::models::Envelope;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RetentionAction {
+    /// Moves the messages to the folder.
+    MoveTo(FolderId),
+    /// Removes the messages for good.
+    Purge,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    pub folder_id: FolderId,
+    /// Acts on messages older than this.
+    pub older_than_days: u32,
+    /// Leaves unread messages alone.
+    pub only_read: bool,
+    pub action: RetentionAction,
+}
+
+impl RetentionPolicy {
+    /// Whether the policy acts on the message at `now`. Flagged and snoozed
+    /// messages are kept.
+    pub fn applies_to(&self, envelope: &Envelope, now: DateTime<Utc>) -> bool {
+        let cutoff = now - Duration::days(self.older_than_days.into());
+        envelope.folder_id == self.folder_id
+            && envelope.date < cutoff
+            && (envelope.is_read || !self.only_read)
+            && !envelope.is_flagged
+            && !envelope.is_snoozed(now)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.action == RetentionAction::MoveTo(self.folder_id.clone()) {
+            return Err(MailinerError::InvalidData(format!(
+                "Retention policy of {} moves to the same folder",
+                self.folder_id
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::ids::{AccountId, MessageId};
+
+    fn envelope(days: i64, is_read: bool) -> Envelope {
+        Envelope {
+            id: MessageId::new("INBOX;UID=1"),
+            account_id: AccountId::new("imap-jan"),
+            folder_id: FolderId::new("INBOX"),
+            subject: None,
+            from: None,
+            to: None,
+            cc: None,
+            bcc: None,
+            reply_to: None,
+            date: Utc::now() - Duration::days(days),
+            is_read,
+            is_starred: false,
+            is_flagged: false,
+            is_draft: false,
+            is_deleted: false,
+            has_attachments: false,
+            size: None,
+            keywords: BTreeSet::new(),
+            labels: BTreeSet::new(),
+            snoozed_until: None,
+            thread_id: None,
+            remote_id: None,
+            message_id: None,
+            in_reply_to: Vec::new(),
+            references: Vec::new(),
+            unsubscribe: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn archives_old_read_mail() {
+        let policy = RetentionPolicy {
+            folder_id: FolderId::new("INBOX"),
+            older_than_days: 30,
+            only_read: true,
+            action: RetentionAction::MoveTo(FolderId::new("Archive")),
+        };
+        let now = Utc::now();
+        assert!(policy.applies_to(&envelope(31, true), now));
+        assert!(!policy.applies_to(&envelope(31, false), now));
+        assert!(!policy.applies_to(&envelope(29, true), now));
+        let mut flagged = envelope(31, true);
+        flagged.is_flagged = true;
+        assert!(!policy.applies_to(&flagged, now));
+        assert!(policy.validate().is_ok());
+
+        let invalid = RetentionPolicy {
+            action: RetentionAction::MoveTo(FolderId::new("INBOX")),
+            ..policy
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
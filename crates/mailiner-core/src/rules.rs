@@ -0,0 +1,205 @@
+//! Filter rules, which act on new messages as they are synced, e.g. to file
+//! the mail of a mailing list into its own folder.
+
+use serde::{Deserialize, Serialize};
+
+use crate::delivery::header_value;
+use crate::error::{MailinerError, Result};
+use crate::ids::{AccountId, FolderId, RuleId};
+use crate::models::{EmailAddress, Envelope};
+
+/// What a message has to look like for a rule to act on it. Texts are
+/// matched anywhere in the field, ignoring case.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Condition {
+    /// The name or address of a sender.
+    From(String),
+    /// The name or address of a To or Cc recipient.
+    Recipient(String),
+    Subject(String),
+    /// A header field, e.g. `List-Id`. Needs the header of the message to
+    /// be fetched.
+    Header {
+        name: String,
+        value: String,
+    },
+}
+
+impl Condition {
+    /// Whether the message holds the condition. `headers` is the header of
+    /// the message, if it was fetched.
+    pub fn matches(&self, envelope: &Envelope, headers: Option<&str>) -> bool {
+        match self {
+            Condition::From(text) => contains(&envelope.from, text),
+            Condition::Recipient(text) => {
+                contains(&envelope.to, text) || contains(&envelope.cc, text)
+            }
+            Condition::Subject(text) => envelope
+                .subject
+                .as_deref()
+                .is_some_and(|subject| contains_text(subject, text)),
+            Condition::Header { name, value } => headers
+                .and_then(|headers| header_value(headers, name))
+                .is_some_and(|field| contains_text(&field, value)),
+        }
+    }
+}
+
+fn contains(addresses: &Option<EmailAddress>, text: &str) -> bool {
+    addresses
+        .as_ref()
+        .is_some_and(|addresses| contains_text(&addresses.to_string(), text))
+}
+
+fn contains_text(field: &str, text: &str) -> bool {
+    field.to_lowercase().contains(&text.to_lowercase())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RuleAction {
+    MoveTo(FolderId),
+    AddLabel(String),
+    MarkRead,
+    /// Deletes the message as the account's
+    /// [`DeletePolicy`](crate::DeletePolicy) says.
+    Delete,
+    /// Sends the message on as it is to the address, through the outbox.
+    Forward(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rule {
+    pub id: RuleId,
+    pub account_id: AccountId,
+    pub name: String,
+    pub enabled: bool,
+    /// Rules are applied by ascending position.
+    pub position: u32,
+    /// Whether all conditions have to hold, or one of them.
+    pub match_all: bool,
+    pub conditions: Vec<Condition>,
+    pub actions: Vec<RuleAction>,
+    /// Don't apply the later rules to the messages this one acted on.
+    pub stop: bool,
+}
+
+impl Rule {
+    pub fn needs_headers(&self) -> bool {
+        self.conditions
+            .iter()
+            .any(|condition| matches!(condition, Condition::Header { .. }))
+    }
+
+    pub fn matches(&self, envelope: &Envelope, headers: Option<&str>) -> bool {
+        let mut conditions = self.conditions.iter();
+        if self.match_all {
+            conditions.all(|condition| condition.matches(envelope, headers))
+        } else {
+            conditions.any(|condition| condition.matches(envelope, headers))
+        }
+    }
+
+    /// Checks that the rule can be applied. A rule without conditions would
+    /// act on every message.
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: &str| {
+            Err(MailinerError::InvalidData(format!(
+                "Rule {}: {}",
+                self.name, reason
+            )))
+        };
+        if self.conditions.is_empty() {
+            return invalid("no conditions");
+        }
+        if self.actions.is_empty() {
+            return invalid("no actions");
+        }
+        for action in &self.actions {
+            if matches!(action, RuleAction::Forward(address) if !address.contains('@')) {
+                return invalid("invalid forward address");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::ids::MessageId;
+    use crate::models::EmailAddr;
+
+    #[test]
+    fn matches_conditions() {
+        let envelope = Envelope {
+            id: MessageId::new("INBOX;UID=1"),
+            account_id: AccountId::new("imap-jan"),
+            folder_id: FolderId::new("INBOX"),
+            subject: Some("[rust-users] Release notes".to_string()),
+            from: Some(EmailAddress::List(vec![EmailAddr {
+                name: Some("Ana".to_string()),
+                email: Some("ana@example.com".to_string()),
+            }])),
+            to: None,
+            cc: None,
+            bcc: None,
+            reply_to: None,
+            date: Utc::now(),
+            is_read: false,
+            is_starred: false,
+            is_flagged: false,
+            is_draft: false,
+            is_deleted: false,
+            has_attachments: false,
+            size: None,
+            keywords: BTreeSet::new(),
+            labels: BTreeSet::new(),
+            snoozed_until: None,
+            thread_id: None,
+            remote_id: None,
+            message_id: None,
+            in_reply_to: Vec::new(),
+            references: Vec::new(),
+            unsubscribe: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let mut rule = Rule {
+            id: RuleId::new("1"),
+            account_id: AccountId::new("imap-jan"),
+            name: "Lists".to_string(),
+            enabled: true,
+            position: 0,
+            match_all: true,
+            conditions: vec![
+                Condition::From("EXAMPLE.com".to_string()),
+                Condition::Header {
+                    name: "List-Id".to_string(),
+                    value: "rust-users".to_string(),
+                },
+            ],
+            actions: vec![RuleAction::MoveTo(FolderId::new("Lists"))],
+            stop: true,
+        };
+        assert!(rule.validate().is_ok());
+        assert!(rule.needs_headers());
+        let headers =
+            "From: ana@example.com\r\nList-Id: Rust users\r\n <rust-users.example.com>\r\n\r\n";
+        assert!(rule.matches(&envelope, Some(headers)));
+        assert!(!rule.matches(&envelope, None));
+
+        rule.match_all = false;
+        rule.conditions[0] = Condition::Recipient("ana".to_string());
+        assert!(!rule.matches(&envelope, None));
+        rule.conditions
+            .push(Condition::Subject("release".to_string()));
+        assert!(rule.matches(&envelope, None));
+
+        rule.actions.push(RuleAction::Forward("nobody".to_string()));
+        assert!(rule.validate().is_err());
+    }
+}
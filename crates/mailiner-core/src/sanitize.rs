@@ -0,0 +1,451 @@
+//! Sanitization of the HTML bodies of received messages.
+//!
+//! Message HTML is rendered inside the app's own page, so anything that can
+//! run code, submit data or restyle the app has to go before it gets there.
+//! Only an allowlist of formatting tags and attributes is kept. Scripts,
+//! frames, forms and style sheets are dropped, links may only lead to web
+//! pages and addresses, and inline styles lose the declarations that load
+//! resources or move the message out of its pane.
+//!
+//! Remote images and links are kept, but [`sanitize_html_without_trackers`]
+//! also removes the ones that track the reader. Images of the message's own
+//! parts keep their `cid:` URLs, which [`inline_images`] replaces once the
+//! parts are fetched.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use base64::prelude::*;
+
+use crate::compose::plaintext::decode_entities;
+use crate::registry::percent_decode;
+use crate::trackers::{is_tracker_url, is_tracking_pixel};
+
+/// Tags that are kept. Any others are dropped, but not their content.
+const ALLOWED_TAGS: &[&str] = &[
+    "a", "abbr", "address", "b", "big", "blockquote", "br", "caption", "center", "cite", "code",
+    "col", "colgroup", "dd", "del", "div", "dl", "dt", "em", "font", "h1", "h2", "h3", "h4", "h5",
+    "h6", "hr", "i", "img", "ins", "li", "ol", "p", "pre", "q", "s", "small", "span", "strike",
+    "strong", "sub", "sup", "table", "tbody", "td", "tfoot", "th", "thead", "tr", "tt", "u", "ul",
+];
+
+/// Tags that are dropped together with their content.
+const DROPPED_TAGS: &[&str] = &[
+    "applet", "button", "embed", "frame", "frameset", "head", "iframe", "math", "noscript",
+    "object", "script", "select", "style", "svg", "template", "textarea", "title",
+];
+
+/// Tags whose content is text up to the closing tag, so they can't nest.
+const RAW_TEXT_TAGS: &[&str] = &["noscript", "script", "style", "textarea", "title"];
+
+/// Attributes allowed on any of the allowed tags. `href` and `src` are
+/// checked separately.
+const ALLOWED_ATTRIBUTES: &[&str] = &[
+    "align", "alt", "bgcolor", "border", "cellpadding", "cellspacing", "color", "cols", "colspan",
+    "dir", "face", "height", "lang", "rows", "rowspan", "size", "style", "title", "valign", "width",
+];
+
+/// Inline style declarations with these in their value are dropped.
+const UNSAFE_STYLE_VALUES: &[&str] = &["url(", "expression", "javascript:", "behavior", "binding"];
+
+/// Style properties that could move content out of the message pane.
+const UNSAFE_STYLE_PROPERTIES: &[&str] = &["position", "z-index"];
+
+/// Returns `html` with everything removed that isn't safe to render.
+pub fn sanitize_html(html: &str) -> String {
+    sanitize(html, false).0
+}
+
+/// Like [`sanitize_html`], but also drops tracking pixels and the links of
+/// tracking services, keeping their text. Returns how many were removed.
+pub fn sanitize_html_without_trackers(html: &str) -> (String, usize) {
+    sanitize(html, true)
+}
+
+/// The Content-IDs of the parts that the images of sanitized HTML show,
+/// from their `cid:` URLs (RFC 2392), each once.
+pub fn content_ids(html: &str) -> Vec<String> {
+    let mut content_ids = Vec::new();
+    for (_, content_id) in cid_sources(html) {
+        if !content_ids.contains(&content_id) {
+            content_ids.push(content_id);
+        }
+    }
+    content_ids
+}
+
+/// Replaces the `cid:` URLs of the images in sanitized HTML with `urls`,
+/// by Content-ID. Images of parts without a URL are left broken.
+pub fn inline_images(html: &str, urls: &HashMap<String, String>) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut copied = 0;
+    for (value, content_id) in cid_sources(html) {
+        if let Some(url) = urls.get(&content_id) {
+            out.push_str(&html[copied..value.start]);
+            out.push_str(&escape_attribute(url));
+            copied = value.end;
+        }
+    }
+    out.push_str(&html[copied..]);
+    out
+}
+
+/// A `data:` URL for the content of an inline part, if it's an image that
+/// can be shown like the ones [`sanitize_html`] keeps. SVG isn't, as it can
+/// hold scripts.
+pub fn image_data_url(content_type: &str, data: &[u8]) -> Option<String> {
+    let content_type = content_type.trim().to_ascii_lowercase();
+    let valid = content_type
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "/+-.".contains(c));
+    (valid && content_type.starts_with("image/") && !content_type.starts_with("image/svg"))
+        .then(|| format!("data:{};base64,{}", content_type, BASE64_STANDARD.encode(data)))
+}
+
+fn sanitize(html: &str, block_trackers: bool) -> (String, usize) {
+    let mut out = String::with_capacity(html.len());
+    let mut trackers = 0;
+    // The tag whose content is being dropped, and how deep it is nested.
+    let mut dropping: Option<(String, usize)> = None;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let start = rest.find('<').unwrap_or(rest.len());
+        if dropping.is_none() {
+            push_text(&mut out, &rest[..start]);
+        }
+        rest = &rest[start..];
+        if rest.is_empty() {
+            break;
+        }
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let is_tag = rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!');
+        if !is_tag {
+            if dropping.is_none() {
+                out.push_str("&lt;");
+            }
+            rest = &rest[1..];
+            continue;
+        }
+        let end = tag_end(rest);
+        let mut tag = Tag::parse(&rest[1..end]);
+        rest = rest.get(end + 1..).unwrap_or_default();
+
+        if let Some((name, depth)) = &mut dropping {
+            if tag.name == *name {
+                if tag.closing {
+                    *depth -= 1;
+                } else if !tag.self_closing && !RAW_TEXT_TAGS.contains(&name.as_str()) {
+                    *depth += 1;
+                }
+                if *depth == 0 {
+                    dropping = None;
+                }
+            }
+            continue;
+        }
+        if DROPPED_TAGS.contains(&tag.name.as_str()) {
+            if !tag.closing && !tag.self_closing {
+                dropping = Some((tag.name, 1));
+            }
+            continue;
+        }
+        if ALLOWED_TAGS.contains(&tag.name.as_str()) {
+            if block_trackers && tag.is_tracker() {
+                trackers += 1;
+                if tag.name == "img" {
+                    continue;
+                }
+                tag.attributes.retain(|(name, _)| name != "href");
+            }
+            tag.write(&mut out);
+        }
+    }
+    (out, trackers)
+}
+
+fn push_text(out: &mut String, text: &str) {
+    out.push_str(&text.replace('>', "&gt;"));
+}
+
+/// The position of the `>` closing the tag `rest` starts with, ignoring
+/// the ones in quoted attribute values.
+fn tag_end(rest: &str) -> usize {
+    let mut quote = None;
+    for (pos, c) in rest.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '>') => return pos,
+            _ => {}
+        }
+    }
+    rest.len()
+}
+
+struct Tag {
+    name: String,
+    closing: bool,
+    self_closing: bool,
+    attributes: Vec<(String, String)>,
+}
+
+impl Tag {
+    fn parse(tag: &str) -> Self {
+        let (closing, tag) = match tag.strip_prefix('/') {
+            Some(tag) => (true, tag),
+            None => (false, tag),
+        };
+        let self_closing = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/');
+        let name_end = tag
+            .find(|c: char| c.is_whitespace() || c == '/')
+            .unwrap_or(tag.len());
+        Self {
+            name: tag[..name_end].to_ascii_lowercase(),
+            closing,
+            self_closing,
+            attributes: parse_attributes(&tag[name_end..]),
+        }
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether the tag is a remote image that tracks the reader, or a link
+    /// through a tracking service.
+    fn is_tracker(&self) -> bool {
+        match self.name.as_str() {
+            "img" => self.attribute("src").is_some_and(|src| {
+                let url = normalize_url(src);
+                (url.starts_with("http://") || url.starts_with("https://"))
+                    && (is_tracker_url(src)
+                        || is_tracking_pixel(
+                            self.attribute("width"),
+                            self.attribute("height"),
+                            self.attribute("style"),
+                        ))
+            }),
+            "a" => self.attribute("href").is_some_and(is_tracker_url),
+            _ => false,
+        }
+    }
+
+    fn write(&self, out: &mut String) {
+        if self.closing {
+            out.push_str(&format!("</{}>", self.name));
+            return;
+        }
+        out.push('<');
+        out.push_str(&self.name);
+        for (name, value) in &self.attributes {
+            let value = match name.as_str() {
+                "href" if self.name == "a" => is_safe_link(value).then(|| value.clone()),
+                "src" if self.name == "img" => is_safe_image(value).then(|| value.clone()),
+                "style" => Some(sanitize_style(value)),
+                _ if ALLOWED_ATTRIBUTES.contains(&name.as_str()) => Some(value.clone()),
+                _ => None,
+            };
+            if let Some(value) = value {
+                out.push_str(&format!(" {}=\"{}\"", name, escape_attribute(&value)));
+            }
+        }
+        if self.name == "a" {
+            // Links open outside of the app, without telling the page where
+            // they were opened from.
+            out.push_str(" target=\"_blank\" rel=\"noopener noreferrer\"");
+        }
+        out.push('>');
+    }
+}
+
+/// The attributes of a tag, with lowercase names and decoded values.
+fn parse_attributes(mut rest: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            return attributes;
+        }
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let mut value = String::new();
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (raw, next) = match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let end = after[1..].find(quote).map_or(after.len(), |end| end + 1);
+                    (&after[1..end], after.get(end + 1..).unwrap_or_default())
+                }
+                _ => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            value = decode_entities(raw);
+            rest = next;
+        }
+        attributes.push((name, value));
+    }
+}
+
+/// The URL with whitespace and control characters removed, which browsers
+/// ignore in the scheme, and in lowercase.
+fn normalize_url(url: &str) -> String {
+    url.chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+fn is_safe_link(url: &str) -> bool {
+    let url = normalize_url(url);
+    ["http://", "https://", "mailto:"].iter().any(|scheme| url.starts_with(scheme))
+}
+
+/// Images may be remote, inline parts of the message, or embedded data that
+/// is an image.
+fn is_safe_image(url: &str) -> bool {
+    let url = normalize_url(url);
+    ["http://", "https://", "cid:", "data:image/"].iter().any(|scheme| url.starts_with(scheme))
+        && !url.starts_with("data:image/svg")
+}
+
+/// Drops the declarations of an inline style that load resources, run code
+/// or position the element.
+fn sanitize_style(style: &str) -> String {
+    style
+        .split(';')
+        .filter_map(|declaration| {
+            let (property, value) = declaration.split_once(':')?;
+            let property = property.trim().to_ascii_lowercase();
+            let lower = normalize_url(value);
+            let safe = !UNSAFE_STYLE_PROPERTIES.contains(&property.as_str())
+                && !UNSAFE_STYLE_VALUES.iter().any(|unsafe_value| lower.contains(unsafe_value))
+                && !value.contains('\\');
+            safe.then(|| format!("{}: {}", property, value.trim()))
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// The `src` values of the `img` tags in sanitized HTML that are `cid:`
+/// URLs, and the Content-IDs they refer to. As text has its `>` escaped and
+/// attribute values are quoted, every `<img` starts a tag ending at the next
+/// `>`.
+fn cid_sources(html: &str) -> Vec<(Range<usize>, String)> {
+    let mut sources = Vec::new();
+    let mut from = 0;
+    while let Some(tag_start) = html[from..].find("<img ").map(|i| from + i) {
+        let tag_end = html[tag_start..].find('>').map_or(html.len(), |i| tag_start + i);
+        from = tag_end;
+        let Some(start) = html[tag_start..tag_end].find(" src=\"").map(|i| tag_start + i + 6) else {
+            continue;
+        };
+        let end = html[start..tag_end].find('"').map_or(tag_end, |i| start + i);
+        let url: String = decode_entities(&html[start..end])
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        if url.get(..4).is_some_and(|scheme| scheme.eq_ignore_ascii_case("cid:")) && url.len() > 4 {
+            let content_id = percent_decode(&url[4..]).unwrap_or_else(|| url[4..].to_string());
+            sources.push((start..end, content_id));
+        }
+    }
+    sources
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_formatting_only() {
+        let html = "<!DOCTYPE html><html><head><title>News</title>\
+            <style>body { display: none }</style>\
+            <link rel=\"stylesheet\" href=\"https://example.com/a.css\"></head>\
+            <body onload=\"steal()\"><p class=\"intro\" style=\"color: red; \
+            background: url(https://tracker.example.com/p.gif); position: fixed\">Hi &amp; 1 < 2 > 0</p>\
+            <script>alert(1)</script><script>nested <script> not really</script>\
+            <a href=\" jav&#x61;script:alert(1)\" title='a \"quote\"'>bad</a> \
+            <A HREF=\"https://example.com/?a=1&amp;b=2\">good</A>\
+            <form action=\"https://example.com\"><input name=\"q\"><button>Go</button></form>\
+            <img src=\"cid:logo@example.com\" alt=\"Logo\" onerror=\"steal()\"/>\
+            <img src=\"data:image/svg+xml;base64,AAAA\">\
+            <iframe src=\"https://example.com\"><p>inside</p></iframe><!-- comment --></body></html>";
+        assert_eq!(
+            sanitize_html(html),
+            "<p style=\"color: red\">Hi &amp; 1 &lt; 2 &gt; 0</p>\
+             <a title=\"a &quot;quote&quot;\" target=\"_blank\" rel=\"noopener noreferrer\">bad</a> \
+             <a href=\"https://example.com/?a=1&amp;b=2\" target=\"_blank\" rel=\"noopener noreferrer\">good</a>\
+             <img src=\"cid:logo@example.com\" alt=\"Logo\">\
+             <img>"
+        );
+    }
+
+    #[test]
+    fn removes_trackers() {
+        let html = "<p>Hello</p>\
+            <img src=\"https://example.com/open.gif\" width=\"1\" height=\"1\">\
+            <img src=\"https://example.com/logo.png\" width=\"120\" height=\"40\">\
+            <img src=\"https://example.com/h.gif\" style=\"display:none\">\
+            <img src=\"cid:spacer@example.com\" width=\"1\" height=\"1\">\
+            <a href=\"https://example.us4.list-manage.com/track/click?u=1\">Read more</a>";
+        assert_eq!(
+            sanitize_html_without_trackers(html),
+            (
+                "<p>Hello</p>\
+                 <img src=\"https://example.com/logo.png\" width=\"120\" height=\"40\">\
+                 <img src=\"cid:spacer@example.com\" width=\"1\" height=\"1\">\
+                 <a target=\"_blank\" rel=\"noopener noreferrer\">Read more</a>"
+                    .to_string(),
+                3
+            )
+        );
+        assert_eq!(sanitize_html(html).matches("<img").count(), 4);
+    }
+
+    #[test]
+    fn inlines_images() {
+        let html = sanitize_html(
+            "<p>src=\"cid:logo@example.com\"</p>\
+             <img alt=\"Logo\" src=\" CID:logo%40example.com\">\
+             <img src=\"cid:chart\"><img src=\"cid:logo@example.com\" width=\"40\">\
+             <img src=\"https://example.com/cid:chart\">",
+        );
+        assert_eq!(content_ids(&html), ["logo@example.com", "chart"]);
+
+        let urls = HashMap::from([("logo@example.com".to_string(), "data:image/png;base64,AAAA".to_string())]);
+        assert_eq!(
+            inline_images(&html, &urls),
+            "<p>src=\"cid:logo@example.com\"</p>\
+             <img alt=\"Logo\" src=\"data:image/png;base64,AAAA\">\
+             <img src=\"cid:chart\"><img src=\"data:image/png;base64,AAAA\" width=\"40\">\
+             <img src=\"https://example.com/cid:chart\">"
+        );
+
+        assert_eq!(image_data_url("Image/PNG", b"\x89PNG").as_deref(), Some("data:image/png;base64,iVBORw=="));
+        assert_eq!(image_data_url("image/svg+xml", b"<svg/>"), None);
+        assert_eq!(image_data_url("text/html", b"<p>"), None);
+    }
+}
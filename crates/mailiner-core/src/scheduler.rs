@@ -0,0 +1,158 @@
+//! Syncing several folders and accounts at the same time, with a bound on
+//! how many folders are synced at once, overall and per account.
+//!
+//! The folder the user is viewing is synced first and doesn't wait for the
+//! other accounts.
+
+use std::sync::Mutex;
+
+use futures_util::future::join_all;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::error::Result;
+use crate::ids::{AccountId, FolderId};
+use crate::models::{AccountMetadata, Folder};
+use crate::service::EmailService;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncLimits {
+    /// How many folders are synced at once over all accounts.
+    pub global: usize,
+    /// How many folders of an account are synced at once.
+    pub per_account: usize,
+}
+
+impl Default for SyncLimits {
+    fn default() -> Self {
+        Self {
+            global: 4,
+            per_account: 2,
+        }
+    }
+}
+
+/// The order to sync `folders` in: the one being viewed, then the inbox,
+/// then the others as listed.
+pub(crate) fn sync_order(folders: &[Folder], viewing: Option<&FolderId>) -> Vec<FolderId> {
+    let mut order: Vec<&Folder> = folders.iter().collect();
+    order.sort_by_key(|folder| {
+        if Some(&folder.id) == viewing {
+            0
+        } else if folder.id.as_str().eq_ignore_ascii_case("INBOX") {
+            1
+        } else {
+            2
+        }
+    });
+    order.into_iter().map(|folder| folder.id.clone()).collect()
+}
+
+pub struct SyncScheduler {
+    limits: SyncLimits,
+    permits: Semaphore,
+    viewing: Mutex<Option<FolderId>>,
+}
+
+impl Default for SyncScheduler {
+    fn default() -> Self {
+        Self::new(SyncLimits::default())
+    }
+}
+
+impl SyncScheduler {
+    /// Limits of 0 are taken as 1.
+    pub fn new(limits: SyncLimits) -> Self {
+        let limits = SyncLimits {
+            global: limits.global.max(1),
+            per_account: limits.per_account.max(1),
+        };
+        Self {
+            limits,
+            permits: Semaphore::new(limits.global),
+            viewing: Mutex::new(None),
+        }
+    }
+
+    pub fn limits(&self) -> SyncLimits {
+        self.limits
+    }
+
+    /// Sets the folder the user is viewing, to sync it before the others.
+    pub fn set_viewing(&self, folder_id: Option<FolderId>) {
+        *self.viewing.lock().unwrap() = folder_id;
+    }
+
+    pub fn viewing(&self) -> Option<FolderId> {
+        self.viewing.lock().unwrap().clone()
+    }
+
+    /// Waits for a slot to sync `folder_id` in. The folder being viewed gets
+    /// one right away.
+    pub(crate) async fn permit(&self, folder_id: &FolderId) -> Option<SemaphorePermit<'_>> {
+        if self.viewing().as_ref() == Some(folder_id) {
+            return None;
+        }
+        // The semaphore is never closed.
+        self.permits.acquire().await.ok()
+    }
+
+    /// Syncs the accounts at the same time, each through its own service.
+    /// Returns the result of each account, in the same order.
+    pub async fn sync_accounts<S>(
+        &self,
+        accounts: &[(&EmailService<S>, AccountId)],
+    ) -> Vec<Result<AccountMetadata>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + std::fmt::Debug + Send + Sync,
+    {
+        join_all(
+            accounts
+                .iter()
+                .map(|(service, account_id)| service.sync_account_with(account_id, self)),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn folder(id: &str) -> Folder {
+        Folder {
+            id: FolderId::new(id),
+            account_id: AccountId::new("imap-jan"),
+            name: id.to_string(),
+            parent_id: None,
+            rights: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn viewed_folder_first() {
+        let folders = [folder("Archive"), folder("INBOX"), folder("Lists")];
+        assert_eq!(
+            sync_order(&folders, None),
+            [
+                FolderId::new("INBOX"),
+                FolderId::new("Archive"),
+                FolderId::new("Lists")
+            ]
+        );
+        assert_eq!(
+            sync_order(&folders, Some(&FolderId::new("Lists")))[0],
+            FolderId::new("Lists")
+        );
+
+        let scheduler = SyncScheduler::new(SyncLimits {
+            global: 0,
+            per_account: 3,
+        });
+        assert_eq!(scheduler.limits().global, 1);
+    }
+}
@@ -0,0 +1,136 @@
+//! Full-text search over the local cache, so that searching works instantly
+//! and offline.
+//!
+//! A [`SearchIndex`] keeps the words of the subjects, addresses and fetched
+//! bodies of messages. [`IndexedStorage`](crate::storage::IndexedStorage)
+//! updates it as the sync saves envelopes and message parts, and answers
+//! [`Storage::search`](crate::Storage::search) with it.
+//!
+//! [`MemoryIndex`] works everywhere, including the browser. With the
+//! `tantivy` feature, [`TantivyIndex`] keeps the index on disk.
+
+use async_trait::async_trait;
+
+use crate::error::Result;
+use crate::ids::{FolderId, MessageId};
+use crate::models::{EmailAddress, Envelope, SearchQuery};
+
+pub mod memory;
+pub mod syntax;
+#[cfg(feature = "tantivy")]
+pub mod tantivy;
+
+#[cfg(feature = "tantivy")]
+pub use self::tantivy::TantivyIndex;
+pub use memory::MemoryIndex;
+
+/// The fields of a message that are indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    From,
+    To,
+    Subject,
+    Body,
+}
+
+impl Field {
+    pub const ALL: [Field; 4] = [Field::From, Field::To, Field::Subject, Field::Body];
+}
+
+/// The text criteria of a search. Every word of every term must be the start
+/// of a word of the message, in the term's field or, without one, in any
+/// field.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TextQuery {
+    pub terms: Vec<(Option<Field>, String)>,
+}
+
+impl TextQuery {
+    /// The sender, recipient, subject and body criteria of `query`, and its
+    /// raw query as words to find in any field. `None` if it has none of
+    /// them.
+    pub fn from_search(query: &SearchQuery) -> Option<Self> {
+        let terms: Vec<_> = [
+            (Some(Field::From), &query.from),
+            (Some(Field::To), &query.to),
+            (Some(Field::Subject), &query.subject),
+            (Some(Field::Body), &query.body),
+            (None, &query.raw),
+        ]
+        .into_iter()
+        .filter_map(|(field, text)| Some((field, text.clone()?)))
+        .collect();
+        (!terms.is_empty()).then_some(Self { terms })
+    }
+
+    /// The words of the terms, with their fields.
+    pub fn words(&self) -> impl Iterator<Item = (Option<Field>, String)> + '_ {
+        self.terms
+            .iter()
+            .flat_map(|(field, text)| tokenize(text).map(move |word| (*field, word)))
+    }
+}
+
+/// Splits text into lowercase words, at everything that isn't a letter or a
+/// digit. Addresses become their parts, `ana@example.com` is `ana`,
+/// `example` and `com`.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// The text of the fields of an envelope, without the body.
+pub(crate) fn envelope_fields(envelope: &Envelope) -> [(Field, String); 3] {
+    let addresses = |address: &Option<EmailAddress>| {
+        address
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_default()
+    };
+    [
+        (Field::From, addresses(&envelope.from)),
+        (Field::To, addresses(&envelope.to)),
+        (Field::Subject, envelope.subject.clone().unwrap_or_default()),
+    ]
+}
+
+#[async_trait]
+pub trait SearchIndex: Send + Sync {
+    /// Adds a message, or replaces its subject and addresses, keeping the
+    /// body indexed before.
+    async fn index_envelope(&self, envelope: &Envelope) -> Result<()>;
+    /// Adds the text of a fetched body part to a message indexed before.
+    async fn index_body(&self, message_id: &MessageId, text: &str) -> Result<()>;
+    async fn remove(&self, message_id: &MessageId) -> Result<()>;
+    /// The messages in `folder_id` that match `query`, in no particular
+    /// order.
+    async fn search(&self, folder_id: &FolderId, query: &TextQuery) -> Result<Vec<MessageId>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_criteria() {
+        assert_eq!(TextQuery::from_search(&SearchQuery::default()), None);
+        let query = SearchQuery {
+            from: Some("ana@example.com".to_string()),
+            raw: Some("Quarterly  REPORT".to_string()),
+            is_read: Some(false),
+            ..Default::default()
+        };
+        let words: Vec<_> = TextQuery::from_search(&query).unwrap().words().collect();
+        assert_eq!(
+            words,
+            [
+                (Some(Field::From), "ana".to_string()),
+                (Some(Field::From), "example".to_string()),
+                (Some(Field::From), "com".to_string()),
+                (None, "quarterly".to_string()),
+                (None, "report".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,255 @@
+//! An inverted index in memory, for platforms without a file system to keep
+//! one on, and for tests.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Bound;
+use std::sync::RwLock;
+
+use async_trait::async_trait;
+
+use crate::error::Result;
+use crate::ids::{FolderId, MessageId};
+use crate::models::Envelope;
+use crate::search::{envelope_fields, tokenize, Field, SearchIndex, TextQuery};
+
+struct Document {
+    folder_id: FolderId,
+    words: HashMap<Field, BTreeSet<String>>,
+}
+
+impl Document {
+    fn has_prefix(&self, field: Option<Field>, prefix: &str) -> bool {
+        let starts = |words: &BTreeSet<String>| {
+            words
+                .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+                .next()
+                .is_some_and(|word| word.starts_with(prefix))
+        };
+        match field {
+            Some(field) => self.words.get(&field).is_some_and(starts),
+            None => self.words.values().any(starts),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    documents: HashMap<MessageId, Document>,
+    /// The messages with each word, in any field.
+    postings: BTreeMap<String, HashSet<MessageId>>,
+}
+
+impl Inner {
+    fn insert(&mut self, id: MessageId, document: Document) {
+        for word in document.words.values().flatten() {
+            self.postings
+                .entry(word.clone())
+                .or_default()
+                .insert(id.clone());
+        }
+        self.documents.insert(id, document);
+    }
+
+    fn remove(&mut self, id: &MessageId) -> Option<Document> {
+        let document = self.documents.remove(id)?;
+        for word in document.words.values().flatten() {
+            if let Some(ids) = self.postings.get_mut(word) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.postings.remove(word);
+                }
+            }
+        }
+        Some(document)
+    }
+
+    fn index_envelope(&mut self, envelope: &Envelope) {
+        let body = self
+            .remove(&envelope.id)
+            .and_then(|mut document| document.words.remove(&Field::Body));
+        let mut words: HashMap<Field, BTreeSet<String>> = envelope_fields(envelope)
+            .into_iter()
+            .map(|(field, text)| (field, tokenize(&text).collect()))
+            .collect();
+        words.insert(Field::Body, body.unwrap_or_default());
+        self.insert(
+            envelope.id.clone(),
+            Document {
+                folder_id: envelope.folder_id.clone(),
+                words,
+            },
+        );
+    }
+
+    fn index_body(&mut self, message_id: &MessageId, text: &str) {
+        if let Some(mut document) = self.remove(message_id) {
+            document
+                .words
+                .entry(Field::Body)
+                .or_default()
+                .extend(tokenize(text));
+            self.insert(message_id.clone(), document);
+        }
+    }
+
+    fn search(&self, folder_id: &FolderId, query: &TextQuery) -> Vec<MessageId> {
+        let mut matches: Option<HashSet<&MessageId>> = None;
+        for (field, word) in query.words() {
+            let found = self
+                .with_prefix(&word)
+                .into_iter()
+                .filter(|id| self.documents[*id].has_prefix(field, &word))
+                .collect();
+            matches = Some(match matches {
+                Some(matches) => matches.intersection(&found).copied().collect(),
+                None => found,
+            });
+        }
+        matches
+            .unwrap_or_else(|| self.documents.keys().collect())
+            .into_iter()
+            .filter(|id| self.documents[*id].folder_id == *folder_id)
+            .cloned()
+            .collect()
+    }
+
+    /// The messages with a word starting with `prefix`.
+    fn with_prefix(&self, prefix: &str) -> HashSet<&MessageId> {
+        self.postings
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(word, _)| word.starts_with(prefix))
+            .flat_map(|(_, ids)| ids)
+            .collect()
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryIndex {
+    inner: RwLock<Inner>,
+}
+
+impl MemoryIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SearchIndex for MemoryIndex {
+    async fn index_envelope(&self, envelope: &Envelope) -> Result<()> {
+        self.inner.write().unwrap().index_envelope(envelope);
+        Ok(())
+    }
+
+    async fn index_body(&self, message_id: &MessageId, text: &str) -> Result<()> {
+        self.inner.write().unwrap().index_body(message_id, text);
+        Ok(())
+    }
+
+    async fn remove(&self, message_id: &MessageId) -> Result<()> {
+        self.inner.write().unwrap().remove(message_id);
+        Ok(())
+    }
+
+    async fn search(&self, folder_id: &FolderId, query: &TextQuery) -> Result<Vec<MessageId>> {
+        Ok(self.inner.read().unwrap().search(folder_id, query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::ids::AccountId;
+    use crate::models::{EmailAddr, EmailAddress, SearchQuery};
+
+    fn envelope(id: &str, from: &str, subject: &str) -> Envelope {
+        Envelope {
+            id: MessageId::new(id),
+            account_id: AccountId::new("imap-jan"),
+            folder_id: FolderId::new("INBOX"),
+            subject: Some(subject.to_string()),
+            from: Some(EmailAddress::List(vec![EmailAddr {
+                name: None,
+                email: Some(from.to_string()),
+            }])),
+            to: None,
+            cc: None,
+            bcc: None,
+            reply_to: None,
+            date: Utc::now(),
+            is_read: false,
+            is_starred: false,
+            is_flagged: false,
+            is_draft: false,
+            is_deleted: false,
+            has_attachments: false,
+            size: None,
+            keywords: BTreeSet::new(),
+            labels: BTreeSet::new(),
+            snoozed_until: None,
+            thread_id: None,
+            remote_id: None,
+            message_id: None,
+            in_reply_to: Vec::new(),
+            references: Vec::new(),
+            unsubscribe: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn finds_words_by_prefix() {
+        let mut index = Inner::default();
+        index.index_envelope(&envelope(
+            "INBOX;UID=1",
+            "ana@example.com",
+            "Quarterly report",
+        ));
+        index.index_envelope(&envelope("INBOX;UID=2", "bo@example.com", "Lunch"));
+        index.index_body(
+            &MessageId::new("INBOX;UID=2"),
+            "About the report, see you at noon",
+        );
+        // Re-indexing the envelope keeps the body.
+        index.index_envelope(&envelope("INBOX;UID=2", "bo@example.com", "Lunch?"));
+
+        let search = |index: &Inner, query: SearchQuery| {
+            let mut ids: Vec<_> = index
+                .search(
+                    &FolderId::new("INBOX"),
+                    &TextQuery::from_search(&query).unwrap(),
+                )
+                .into_iter()
+                .map(|id| id.to_string())
+                .collect();
+            ids.sort();
+            ids
+        };
+        let raw = |text: &str| SearchQuery {
+            raw: Some(text.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(search(&index, raw("repo")), ["INBOX;UID=1", "INBOX;UID=2"]);
+        assert_eq!(search(&index, raw("report noon")), ["INBOX;UID=2"]);
+        let query = SearchQuery {
+            subject: Some("report".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(search(&index, query), ["INBOX;UID=1"]);
+        let query = SearchQuery {
+            from: Some("bo@example".to_string()),
+            body: Some("noon".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(search(&index, query), ["INBOX;UID=2"]);
+
+        index.remove(&MessageId::new("INBOX;UID=2"));
+        assert!(search(&index, raw("noon")).is_empty());
+        assert!(!index.postings.contains_key("noon"));
+    }
+}
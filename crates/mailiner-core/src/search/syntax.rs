@@ -0,0 +1,122 @@
+//! The search syntax of the search bar, e.g.
+//! `from:alice subject:"quarterly report" has:attachment after:2024-03-01`.
+//!
+//! The operators are `from:`, `to:`, `subject:`, `has:attachment`,
+//! `is:unread`, `is:read`, `is:flagged`, `before:` and `after:`, with dates
+//! as `2024-03-01` or `2024/03/01`. Values with spaces are quoted. Other
+//! words, including operators that aren't understood, are looked for in the
+//! body.
+
+use chrono::NaiveDate;
+
+use crate::models::SearchQuery;
+
+/// Parses the text typed in the search bar.
+pub fn parse_query(input: &str) -> SearchQuery {
+    let mut query = SearchQuery::default();
+    let mut words = Vec::new();
+
+    for token in tokens(input) {
+        let understood = match token.split_once(':') {
+            Some((operator, value)) if !value.is_empty() => {
+                apply(&mut query, &operator.to_ascii_lowercase(), value)
+            }
+            _ => false,
+        };
+        if !understood {
+            words.push(token.replace('"', ""));
+        }
+    }
+    if !words.is_empty() {
+        query.body = Some(words.join(" "));
+    }
+    query
+}
+
+/// Sets the criterion of `operator`, if it is one with a valid value.
+fn apply(query: &mut SearchQuery, operator: &str, value: &str) -> bool {
+    let value = value.trim_matches('"');
+    match operator {
+        "from" => query.from = Some(value.to_string()),
+        "to" => query.to = Some(value.to_string()),
+        "subject" => query.subject = Some(value.to_string()),
+        "before" | "after" => {
+            let Some(date) = date(value) else {
+                return false;
+            };
+            if operator == "before" {
+                query.before = Some(date);
+            } else {
+                query.since = Some(date);
+            }
+        }
+        "has" if value.eq_ignore_ascii_case("attachment") => query.has_attachment = Some(true),
+        "is" => match value.to_ascii_lowercase().as_str() {
+            "unread" => query.is_read = Some(false),
+            "read" => query.is_read = Some(true),
+            "flagged" | "starred" => query.is_flagged = Some(true),
+            _ => return false,
+        },
+        _ => return false,
+    }
+    true
+}
+
+fn date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y/%m/%d"))
+        .ok()
+}
+
+/// Splits the input at whitespace outside of quotes.
+fn tokens(input: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in input.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                token.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators_and_words() {
+        assert_eq!(parse_query("  "), SearchQuery::default());
+
+        let query = parse_query(
+            "From:alice subject:\"quarterly report\" has:attachment is:unread \
+             after:2024-03-01 before:2024/04/01 budget \"next year\" is:important before:soon",
+        );
+        assert_eq!(
+            query,
+            SearchQuery {
+                from: Some("alice".to_string()),
+                subject: Some("quarterly report".to_string()),
+                body: Some("budget next year is:important before:soon".to_string()),
+                since: NaiveDate::from_ymd_opt(2024, 3, 1),
+                before: NaiveDate::from_ymd_opt(2024, 4, 1),
+                is_read: Some(false),
+                has_attachment: Some(true),
+                ..Default::default()
+            }
+        );
+    }
+}
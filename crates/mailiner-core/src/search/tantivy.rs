@@ -0,0 +1,236 @@
+//! A search index on disk, built with tantivy.
+//!
+//! Changes are committed lazily, before the next search and when the index
+//! is dropped, since a commit per saved envelope would slow the sync down
+//! considerably.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use ::tantivy::collector::{DocSetCollector, TopDocs};
+use ::tantivy::directory::MmapDirectory;
+use ::tantivy::query::{BooleanQuery, Occur, Query, RegexQuery, TermQuery};
+use ::tantivy::schema::{IndexRecordOption, Schema, Value, STORED, STRING, TEXT};
+use ::tantivy::{
+    Index, IndexReader, IndexWriter, ReloadPolicy, TantivyDocument, TantivyError, Term,
+};
+use async_trait::async_trait;
+
+use crate::error::{MailinerError, Result};
+use crate::ids::{FolderId, MessageId};
+use crate::models::Envelope;
+use crate::search::{envelope_fields, Field, SearchIndex, TextQuery};
+
+const WRITER_MEMORY: usize = 20_000_000;
+
+impl From<TantivyError> for MailinerError {
+    fn from(e: TantivyError) -> Self {
+        MailinerError::Storage(format!("Search index error: {}", e))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Fields {
+    id: ::tantivy::schema::Field,
+    folder: ::tantivy::schema::Field,
+    from: ::tantivy::schema::Field,
+    to: ::tantivy::schema::Field,
+    subject: ::tantivy::schema::Field,
+    body: ::tantivy::schema::Field,
+}
+
+impl Fields {
+    fn schema() -> (Schema, Self) {
+        let mut schema = Schema::builder();
+        let fields = Self {
+            id: schema.add_text_field("id", STRING | STORED),
+            folder: schema.add_text_field("folder", STRING | STORED),
+            from: schema.add_text_field("from", TEXT | STORED),
+            to: schema.add_text_field("to", TEXT | STORED),
+            subject: schema.add_text_field("subject", TEXT | STORED),
+            body: schema.add_text_field("body", TEXT | STORED),
+        };
+        (schema.build(), fields)
+    }
+
+    fn get(&self, field: Field) -> ::tantivy::schema::Field {
+        match field {
+            Field::From => self.from,
+            Field::To => self.to,
+            Field::Subject => self.subject,
+            Field::Body => self.body,
+        }
+    }
+}
+
+pub struct TantivyIndex {
+    fields: Fields,
+    writer: Mutex<IndexWriter>,
+    reader: IndexReader,
+    /// The documents added since the last commit, which searches don't see
+    /// yet. `None` for removed ones.
+    pending: Mutex<HashMap<MessageId, Option<TantivyDocument>>>,
+}
+
+impl TantivyIndex {
+    /// Opens the index in the directory `path`, creating it if needed.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        std::fs::create_dir_all(path.as_ref())?;
+        let directory = MmapDirectory::open(path.as_ref())
+            .map_err(|e| MailinerError::Storage(format!("Search index error: {}", e)))?;
+        let (schema, fields) = Fields::schema();
+        Self::new(Index::open_or_create(directory, schema)?, fields)
+    }
+
+    /// An index that is lost when dropped.
+    pub fn in_memory() -> Result<Self> {
+        let (schema, fields) = Fields::schema();
+        Self::new(Index::create_in_ram(schema), fields)
+    }
+
+    fn new(index: Index, fields: Fields) -> Result<Self> {
+        Ok(Self {
+            fields,
+            writer: Mutex::new(index.writer_with_num_threads(1, WRITER_MEMORY)?),
+            reader: index
+                .reader_builder()
+                .reload_policy(ReloadPolicy::Manual)
+                .try_into()?,
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Makes the changes so far visible to searches and durable.
+    pub fn commit(&self) -> Result<()> {
+        let mut pending = self.pending.lock().unwrap();
+        if !pending.is_empty() {
+            self.writer.lock().unwrap().commit()?;
+            self.reader.reload()?;
+            pending.clear();
+        }
+        Ok(())
+    }
+
+    fn id_term(&self, message_id: &MessageId) -> Term {
+        Term::from_field_text(self.fields.id, message_id.as_str())
+    }
+
+    /// The stored document of a message.
+    fn document(&self, message_id: &MessageId) -> Result<Option<TantivyDocument>> {
+        if let Some(document) = self.pending.lock().unwrap().get(message_id) {
+            return Ok(document.clone());
+        }
+        let searcher = self.reader.searcher();
+        let query = TermQuery::new(self.id_term(message_id), IndexRecordOption::Basic);
+        match searcher.search(&query, &TopDocs::with_limit(1))?.first() {
+            Some((_, address)) => Ok(Some(searcher.doc(*address)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn replace(&self, message_id: &MessageId, document: TantivyDocument) -> Result<()> {
+        {
+            let writer = self.writer.lock().unwrap();
+            writer.delete_term(self.id_term(message_id));
+            writer.add_document(document.clone())?;
+        }
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(message_id.clone(), Some(document));
+        Ok(())
+    }
+
+    /// Matches messages with a word starting with `word`.
+    fn prefix_query(&self, field: Option<Field>, word: &str) -> Result<Box<dyn Query>> {
+        // Words are letters and digits only, nothing to escape.
+        let pattern = format!("{}.*", word);
+        let fields = match field {
+            Some(field) => vec![self.fields.get(field)],
+            None => Field::ALL.map(|field| self.fields.get(field)).to_vec(),
+        };
+        let queries = fields
+            .into_iter()
+            .map(|field| {
+                let query: Box<dyn Query> = Box::new(RegexQuery::from_pattern(&pattern, field)?);
+                Ok((Occur::Should, query))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Box::new(BooleanQuery::new(queries)))
+    }
+}
+
+impl Drop for TantivyIndex {
+    fn drop(&mut self) {
+        // Nothing to report to, the changes are indexed again on the next
+        // sync.
+        let _ = self.commit();
+    }
+}
+
+#[async_trait]
+impl SearchIndex for TantivyIndex {
+    async fn index_envelope(&self, envelope: &Envelope) -> Result<()> {
+        let mut document = TantivyDocument::new();
+        document.add_text(self.fields.id, envelope.id.as_str());
+        document.add_text(self.fields.folder, envelope.folder_id.as_str());
+        for (field, text) in envelope_fields(envelope) {
+            document.add_text(self.fields.get(field), text);
+        }
+        if let Some(existing) = self.document(&envelope.id)? {
+            for body in existing
+                .get_all(self.fields.body)
+                .filter_map(|v| v.as_str())
+            {
+                document.add_text(self.fields.body, body);
+            }
+        }
+        self.replace(&envelope.id, document)
+    }
+
+    async fn index_body(&self, message_id: &MessageId, text: &str) -> Result<()> {
+        if let Some(mut document) = self.document(message_id)? {
+            document.add_text(self.fields.body, text);
+            self.replace(message_id, document)?;
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, message_id: &MessageId) -> Result<()> {
+        self.writer
+            .lock()
+            .unwrap()
+            .delete_term(self.id_term(message_id));
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(message_id.clone(), None);
+        Ok(())
+    }
+
+    async fn search(&self, folder_id: &FolderId, query: &TextQuery) -> Result<Vec<MessageId>> {
+        self.commit()?;
+        let folder = Term::from_field_text(self.fields.folder, folder_id.as_str());
+        let mut queries: Vec<(Occur, Box<dyn Query>)> = vec![(
+            Occur::Must,
+            Box::new(TermQuery::new(folder, IndexRecordOption::Basic)),
+        )];
+        for (field, word) in query.words() {
+            queries.push((Occur::Must, self.prefix_query(field, &word)?));
+        }
+
+        let searcher = self.reader.searcher();
+        let mut ids = Vec::new();
+        for address in searcher.search(&BooleanQuery::new(queries), &DocSetCollector)? {
+            let document: TantivyDocument = searcher.doc(address)?;
+            if let Some(id) = document
+                .get_first(self.fields.id)
+                .and_then(|value| value.as_str())
+            {
+                ids.push(MessageId::new(id));
+            }
+        }
+        Ok(ids)
+    }
+}
@@ -0,0 +1,83 @@
+//! Message submission, e.g. over SMTP.
+
+use std::fmt::Debug;
+#[cfg(feature = "mock")]
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::error::Result;
+
+#[cfg(feature = "native")]
+pub mod sendmail;
+
+#[async_trait]
+pub trait MessageSender<S>: Send + Sync
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    async fn connect(&self, stream: S) -> Result<()>;
+    async fn disconnect(&self) -> Result<()>;
+
+    async fn authenticate(&self, credentials: &str) -> Result<()>;
+
+    /// Submits `message`, a complete RFC 5322 message, for delivery to
+    /// `recipients`. `from` is the envelope sender, which bounces go to.
+    async fn send(&self, from: &str, recipients: &[String], message: &[u8]) -> Result<()>;
+}
+
+/// A message submitted to the [`MockSender`].
+#[cfg(feature = "mock")]
+#[derive(Debug, Clone)]
+pub struct SentMessage {
+    pub from: String,
+    pub recipients: Vec<String>,
+    pub message: Vec<u8>,
+}
+
+/// Sender that keeps the submitted messages instead of sending them.
+#[cfg(feature = "mock")]
+#[derive(Default)]
+pub struct MockSender {
+    sent: Mutex<Vec<SentMessage>>,
+}
+
+#[cfg(feature = "mock")]
+impl MockSender {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sent(&self) -> Vec<SentMessage> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[cfg(feature = "mock")]
+#[async_trait]
+impl<S> MessageSender<S> for MockSender
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync + 'static,
+{
+    async fn connect(&self, _stream: S) -> Result<()> {
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn authenticate(&self, _credentials: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn send(&self, from: &str, recipients: &[String], message: &[u8]) -> Result<()> {
+        self.sent.lock().unwrap().push(SentMessage {
+            from: from.to_string(),
+            recipients: recipients.to_vec(),
+            message: message.to_vec(),
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+//! Submission through a local `sendmail` compatible program, e.g. msmtp or
+//! the MTA's sendmail, for setups that relay mail locally.
+
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::process::Command;
+
+use super::MessageSender;
+use crate::error::{MailinerError, Result};
+
+/// `EX_TEMPFAIL` from sysexits.h: the program may succeed later.
+const EX_TEMPFAIL: i32 = 75;
+
+/// Pipes messages to a `sendmail` compatible program. There is no
+/// connection, so `connect` and `authenticate` do nothing.
+#[derive(Debug, Clone)]
+pub struct SendmailSender {
+    program: PathBuf,
+    args: Vec<String>,
+}
+
+impl Default for SendmailSender {
+    fn default() -> Self {
+        Self::new("/usr/sbin/sendmail")
+    }
+}
+
+impl SendmailSender {
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Adds arguments passed before the standard ones, e.g. `--account=work`
+    /// for msmtp.
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// `-i` keeps lines with a single dot, `-f` sets the envelope sender,
+    /// and `--` keeps recipients from being taken for options.
+    fn arguments(&self, from: &str, recipients: &[String]) -> Vec<String> {
+        let mut args = self.args.clone();
+        args.extend(["-i".to_string(), "-f".to_string(), from.to_string()]);
+        args.push("--".to_string());
+        args.extend(recipients.iter().cloned());
+        args
+    }
+}
+
+/// Converts CRLF to LF, which local programs expect.
+fn local_line_endings(message: &[u8]) -> Vec<u8> {
+    let mut converted = Vec::with_capacity(message.len());
+    let mut bytes = message.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'\r' && bytes.peek() == Some(&&b'\n') {
+            continue;
+        }
+        converted.push(byte);
+    }
+    converted
+}
+
+/// The error for a failed run of the program. Only temporary failures are
+/// worth retrying.
+fn exit_error(code: Option<i32>, stderr: &[u8]) -> MailinerError {
+    let message = format!(
+        "sendmail failed ({}): {}",
+        code.map_or("killed".to_string(), |code| format!("exit code {}", code)),
+        String::from_utf8_lossy(stderr).trim()
+    );
+    match code {
+        Some(EX_TEMPFAIL) | None => MailinerError::Connector(message),
+        Some(_) => MailinerError::Rejected(message),
+    }
+}
+
+#[async_trait]
+impl<S> MessageSender<S> for SendmailSender
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync + 'static,
+{
+    async fn connect(&self, _stream: S) -> Result<()> {
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn authenticate(&self, _credentials: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn send(&self, from: &str, recipients: &[String], message: &[u8]) -> Result<()> {
+        if recipients.is_empty() {
+            return Err(MailinerError::InvalidData(
+                "The message has no recipients".to_string(),
+            ));
+        }
+        let mut child = Command::new(&self.program)
+            .args(self.arguments(from, recipients))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                MailinerError::Connector(format!("Failed to run {}: {}", self.program.display(), e))
+            })?;
+
+        let mut written = Ok(());
+        if let Some(mut stdin) = child.stdin.take() {
+            written = stdin.write_all(&local_line_endings(message)).await;
+            // Dropping stdin closes it, which ends the message.
+        }
+        // A program that fails early stops reading, so its exit status
+        // explains a failed write better.
+        let output = child.wait_with_output().await?;
+        if !output.status.success() {
+            return Err(exit_error(output.status.code(), &output.stderr));
+        }
+        Ok(written?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_and_errors() {
+        let sender = SendmailSender::new("msmtp").with_args(vec!["--account=work".to_string()]);
+        assert_eq!(
+            sender.arguments("jan@example.com", &["-oi@example.com".to_string()]),
+            [
+                "--account=work",
+                "-i",
+                "-f",
+                "jan@example.com",
+                "--",
+                "-oi@example.com"
+            ]
+        );
+        assert_eq!(
+            local_line_endings(b"A: b\r\n\r\nc\rd\r\n"),
+            b"A: b\n\nc\rd\n"
+        );
+        assert!(matches!(
+            exit_error(Some(EX_TEMPFAIL), b"server busy"),
+            MailinerError::Connector(_)
+        ));
+        assert!(matches!(
+            exit_error(Some(67), b"no such user"),
+            MailinerError::Rejected(_)
+        ));
+    }
+}
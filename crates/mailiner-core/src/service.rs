@@ -0,0 +1,692 @@
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt, TryStreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{broadcast, Notify};
+
+use crate::calendar::{Calendar, ParticipationStatus};
+use crate::connector::EmailConnector;
+use crate::delivery;
+use crate::metrics::{self, Metrics, NoMetrics};
+use crate::error::{MailinerError, Result};
+use crate::ids::{AccountId, FolderId, MessageId, MessagePartId};
+use crate::models::{
+    AccountMetadata, AccountPreferences, EmailAddr, Envelope, EnvelopeQuery, Folder, FolderEvent, FolderMetadata,
+    Label, MessageContent, MessagePart, MessageStructure, SearchQuery,
+};
+use crate::platform::FileSystem;
+use crate::scheduler::{self, SyncScheduler};
+use crate::storage::Storage;
+
+mod attachments;
+mod contacts;
+mod reports;
+mod drafts;
+mod junk;
+mod new_mail;
+mod outbox;
+mod prefetch;
+mod retention;
+mod rules;
+mod search;
+mod snooze;
+mod trash;
+mod undo;
+
+pub use new_mail::{EnvelopeSummary, NewMailEvent};
+pub use search::{SearchHits, SearchSource};
+pub use snooze::SnoozeEvent;
+pub use undo::MailAction;
+
+/// How long a single IDLE lasts before it is restarted. Servers may drop
+/// connections idle for more than 30 minutes (RFC 2177).
+const IDLE_TIMEOUT: Duration = Duration::from_secs(29 * 60);
+/// How many new mail or snooze events a subscriber can fall behind before it
+/// misses some.
+const EVENT_CAPACITY: usize = 64;
+
+fn elapsed_ms(since: DateTime<Utc>) -> f64 {
+    (Utc::now() - since).num_milliseconds() as f64
+}
+
+/// High-level entry point for consumers of the library.
+///
+/// `EmailService` ties an [`EmailConnector`] (the remote side) to a [`Storage`]
+/// (the local cache). Sync operations pull data from the connector and persist
+/// it, read operations are served from storage whenever possible.
+pub struct EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    connector: Arc<dyn EmailConnector<S>>,
+    storage: Arc<dyn Storage>,
+    /// Where large attachments are kept while composing.
+    files: Option<Arc<dyn FileSystem>>,
+    new_mail: broadcast::Sender<NewMailEvent>,
+    metrics: Arc<dyn Metrics>,
+    snoozes: broadcast::Sender<SnoozeEvent>,
+    /// Wakes [`run_snoozes`](Self::run_snoozes) when a snooze is added or
+    /// taken back.
+    snooze_changed: Notify,
+    /// What reverts the last actions, and what takes back the last undos.
+    undo: Mutex<Vec<Vec<undo::Change>>>,
+    redo: Mutex<Vec<Vec<undo::Change>>>,
+}
+
+impl<S> EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    pub fn new(connector: Arc<dyn EmailConnector<S>>, storage: Arc<dyn Storage>) -> Self {
+        Self {
+            connector,
+            storage,
+            files: None,
+            new_mail: broadcast::channel(EVENT_CAPACITY).0,
+            metrics: Arc::new(NoMetrics),
+            snoozes: broadcast::channel(EVENT_CAPACITY).0,
+            snooze_changed: Notify::new(),
+            undo: Mutex::new(Vec::new()),
+            redo: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Reports sync durations, fetches and cache use to `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn with_file_system(mut self, files: Arc<dyn FileSystem>) -> Self {
+        self.files = Some(files);
+        self
+    }
+
+    pub fn connector(&self) -> &Arc<dyn EmailConnector<S>> {
+        &self.connector
+    }
+
+    pub fn storage(&self) -> &Arc<dyn Storage> {
+        &self.storage
+    }
+
+    /// Fetches the folder list from the server and replaces the cached one.
+    pub async fn sync_folders(&self, account_id: &AccountId) -> Result<Vec<Folder>> {
+        let folders = self.connector.list_folders(account_id).await?;
+        let remote_ids: HashSet<&FolderId> = folders.iter().map(|f| &f.id).collect();
+
+        for cached in self.storage.list_folders(account_id).await? {
+            if !remote_ids.contains(&cached.id) {
+                self.storage.delete_folder(&cached.id).await?;
+            }
+        }
+        for folder in &folders {
+            self.storage.save_folder(folder).await?;
+        }
+
+        Ok(folders)
+    }
+
+    /// Fetches the labels from the server and replaces the cached ones,
+    /// keeping the colors picked locally for labels the server has no color
+    /// for. Servers without a list of labels leave the cached ones alone.
+    pub async fn sync_labels(&self, account_id: &AccountId) -> Result<Vec<Label>> {
+        let mut labels = self.connector.list_labels(account_id).await?;
+        let cached = self.storage.list_labels(account_id).await?;
+        if labels.is_empty() {
+            return Ok(cached);
+        }
+
+        for cached in cached {
+            match labels.iter_mut().find(|label| label.name == cached.name) {
+                Some(label) if label.color.is_none() => label.color = cached.color,
+                Some(_) => {}
+                None => self.storage.delete_label(account_id, &cached.name).await?,
+            }
+        }
+        for label in &labels {
+            self.storage.save_label(label).await?;
+        }
+
+        Ok(labels)
+    }
+
+    /// Fetches the changes in a folder since the last sync and applies them to
+    /// the cached envelopes.
+    pub async fn sync_folder(&self, folder_id: &FolderId) -> Result<FolderMetadata> {
+        let started = Utc::now();
+        let state = match self.storage.get_folder_metadata(folder_id).await {
+            Ok(metadata) => metadata.sync_state,
+            Err(MailinerError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+        let cached = self.storage.list_envelopes(folder_id).await?;
+        let known: Vec<MessageId> = cached.iter().map(|e| e.id.clone()).collect();
+
+        let request = Utc::now();
+        let mut delta = self
+            .connector
+            .sync_folder(folder_id, state.as_ref(), &known)
+            .await?;
+        self.metrics.record(metrics::COMMAND_LATENCY_MS, elapsed_ms(request));
+        let cached_ids: HashSet<&MessageId> = known.iter().collect();
+        snooze::keep_snoozes(&cached, &mut delta.changed);
+        self.storage.delete_envelopes(&delta.vanished).await?;
+        self.storage.save_envelopes(&delta.changed).await?;
+        let new: Vec<Envelope> = delta
+            .changed
+            .iter()
+            .filter(|envelope| !cached_ids.contains(&envelope.id))
+            .cloned()
+            .collect();
+        self.collect_contacts(&new).await?;
+        for envelope in &delta.changed {
+            if cached_ids.contains(&envelope.id) || !delivery::looks_like_report(envelope) {
+                continue;
+            }
+            // A malformed notification is still an ordinary message.
+            match self.read_delivery_report(envelope).await {
+                Ok(_) | Err(MailinerError::InvalidData(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        // The first sync would apply the rules to, and announce, the whole
+        // mailbox.
+        if !known.is_empty() {
+            self.apply_rules(&new).await?;
+        }
+
+        let envelopes = self.storage.list_envelopes(folder_id).await?;
+        if !known.is_empty() {
+            // What the rules left of the new messages.
+            let new: HashSet<&MessageId> = new.iter().map(|envelope| &envelope.id).collect();
+            let left: Vec<Envelope> = envelopes
+                .iter()
+                .filter(|envelope| new.contains(&envelope.id))
+                .cloned()
+                .collect();
+            self.announce_new_mail(folder_id, &left).await?;
+        }
+        let metadata = FolderMetadata {
+            id: folder_id.clone(),
+            total_messages: envelopes.len() as u64,
+            unread_messages: envelopes.iter().filter(|e| !e.is_read).count() as u64,
+            last_sync: Utc::now(),
+            sync_state: delta.sync_state,
+            read_only: delta.read_only,
+        };
+        self.storage.save_folder_metadata(&metadata).await?;
+        self.metrics.record(metrics::SYNC_DURATION_MS, elapsed_ms(started));
+
+        Ok(metadata)
+    }
+
+    /// Refreshes the flags of cached envelopes that changed on the server since
+    /// the last sync, e.g. messages read in another client. Cheaper than
+    /// `sync_folder`, but doesn't pick up new or removed messages. Returns the
+    /// number of updated envelopes.
+    pub async fn resync_flags(&self, folder_id: &FolderId) -> Result<usize> {
+        let since = match self.storage.get_folder_metadata(folder_id).await {
+            Ok(metadata) => metadata.sync_state.map(|state| state.highest_modseq),
+            Err(MailinerError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+        // Without a sync state, fall back to the flags of all messages.
+        let updates = self
+            .connector
+            .resync_flags(folder_id, since.unwrap_or(0))
+            .await?;
+
+        let mut updated = Vec::new();
+        for update in &updates {
+            let mut envelope = match self.storage.get_envelope(&update.message_id).await {
+                Ok(envelope) => envelope,
+                // New messages are left to the next sync.
+                Err(MailinerError::NotFound(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            update.apply(&mut envelope);
+            envelope.updated_at = Utc::now();
+            updated.push(envelope);
+        }
+        self.storage.save_envelopes(&updated).await?;
+        Ok(updated.len())
+    }
+
+    /// Syncs the folder list and then every folder of the account, purges
+    /// the messages deleted long enough ago and applies the retention
+    /// policies. Folders are synced a few at a time, within the
+    /// [`SyncLimits`](crate::SyncLimits) defaults.
+    pub async fn sync_account(&self, account_id: &AccountId) -> Result<AccountMetadata> {
+        self.sync_account_with(account_id, &SyncScheduler::default())
+            .await
+    }
+
+    /// Like [`sync_account`](Self::sync_account), within the limits of
+    /// `scheduler` and starting with the folder it says is being viewed.
+    pub async fn sync_account_with(
+        &self,
+        account_id: &AccountId,
+        scheduler: &SyncScheduler,
+    ) -> Result<AccountMetadata> {
+        let folders = self.sync_folders(account_id).await?;
+
+        let order = scheduler::sync_order(&folders, scheduler.viewing().as_ref());
+        let folder_metadata: Vec<FolderMetadata> = stream::iter(&order)
+            .map(|folder_id| async move {
+                let _permit = scheduler.permit(folder_id).await;
+                self.sync_folder(folder_id).await
+            })
+            .buffered(scheduler.limits().per_account)
+            .try_collect()
+            .await?;
+        self.purge_deleted(account_id).await?;
+        self.apply_retention(account_id, false).await?;
+
+        let metadata = AccountMetadata {
+            id: account_id.clone(),
+            last_sync: Utc::now(),
+            folders: folder_metadata,
+        };
+        self.storage.save_account_metadata(&metadata).await?;
+
+        Ok(metadata)
+    }
+
+    /// Returns the account's preferences, or the defaults if none were saved yet.
+    pub async fn account_preferences(&self, account_id: &AccountId) -> Result<AccountPreferences> {
+        match self.storage.get_account_preferences(account_id).await {
+            Err(MailinerError::NotFound(_)) => Ok(AccountPreferences::new(account_id.clone())),
+            result => result,
+        }
+    }
+
+    pub async fn set_account_preferences(&self, preferences: &AccountPreferences) -> Result<()> {
+        self.storage.save_account_preferences(preferences).await
+    }
+
+    /// Adds an exception for a certificate the user approved after a
+    /// [`MailinerError::UntrustedCertificate`]. The connector has to be
+    /// recreated with the account's trusted certificates to pick it up.
+    pub async fn trust_certificate(&self, account_id: &AccountId, fingerprint: &str) -> Result<()> {
+        let mut preferences = self.account_preferences(account_id).await?;
+        if !preferences.trusted_certificates.iter().any(|f| f == fingerprint) {
+            preferences.trusted_certificates.push(fingerprint.to_string());
+            self.storage.save_account_preferences(&preferences).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn list_folders(&self, account_id: &AccountId) -> Result<Vec<Folder>> {
+        self.storage.list_folders(account_id).await
+    }
+
+    /// Renames a folder on the server and updates the cache, including the
+    /// subfolders, whose ids change along with it, and the cached envelopes.
+    pub async fn rename_folder(&self, folder_id: &FolderId, new_name: &str) -> Result<Folder> {
+        let folder = self.storage.get_folder(folder_id).await?;
+        let new_id = self.connector.rename_folder(folder_id, new_name).await?;
+        let folders = self.storage.list_folders(&folder.account_id).await?;
+
+        let renamed = Folder {
+            id: new_id,
+            name: new_name.to_string(),
+            updated_at: Utc::now(),
+            ..folder
+        };
+        let mut pending = vec![(folder_id.clone(), renamed.clone())];
+        while let Some((old_id, folder)) = pending.pop() {
+            for child in folders
+                .iter()
+                .filter(|f| f.parent_id.as_ref() == Some(&old_id))
+            {
+                let id = match child.id.as_str().strip_prefix(old_id.as_str()) {
+                    Some(suffix) => FolderId::new(format!("{}{}", folder.id, suffix)),
+                    None => child.id.clone(),
+                };
+                let moved = Folder {
+                    id,
+                    parent_id: Some(folder.id.clone()),
+                    ..child.clone()
+                };
+                pending.push((child.id.clone(), moved));
+            }
+            self.replace_cached_folder(&old_id, &folder).await?;
+        }
+
+        Ok(renamed)
+    }
+
+    async fn replace_cached_folder(&self, old_id: &FolderId, folder: &Folder) -> Result<()> {
+        match self.storage.delete_folder(old_id).await {
+            Err(MailinerError::NotFound(_)) | Ok(()) => {}
+            Err(e) => return Err(e),
+        }
+        self.storage.save_folder(folder).await?;
+
+        let mut envelopes = self.storage.list_envelopes(old_id).await?;
+        for envelope in &mut envelopes {
+            envelope.folder_id = folder.id.clone();
+        }
+        self.storage.save_envelopes(&envelopes).await?;
+        match self.storage.get_folder_metadata(old_id).await {
+            Ok(metadata) => {
+                self.storage
+                    .save_folder_metadata(&FolderMetadata {
+                        id: folder.id.clone(),
+                        ..metadata
+                    })
+                    .await
+            }
+            Err(MailinerError::NotFound(_)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the cached envelopes of a folder, without the snoozed ones.
+    pub async fn list_envelopes(&self, folder_id: &FolderId) -> Result<Vec<Envelope>> {
+        let now = Utc::now();
+        let mut envelopes = self.storage.list_envelopes(folder_id).await?;
+        envelopes.retain(|envelope| !envelope.is_snoozed(now));
+        Ok(envelopes)
+    }
+
+    /// Returns a filtered, sorted page of the cached envelopes of a folder.
+    pub async fn query_envelopes(
+        &self,
+        folder_id: &FolderId,
+        query: &EnvelopeQuery,
+    ) -> Result<Vec<Envelope>> {
+        self.storage.query_envelopes(folder_id, query).await
+    }
+
+    /// Fetches `limit` envelopes of a folder from the server, skipping the
+    /// `offset` newest ones, and caches them. Lets a message list show the
+    /// newest messages without waiting for a full sync of a large folder.
+    /// Snoozed messages are left out.
+    pub async fn list_envelopes_page(
+        &self,
+        folder_id: &FolderId,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<Envelope>> {
+        let mut envelopes = self
+            .connector
+            .list_envelopes_range(folder_id, offset..offset.saturating_add(limit))
+            .await?;
+        let cached = self.storage.list_envelopes(folder_id).await?;
+        snooze::keep_snoozes(&cached, &mut envelopes);
+        self.storage.save_envelopes(&envelopes).await?;
+        let now = Utc::now();
+        envelopes.retain(|envelope| !envelope.is_snoozed(now));
+        Ok(envelopes)
+    }
+
+    pub async fn get_message_structure(&self, message_id: &MessageId) -> Result<MessageStructure> {
+        self.connector.get_message_structure(message_id).await
+    }
+
+    /// Returns the source of a message, to save it as an `.eml` file. It's
+    /// always fetched, the cache only keeps decoded parts.
+    pub async fn export_message(&self, message_id: &MessageId) -> Result<Vec<u8>> {
+        self.connector.fetch_raw_message(message_id).await
+    }
+
+    /// Returns a message part from the cache, fetching and caching it on a miss.
+    pub async fn get_message_part(
+        &self,
+        message_id: &MessageId,
+        part_id: &MessagePartId,
+    ) -> Result<MessagePart> {
+        self.get_message_part_with_progress(message_id, part_id, &|_, _| {})
+            .await
+    }
+
+    /// Like `get_message_part`, reporting the download progress of parts
+    /// that aren't cached yet, e.g. for a progress bar of large attachments.
+    pub async fn get_message_part_with_progress(
+        &self,
+        message_id: &MessageId,
+        part_id: &MessagePartId,
+        progress: &(dyn Fn(u64, Option<u64>) + Send + Sync),
+    ) -> Result<MessagePart> {
+        // Part IDs are only unique within a message, so look them up per envelope.
+        let cached = self
+            .storage
+            .list_message_parts(message_id)
+            .await?
+            .into_iter()
+            .find(|part| part.id == *part_id);
+        if let Some(part) = cached {
+            self.metrics.increment(metrics::CACHE_HITS, 1);
+            return Ok(part);
+        }
+
+        self.metrics.increment(metrics::CACHE_MISSES, 1);
+        let request = Utc::now();
+        let part = self
+            .connector
+            .get_message_part_with_progress(message_id, part_id, progress)
+            .await?;
+        self.metrics.record(metrics::COMMAND_LATENCY_MS, elapsed_ms(request));
+        self.metrics.record(metrics::FETCH_BYTES, part.size as f64);
+        self.storage.save_message_part(&part).await?;
+        Ok(part)
+    }
+
+    /// Updates flags on the server first and mirrors the change into storage.
+    pub async fn update_envelope_flags(
+        &self,
+        message_id: &MessageId,
+        flags: &[(&str, bool)],
+    ) -> Result<()> {
+        self.connector
+            .update_envelope_flags(message_id, flags)
+            .await?;
+        self.storage.update_envelope_flags(message_id, flags).await
+    }
+
+    /// Updates keywords on the server first and mirrors the change into storage.
+    pub async fn update_envelope_keywords(
+        &self,
+        message_id: &MessageId,
+        keywords: &[(&str, bool)],
+    ) -> Result<()> {
+        self.connector
+            .update_envelope_keywords(message_id, keywords)
+            .await?;
+        self.storage
+            .update_envelope_keywords(message_id, keywords)
+            .await
+    }
+
+    /// Puts labels on or takes them off a message on the server first, then
+    /// in storage. Labels the account doesn't have yet are added to it.
+    pub async fn update_envelope_labels(
+        &self,
+        message_id: &MessageId,
+        labels: &[(&str, bool)],
+    ) -> Result<()> {
+        self.connector
+            .update_envelope_labels(message_id, labels)
+            .await?;
+        let envelope = self.storage.get_envelope(message_id).await?;
+        let known: HashSet<String> = self
+            .storage
+            .list_labels(&envelope.account_id)
+            .await?
+            .into_iter()
+            .map(|label| label.name)
+            .collect();
+        for (name, _) in labels.iter().filter(|(_, value)| *value) {
+            if !known.contains(*name) {
+                let label = Label::new(envelope.account_id.clone(), *name);
+                self.storage.save_label(&label).await?;
+            }
+        }
+        self.storage
+            .update_envelope_labels(message_id, labels)
+            .await
+    }
+
+    /// Moves a message on the server. The message gets a new id in the target
+    /// folder; if the server reports it, the cached envelope is moved along,
+    /// otherwise it's dropped from the cache and shows up with the next sync
+    /// of the target folder.
+    pub async fn move_message(
+        &self,
+        message_id: &MessageId,
+        target_folder_id: &FolderId,
+    ) -> Result<()> {
+        self.move_message_tracked(message_id, target_folder_id)
+            .await
+            .map(|_| ())
+    }
+
+    /// Like [`move_message`](Self::move_message), returning the new id if the
+    /// server reports it.
+    pub(super) async fn move_message_tracked(
+        &self,
+        message_id: &MessageId,
+        target_folder_id: &FolderId,
+    ) -> Result<Option<MessageId>> {
+        let new_id = self
+            .connector
+            .move_message(message_id, target_folder_id)
+            .await?;
+        self.cache_move(message_id, new_id.clone(), target_folder_id)
+            .await?;
+        Ok(new_id)
+    }
+
+    /// Moves the cached envelope of a message the server moved to `new_id`,
+    /// or drops it if the new id isn't known.
+    pub(super) async fn cache_move(
+        &self,
+        message_id: &MessageId,
+        new_id: Option<MessageId>,
+        target_folder_id: &FolderId,
+    ) -> Result<()> {
+        let envelope = self.cached_envelope(message_id).await?;
+        match self.storage.delete_envelope(message_id).await {
+            Err(MailinerError::NotFound(_)) | Ok(()) => {}
+            Err(e) => return Err(e),
+        }
+        if let (Some(envelope), Some(new_id)) = (envelope, new_id) {
+            self.cache_envelope_copy(envelope, new_id, target_folder_id)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn copy_message(
+        &self,
+        message_id: &MessageId,
+        target_folder_id: &FolderId,
+    ) -> Result<()> {
+        let new_id = self
+            .connector
+            .copy_message(message_id, target_folder_id)
+            .await?;
+        if let (Some(envelope), Some(new_id)) = (self.cached_envelope(message_id).await?, new_id) {
+            self.cache_envelope_copy(envelope, new_id, target_folder_id)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn cached_envelope(&self, message_id: &MessageId) -> Result<Option<Envelope>> {
+        match self.storage.get_envelope(message_id).await {
+            Ok(envelope) => Ok(Some(envelope)),
+            Err(MailinerError::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn cache_envelope_copy(
+        &self,
+        mut envelope: Envelope,
+        id: MessageId,
+        folder_id: &FolderId,
+    ) -> Result<()> {
+        envelope.id = id;
+        envelope.folder_id = folder_id.clone();
+        envelope.updated_at = Utc::now();
+        self.storage.save_envelope(&envelope).await
+    }
+
+    /// Searches a folder on the server. See
+    /// [`search_account`](Self::search_account) for a search that answers
+    /// from the cache first.
+    pub async fn search(
+        &self,
+        folder_id: &FolderId,
+        query: &SearchQuery,
+    ) -> Result<Vec<MessageId>> {
+        self.connector.search(folder_id, query).await
+    }
+
+    /// Searches the local cache instead of the server, so it works offline.
+    pub async fn search_local(
+        &self,
+        folder_id: &FolderId,
+        query: &SearchQuery,
+    ) -> Result<Vec<MessageId>> {
+        self.storage.search(folder_id, query).await
+    }
+
+    /// Watches a folder for changes, calling `on_event` for each of them, until
+    /// the connection fails.
+    pub async fn watch_folder(
+        &self,
+        folder_id: &FolderId,
+        mut on_event: impl FnMut(FolderEvent) + Send,
+    ) -> Result<()> {
+        loop {
+            for event in self.connector.idle(folder_id, IDLE_TIMEOUT).await? {
+                on_event(event);
+            }
+        }
+    }
+
+    /// Answers a calendar invitation stored in the `text/calendar` part `part_id`.
+    ///
+    /// Returns the iMIP reply message for the organizer, ready for submission,
+    /// and records the answer in the cached invitation so that it shows the new
+    /// RSVP state.
+    pub async fn respond_to_invitation(
+        &self,
+        message_id: &MessageId,
+        part_id: &MessagePartId,
+        attendee: &EmailAddr,
+        status: ParticipationStatus,
+    ) -> Result<Vec<u8>> {
+        let mut part = self.get_message_part(message_id, part_id).await?;
+        let mut calendar = match &part.content {
+            MessageContent::Text(text) => Calendar::parse(text)?,
+            MessageContent::Binary(data) => Calendar::parse(&String::from_utf8_lossy(data))?,
+            MessageContent::Html(_) => {
+                return Err(MailinerError::InvalidData(format!(
+                    "Part {} is not a calendar",
+                    part_id
+                )))
+            }
+        };
+        let event = calendar.events().into_iter().next().ok_or_else(|| {
+            MailinerError::InvalidData(format!("Part {} contains no event", part_id))
+        })?;
+
+        let reply = event.reply_message(attendee, status)?;
+
+        calendar.set_participation_status(attendee.email.as_deref().unwrap_or_default(), status);
+        part.content = MessageContent::Text(calendar.to_string());
+        part.updated_at = Utc::now();
+        self.storage.save_message_part(&part).await?;
+
+        Ok(reply)
+    }
+}
@@ -0,0 +1,89 @@
+//! Attachments of compositions, of which large ones are kept on disk rather
+//! than in memory while the message is being written.
+
+use std::fmt::Debug;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::EmailService;
+use crate::compose::{attachments, Attachment, AttachmentData, Composition};
+use crate::error::{MailinerError, Result};
+use crate::ids::AccountId;
+use crate::models::AttachmentLimits;
+
+impl<S> EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    /// Attaches a file to `composition`, within the account's size limits.
+    pub async fn attach(
+        &self,
+        account_id: &AccountId,
+        composition: &mut Composition,
+        filename: &str,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let limits = self.account_preferences(account_id).await?.attachment_limits;
+        attachments::check_size(composition, data.len() as u64, &limits)?;
+        let content_type = attachments::content_type(filename, &data).to_string();
+        composition.attachments.push(Attachment {
+            filename: filename.to_string(),
+            content_type,
+            data: self.keep_attachment_data(data, &limits).await?,
+        });
+        Ok(())
+    }
+
+    /// Removes the attachment at `index` from `composition`.
+    pub async fn remove_attachment(
+        &self,
+        composition: &mut Composition,
+        index: usize,
+    ) -> Result<()> {
+        if index >= composition.attachments.len() {
+            return Err(MailinerError::NotFound(format!("Attachment {}", index)));
+        }
+        let attachment = composition.attachments.remove(index);
+        self.delete_attachment_file(&attachment).await
+    }
+
+    /// Deletes the files of the attachments of a composition that was sent
+    /// or discarded.
+    pub async fn discard_attachments(&self, composition: &Composition) -> Result<()> {
+        for attachment in &composition.attachments {
+            self.delete_attachment_file(attachment).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes data over the memory threshold to a file, if there is a file
+    /// system to write to.
+    pub(super) async fn keep_attachment_data(
+        &self,
+        data: Vec<u8>,
+        limits: &AttachmentLimits,
+    ) -> Result<AttachmentData> {
+        match &self.files {
+            Some(files) if data.len() as u64 > limits.memory_threshold => {
+                let path = format!("attachments/{}", uuid::Uuid::new_v4());
+                files.write(&path, &data).await?;
+                Ok(AttachmentData::File {
+                    path,
+                    size: data.len() as u64,
+                })
+            }
+            _ => Ok(AttachmentData::Memory(data)),
+        }
+    }
+
+    async fn delete_attachment_file(&self, attachment: &Attachment) -> Result<()> {
+        let (Some(files), AttachmentData::File { path, .. }) = (&self.files, &attachment.data)
+        else {
+            return Ok(());
+        };
+        match files.remove(path).await {
+            Err(MailinerError::NotFound(_)) | Ok(()) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Collecting contacts from the mail that is synced and sent, for completing
+//! addresses in the composer.
+
+use std::fmt::Debug;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::EmailService;
+use crate::contacts::{self, sightings, Contact, Sighting};
+use crate::error::{MailinerError, Result};
+use crate::models::Envelope;
+
+impl<S> EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    /// The contacts to offer when completing an address that starts with
+    /// `prefix`, the most frecent first.
+    pub async fn suggest_contacts(&self, prefix: &str, limit: usize) -> Result<Vec<Contact>> {
+        self.storage.search_contacts(prefix, limit).await
+    }
+
+    /// Counts the senders of newly synced `envelopes` of an account, or
+    /// their recipients for the ones the user sent.
+    pub(super) async fn collect_contacts(&self, envelopes: &[Envelope]) -> Result<()> {
+        let Some(first) = envelopes.first() else {
+            return Ok(());
+        };
+        let own = match self.storage.get_account(&first.account_id).await {
+            Ok(account) => Some(account.email),
+            Err(MailinerError::NotFound(_)) => None,
+            Err(e) => return Err(e),
+        };
+        self.record_contacts(sightings(envelopes, own.as_deref()))
+            .await
+    }
+
+    /// Adds what was `seen` to the contacts, creating ones for new
+    /// addresses.
+    pub(super) async fn record_contacts(&self, seen: Vec<Sighting>) -> Result<()> {
+        if seen.is_empty() {
+            return Ok(());
+        }
+        let mut contacts = self.storage.list_contacts().await?;
+        let changed = contacts::record(&mut contacts, seen);
+        for i in changed {
+            self.storage.save_contact(&contacts[i]).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::contacts::{RECEIVED_WEIGHT, SENT_WEIGHT};
+    use crate::ids::{AccountId, FolderId, MessageId};
+    use crate::models::{EmailAddr, EmailAddress};
+
+    fn addr(email: &str) -> EmailAddress {
+        EmailAddress::List(vec![EmailAddr {
+            name: None,
+            email: Some(email.to_string()),
+        }])
+    }
+
+    fn envelope(from: &str, to: &str) -> Envelope {
+        Envelope {
+            id: MessageId::new("INBOX;UID=1"),
+            account_id: AccountId::new("imap-jan"),
+            folder_id: FolderId::new("INBOX"),
+            subject: None,
+            from: Some(addr(from)),
+            to: Some(addr(to)),
+            cc: None,
+            bcc: None,
+            reply_to: None,
+            date: Utc::now(),
+            is_read: false,
+            is_starred: false,
+            is_flagged: false,
+            is_draft: false,
+            is_deleted: false,
+            has_attachments: false,
+            size: None,
+            keywords: BTreeSet::new(),
+            labels: BTreeSet::new(),
+            snoozed_until: None,
+            thread_id: None,
+            remote_id: None,
+            message_id: None,
+            in_reply_to: Vec::new(),
+            references: Vec::new(),
+            unsubscribe: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn collects_senders_and_recipients() {
+        let envelopes = [
+            envelope("ana@example.com", "jan@example.com"),
+            envelope("Jan@Example.com", "bob@example.com"),
+        ];
+        let seen: Vec<(Option<String>, f64)> = sightings(&envelopes, Some("jan@example.com"))
+            .into_iter()
+            .map(|(addr, _, weight)| (addr.email, weight))
+            .collect();
+        assert_eq!(
+            seen,
+            [
+                (Some("ana@example.com".to_string()), RECEIVED_WEIGHT),
+                (Some("bob@example.com".to_string()), SENT_WEIGHT),
+            ]
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Drafts, which are kept as messages in the account's Drafts folder so that
+//! other clients see them too.
+
+use std::fmt::Debug;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::EmailService;
+use crate::compose::{Attachment, Composition};
+use crate::error::{MailinerError, Result};
+use crate::ids::{AccountId, FolderId, MessageId};
+use crate::models::{EmailAddr, EmailAddress, MessageContent, MessagePart};
+use crate::sender::MessageSender;
+
+impl<S> EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    /// Saves a draft to `folder_id`, replacing `previous`, the version saved
+    /// before. Returns the id of the new version if the server reports it;
+    /// otherwise it shows up with the next sync of the folder.
+    pub async fn save_draft(
+        &self,
+        folder_id: &FolderId,
+        composition: &Composition,
+        previous: Option<&MessageId>,
+    ) -> Result<Option<MessageId>> {
+        let id = self
+            .connector
+            .append_message(
+                folder_id,
+                &["\\Draft", "\\Seen"],
+                &composition.to_draft(self.files.as_deref()).await?,
+            )
+            .await?;
+        // Only drop the old version once the new one is safe.
+        if let Some(previous) = previous {
+            self.delete_draft(previous).await?;
+        }
+        Ok(id)
+    }
+
+    /// Loads a draft back into a composition, to continue writing it.
+    pub async fn open_draft(&self, message_id: &MessageId) -> Result<Composition> {
+        let envelope = self.connector.get_envelope(message_id).await?;
+        let structure = self.get_message_structure(message_id).await?;
+        let limits = self
+            .account_preferences(&envelope.account_id)
+            .await?
+            .attachment_limits;
+
+        let mut composition = Composition {
+            from: envelope
+                .from
+                .as_ref()
+                .and_then(|from| from.addrs().first().map(|addr| (*addr).clone())),
+            to: addr_list(envelope.to.as_ref()),
+            cc: addr_list(envelope.cc.as_ref()),
+            bcc: addr_list(envelope.bcc.as_ref()),
+            subject: envelope.subject.unwrap_or_default(),
+            in_reply_to: envelope.in_reply_to.first().cloned(),
+            references: envelope.references,
+            ..Default::default()
+        };
+        if let Some(id) = structure.find("text/html").and_then(|part| part.id.as_ref()) {
+            let part = self.get_message_part(message_id, id).await?;
+            composition.body_html = Some(text_content(part));
+        }
+        if let Some(id) = structure.find("text/plain").and_then(|part| part.id.as_ref()) {
+            let part = self.get_message_part(message_id, id).await?;
+            composition.body_text = crate::compose::plaintext::unflow(&text_content(part));
+        }
+        for attachment in structure.attachments() {
+            let Some(id) = &attachment.id else {
+                continue;
+            };
+            let part = self.get_message_part(message_id, id).await?;
+            let data = match part.content {
+                MessageContent::Binary(data) => data,
+                MessageContent::Text(text) | MessageContent::Html(text) => text.into_bytes(),
+            };
+            composition.attachments.push(Attachment {
+                filename: attachment.filename.clone().unwrap_or_default(),
+                content_type: attachment.content_type.clone(),
+                data: self.keep_attachment_data(data, &limits).await?,
+            });
+        }
+        Ok(composition)
+    }
+
+    /// Deletes a draft from the server and from the cache.
+    pub async fn delete_draft(&self, message_id: &MessageId) -> Result<()> {
+        self.connector.delete_message(message_id).await?;
+        match self.storage.delete_envelope(message_id).await {
+            Err(MailinerError::NotFound(_)) | Ok(()) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Sends a composition with `sender`, and deletes the draft it was
+    /// written in, if any, once it was sent.
+    pub async fn send_composition<T>(
+        &self,
+        sender: &dyn MessageSender<T>,
+        account_id: &AccountId,
+        composition: &Composition,
+        draft: Option<&MessageId>,
+    ) -> Result<()>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+    {
+        let from = composition
+            .from
+            .as_ref()
+            .and_then(|from| from.email.clone())
+            .ok_or_else(|| MailinerError::InvalidData("The message has no sender".to_string()))?;
+        let recipients: Vec<String> = composition
+            .recipients()
+            .filter_map(|addr| addr.email.clone())
+            .collect();
+        let preferences = self.account_preferences(account_id).await?;
+        let message = composition
+            .to_message(&preferences, self.files.as_deref())
+            .await?;
+
+        sender.send(&from, &recipients, &message).await?;
+        self.discard_attachments(composition).await?;
+        if let Some(draft) = draft {
+            self.delete_draft(draft).await?;
+        }
+        Ok(())
+    }
+}
+
+fn addr_list(address: Option<&EmailAddress>) -> Vec<EmailAddr> {
+    address
+        .map(|address| address.addrs().into_iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+fn text_content(part: MessagePart) -> String {
+    match part.content {
+        MessageContent::Text(text) | MessageContent::Html(text) => text,
+        MessageContent::Binary(data) => String::from_utf8_lossy(&data).into_owned(),
+    }
+}
@@ -0,0 +1,107 @@
+//! Marking messages as junk and back, moving them into the Junk folder or
+//! out of it.
+
+use std::fmt::Debug;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::EmailService;
+use crate::error::{MailinerError, Result};
+use crate::ids::{AccountId, FolderId, MessageId};
+use crate::models::Folder;
+
+const JUNK_NAMES: [&str; 4] = ["junk", "spam", "junk e-mail", "bulk mail"];
+
+/// The folder named like a Junk folder, e.g. `INBOX.Junk` or `[Gmail]/Spam`.
+fn find_junk_folder(folders: &[Folder]) -> Option<&Folder> {
+    folders.iter().find(|folder| {
+        let name = folder
+            .name
+            .rsplit(['/', '.'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        JUNK_NAMES.contains(&name.as_str())
+    })
+}
+
+impl<S> EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    /// The account's Junk folder: the one set in the preferences, or else
+    /// the one named like it.
+    pub async fn junk_folder(&self, account_id: &AccountId) -> Result<FolderId> {
+        if let Some(folder_id) = self.account_preferences(account_id).await?.junk_folder {
+            return Ok(folder_id);
+        }
+        let folders = self.storage.list_folders(account_id).await?;
+        find_junk_folder(&folders)
+            .map(|folder| folder.id.clone())
+            .ok_or_else(|| MailinerError::NotFound(format!("Junk folder of {}", account_id)))
+    }
+
+    /// Marks a cached message as junk and moves it to the Junk folder.
+    pub async fn mark_junk(&self, message_id: &MessageId) -> Result<()> {
+        let account_id = self.storage.get_envelope(message_id).await?.account_id;
+        let junk = self.junk_folder(&account_id).await?;
+        self.set_junk(message_id, true, &junk).await
+    }
+
+    /// Marks a cached message as not junk and moves it to `folder_id`,
+    /// usually the inbox.
+    pub async fn mark_not_junk(&self, message_id: &MessageId, folder_id: &FolderId) -> Result<()> {
+        self.set_junk(message_id, false, folder_id).await
+    }
+
+    async fn set_junk(
+        &self,
+        message_id: &MessageId,
+        junk: bool,
+        folder_id: &FolderId,
+    ) -> Result<()> {
+        let keywords = [("$Junk", junk), ("$NotJunk", !junk)];
+        let envelope = self.storage.get_envelope(message_id).await?;
+        if envelope.folder_id == *folder_id {
+            return self.update_envelope_keywords(message_id, &keywords).await;
+        }
+        let new_id = self
+            .connector
+            .mark_junk(message_id, junk, folder_id)
+            .await?;
+        self.storage
+            .update_envelope_keywords(message_id, &keywords)
+            .await?;
+        self.cache_move(message_id, new_id, folder_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn folder(name: &str) -> Folder {
+        Folder {
+            id: FolderId::new(name),
+            account_id: AccountId::new("imap-jan"),
+            name: name.to_string(),
+            parent_id: None,
+            rights: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn finds_junk_folder() {
+        let folders = [folder("INBOX"), folder("Junkyard"), folder("[Gmail]/Spam")];
+        assert_eq!(
+            find_junk_folder(&folders).map(|folder| folder.name.as_str()),
+            Some("[Gmail]/Spam")
+        );
+        assert!(find_junk_folder(&folders[..2]).is_none());
+        assert!(find_junk_folder(&[folder("INBOX.Junk")]).is_some());
+    }
+}
@@ -0,0 +1,211 @@
+//! Events about new mail found by a sync, for the UI to show as
+//! notifications.
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::broadcast;
+
+use super::EmailService;
+use crate::error::Result;
+use crate::ids::{AccountId, FolderId, MessageId};
+use crate::models::{AccountPreferences, Envelope};
+use crate::platform::Notification;
+
+/// What a notification shows of a new message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvelopeSummary {
+    pub id: MessageId,
+    pub from: Option<String>,
+    pub subject: Option<String>,
+    pub date: DateTime<Utc>,
+}
+
+/// New unread messages in a folder the user wants to be notified about.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NewMailEvent {
+    pub account_id: AccountId,
+    pub folder_id: FolderId,
+    /// The new messages, newest first.
+    pub envelopes: Vec<EnvelopeSummary>,
+    pub count: usize,
+}
+
+impl NewMailEvent {
+    pub fn to_notification(&self) -> Notification {
+        match self.envelopes.as_slice() {
+            [envelope] => Notification {
+                title: envelope.from.clone().unwrap_or_default(),
+                body: envelope.subject.clone().unwrap_or_default(),
+            },
+            _ => Notification {
+                title: format!("{} new messages", self.count),
+                body: self
+                    .envelopes
+                    .iter()
+                    .filter_map(|envelope| envelope.from.clone())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            },
+        }
+    }
+}
+
+/// The event for the `new` messages of a folder, if the preferences ask for
+/// one. `contacts` are the known addresses, lowercase.
+fn new_mail_event(
+    preferences: &AccountPreferences,
+    folder_id: &FolderId,
+    new: &[Envelope],
+    contacts: &HashSet<String>,
+) -> Option<NewMailEvent> {
+    if !preferences.notify_folders.contains(folder_id) {
+        return None;
+    }
+    let now = Utc::now();
+    let mut envelopes: Vec<&Envelope> = new
+        .iter()
+        .filter(|envelope| !envelope.is_read && !envelope.is_deleted && !envelope.is_snoozed(now))
+        .filter(|envelope| {
+            !preferences.notify_only_contacts
+                || envelope.from.as_ref().is_some_and(|from| {
+                    from.addrs().iter().any(|addr| {
+                        addr.email
+                            .as_ref()
+                            .is_some_and(|email| contacts.contains(&email.to_lowercase()))
+                    })
+                })
+        })
+        .collect();
+    if envelopes.is_empty() {
+        return None;
+    }
+    envelopes.sort_by_key(|envelope| std::cmp::Reverse(envelope.date));
+    Some(NewMailEvent {
+        account_id: preferences.account_id.clone(),
+        folder_id: folder_id.clone(),
+        count: envelopes.len(),
+        envelopes: envelopes
+            .into_iter()
+            .map(|envelope| EnvelopeSummary {
+                id: envelope.id.clone(),
+                from: envelope.from.as_ref().map(|from| from.to_string()),
+                subject: envelope.subject.clone(),
+                date: envelope.date,
+            })
+            .collect(),
+    })
+}
+
+impl<S> EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    /// Subscribes to the new mail found by [`sync_folder`](Self::sync_folder)
+    /// in the folders listed in
+    /// [`AccountPreferences::notify_folders`](crate::AccountPreferences::notify_folders).
+    pub fn subscribe_new_mail(&self) -> broadcast::Receiver<NewMailEvent> {
+        self.new_mail.subscribe()
+    }
+
+    /// Announces the `new` messages of a folder to the subscribers.
+    pub(super) async fn announce_new_mail(
+        &self,
+        folder_id: &FolderId,
+        new: &[Envelope],
+    ) -> Result<()> {
+        let Some(first) = new.first() else {
+            return Ok(());
+        };
+        if self.new_mail.receiver_count() == 0 {
+            return Ok(());
+        }
+        let preferences = self.account_preferences(&first.account_id).await?;
+        let contacts = if preferences.notify_only_contacts {
+            self.storage
+                .list_contacts()
+                .await?
+                .into_iter()
+                .flat_map(|contact| contact.addresses)
+                .collect()
+        } else {
+            HashSet::new()
+        };
+        if let Some(event) = new_mail_event(&preferences, folder_id, new, &contacts) {
+            // Nobody listening anymore is fine.
+            let _ = self.new_mail.send(event);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::models::{EmailAddr, EmailAddress};
+
+    fn envelope(uid: u32, from: &str, is_read: bool) -> Envelope {
+        Envelope {
+            id: MessageId::new(format!("INBOX;UID={}", uid)),
+            account_id: AccountId::new("imap-jan"),
+            folder_id: FolderId::new("INBOX"),
+            subject: Some(format!("Message {}", uid)),
+            from: Some(EmailAddress::List(vec![EmailAddr {
+                name: None,
+                email: Some(from.to_string()),
+            }])),
+            to: None,
+            cc: None,
+            bcc: None,
+            reply_to: None,
+            date: Utc::now() - chrono::Duration::minutes(uid.into()),
+            is_read,
+            is_starred: false,
+            is_flagged: false,
+            is_draft: false,
+            is_deleted: false,
+            has_attachments: false,
+            size: None,
+            keywords: BTreeSet::new(),
+            labels: BTreeSet::new(),
+            snoozed_until: None,
+            thread_id: None,
+            remote_id: None,
+            message_id: None,
+            in_reply_to: Vec::new(),
+            references: Vec::new(),
+            unsubscribe: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn filters_new_mail() {
+        let inbox = FolderId::new("INBOX");
+        let new = [
+            envelope(1, "ana@example.com", false),
+            envelope(2, "spam@example.net", false),
+            envelope(3, "bob@example.com", true),
+        ];
+        let contacts = HashSet::from(["ana@example.com".to_string()]);
+        let mut preferences = AccountPreferences::new(AccountId::new("imap-jan"));
+        assert_eq!(new_mail_event(&preferences, &inbox, &new, &contacts), None);
+
+        preferences.notify_folders.push(inbox.clone());
+        let event = new_mail_event(&preferences, &inbox, &new, &contacts).unwrap();
+        assert_eq!(event.count, 2);
+        assert_eq!(event.envelopes[0].id, MessageId::new("INBOX;UID=1"));
+        assert_eq!(event.to_notification().title, "2 new messages");
+
+        preferences.notify_only_contacts = true;
+        let event = new_mail_event(&preferences, &inbox, &new, &contacts).unwrap();
+        assert_eq!(event.count, 1);
+        assert_eq!(event.to_notification().body, "Message 1");
+    }
+}
@@ -0,0 +1,305 @@
+//! The outbox, which keeps composed messages until they are sent, so that
+//! sending works offline and survives temporary server failures.
+
+use std::fmt::Debug;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::EmailService;
+use crate::compose::Composition;
+use crate::contacts::SENT_WEIGHT;
+use crate::error::{MailinerError, Result};
+use crate::ids::{AccountId, MessageId, OutboxId};
+use crate::models::{EmailAddr, OutboxMessage, OutboxStatus};
+use crate::platform::Timer;
+use crate::sender::MessageSender;
+
+/// Number of failed attempts after which a message is given up on.
+const MAX_ATTEMPTS: u32 = 8;
+const FIRST_RETRY_DELAY_SECS: i64 = 30;
+const MAX_RETRY_DELAY_SECS: i64 = 60 * 60;
+/// How long sent messages stay in the outbox.
+const SENT_RETENTION_HOURS: i64 = 24;
+
+/// How long to wait after the `attempts`-th failure: twice as long after
+/// each failure, starting at 30 seconds, up to an hour.
+fn retry_delay(attempts: u32) -> chrono::Duration {
+    let factor = 1i64 << attempts.saturating_sub(1).min(16);
+    chrono::Duration::seconds((FIRST_RETRY_DELAY_SECS * factor).min(MAX_RETRY_DELAY_SECS))
+}
+
+impl<S> EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    /// Puts a composition into the outbox. `draft` is deleted once the
+    /// message is sent.
+    pub async fn queue_composition(
+        &self,
+        account_id: &AccountId,
+        composition: &Composition,
+        draft: Option<&MessageId>,
+    ) -> Result<OutboxMessage> {
+        let from = composition
+            .from
+            .as_ref()
+            .and_then(|from| from.email.clone())
+            .ok_or_else(|| MailinerError::InvalidData("The message has no sender".to_string()))?;
+        let recipients: Vec<String> = composition
+            .recipients()
+            .filter_map(|addr| addr.email.clone())
+            .collect();
+        if recipients.is_empty() {
+            return Err(MailinerError::InvalidData(
+                "The message has no recipients".to_string(),
+            ));
+        }
+        let preferences = self.account_preferences(account_id).await?;
+        let data = composition
+            .to_message(&preferences, self.files.as_deref())
+            .await?;
+        let message = self
+            .queue_message(
+                account_id,
+                from,
+                recipients,
+                composition.subject.clone(),
+                data,
+                draft.cloned(),
+            )
+            .await?;
+        self.discard_attachments(composition).await?;
+        Ok(message)
+    }
+
+    /// Puts a message that is ready to be sent into the outbox.
+    pub(super) async fn queue_message(
+        &self,
+        account_id: &AccountId,
+        from: String,
+        recipients: Vec<String>,
+        subject: String,
+        data: Vec<u8>,
+        draft: Option<MessageId>,
+    ) -> Result<OutboxMessage> {
+        let now = Utc::now();
+        let message = OutboxMessage {
+            id: OutboxId::new(uuid::Uuid::new_v4().to_string()),
+            account_id: account_id.clone(),
+            from,
+            recipients,
+            subject,
+            message: data,
+            draft_id: draft,
+            status: OutboxStatus::Queued,
+            attempts: 0,
+            last_error: None,
+            next_attempt_at: now,
+            sent_at: None,
+            created_at: now,
+            updated_at: now,
+        };
+        self.storage.save_outbox_message(&message).await?;
+        Ok(message)
+    }
+
+    /// Returns the messages in the outbox, oldest first, with the ones sent
+    /// in the last day.
+    pub async fn outbox(&self, account_id: &AccountId) -> Result<Vec<OutboxMessage>> {
+        let mut messages = self.storage.list_outbox_messages(account_id).await?;
+        messages.sort_by_key(|message| message.created_at);
+        Ok(messages)
+    }
+
+    /// Queues a failed message again, to be sent right away.
+    pub async fn retry_outbox_message(&self, id: &OutboxId) -> Result<()> {
+        let mut message = self.storage.get_outbox_message(id).await?;
+        message.status = OutboxStatus::Queued;
+        message.attempts = 0;
+        message.next_attempt_at = Utc::now();
+        message.updated_at = Utc::now();
+        self.storage.save_outbox_message(&message).await
+    }
+
+    /// Holds a message back from sending, if it isn't being sent already.
+    pub async fn hold_outbox_message(&self, id: &OutboxId) -> Result<()> {
+        let mut message = self.storage.get_outbox_message(id).await?;
+        if message.status != OutboxStatus::Queued {
+            return Err(MailinerError::InvalidData(format!(
+                "Outbox message {} is not queued",
+                id
+            )));
+        }
+        message.status = OutboxStatus::Draft;
+        message.updated_at = Utc::now();
+        self.storage.save_outbox_message(&message).await
+    }
+
+    /// Queues a held message, to be sent at `at`.
+    pub async fn release_outbox_message(&self, id: &OutboxId, at: DateTime<Utc>) -> Result<()> {
+        let mut message = self.storage.get_outbox_message(id).await?;
+        if message.status != OutboxStatus::Draft {
+            return Err(MailinerError::InvalidData(format!(
+                "Outbox message {} is not held",
+                id
+            )));
+        }
+        message.status = OutboxStatus::Queued;
+        message.next_attempt_at = at;
+        message.updated_at = Utc::now();
+        self.storage.save_outbox_message(&message).await
+    }
+
+    /// Removes a message from the outbox without sending it.
+    pub async fn cancel_outbox_message(&self, id: &OutboxId) -> Result<()> {
+        self.storage.delete_outbox_message(id).await
+    }
+
+    /// Sends the queued messages that are due, returning how many were sent.
+    ///
+    /// Messages the server refuses are marked as failed. A temporary failure
+    /// schedules the message for a retry and ends the round with the error,
+    /// as it usually means the connection is gone.
+    pub async fn send_outbox<T>(
+        &self,
+        sender: &dyn MessageSender<T>,
+        account_id: &AccountId,
+    ) -> Result<usize>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+    {
+        let now = Utc::now();
+        let mut sent = 0;
+        for mut message in self.outbox(account_id).await? {
+            if message.status == OutboxStatus::Sent {
+                if message
+                    .sent_at
+                    .is_some_and(|at| now - at > chrono::Duration::hours(SENT_RETENTION_HOURS))
+                {
+                    self.storage.delete_outbox_message(&message.id).await?;
+                }
+                continue;
+            }
+            if !message.is_due(now) {
+                continue;
+            }
+            message.status = OutboxStatus::Sending;
+            message.updated_at = Utc::now();
+            self.storage.save_outbox_message(&message).await?;
+
+            match sender
+                .send(&message.from, &message.recipients, &message.message)
+                .await
+            {
+                Ok(()) => {
+                    message.status = OutboxStatus::Sent;
+                    message.sent_at = Some(Utc::now());
+                    message.last_error = None;
+                    message.updated_at = Utc::now();
+                    self.storage.save_outbox_message(&message).await?;
+                    let seen = message
+                        .recipients
+                        .iter()
+                        .filter(|recipient| !recipient.eq_ignore_ascii_case(&message.from))
+                        .map(|recipient| {
+                            let addr = EmailAddr {
+                                name: None,
+                                email: Some(recipient.clone()),
+                            };
+                            (addr, Utc::now(), SENT_WEIGHT)
+                        })
+                        .collect();
+                    self.record_contacts(seen).await?;
+                    if let Some(draft) = &message.draft_id {
+                        self.delete_draft(draft).await?;
+                    }
+                    sent += 1;
+                }
+                Err(e @ (MailinerError::Rejected(_) | MailinerError::InvalidData(_))) => {
+                    message.status = OutboxStatus::Failed;
+                    message.attempts += 1;
+                    message.last_error = Some(e.to_string());
+                    message.updated_at = Utc::now();
+                    self.storage.save_outbox_message(&message).await?;
+                }
+                Err(e) => {
+                    message.attempts += 1;
+                    message.status = if message.attempts >= MAX_ATTEMPTS {
+                        OutboxStatus::Failed
+                    } else {
+                        OutboxStatus::Queued
+                    };
+                    message.last_error = Some(e.to_string());
+                    message.next_attempt_at = Utc::now() + retry_delay(message.attempts);
+                    message.updated_at = Utc::now();
+                    self.storage.save_outbox_message(&message).await?;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Sends queued messages every `interval` for as long as `sender` stays
+    /// connected. Returns the error of the connection when it fails, after
+    /// which the caller reconnects and runs it again.
+    pub async fn run_outbox<T>(
+        &self,
+        sender: &dyn MessageSender<T>,
+        account_id: &AccountId,
+        timer: &dyn Timer,
+        interval: Duration,
+    ) -> Result<()>
+    where
+        T: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+    {
+        loop {
+            self.send_outbox(sender, account_id).await?;
+            timer.sleep(interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        assert_eq!(retry_delay(1).num_seconds(), 30);
+        assert_eq!(retry_delay(2).num_seconds(), 60);
+        assert_eq!(retry_delay(4).num_seconds(), 240);
+        assert_eq!(retry_delay(MAX_ATTEMPTS).num_seconds(), 3600);
+        assert_eq!(retry_delay(u32::MAX).num_seconds(), 3600);
+    }
+
+    #[test]
+    fn due() {
+        let now = Utc::now();
+        let mut message = OutboxMessage {
+            id: OutboxId::new("1"),
+            account_id: AccountId::new("imap-jan"),
+            from: "jan@example.com".to_string(),
+            recipients: vec!["ana@example.com".to_string()],
+            subject: "Hello".to_string(),
+            message: Vec::new(),
+            draft_id: None,
+            status: OutboxStatus::Draft,
+            attempts: 0,
+            last_error: None,
+            next_attempt_at: now,
+            sent_at: None,
+            created_at: now,
+            updated_at: now,
+        };
+        assert!(!message.is_due(now));
+        message.status = OutboxStatus::Queued;
+        assert!(message.is_due(now));
+        // Scheduled for later.
+        assert!(!message.is_due(now - chrono::Duration::minutes(1)));
+        message.status = OutboxStatus::Sent;
+        assert!(!message.is_due(now));
+    }
+}
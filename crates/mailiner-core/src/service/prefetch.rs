@@ -0,0 +1,128 @@
+//! Prefetching the bodies of the messages likely to be opened next, so that
+//! opening them doesn't wait on the server.
+
+use std::fmt::Debug;
+
+use chrono::Utc;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::EmailService;
+use crate::error::Result;
+use crate::ids::FolderId;
+use crate::models::MessageStructure;
+
+/// The text parts of a message a reader shows, leaving out attachments.
+fn body_parts(structure: &MessageStructure) -> Vec<&MessageStructure> {
+    if structure.is_attachment {
+        return Vec::new();
+    }
+    if structure.children.is_empty() {
+        let content_type = structure.content_type.to_ascii_lowercase();
+        return match structure.id {
+            Some(_) if content_type == "text/plain" || content_type == "text/html" => {
+                vec![structure]
+            }
+            _ => Vec::new(),
+        };
+    }
+    structure.children.iter().flat_map(body_parts).collect()
+}
+
+impl<S> EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    /// Caches the bodies of the newest `count` unread messages of a folder,
+    /// e.g. in the background when the folder is opened. Stops before
+    /// downloading more than `budget` bytes. Returns the number of bytes
+    /// fetched.
+    pub async fn prefetch_bodies(
+        &self,
+        folder_id: &FolderId,
+        count: usize,
+        budget: u64,
+    ) -> Result<u64> {
+        let now = Utc::now();
+        let mut unread: Vec<_> = self
+            .storage
+            .list_envelopes(folder_id)
+            .await?
+            .into_iter()
+            .filter(|envelope| !envelope.is_read && !envelope.is_snoozed(now))
+            .collect();
+        unread.sort_by_key(|envelope| std::cmp::Reverse(envelope.date));
+
+        let mut fetched = 0;
+        for envelope in unread.iter().take(count) {
+            let cached = self.storage.list_message_parts(&envelope.id).await?;
+            let structure = self.connector.get_message_structure(&envelope.id).await?;
+            for part in body_parts(&structure) {
+                let Some(part_id) = &part.id else {
+                    continue;
+                };
+                if cached.iter().any(|cached| cached.id == *part_id) {
+                    continue;
+                }
+                let size = part.size.unwrap_or(0);
+                if fetched + size > budget {
+                    return Ok(fetched);
+                }
+                self.get_message_part(&envelope.id, part_id).await?;
+                fetched += size;
+            }
+        }
+        Ok(fetched)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::MessagePartId;
+
+    fn part(id: &str, content_type: &str, is_attachment: bool) -> MessageStructure {
+        MessageStructure {
+            id: Some(MessagePartId::new(id)),
+            content_type: content_type.to_string(),
+            filename: None,
+            size: Some(100),
+            is_attachment,
+            content_id: None,
+            children: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn finds_body_parts() {
+        let alternative = MessageStructure {
+            id: None,
+            content_type: "multipart/alternative".to_string(),
+            filename: None,
+            size: None,
+            is_attachment: false,
+            content_id: None,
+            children: vec![
+                part("1.1", "text/plain", false),
+                part("1.2", "text/html", false),
+            ],
+        };
+        let mixed = MessageStructure {
+            id: None,
+            content_type: "multipart/mixed".to_string(),
+            filename: None,
+            size: None,
+            is_attachment: false,
+            content_id: None,
+            children: vec![
+                alternative,
+                part("2", "image/png", false),
+                part("3", "text/plain", true),
+            ],
+        };
+        let ids: Vec<_> = body_parts(&mixed)
+            .into_iter()
+            .filter_map(|part| part.id.clone())
+            .collect();
+        assert_eq!(ids, [MessagePartId::new("1.1"), MessagePartId::new("1.2")]);
+    }
+}
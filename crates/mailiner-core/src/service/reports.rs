@@ -0,0 +1,94 @@
+//! Delivery status notifications, linked to the sent messages they report on.
+
+use std::fmt::Debug;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::EmailService;
+use crate::delivery::{self, DeliveryReport};
+use crate::error::Result;
+use crate::ids::{MessageId, MessagePartId};
+use crate::models::{Envelope, MessageContent, MessageStructure};
+
+/// Parts that carry the headers of the message a notification is about.
+const RETURNED_TYPES: &[&str] = &[
+    "message/rfc822",
+    "text/rfc822-headers",
+    "message/global-headers",
+];
+
+impl<S> EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    /// Reads the delivery status notification `envelope` and stores it
+    /// with the message it reports on. Returns `None` if the message isn't
+    /// a notification.
+    pub async fn read_delivery_report(
+        &self,
+        envelope: &Envelope,
+    ) -> Result<Option<DeliveryReport>> {
+        let structure = self.get_message_structure(&envelope.id).await?;
+        if !structure
+            .content_type
+            .eq_ignore_ascii_case("multipart/report")
+        {
+            return Ok(None);
+        }
+        let Some(status_id) = find_part(
+            &structure,
+            &["message/delivery-status", "message/global-delivery-status"],
+        )
+        .and_then(|part| part.id.clone()) else {
+            return Ok(None);
+        };
+        let status = self.part_text(&envelope.id, &status_id).await?;
+
+        let mut original_message_id = None;
+        if let Some(id) = find_part(&structure, RETURNED_TYPES).and_then(|part| part.id.clone()) {
+            let headers = self.part_text(&envelope.id, &id).await?;
+            original_message_id = delivery::header_value(&headers, "Message-ID").map(|id| {
+                id.trim_matches(|c| c == '<' || c == '>' || c == ' ')
+                    .to_string()
+            });
+        }
+
+        let report = DeliveryReport {
+            id: envelope.id.clone(),
+            account_id: envelope.account_id.clone(),
+            original_message_id,
+            recipients: delivery::parse_delivery_status(&status)?,
+            received_at: envelope.date,
+        };
+        self.storage.save_delivery_report(&report).await?;
+        Ok(Some(report))
+    }
+
+    /// The notifications received about a sent message, e.g. to show that
+    /// it couldn't be delivered to some recipients.
+    pub async fn delivery_reports(&self, sent: &Envelope) -> Result<Vec<DeliveryReport>> {
+        match &sent.message_id {
+            Some(message_id) => self.storage.list_delivery_reports(message_id).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn part_text(&self, message_id: &MessageId, part_id: &MessagePartId) -> Result<String> {
+        Ok(
+            match self.get_message_part(message_id, part_id).await?.content {
+                MessageContent::Text(text) | MessageContent::Html(text) => text,
+                MessageContent::Binary(data) => String::from_utf8_lossy(&data).into_owned(),
+            },
+        )
+    }
+}
+
+/// Finds a direct part of the report, whether or not it is marked as an
+/// attachment.
+fn find_part<'a>(structure: &'a MessageStructure, types: &[&str]) -> Option<&'a MessageStructure> {
+    structure.children.iter().find(|part| {
+        types
+            .iter()
+            .any(|content_type| part.content_type.eq_ignore_ascii_case(content_type))
+    })
+}
@@ -0,0 +1,76 @@
+//! Applying the account's retention policies, and the log of what they did.
+
+use std::fmt::Debug;
+
+use chrono::Utc;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::EmailService;
+use crate::activity::ActivityEntry;
+use crate::error::{MailinerError, Result};
+use crate::ids::AccountId;
+use crate::retention::RetentionAction;
+
+impl<S> EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    /// Applies the account's
+    /// [`AccountPreferences::retention`](crate::AccountPreferences::retention)
+    /// policies to the cached messages, logging each action to the activity
+    /// log. With `dry_run` nothing is changed or logged, the returned
+    /// entries only tell what would be done.
+    pub async fn apply_retention(
+        &self,
+        account_id: &AccountId,
+        dry_run: bool,
+    ) -> Result<Vec<ActivityEntry>> {
+        let preferences = self.account_preferences(account_id).await?;
+        let now = Utc::now();
+        let mut entries = Vec::new();
+        for policy in &preferences.retention {
+            policy.validate()?;
+            let envelopes = match self.storage.list_envelopes(&policy.folder_id).await {
+                Ok(envelopes) => envelopes,
+                Err(MailinerError::NotFound(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            for envelope in envelopes
+                .iter()
+                .filter(|envelope| policy.applies_to(envelope, now))
+            {
+                let action = match &policy.action {
+                    RetentionAction::MoveTo(folder_id) => {
+                        if !dry_run {
+                            self.move_message(&envelope.id, folder_id).await?;
+                        }
+                        format!("Moved to {}", folder_id)
+                    }
+                    RetentionAction::Purge => {
+                        if !dry_run {
+                            self.purge_message(&envelope.id).await?;
+                        }
+                        "Purged".to_string()
+                    }
+                };
+                let entry = ActivityEntry {
+                    account_id: account_id.clone(),
+                    at: now,
+                    folder_id: envelope.folder_id.clone(),
+                    message_id: envelope.id.clone(),
+                    action,
+                };
+                if !dry_run {
+                    self.storage.add_activity(&entry).await?;
+                }
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// What was done to the account's messages automatically, oldest first.
+    pub async fn activity(&self, account_id: &AccountId) -> Result<Vec<ActivityEntry>> {
+        self.storage.list_activity(account_id).await
+    }
+}
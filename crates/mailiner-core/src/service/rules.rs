@@ -0,0 +1,138 @@
+//! Managing filter rules and applying them to new mail.
+
+use std::fmt::Debug;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::EmailService;
+use crate::error::{MailinerError, Result};
+use crate::ids::{AccountId, RuleId};
+use crate::models::Envelope;
+use crate::rules::{Rule, RuleAction};
+
+/// The header of a raw message, up to the first empty line.
+fn header_block(message: &[u8]) -> String {
+    let end = message
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .unwrap_or(message.len());
+    String::from_utf8_lossy(&message[..end]).into_owned()
+}
+
+impl<S> EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    /// Returns the rules of an account, in the order they are applied.
+    pub async fn rules(&self, account_id: &AccountId) -> Result<Vec<Rule>> {
+        let mut rules = self.storage.list_rules(account_id).await?;
+        rules.sort_by_key(|rule| rule.position);
+        Ok(rules)
+    }
+
+    pub async fn save_rule(&self, rule: &Rule) -> Result<()> {
+        rule.validate()?;
+        self.storage.save_rule(rule).await
+    }
+
+    pub async fn delete_rule(&self, id: &RuleId) -> Result<()> {
+        self.storage.delete_rule(id).await
+    }
+
+    /// Applies the enabled rules of the account to newly synced
+    /// `envelopes`.
+    pub(super) async fn apply_rules(&self, envelopes: &[Envelope]) -> Result<()> {
+        let Some(first) = envelopes.first() else {
+            return Ok(());
+        };
+        let rules: Vec<Rule> = self
+            .rules(&first.account_id)
+            .await?
+            .into_iter()
+            .filter(|rule| rule.enabled)
+            .collect();
+        if rules.is_empty() {
+            return Ok(());
+        }
+        for envelope in envelopes {
+            // Fetched once a rule needs more than the envelope.
+            let mut raw: Option<Vec<u8>> = None;
+            for rule in &rules {
+                if raw.is_none() && rule.needs_headers() {
+                    raw = Some(self.connector.fetch_raw_message(&envelope.id).await?);
+                }
+                let headers = raw.as_deref().map(header_block);
+                if !rule.matches(envelope, headers.as_deref()) {
+                    continue;
+                }
+                let forwards = rule
+                    .actions
+                    .iter()
+                    .any(|action| matches!(action, RuleAction::Forward(_)));
+                if raw.is_none() && forwards {
+                    raw = Some(self.connector.fetch_raw_message(&envelope.id).await?);
+                }
+                let gone = self.run_actions(rule, envelope, raw.as_deref()).await?;
+                if gone || rule.stop {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the actions of a matching rule on a message. Returns whether the
+    /// message left the folder, after which no other rule applies to it.
+    async fn run_actions(
+        &self,
+        rule: &Rule,
+        envelope: &Envelope,
+        raw: Option<&[u8]>,
+    ) -> Result<bool> {
+        // Moving or deleting changes the id of the message, so those come
+        // last.
+        let (last, first): (Vec<&RuleAction>, Vec<&RuleAction>) = rule
+            .actions
+            .iter()
+            .partition(|action| matches!(action, RuleAction::MoveTo(_) | RuleAction::Delete));
+        for action in first {
+            match action {
+                RuleAction::AddLabel(label) => {
+                    self.update_envelope_labels(&envelope.id, &[(label.as_str(), true)])
+                        .await?
+                }
+                RuleAction::MarkRead => {
+                    self.update_envelope_flags(&envelope.id, &[("is_read", true)])
+                        .await?
+                }
+                RuleAction::Forward(address) => {
+                    let account = self.storage.get_account(&envelope.account_id).await?;
+                    let raw = raw.ok_or_else(|| {
+                        MailinerError::InvalidData(format!("Message {} not fetched", envelope.id))
+                    })?;
+                    self.queue_message(
+                        &envelope.account_id,
+                        account.email,
+                        vec![address.clone()],
+                        envelope.subject.clone().unwrap_or_default(),
+                        raw.to_vec(),
+                        None,
+                    )
+                    .await?;
+                }
+                RuleAction::MoveTo(_) | RuleAction::Delete => unreachable!(),
+            }
+        }
+        match last.first() {
+            Some(RuleAction::MoveTo(folder_id)) if *folder_id != envelope.folder_id => {
+                self.move_message(&envelope.id, folder_id).await?;
+                Ok(true)
+            }
+            Some(RuleAction::Delete) => {
+                self.delete_message(&envelope.id).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
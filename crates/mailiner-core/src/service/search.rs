@@ -0,0 +1,118 @@
+//! Searching all folders of an account: the local cache answers right away,
+//! the server fills in what the cache can't know.
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::EmailService;
+use crate::error::{MailinerError, Result};
+use crate::ids::{AccountId, FolderId, MessageId};
+use crate::models::SearchQuery;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchSource {
+    Local,
+    Server,
+}
+
+/// Matches found in a folder, not found before by the same search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchHits {
+    pub folder_id: FolderId,
+    pub ids: Vec<MessageId>,
+    pub source: SearchSource,
+}
+
+/// Whether the server has to be asked too. The cache knows all envelopes of
+/// a synced folder, but only the bodies of the messages that were read, and
+/// nothing of the connector's own query language.
+fn needs_server(query: &SearchQuery, synced: bool) -> bool {
+    !synced || query.body.is_some() || query.raw.is_some()
+}
+
+/// Drops the `ids` already `seen`, and remembers the others.
+fn unseen(seen: &mut HashSet<MessageId>, ids: Vec<MessageId>) -> Vec<MessageId> {
+    ids.into_iter()
+        .filter(|id| seen.insert(id.clone()))
+        .collect()
+}
+
+impl<S> EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    /// Searches all folders of an account, calling `on_hits` as matches come
+    /// in: first from the local cache of every folder, then from the server
+    /// for the folders the cache can't answer for. Returns all matches.
+    pub async fn search_account(
+        &self,
+        account_id: &AccountId,
+        query: &SearchQuery,
+        mut on_hits: impl FnMut(SearchHits) + Send,
+    ) -> Result<Vec<MessageId>> {
+        let folders = self.storage.list_folders(account_id).await?;
+        let mut seen = HashSet::new();
+        let mut all = Vec::new();
+        let mut remote = Vec::new();
+        for folder in &folders {
+            let ids = unseen(&mut seen, self.storage.search(&folder.id, query).await?);
+            if !ids.is_empty() {
+                all.extend(ids.iter().cloned());
+                on_hits(SearchHits {
+                    folder_id: folder.id.clone(),
+                    ids,
+                    source: SearchSource::Local,
+                });
+            }
+            let synced = match self.storage.get_folder_metadata(&folder.id).await {
+                Ok(_) => true,
+                Err(MailinerError::NotFound(_)) => false,
+                Err(e) => return Err(e),
+            };
+            if needs_server(query, synced) {
+                remote.push(&folder.id);
+            }
+        }
+
+        for folder_id in remote {
+            let ids = unseen(&mut seen, self.connector.search(folder_id, query).await?);
+            if !ids.is_empty() {
+                all.extend(ids.iter().cloned());
+                on_hits(SearchHits {
+                    folder_id: folder_id.clone(),
+                    ids,
+                    source: SearchSource::Server,
+                });
+            }
+        }
+        Ok(all)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hybrid() {
+        let subject = SearchQuery {
+            subject: Some("invoice".to_string()),
+            ..Default::default()
+        };
+        assert!(!needs_server(&subject, true));
+        assert!(needs_server(&subject, false));
+        let body = SearchQuery {
+            body: Some("invoice".to_string()),
+            ..Default::default()
+        };
+        assert!(needs_server(&body, true));
+
+        let mut seen = HashSet::new();
+        let local = vec![MessageId::new("INBOX;UID=1")];
+        assert_eq!(unseen(&mut seen, local.clone()), local);
+        let server = vec![MessageId::new("INBOX;UID=1"), MessageId::new("INBOX;UID=2")];
+        assert_eq!(unseen(&mut seen, server), [MessageId::new("INBOX;UID=2")]);
+    }
+}
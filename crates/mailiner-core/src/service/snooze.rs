@@ -0,0 +1,238 @@
+//! Snoozing, which hides a message from its folder until a given time. The
+//! time is kept on the cached envelope, so snoozes survive restarts, but not
+//! on the server.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::pin::pin;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures_util::future::select;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::broadcast;
+
+use super::EmailService;
+use crate::error::Result;
+use crate::ids::{AccountId, FolderId, MessageId};
+use crate::models::Envelope;
+use crate::platform::Timer;
+
+/// Copies the snoozes of the `cached` envelopes to the same envelopes
+/// fetched from the server again, which doesn't know about them.
+pub(super) fn keep_snoozes(cached: &[Envelope], envelopes: &mut [Envelope]) {
+    let snoozes: HashMap<&MessageId, DateTime<Utc>> = cached
+        .iter()
+        .filter_map(|envelope| Some((&envelope.id, envelope.snoozed_until?)))
+        .collect();
+    for envelope in envelopes {
+        if let Some(until) = snoozes.get(&envelope.id) {
+            envelope.snoozed_until = Some(*until);
+        }
+    }
+}
+
+/// A message leaving its folder for a snooze, or coming back, for the UI to
+/// animate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SnoozeEvent {
+    Snoozed {
+        message_id: MessageId,
+        folder_id: FolderId,
+        until: DateTime<Utc>,
+    },
+    Woken {
+        message_id: MessageId,
+        folder_id: FolderId,
+    },
+}
+
+/// The `envelopes` whose snooze has run out by `now`, and when the next of
+/// the others runs out.
+fn due(envelopes: Vec<Envelope>, now: DateTime<Utc>) -> (Vec<Envelope>, Option<DateTime<Utc>>) {
+    let mut next: Option<DateTime<Utc>> = None;
+    let mut due = Vec::new();
+    for envelope in envelopes {
+        match envelope.snoozed_until {
+            Some(until) if until <= now => due.push(envelope),
+            Some(until) => next = Some(next.map_or(until, |next| next.min(until))),
+            None => {}
+        }
+    }
+    (due, next)
+}
+
+impl<S> EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    /// Hides a cached message from its folder until `until`, when
+    /// [`run_snoozes`](Self::run_snoozes) shows it again.
+    pub async fn snooze(&self, message_id: &MessageId, until: DateTime<Utc>) -> Result<()> {
+        let envelope = self.set_snooze(message_id, Some(until)).await?;
+        self.snooze_changed.notify_one();
+        let _ = self.snoozes.send(SnoozeEvent::Snoozed {
+            message_id: envelope.id,
+            folder_id: envelope.folder_id,
+            until,
+        });
+        Ok(())
+    }
+
+    /// Shows a snoozed message again before its time.
+    pub async fn unsnooze(&self, message_id: &MessageId) -> Result<()> {
+        let envelope = self.set_snooze(message_id, None).await?;
+        self.snooze_changed.notify_one();
+        let _ = self.snoozes.send(SnoozeEvent::Woken {
+            message_id: envelope.id,
+            folder_id: envelope.folder_id,
+        });
+        Ok(())
+    }
+
+    pub fn subscribe_snoozes(&self) -> broadcast::Receiver<SnoozeEvent> {
+        self.snoozes.subscribe()
+    }
+
+    async fn set_snooze(
+        &self,
+        message_id: &MessageId,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Envelope> {
+        let mut envelope = self.storage.get_envelope(message_id).await?;
+        envelope.snoozed_until = until;
+        envelope.updated_at = Utc::now();
+        self.storage.save_envelope(&envelope).await?;
+        Ok(envelope)
+    }
+
+    /// Shows the messages of an account whose snooze has run out again, and
+    /// returns them. Returns also when the next snooze runs out.
+    pub async fn wake_snoozed(
+        &self,
+        account_id: &AccountId,
+    ) -> Result<(Vec<Envelope>, Option<DateTime<Utc>>)> {
+        let now = Utc::now();
+        let mut snoozed = Vec::new();
+        for folder in self.storage.list_folders(account_id).await? {
+            snoozed.extend(
+                self.storage
+                    .list_envelopes(&folder.id)
+                    .await?
+                    .into_iter()
+                    .filter(|envelope| envelope.snoozed_until.is_some()),
+            );
+        }
+        let (mut woken, next) = due(snoozed, now);
+        for envelope in &mut woken {
+            envelope.snoozed_until = None;
+            envelope.updated_at = now;
+        }
+        self.storage.save_envelopes(&woken).await?;
+        for envelope in &woken {
+            let _ = self.snoozes.send(SnoozeEvent::Woken {
+                message_id: envelope.id.clone(),
+                folder_id: envelope.folder_id.clone(),
+            });
+        }
+        Ok((woken, next))
+    }
+
+    /// Wakes snoozed messages when their time comes, announcing them to the
+    /// [`subscribe_snoozes`](Self::subscribe_snoozes) subscribers. Snoozes
+    /// that ran out while the app was closed are woken right away. Sleeps
+    /// until the next snooze runs out or one is added, but at most
+    /// `interval`, so a changed clock is noticed. Returns only if storage
+    /// fails.
+    pub async fn run_snoozes(
+        &self,
+        account_id: &AccountId,
+        timer: &dyn Timer,
+        interval: Duration,
+    ) -> Result<()> {
+        loop {
+            let (_, next) = self.wake_snoozed(account_id).await?;
+            let wait = next
+                .and_then(|next| (next - Utc::now()).to_std().ok())
+                .map_or(interval, |wait| wait.min(interval));
+            let changed = pin!(self.snooze_changed.notified());
+            select(timer.sleep(wait), changed).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    fn envelope(id: &str, snoozed_until: Option<DateTime<Utc>>) -> Envelope {
+        Envelope {
+            id: MessageId::new(id),
+            account_id: AccountId::new("imap-jan"),
+            folder_id: FolderId::new("INBOX"),
+            subject: None,
+            from: None,
+            to: None,
+            cc: None,
+            bcc: None,
+            reply_to: None,
+            date: Utc::now(),
+            is_read: false,
+            is_starred: false,
+            is_flagged: false,
+            is_draft: false,
+            is_deleted: false,
+            has_attachments: false,
+            size: None,
+            keywords: BTreeSet::new(),
+            labels: BTreeSet::new(),
+            snoozed_until,
+            thread_id: None,
+            remote_id: None,
+            message_id: None,
+            in_reply_to: Vec::new(),
+            references: Vec::new(),
+            unsubscribe: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn sync_keeps_snoozes() {
+        let until = Utc::now() + chrono::Duration::days(1);
+        let cached = [
+            envelope("INBOX;UID=1", Some(until)),
+            envelope("INBOX;UID=2", None),
+        ];
+        let mut fetched = [
+            envelope("INBOX;UID=1", None),
+            envelope("INBOX;UID=2", None),
+            envelope("INBOX;UID=3", None),
+        ];
+        keep_snoozes(&cached, &mut fetched);
+        assert_eq!(fetched[0].snoozed_until, Some(until));
+        assert!(fetched[0].is_snoozed(Utc::now()));
+        assert_eq!(fetched[1].snoozed_until, None);
+        assert_eq!(fetched[2].snoozed_until, None);
+    }
+
+    #[test]
+    fn finds_due_snoozes() {
+        let now = Utc::now();
+        let later = now + chrono::Duration::hours(1);
+        let envelopes = vec![
+            envelope("INBOX;UID=1", Some(now - chrono::Duration::minutes(1))),
+            envelope("INBOX;UID=2", Some(now + chrono::Duration::hours(2))),
+            envelope("INBOX;UID=3", Some(later)),
+            envelope("INBOX;UID=4", None),
+        ];
+        let (due, next) = due(envelopes, now);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].id, MessageId::new("INBOX;UID=1"));
+        assert_eq!(next, Some(later));
+    }
+}
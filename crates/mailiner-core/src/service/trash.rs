@@ -0,0 +1,166 @@
+//! Deleting and restoring messages, as the account's [`DeletePolicy`] says,
+//! and purging them once they have been deleted long enough.
+
+use std::fmt::Debug;
+
+use chrono::{DateTime, Duration, Utc};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::EmailService;
+use crate::error::{MailinerError, Result};
+use crate::ids::{AccountId, FolderId, MessageId};
+use crate::models::{DeletePolicy, Envelope};
+
+/// The deleted messages among `envelopes` that are older than `days`.
+/// Servers don't keep when a message was deleted, so this goes by the date
+/// of the message.
+fn expired(envelopes: &[Envelope], days: u32, now: DateTime<Utc>) -> Vec<MessageId> {
+    let cutoff = now - Duration::days(days.into());
+    envelopes
+        .iter()
+        .filter(|envelope| envelope.date < cutoff)
+        .map(|envelope| envelope.id.clone())
+        .collect()
+}
+
+impl<S> EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    /// Deletes a cached message: moves it to Trash or marks it `\Deleted`,
+    /// depending on the account's [`DeletePolicy`]. Messages already in
+    /// Trash are removed for good.
+    pub async fn delete_message(&self, message_id: &MessageId) -> Result<()> {
+        let envelope = self.storage.get_envelope(message_id).await?;
+        let preferences = self.account_preferences(&envelope.account_id).await?;
+        match preferences.delete_policy {
+            DeletePolicy::MoveToTrash(trash) if envelope.folder_id != trash => {
+                self.move_message(message_id, &trash).await
+            }
+            DeletePolicy::MoveToTrash(_) => self.purge_message(message_id).await,
+            DeletePolicy::MarkDeleted => {
+                self.update_envelope_flags(message_id, &[("is_deleted", true)])
+                    .await
+            }
+        }
+    }
+
+    /// Undoes [`delete_message`](Self::delete_message): clears `\Deleted`,
+    /// or moves the message out of Trash to `folder_id`, usually the folder
+    /// it was deleted from.
+    pub async fn restore_message(
+        &self,
+        message_id: &MessageId,
+        folder_id: &FolderId,
+    ) -> Result<()> {
+        let envelope = self.storage.get_envelope(message_id).await?;
+        if envelope.is_deleted {
+            self.update_envelope_flags(message_id, &[("is_deleted", false)])
+                .await
+        } else {
+            self.move_message(message_id, folder_id).await
+        }
+    }
+
+    /// Removes the cached messages that have been deleted for longer than
+    /// [`AccountPreferences::purge_after_days`](crate::AccountPreferences::purge_after_days):
+    /// the ones in Trash, or the ones marked `\Deleted`. Returns the number
+    /// of removed messages.
+    pub async fn purge_deleted(&self, account_id: &AccountId) -> Result<usize> {
+        let preferences = self.account_preferences(account_id).await?;
+        let Some(days) = preferences.purge_after_days else {
+            return Ok(0);
+        };
+        let deleted: Vec<Envelope> = match preferences.delete_policy {
+            DeletePolicy::MoveToTrash(trash) => match self.storage.list_envelopes(&trash).await {
+                Ok(envelopes) => envelopes,
+                Err(MailinerError::NotFound(_)) => Vec::new(),
+                Err(e) => return Err(e),
+            },
+            DeletePolicy::MarkDeleted => {
+                let mut deleted = Vec::new();
+                for folder in self.storage.list_folders(account_id).await? {
+                    deleted.extend(
+                        self.storage
+                            .list_envelopes(&folder.id)
+                            .await?
+                            .into_iter()
+                            .filter(|envelope| envelope.is_deleted),
+                    );
+                }
+                deleted
+            }
+        };
+
+        let expired = expired(&deleted, days, Utc::now());
+        for id in &expired {
+            self.purge_message(id).await?;
+        }
+        Ok(expired.len())
+    }
+
+    pub(super) async fn purge_message(&self, message_id: &MessageId) -> Result<()> {
+        self.connector.delete_message(message_id).await?;
+        match self.storage.delete_envelope(message_id).await {
+            Ok(()) | Err(MailinerError::NotFound(_)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn envelope(id: &str, date: DateTime<Utc>) -> Envelope {
+        Envelope {
+            id: MessageId::new(id),
+            account_id: AccountId::new("imap-jan"),
+            folder_id: FolderId::new("Trash"),
+            subject: None,
+            from: None,
+            to: None,
+            cc: None,
+            bcc: None,
+            reply_to: None,
+            date,
+            is_read: true,
+            is_starred: false,
+            is_flagged: false,
+            is_draft: false,
+            is_deleted: false,
+            has_attachments: false,
+            size: None,
+            keywords: BTreeSet::new(),
+            labels: BTreeSet::new(),
+            snoozed_until: None,
+            thread_id: None,
+            remote_id: None,
+            message_id: None,
+            in_reply_to: Vec::new(),
+            references: Vec::new(),
+            unsubscribe: None,
+            created_at: date,
+            updated_at: date,
+        }
+    }
+
+    #[test]
+    fn purges_by_age() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 30, 12, 0, 0).unwrap();
+        let envelopes = [
+            envelope("Trash;UID=1", now - Duration::days(45)),
+            envelope("Trash;UID=2", now - Duration::days(29)),
+            envelope("Trash;UID=3", now - Duration::days(31)),
+        ];
+        assert_eq!(
+            expired(&envelopes, 30, now),
+            [MessageId::new("Trash;UID=1"), MessageId::new("Trash;UID=3")]
+        );
+        assert!(expired(&envelopes, 60, now).is_empty());
+    }
+}
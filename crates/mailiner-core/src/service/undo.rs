@@ -0,0 +1,385 @@
+//! Bulk actions on messages that can be undone and redone, both in the cache
+//! and on the server, e.g. from Ctrl+Z or an "Undo" toast.
+
+use std::fmt::Debug;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::EmailService;
+use crate::error::{MailinerError, Result};
+use crate::ids::{FolderId, MessageId};
+use crate::models::{DeletePolicy, Envelope};
+
+/// How many actions can be undone.
+pub(super) const UNDO_DEPTH: usize = 50;
+
+/// An action the user takes on a selection of messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MailAction {
+    /// Moves the messages to a folder, e.g. to archive them.
+    Move {
+        message_ids: Vec<MessageId>,
+        folder_id: FolderId,
+    },
+    /// Deletes the messages as the account's [`DeletePolicy`] says.
+    /// Messages already in Trash are removed for good, which can't be
+    /// undone.
+    Delete(Vec<MessageId>),
+    /// Sets a flag, e.g. `is_read` to mark the messages read.
+    SetFlag {
+        message_ids: Vec<MessageId>,
+        flag: String,
+        value: bool,
+    },
+    SetLabel {
+        message_ids: Vec<MessageId>,
+        label: String,
+        value: bool,
+    },
+}
+
+/// A change to a single message. Applying a change yields the change that
+/// reverts it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Change {
+    Move {
+        message_id: MessageId,
+        from: FolderId,
+        to: FolderId,
+    },
+    Flag {
+        message_id: MessageId,
+        flag: String,
+        value: bool,
+    },
+    Label {
+        message_id: MessageId,
+        label: String,
+        value: bool,
+    },
+}
+
+fn flag(envelope: &Envelope, flag: &str) -> Option<bool> {
+    match flag {
+        "is_read" => Some(envelope.is_read),
+        "is_starred" => Some(envelope.is_starred),
+        "is_flagged" => Some(envelope.is_flagged),
+        "is_draft" => Some(envelope.is_draft),
+        "is_deleted" => Some(envelope.is_deleted),
+        _ => None,
+    }
+}
+
+/// The changes `action` makes to the `envelopes` it applies to, leaving out
+/// the ones that change nothing, and the messages to purge.
+fn plan(
+    action: &MailAction,
+    envelopes: &[Envelope],
+    policy: &DeletePolicy,
+) -> Result<(Vec<Change>, Vec<MessageId>)> {
+    let mut changes = Vec::new();
+    let mut purge = Vec::new();
+    for envelope in envelopes {
+        let message_id = envelope.id.clone();
+        match action {
+            MailAction::Move { folder_id, .. } => {
+                if envelope.folder_id != *folder_id {
+                    changes.push(Change::Move {
+                        message_id,
+                        from: envelope.folder_id.clone(),
+                        to: folder_id.clone(),
+                    });
+                }
+            }
+            MailAction::Delete(_) => match policy {
+                DeletePolicy::MoveToTrash(trash) if envelope.folder_id != *trash => {
+                    changes.push(Change::Move {
+                        message_id,
+                        from: envelope.folder_id.clone(),
+                        to: trash.clone(),
+                    });
+                }
+                DeletePolicy::MoveToTrash(_) => purge.push(message_id),
+                DeletePolicy::MarkDeleted => {
+                    if !envelope.is_deleted {
+                        changes.push(Change::Flag {
+                            message_id,
+                            flag: "is_deleted".to_string(),
+                            value: true,
+                        });
+                    }
+                }
+            },
+            MailAction::SetFlag {
+                flag: name, value, ..
+            } => {
+                let current = flag(envelope, name)
+                    .ok_or_else(|| MailinerError::InvalidData(format!("Unknown flag {}", name)))?;
+                if current != *value {
+                    changes.push(Change::Flag {
+                        message_id,
+                        flag: name.clone(),
+                        value: *value,
+                    });
+                }
+            }
+            MailAction::SetLabel { label, value, .. } => {
+                if envelope.labels.contains(label) != *value {
+                    changes.push(Change::Label {
+                        message_id,
+                        label: label.clone(),
+                        value: *value,
+                    });
+                }
+            }
+        }
+    }
+    Ok((changes, purge))
+}
+
+impl MailAction {
+    fn message_ids(&self) -> &[MessageId] {
+        match self {
+            MailAction::Move { message_ids, .. }
+            | MailAction::SetFlag { message_ids, .. }
+            | MailAction::SetLabel { message_ids, .. } => message_ids,
+            MailAction::Delete(message_ids) => message_ids,
+        }
+    }
+}
+
+impl<S> EmailService<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync,
+{
+    /// Applies an action to cached messages, on the server first, and
+    /// remembers how to revert it for [`undo`](Self::undo). Taking an action
+    /// clears what could be redone.
+    pub async fn perform(&self, action: MailAction) -> Result<()> {
+        let mut envelopes = Vec::new();
+        for message_id in action.message_ids() {
+            envelopes.push(self.storage.get_envelope(message_id).await?);
+        }
+        let (changes, purge) = match (&action, envelopes.first()) {
+            (MailAction::Delete(_), Some(first)) => {
+                let preferences = self.account_preferences(&first.account_id).await?;
+                plan(&action, &envelopes, &preferences.delete_policy)?
+            }
+            _ => plan(&action, &envelopes, &DeletePolicy::MarkDeleted)?,
+        };
+
+        self.redo.lock().unwrap().clear();
+        let result = self.apply_changes(changes).await;
+        if !result.reverts.is_empty() {
+            push(&mut self.undo.lock().unwrap(), result.reverts);
+        }
+        result.error.map_or(Ok(()), Err)?;
+        for message_id in &purge {
+            self.purge_message(message_id).await?;
+        }
+        Ok(())
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.lock().unwrap().is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.lock().unwrap().is_empty()
+    }
+
+    /// Reverts the last action. Returns whether there was one.
+    pub async fn undo(&self) -> Result<bool> {
+        let Some(changes) = self.undo.lock().unwrap().pop() else {
+            return Ok(false);
+        };
+        let result = self.apply_changes(changes).await;
+        if !result.reverts.is_empty() {
+            push(&mut self.redo.lock().unwrap(), result.reverts);
+        }
+        result.error.map_or(Ok(true), Err)
+    }
+
+    /// Takes the last undone action again. Returns whether there was one.
+    pub async fn redo(&self) -> Result<bool> {
+        let Some(changes) = self.redo.lock().unwrap().pop() else {
+            return Ok(false);
+        };
+        let result = self.apply_changes(changes).await;
+        if !result.reverts.is_empty() {
+            push(&mut self.undo.lock().unwrap(), result.reverts);
+        }
+        result.error.map_or(Ok(true), Err)
+    }
+
+    /// Applies the changes until one fails, collecting what reverts the ones
+    /// applied so far.
+    async fn apply_changes(&self, changes: Vec<Change>) -> Applied {
+        let mut reverts = Vec::new();
+        for change in changes {
+            match self.apply_change(change).await {
+                Ok(Some(revert)) => reverts.push(revert),
+                Ok(None) => {}
+                Err(e) => {
+                    return Applied {
+                        reverts,
+                        error: Some(e),
+                    }
+                }
+            }
+        }
+        Applied {
+            reverts,
+            error: None,
+        }
+    }
+
+    /// Applies a change and returns the one that reverts it. A moved message
+    /// can only be moved back if the server tells its new id.
+    async fn apply_change(&self, change: Change) -> Result<Option<Change>> {
+        match change {
+            Change::Move {
+                message_id,
+                from,
+                to,
+            } => Ok(self
+                .move_message_tracked(&message_id, &to)
+                .await?
+                .map(|message_id| Change::Move {
+                    message_id,
+                    from: to,
+                    to: from,
+                })),
+            Change::Flag {
+                message_id,
+                flag,
+                value,
+            } => {
+                self.update_envelope_flags(&message_id, &[(flag.as_str(), value)])
+                    .await?;
+                Ok(Some(Change::Flag {
+                    message_id,
+                    flag,
+                    value: !value,
+                }))
+            }
+            Change::Label {
+                message_id,
+                label,
+                value,
+            } => {
+                self.update_envelope_labels(&message_id, &[(label.as_str(), value)])
+                    .await?;
+                Ok(Some(Change::Label {
+                    message_id,
+                    label,
+                    value: !value,
+                }))
+            }
+        }
+    }
+}
+
+struct Applied {
+    reverts: Vec<Change>,
+    error: Option<MailinerError>,
+}
+
+fn push(stack: &mut Vec<Vec<Change>>, mut reverts: Vec<Change>) {
+    // Reverting goes backwards, in case changes build on each other.
+    reverts.reverse();
+    if stack.len() == UNDO_DEPTH {
+        stack.remove(0);
+    }
+    stack.push(reverts);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::ids::AccountId;
+
+    fn envelope(uid: u32, folder: &str, is_read: bool) -> Envelope {
+        Envelope {
+            id: MessageId::new(format!("{};UID={}", folder, uid)),
+            account_id: AccountId::new("imap-jan"),
+            folder_id: FolderId::new(folder),
+            subject: None,
+            from: None,
+            to: None,
+            cc: None,
+            bcc: None,
+            reply_to: None,
+            date: Utc::now(),
+            is_read,
+            is_starred: false,
+            is_flagged: false,
+            is_draft: false,
+            is_deleted: false,
+            has_attachments: false,
+            size: None,
+            keywords: BTreeSet::new(),
+            labels: BTreeSet::new(),
+            snoozed_until: None,
+            thread_id: None,
+            remote_id: None,
+            message_id: None,
+            in_reply_to: Vec::new(),
+            references: Vec::new(),
+            unsubscribe: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn plans_changes() {
+        let envelopes = [envelope(1, "INBOX", false), envelope(2, "Trash", true)];
+        let ids = vec![envelopes[0].id.clone(), envelopes[1].id.clone()];
+        let trash = DeletePolicy::MoveToTrash(FolderId::new("Trash"));
+
+        let read = MailAction::SetFlag {
+            message_ids: ids.clone(),
+            flag: "is_read".to_string(),
+            value: true,
+        };
+        let (changes, purge) = plan(&read, &envelopes, &trash).unwrap();
+        assert_eq!(
+            changes,
+            [Change::Flag {
+                message_id: ids[0].clone(),
+                flag: "is_read".to_string(),
+                value: true,
+            }]
+        );
+        assert!(purge.is_empty());
+
+        let (changes, purge) = plan(&MailAction::Delete(ids.clone()), &envelopes, &trash).unwrap();
+        assert_eq!(
+            changes,
+            [Change::Move {
+                message_id: ids[0].clone(),
+                from: FolderId::new("INBOX"),
+                to: FolderId::new("Trash"),
+            }]
+        );
+        assert_eq!(purge, [ids[1].clone()]);
+
+        let unknown = MailAction::SetFlag {
+            message_ids: ids,
+            flag: "is_pinned".to_string(),
+            value: true,
+        };
+        assert!(plan(&unknown, &envelopes, &trash).is_err());
+
+        let mut stack = Vec::new();
+        for _ in 0..=UNDO_DEPTH {
+            push(&mut stack, changes.clone());
+        }
+        assert_eq!(stack.len(), UNDO_DEPTH);
+    }
+}
@@ -1,19 +1,65 @@
 use async_trait::async_trait;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
 
+use crate::contacts::{self, Contact};
+use crate::rules::Rule;
+use crate::activity::ActivityEntry;
 use crate::error::{MailinerError, Result};
-use crate::ids::{AccountId, FolderId, MessageId, MessagePartId};
-use crate::models::{Account, AccountMetadata, Envelope, Folder, FolderMetadata, MessagePart};
+use crate::delivery::DeliveryReport;
+use crate::ids::{AccountId, ContactId, FolderId, MessageId, MessagePartId, OutboxId, RuleId};
+use crate::models::{Account, AccountConfig, AccountMetadata, AccountPreferences, Envelope, EnvelopeQuery, Folder, FolderMetadata, Label, MessagePart, OutboxMessage, SearchQuery};
+
+pub mod cache;
+pub mod encrypted;
+pub mod file;
+pub mod indexed;
+pub mod migration;
+
+pub use cache::PartCache;
+pub use encrypted::{EncryptedStorage, StorageKey};
+pub use file::FileStorage;
+pub use indexed::IndexedStorage;
+
+/// How many events a subscriber can fall behind before it misses some.
+const EVENT_CAPACITY: usize = 256;
+
+/// A change to the stored data, so that views can update what they show
+/// instead of reading whole folders again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageEvent {
+    EnvelopeAdded { folder_id: FolderId, id: MessageId },
+    /// An existing envelope was saved again, e.g. by a sync.
+    EnvelopeUpdated { folder_id: FolderId, id: MessageId },
+    EnvelopeRemoved { folder_id: FolderId, id: MessageId },
+    /// The flags, keywords or labels of an envelope changed.
+    FlagsChanged { folder_id: FolderId, id: MessageId },
+    /// The folder or its metadata, e.g. the unread count, changed.
+    FolderUpdated { folder_id: FolderId },
+    FolderRemoved { folder_id: FolderId },
+    /// A label of the account was saved or deleted.
+    LabelsChanged { account_id: AccountId },
+}
 
 #[async_trait]
 pub trait Storage: Send + Sync {
+    /// Subscribes to the changes made from now on. A subscriber that falls
+    /// behind gets [`broadcast::error::RecvError::Lagged`] and should reload
+    /// what it shows.
+    fn subscribe(&self) -> broadcast::Receiver<StorageEvent>;
+
     // Account operations
     async fn save_account(&self, account: &Account) -> Result<()>;
     async fn get_account(&self, id: &AccountId) -> Result<Account>;
     async fn list_accounts(&self) -> Result<Vec<Account>>;
     async fn delete_account(&self, id: &AccountId) -> Result<()>;
+    async fn save_account_preferences(&self, preferences: &AccountPreferences) -> Result<()>;
+    async fn get_account_preferences(&self, account_id: &AccountId) -> Result<AccountPreferences>;
+    async fn save_account_config(&self, config: &AccountConfig) -> Result<()>;
+    async fn get_account_config(&self, account_id: &AccountId) -> Result<AccountConfig>;
+    async fn list_account_configs(&self) -> Result<Vec<AccountConfig>>;
+    async fn delete_account_config(&self, account_id: &AccountId) -> Result<()>;
 
     // Folder operations
     async fn save_folder(&self, folder: &Folder) -> Result<()>;
@@ -25,8 +71,50 @@ pub trait Storage: Send + Sync {
     async fn save_envelope(&self, envelope: &Envelope) -> Result<()>;
     async fn get_envelope(&self, id: &MessageId) -> Result<Envelope>;
     async fn list_envelopes(&self, folder_id: &FolderId) -> Result<Vec<Envelope>>;
+    /// Returns a filtered, sorted page of the envelopes of a folder. The
+    /// default loads the whole folder, storages that can should do better.
+    async fn query_envelopes(&self, folder_id: &FolderId, query: &EnvelopeQuery) -> Result<Vec<Envelope>> {
+        Ok(query.apply(self.list_envelopes(folder_id).await?))
+    }
     async fn delete_envelope(&self, id: &MessageId) -> Result<()>;
+    /// Saves many envelopes at once, e.g. the changes of a sync, adding or
+    /// replacing them. Storages with transactions should save them in one.
+    async fn save_envelopes(&self, envelopes: &[Envelope]) -> Result<()> {
+        for envelope in envelopes {
+            self.save_envelope(envelope).await?;
+        }
+        Ok(())
+    }
+    /// Deletes many envelopes at once, skipping the ones that don't exist.
+    async fn delete_envelopes(&self, ids: &[MessageId]) -> Result<()> {
+        for id in ids {
+            match self.delete_envelope(id).await {
+                Ok(()) | Err(MailinerError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
     async fn update_envelope_flags(&self, id: &MessageId, flags: &[(&str, bool)]) -> Result<()>;
+    async fn update_envelope_keywords(&self, id: &MessageId, keywords: &[(&str, bool)]) -> Result<()>;
+    /// Puts labels on or takes them off an envelope. The default loads and
+    /// saves the whole envelope.
+    async fn update_envelope_labels(&self, id: &MessageId, labels: &[(&str, bool)]) -> Result<()> {
+        let mut envelope = self.get_envelope(id).await?;
+        for (label, value) in labels {
+            if *value {
+                envelope.labels.insert(label.to_string());
+            } else {
+                envelope.labels.remove(*label);
+            }
+        }
+        self.save_envelope(&envelope).await
+    }
+
+    // Label operations
+    async fn save_label(&self, label: &Label) -> Result<()>;
+    async fn list_labels(&self, account_id: &AccountId) -> Result<Vec<Label>>;
+    async fn delete_label(&self, account_id: &AccountId, name: &str) -> Result<()>;
 
     // Message part operations
     async fn save_message_part(&self, part: &MessagePart) -> Result<()>;
@@ -39,33 +127,121 @@ pub trait Storage: Send + Sync {
     async fn get_account_metadata(&self, account_id: &AccountId) -> Result<AccountMetadata>;
     async fn save_folder_metadata(&self, metadata: &FolderMetadata) -> Result<()>;
     async fn get_folder_metadata(&self, folder_id: &FolderId) -> Result<FolderMetadata>;
+
+    // Outbox operations
+    async fn save_outbox_message(&self, message: &OutboxMessage) -> Result<()>;
+    async fn get_outbox_message(&self, id: &OutboxId) -> Result<OutboxMessage>;
+    async fn list_outbox_messages(&self, account_id: &AccountId) -> Result<Vec<OutboxMessage>>;
+    async fn delete_outbox_message(&self, id: &OutboxId) -> Result<()>;
+
+    // Delivery report operations
+    async fn save_delivery_report(&self, report: &DeliveryReport) -> Result<()>;
+    /// Lists the reports on the message with the given Message-ID.
+    async fn list_delivery_reports(&self, original_message_id: &str) -> Result<Vec<DeliveryReport>>;
+
+    // Contact operations
+    async fn save_contact(&self, contact: &Contact) -> Result<()>;
+    async fn get_contact(&self, id: &ContactId) -> Result<Contact>;
+    async fn list_contacts(&self) -> Result<Vec<Contact>>;
+    async fn delete_contact(&self, id: &ContactId) -> Result<()>;
+    /// The contacts whose names or addresses start with `prefix`, the most
+    /// frecent first.
+    async fn search_contacts(&self, prefix: &str, limit: usize) -> Result<Vec<Contact>> {
+        Ok(contacts::search(self.list_contacts().await?, prefix, limit))
+    }
+
+    // Rule operations
+    async fn save_rule(&self, rule: &Rule) -> Result<()>;
+    async fn list_rules(&self, account_id: &AccountId) -> Result<Vec<Rule>>;
+    async fn delete_rule(&self, id: &RuleId) -> Result<()>;
+
+    // Activity log
+    async fn add_activity(&self, entry: &ActivityEntry) -> Result<()>;
+    /// The account's log, oldest first.
+    async fn list_activity(&self, account_id: &AccountId) -> Result<Vec<ActivityEntry>>;
+
+    // Search
+    /// Searches the cached envelopes of a folder, newest first. The body and
+    /// raw criteria need a search index, see [`IndexedStorage`]; by default
+    /// they are ignored.
+    async fn search(&self, folder_id: &FolderId, query: &SearchQuery) -> Result<Vec<MessageId>> {
+        let mut envelopes: Vec<Envelope> = self
+            .list_envelopes(folder_id)
+            .await?
+            .into_iter()
+            .filter(|envelope| query.matches(envelope))
+            .collect();
+        envelopes.sort_by_key(|envelope| std::cmp::Reverse(envelope.date));
+        Ok(envelopes.into_iter().map(|envelope| envelope.id).collect())
+    }
 }
 
 // In-memory implementation for testing
 pub struct InMemoryStorage {
     accounts: Arc<RwLock<HashMap<AccountId, Account>>>,
+    account_preferences: Arc<RwLock<HashMap<AccountId, AccountPreferences>>>,
+    account_configs: Arc<RwLock<HashMap<AccountId, AccountConfig>>>,
     folders: Arc<RwLock<HashMap<FolderId, Folder>>>,
+    labels: Arc<RwLock<HashMap<(AccountId, String), Label>>>,
     envelopes: Arc<RwLock<HashMap<MessageId, Envelope>>>,
     message_parts: Arc<RwLock<HashMap<MessagePartId, MessagePart>>>,
     account_metadata: Arc<RwLock<HashMap<AccountId, AccountMetadata>>>,
     folder_metadata: Arc<RwLock<HashMap<FolderId, FolderMetadata>>>,
+    outbox: Arc<RwLock<HashMap<OutboxId, OutboxMessage>>>,
+    delivery_reports: Arc<RwLock<HashMap<MessageId, DeliveryReport>>>,
+    contacts: Arc<RwLock<HashMap<ContactId, Contact>>>,
+    rules: Arc<RwLock<HashMap<RuleId, Rule>>>,
+    activity: Arc<RwLock<Vec<ActivityEntry>>>,
+    events: broadcast::Sender<StorageEvent>,
 }
 
 impl InMemoryStorage {
     pub fn new() -> Self {
         Self {
             accounts: Arc::new(RwLock::new(HashMap::new())),
+            account_preferences: Arc::new(RwLock::new(HashMap::new())),
+            account_configs: Arc::new(RwLock::new(HashMap::new())),
             folders: Arc::new(RwLock::new(HashMap::new())),
+            labels: Arc::new(RwLock::new(HashMap::new())),
             envelopes: Arc::new(RwLock::new(HashMap::new())),
             message_parts: Arc::new(RwLock::new(HashMap::new())),
             account_metadata: Arc::new(RwLock::new(HashMap::new())),
             folder_metadata: Arc::new(RwLock::new(HashMap::new())),
+            outbox: Arc::new(RwLock::new(HashMap::new())),
+            delivery_reports: Arc::new(RwLock::new(HashMap::new())),
+            contacts: Arc::new(RwLock::new(HashMap::new())),
+            rules: Arc::new(RwLock::new(HashMap::new())),
+            activity: Arc::new(RwLock::new(Vec::new())),
+            events: broadcast::channel(EVENT_CAPACITY).0,
         }
     }
+
+    fn insert_envelope(&self, envelopes: &mut HashMap<MessageId, Envelope>, envelope: &Envelope) {
+        let existing = envelopes.insert(envelope.id.clone(), envelope.clone());
+        let (folder_id, id) = (envelope.folder_id.clone(), envelope.id.clone());
+        self.notify(match existing {
+            // Moved envelopes are gone from their old folder.
+            Some(old) if old.folder_id != folder_id => {
+                self.notify(StorageEvent::EnvelopeRemoved { folder_id: old.folder_id, id: id.clone() });
+                StorageEvent::EnvelopeAdded { folder_id, id }
+            }
+            Some(_) => StorageEvent::EnvelopeUpdated { folder_id, id },
+            None => StorageEvent::EnvelopeAdded { folder_id, id },
+        });
+    }
+
+    fn notify(&self, event: StorageEvent) {
+        // Nobody listening is fine.
+        let _ = self.events.send(event);
+    }
 }
 
 #[async_trait]
 impl Storage for InMemoryStorage {
+    fn subscribe(&self) -> broadcast::Receiver<StorageEvent> {
+        self.events.subscribe()
+    }
+
     async fn save_account(&self, account: &Account) -> Result<()> {
         self.accounts.write().await.insert(account.id.clone(), account.clone());
         Ok(())
@@ -84,8 +260,36 @@ impl Storage for InMemoryStorage {
         Ok(())
     }
 
+    async fn save_account_preferences(&self, preferences: &AccountPreferences) -> Result<()> {
+        self.account_preferences.write().await.insert(preferences.account_id.clone(), preferences.clone());
+        Ok(())
+    }
+
+    async fn get_account_preferences(&self, account_id: &AccountId) -> Result<AccountPreferences> {
+        self.account_preferences.read().await.get(account_id).cloned().ok_or_else(|| MailinerError::NotFound(format!("Account preferences {}", account_id)))
+    }
+
+    async fn save_account_config(&self, config: &AccountConfig) -> Result<()> {
+        self.account_configs.write().await.insert(config.account_id.clone(), config.clone());
+        Ok(())
+    }
+
+    async fn get_account_config(&self, account_id: &AccountId) -> Result<AccountConfig> {
+        self.account_configs.read().await.get(account_id).cloned().ok_or_else(|| MailinerError::NotFound(format!("Account config {}", account_id)))
+    }
+
+    async fn list_account_configs(&self) -> Result<Vec<AccountConfig>> {
+        Ok(self.account_configs.read().await.values().cloned().collect())
+    }
+
+    async fn delete_account_config(&self, account_id: &AccountId) -> Result<()> {
+        self.account_configs.write().await.remove(account_id).ok_or_else(|| MailinerError::NotFound(format!("Account config {}", account_id)))?;
+        Ok(())
+    }
+
     async fn save_folder(&self, folder: &Folder) -> Result<()> {
         self.folders.write().await.insert(folder.id.clone(), folder.clone());
+        self.notify(StorageEvent::FolderUpdated { folder_id: folder.id.clone() });
         Ok(())
     }
 
@@ -99,11 +303,21 @@ impl Storage for InMemoryStorage {
 
     async fn delete_folder(&self, id: &FolderId) -> Result<()> {
         self.folders.write().await.remove(id).ok_or_else(|| MailinerError::NotFound(format!("Folder {}", id)))?;
+        self.notify(StorageEvent::FolderRemoved { folder_id: id.clone() });
         Ok(())
     }
 
     async fn save_envelope(&self, envelope: &Envelope) -> Result<()> {
-        self.envelopes.write().await.insert(envelope.id.clone(), envelope.clone());
+        let mut envelopes = self.envelopes.write().await;
+        self.insert_envelope(&mut envelopes, envelope);
+        Ok(())
+    }
+
+    async fn save_envelopes(&self, new: &[Envelope]) -> Result<()> {
+        let mut envelopes = self.envelopes.write().await;
+        for envelope in new {
+            self.insert_envelope(&mut envelopes, envelope);
+        }
         Ok(())
     }
 
@@ -115,8 +329,26 @@ impl Storage for InMemoryStorage {
         Ok(self.envelopes.read().await.values().filter(|e| e.folder_id == *folder_id).cloned().collect())
     }
 
+    async fn query_envelopes(&self, folder_id: &FolderId, query: &EnvelopeQuery) -> Result<Vec<Envelope>> {
+        let envelopes = self.envelopes.read().await;
+        let mut matching: Vec<&Envelope> = envelopes.values().filter(|e| e.folder_id == *folder_id && query.filter.matches(e)).collect();
+        matching.sort_by(|a, b| query.sort.compare(a, b));
+        Ok(matching.into_iter().skip(query.offset).take(query.limit.unwrap_or(usize::MAX)).cloned().collect())
+    }
+
     async fn delete_envelope(&self, id: &MessageId) -> Result<()> {
-        self.envelopes.write().await.remove(id).ok_or_else(|| MailinerError::NotFound(format!("Envelope {}", id)))?;
+        let envelope = self.envelopes.write().await.remove(id).ok_or_else(|| MailinerError::NotFound(format!("Envelope {}", id)))?;
+        self.notify(StorageEvent::EnvelopeRemoved { folder_id: envelope.folder_id, id: id.clone() });
+        Ok(())
+    }
+
+    async fn delete_envelopes(&self, ids: &[MessageId]) -> Result<()> {
+        let mut envelopes = self.envelopes.write().await;
+        for id in ids {
+            if let Some(envelope) = envelopes.remove(id) {
+                self.notify(StorageEvent::EnvelopeRemoved { folder_id: envelope.folder_id, id: id.clone() });
+            }
+        }
         Ok(())
     }
 
@@ -134,6 +366,53 @@ impl Storage for InMemoryStorage {
                 _ => return Err(MailinerError::InvalidData(format!("Unknown flag: {}", flag))),
             }
         }
+        self.notify(StorageEvent::FlagsChanged { folder_id: envelope.folder_id.clone(), id: id.clone() });
+        Ok(())
+    }
+
+    async fn update_envelope_keywords(&self, id: &MessageId, keywords: &[(&str, bool)]) -> Result<()> {
+        let mut envelopes = self.envelopes.write().await;
+        let envelope = envelopes.get_mut(id).ok_or_else(|| MailinerError::NotFound(format!("Envelope {}", id)))?;
+
+        for (keyword, value) in keywords {
+            if *value {
+                envelope.keywords.insert(keyword.to_string());
+            } else {
+                envelope.keywords.remove(*keyword);
+            }
+        }
+        self.notify(StorageEvent::FlagsChanged { folder_id: envelope.folder_id.clone(), id: id.clone() });
+        Ok(())
+    }
+
+    async fn update_envelope_labels(&self, id: &MessageId, labels: &[(&str, bool)]) -> Result<()> {
+        let mut envelopes = self.envelopes.write().await;
+        let envelope = envelopes.get_mut(id).ok_or_else(|| MailinerError::NotFound(format!("Envelope {}", id)))?;
+
+        for (label, value) in labels {
+            if *value {
+                envelope.labels.insert(label.to_string());
+            } else {
+                envelope.labels.remove(*label);
+            }
+        }
+        self.notify(StorageEvent::FlagsChanged { folder_id: envelope.folder_id.clone(), id: id.clone() });
+        Ok(())
+    }
+
+    async fn save_label(&self, label: &Label) -> Result<()> {
+        self.labels.write().await.insert((label.account_id.clone(), label.name.clone()), label.clone());
+        self.notify(StorageEvent::LabelsChanged { account_id: label.account_id.clone() });
+        Ok(())
+    }
+
+    async fn list_labels(&self, account_id: &AccountId) -> Result<Vec<Label>> {
+        Ok(self.labels.read().await.values().filter(|l| l.account_id == *account_id).cloned().collect())
+    }
+
+    async fn delete_label(&self, account_id: &AccountId, name: &str) -> Result<()> {
+        self.labels.write().await.remove(&(account_id.clone(), name.to_string())).ok_or_else(|| MailinerError::NotFound(format!("Label {}", name)))?;
+        self.notify(StorageEvent::LabelsChanged { account_id: account_id.clone() });
         Ok(())
     }
 
@@ -166,10 +445,79 @@ impl Storage for InMemoryStorage {
 
     async fn save_folder_metadata(&self, metadata: &FolderMetadata) -> Result<()> {
         self.folder_metadata.write().await.insert(metadata.id.clone(), metadata.clone());
+        self.notify(StorageEvent::FolderUpdated { folder_id: metadata.id.clone() });
         Ok(())
     }
 
     async fn get_folder_metadata(&self, folder_id: &FolderId) -> Result<FolderMetadata> {
         self.folder_metadata.read().await.get(folder_id).cloned().ok_or_else(|| MailinerError::NotFound(format!("Folder metadata {}", folder_id)))
     }
+
+    async fn save_outbox_message(&self, message: &OutboxMessage) -> Result<()> {
+        self.outbox.write().await.insert(message.id.clone(), message.clone());
+        Ok(())
+    }
+
+    async fn get_outbox_message(&self, id: &OutboxId) -> Result<OutboxMessage> {
+        self.outbox.read().await.get(id).cloned().ok_or_else(|| MailinerError::NotFound(format!("Outbox message {}", id)))
+    }
+
+    async fn list_outbox_messages(&self, account_id: &AccountId) -> Result<Vec<OutboxMessage>> {
+        Ok(self.outbox.read().await.values().filter(|m| m.account_id == *account_id).cloned().collect())
+    }
+
+    async fn delete_outbox_message(&self, id: &OutboxId) -> Result<()> {
+        self.outbox.write().await.remove(id).ok_or_else(|| MailinerError::NotFound(format!("Outbox message {}", id)))?;
+        Ok(())
+    }
+
+    async fn save_delivery_report(&self, report: &DeliveryReport) -> Result<()> {
+        self.delivery_reports.write().await.insert(report.id.clone(), report.clone());
+        Ok(())
+    }
+
+    async fn list_delivery_reports(&self, original_message_id: &str) -> Result<Vec<DeliveryReport>> {
+        Ok(self.delivery_reports.read().await.values().filter(|r| r.original_message_id.as_deref() == Some(original_message_id)).cloned().collect())
+    }
+
+    async fn save_contact(&self, contact: &Contact) -> Result<()> {
+        self.contacts.write().await.insert(contact.id.clone(), contact.clone());
+        Ok(())
+    }
+
+    async fn get_contact(&self, id: &ContactId) -> Result<Contact> {
+        self.contacts.read().await.get(id).cloned().ok_or_else(|| MailinerError::NotFound(format!("Contact {}", id)))
+    }
+
+    async fn list_contacts(&self) -> Result<Vec<Contact>> {
+        Ok(self.contacts.read().await.values().cloned().collect())
+    }
+
+    async fn delete_contact(&self, id: &ContactId) -> Result<()> {
+        self.contacts.write().await.remove(id).ok_or_else(|| MailinerError::NotFound(format!("Contact {}", id)))?;
+        Ok(())
+    }
+
+    async fn save_rule(&self, rule: &Rule) -> Result<()> {
+        self.rules.write().await.insert(rule.id.clone(), rule.clone());
+        Ok(())
+    }
+
+    async fn list_rules(&self, account_id: &AccountId) -> Result<Vec<Rule>> {
+        Ok(self.rules.read().await.values().filter(|r| r.account_id == *account_id).cloned().collect())
+    }
+
+    async fn delete_rule(&self, id: &RuleId) -> Result<()> {
+        self.rules.write().await.remove(id).ok_or_else(|| MailinerError::NotFound(format!("Rule {}", id)))?;
+        Ok(())
+    }
+
+    async fn add_activity(&self, entry: &ActivityEntry) -> Result<()> {
+        self.activity.write().await.push(entry.clone());
+        Ok(())
+    }
+
+    async fn list_activity(&self, account_id: &AccountId) -> Result<Vec<ActivityEntry>> {
+        Ok(self.activity.read().await.iter().filter(|e| e.account_id == *account_id).cloned().collect())
+    }
 } 
\ No newline at end of file
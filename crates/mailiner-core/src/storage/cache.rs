@@ -0,0 +1,423 @@
+//! A size-bounded cache of message parts.
+//!
+//! [`PartCache`] wraps another [`Storage`] and keeps the parts saved in it
+//! under a byte budget, deleting the least recently used ones when it's
+//! exceeded, so that reading a large mailbox doesn't grow the cache without
+//! bound. Evicted parts are fetched from the server again when needed.
+//!
+//! The parts of pinned messages, e.g. the one open in the reader, and of
+//! drafts are never evicted.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use tokio::sync::broadcast;
+
+use crate::activity::ActivityEntry;
+use crate::contacts::Contact;
+use crate::delivery::DeliveryReport;
+use crate::error::{MailinerError, Result};
+use crate::ids::{AccountId, ContactId, FolderId, MessageId, MessagePartId, OutboxId, RuleId};
+use crate::models::{
+    Account, AccountConfig, AccountMetadata, AccountPreferences, Envelope, EnvelopeQuery, Folder,
+    FolderMetadata, Label, MessagePart, OutboxMessage, SearchQuery,
+};
+use crate::rules::Rule;
+use crate::storage::{Storage, StorageEvent};
+
+struct Entry {
+    envelope_id: MessageId,
+    size: u64,
+    draft: bool,
+    /// When the part was last used, the key in `Lru::order`.
+    used: u64,
+}
+
+/// The parts in the cache, by when they were last used.
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<MessagePartId, Entry>,
+    order: BTreeMap<u64, MessagePartId>,
+    pinned: HashSet<MessageId>,
+    clock: u64,
+    total: u64,
+}
+
+impl Lru {
+    fn insert(&mut self, part: &MessagePart, draft: bool) {
+        self.remove(&part.id);
+        self.clock += 1;
+        self.order.insert(self.clock, part.id.clone());
+        self.total += part.size;
+        self.entries.insert(
+            part.id.clone(),
+            Entry {
+                envelope_id: part.envelope_id.clone(),
+                size: part.size,
+                draft,
+                used: self.clock,
+            },
+        );
+    }
+
+    fn touch(&mut self, id: &MessagePartId) {
+        if let Some(entry) = self.entries.get_mut(id) {
+            self.order.remove(&entry.used);
+            self.clock += 1;
+            entry.used = self.clock;
+            self.order.insert(self.clock, id.clone());
+        }
+    }
+
+    fn remove(&mut self, id: &MessagePartId) {
+        if let Some(entry) = self.entries.remove(id) {
+            self.order.remove(&entry.used);
+            self.total -= entry.size;
+        }
+    }
+
+    /// Takes the least recently used parts out until the rest fit in
+    /// `budget`, skipping pinned ones, and returns them.
+    fn evict(&mut self, budget: u64) -> Vec<MessagePartId> {
+        let mut evicted = Vec::new();
+        let mut total = self.total;
+        for id in self.order.values() {
+            if total <= budget {
+                break;
+            }
+            let entry = &self.entries[id];
+            if entry.draft || self.pinned.contains(&entry.envelope_id) {
+                continue;
+            }
+            total -= entry.size;
+            evicted.push(id.clone());
+        }
+        for id in &evicted {
+            self.remove(id);
+        }
+        evicted
+    }
+}
+
+pub struct PartCache<S: Storage> {
+    inner: S,
+    budget: u64,
+    lru: Mutex<Lru>,
+}
+
+impl<S: Storage> PartCache<S> {
+    /// Wraps `inner`, keeping the parts saved from now on under `budget`
+    /// bytes. Parts saved before aren't counted.
+    pub fn new(inner: S, budget: u64) -> Self {
+        Self {
+            inner,
+            budget,
+            lru: Mutex::new(Lru::default()),
+        }
+    }
+
+    /// Keeps the parts of a message until it's unpinned.
+    pub fn pin(&self, message_id: &MessageId) {
+        self.lru.lock().unwrap().pinned.insert(message_id.clone());
+    }
+
+    pub fn unpin(&self, message_id: &MessageId) {
+        self.lru.lock().unwrap().pinned.remove(message_id);
+    }
+
+    /// The size of the parts in the cache, in bytes.
+    pub fn used(&self) -> u64 {
+        self.lru.lock().unwrap().total
+    }
+}
+
+#[async_trait]
+impl<S: Storage> Storage for PartCache<S> {
+    fn subscribe(&self) -> broadcast::Receiver<StorageEvent> {
+        self.inner.subscribe()
+    }
+
+    async fn save_account(&self, account: &Account) -> Result<()> {
+        self.inner.save_account(account).await
+    }
+
+    async fn get_account(&self, id: &AccountId) -> Result<Account> {
+        self.inner.get_account(id).await
+    }
+
+    async fn list_accounts(&self) -> Result<Vec<Account>> {
+        self.inner.list_accounts().await
+    }
+
+    async fn delete_account(&self, id: &AccountId) -> Result<()> {
+        self.inner.delete_account(id).await
+    }
+
+    async fn save_account_preferences(&self, preferences: &AccountPreferences) -> Result<()> {
+        self.inner.save_account_preferences(preferences).await
+    }
+
+    async fn get_account_preferences(&self, account_id: &AccountId) -> Result<AccountPreferences> {
+        self.inner.get_account_preferences(account_id).await
+    }
+
+    async fn save_account_config(&self, config: &AccountConfig) -> Result<()> {
+        self.inner.save_account_config(config).await
+    }
+
+    async fn get_account_config(&self, account_id: &AccountId) -> Result<AccountConfig> {
+        self.inner.get_account_config(account_id).await
+    }
+
+    async fn list_account_configs(&self) -> Result<Vec<AccountConfig>> {
+        self.inner.list_account_configs().await
+    }
+
+    async fn delete_account_config(&self, account_id: &AccountId) -> Result<()> {
+        self.inner.delete_account_config(account_id).await
+    }
+
+    async fn save_folder(&self, folder: &Folder) -> Result<()> {
+        self.inner.save_folder(folder).await
+    }
+
+    async fn get_folder(&self, id: &FolderId) -> Result<Folder> {
+        self.inner.get_folder(id).await
+    }
+
+    async fn list_folders(&self, account_id: &AccountId) -> Result<Vec<Folder>> {
+        self.inner.list_folders(account_id).await
+    }
+
+    async fn delete_folder(&self, id: &FolderId) -> Result<()> {
+        self.inner.delete_folder(id).await
+    }
+
+    async fn save_envelope(&self, envelope: &Envelope) -> Result<()> {
+        self.inner.save_envelope(envelope).await
+    }
+
+    async fn save_envelopes(&self, envelopes: &[Envelope]) -> Result<()> {
+        self.inner.save_envelopes(envelopes).await
+    }
+
+    async fn get_envelope(&self, id: &MessageId) -> Result<Envelope> {
+        self.inner.get_envelope(id).await
+    }
+
+    async fn list_envelopes(&self, folder_id: &FolderId) -> Result<Vec<Envelope>> {
+        self.inner.list_envelopes(folder_id).await
+    }
+
+    async fn query_envelopes(
+        &self,
+        folder_id: &FolderId,
+        query: &EnvelopeQuery,
+    ) -> Result<Vec<Envelope>> {
+        self.inner.query_envelopes(folder_id, query).await
+    }
+
+    async fn delete_envelope(&self, id: &MessageId) -> Result<()> {
+        self.inner.delete_envelope(id).await
+    }
+
+    async fn delete_envelopes(&self, ids: &[MessageId]) -> Result<()> {
+        self.inner.delete_envelopes(ids).await
+    }
+
+    async fn update_envelope_flags(&self, id: &MessageId, flags: &[(&str, bool)]) -> Result<()> {
+        self.inner.update_envelope_flags(id, flags).await
+    }
+
+    async fn update_envelope_keywords(
+        &self,
+        id: &MessageId,
+        keywords: &[(&str, bool)],
+    ) -> Result<()> {
+        self.inner.update_envelope_keywords(id, keywords).await
+    }
+
+    async fn update_envelope_labels(&self, id: &MessageId, labels: &[(&str, bool)]) -> Result<()> {
+        self.inner.update_envelope_labels(id, labels).await
+    }
+
+    async fn save_label(&self, label: &Label) -> Result<()> {
+        self.inner.save_label(label).await
+    }
+
+    async fn list_labels(&self, account_id: &AccountId) -> Result<Vec<Label>> {
+        self.inner.list_labels(account_id).await
+    }
+
+    async fn delete_label(&self, account_id: &AccountId, name: &str) -> Result<()> {
+        self.inner.delete_label(account_id, name).await
+    }
+
+    /// Saves the part, then evicts the least recently used ones if the
+    /// cache is over its budget.
+    async fn save_message_part(&self, part: &MessagePart) -> Result<()> {
+        let draft = match self.inner.get_envelope(&part.envelope_id).await {
+            Ok(envelope) => envelope.is_draft,
+            Err(MailinerError::NotFound(_)) => false,
+            Err(e) => return Err(e),
+        };
+        self.inner.save_message_part(part).await?;
+        let evicted = {
+            let mut lru = self.lru.lock().unwrap();
+            lru.insert(part, draft);
+            lru.evict(self.budget)
+        };
+        for id in &evicted {
+            match self.inner.delete_message_part(id).await {
+                Ok(()) | Err(MailinerError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_message_part(&self, id: &MessagePartId) -> Result<MessagePart> {
+        let part = self.inner.get_message_part(id).await?;
+        self.lru.lock().unwrap().touch(id);
+        Ok(part)
+    }
+
+    async fn list_message_parts(&self, envelope_id: &MessageId) -> Result<Vec<MessagePart>> {
+        self.inner.list_message_parts(envelope_id).await
+    }
+
+    async fn delete_message_part(&self, id: &MessagePartId) -> Result<()> {
+        self.inner.delete_message_part(id).await?;
+        self.lru.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    async fn save_account_metadata(&self, metadata: &AccountMetadata) -> Result<()> {
+        self.inner.save_account_metadata(metadata).await
+    }
+
+    async fn get_account_metadata(&self, account_id: &AccountId) -> Result<AccountMetadata> {
+        self.inner.get_account_metadata(account_id).await
+    }
+
+    async fn save_folder_metadata(&self, metadata: &FolderMetadata) -> Result<()> {
+        self.inner.save_folder_metadata(metadata).await
+    }
+
+    async fn get_folder_metadata(&self, folder_id: &FolderId) -> Result<FolderMetadata> {
+        self.inner.get_folder_metadata(folder_id).await
+    }
+
+    async fn save_outbox_message(&self, message: &OutboxMessage) -> Result<()> {
+        self.inner.save_outbox_message(message).await
+    }
+
+    async fn get_outbox_message(&self, id: &OutboxId) -> Result<OutboxMessage> {
+        self.inner.get_outbox_message(id).await
+    }
+
+    async fn list_outbox_messages(&self, account_id: &AccountId) -> Result<Vec<OutboxMessage>> {
+        self.inner.list_outbox_messages(account_id).await
+    }
+
+    async fn delete_outbox_message(&self, id: &OutboxId) -> Result<()> {
+        self.inner.delete_outbox_message(id).await
+    }
+
+    async fn save_delivery_report(&self, report: &DeliveryReport) -> Result<()> {
+        self.inner.save_delivery_report(report).await
+    }
+
+    async fn list_delivery_reports(
+        &self,
+        original_message_id: &str,
+    ) -> Result<Vec<DeliveryReport>> {
+        self.inner.list_delivery_reports(original_message_id).await
+    }
+
+    async fn save_contact(&self, contact: &Contact) -> Result<()> {
+        self.inner.save_contact(contact).await
+    }
+
+    async fn get_contact(&self, id: &ContactId) -> Result<Contact> {
+        self.inner.get_contact(id).await
+    }
+
+    async fn list_contacts(&self) -> Result<Vec<Contact>> {
+        self.inner.list_contacts().await
+    }
+
+    async fn delete_contact(&self, id: &ContactId) -> Result<()> {
+        self.inner.delete_contact(id).await
+    }
+
+    async fn save_rule(&self, rule: &Rule) -> Result<()> {
+        self.inner.save_rule(rule).await
+    }
+
+    async fn list_rules(&self, account_id: &AccountId) -> Result<Vec<Rule>> {
+        self.inner.list_rules(account_id).await
+    }
+
+    async fn delete_rule(&self, id: &RuleId) -> Result<()> {
+        self.inner.delete_rule(id).await
+    }
+
+    async fn add_activity(&self, entry: &ActivityEntry) -> Result<()> {
+        self.inner.add_activity(entry).await
+    }
+
+    async fn list_activity(&self, account_id: &AccountId) -> Result<Vec<ActivityEntry>> {
+        self.inner.list_activity(account_id).await
+    }
+
+    async fn search_contacts(&self, prefix: &str, limit: usize) -> Result<Vec<Contact>> {
+        self.inner.search_contacts(prefix, limit).await
+    }
+
+    async fn search(&self, folder_id: &FolderId, query: &SearchQuery) -> Result<Vec<MessageId>> {
+        self.inner.search(folder_id, query).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::models::MessageContent;
+
+    fn part(message: &str, index: u32, size: u64) -> MessagePart {
+        MessagePart {
+            id: MessagePartId::new(format!("{}/{}", message, index)),
+            envelope_id: MessageId::new(message),
+            content_type: "text/plain".to_string(),
+            filename: None,
+            size,
+            is_attachment: false,
+            content: MessageContent::Text(String::new()),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut lru = Lru::default();
+        lru.insert(&part("INBOX;UID=1", 1, 400), false);
+        lru.insert(&part("INBOX;UID=2", 1, 400), false);
+        lru.insert(&part("Drafts;UID=3", 1, 400), true);
+        lru.insert(&part("INBOX;UID=4", 1, 400), false);
+        lru.touch(&MessagePartId::new("INBOX;UID=1/1"));
+        lru.pinned.insert(MessageId::new("INBOX;UID=4"));
+
+        assert!(lru.evict(1600).is_empty());
+        // Message 2 is the least recently used, the draft and the pinned
+        // message are skipped.
+        assert_eq!(lru.evict(1200), [MessagePartId::new("INBOX;UID=2/1")]);
+        assert_eq!(lru.evict(400), [MessagePartId::new("INBOX;UID=1/1")]);
+        assert_eq!(lru.total, 800);
+        assert!(lru.evict(0).is_empty());
+    }
+}
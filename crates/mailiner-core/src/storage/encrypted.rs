@@ -0,0 +1,703 @@
+//! Encryption at rest for the local cache.
+//!
+//! [`EncryptedStorage`] wraps another [`Storage`] and encrypts what would
+//! give away the user's mail before it is written: message parts, the
+//! subjects, addresses and labels of envelopes, messages in the outbox and
+//! the names and addresses of contacts.
+//! Ids, flags, keywords, dates and the lists of folders and labels stay
+//! readable, so that the wrapped storage can still look records up and
+//! update flags.
+//!
+//! The sealed fields are AES-256-GCM encrypted JSON, bound to the id of their
+//! record. Records written before encryption was turned on are read as they
+//! are.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use async_trait::async_trait;
+use base64::prelude::*;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::activity::ActivityEntry;
+use crate::backup::derive_key;
+use crate::contacts::{self, Contact};
+use crate::delivery::DeliveryReport;
+use crate::error::{MailinerError, Result};
+use crate::ids::{AccountId, ContactId, FolderId, MessageId, MessagePartId, OutboxId, RuleId};
+use crate::models::{
+    Account, AccountConfig, AccountMetadata, AccountPreferences, EmailAddress, Envelope,
+    EnvelopeQuery, Folder, FolderMetadata, Label, MessageContent, MessagePart, OutboxMessage,
+};
+use crate::platform::SecureStorage;
+use crate::rules::Rule;
+use crate::storage::{Storage, StorageEvent};
+use crate::unsubscribe::Unsubscribe;
+
+/// Marks sealed data, followed by the nonce and the ciphertext.
+const PREFIX: &[u8] = b"mlnrenc1:";
+const KEY_LEN: usize = 32;
+pub const SALT_LEN: usize = 16;
+
+/// The key the cache is encrypted with.
+pub struct StorageKey(LessSafeKey);
+
+impl fmt::Debug for StorageKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StorageKey(..)")
+    }
+}
+
+impl StorageKey {
+    /// Derives the key from the user's master password. The salt comes from
+    /// [`StorageKey::generate_salt`] when the cache is created and is kept
+    /// next to it.
+    pub fn from_password(password: &str, salt: &[u8]) -> Self {
+        Self(derive_key(password, salt))
+    }
+
+    pub fn generate_salt() -> Result<[u8; SALT_LEN]> {
+        let mut salt = [0u8; SALT_LEN];
+        random(&mut salt)?;
+        Ok(salt)
+    }
+
+    /// Loads the key stored as `name` in `secrets`, e.g. the OS keychain,
+    /// and stores a new random key there the first time.
+    pub async fn from_secure_storage(secrets: &dyn SecureStorage, name: &str) -> Result<Self> {
+        let mut key = [0u8; KEY_LEN];
+        match secrets.get_secret(name).await? {
+            Some(stored) => {
+                let stored = BASE64_STANDARD
+                    .decode(stored)
+                    .ok()
+                    .filter(|stored| stored.len() == KEY_LEN)
+                    .ok_or_else(|| {
+                        MailinerError::InvalidData(format!("Invalid storage key {}", name))
+                    })?;
+                key.copy_from_slice(&stored);
+            }
+            None => {
+                random(&mut key)?;
+                secrets
+                    .set_secret(name, &BASE64_STANDARD.encode(key))
+                    .await?;
+            }
+        }
+        // The key length always matches AES-256.
+        Ok(Self(LessSafeKey::new(
+            UnboundKey::new(&AES_256_GCM, &key).unwrap(),
+        )))
+    }
+
+    /// Encrypts `data` for the record `id`.
+    fn seal(&self, id: &str, mut data: Vec<u8>) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        random(&mut nonce)?;
+        self.0
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(id.as_bytes()),
+                &mut data,
+            )
+            .map_err(|_| MailinerError::Storage(format!("Failed to encrypt {}", id)))?;
+
+        let mut sealed = Vec::with_capacity(PREFIX.len() + NONCE_LEN + data.len());
+        sealed.extend_from_slice(PREFIX);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&data);
+        Ok(sealed)
+    }
+
+    /// Decrypts what [`StorageKey::seal`] returned for the record `id`.
+    fn open(&self, id: &str, sealed: &[u8]) -> Result<Vec<u8>> {
+        let invalid = || MailinerError::Storage(format!("Failed to decrypt {}, wrong key?", id));
+        let rest = sealed
+            .strip_prefix(PREFIX)
+            .filter(|rest| rest.len() >= NONCE_LEN)
+            .ok_or_else(invalid)?;
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| invalid())?;
+        let mut data = ciphertext.to_vec();
+        let len = self
+            .0
+            .open_in_place(nonce, Aad::from(id.as_bytes()), &mut data)
+            .map_err(|_| invalid())?
+            .len();
+        data.truncate(len);
+        Ok(data)
+    }
+
+    fn seal_json<T: Serialize>(&self, id: &str, value: &T) -> Result<Vec<u8>> {
+        self.seal(id, serde_json::to_vec(value)?)
+    }
+
+    fn open_json<T: for<'de> Deserialize<'de>>(&self, id: &str, sealed: &[u8]) -> Result<T> {
+        Ok(serde_json::from_slice(&self.open(id, sealed)?)?)
+    }
+}
+
+fn random(buffer: &mut [u8]) -> Result<()> {
+    SystemRandom::new()
+        .fill(buffer)
+        .map_err(|_| MailinerError::Storage("Failed to generate random data".to_string()))
+}
+
+/// The fields of an [`Envelope`] that are encrypted, kept in its subject.
+#[derive(Serialize, Deserialize)]
+struct SealedEnvelope {
+    subject: Option<String>,
+    from: Option<EmailAddress>,
+    to: Option<EmailAddress>,
+    cc: Option<EmailAddress>,
+    bcc: Option<EmailAddress>,
+    reply_to: Option<EmailAddress>,
+    labels: BTreeSet<String>,
+    message_id: Option<String>,
+    in_reply_to: Vec<String>,
+    references: Vec<String>,
+    /// The URLs of `List-Unsubscribe` identify the user.
+    #[serde(default)]
+    unsubscribe: Option<Unsubscribe>,
+}
+
+/// The fields of a [`MessagePart`] that are encrypted, kept as its content.
+#[derive(Serialize, Deserialize)]
+struct SealedPart {
+    filename: Option<String>,
+    content: MessageContent,
+}
+
+/// The fields of an [`OutboxMessage`] that are encrypted, kept as its
+/// message.
+#[derive(Serialize, Deserialize)]
+struct SealedOutboxMessage {
+    recipients: Vec<String>,
+    subject: String,
+    message: Vec<u8>,
+}
+
+/// The fields of a [`Contact`] that are encrypted, kept as its only name.
+#[derive(Serialize, Deserialize)]
+struct SealedContact {
+    names: Vec<String>,
+    addresses: Vec<String>,
+}
+
+fn seal_envelope(key: &StorageKey, envelope: &Envelope) -> Result<Envelope> {
+    let mut sealed = envelope.clone();
+    let fields = SealedEnvelope {
+        subject: sealed.subject.take(),
+        from: sealed.from.take(),
+        to: sealed.to.take(),
+        cc: sealed.cc.take(),
+        bcc: sealed.bcc.take(),
+        reply_to: sealed.reply_to.take(),
+        labels: std::mem::take(&mut sealed.labels),
+        message_id: sealed.message_id.take(),
+        in_reply_to: std::mem::take(&mut sealed.in_reply_to),
+        references: std::mem::take(&mut sealed.references),
+        unsubscribe: sealed.unsubscribe.take(),
+    };
+    let data = key.seal_json(envelope.id.as_str(), &fields)?;
+    // Subjects are text, so what follows the prefix is base64.
+    sealed.subject = Some(format!(
+        "{}{}",
+        String::from_utf8_lossy(PREFIX),
+        BASE64_STANDARD.encode(&data[PREFIX.len()..])
+    ));
+    Ok(sealed)
+}
+
+fn open_envelope(key: &StorageKey, mut envelope: Envelope) -> Result<Envelope> {
+    let Some(encoded) = envelope
+        .subject
+        .as_deref()
+        .and_then(|subject| subject.strip_prefix(std::str::from_utf8(PREFIX).unwrap()))
+    else {
+        return Ok(envelope);
+    };
+    let mut data = PREFIX.to_vec();
+    BASE64_STANDARD
+        .decode_vec(encoded, &mut data)
+        .map_err(|_| MailinerError::Storage(format!("Invalid envelope {}", envelope.id)))?;
+    let fields: SealedEnvelope = key.open_json(envelope.id.as_str(), &data)?;
+    envelope.subject = fields.subject;
+    envelope.from = fields.from;
+    envelope.to = fields.to;
+    envelope.cc = fields.cc;
+    envelope.bcc = fields.bcc;
+    envelope.reply_to = fields.reply_to;
+    envelope.labels = fields.labels;
+    envelope.message_id = fields.message_id;
+    envelope.in_reply_to = fields.in_reply_to;
+    envelope.references = fields.references;
+    envelope.unsubscribe = fields.unsubscribe;
+    Ok(envelope)
+}
+
+fn seal_part(key: &StorageKey, part: &MessagePart) -> Result<MessagePart> {
+    let fields = SealedPart {
+        filename: part.filename.clone(),
+        content: part.content.clone(),
+    };
+    Ok(MessagePart {
+        filename: None,
+        content: MessageContent::Binary(key.seal_json(part.id.as_str(), &fields)?),
+        ..part.clone()
+    })
+}
+
+fn open_part(key: &StorageKey, mut part: MessagePart) -> Result<MessagePart> {
+    match &part.content {
+        MessageContent::Binary(data) if data.starts_with(PREFIX) => {
+            let fields: SealedPart = key.open_json(part.id.as_str(), data)?;
+            part.filename = fields.filename;
+            part.content = fields.content;
+            Ok(part)
+        }
+        _ => Ok(part),
+    }
+}
+
+fn seal_outbox_message(key: &StorageKey, message: &OutboxMessage) -> Result<OutboxMessage> {
+    let fields = SealedOutboxMessage {
+        recipients: message.recipients.clone(),
+        subject: message.subject.clone(),
+        message: message.message.clone(),
+    };
+    Ok(OutboxMessage {
+        recipients: Vec::new(),
+        subject: String::new(),
+        message: key.seal_json(message.id.as_str(), &fields)?,
+        ..message.clone()
+    })
+}
+
+fn open_outbox_message(key: &StorageKey, mut message: OutboxMessage) -> Result<OutboxMessage> {
+    if message.message.starts_with(PREFIX) {
+        let fields: SealedOutboxMessage = key.open_json(message.id.as_str(), &message.message)?;
+        message.recipients = fields.recipients;
+        message.subject = fields.subject;
+        message.message = fields.message;
+    }
+    Ok(message)
+}
+
+fn seal_contact(key: &StorageKey, contact: &Contact) -> Result<Contact> {
+    let fields = SealedContact {
+        names: contact.names.clone(),
+        addresses: contact.addresses.clone(),
+    };
+    let data = key.seal_json(contact.id.as_str(), &fields)?;
+    Ok(Contact {
+        names: vec![format!(
+            "{}{}",
+            String::from_utf8_lossy(PREFIX),
+            BASE64_STANDARD.encode(&data[PREFIX.len()..])
+        )],
+        addresses: Vec::new(),
+        ..contact.clone()
+    })
+}
+
+fn open_contact(key: &StorageKey, mut contact: Contact) -> Result<Contact> {
+    let Some(encoded) = contact
+        .names
+        .first()
+        .and_then(|name| name.strip_prefix(std::str::from_utf8(PREFIX).unwrap()))
+    else {
+        return Ok(contact);
+    };
+    let mut data = PREFIX.to_vec();
+    BASE64_STANDARD
+        .decode_vec(encoded, &mut data)
+        .map_err(|_| MailinerError::Storage(format!("Invalid contact {}", contact.id)))?;
+    let fields: SealedContact = key.open_json(contact.id.as_str(), &data)?;
+    contact.names = fields.names;
+    contact.addresses = fields.addresses;
+    Ok(contact)
+}
+
+/// A [`Storage`] that encrypts the mail it keeps in another one.
+pub struct EncryptedStorage<S: Storage> {
+    inner: S,
+    key: StorageKey,
+}
+
+impl<S: Storage> EncryptedStorage<S> {
+    pub fn new(inner: S, key: StorageKey) -> Self {
+        Self { inner, key }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<S: Storage> Storage for EncryptedStorage<S> {
+    fn subscribe(&self) -> broadcast::Receiver<StorageEvent> {
+        self.inner.subscribe()
+    }
+
+    async fn save_account(&self, account: &Account) -> Result<()> {
+        self.inner.save_account(account).await
+    }
+
+    async fn get_account(&self, id: &AccountId) -> Result<Account> {
+        self.inner.get_account(id).await
+    }
+
+    async fn list_accounts(&self) -> Result<Vec<Account>> {
+        self.inner.list_accounts().await
+    }
+
+    async fn delete_account(&self, id: &AccountId) -> Result<()> {
+        self.inner.delete_account(id).await
+    }
+
+    async fn save_account_preferences(&self, preferences: &AccountPreferences) -> Result<()> {
+        self.inner.save_account_preferences(preferences).await
+    }
+
+    async fn get_account_preferences(&self, account_id: &AccountId) -> Result<AccountPreferences> {
+        self.inner.get_account_preferences(account_id).await
+    }
+
+    async fn save_account_config(&self, config: &AccountConfig) -> Result<()> {
+        self.inner.save_account_config(config).await
+    }
+
+    async fn get_account_config(&self, account_id: &AccountId) -> Result<AccountConfig> {
+        self.inner.get_account_config(account_id).await
+    }
+
+    async fn list_account_configs(&self) -> Result<Vec<AccountConfig>> {
+        self.inner.list_account_configs().await
+    }
+
+    async fn delete_account_config(&self, account_id: &AccountId) -> Result<()> {
+        self.inner.delete_account_config(account_id).await
+    }
+
+    async fn save_folder(&self, folder: &Folder) -> Result<()> {
+        self.inner.save_folder(folder).await
+    }
+
+    async fn get_folder(&self, id: &FolderId) -> Result<Folder> {
+        self.inner.get_folder(id).await
+    }
+
+    async fn list_folders(&self, account_id: &AccountId) -> Result<Vec<Folder>> {
+        self.inner.list_folders(account_id).await
+    }
+
+    async fn delete_folder(&self, id: &FolderId) -> Result<()> {
+        self.inner.delete_folder(id).await
+    }
+
+    async fn save_envelope(&self, envelope: &Envelope) -> Result<()> {
+        self.inner
+            .save_envelope(&seal_envelope(&self.key, envelope)?)
+            .await
+    }
+
+    async fn save_envelopes(&self, envelopes: &[Envelope]) -> Result<()> {
+        let sealed = envelopes
+            .iter()
+            .map(|envelope| seal_envelope(&self.key, envelope))
+            .collect::<Result<Vec<_>>>()?;
+        self.inner.save_envelopes(&sealed).await
+    }
+
+    async fn get_envelope(&self, id: &MessageId) -> Result<Envelope> {
+        open_envelope(&self.key, self.inner.get_envelope(id).await?)
+    }
+
+    async fn list_envelopes(&self, folder_id: &FolderId) -> Result<Vec<Envelope>> {
+        self.inner
+            .list_envelopes(folder_id)
+            .await?
+            .into_iter()
+            .map(|envelope| open_envelope(&self.key, envelope))
+            .collect()
+    }
+
+    /// Labels, subjects and addresses are sealed, so the wrapped storage
+    /// can't filter by label or sort by subject or sender.
+    async fn query_envelopes(
+        &self,
+        folder_id: &FolderId,
+        query: &EnvelopeQuery,
+    ) -> Result<Vec<Envelope>> {
+        if query.sort.uses_headers() || query.filter.label.is_some() {
+            return Ok(query.apply(self.list_envelopes(folder_id).await?));
+        }
+        self.inner
+            .query_envelopes(folder_id, query)
+            .await?
+            .into_iter()
+            .map(|envelope| open_envelope(&self.key, envelope))
+            .collect()
+    }
+
+    async fn delete_envelope(&self, id: &MessageId) -> Result<()> {
+        self.inner.delete_envelope(id).await
+    }
+
+    async fn delete_envelopes(&self, ids: &[MessageId]) -> Result<()> {
+        self.inner.delete_envelopes(ids).await
+    }
+
+    async fn update_envelope_flags(&self, id: &MessageId, flags: &[(&str, bool)]) -> Result<()> {
+        self.inner.update_envelope_flags(id, flags).await
+    }
+
+    async fn update_envelope_keywords(
+        &self,
+        id: &MessageId,
+        keywords: &[(&str, bool)],
+    ) -> Result<()> {
+        self.inner.update_envelope_keywords(id, keywords).await
+    }
+
+    async fn save_label(&self, label: &Label) -> Result<()> {
+        self.inner.save_label(label).await
+    }
+
+    async fn list_labels(&self, account_id: &AccountId) -> Result<Vec<Label>> {
+        self.inner.list_labels(account_id).await
+    }
+
+    async fn delete_label(&self, account_id: &AccountId, name: &str) -> Result<()> {
+        self.inner.delete_label(account_id, name).await
+    }
+
+    async fn save_message_part(&self, part: &MessagePart) -> Result<()> {
+        self.inner
+            .save_message_part(&seal_part(&self.key, part)?)
+            .await
+    }
+
+    async fn get_message_part(&self, id: &MessagePartId) -> Result<MessagePart> {
+        open_part(&self.key, self.inner.get_message_part(id).await?)
+    }
+
+    async fn list_message_parts(&self, envelope_id: &MessageId) -> Result<Vec<MessagePart>> {
+        self.inner
+            .list_message_parts(envelope_id)
+            .await?
+            .into_iter()
+            .map(|part| open_part(&self.key, part))
+            .collect()
+    }
+
+    async fn delete_message_part(&self, id: &MessagePartId) -> Result<()> {
+        self.inner.delete_message_part(id).await
+    }
+
+    async fn save_account_metadata(&self, metadata: &AccountMetadata) -> Result<()> {
+        self.inner.save_account_metadata(metadata).await
+    }
+
+    async fn get_account_metadata(&self, account_id: &AccountId) -> Result<AccountMetadata> {
+        self.inner.get_account_metadata(account_id).await
+    }
+
+    async fn save_folder_metadata(&self, metadata: &FolderMetadata) -> Result<()> {
+        self.inner.save_folder_metadata(metadata).await
+    }
+
+    async fn get_folder_metadata(&self, folder_id: &FolderId) -> Result<FolderMetadata> {
+        self.inner.get_folder_metadata(folder_id).await
+    }
+
+    async fn save_outbox_message(&self, message: &OutboxMessage) -> Result<()> {
+        self.inner
+            .save_outbox_message(&seal_outbox_message(&self.key, message)?)
+            .await
+    }
+
+    async fn get_outbox_message(&self, id: &OutboxId) -> Result<OutboxMessage> {
+        open_outbox_message(&self.key, self.inner.get_outbox_message(id).await?)
+    }
+
+    async fn list_outbox_messages(&self, account_id: &AccountId) -> Result<Vec<OutboxMessage>> {
+        self.inner
+            .list_outbox_messages(account_id)
+            .await?
+            .into_iter()
+            .map(|message| open_outbox_message(&self.key, message))
+            .collect()
+    }
+
+    async fn delete_outbox_message(&self, id: &OutboxId) -> Result<()> {
+        self.inner.delete_outbox_message(id).await
+    }
+
+    async fn save_delivery_report(&self, report: &DeliveryReport) -> Result<()> {
+        self.inner.save_delivery_report(report).await
+    }
+
+    async fn list_delivery_reports(
+        &self,
+        original_message_id: &str,
+    ) -> Result<Vec<DeliveryReport>> {
+        self.inner.list_delivery_reports(original_message_id).await
+    }
+
+    async fn save_contact(&self, contact: &Contact) -> Result<()> {
+        self.inner
+            .save_contact(&seal_contact(&self.key, contact)?)
+            .await
+    }
+
+    async fn get_contact(&self, id: &ContactId) -> Result<Contact> {
+        open_contact(&self.key, self.inner.get_contact(id).await?)
+    }
+
+    async fn list_contacts(&self) -> Result<Vec<Contact>> {
+        self.inner
+            .list_contacts()
+            .await?
+            .into_iter()
+            .map(|contact| open_contact(&self.key, contact))
+            .collect()
+    }
+
+    async fn delete_contact(&self, id: &ContactId) -> Result<()> {
+        self.inner.delete_contact(id).await
+    }
+
+    async fn save_rule(&self, rule: &Rule) -> Result<()> {
+        self.inner.save_rule(rule).await
+    }
+
+    async fn list_rules(&self, account_id: &AccountId) -> Result<Vec<Rule>> {
+        self.inner.list_rules(account_id).await
+    }
+
+    async fn delete_rule(&self, id: &RuleId) -> Result<()> {
+        self.inner.delete_rule(id).await
+    }
+
+    async fn add_activity(&self, entry: &ActivityEntry) -> Result<()> {
+        self.inner.add_activity(entry).await
+    }
+
+    async fn list_activity(&self, account_id: &AccountId) -> Result<Vec<ActivityEntry>> {
+        self.inner.list_activity(account_id).await
+    }
+
+    /// The wrapped storage can't match sealed contacts, so this searches all
+    /// of them here.
+    async fn search_contacts(&self, prefix: &str, limit: usize) -> Result<Vec<Contact>> {
+        Ok(contacts::search(self.list_contacts().await?, prefix, limit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::models::EmailAddr;
+
+    fn envelope() -> Envelope {
+        Envelope {
+            id: MessageId::new("INBOX;UID=7"),
+            account_id: AccountId::new("imap-jan"),
+            folder_id: FolderId::new("INBOX"),
+            subject: Some("Salary review".to_string()),
+            from: Some(EmailAddress::List(vec![EmailAddr {
+                name: Some("Ana".to_string()),
+                email: Some("ana@example.com".to_string()),
+            }])),
+            to: None,
+            cc: None,
+            bcc: None,
+            reply_to: None,
+            date: Utc::now(),
+            is_read: false,
+            is_starred: false,
+            is_flagged: true,
+            is_draft: false,
+            is_deleted: false,
+            has_attachments: false,
+            size: None,
+            keywords: BTreeSet::new(),
+            labels: BTreeSet::from(["Personal".to_string()]),
+            snoozed_until: None,
+            thread_id: None,
+            remote_id: None,
+            message_id: Some("1@example.com".to_string()),
+            in_reply_to: Vec::new(),
+            references: Vec::new(),
+            unsubscribe: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn seals_envelopes_and_parts() {
+        let key = StorageKey::from_password("correct horse", &[7; SALT_LEN]);
+        let envelope = envelope();
+        let sealed = seal_envelope(&key, &envelope).unwrap();
+        let subject = sealed.subject.as_deref().unwrap();
+        assert!(subject.starts_with("mlnrenc1:") && !subject.contains("Salary"));
+        assert!(sealed.from.is_none() && sealed.labels.is_empty() && sealed.is_flagged);
+
+        let opened = open_envelope(&key, sealed.clone()).unwrap();
+        assert_eq!(opened.subject, envelope.subject);
+        assert_eq!(opened.labels, envelope.labels);
+        assert_eq!(opened.message_id, envelope.message_id);
+        assert!(
+            matches!(opened.from, Some(EmailAddress::List(from)) if from[0].name.as_deref() == Some("Ana"))
+        );
+
+        // A different key, or a record under another id, doesn't open.
+        let other = StorageKey::from_password("wrong horse", &[7; SALT_LEN]);
+        assert!(open_envelope(&other, sealed.clone()).is_err());
+        let moved = Envelope {
+            id: MessageId::new("INBOX;UID=8"),
+            ..sealed
+        };
+        assert!(open_envelope(&key, moved).is_err());
+        // Plain records from before encryption are read as they are.
+        assert_eq!(
+            open_envelope(&key, envelope.clone()).unwrap().subject,
+            envelope.subject
+        );
+
+        let part = MessagePart {
+            id: MessagePartId::new("INBOX;UID=7/2"),
+            envelope_id: envelope.id.clone(),
+            content_type: "text/plain".to_string(),
+            filename: Some("review.txt".to_string()),
+            size: 5,
+            is_attachment: true,
+            content: MessageContent::Text("Hello".to_string()),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        let sealed = seal_part(&key, &part).unwrap();
+        assert!(sealed.filename.is_none());
+        assert!(
+            matches!(&sealed.content, MessageContent::Binary(data) if data.starts_with(PREFIX))
+        );
+        let opened = open_part(&key, sealed).unwrap();
+        assert_eq!(opened.filename.as_deref(), Some("review.txt"));
+        assert!(matches!(opened.content, MessageContent::Text(text) if text == "Hello"));
+
+        let mut contact = Contact::new("ana@example.com", Utc::now());
+        contact.names.push("Ana".to_string());
+        let sealed = seal_contact(&key, &contact).unwrap();
+        assert!(sealed.addresses.is_empty() && !sealed.names[0].contains("Ana"));
+        assert_eq!(open_contact(&key, sealed).unwrap(), contact);
+    }
+}
@@ -0,0 +1,467 @@
+//! A [`Storage`] kept in the files of a [`FileSystem`], so that it outlives
+//! the process: in the data directory natively, in `localStorage` in the
+//! browser.
+//!
+//! [`FileStorage`] keeps the data in memory and writes each kind of record,
+//! e.g. the contacts, to a JSON file of its own when one of them changes.
+//! Opening it reads the files back, so a process opening the directory
+//! after another one wrote to it, e.g. the app after the sync daemon, starts
+//! with what the other one left.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::{broadcast, Mutex, RwLock};
+
+use crate::activity::ActivityEntry;
+use crate::contacts::Contact;
+use crate::delivery::DeliveryReport;
+use crate::error::{MailinerError, Result};
+use crate::ids::{AccountId, ContactId, FolderId, MessageId, MessagePartId, OutboxId, RuleId};
+use crate::models::{
+    Account, AccountConfig, AccountMetadata, AccountPreferences, Envelope, EnvelopeQuery, Folder,
+    FolderMetadata, Label, MessagePart, OutboxMessage, SearchQuery,
+};
+use crate::platform::FileSystem;
+use crate::rules::Rule;
+use crate::storage::{InMemoryStorage, Storage, StorageEvent};
+
+/// The directory of the storage in the platform's files, shared by the app
+/// and the sync daemon.
+pub const STORAGE_DIR: &str = "storage";
+
+/// A kind of record, kept in a file of its own.
+#[derive(Debug, Clone, Copy)]
+enum Table {
+    Accounts,
+    AccountPreferences,
+    AccountConfigs,
+    Folders,
+    Labels,
+    Envelopes,
+    MessageParts,
+    AccountMetadata,
+    FolderMetadata,
+    Outbox,
+    DeliveryReports,
+    Contacts,
+    Rules,
+    Activity,
+}
+
+impl Table {
+    fn file_name(self) -> &'static str {
+        match self {
+            Table::Accounts => "accounts.json",
+            Table::AccountPreferences => "account_preferences.json",
+            Table::AccountConfigs => "account_configs.json",
+            Table::Folders => "folders.json",
+            Table::Labels => "labels.json",
+            Table::Envelopes => "envelopes.json",
+            Table::MessageParts => "message_parts.json",
+            Table::AccountMetadata => "account_metadata.json",
+            Table::FolderMetadata => "folder_metadata.json",
+            Table::Outbox => "outbox.json",
+            Table::DeliveryReports => "delivery_reports.json",
+            Table::Contacts => "contacts.json",
+            Table::Rules => "rules.json",
+            Table::Activity => "activity.json",
+        }
+    }
+}
+
+pub struct FileStorage {
+    inner: InMemoryStorage,
+    fs: Arc<dyn FileSystem>,
+    /// The directory of the files, relative to the root of `fs`.
+    dir: String,
+    /// Held while a file is written, so that an older state of a table
+    /// can't be written after a newer one.
+    writing: Mutex<()>,
+}
+
+impl FileStorage {
+    /// Opens the storage kept in `dir`, reading what was saved there
+    /// before. A new directory is an empty storage.
+    pub async fn open(fs: Arc<dyn FileSystem>, dir: impl Into<String>) -> Result<Self> {
+        let storage = Self {
+            inner: InMemoryStorage::new(),
+            fs,
+            dir: dir.into(),
+            writing: Mutex::new(()),
+        };
+        let inner = &storage.inner;
+        storage.load(Table::Accounts, &inner.accounts, |a: &Account| a.id.clone()).await?;
+        storage
+            .load(Table::AccountPreferences, &inner.account_preferences, |p: &AccountPreferences| {
+                p.account_id.clone()
+            })
+            .await?;
+        storage
+            .load(Table::AccountConfigs, &inner.account_configs, |c: &AccountConfig| c.account_id.clone())
+            .await?;
+        storage.load(Table::Folders, &inner.folders, |f: &Folder| f.id.clone()).await?;
+        storage
+            .load(Table::Labels, &inner.labels, |l: &Label| (l.account_id.clone(), l.name.clone()))
+            .await?;
+        storage.load(Table::Envelopes, &inner.envelopes, |e: &Envelope| e.id.clone()).await?;
+        storage.load(Table::MessageParts, &inner.message_parts, |p: &MessagePart| p.id.clone()).await?;
+        storage.load(Table::AccountMetadata, &inner.account_metadata, |m: &AccountMetadata| m.id.clone()).await?;
+        storage.load(Table::FolderMetadata, &inner.folder_metadata, |m: &FolderMetadata| m.id.clone()).await?;
+        storage.load(Table::Outbox, &inner.outbox, |m: &OutboxMessage| m.id.clone()).await?;
+        storage.load(Table::DeliveryReports, &inner.delivery_reports, |r: &DeliveryReport| r.id.clone()).await?;
+        storage.load(Table::Contacts, &inner.contacts, |c: &Contact| c.id.clone()).await?;
+        storage.load(Table::Rules, &inner.rules, |r: &Rule| r.id.clone()).await?;
+        *inner.activity.write().await = storage.read(Table::Activity).await?;
+        Ok(storage)
+    }
+
+    fn path(&self, table: Table) -> String {
+        format!("{}/{}", self.dir, table.file_name())
+    }
+
+    /// The records saved in the file of `table`, none if there is no file.
+    async fn read<V: DeserializeOwned>(&self, table: Table) -> Result<Vec<V>> {
+        match self.fs.read(&self.path(table)).await {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(MailinerError::NotFound(_)) => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn load<K, V>(&self, table: Table, map: &RwLock<HashMap<K, V>>, key: impl Fn(&V) -> K) -> Result<()>
+    where
+        K: Eq + Hash,
+        V: DeserializeOwned,
+    {
+        let records: Vec<V> = self.read(table).await?;
+        *map.write().await = records.into_iter().map(|record| (key(&record), record)).collect();
+        Ok(())
+    }
+
+    /// Writes the records of `table` after they changed.
+    async fn persist(&self, table: Table) -> Result<()> {
+        let _writing = self.writing.lock().await;
+        let inner = &self.inner;
+        let data = match table {
+            Table::Accounts => to_json(&inner.accounts).await?,
+            Table::AccountPreferences => to_json(&inner.account_preferences).await?,
+            Table::AccountConfigs => to_json(&inner.account_configs).await?,
+            Table::Folders => to_json(&inner.folders).await?,
+            Table::Labels => to_json(&inner.labels).await?,
+            Table::Envelopes => to_json(&inner.envelopes).await?,
+            Table::MessageParts => to_json(&inner.message_parts).await?,
+            Table::AccountMetadata => to_json(&inner.account_metadata).await?,
+            Table::FolderMetadata => to_json(&inner.folder_metadata).await?,
+            Table::Outbox => to_json(&inner.outbox).await?,
+            Table::DeliveryReports => to_json(&inner.delivery_reports).await?,
+            Table::Contacts => to_json(&inner.contacts).await?,
+            Table::Rules => to_json(&inner.rules).await?,
+            Table::Activity => serde_json::to_vec(&*inner.activity.read().await)?,
+        };
+        self.fs.write(&self.path(table), &data).await
+    }
+
+    /// Writes `table` if `changed` succeeded, and passes on its error if not.
+    async fn persisted(&self, table: Table, changed: Result<()>) -> Result<()> {
+        changed?;
+        self.persist(table).await
+    }
+}
+
+async fn to_json<K, V: Serialize>(map: &RwLock<HashMap<K, V>>) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec(&map.read().await.values().collect::<Vec<_>>())?)
+}
+
+#[async_trait]
+impl Storage for FileStorage {
+    fn subscribe(&self) -> broadcast::Receiver<StorageEvent> {
+        self.inner.subscribe()
+    }
+
+    async fn save_account(&self, account: &Account) -> Result<()> {
+        self.persisted(Table::Accounts, self.inner.save_account(account).await).await
+    }
+
+    async fn get_account(&self, id: &AccountId) -> Result<Account> {
+        self.inner.get_account(id).await
+    }
+
+    async fn list_accounts(&self) -> Result<Vec<Account>> {
+        self.inner.list_accounts().await
+    }
+
+    async fn delete_account(&self, id: &AccountId) -> Result<()> {
+        self.persisted(Table::Accounts, self.inner.delete_account(id).await).await
+    }
+
+    async fn save_account_preferences(&self, preferences: &AccountPreferences) -> Result<()> {
+        self.persisted(Table::AccountPreferences, self.inner.save_account_preferences(preferences).await)
+            .await
+    }
+
+    async fn get_account_preferences(&self, account_id: &AccountId) -> Result<AccountPreferences> {
+        self.inner.get_account_preferences(account_id).await
+    }
+
+    async fn save_account_config(&self, config: &AccountConfig) -> Result<()> {
+        self.persisted(Table::AccountConfigs, self.inner.save_account_config(config).await).await
+    }
+
+    async fn get_account_config(&self, account_id: &AccountId) -> Result<AccountConfig> {
+        self.inner.get_account_config(account_id).await
+    }
+
+    async fn list_account_configs(&self) -> Result<Vec<AccountConfig>> {
+        self.inner.list_account_configs().await
+    }
+
+    async fn delete_account_config(&self, account_id: &AccountId) -> Result<()> {
+        self.persisted(Table::AccountConfigs, self.inner.delete_account_config(account_id).await).await
+    }
+
+    async fn save_folder(&self, folder: &Folder) -> Result<()> {
+        self.persisted(Table::Folders, self.inner.save_folder(folder).await).await
+    }
+
+    async fn get_folder(&self, id: &FolderId) -> Result<Folder> {
+        self.inner.get_folder(id).await
+    }
+
+    async fn list_folders(&self, account_id: &AccountId) -> Result<Vec<Folder>> {
+        self.inner.list_folders(account_id).await
+    }
+
+    async fn delete_folder(&self, id: &FolderId) -> Result<()> {
+        self.persisted(Table::Folders, self.inner.delete_folder(id).await).await
+    }
+
+    async fn save_envelope(&self, envelope: &Envelope) -> Result<()> {
+        self.persisted(Table::Envelopes, self.inner.save_envelope(envelope).await).await
+    }
+
+    async fn save_envelopes(&self, envelopes: &[Envelope]) -> Result<()> {
+        self.persisted(Table::Envelopes, self.inner.save_envelopes(envelopes).await).await
+    }
+
+    async fn get_envelope(&self, id: &MessageId) -> Result<Envelope> {
+        self.inner.get_envelope(id).await
+    }
+
+    async fn list_envelopes(&self, folder_id: &FolderId) -> Result<Vec<Envelope>> {
+        self.inner.list_envelopes(folder_id).await
+    }
+
+    async fn query_envelopes(&self, folder_id: &FolderId, query: &EnvelopeQuery) -> Result<Vec<Envelope>> {
+        self.inner.query_envelopes(folder_id, query).await
+    }
+
+    async fn delete_envelope(&self, id: &MessageId) -> Result<()> {
+        self.persisted(Table::Envelopes, self.inner.delete_envelope(id).await).await
+    }
+
+    async fn delete_envelopes(&self, ids: &[MessageId]) -> Result<()> {
+        self.persisted(Table::Envelopes, self.inner.delete_envelopes(ids).await).await
+    }
+
+    async fn update_envelope_flags(&self, id: &MessageId, flags: &[(&str, bool)]) -> Result<()> {
+        self.persisted(Table::Envelopes, self.inner.update_envelope_flags(id, flags).await).await
+    }
+
+    async fn update_envelope_keywords(&self, id: &MessageId, keywords: &[(&str, bool)]) -> Result<()> {
+        self.persisted(Table::Envelopes, self.inner.update_envelope_keywords(id, keywords).await).await
+    }
+
+    async fn update_envelope_labels(&self, id: &MessageId, labels: &[(&str, bool)]) -> Result<()> {
+        self.persisted(Table::Envelopes, self.inner.update_envelope_labels(id, labels).await).await
+    }
+
+    async fn save_label(&self, label: &Label) -> Result<()> {
+        self.persisted(Table::Labels, self.inner.save_label(label).await).await
+    }
+
+    async fn list_labels(&self, account_id: &AccountId) -> Result<Vec<Label>> {
+        self.inner.list_labels(account_id).await
+    }
+
+    async fn delete_label(&self, account_id: &AccountId, name: &str) -> Result<()> {
+        self.persisted(Table::Labels, self.inner.delete_label(account_id, name).await).await
+    }
+
+    async fn save_message_part(&self, part: &MessagePart) -> Result<()> {
+        self.persisted(Table::MessageParts, self.inner.save_message_part(part).await).await
+    }
+
+    async fn get_message_part(&self, id: &MessagePartId) -> Result<MessagePart> {
+        self.inner.get_message_part(id).await
+    }
+
+    async fn list_message_parts(&self, envelope_id: &MessageId) -> Result<Vec<MessagePart>> {
+        self.inner.list_message_parts(envelope_id).await
+    }
+
+    async fn delete_message_part(&self, id: &MessagePartId) -> Result<()> {
+        self.persisted(Table::MessageParts, self.inner.delete_message_part(id).await).await
+    }
+
+    async fn save_account_metadata(&self, metadata: &AccountMetadata) -> Result<()> {
+        self.persisted(Table::AccountMetadata, self.inner.save_account_metadata(metadata).await).await
+    }
+
+    async fn get_account_metadata(&self, account_id: &AccountId) -> Result<AccountMetadata> {
+        self.inner.get_account_metadata(account_id).await
+    }
+
+    async fn save_folder_metadata(&self, metadata: &FolderMetadata) -> Result<()> {
+        self.persisted(Table::FolderMetadata, self.inner.save_folder_metadata(metadata).await).await
+    }
+
+    async fn get_folder_metadata(&self, folder_id: &FolderId) -> Result<FolderMetadata> {
+        self.inner.get_folder_metadata(folder_id).await
+    }
+
+    async fn save_outbox_message(&self, message: &OutboxMessage) -> Result<()> {
+        self.persisted(Table::Outbox, self.inner.save_outbox_message(message).await).await
+    }
+
+    async fn get_outbox_message(&self, id: &OutboxId) -> Result<OutboxMessage> {
+        self.inner.get_outbox_message(id).await
+    }
+
+    async fn list_outbox_messages(&self, account_id: &AccountId) -> Result<Vec<OutboxMessage>> {
+        self.inner.list_outbox_messages(account_id).await
+    }
+
+    async fn delete_outbox_message(&self, id: &OutboxId) -> Result<()> {
+        self.persisted(Table::Outbox, self.inner.delete_outbox_message(id).await).await
+    }
+
+    async fn save_delivery_report(&self, report: &DeliveryReport) -> Result<()> {
+        self.persisted(Table::DeliveryReports, self.inner.save_delivery_report(report).await).await
+    }
+
+    async fn list_delivery_reports(&self, original_message_id: &str) -> Result<Vec<DeliveryReport>> {
+        self.inner.list_delivery_reports(original_message_id).await
+    }
+
+    async fn save_contact(&self, contact: &Contact) -> Result<()> {
+        self.persisted(Table::Contacts, self.inner.save_contact(contact).await).await
+    }
+
+    async fn get_contact(&self, id: &ContactId) -> Result<Contact> {
+        self.inner.get_contact(id).await
+    }
+
+    async fn list_contacts(&self) -> Result<Vec<Contact>> {
+        self.inner.list_contacts().await
+    }
+
+    async fn delete_contact(&self, id: &ContactId) -> Result<()> {
+        self.persisted(Table::Contacts, self.inner.delete_contact(id).await).await
+    }
+
+    async fn search_contacts(&self, prefix: &str, limit: usize) -> Result<Vec<Contact>> {
+        self.inner.search_contacts(prefix, limit).await
+    }
+
+    async fn save_rule(&self, rule: &Rule) -> Result<()> {
+        self.persisted(Table::Rules, self.inner.save_rule(rule).await).await
+    }
+
+    async fn list_rules(&self, account_id: &AccountId) -> Result<Vec<Rule>> {
+        self.inner.list_rules(account_id).await
+    }
+
+    async fn delete_rule(&self, id: &RuleId) -> Result<()> {
+        self.persisted(Table::Rules, self.inner.delete_rule(id).await).await
+    }
+
+    async fn add_activity(&self, entry: &ActivityEntry) -> Result<()> {
+        self.persisted(Table::Activity, self.inner.add_activity(entry).await).await
+    }
+
+    async fn list_activity(&self, account_id: &AccountId) -> Result<Vec<ActivityEntry>> {
+        self.inner.list_activity(account_id).await
+    }
+
+    async fn search(&self, folder_id: &FolderId, query: &SearchQuery) -> Result<Vec<MessageId>> {
+        self.inner.search(folder_id, query).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex as StdMutex;
+
+    use chrono::Utc;
+
+    use super::*;
+
+    /// Files kept in memory, shared by the storages opened on it.
+    #[derive(Default)]
+    struct MemoryFileSystem {
+        files: StdMutex<HashMap<String, Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl FileSystem for MemoryFileSystem {
+        async fn read(&self, path: &str) -> Result<Vec<u8>> {
+            self.files
+                .lock()
+                .unwrap()
+                .get(path)
+                .cloned()
+                .ok_or_else(|| MailinerError::NotFound(format!("File {}", path)))
+        }
+
+        async fn write(&self, path: &str, data: &[u8]) -> Result<()> {
+            self.files.lock().unwrap().insert(path.to_string(), data.to_vec());
+            Ok(())
+        }
+
+        async fn remove(&self, path: &str) -> Result<()> {
+            self.files.lock().unwrap().remove(path);
+            Ok(())
+        }
+
+        async fn exists(&self, path: &str) -> Result<bool> {
+            Ok(self.files.lock().unwrap().contains_key(path))
+        }
+
+        async fn list(&self, _path: &str) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_records_across_opening() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        let storage = FileStorage::open(fs.clone(), "storage").await.unwrap();
+        assert!(storage.list_contacts().await.unwrap().is_empty());
+
+        let mut jan = Contact::new("jan@example.com", Utc::now());
+        storage.save_contact(&jan).await.unwrap();
+        let eva = Contact::new("eva@example.com", Utc::now());
+        storage.save_contact(&eva).await.unwrap();
+        jan.names.push("Honza".to_string());
+        storage.save_contact(&jan).await.unwrap();
+        storage.delete_contact(&eva.id).await.unwrap();
+        assert!(fs.exists("storage/contacts.json").await.unwrap());
+
+        let reopened = FileStorage::open(fs, "storage").await.unwrap();
+        assert_eq!(reopened.list_contacts().await.unwrap(), vec![jan]);
+        assert!(reopened.get_contact(&eva.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_changes_are_not_written() {
+        let fs = Arc::new(MemoryFileSystem::default());
+        let storage = FileStorage::open(fs.clone(), "storage").await.unwrap();
+        assert!(storage.delete_rule(&RuleId::new("missing")).await.is_err());
+        assert!(!fs.exists("storage/rules.json").await.unwrap());
+    }
+}
@@ -0,0 +1,299 @@
+//! A [`Storage`] that keeps a [`SearchIndex`] up to date with what is saved
+//! in it, so that [`Storage::search`] finds words in the cached bodies too.
+
+use async_trait::async_trait;
+use tokio::sync::broadcast;
+
+use crate::activity::ActivityEntry;
+use crate::compose::plaintext::html_to_text;
+use crate::contacts::Contact;
+use crate::delivery::DeliveryReport;
+use crate::error::{MailinerError, Result};
+use crate::ids::{AccountId, ContactId, FolderId, MessageId, MessagePartId, OutboxId, RuleId};
+use crate::models::{
+    Account, AccountConfig, AccountMetadata, AccountPreferences, Envelope, EnvelopeQuery, Folder,
+    FolderMetadata, Label, MessageContent, MessagePart, OutboxMessage, SearchQuery,
+};
+use crate::rules::Rule;
+use crate::search::{SearchIndex, TextQuery};
+use crate::storage::{Storage, StorageEvent};
+
+pub struct IndexedStorage<S: Storage, I: SearchIndex> {
+    inner: S,
+    index: I,
+}
+
+impl<S: Storage, I: SearchIndex> IndexedStorage<S, I> {
+    /// Wraps `inner`, whose envelopes have to be indexed already, e.g. when
+    /// both start out empty.
+    pub fn new(inner: S, index: I) -> Self {
+        Self { inner, index }
+    }
+
+    pub fn index(&self) -> &I {
+        &self.index
+    }
+}
+
+#[async_trait]
+impl<S: Storage, I: SearchIndex> Storage for IndexedStorage<S, I> {
+    fn subscribe(&self) -> broadcast::Receiver<StorageEvent> {
+        self.inner.subscribe()
+    }
+
+    async fn save_account(&self, account: &Account) -> Result<()> {
+        self.inner.save_account(account).await
+    }
+
+    async fn get_account(&self, id: &AccountId) -> Result<Account> {
+        self.inner.get_account(id).await
+    }
+
+    async fn list_accounts(&self) -> Result<Vec<Account>> {
+        self.inner.list_accounts().await
+    }
+
+    async fn delete_account(&self, id: &AccountId) -> Result<()> {
+        self.inner.delete_account(id).await
+    }
+
+    async fn save_account_preferences(&self, preferences: &AccountPreferences) -> Result<()> {
+        self.inner.save_account_preferences(preferences).await
+    }
+
+    async fn get_account_preferences(&self, account_id: &AccountId) -> Result<AccountPreferences> {
+        self.inner.get_account_preferences(account_id).await
+    }
+
+    async fn save_account_config(&self, config: &AccountConfig) -> Result<()> {
+        self.inner.save_account_config(config).await
+    }
+
+    async fn get_account_config(&self, account_id: &AccountId) -> Result<AccountConfig> {
+        self.inner.get_account_config(account_id).await
+    }
+
+    async fn list_account_configs(&self) -> Result<Vec<AccountConfig>> {
+        self.inner.list_account_configs().await
+    }
+
+    async fn delete_account_config(&self, account_id: &AccountId) -> Result<()> {
+        self.inner.delete_account_config(account_id).await
+    }
+
+    async fn save_folder(&self, folder: &Folder) -> Result<()> {
+        self.inner.save_folder(folder).await
+    }
+
+    async fn get_folder(&self, id: &FolderId) -> Result<Folder> {
+        self.inner.get_folder(id).await
+    }
+
+    async fn list_folders(&self, account_id: &AccountId) -> Result<Vec<Folder>> {
+        self.inner.list_folders(account_id).await
+    }
+
+    async fn delete_folder(&self, id: &FolderId) -> Result<()> {
+        self.inner.delete_folder(id).await
+    }
+
+    async fn save_envelope(&self, envelope: &Envelope) -> Result<()> {
+        self.inner.save_envelope(envelope).await?;
+        self.index.index_envelope(envelope).await
+    }
+
+    async fn save_envelopes(&self, envelopes: &[Envelope]) -> Result<()> {
+        self.inner.save_envelopes(envelopes).await?;
+        for envelope in envelopes {
+            self.index.index_envelope(envelope).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_envelope(&self, id: &MessageId) -> Result<Envelope> {
+        self.inner.get_envelope(id).await
+    }
+
+    async fn list_envelopes(&self, folder_id: &FolderId) -> Result<Vec<Envelope>> {
+        self.inner.list_envelopes(folder_id).await
+    }
+
+    async fn query_envelopes(
+        &self,
+        folder_id: &FolderId,
+        query: &EnvelopeQuery,
+    ) -> Result<Vec<Envelope>> {
+        self.inner.query_envelopes(folder_id, query).await
+    }
+
+    async fn delete_envelope(&self, id: &MessageId) -> Result<()> {
+        self.inner.delete_envelope(id).await?;
+        self.index.remove(id).await
+    }
+
+    async fn delete_envelopes(&self, ids: &[MessageId]) -> Result<()> {
+        self.inner.delete_envelopes(ids).await?;
+        for id in ids {
+            self.index.remove(id).await?;
+        }
+        Ok(())
+    }
+
+    async fn update_envelope_flags(&self, id: &MessageId, flags: &[(&str, bool)]) -> Result<()> {
+        self.inner.update_envelope_flags(id, flags).await
+    }
+
+    async fn update_envelope_keywords(
+        &self,
+        id: &MessageId,
+        keywords: &[(&str, bool)],
+    ) -> Result<()> {
+        self.inner.update_envelope_keywords(id, keywords).await
+    }
+
+    async fn update_envelope_labels(&self, id: &MessageId, labels: &[(&str, bool)]) -> Result<()> {
+        self.inner.update_envelope_labels(id, labels).await
+    }
+
+    async fn save_label(&self, label: &Label) -> Result<()> {
+        self.inner.save_label(label).await
+    }
+
+    async fn list_labels(&self, account_id: &AccountId) -> Result<Vec<Label>> {
+        self.inner.list_labels(account_id).await
+    }
+
+    async fn delete_label(&self, account_id: &AccountId, name: &str) -> Result<()> {
+        self.inner.delete_label(account_id, name).await
+    }
+
+    /// Indexes the text of body parts, attachments aren't.
+    async fn save_message_part(&self, part: &MessagePart) -> Result<()> {
+        self.inner.save_message_part(part).await?;
+        if part.is_attachment {
+            return Ok(());
+        }
+        match &part.content {
+            MessageContent::Text(text) => self.index.index_body(&part.envelope_id, text).await,
+            MessageContent::Html(html) => {
+                self.index
+                    .index_body(&part.envelope_id, &html_to_text(html))
+                    .await
+            }
+            MessageContent::Binary(_) => Ok(()),
+        }
+    }
+
+    async fn get_message_part(&self, id: &MessagePartId) -> Result<MessagePart> {
+        self.inner.get_message_part(id).await
+    }
+
+    async fn list_message_parts(&self, envelope_id: &MessageId) -> Result<Vec<MessagePart>> {
+        self.inner.list_message_parts(envelope_id).await
+    }
+
+    async fn delete_message_part(&self, id: &MessagePartId) -> Result<()> {
+        self.inner.delete_message_part(id).await
+    }
+
+    async fn save_account_metadata(&self, metadata: &AccountMetadata) -> Result<()> {
+        self.inner.save_account_metadata(metadata).await
+    }
+
+    async fn get_account_metadata(&self, account_id: &AccountId) -> Result<AccountMetadata> {
+        self.inner.get_account_metadata(account_id).await
+    }
+
+    async fn save_folder_metadata(&self, metadata: &FolderMetadata) -> Result<()> {
+        self.inner.save_folder_metadata(metadata).await
+    }
+
+    async fn get_folder_metadata(&self, folder_id: &FolderId) -> Result<FolderMetadata> {
+        self.inner.get_folder_metadata(folder_id).await
+    }
+
+    async fn save_outbox_message(&self, message: &OutboxMessage) -> Result<()> {
+        self.inner.save_outbox_message(message).await
+    }
+
+    async fn get_outbox_message(&self, id: &OutboxId) -> Result<OutboxMessage> {
+        self.inner.get_outbox_message(id).await
+    }
+
+    async fn list_outbox_messages(&self, account_id: &AccountId) -> Result<Vec<OutboxMessage>> {
+        self.inner.list_outbox_messages(account_id).await
+    }
+
+    async fn delete_outbox_message(&self, id: &OutboxId) -> Result<()> {
+        self.inner.delete_outbox_message(id).await
+    }
+
+    async fn save_delivery_report(&self, report: &DeliveryReport) -> Result<()> {
+        self.inner.save_delivery_report(report).await
+    }
+
+    async fn list_delivery_reports(
+        &self,
+        original_message_id: &str,
+    ) -> Result<Vec<DeliveryReport>> {
+        self.inner.list_delivery_reports(original_message_id).await
+    }
+
+    async fn save_contact(&self, contact: &Contact) -> Result<()> {
+        self.inner.save_contact(contact).await
+    }
+
+    async fn get_contact(&self, id: &ContactId) -> Result<Contact> {
+        self.inner.get_contact(id).await
+    }
+
+    async fn list_contacts(&self) -> Result<Vec<Contact>> {
+        self.inner.list_contacts().await
+    }
+
+    async fn delete_contact(&self, id: &ContactId) -> Result<()> {
+        self.inner.delete_contact(id).await
+    }
+
+    async fn save_rule(&self, rule: &Rule) -> Result<()> {
+        self.inner.save_rule(rule).await
+    }
+
+    async fn list_rules(&self, account_id: &AccountId) -> Result<Vec<Rule>> {
+        self.inner.list_rules(account_id).await
+    }
+
+    async fn delete_rule(&self, id: &RuleId) -> Result<()> {
+        self.inner.delete_rule(id).await
+    }
+
+    async fn add_activity(&self, entry: &ActivityEntry) -> Result<()> {
+        self.inner.add_activity(entry).await
+    }
+
+    async fn list_activity(&self, account_id: &AccountId) -> Result<Vec<ActivityEntry>> {
+        self.inner.list_activity(account_id).await
+    }
+
+    async fn search_contacts(&self, prefix: &str, limit: usize) -> Result<Vec<Contact>> {
+        self.inner.search_contacts(prefix, limit).await
+    }
+
+    /// Looks the words up in the index, then checks the other criteria on
+    /// the envelopes found.
+    async fn search(&self, folder_id: &FolderId, query: &SearchQuery) -> Result<Vec<MessageId>> {
+        let Some(text) = TextQuery::from_search(query) else {
+            return self.inner.search(folder_id, query).await;
+        };
+        let mut envelopes = Vec::new();
+        for id in self.index.search(folder_id, &text).await? {
+            match self.inner.get_envelope(&id).await {
+                Ok(envelope) if query.matches(&envelope) => envelopes.push(envelope),
+                Ok(_) | Err(MailinerError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        envelopes.sort_by_key(|envelope| std::cmp::Reverse(envelope.date));
+        Ok(envelopes.into_iter().map(|envelope| envelope.id).collect())
+    }
+}
@@ -0,0 +1,171 @@
+//! Versioned schema migrations for persistent storage backends.
+//!
+//! A backend keeps the version of its schema next to the data, e.g. in
+//! SQLite's `user_version` or as the version of an IndexedDB database, and
+//! lists a [`Migration`] for every version after the first. On opening,
+//! [`Migrator::migrate`] applies the ones the store hasn't seen yet, so
+//! that model changes don't require wiping the user's cache.
+//!
+//! New optional fields don't need a migration, records without them are read
+//! with the `#[serde(default)]` of the field.
+
+use std::fmt;
+
+use async_trait::async_trait;
+
+use crate::error::{MailinerError, Result};
+
+/// Reads and writes the schema version of a store. A new, empty store is at
+/// version 0.
+#[async_trait]
+pub trait SchemaVersion: Send + Sync {
+    async fn schema_version(&self) -> Result<u32>;
+    async fn set_schema_version(&self, version: u32) -> Result<()>;
+}
+
+/// Brings a store of type `T` from `version - 1` to `version`.
+#[async_trait]
+pub trait Migration<T: ?Sized>: Send + Sync {
+    fn version(&self) -> u32;
+    /// What the migration changes, for logs.
+    fn description(&self) -> &str;
+    async fn apply(&self, store: &T) -> Result<()>;
+}
+
+/// The migrations of a backend, by version.
+pub struct Migrator<T: ?Sized> {
+    migrations: Vec<Box<dyn Migration<T>>>,
+}
+
+impl<T: ?Sized> fmt::Debug for Migrator<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.migrations
+                    .iter()
+                    .map(|m| (m.version(), m.description())),
+            )
+            .finish()
+    }
+}
+
+impl<T: ?Sized> Default for Migrator<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: ?Sized> Migrator<T> {
+    pub fn new() -> Self {
+        Self {
+            migrations: Vec::new(),
+        }
+    }
+
+    /// Adds the migration to the next version.
+    ///
+    /// # Panics
+    ///
+    /// If the migration isn't for the version after the last one added,
+    /// starting with 1.
+    pub fn with(mut self, migration: impl Migration<T> + 'static) -> Self {
+        assert_eq!(
+            migration.version(),
+            self.latest_version() + 1,
+            "migration \"{}\" is out of order",
+            migration.description()
+        );
+        self.migrations.push(Box::new(migration));
+        self
+    }
+
+    /// The version of a store with all migrations applied.
+    pub fn latest_version(&self) -> u32 {
+        self.migrations.len() as u32
+    }
+
+    /// The migrations a store at `version` still needs, in order.
+    fn pending(&self, version: u32) -> Result<&[Box<dyn Migration<T>>]> {
+        self.migrations.get(version as usize..).ok_or_else(|| {
+            MailinerError::Storage(format!(
+                "The cache is at version {}, newer than this version of Mailiner ({})",
+                version,
+                self.latest_version()
+            ))
+        })
+    }
+}
+
+impl<T: ?Sized + SchemaVersion> Migrator<T> {
+    /// Applies the migrations `store` hasn't seen yet and returns the new
+    /// version. The version is saved after every migration, so that an
+    /// interrupted upgrade carries on where it stopped.
+    pub async fn migrate(&self, store: &T) -> Result<u32> {
+        let mut version = store.schema_version().await?;
+        for migration in self.pending(version)? {
+            migration.apply(store).await.map_err(|e| {
+                MailinerError::Storage(format!(
+                    "Migration to version {} ({}) failed: {}",
+                    migration.version(),
+                    migration.description(),
+                    e
+                ))
+            })?;
+            version = migration.version();
+            store.set_schema_version(version).await?;
+        }
+        Ok(version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Store;
+
+    #[async_trait]
+    impl SchemaVersion for Store {
+        async fn schema_version(&self) -> Result<u32> {
+            Ok(0)
+        }
+
+        async fn set_schema_version(&self, _version: u32) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Step(u32, &'static str);
+
+    #[async_trait]
+    impl Migration<Store> for Step {
+        fn version(&self) -> u32 {
+            self.0
+        }
+
+        fn description(&self) -> &str {
+            self.1
+        }
+
+        async fn apply(&self, _store: &Store) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn pending_migrations() {
+        let migrator = Migrator::new()
+            .with(Step(1, "add thread ids"))
+            .with(Step(2, "index labels"));
+        assert_eq!(migrator.latest_version(), 2);
+        let versions = |version| {
+            migrator
+                .pending(version)
+                .map(|pending| pending.iter().map(|m| m.version()).collect::<Vec<_>>())
+        };
+        assert_eq!(versions(0).unwrap(), [1, 2]);
+        assert_eq!(versions(1).unwrap(), [2]);
+        assert!(versions(2).unwrap().is_empty());
+        assert!(matches!(versions(3), Err(MailinerError::Storage(_))));
+    }
+}
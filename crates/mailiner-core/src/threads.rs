@@ -0,0 +1,126 @@
+//! Conversations: the messages that reply to each other.
+
+use std::collections::HashSet;
+
+use crate::ids::MessageId;
+use crate::models::Envelope;
+
+/// The envelopes of the conversation `message_id` belongs to, oldest first.
+///
+/// Messages are in the same conversation if the server put them in the same
+/// thread, e.g. Gmail, or else if they are linked by their References and
+/// In-Reply-To headers.
+pub fn conversation(envelopes: &[Envelope], message_id: &MessageId) -> Vec<Envelope> {
+    let Some(message) = envelopes.iter().find(|envelope| envelope.id == *message_id) else {
+        return Vec::new();
+    };
+
+    let mut members: Vec<&Envelope> = match &message.thread_id {
+        Some(thread_id) => envelopes
+            .iter()
+            .filter(|envelope| envelope.thread_id.as_ref() == Some(thread_id))
+            .collect(),
+        None => linked(envelopes, message),
+    };
+    members.sort_by_key(|envelope| envelope.date);
+    members.into_iter().cloned().collect()
+}
+
+/// The envelopes linked to `message` through their headers, directly or by
+/// way of the others.
+fn linked<'a>(envelopes: &'a [Envelope], message: &'a Envelope) -> Vec<&'a Envelope> {
+    let mut ids: HashSet<&str> = header_ids(message).collect();
+    let mut members = vec![message];
+    let mut rest: Vec<&Envelope> = envelopes
+        .iter()
+        .filter(|envelope| envelope.id != message.id)
+        .collect();
+
+    // Repeated until nothing joins, since a reply may only link to the
+    // conversation through another reply.
+    loop {
+        let (joined, others): (Vec<&Envelope>, Vec<&Envelope>) = rest
+            .into_iter()
+            .partition(|envelope| header_ids(envelope).any(|id| ids.contains(id)));
+        if joined.is_empty() {
+            return members;
+        }
+        ids.extend(joined.iter().flat_map(|envelope| header_ids(envelope)));
+        members.extend(joined);
+        rest = others;
+    }
+}
+
+fn header_ids(envelope: &Envelope) -> impl Iterator<Item = &str> {
+    envelope
+        .message_id
+        .iter()
+        .chain(&envelope.in_reply_to)
+        .chain(&envelope.references)
+        .map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use super::*;
+    use crate::ids::{AccountId, FolderId};
+
+    fn envelope(id: &str, hours: i64, in_reply_to: Option<&str>) -> Envelope {
+        Envelope {
+            id: MessageId::new(id),
+            account_id: AccountId::new("imap-jan"),
+            folder_id: FolderId::new("INBOX"),
+            subject: Some("Plans".to_string()),
+            from: None,
+            to: None,
+            cc: None,
+            bcc: None,
+            reply_to: None,
+            date: Utc::now() + Duration::hours(hours),
+            is_read: false,
+            is_starred: false,
+            is_flagged: false,
+            is_draft: false,
+            is_deleted: false,
+            has_attachments: false,
+            size: None,
+            keywords: Default::default(),
+            labels: Default::default(),
+            snoozed_until: None,
+            thread_id: None,
+            remote_id: None,
+            message_id: Some(format!("{}@example.com", id)),
+            in_reply_to: in_reply_to.map(|id| format!("{}@example.com", id)).into_iter().collect(),
+            references: Vec::new(),
+            unsubscribe: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn ids(envelopes: Vec<Envelope>) -> Vec<String> {
+        envelopes.into_iter().map(|envelope| envelope.id.to_string()).collect()
+    }
+
+    #[test]
+    fn links_replies() {
+        // c only links to a by way of b, which comes after it in the list.
+        let envelopes = [
+            envelope("c", 2, Some("b")),
+            envelope("other", 1, None),
+            envelope("a", 0, None),
+            envelope("b", 1, Some("a")),
+        ];
+        assert_eq!(ids(conversation(&envelopes, &MessageId::new("c"))), ["a", "b", "c"]);
+        assert_eq!(ids(conversation(&envelopes, &MessageId::new("other"))), ["other"]);
+        assert!(conversation(&envelopes, &MessageId::new("missing")).is_empty());
+
+        let mut threaded = envelopes.clone();
+        for envelope in &mut threaded[..2] {
+            envelope.thread_id = Some("t1".to_string());
+        }
+        assert_eq!(ids(conversation(&threaded, &MessageId::new("c"))), ["other", "c"]);
+    }
+}
@@ -0,0 +1,112 @@
+//! The stream of connectors that encrypt with rustls and the Mozilla root
+//! certificates, e.g. for SMTP or HTTP, upgraded to TLS either right away
+//! or after STARTTLS. Without the `rustls` feature, the streams passed to
+//! the connectors have to be encrypted already.
+
+use std::fmt::Debug;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::error::{MailinerError, Result};
+
+#[derive(Debug)]
+pub enum MaybeTlsStream<S> {
+    /// Before the handshake, or a stream that is encrypted already.
+    Plain(S),
+    #[cfg(feature = "rustls")]
+    Tls(Box<tokio_rustls::client::TlsStream<S>>),
+}
+
+impl<S> MaybeTlsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug,
+{
+    /// Runs the TLS handshake with `host` on a plain stream.
+    pub async fn start_tls(self, host: &str) -> Result<Self> {
+        match self {
+            #[cfg(feature = "rustls")]
+            MaybeTlsStream::Plain(stream) => {
+                use std::sync::Arc;
+                use tokio_rustls::rustls::pki_types::ServerName;
+                use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+                use tokio_rustls::TlsConnector;
+
+                let root_store = RootCertStore {
+                    roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+                };
+                let config = ClientConfig::builder()
+                    .with_root_certificates(root_store)
+                    .with_no_client_auth();
+                let server_name = ServerName::try_from(host.to_string())
+                    .map_err(|e| MailinerError::Connector(format!("Invalid server name: {}", e)))?;
+                let stream = TlsConnector::from(Arc::new(config))
+                    .connect(server_name, stream)
+                    .await
+                    .map_err(|e| MailinerError::Tls(format!("Failed to establish TLS: {}", e)))?;
+                Ok(MaybeTlsStream::Tls(Box::new(stream)))
+            }
+            #[cfg(feature = "rustls")]
+            MaybeTlsStream::Tls(_) => Ok(self),
+            #[cfg(not(feature = "rustls"))]
+            MaybeTlsStream::Plain(_) => {
+                let _ = host;
+                Err(MailinerError::Connector(
+                    "Built without TLS support, the stream must be encrypted already".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+impl<S> AsyncRead for MaybeTlsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "rustls")]
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<S> AsyncWrite for MaybeTlsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "rustls")]
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "rustls")]
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "rustls")]
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
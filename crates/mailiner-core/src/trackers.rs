@@ -0,0 +1,88 @@
+//! Detection of trackers in HTML bodies: remote images that are only there
+//! to report that the message was opened, and links through services that
+//! report what was clicked.
+
+/// Domains of services that track opens or clicks. Their subdomains track
+/// too.
+const TRACKER_DOMAINS: &[&str] = &[
+    "awstrack.me", "bananatag.com", "createsend.com", "doubleclick.net", "exct.net",
+    "getnotify.com", "google-analytics.com", "hubspotemail.net", "hubspotlinks.com",
+    "list-manage.com", "mailfoogae.appspot.com", "mailtrack.io", "mandrillapp.com", "mcsv.net",
+    "mixmax.com", "returnpath.net", "rs6.net", "sendgrid.net", "yesware.com",
+];
+
+/// Whether `url` leads to a known tracking service.
+pub fn is_tracker_url(url: &str) -> bool {
+    host(url).is_some_and(|host| {
+        TRACKER_DOMAINS.iter().any(|domain| {
+            host == *domain || host.strip_suffix(domain).is_some_and(|sub| sub.ends_with('.'))
+        })
+    })
+}
+
+/// Whether an image with these attributes can't be seen: one pixel or
+/// smaller, or hidden by its style.
+pub fn is_tracking_pixel(width: Option<&str>, height: Option<&str>, style: Option<&str>) -> bool {
+    let mut width = width.and_then(pixels);
+    let mut height = height.and_then(pixels);
+    for (property, value) in style
+        .unwrap_or_default()
+        .split(';')
+        .filter_map(|declaration| declaration.split_once(':'))
+    {
+        let value = value.trim().to_ascii_lowercase();
+        match property.trim().to_ascii_lowercase().as_str() {
+            "display" if value == "none" => return true,
+            "visibility" if value == "hidden" => return true,
+            "width" | "max-width" => width = pixels(&value).or(width),
+            "height" | "max-height" => height = pixels(&value).or(height),
+            _ => {}
+        }
+    }
+    width.is_some_and(|width| width <= 1.0) && height.is_some_and(|height| height <= 1.0)
+}
+
+/// The size in a `width` or `height`, e.g. `1` or `1px`. Other units
+/// aren't pixels.
+fn pixels(value: &str) -> Option<f32> {
+    let value = value.trim();
+    value.strip_suffix("px").unwrap_or(value).trim().parse().ok()
+}
+
+/// The lowercase host of an HTTP(S) URL.
+fn host(url: &str) -> Option<String> {
+    let url: String = url.chars().filter(|c| !c.is_whitespace()).collect();
+    let lower = url.to_ascii_lowercase();
+    let rest = ["https://", "http://", "//"]
+        .iter()
+        .find_map(|scheme| lower.strip_prefix(scheme))?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?.split(':').next()?;
+    (!host.is_empty()).then(|| host.trim_end_matches('.').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracker_urls() {
+        assert!(is_tracker_url("https://example.us4.list-manage.com/track/click?u=1"));
+        assert!(is_tracker_url(" HTTP://u123.ct.SendGrid.net/ls/click"));
+        assert!(is_tracker_url("https://user@mailtrack.io:443/trace"));
+        assert!(!is_tracker_url("https://notlist-manage.com/"));
+        assert!(!is_tracker_url("https://example.com/?r=list-manage.com"));
+        assert!(!is_tracker_url("mailto:news@list-manage.com"));
+    }
+
+    #[test]
+    fn tracking_pixels() {
+        assert!(is_tracking_pixel(Some("1"), Some("1"), None));
+        assert!(is_tracking_pixel(Some("0"), Some("1px"), None));
+        assert!(is_tracking_pixel(None, None, Some("width: 1px; height:1px")));
+        assert!(is_tracking_pixel(Some("600"), Some("200"), Some("display: none")));
+        assert!(!is_tracking_pixel(Some("600"), Some("1"), None));
+        assert!(!is_tracking_pixel(Some("1"), None, None));
+        assert!(!is_tracking_pixel(Some("1em"), Some("1em"), None));
+    }
+}
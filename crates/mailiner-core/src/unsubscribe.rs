@@ -0,0 +1,150 @@
+//! Unsubscribing from mailing lists, with the `List-Unsubscribe` header
+//! (RFC 2369) and one-click unsubscription (RFC 8058).
+
+use serde::{Deserialize, Serialize};
+
+use crate::compose::Composition;
+use crate::models::EmailAddr;
+use crate::registry::percent_decode;
+
+/// The form to POST to the unsubscribe URL for one-click unsubscription.
+pub const ONE_CLICK_BODY: &str = "List-Unsubscribe=One-Click";
+
+/// How to unsubscribe from the mailing list a message came from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Unsubscribe {
+    /// The HTTPS URL of the header, if it has one.
+    pub url: Option<String>,
+    /// The `mailto:` URL of the header, if it has one.
+    pub mailto: Option<String>,
+    /// Whether the URL unsubscribes with a POST, without a web page to
+    /// confirm on, as announced by `List-Unsubscribe-Post`.
+    pub one_click: bool,
+}
+
+/// What unsubscribing takes, see [`Unsubscribe::method`].
+#[derive(Debug, Clone)]
+pub enum UnsubscribeMethod {
+    /// POST [`ONE_CLICK_BODY`] as `application/x-www-form-urlencoded` to
+    /// the URL.
+    OneClick(String),
+    /// Send the message.
+    Mail(Box<Composition>),
+    /// Open the web page, where the user confirms.
+    Link(String),
+}
+
+impl Unsubscribe {
+    /// Parses the values of the `List-Unsubscribe` and
+    /// `List-Unsubscribe-Post` headers, e.g.
+    /// `<mailto:leave@example.com>, <https://example.com/unsubscribe/42>`.
+    /// Plain HTTP URLs are left out, as are headers without any URL left.
+    pub fn parse(header: &str, post: Option<&str>) -> Option<Self> {
+        let mut unsubscribe = Unsubscribe {
+            url: None,
+            mailto: None,
+            one_click: false,
+        };
+        for url in header.split(',').filter_map(|item| {
+            let item = item.trim();
+            let url = item.strip_prefix('<')?.split('>').next()?;
+            // URLs may be folded over several lines.
+            Some(url.split_whitespace().collect::<String>())
+        }) {
+            let scheme = url.split(':').next().unwrap_or_default().to_ascii_lowercase();
+            if scheme == "https" && unsubscribe.url.is_none() {
+                unsubscribe.url = Some(url);
+            } else if scheme == "mailto" && unsubscribe.mailto.is_none() {
+                unsubscribe.mailto = Some(url);
+            }
+        }
+        unsubscribe.one_click = unsubscribe.url.is_some()
+            && post.is_some_and(|post| post.trim().eq_ignore_ascii_case(ONE_CLICK_BODY));
+        (unsubscribe.url.is_some() || unsubscribe.mailto.is_some()).then_some(unsubscribe)
+    }
+
+    /// The best way to unsubscribe: one click if the list supports it, else
+    /// a message from `from`, else the web page.
+    pub fn method(&self, from: &EmailAddr) -> Option<UnsubscribeMethod> {
+        if let (true, Some(url)) = (self.one_click, &self.url) {
+            return Some(UnsubscribeMethod::OneClick(url.clone()));
+        }
+        if let Some(composition) = self.mailto.as_deref().and_then(|mailto| mail(mailto, from)) {
+            return Some(UnsubscribeMethod::Mail(Box::new(composition)));
+        }
+        self.url.clone().map(UnsubscribeMethod::Link)
+    }
+}
+
+/// The message a `mailto:` URL (RFC 6068) asks for, e.g.
+/// `mailto:list@example.com?subject=unsubscribe`.
+fn mail(mailto: &str, from: &EmailAddr) -> Option<Composition> {
+    let rest = mailto.get(..7)?.eq_ignore_ascii_case("mailto:").then(|| &mailto[7..])?;
+    let (to, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let to = percent_decode(to)?;
+    if !to.contains('@') {
+        return None;
+    }
+    let mut composition = Composition {
+        from: Some(from.clone()),
+        to: vec![EmailAddr { name: None, email: Some(to) }],
+        subject: "unsubscribe".to_string(),
+        ..Default::default()
+    };
+    for (name, value) in query.split('&').filter_map(|field| field.split_once('=')) {
+        match name.to_ascii_lowercase().as_str() {
+            "subject" => composition.subject = percent_decode(value)?,
+            "body" => composition.body_text = percent_decode(value)?,
+            _ => {}
+        }
+    }
+    composition.send_plain_text = Some(true);
+    Some(composition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_headers() {
+        let header = "<mailto:leave-42@lists.example.com?subject=unsubscribe%2042>,\r\n <https://example.com/\r\n unsubscribe/42>, <http://example.com/u/42>";
+        let unsubscribe = Unsubscribe::parse(header, Some("List-Unsubscribe=One-Click")).unwrap();
+        assert_eq!(unsubscribe.url.as_deref(), Some("https://example.com/unsubscribe/42"));
+        assert_eq!(
+            unsubscribe.mailto.as_deref(),
+            Some("mailto:leave-42@lists.example.com?subject=unsubscribe%2042")
+        );
+        assert!(unsubscribe.one_click);
+
+        assert!(!Unsubscribe::parse("<https://example.com/u>", None).unwrap().one_click);
+        assert!(!Unsubscribe::parse("<mailto:leave@example.com>", Some(ONE_CLICK_BODY)).unwrap().one_click);
+        assert_eq!(Unsubscribe::parse("<http://example.com/u>, garbage", None), None);
+    }
+
+    #[test]
+    fn chooses_method() {
+        let me = EmailAddr { name: None, email: Some("me@example.com".to_string()) };
+        let mut unsubscribe = Unsubscribe::parse(
+            "<mailto:leave@example.com?subject=stop&body=Remove%20me>, <https://example.com/u/42>",
+            Some(ONE_CLICK_BODY),
+        )
+        .unwrap();
+        assert!(matches!(
+            unsubscribe.method(&me),
+            Some(UnsubscribeMethod::OneClick(url)) if url == "https://example.com/u/42"
+        ));
+
+        unsubscribe.one_click = false;
+        let Some(UnsubscribeMethod::Mail(composition)) = unsubscribe.method(&me) else {
+            panic!("expected a message");
+        };
+        assert_eq!(composition.to[0].email.as_deref(), Some("leave@example.com"));
+        assert_eq!(composition.subject, "stop");
+        assert_eq!(composition.body_text, "Remove me");
+        assert_eq!(composition.from.and_then(|from| from.email).as_deref(), Some("me@example.com"));
+
+        unsubscribe.mailto = None;
+        assert!(matches!(unsubscribe.method(&me), Some(UnsubscribeMethod::Link(_))));
+    }
+}
@@ -0,0 +1,114 @@
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use mailiner_core::{AuthMethod, ConnectionSecurity};
+
+const DEFAULT_IMAP_PORT: u16 = 993;
+const DEFAULT_IMAP_STARTTLS_PORT: u16 = 143;
+const DEFAULT_SYNC_INTERVAL_SECS: u64 = 300;
+const DEFAULT_COMMAND_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_SYNC_CONCURRENCY: usize = 2;
+const DEFAULT_DATA_DIR: &str = ".mailiner";
+
+/// Daemon settings, read from `MAILINER_*` environment variables.
+#[derive(Debug, Clone)]
+pub struct DaemonConfig {
+    pub imap_host: String,
+    pub imap_port: u16,
+    pub imap_security: ConnectionSecurity,
+    pub username: String,
+    /// The password, or the access token with OAuth.
+    pub password: String,
+    /// `None` picks the strongest password method the server supports.
+    pub auth_method: Option<AuthMethod>,
+    /// Fingerprints of server certificates to accept although they fail
+    /// validation, e.g. of a self-signed certificate.
+    pub trusted_certificates: Vec<String>,
+    pub sync_interval: Duration,
+    /// How many folders are synced at once.
+    pub sync_concurrency: usize,
+    /// How long to wait for the server to respond before giving up on the
+    /// connection.
+    pub command_timeout: Duration,
+    pub data_dir: PathBuf,
+}
+
+impl DaemonConfig {
+    pub fn from_env() -> Result<Self, String> {
+        let imap_security = match env::var("MAILINER_IMAP_SECURITY") {
+            Ok(security) => match security.to_ascii_lowercase().as_str() {
+                "tls" => ConnectionSecurity::Tls,
+                "starttls" => ConnectionSecurity::StartTls,
+                _ => return Err(format!("Invalid MAILINER_IMAP_SECURITY '{}'", security)),
+            },
+            Err(_) => ConnectionSecurity::Tls,
+        };
+        let imap_port = match env::var("MAILINER_IMAP_PORT") {
+            Ok(port) => port
+                .parse()
+                .map_err(|e| format!("Invalid MAILINER_IMAP_PORT '{}': {}", port, e))?,
+            Err(_) if imap_security == ConnectionSecurity::StartTls => DEFAULT_IMAP_STARTTLS_PORT,
+            Err(_) => DEFAULT_IMAP_PORT,
+        };
+        let auth_method = match env::var("MAILINER_IMAP_AUTH") {
+            Ok(method) => Some(match method.to_ascii_lowercase().as_str() {
+                "login" => AuthMethod::Login,
+                "plain" => AuthMethod::Plain,
+                "cram-md5" => AuthMethod::CramMd5,
+                "xoauth2" => AuthMethod::XOAuth2,
+                "oauthbearer" => AuthMethod::OAuthBearer,
+                _ => return Err(format!("Invalid MAILINER_IMAP_AUTH '{}'", method)),
+            }),
+            Err(_) => None,
+        };
+        let trusted_certificates = env::var("MAILINER_IMAP_TRUSTED_CERTS")
+            .map(|certs| {
+                certs
+                    .split(',')
+                    .map(|cert| cert.trim().to_ascii_uppercase())
+                    .filter(|cert| !cert.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let sync_interval = match env::var("MAILINER_SYNC_INTERVAL") {
+            Ok(secs) => secs
+                .parse()
+                .map_err(|e| format!("Invalid MAILINER_SYNC_INTERVAL '{}': {}", secs, e))?,
+            Err(_) => DEFAULT_SYNC_INTERVAL_SECS,
+        };
+        let sync_concurrency = match env::var("MAILINER_SYNC_CONCURRENCY") {
+            Ok(count) => match count.parse() {
+                Ok(count) if count > 0 => count,
+                _ => return Err(format!("Invalid MAILINER_SYNC_CONCURRENCY '{}'", count)),
+            },
+            Err(_) => DEFAULT_SYNC_CONCURRENCY,
+        };
+        let command_timeout = match env::var("MAILINER_IMAP_TIMEOUT") {
+            Ok(secs) => secs
+                .parse()
+                .map_err(|e| format!("Invalid MAILINER_IMAP_TIMEOUT '{}': {}", secs, e))?,
+            Err(_) => DEFAULT_COMMAND_TIMEOUT_SECS,
+        };
+
+        Ok(Self {
+            imap_host: required("MAILINER_IMAP_HOST")?,
+            imap_port,
+            imap_security,
+            username: required("MAILINER_IMAP_USER")?,
+            password: required("MAILINER_IMAP_PASSWORD")?,
+            auth_method,
+            trusted_certificates,
+            sync_interval: Duration::from_secs(sync_interval),
+            sync_concurrency,
+            command_timeout: Duration::from_secs(command_timeout),
+            data_dir: env::var("MAILINER_DATA_DIR")
+                .unwrap_or_else(|_| DEFAULT_DATA_DIR.to_string())
+                .into(),
+        })
+    }
+}
+
+fn required(name: &str) -> Result<String, String> {
+    env::var(name).map_err(|_| format!("Missing required environment variable {}", name))
+}
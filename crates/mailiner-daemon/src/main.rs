@@ -0,0 +1,179 @@
+//! Headless Mailiner sync daemon.
+//!
+//! Runs the sync engine without any UI. After each sync the daemon waits for
+//! changes in the INBOX using IDLE, and re-syncs at the latest after the sync
+//! interval. The filter rules of the account are applied to new mail as it's
+//! synced, and the new mail in the folders the user asked to be notified
+//! about is shown as desktop notifications.
+//!
+//! The synced data goes to the [`FileStorage`] in the data directory, the
+//! same one the app opens, so that the app starts with fresh data.
+
+use std::sync::Arc;
+
+use mailiner_core::platform::{native, Platform};
+use mailiner_core::storage::file::STORAGE_DIR;
+use mailiner_core::{
+    Account, EmailConnector, EmailService, FileStorage, FolderId, MailinerError, NewMailEvent,
+    Result, Storage, SyncLimits, SyncScheduler,
+};
+use mailiner_imap_connector::ImapConnector;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, info, warn};
+
+use crate::config::DaemonConfig;
+
+mod config;
+
+type Connector = ImapConnector<TcpStream>;
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let config = match DaemonConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let platform = native::platform(&config.data_dir);
+    let mut connector = Connector::new(
+        config.imap_host.clone(),
+        config.imap_port,
+        config.username.clone(),
+        config.password.clone(),
+    )
+    .with_security(config.imap_security)
+    .with_trusted_certificates(config.trusted_certificates.clone())
+    .with_command_timeout(platform.timer.clone(), config.command_timeout);
+    if let Some(auth_method) = config.auth_method {
+        connector = connector.with_auth_method(auth_method);
+    }
+    let connector = Arc::new(connector);
+    let storage: Arc<dyn Storage> = match FileStorage::open(platform.fs.clone(), STORAGE_DIR).await {
+        Ok(storage) => Arc::new(storage),
+        Err(e) => {
+            error!("Failed to open the storage in {}: {}", config.data_dir.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let service = EmailService::new(connector.clone(), storage);
+    tokio::spawn(notify_new_mail(service.subscribe_new_mail(), platform.clone()));
+
+    let mut account = None;
+    loop {
+        tokio::select! {
+            _ = sync_cycle(&config, &platform, &connector, &service, &mut account) => {}
+            _ = tokio::signal::ctrl_c() => {
+                info!("Shutting down");
+                let _ = connector.disconnect().await;
+                break;
+            }
+        }
+    }
+}
+
+/// Syncs the account and waits until the next sync is due.
+async fn sync_cycle(
+    config: &DaemonConfig,
+    platform: &Platform<TcpStream>,
+    connector: &Connector,
+    service: &EmailService<TcpStream>,
+    account: &mut Option<Account>,
+) {
+    match sync_once(config, platform, connector, service, account).await {
+        Ok(()) => wait_for_changes(config, platform, connector).await,
+        Err(MailinerError::UntrustedCertificate(certificate)) => {
+            error!(
+                "The server's certificate is not trusted ({}). If this is the certificate \
+                 you expect, add {} to MAILINER_IMAP_TRUSTED_CERTS",
+                certificate.reason, certificate.fingerprint
+            );
+            let _ = connector.disconnect().await;
+            *account = None;
+            platform.timer.sleep(config.sync_interval).await;
+        }
+        Err(e) => {
+            error!("Sync failed: {}", e);
+            // Start from a fresh connection on the next attempt.
+            let _ = connector.disconnect().await;
+            *account = None;
+            platform.timer.sleep(config.sync_interval).await;
+        }
+    }
+}
+
+/// Waits until the server reports a change in the INBOX, or at most for the
+/// sync interval. Other folders are only picked up by the periodic sync.
+async fn wait_for_changes(config: &DaemonConfig, platform: &Platform<TcpStream>, connector: &Connector) {
+    match connector.idle(&FolderId::new("INBOX"), config.sync_interval).await {
+        Ok(events) if !events.is_empty() => info!("{} changes in INBOX", events.len()),
+        Ok(_) => {}
+        Err(e) => {
+            warn!("IDLE failed, falling back to polling: {}", e);
+            platform.timer.sleep(config.sync_interval).await;
+        }
+    }
+}
+
+/// Shows the new mail found by the syncs as notifications.
+async fn notify_new_mail(
+    mut new_mail: broadcast::Receiver<NewMailEvent>,
+    platform: Platform<TcpStream>,
+) {
+    loop {
+        match new_mail.recv().await {
+            Ok(event) => {
+                info!("{} new messages in {}", event.count, event.folder_id);
+                if let Err(e) = platform.notifier.notify(&event.to_notification()) {
+                    warn!("Failed to show a notification: {}", e);
+                }
+            }
+            Err(RecvError::Lagged(missed)) => warn!("Missed {} new mail events", missed),
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+async fn sync_once(
+    config: &DaemonConfig,
+    platform: &Platform<TcpStream>,
+    connector: &Connector,
+    service: &EmailService<TcpStream>,
+    account: &mut Option<Account>,
+) -> Result<()> {
+    let account = match account {
+        Some(account) => account,
+        None => {
+            info!("Connecting to {}:{}", config.imap_host, config.imap_port);
+            let stream = platform
+                .network
+                .connect(&config.imap_host, config.imap_port)
+                .await?;
+            connector.connect(stream).await?;
+            let authenticated = connector.authenticate(&config.password).await?;
+            service.storage().save_account(&authenticated).await?;
+            account.insert(authenticated)
+        }
+    };
+
+    let scheduler = SyncScheduler::new(SyncLimits {
+        global: config.sync_concurrency,
+        per_account: config.sync_concurrency,
+    });
+    let metadata = service.sync_account_with(&account.id, &scheduler).await?;
+    let total: u64 = metadata.folders.iter().map(|f| f.total_messages).sum();
+    let unread: u64 = metadata.folders.iter().map(|f| f.unread_messages).sum();
+    info!(
+        "Synced {} folders ({} messages, {} unread)",
+        metadata.folders.len(),
+        total,
+        unread
+    );
+
+    Ok(())
+}
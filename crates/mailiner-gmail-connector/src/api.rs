@@ -0,0 +1,215 @@
+//! The resources of the Gmail API (v1) used by the connector.
+
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::DecodePaddingMode;
+use base64::{alphabet, Engine};
+use serde::{Deserialize, Serialize};
+
+use crate::GmailError;
+
+/// The API sends base64url with or without padding, depending on the field.
+const BASE64_URL: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+pub(crate) fn decode(data: &str) -> Result<Vec<u8>, GmailError> {
+    BASE64_URL
+        .decode(data)
+        .map_err(|e| GmailError::InvalidData(format!("Invalid base64 data: {}", e)))
+}
+
+pub(crate) fn encode(data: &[u8]) -> String {
+    BASE64_URL.encode(data)
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ErrorResponse {
+    pub(crate) error: ErrorDetails,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ErrorDetails {
+    pub(crate) message: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Profile {
+    pub(crate) email_address: String,
+    pub(crate) history_id: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Label {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub(crate) id: String,
+    pub(crate) name: String,
+    /// `system` for the built-in labels, e.g. `INBOX`, `user` for the rest.
+    #[serde(default, rename = "type", skip_serializing)]
+    pub(crate) kind: String,
+    #[serde(default, skip_serializing)]
+    pub(crate) messages_total: u64,
+    #[serde(default, skip_serializing)]
+    pub(crate) color: Option<LabelColor>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LabelColor {
+    pub(crate) background_color: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct LabelList {
+    #[serde(default)]
+    pub(crate) labels: Vec<Label>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct MessageList {
+    #[serde(default)]
+    pub(crate) messages: Vec<MessageRef>,
+    pub(crate) next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct MessageRef {
+    pub(crate) id: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Message {
+    pub(crate) id: String,
+    pub(crate) thread_id: Option<String>,
+    #[serde(default)]
+    pub(crate) label_ids: Vec<String>,
+    /// Milliseconds since the epoch, as a string.
+    pub(crate) internal_date: Option<String>,
+    pub(crate) size_estimate: Option<u64>,
+    pub(crate) payload: Option<MessagePart>,
+    /// The whole message, in base64url, with `format=raw`.
+    pub(crate) raw: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct MessagePart {
+    /// Empty for the root part, `0`, `1`, `1.0`, ... for the others.
+    #[serde(default)]
+    pub(crate) part_id: String,
+    #[serde(default)]
+    pub(crate) mime_type: String,
+    #[serde(default)]
+    pub(crate) filename: String,
+    #[serde(default)]
+    pub(crate) headers: Vec<Header>,
+    #[serde(default)]
+    pub(crate) body: PartBody,
+    #[serde(default)]
+    pub(crate) parts: Vec<MessagePart>,
+}
+
+impl MessagePart {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str())
+    }
+
+    /// Finds the part with the given id in the tree.
+    pub(crate) fn find(&self, part_id: &str) -> Option<&MessagePart> {
+        if self.part_id == part_id {
+            return Some(self);
+        }
+        self.parts.iter().find_map(|part| part.find(part_id))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Header {
+    pub(crate) name: String,
+    pub(crate) value: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct PartBody {
+    #[serde(default)]
+    pub(crate) size: u64,
+    /// The content, unless it is fetched separately with the attachment id.
+    pub(crate) data: Option<String>,
+    pub(crate) attachment_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct HistoryList {
+    #[serde(default)]
+    pub(crate) history: Vec<History>,
+    pub(crate) history_id: String,
+    pub(crate) next_page_token: Option<String>,
+}
+
+/// A change to the mailbox. Each list names the messages the change was
+/// made to.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct History {
+    #[serde(default)]
+    pub(crate) messages_added: Vec<HistoryMessage>,
+    #[serde(default)]
+    pub(crate) messages_deleted: Vec<HistoryMessage>,
+    #[serde(default)]
+    pub(crate) labels_added: Vec<HistoryMessage>,
+    #[serde(default)]
+    pub(crate) labels_removed: Vec<HistoryMessage>,
+}
+
+impl History {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.messages_added.is_empty()
+            && self.messages_deleted.is_empty()
+            && self.labels_added.is_empty()
+            && self.labels_removed.is_empty()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct HistoryMessage {
+    pub(crate) message: MessageRef,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ModifyRequest<'a> {
+    pub(crate) add_label_ids: Vec<&'a str>,
+    pub(crate) remove_label_ids: Vec<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct InsertRequest<'a> {
+    /// The whole message, base64url encoded.
+    pub(crate) raw: String,
+    pub(crate) label_ids: Vec<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct DraftRequest<'a> {
+    pub(crate) message: InsertRequest<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Draft {
+    pub(crate) message: MessageRef,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Attachment {
+    pub(crate) data: String,
+}
@@ -0,0 +1,235 @@
+//! A minimal HTTP/1.1 client (RFC 9112), enough for JSON requests to the
+//! Gmail API over a single persistent connection.
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufStream};
+
+use crate::GmailError;
+
+#[derive(Debug)]
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    pub(crate) body: Vec<u8>,
+    /// Whether the server closes the connection after the response.
+    pub(crate) close: bool,
+}
+
+pub(crate) struct HttpConnection<T> {
+    stream: BufStream<T>,
+    host: String,
+}
+
+impl<T> HttpConnection<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    pub(crate) fn new(stream: T, host: &str) -> Self {
+        Self {
+            stream: BufStream::new(stream),
+            host: host.to_string(),
+        }
+    }
+
+    /// Sends a request with a JSON `body` and reads the response.
+    pub(crate) async fn request(
+        &mut self,
+        method: &str,
+        path: &str,
+        token: &str,
+        body: Option<&[u8]>,
+    ) -> Result<Response, GmailError> {
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer {}\r\nAccept: application/json\r\nUser-Agent: mailiner\r\n",
+            method, path, self.host, token
+        );
+        if let Some(body) = body {
+            head.push_str(&format!(
+                "Content-Type: application/json\r\nContent-Length: {}\r\n",
+                body.len()
+            ));
+        } else if method != "GET" {
+            head.push_str("Content-Length: 0\r\n");
+        }
+        head.push_str("\r\n");
+
+        self.stream
+            .write_all(head.as_bytes())
+            .await
+            .map_err(write_error)?;
+        if let Some(body) = body {
+            self.stream.write_all(body).await.map_err(write_error)?;
+        }
+        self.stream.flush().await.map_err(write_error)?;
+
+        loop {
+            let response = self.read_response(method).await?;
+            // Informational responses, e.g. 100 Continue, precede the real one.
+            if !(100..200).contains(&response.status) {
+                return Ok(response);
+            }
+        }
+    }
+
+    async fn read_response(&mut self, method: &str) -> Result<Response, GmailError> {
+        let status_line = self.read_line().await?;
+        // E.g. `HTTP/1.1 200 OK`.
+        let status = status_line
+            .split(' ')
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| {
+                GmailError::Connection(format!("Invalid status line: {}", status_line))
+            })?;
+
+        let mut content_length = None;
+        let mut chunked = false;
+        let mut close = status_line.starts_with("HTTP/1.0");
+        loop {
+            let line = self.read_line().await?;
+            if line.is_empty() {
+                break;
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "content-length" => content_length = value.parse::<usize>().ok(),
+                "transfer-encoding" => chunked = value.to_ascii_lowercase().contains("chunked"),
+                "connection" => close = value.eq_ignore_ascii_case("close"),
+                _ => {}
+            }
+        }
+
+        let body =
+            if method == "HEAD" || (100..200).contains(&status) || status == 204 || status == 304 {
+                Vec::new()
+            } else if chunked {
+                self.read_chunked().await?
+            } else if let Some(length) = content_length {
+                let mut body = vec![0; length];
+                self.stream
+                    .read_exact(&mut body)
+                    .await
+                    .map_err(read_error)?;
+                body
+            } else {
+                // Without a length, the body ends with the connection.
+                close = true;
+                let mut body = Vec::new();
+                self.stream
+                    .read_to_end(&mut body)
+                    .await
+                    .map_err(read_error)?;
+                body
+            };
+
+        Ok(Response {
+            status,
+            body,
+            close,
+        })
+    }
+
+    async fn read_chunked(&mut self) -> Result<Vec<u8>, GmailError> {
+        let mut body = Vec::new();
+        loop {
+            let line = self.read_line().await?;
+            // The size may be followed by extensions, e.g. `1a;name=value`.
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16)
+                .map_err(|_| GmailError::Connection(format!("Invalid chunk size: {}", line)))?;
+            if size == 0 {
+                // Skip the trailer fields.
+                while !self.read_line().await?.is_empty() {}
+                return Ok(body);
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            self.stream
+                .read_exact(&mut body[start..])
+                .await
+                .map_err(read_error)?;
+            self.read_line().await?;
+        }
+    }
+
+    /// Reads a line without its line ending.
+    async fn read_line(&mut self) -> Result<String, GmailError> {
+        let mut line = String::new();
+        let read = self.stream.read_line(&mut line).await.map_err(read_error)?;
+        if read == 0 {
+            return Err(GmailError::Connection(
+                "Connection closed by the server".to_string(),
+            ));
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+/// Percent-encodes a query parameter value.
+pub(crate) fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn read_error(e: std::io::Error) -> GmailError {
+    GmailError::Connection(format!("Failed to read response: {}", e))
+}
+
+fn write_error(e: std::io::Error) -> GmailError {
+    GmailError::Connection(format!("Failed to send request: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn chunked_response() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let server = tokio::spawn(async move {
+            let mut request = vec![0; 1024];
+            let read = server.read(&mut request).await.unwrap();
+            let request = String::from_utf8_lossy(&request[..read]).into_owned();
+            server
+                .write_all(
+                    b"HTTP/1.1 100 Continue\r\n\r\n\
+                    HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    4;ext=1\r\n{\"a\"\r\n3\r\n:1}\r\n0\r\nExpires: 0\r\n\r\n",
+                )
+                .await
+                .unwrap();
+            request
+        });
+
+        let mut connection = HttpConnection::new(client, "gmail.googleapis.com");
+        let response = connection
+            .request("POST", "/gmail/v1/users/me/messages", "token", Some(b"{}"))
+            .await
+            .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"{\"a\":1}");
+        assert!(!response.close);
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /gmail/v1/users/me/messages HTTP/1.1\r\n"));
+        assert!(request.contains("Authorization: Bearer token\r\n"));
+        assert!(request.ends_with("Content-Length: 2\r\n\r\n{}"));
+    }
+
+    #[test]
+    fn query_encoding() {
+        assert_eq!(encode("from:jan is:unread"), "from%3Ajan%20is%3Aunread");
+        assert_eq!(encode("Label_1-a.b~c"), "Label_1-a.b~c");
+        assert_eq!(encode("ü"), "%C3%BC");
+    }
+}
@@ -0,0 +1,1216 @@
+//! Access to Gmail over its REST API (v1), as an alternative to Gmail's IMAP
+//! server. Folders are labels, flags are labels too, and changes are read
+//! from the mailbox history instead of being compared message by message.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Debug;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use mailiner_core::oauth::TokenProvider;
+use mailiner_core::platform::Timer;
+use mailiner_core::tls::MaybeTlsStream;
+use mailiner_core::{
+    Account, AccountId, BackendUri, ConnectorRegistry, EmailConnector, Envelope, FlagUpdate,
+    Folder, FolderDelta, FolderEvent, FolderId, FolderSyncState, MailinerError, MessageId,
+    MessagePart, MessagePartId, MessageStructure, Result as MailinerResult, SearchQuery,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::api::Label;
+use crate::http::{encode, HttpConnection, Response};
+use crate::message::{DRAFT, SPAM, TRASH};
+
+mod api;
+mod http;
+mod message;
+mod query;
+
+const API_HOST: &str = "gmail.googleapis.com";
+const API_PATH: &str = "/gmail/v1/users/me";
+/// The most messages `messages.list` returns at once.
+const PAGE_SIZE: usize = 500;
+/// History ids stay valid for the whole mailbox, so there is nothing like a
+/// UIDVALIDITY change. They do expire after about a week, though.
+const UID_VALIDITY: u32 = 1;
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Builds the id of a message as seen in a label, e.g. `INBOX;ID=18c2a`.
+pub(crate) fn to_message_id(folder_id: &FolderId, id: &str) -> MessageId {
+    MessageId::new(format!("{};ID={}", folder_id.as_str(), id))
+}
+
+/// Splits a message id into the label and Gmail's id of the message.
+fn parse_message_id(message_id: &MessageId) -> Result<(FolderId, String), GmailError> {
+    message_id
+        .as_str()
+        .rsplit_once(";ID=")
+        .filter(|(_, id)| !id.is_empty())
+        .map(|(folder, id)| (FolderId::new(folder), id.to_string()))
+        .ok_or_else(|| GmailError::InvalidData(format!("Invalid message id: {}", message_id)))
+}
+
+/// Labels that are message states rather than folders.
+fn is_folder(label: &Label) -> bool {
+    label.id != message::UNREAD && label.id != "CHAT" && !label.id.starts_with("CATEGORY_")
+}
+
+#[derive(Error, Debug)]
+pub enum GmailError {
+    #[error("Connection error: {0}")]
+    Connection(String),
+    #[error("Authentication error: {0}")]
+    Authentication(String),
+    /// An error response of the API, with its HTTP status.
+    #[error("Gmail API error {0}: {1}")]
+    Api(u16, String),
+    #[error("Invalid data: {0}")]
+    InvalidData(String),
+    #[error("Not authenticated")]
+    NotAuthenticated,
+    #[error("Not connected")]
+    NotConnected,
+    #[error("TLS error: {0}")]
+    Tls(String),
+}
+
+impl From<GmailError> for MailinerError {
+    fn from(err: GmailError) -> Self {
+        match err {
+            GmailError::Connection(msg) => MailinerError::Connector(msg),
+            GmailError::Authentication(msg) => MailinerError::Authentication(msg),
+            GmailError::Api(404, msg) => MailinerError::NotFound(msg),
+            GmailError::Api(429, msg) => MailinerError::RateLimited(msg),
+            // Server errors are worth retrying.
+            GmailError::Api(status, msg) if status >= 500 => MailinerError::Connector(msg),
+            GmailError::Api(_, msg) => MailinerError::Rejected(msg),
+            GmailError::InvalidData(msg) => MailinerError::InvalidData(msg),
+            GmailError::NotAuthenticated => {
+                MailinerError::Connector("Not authenticated".to_string())
+            }
+            GmailError::NotConnected => MailinerError::Connector("Not connected".to_string()),
+            GmailError::Tls(msg) => MailinerError::Tls(msg),
+        }
+    }
+}
+
+/// The error for a response that isn't a success.
+fn response_error(response: &Response) -> GmailError {
+    let message = serde_json::from_slice::<api::ErrorResponse>(&response.body)
+        .map(|error| error.error.message)
+        .unwrap_or_else(|_| String::from_utf8_lossy(&response.body).trim().to_string());
+    if response.status == 401 {
+        GmailError::Authentication(message)
+    } else {
+        GmailError::Api(response.status, message)
+    }
+}
+
+/// The messages a batch of history records is about.
+#[derive(Debug, Default)]
+struct Changes {
+    /// Messages that were added or whose labels changed.
+    touched: BTreeSet<String>,
+    deleted: BTreeSet<String>,
+}
+
+impl Changes {
+    fn new(history: &[api::History]) -> Self {
+        let mut changes = Self::default();
+        for record in history {
+            for changed in record
+                .messages_added
+                .iter()
+                .chain(&record.labels_added)
+                .chain(&record.labels_removed)
+            {
+                changes.touched.insert(changed.message.id.clone());
+            }
+            for deleted in &record.messages_deleted {
+                changes.deleted.insert(deleted.message.id.clone());
+            }
+        }
+        changes.touched.retain(|id| !changes.deleted.contains(id));
+        changes
+    }
+}
+
+pub struct GmailConnector<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send,
+{
+    host: String,
+    /// Where access tokens come from, instead of the credentials passed to
+    /// `authenticate`.
+    tokens: Option<Arc<TokenProvider>>,
+    /// The access token passed to `authenticate`.
+    access_token: std::sync::Mutex<Option<String>>,
+    /// Whether the stream passed to `connect` is encrypted already, e.g. a
+    /// WebSocket to a proxy that connects to the server with TLS.
+    external_tls: bool,
+    /// Waits between the history checks of `idle`, which the API has no
+    /// push for without a Cloud Pub/Sub topic.
+    timer: Option<Arc<dyn Timer>>,
+    poll_interval: Duration,
+    connection: Mutex<Option<HttpConnection<MaybeTlsStream<S>>>>,
+    /// The account of the authenticated user.
+    account_id: std::sync::Mutex<Option<AccountId>>,
+    /// The labels by id, as of the last `list_folders`.
+    labels: std::sync::Mutex<HashMap<String, Label>>,
+}
+
+impl<S> Default for GmailConnector<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> GmailConnector<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send,
+{
+    pub fn new() -> Self {
+        Self {
+            host: API_HOST.to_string(),
+            tokens: None,
+            access_token: std::sync::Mutex::new(None),
+            external_tls: false,
+            timer: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            connection: Mutex::new(None),
+            account_id: std::sync::Mutex::new(None),
+            labels: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes access tokens from `tokens` instead of the credentials passed
+    /// to `authenticate`. A token the API refuses is refreshed and the
+    /// request retried once.
+    pub fn with_token_provider(mut self, tokens: Arc<TokenProvider>) -> Self {
+        self.tokens = Some(tokens);
+        self
+    }
+
+    /// Uses the stream passed to `connect` as it is, because it is encrypted
+    /// already.
+    pub fn with_external_tls(mut self, external_tls: bool) -> Self {
+        self.external_tls = external_tls;
+        self
+    }
+
+    /// Sets the timer `idle` waits with between checking for changes, every
+    /// `poll_interval`.
+    pub fn with_timer(mut self, timer: Arc<dyn Timer>, poll_interval: Duration) -> Self {
+        self.timer = Some(timer);
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// The host to open the stream passed to `connect` to.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        443
+    }
+
+    fn account_id(&self) -> Result<AccountId, GmailError> {
+        self.account_id
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or(GmailError::NotAuthenticated)
+    }
+
+    async fn token(&self, refresh: bool) -> MailinerResult<String> {
+        match &self.tokens {
+            Some(tokens) => tokens.access_token(refresh).await,
+            None => Ok(self
+                .access_token
+                .lock()
+                .unwrap()
+                .clone()
+                .ok_or(GmailError::NotAuthenticated)?),
+        }
+    }
+
+    /// Sends a request to the API and returns the body of the response.
+    async fn call(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> MailinerResult<Vec<u8>> {
+        let path = format!("{}{}", API_PATH, path);
+        let mut refresh = false;
+        loop {
+            let token = self.token(refresh).await?;
+            let mut connection = self.connection.lock().await;
+            let http = connection.as_mut().ok_or(GmailError::NotConnected)?;
+            let response = match http.request(method, &path, &token, body.as_deref()).await {
+                Ok(response) => response,
+                Err(e) => {
+                    // The connection is out of step with the server.
+                    *connection = None;
+                    return Err(e.into());
+                }
+            };
+            if response.close {
+                *connection = None;
+            }
+            if response.status == 401 && self.tokens.is_some() && !refresh {
+                info!("Access token refused, refreshing it");
+                refresh = true;
+                continue;
+            }
+            if !(200..300).contains(&response.status) {
+                return Err(response_error(&response).into());
+            }
+            return Ok(response.body);
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> MailinerResult<T> {
+        Ok(serde_json::from_slice(
+            &self.call("GET", path, None).await?,
+        )?)
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        path: &str,
+        body: &impl Serialize,
+    ) -> MailinerResult<T> {
+        let body = serde_json::to_vec(body)?;
+        Ok(serde_json::from_slice(
+            &self.call(method, path, Some(body)).await?,
+        )?)
+    }
+
+    /// Returns the labels by id, listing them if they aren't known yet.
+    async fn labels(&self) -> MailinerResult<HashMap<String, Label>> {
+        let labels = self.labels.lock().unwrap().clone();
+        if !labels.is_empty() {
+            return Ok(labels);
+        }
+        self.refresh_labels().await
+    }
+
+    async fn refresh_labels(&self) -> MailinerResult<HashMap<String, Label>> {
+        let list: api::LabelList = self.get("/labels").await?;
+        let labels: HashMap<_, _> = list
+            .labels
+            .into_iter()
+            .map(|label| (label.id.clone(), label))
+            .collect();
+        *self.labels.lock().unwrap() = labels.clone();
+        Ok(labels)
+    }
+
+    async fn label(&self, folder_id: &FolderId) -> MailinerResult<Label> {
+        match self.labels().await?.remove(folder_id.as_str()) {
+            Some(label) => Ok(label),
+            None => self
+                .refresh_labels()
+                .await?
+                .remove(folder_id.as_str())
+                .ok_or_else(|| MailinerError::NotFound(format!("No label {}", folder_id))),
+        }
+    }
+
+    /// The folder of a label. Nested labels are named like paths, e.g.
+    /// `Work/Projects`.
+    fn folder(account_id: &AccountId, label: &Label, labels: &HashMap<String, Label>) -> Folder {
+        let parent = label.name.rsplit_once('/').and_then(|(parent, name)| {
+            labels
+                .values()
+                .find(|label| label.name == parent)
+                .map(|parent| (FolderId::new(parent.id.clone()), name))
+        });
+        Folder {
+            id: FolderId::new(label.id.clone()),
+            account_id: account_id.clone(),
+            name: parent
+                .as_ref()
+                .map_or(&label.name[..], |(_, name)| name)
+                .to_string(),
+            parent_id: parent.map(|(id, _)| id),
+            rights: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    /// Lists the ids of the messages with a label, newest first, stopping
+    /// once there are `limit` of them.
+    async fn list_message_ids(
+        &self,
+        folder_id: &FolderId,
+        query: Option<&str>,
+        limit: Option<usize>,
+    ) -> MailinerResult<Vec<String>> {
+        let mut ids = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let page_size = limit.map_or(PAGE_SIZE, |limit| (limit - ids.len()).min(PAGE_SIZE));
+            let mut path = format!(
+                "/messages?labelIds={}&maxResults={}&includeSpamTrash=true",
+                encode(folder_id.as_str()),
+                page_size
+            );
+            if let Some(query) = query {
+                path.push_str(&format!("&q={}", encode(query)));
+            }
+            if let Some(page_token) = &page_token {
+                path.push_str(&format!("&pageToken={}", encode(page_token)));
+            }
+            let list: api::MessageList = self.get(&path).await?;
+            ids.extend(list.messages.into_iter().map(|message| message.id));
+            page_token = list.next_page_token;
+            if page_token.is_none() || limit.is_some_and(|limit| ids.len() >= limit) {
+                return Ok(ids);
+            }
+        }
+    }
+
+    /// Fetches a message in one of the API's formats: `minimal` for the
+    /// labels only, `metadata` for the envelope headers, `full` for the MIME
+    /// tree and small bodies, `raw` for the source.
+    async fn get_message(&self, id: &str, format: &str) -> MailinerResult<api::Message> {
+        let mut path = format!("/messages/{}?format={}", encode(id), format);
+        if format == "metadata" {
+            for header in message::ENVELOPE_HEADERS {
+                path.push_str(&format!("&metadataHeaders={}", header));
+            }
+        }
+        self.get(&path).await
+    }
+
+    /// Fetches the envelopes of messages in a label, skipping the ones
+    /// deleted in the meantime.
+    async fn envelopes(
+        &self,
+        folder_id: &FolderId,
+        ids: &[String],
+    ) -> MailinerResult<Vec<Envelope>> {
+        let account_id = self.account_id()?;
+        let labels = self.labels().await?;
+        let mut envelopes = Vec::with_capacity(ids.len());
+        for id in ids {
+            match self.get_message(id, "metadata").await {
+                Ok(message) => {
+                    envelopes.push(message::envelope(&message, &account_id, folder_id, &labels))
+                }
+                Err(MailinerError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(envelopes)
+    }
+
+    async fn current_history_id(&self) -> MailinerResult<u64> {
+        let profile: api::Profile = self.get("/profile").await?;
+        Ok(parse_history_id(&profile.history_id)?)
+    }
+
+    /// Returns the history since `start`, optionally only of a label, and
+    /// the history id to continue from. Fails with `NotFound` once `start`
+    /// is too old.
+    async fn history(
+        &self,
+        start: u64,
+        folder_id: Option<&FolderId>,
+    ) -> MailinerResult<(Vec<api::History>, u64)> {
+        let mut history = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut path = format!("/history?startHistoryId={}&maxResults={}", start, PAGE_SIZE);
+            if let Some(folder_id) = folder_id {
+                path.push_str(&format!("&labelId={}", encode(folder_id.as_str())));
+            }
+            if let Some(page_token) = &page_token {
+                path.push_str(&format!("&pageToken={}", encode(page_token)));
+            }
+            let list: api::HistoryList = self.get(&path).await?;
+            history.extend(list.history);
+            page_token = list.next_page_token;
+            if page_token.is_none() {
+                return Ok((history, parse_history_id(&list.history_id)?));
+            }
+        }
+    }
+
+    /// Adds and removes labels of a message by name, creating the user
+    /// labels that don't exist yet. With `user_only`, system labels of the
+    /// same name are left alone.
+    async fn update_labels(
+        &self,
+        message_id: &MessageId,
+        changes: &[(&str, bool)],
+        user_only: bool,
+    ) -> MailinerResult<()> {
+        let (_, id) = parse_message_id(message_id)?;
+        let mut labels = self.labels().await?;
+        let mut add = Vec::new();
+        let mut remove = Vec::new();
+        for (name, value) in changes {
+            let existing = labels
+                .values()
+                .find(|label| (!user_only || label.kind == "user") && label.name == *name)
+                .map(|label| label.id.clone());
+            match (existing, value) {
+                (Some(label_id), true) => add.push(label_id),
+                (Some(label_id), false) => remove.push(label_id),
+                (None, true) => {
+                    let request = Label {
+                        name: name.to_string(),
+                        ..Default::default()
+                    };
+                    let label: Label = self.send("POST", "/labels", &request).await?;
+                    add.push(label.id.clone());
+                    labels.insert(label.id.clone(), label);
+                }
+                (None, false) => {}
+            }
+        }
+        *self.labels.lock().unwrap() = labels;
+        self.modify(
+            &id,
+            add.iter().map(String::as_str).collect(),
+            remove.iter().map(String::as_str).collect(),
+        )
+        .await
+    }
+
+    /// Adds and removes labels of a message.
+    async fn modify(&self, id: &str, add: Vec<&str>, remove: Vec<&str>) -> MailinerResult<()> {
+        let request = api::ModifyRequest {
+            add_label_ids: add,
+            remove_label_ids: remove,
+        };
+        let _: api::Message = self
+            .send(
+                "POST",
+                &format!("/messages/{}/modify", encode(id)),
+                &request,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+fn parse_history_id(history_id: &str) -> Result<u64, GmailError> {
+    history_id
+        .parse()
+        .map_err(|_| GmailError::InvalidData(format!("Invalid history id: {}", history_id)))
+}
+
+/// Registers the `gmail` scheme, `gmail://`. The credentials passed to
+/// `authenticate` are the access token.
+pub fn register<S>(registry: &mut ConnectorRegistry<S>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync + 'static,
+{
+    registry.register(
+        "gmail",
+        |_: &BackendUri| -> MailinerResult<Arc<dyn EmailConnector<S>>> {
+            Ok(Arc::new(GmailConnector::<S>::new()))
+        },
+    );
+}
+
+#[async_trait]
+impl<S> EmailConnector<S> for GmailConnector<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync + 'static,
+{
+    async fn connect(&self, stream: S) -> MailinerResult<()> {
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() {
+            let mut stream = MaybeTlsStream::Plain(stream);
+            if !self.external_tls {
+                info!("Establishing TLS connection...");
+                stream = stream.start_tls(&self.host).await?;
+            }
+            *connection = Some(HttpConnection::new(stream, &self.host));
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> MailinerResult<()> {
+        self.connection.lock().await.take();
+        Ok(())
+    }
+
+    /// `credentials` is the OAuth access token, unless there is a token
+    /// provider.
+    async fn authenticate(&self, credentials: &str) -> MailinerResult<Account> {
+        if self.tokens.is_none() {
+            *self.access_token.lock().unwrap() = Some(credentials.to_string());
+        }
+        let profile: api::Profile = self.get("/profile").await?;
+        let id = AccountId::new(format!("gmail-{}", profile.email_address));
+        *self.account_id.lock().unwrap() = Some(id.clone());
+        Ok(Account {
+            id,
+            name: profile.email_address.clone(),
+            email: profile.email_address,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        })
+    }
+
+    async fn list_folders(&self, account_id: &AccountId) -> MailinerResult<Vec<Folder>> {
+        let labels = self.refresh_labels().await?;
+        let mut folders: Vec<Folder> = labels
+            .values()
+            .filter(|label| is_folder(label))
+            .map(|label| Self::folder(account_id, label, &labels))
+            .collect();
+        folders.sort_by(|a, b| a.id.as_str().cmp(b.id.as_str()));
+        Ok(folders)
+    }
+
+    async fn create_folder(
+        &self,
+        account_id: &AccountId,
+        name: &str,
+        parent_id: Option<&FolderId>,
+    ) -> MailinerResult<Folder> {
+        if name.contains('/') {
+            return Err(
+                GmailError::InvalidData("Label names must not contain \"/\"".to_string()).into(),
+            );
+        }
+        let full_name = match parent_id {
+            Some(parent_id) => format!("{}/{}", self.label(parent_id).await?.name, name),
+            None => name.to_string(),
+        };
+        let request = Label {
+            name: full_name,
+            ..Default::default()
+        };
+        let label: Label = self.send("POST", "/labels", &request).await?;
+        let mut labels = self.labels().await?;
+        labels.insert(label.id.clone(), label.clone());
+        *self.labels.lock().unwrap() = labels.clone();
+        Ok(Self::folder(account_id, &label, &labels))
+    }
+
+    async fn delete_folder(&self, folder_id: &FolderId) -> MailinerResult<()> {
+        self.call(
+            "DELETE",
+            &format!("/labels/{}", encode(folder_id.as_str())),
+            None,
+        )
+        .await?;
+        self.labels.lock().unwrap().remove(folder_id.as_str());
+        Ok(())
+    }
+
+    /// Label ids don't change with the name. Nested labels are renamed
+    /// along, which the API doesn't do on its own.
+    async fn rename_folder(
+        &self,
+        folder_id: &FolderId,
+        new_name: &str,
+    ) -> MailinerResult<FolderId> {
+        if new_name.contains('/') {
+            return Err(
+                GmailError::InvalidData("Label names must not contain \"/\"".to_string()).into(),
+            );
+        }
+        let label = self.label(folder_id).await?;
+        let new_full_name = match label.name.rsplit_once('/') {
+            Some((parent, _)) => format!("{}/{}", parent, new_name),
+            None => new_name.to_string(),
+        };
+        let prefix = format!("{}/", label.name);
+        let mut renames = vec![(label.id.clone(), new_full_name.clone())];
+        for nested in self.labels().await?.values() {
+            if let Some(rest) = nested.name.strip_prefix(&prefix) {
+                renames.push((nested.id.clone(), format!("{}/{}", new_full_name, rest)));
+            }
+        }
+        for (id, name) in renames {
+            let request = Label {
+                name,
+                ..Default::default()
+            };
+            let _: Label = self
+                .send("PATCH", &format!("/labels/{}", encode(&id)), &request)
+                .await?;
+        }
+        self.refresh_labels().await?;
+        Ok(folder_id.clone())
+    }
+
+    async fn list_envelopes(&self, folder_id: &FolderId) -> MailinerResult<Vec<Envelope>> {
+        let ids = self.list_message_ids(folder_id, None, None).await?;
+        self.envelopes(folder_id, &ids).await
+    }
+
+    async fn list_envelopes_range(
+        &self,
+        folder_id: &FolderId,
+        range: Range<usize>,
+    ) -> MailinerResult<Vec<Envelope>> {
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let ids = self
+            .list_message_ids(folder_id, None, Some(range.end))
+            .await?;
+        let ids = &ids[range.start.min(ids.len())..range.end.min(ids.len())];
+        self.envelopes(folder_id, ids).await
+    }
+
+    async fn get_envelope(&self, message_id: &MessageId) -> MailinerResult<Envelope> {
+        let (folder_id, id) = parse_message_id(message_id)?;
+        let message = self.get_message(&id, "metadata").await?;
+        Ok(message::envelope(
+            &message,
+            &self.account_id()?,
+            &folder_id,
+            &self.labels().await?,
+        ))
+    }
+
+    async fn sync_folder(
+        &self,
+        folder_id: &FolderId,
+        state: Option<&FolderSyncState>,
+        known: &[MessageId],
+    ) -> MailinerResult<FolderDelta> {
+        if let Some(state) = state.filter(|state| state.uid_validity == UID_VALIDITY) {
+            match self.history(state.highest_modseq, None).await {
+                Ok((history, history_id)) => {
+                    let changes = Changes::new(&history);
+                    let account_id = self.account_id()?;
+                    let labels = self.labels().await?;
+                    let mut changed = Vec::new();
+                    let mut gone: Vec<&String> = changes.deleted.iter().collect();
+                    for id in &changes.touched {
+                        match self.get_message(id, "metadata").await {
+                            Ok(message)
+                                if message
+                                    .label_ids
+                                    .iter()
+                                    .any(|label| label == folder_id.as_str()) =>
+                            {
+                                changed.push(message::envelope(
+                                    &message,
+                                    &account_id,
+                                    folder_id,
+                                    &labels,
+                                ));
+                            }
+                            // The label was removed.
+                            Ok(_) | Err(MailinerError::NotFound(_)) => gone.push(id),
+                            Err(e) => return Err(e),
+                        }
+                    }
+                    return Ok(FolderDelta {
+                        sync_state: Some(FolderSyncState {
+                            uid_validity: UID_VALIDITY,
+                            highest_modseq: history_id,
+                        }),
+                        changed,
+                        vanished: gone
+                            .into_iter()
+                            .map(|id| to_message_id(folder_id, id))
+                            .filter(|id| known.contains(id))
+                            .collect(),
+                        read_only: false,
+                    });
+                }
+                Err(MailinerError::NotFound(_)) => {
+                    info!("History of {} expired, fetching all messages", folder_id);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Taken before listing, so that changes made meanwhile are fetched
+        // again next time rather than missed.
+        let history_id = self.current_history_id().await?;
+        let changed = self.list_envelopes(folder_id).await?;
+        let current: HashSet<&MessageId> = changed.iter().map(|envelope| &envelope.id).collect();
+        let vanished = known
+            .iter()
+            .filter(|id| !current.contains(id))
+            .cloned()
+            .collect();
+        Ok(FolderDelta {
+            sync_state: Some(FolderSyncState {
+                uid_validity: UID_VALIDITY,
+                highest_modseq: history_id,
+            }),
+            changed,
+            vanished,
+            read_only: false,
+        })
+    }
+
+    async fn resync_flags(
+        &self,
+        folder_id: &FolderId,
+        since_modseq: u64,
+    ) -> MailinerResult<Vec<FlagUpdate>> {
+        let ids: Vec<String> = match self.history(since_modseq, Some(folder_id)).await {
+            Ok((history, _)) => Changes::new(&history).touched.into_iter().collect(),
+            Err(MailinerError::NotFound(_)) => self.list_message_ids(folder_id, None, None).await?,
+            Err(e) => return Err(e),
+        };
+        let labels = self.labels().await?;
+        let mut updates = Vec::new();
+        for id in ids {
+            match self.get_message(&id, "minimal").await {
+                Ok(message)
+                    if message
+                        .label_ids
+                        .iter()
+                        .any(|label| label == folder_id.as_str()) =>
+                {
+                    updates.push(message::flag_update(&message, folder_id, &labels));
+                }
+                Ok(_) | Err(MailinerError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(updates)
+    }
+
+    async fn update_envelope_flags(
+        &self,
+        message_id: &MessageId,
+        flags: &[(&str, bool)],
+    ) -> MailinerResult<()> {
+        let (_, id) = parse_message_id(message_id)?;
+        let mut add = Vec::new();
+        let mut remove = Vec::new();
+        for (flag, value) in flags {
+            let (label, inverted) = message::flag_label(flag)
+                .ok_or_else(|| GmailError::InvalidData(format!("Unknown flag: {}", flag)))?;
+            if *value != inverted {
+                add.push(label);
+            } else {
+                remove.push(label);
+            }
+        }
+        self.modify(&id, add, remove).await
+    }
+
+    /// Keywords are user labels, which are created when first used.
+    async fn update_envelope_keywords(
+        &self,
+        message_id: &MessageId,
+        keywords: &[(&str, bool)],
+    ) -> MailinerResult<()> {
+        self.update_labels(message_id, keywords, true).await
+    }
+
+    async fn list_labels(
+        &self,
+        account_id: &AccountId,
+    ) -> MailinerResult<Vec<mailiner_core::Label>> {
+        let mut labels: Vec<_> = self
+            .refresh_labels()
+            .await?
+            .values()
+            .map(|label| message::label(account_id, label))
+            .collect();
+        labels.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(labels)
+    }
+
+    /// Unlike keywords, these can be system labels too, e.g. `IMPORTANT`.
+    async fn update_envelope_labels(
+        &self,
+        message_id: &MessageId,
+        labels: &[(&str, bool)],
+    ) -> MailinerResult<()> {
+        self.update_labels(message_id, labels, false).await
+    }
+
+    async fn move_message(
+        &self,
+        message_id: &MessageId,
+        target_folder_id: &FolderId,
+    ) -> MailinerResult<Option<MessageId>> {
+        let (folder_id, id) = parse_message_id(message_id)?;
+        if target_folder_id.as_str() == TRASH {
+            // Trashing also removes the other labels' claim on the message,
+            // e.g. it leaves the INBOX.
+            self.call("POST", &format!("/messages/{}/trash", encode(&id)), None)
+                .await?;
+        } else if &folder_id != target_folder_id {
+            self.modify(
+                &id,
+                vec![target_folder_id.as_str()],
+                vec![folder_id.as_str()],
+            )
+            .await?;
+        }
+        Ok(Some(to_message_id(target_folder_id, &id)))
+    }
+
+    async fn copy_message(
+        &self,
+        message_id: &MessageId,
+        target_folder_id: &FolderId,
+    ) -> MailinerResult<Option<MessageId>> {
+        let (_, id) = parse_message_id(message_id)?;
+        self.modify(&id, vec![target_folder_id.as_str()], Vec::new())
+            .await?;
+        Ok(Some(to_message_id(target_folder_id, &id)))
+    }
+
+    /// Gmail learns from messages moved into and out of SPAM, and has no use
+    /// for the keywords.
+    async fn mark_junk(
+        &self,
+        message_id: &MessageId,
+        _junk: bool,
+        target_folder_id: &FolderId,
+    ) -> MailinerResult<Option<MessageId>> {
+        self.move_message(message_id, target_folder_id).await
+    }
+
+    /// Messages appended to `DRAFT` become drafts, which the DRAFT label
+    /// can't be added to on its own.
+    async fn append_message(
+        &self,
+        folder_id: &FolderId,
+        flags: &[&str],
+        content: &[u8],
+    ) -> MailinerResult<Option<MessageId>> {
+        let mut label_ids = message::append_labels(flags);
+        let id = if folder_id.as_str() == DRAFT {
+            let request = api::DraftRequest {
+                message: api::InsertRequest {
+                    raw: api::encode(content),
+                    label_ids,
+                },
+            };
+            let draft: api::Draft = self.send("POST", "/drafts", &request).await?;
+            draft.message.id
+        } else {
+            label_ids.push(folder_id.as_str());
+            let request = api::InsertRequest {
+                raw: api::encode(content),
+                label_ids,
+            };
+            let message: api::Message = self
+                .send("POST", "/messages?internalDateSource=dateHeader", &request)
+                .await?;
+            message.id
+        };
+        Ok(Some(to_message_id(folder_id, &id)))
+    }
+
+    /// Only messages in the trash, in spam and drafts are deleted for good.
+    /// Elsewhere the message just loses the label, and stays in All Mail like
+    /// with Gmail's IMAP server.
+    async fn delete_message(&self, message_id: &MessageId) -> MailinerResult<()> {
+        let (folder_id, id) = parse_message_id(message_id)?;
+        if [TRASH, SPAM, DRAFT].contains(&folder_id.as_str()) {
+            self.call("DELETE", &format!("/messages/{}", encode(&id)), None)
+                .await?;
+            Ok(())
+        } else {
+            self.modify(&id, Vec::new(), vec![folder_id.as_str()]).await
+        }
+    }
+
+    async fn search(
+        &self,
+        folder_id: &FolderId,
+        query: &SearchQuery,
+    ) -> MailinerResult<Vec<MessageId>> {
+        let query = query::search_query(query);
+        let ids = self
+            .list_message_ids(
+                folder_id,
+                Some(&query).filter(|q| !q.is_empty()).map(|q| q.as_str()),
+                None,
+            )
+            .await?;
+        Ok(ids.iter().map(|id| to_message_id(folder_id, id)).collect())
+    }
+
+    async fn get_message_structure(
+        &self,
+        message_id: &MessageId,
+    ) -> MailinerResult<MessageStructure> {
+        let (_, id) = parse_message_id(message_id)?;
+        let message = self.get_message(&id, "full").await?;
+        let payload = message
+            .payload
+            .ok_or_else(|| GmailError::InvalidData(format!("Message {} has no content", id)))?;
+        Ok(message::structure(&payload))
+    }
+
+    async fn fetch_raw_message(&self, message_id: &MessageId) -> MailinerResult<Vec<u8>> {
+        let (_, id) = parse_message_id(message_id)?;
+        let message = self.get_message(&id, "raw").await?;
+        let raw = message
+            .raw
+            .ok_or_else(|| GmailError::InvalidData(format!("Message {} has no content", id)))?;
+        Ok(api::decode(&raw)?)
+    }
+
+    async fn get_message_part(
+        &self,
+        message_id: &MessageId,
+        part_id: &MessagePartId,
+    ) -> MailinerResult<MessagePart> {
+        let (_, id) = parse_message_id(message_id)?;
+        let message = self.get_message(&id, "full").await?;
+        let part = message
+            .payload
+            .as_ref()
+            .and_then(|payload| message::find_part(payload, part_id))
+            .ok_or_else(|| {
+                MailinerError::NotFound(format!("No part {} in {}", part_id, message_id))
+            })?;
+
+        // Large parts are left out and fetched as attachments.
+        let data = match (&part.body.data, &part.body.attachment_id) {
+            (Some(data), _) => api::decode(data)?,
+            (None, Some(attachment_id)) => {
+                let attachment: api::Attachment = self
+                    .get(&format!(
+                        "/messages/{}/attachments/{}",
+                        encode(&id),
+                        encode(attachment_id)
+                    ))
+                    .await?;
+                api::decode(&attachment.data)?
+            }
+            (None, None) => Vec::new(),
+        };
+        let structure = message::structure(part);
+        Ok(MessagePart {
+            id: part_id.clone(),
+            envelope_id: message_id.clone(),
+            content_type: structure.content_type,
+            filename: structure.filename,
+            size: data.len() as u64,
+            is_attachment: structure.is_attachment,
+            content: message::decode_content(part, data),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        })
+    }
+
+    /// Polls the history of the label, as there is no push without Cloud
+    /// Pub/Sub. Gmail has no sequence numbers, so events carry 0 instead.
+    async fn idle(
+        &self,
+        folder_id: &FolderId,
+        timeout: Duration,
+    ) -> MailinerResult<Vec<FolderEvent>> {
+        let timer = self.timer.clone().ok_or_else(|| {
+            GmailError::InvalidData("Watching a folder needs a timer, see with_timer".to_string())
+        })?;
+        let mut history_id = self.current_history_id().await?;
+        let mut waited = Duration::ZERO;
+        while waited < timeout {
+            let interval = self.poll_interval.min(timeout - waited);
+            timer.sleep(interval).await;
+            waited += interval;
+
+            let (history, next) = self.history(history_id, Some(folder_id)).await?;
+            history_id = next;
+            let history: Vec<_> = history
+                .into_iter()
+                .filter(|record| !record.is_empty())
+                .collect();
+            if history.is_empty() {
+                continue;
+            }
+
+            let mut events = Vec::new();
+            if history
+                .iter()
+                .any(|record| !record.messages_added.is_empty())
+            {
+                let label = self.label(folder_id).await?;
+                let exists: Label = self.get(&format!("/labels/{}", encode(&label.id))).await?;
+                events.push(FolderEvent::NewMessages {
+                    folder_id: folder_id.clone(),
+                    exists: exists.messages_total.try_into().unwrap_or(u32::MAX),
+                });
+            }
+            for record in &history {
+                events.extend(record.messages_deleted.iter().map(|_| {
+                    FolderEvent::MessageExpunged {
+                        folder_id: folder_id.clone(),
+                        sequence: 0,
+                    }
+                }));
+                events.extend(
+                    record
+                        .labels_added
+                        .iter()
+                        .chain(&record.labels_removed)
+                        .map(|_| FolderEvent::FlagsChanged {
+                            folder_id: folder_id.clone(),
+                            sequence: 0,
+                        }),
+                );
+            }
+            return Ok(events);
+        }
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream};
+
+    use super::*;
+
+    /// Plays the API: for each step, checks the start of the request line
+    /// and sends the status with the JSON body.
+    async fn serve(server: DuplexStream, script: Vec<(&'static str, u16, &'static str)>) {
+        let mut server = BufReader::new(server);
+        for (expected, status, body) in script {
+            let mut request_line = String::new();
+            server.read_line(&mut request_line).await.unwrap();
+            assert!(request_line.starts_with(expected), "{}", request_line);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                server.read_line(&mut line).await.unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(length) = line.strip_prefix("Content-Length: ") {
+                    content_length = length.trim().parse().unwrap();
+                }
+            }
+            let mut request_body = vec![0; content_length];
+            server.read_exact(&mut request_body).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            server
+                .get_mut()
+                .write_all(response.as_bytes())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn syncs_from_the_history() {
+        let (client, server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(serve(
+            server,
+            vec![
+                (
+                    "GET /gmail/v1/users/me/profile ",
+                    200,
+                    r#"{"emailAddress": "jan@gmail.com", "historyId": "100"}"#,
+                ),
+                (
+                    "GET /gmail/v1/users/me/history?startHistoryId=90&",
+                    200,
+                    r#"{"history": [
+                        {"messagesAdded": [{"message": {"id": "m1"}}]},
+                        {"labelsRemoved": [{"message": {"id": "m2"}}]},
+                        {"messagesDeleted": [{"message": {"id": "m3"}}]}
+                    ], "historyId": "105"}"#,
+                ),
+                (
+                    "GET /gmail/v1/users/me/labels ",
+                    200,
+                    r#"{"labels": [{"id": "INBOX", "name": "INBOX", "type": "system"}]}"#,
+                ),
+                (
+                    "GET /gmail/v1/users/me/messages/m",
+                    200,
+                    r#"{"id": "m1", "labelIds": ["INBOX", "UNREAD"]}"#,
+                ),
+                (
+                    "GET /gmail/v1/users/me/messages/m",
+                    200,
+                    r#"{"id": "m2", "labelIds": ["Label_1"]}"#,
+                ),
+                (
+                    "POST /gmail/v1/users/me/messages/m1/modify ",
+                    200,
+                    r#"{"id": "m1"}"#,
+                ),
+                (
+                    "GET /gmail/v1/users/me/profile ",
+                    401,
+                    r#"{"error": {"code": 401, "message": "Invalid Credentials"}}"#,
+                ),
+            ],
+        ));
+
+        let gmail = GmailConnector::new().with_external_tls(true);
+        gmail.connect(client).await.unwrap();
+        let account = gmail.authenticate("token").await.unwrap();
+        assert_eq!(account.id, AccountId::new("gmail-jan@gmail.com"));
+
+        let inbox = FolderId::new("INBOX");
+        let known = [to_message_id(&inbox, "m2"), to_message_id(&inbox, "m3")];
+        let state = FolderSyncState {
+            uid_validity: UID_VALIDITY,
+            highest_modseq: 90,
+        };
+        let delta = gmail
+            .sync_folder(&inbox, Some(&state), &known)
+            .await
+            .unwrap();
+        assert_eq!(delta.sync_state.unwrap().highest_modseq, 105);
+        assert_eq!(delta.changed.len(), 1);
+        assert_eq!(delta.changed[0].id.as_str(), "INBOX;ID=m1");
+        assert!(!delta.changed[0].is_read);
+        // m2 lost the label, m3 was deleted.
+        assert_eq!(delta.vanished, [known[1].clone(), known[0].clone()]);
+
+        gmail
+            .update_envelope_flags(&to_message_id(&inbox, "m1"), &[("is_read", true)])
+            .await
+            .unwrap();
+        let error = gmail.authenticate("expired").await.unwrap_err();
+        assert!(
+            error.to_string().contains("Invalid Credentials"),
+            "{}",
+            error
+        );
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn message_ids() {
+        let id = to_message_id(&FolderId::new("Label_12"), "18c2a");
+        assert_eq!(id.as_str(), "Label_12;ID=18c2a");
+        let (folder_id, gmail_id) = parse_message_id(&id).unwrap();
+        assert_eq!(folder_id.as_str(), "Label_12");
+        assert_eq!(gmail_id, "18c2a");
+        assert!(parse_message_id(&MessageId::new("INBOX;UID=4")).is_err());
+    }
+}
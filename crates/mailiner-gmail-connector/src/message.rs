@@ -0,0 +1,368 @@
+//! Conversion of Gmail messages into envelopes and MIME trees. Flags are
+//! labels in Gmail, e.g. a message without the `UNREAD` label is read.
+
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::{DateTime, Utc};
+use mail_parser::{MessageParser, PartType};
+use mailiner_core::mime;
+use mailiner_core::{
+    AccountId, Envelope, FlagUpdate, FolderId, LabelKind, MessageContent, MessagePartId,
+    MessageStructure,
+};
+
+use crate::api::{self, Label};
+use crate::to_message_id;
+
+pub(crate) const UNREAD: &str = "UNREAD";
+pub(crate) const STARRED: &str = "STARRED";
+pub(crate) const DRAFT: &str = "DRAFT";
+pub(crate) const TRASH: &str = "TRASH";
+pub(crate) const SPAM: &str = "SPAM";
+
+/// The headers requested for envelopes.
+pub(crate) const ENVELOPE_HEADERS: &[&str] = &[
+    "From",
+    "To",
+    "Cc",
+    "Bcc",
+    "Reply-To",
+    "Subject",
+    "Date",
+    "Message-ID",
+    "In-Reply-To",
+    "References",
+    "List-Unsubscribe",
+    "List-Unsubscribe-Post",
+];
+
+/// The Gmail label for a flag of [`EmailConnector::update_envelope_flags`],
+/// and whether the label means the flag is unset.
+///
+/// [`EmailConnector::update_envelope_flags`]: mailiner_core::EmailConnector::update_envelope_flags
+pub(crate) fn flag_label(flag: &str) -> Option<(&'static str, bool)> {
+    match flag {
+        "is_read" => Some((UNREAD, true)),
+        "is_flagged" | "is_starred" => Some((STARRED, false)),
+        "is_draft" => Some((DRAFT, false)),
+        "is_deleted" => Some((TRASH, false)),
+        _ => None,
+    }
+}
+
+/// The labels to add for IMAP system flags, e.g. `\Seen`, when appending.
+pub(crate) fn append_labels(flags: &[&str]) -> Vec<&'static str> {
+    let has = |flag: &str| flags.iter().any(|f| f.eq_ignore_ascii_case(flag));
+    let mut labels = Vec::new();
+    if !has("\\Seen") {
+        labels.push(UNREAD);
+    }
+    if has("\\Flagged") {
+        labels.push(STARRED);
+    }
+    labels
+}
+
+/// The user labels of a message, which double as its keywords.
+fn keywords(message: &api::Message, labels: &HashMap<String, Label>) -> BTreeSet<String> {
+    message
+        .label_ids
+        .iter()
+        .filter_map(|id| labels.get(id))
+        .filter(|label| label.kind == "user")
+        .map(|label| label.name.clone())
+        .collect()
+}
+
+/// The label of the core model for a Gmail label.
+pub(crate) fn label(account_id: &AccountId, label: &Label) -> mailiner_core::Label {
+    mailiner_core::Label {
+        account_id: account_id.clone(),
+        name: label.name.clone(),
+        color: label
+            .color
+            .as_ref()
+            .map(|color| color.background_color.clone()),
+        kind: if label.kind == "system" {
+            LabelKind::System
+        } else {
+            LabelKind::User
+        },
+    }
+}
+
+pub(crate) fn flag_update(
+    message: &api::Message,
+    folder_id: &FolderId,
+    labels: &HashMap<String, Label>,
+) -> FlagUpdate {
+    let has = |label: &str| message.label_ids.iter().any(|id| id == label);
+    FlagUpdate {
+        message_id: to_message_id(folder_id, &message.id),
+        is_read: !has(UNREAD),
+        // Gmail's IMAP server shows stars as \Flagged too.
+        is_starred: has(STARRED),
+        is_flagged: has(STARRED),
+        is_draft: has(DRAFT),
+        is_deleted: has(TRASH),
+        keywords: keywords(message, labels),
+    }
+}
+
+/// Builds the envelope of a message fetched with its metadata headers.
+pub(crate) fn envelope(
+    message: &api::Message,
+    account_id: &AccountId,
+    folder_id: &FolderId,
+    labels: &HashMap<String, Label>,
+) -> Envelope {
+    let payload = message.payload.as_ref();
+    let mut headers: String = payload
+        .map(|payload| payload.headers.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .map(|header| format!("{}: {}\r\n", header.name, header.value))
+        .collect();
+    headers.push_str("\r\n");
+    let parsed = MessageParser::new().parse_headers(headers.as_bytes());
+    let received = message
+        .internal_date
+        .as_deref()
+        .and_then(|date| date.parse().ok())
+        .and_then(DateTime::from_timestamp_millis)
+        .unwrap_or_else(Utc::now);
+    let flags = flag_update(message, folder_id, labels);
+
+    Envelope {
+        id: flags.message_id,
+        account_id: account_id.clone(),
+        folder_id: folder_id.clone(),
+        subject: parsed
+            .as_ref()
+            .and_then(|m| m.subject())
+            .map(|s| s.to_string()),
+        from: parsed.as_ref().and_then(|m| mime::email_address(m.from())),
+        to: parsed.as_ref().and_then(|m| mime::email_address(m.to())),
+        cc: parsed.as_ref().and_then(|m| mime::email_address(m.cc())),
+        bcc: parsed.as_ref().and_then(|m| mime::email_address(m.bcc())),
+        reply_to: parsed.as_ref().and_then(|m| mime::email_address(m.reply_to())),
+        date: parsed
+            .as_ref()
+            .and_then(mime::date)
+            .unwrap_or(received),
+        is_read: flags.is_read,
+        is_starred: flags.is_starred,
+        is_flagged: flags.is_flagged,
+        is_draft: flags.is_draft,
+        is_deleted: flags.is_deleted,
+        has_attachments: payload.is_some_and(has_attachments),
+        size: message.size_estimate,
+        keywords: flags.keywords,
+        labels: message
+            .label_ids
+            .iter()
+            .map(|id| labels.get(id).map_or(id, |label| &label.name).clone())
+            .collect(),
+        snoozed_until: None,
+        thread_id: message.thread_id.clone(),
+        remote_id: Some(message.id.clone()),
+        message_id: parsed
+            .as_ref()
+            .and_then(|m| m.message_id())
+            .map(|id| id.to_string()),
+        in_reply_to: parsed
+            .as_ref()
+            .map(|m| mime::id_list(m.in_reply_to()))
+            .unwrap_or_default(),
+        references: parsed
+            .as_ref()
+            .map(|m| mime::id_list(m.references()))
+            .unwrap_or_default(),
+        unsubscribe: parsed.as_ref().and_then(mime::unsubscribe),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    }
+}
+
+/// Without the full structure, which metadata requests don't return, a
+/// `multipart/mixed` message is taken to have attachments.
+fn has_attachments(part: &api::MessagePart) -> bool {
+    !part.filename.is_empty()
+        || part.mime_type.eq_ignore_ascii_case("multipart/mixed")
+        || part.parts.iter().any(has_attachments)
+}
+
+/// The part id of a Gmail part. The root part has an empty id, which only
+/// needs an id of its own if it isn't a multipart, in which case there is no
+/// part `0`.
+pub(crate) fn part_id(part: &api::MessagePart) -> MessagePartId {
+    if part.part_id.is_empty() {
+        MessagePartId::new("0")
+    } else {
+        MessagePartId::new(part.part_id.clone())
+    }
+}
+
+/// Finds the part with the given id, see [`part_id`].
+pub(crate) fn find_part<'a>(
+    payload: &'a api::MessagePart,
+    part_id: &MessagePartId,
+) -> Option<&'a api::MessagePart> {
+    payload
+        .find(part_id.as_str())
+        .or_else(|| (part_id.as_str() == "0" && payload.parts.is_empty()).then_some(payload))
+}
+
+fn is_attachment(part: &api::MessagePart) -> bool {
+    part.header("Content-Disposition")
+        .is_some_and(|disposition| {
+            disposition
+                .trim_start()
+                .to_ascii_lowercase()
+                .starts_with("attachment")
+        })
+}
+
+pub(crate) fn structure(part: &api::MessagePart) -> MessageStructure {
+    let content_type = part.mime_type.to_ascii_lowercase();
+    let multipart = content_type.starts_with("multipart/");
+    MessageStructure {
+        id: (!multipart).then(|| part_id(part)),
+        content_type,
+        filename: (!part.filename.is_empty()).then(|| part.filename.clone()),
+        size: (!multipart).then_some(part.body.size),
+        is_attachment: is_attachment(part),
+        content_id: part.header("Content-ID").map(|id| {
+            id.trim().trim_start_matches('<').trim_end_matches('>').to_string()
+        }),
+        children: part.parts.iter().map(structure).collect(),
+    }
+}
+
+/// The API decodes the transfer encoding, text still has to be converted to
+/// UTF-8 from its charset.
+pub(crate) fn decode_content(part: &api::MessagePart, data: Vec<u8>) -> MessageContent {
+    let content_type = part.mime_type.to_ascii_lowercase();
+    if !content_type.starts_with("text/") {
+        return MessageContent::Binary(data);
+    }
+    let mut message = format!(
+        "Content-Type: {}\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+        part.header("Content-Type").unwrap_or(&content_type)
+    )
+    .into_bytes();
+    message.extend_from_slice(&data);
+
+    let body = MessageParser::new()
+        .parse(&message)
+        .and_then(|message| message.parts.into_iter().next())
+        .map(|part| part.body);
+    match body {
+        Some(PartType::Html(html)) => MessageContent::Html(html.into_owned()),
+        Some(PartType::Text(text)) => MessageContent::Text(text.into_owned()),
+        _ => MessageContent::Text(String::from_utf8_lossy(&data).into_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels() -> HashMap<String, Label> {
+        [("Label_1", "Work", "user"), ("INBOX", "INBOX", "system")]
+            .into_iter()
+            .map(|(id, name, kind)| {
+                let label = Label {
+                    id: id.to_string(),
+                    name: name.to_string(),
+                    kind: kind.to_string(),
+                    ..Default::default()
+                };
+                (id.to_string(), label)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn envelope_from_metadata() {
+        let message: api::Message = serde_json::from_str(
+            r#"{
+                "id": "18c2a", "threadId": "18c29",
+                "labelIds": ["INBOX", "STARRED", "Label_1"],
+                "internalDate": "1700000000000", "sizeEstimate": 8200,
+                "payload": {
+                    "mimeType": "multipart/mixed",
+                    "headers": [
+                        {"name": "From", "value": "Jan <jan@example.com>"},
+                        {"name": "Subject", "value": "=?UTF-8?Q?Gr=C3=BC=C3=9Fe?="},
+                        {"name": "In-Reply-To", "value": "<a@example.com>"}
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+        let envelope = envelope(
+            &message,
+            &AccountId::new("gmail-jan@example.com"),
+            &FolderId::new("INBOX"),
+            &labels(),
+        );
+        assert_eq!(envelope.id.as_str(), "INBOX;ID=18c2a");
+        assert_eq!(envelope.subject.as_deref(), Some("Grüße"));
+        assert_eq!(
+            envelope.from.map(|from| from.to_string()).as_deref(),
+            Some("Jan <jan@example.com>")
+        );
+        assert_eq!(envelope.in_reply_to, ["a@example.com"]);
+        assert_eq!(envelope.date.timestamp(), 1_700_000_000);
+        assert_eq!(envelope.size, Some(8200));
+        assert!(envelope.is_read && envelope.is_flagged && envelope.has_attachments);
+        assert_eq!(envelope.keywords, BTreeSet::from(["Work".to_string()]));
+        assert_eq!(
+            envelope.labels,
+            BTreeSet::from(["INBOX", "STARRED", "Work"].map(String::from))
+        );
+        assert_eq!(envelope.thread_id.as_deref(), Some("18c29"));
+
+        let work = label(&envelope.account_id, &labels()["Label_1"]);
+        assert_eq!((work.name.as_str(), work.kind), ("Work", LabelKind::User));
+
+        assert_eq!(flag_label("is_read"), Some((UNREAD, true)));
+        assert_eq!(append_labels(&["\\Seen", "\\Flagged"]), [STARRED]);
+        assert_eq!(append_labels(&[]), [UNREAD]);
+    }
+
+    #[test]
+    fn parts() {
+        let payload: api::MessagePart = serde_json::from_str(
+            r#"{
+                "partId": "", "mimeType": "multipart/mixed",
+                "parts": [
+                    {"partId": "0", "mimeType": "text/plain", "body": {"size": 5, "data": "R3L832U"},
+                     "headers": [{"name": "Content-Type", "value": "text/plain; charset=iso-8859-1"}]},
+                    {"partId": "1", "mimeType": "image/png", "filename": "a.png",
+                     "body": {"size": 8000, "attachmentId": "ANGj"},
+                     "headers": [{"name": "Content-Disposition", "value": "attachment; filename=a.png"}]}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let structure = structure(&payload);
+        assert_eq!(structure.id, None);
+        assert_eq!(structure.children[1].id, Some(MessagePartId::new("1")));
+        assert!(structure.children[1].is_attachment);
+        assert_eq!(structure.children[1].size, Some(8000));
+
+        let text = find_part(&payload, &MessagePartId::new("0")).unwrap();
+        let data = api::decode(text.body.data.as_deref().unwrap()).unwrap();
+        match decode_content(text, data) {
+            MessageContent::Text(text) => assert_eq!(text, "Grüße"),
+            other => panic!("unexpected content: {:?}", other),
+        }
+
+        let single: api::MessagePart =
+            serde_json::from_str(r#"{"partId": "", "mimeType": "text/html"}"#).unwrap();
+        assert!(find_part(&single, &MessagePartId::new("0")).is_some());
+        assert_eq!(part_id(&single), MessagePartId::new("0"));
+    }
+}
@@ -0,0 +1,74 @@
+//! Translation of [`SearchQuery`] into Gmail's search syntax, the same as in
+//! the Gmail search box. A raw query is passed on as it is.
+
+use mailiner_core::SearchQuery;
+
+/// Quotes a value as a phrase. Gmail has no escape for quotes, so they are
+/// dropped.
+fn phrase(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', ""))
+}
+
+/// Builds the `q` parameter of `messages.list`.
+pub(crate) fn search_query(query: &SearchQuery) -> String {
+    let mut terms = Vec::new();
+
+    for (operator, value) in [
+        ("from:", &query.from),
+        ("to:", &query.to),
+        ("subject:", &query.subject),
+        ("", &query.body),
+    ] {
+        if let Some(value) = value {
+            terms.push(format!("{}{}", operator, phrase(value)));
+        }
+    }
+    if let Some(since) = query.since {
+        terms.push(format!("after:{}", since.format("%Y/%m/%d")));
+    }
+    if let Some(before) = query.before {
+        terms.push(format!("before:{}", before.format("%Y/%m/%d")));
+    }
+    for (flag, term) in [
+        (query.is_read, "is:read"),
+        (query.is_flagged, "is:starred"),
+        (query.is_draft, "in:drafts"),
+        (query.is_deleted, "in:trash"),
+        (query.has_attachment, "has:attachment"),
+    ] {
+        match flag {
+            Some(true) => terms.push(term.to_string()),
+            Some(false) => terms.push(format!("-{}", term)),
+            None => {}
+        }
+    }
+    if let Some(raw) = &query.raw {
+        terms.push(format!("({})", raw));
+    }
+    terms.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    #[test]
+    fn query_terms() {
+        let query = SearchQuery {
+            from: Some("Jan \"JD\" Doe".to_string()),
+            body: Some("invoice".to_string()),
+            since: NaiveDate::from_ymd_opt(2024, 3, 1),
+            is_read: Some(false),
+            is_flagged: Some(true),
+            raw: Some("has:attachment OR larger:5M".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            search_query(&query),
+            "from:\"Jan JD Doe\" \"invoice\" after:2024/03/01 -is:read is:starred (has:attachment OR larger:5M)"
+        );
+        assert_eq!(search_query(&SearchQuery::default()), "");
+    }
+}
@@ -0,0 +1,195 @@
+//! SASL mechanisms for `AUTHENTICATE`.
+
+use async_imap::Authenticator;
+use base64::prelude::*;
+use hmac::{Hmac, Mac};
+use mailiner_core::oauth::{oauthbearer_response, xoauth2_response};
+use mailiner_core::AuthMethod;
+use md5::Md5;
+
+/// Authenticator for the SASL mechanisms, which all complete in a single
+/// step on success.
+pub(crate) struct SaslAuthenticator<'a> {
+    method: AuthMethod,
+    username: &'a str,
+    secret: &'a str,
+    host: &'a str,
+    port: u16,
+    sent: bool,
+    /// The error details sent by the server when authentication fails.
+    pub(crate) error: Option<String>,
+}
+
+impl<'a> SaslAuthenticator<'a> {
+    pub(crate) fn new(
+        method: AuthMethod,
+        username: &'a str,
+        secret: &'a str,
+        host: &'a str,
+        port: u16,
+    ) -> Self {
+        Self {
+            method,
+            username,
+            secret,
+            host,
+            port,
+            sent: false,
+            error: None,
+        }
+    }
+
+    /// The mechanism to send with AUTHENTICATE. With SASL-IR (RFC 4959) it
+    /// is followed by the initial response, which saves waiting for the
+    /// server's empty challenge. CRAM-MD5 has no initial response, the
+    /// server challenges first.
+    pub(crate) fn command(&mut self, sasl_ir: bool) -> String {
+        let mechanism = mechanism(self.method);
+        if !sasl_ir || matches!(self.method, AuthMethod::Login | AuthMethod::CramMd5) {
+            return mechanism.to_string();
+        }
+        let response = self.initial_response(&[]);
+        format!("{} {}", mechanism, BASE64_STANDARD.encode(response))
+    }
+
+    fn initial_response(&mut self, challenge: &[u8]) -> Vec<u8> {
+        self.sent = true;
+        match self.method {
+            // LOGIN uses the LOGIN command rather than AUTHENTICATE.
+            AuthMethod::Login => Vec::new(),
+            AuthMethod::Plain => format!("\0{}\0{}", self.username, self.secret).into_bytes(),
+            AuthMethod::CramMd5 => cram_md5_response(self.username, self.secret, challenge).into_bytes(),
+            AuthMethod::XOAuth2 => xoauth2_response(self.username, self.secret).into_bytes(),
+            AuthMethod::OAuthBearer => {
+                oauthbearer_response(self.username, self.host, self.port, self.secret).into_bytes()
+            }
+        }
+    }
+}
+
+impl Authenticator for &mut SaslAuthenticator<'_> {
+    type Response = Vec<u8>;
+
+    fn process(&mut self, challenge: &[u8]) -> Self::Response {
+        if !self.sent {
+            return self.initial_response(challenge);
+        }
+        // On failure the OAuth mechanisms send the error (a JSON document)
+        // as a challenge, which must be answered before the server replies
+        // with NO.
+        self.error = Some(String::from_utf8_lossy(challenge).into_owned());
+        match self.method {
+            AuthMethod::OAuthBearer => b"\x01".to_vec(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn mechanism(method: AuthMethod) -> &'static str {
+    match method {
+        AuthMethod::Login => "LOGIN",
+        AuthMethod::Plain => "PLAIN",
+        AuthMethod::CramMd5 => "CRAM-MD5",
+        AuthMethod::XOAuth2 => "XOAUTH2",
+        AuthMethod::OAuthBearer => "OAUTHBEARER",
+    }
+}
+
+/// Picks the strongest method for logging in with a password that the
+/// server advertises, or `None` if it doesn't allow password logins (e.g.
+/// before STARTTLS).
+pub(crate) fn password_method(capabilities: &[String]) -> Option<AuthMethod> {
+    let has = |capability: &str| {
+        capabilities
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case(capability))
+    };
+    if has("AUTH=CRAM-MD5") {
+        // The password never leaves the client.
+        Some(AuthMethod::CramMd5)
+    } else if has("AUTH=PLAIN") {
+        // Unlike LOGIN, PLAIN handles passwords with any characters.
+        Some(AuthMethod::Plain)
+    } else if has("LOGINDISABLED") {
+        None
+    } else {
+        Some(AuthMethod::Login)
+    }
+}
+
+/// Response to a CRAM-MD5 challenge (RFC 2195).
+fn cram_md5_response(username: &str, password: &str, challenge: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Md5>::new_from_slice(password.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(challenge);
+    let digest: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("{} {}", username, digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cram_md5() {
+        // The example from RFC 2195.
+        assert_eq!(
+            cram_md5_response(
+                "tim",
+                "tanstaaftanstaaf",
+                b"<1896.697170952@postoffice.reston.mci.net>"
+            ),
+            "tim b913a602c7eda7a495b4e6e7334d3890"
+        );
+    }
+
+    #[test]
+    fn initial_response_with_command() {
+        let mut authenticator =
+            SaslAuthenticator::new(AuthMethod::Plain, "user", "secret", "imap.example.com", 993);
+        assert_eq!(authenticator.command(true), "PLAIN AHVzZXIAc2VjcmV0");
+        // A challenge now can only be the error.
+        assert_eq!((&mut authenticator).process(b"failed"), b"");
+        assert_eq!(authenticator.error.as_deref(), Some("failed"));
+
+        // CRAM-MD5 needs the server's challenge.
+        let mut authenticator =
+            SaslAuthenticator::new(AuthMethod::CramMd5, "user", "secret", "imap.example.com", 993);
+        assert_eq!(authenticator.command(true), "CRAM-MD5");
+    }
+
+    #[test]
+    fn initial_response_to_challenge() {
+        let mut authenticator =
+            SaslAuthenticator::new(AuthMethod::Plain, "user", "secret", "imap.example.com", 993);
+        assert_eq!(authenticator.command(false), "PLAIN");
+        assert_eq!((&mut authenticator).process(b""), b"\0user\0secret");
+        assert_eq!(authenticator.error, None);
+    }
+
+    #[test]
+    fn strongest_password_method() {
+        let capabilities = |caps: &[&str]| caps.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            password_method(&capabilities(&["IMAP4rev1", "AUTH=PLAIN", "AUTH=CRAM-MD5"])),
+            Some(AuthMethod::CramMd5)
+        );
+        assert_eq!(
+            password_method(&capabilities(&["IMAP4rev1", "AUTH=PLAIN"])),
+            Some(AuthMethod::Plain)
+        );
+        assert_eq!(
+            password_method(&capabilities(&["IMAP4rev1"])),
+            Some(AuthMethod::Login)
+        );
+        assert_eq!(
+            password_method(&capabilities(&["IMAP4rev1", "STARTTLS", "LOGINDISABLED"])),
+            None
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Conversion of BODYSTRUCTURE into the MIME tree of a message.
+
+use imap_proto::types::BodyStructure;
+use mailiner_core::{MessagePartId, MessageStructure};
+
+use crate::mime::describe;
+
+/// Builds the MIME tree of a message from its BODYSTRUCTURE, numbering the
+/// parts like the section numbers of `BODY[]` (RFC 3501, section 6.4.5).
+pub(crate) fn message_structure(bodystructure: &BodyStructure<'_>) -> MessageStructure {
+    match bodystructure {
+        // The root of a multipart message has no section number of its own.
+        BodyStructure::Multipart { bodies, .. } => node(bodystructure, None, children(bodies, "")),
+        _ => part(bodystructure, "1".to_string()),
+    }
+}
+
+fn part(bodystructure: &BodyStructure<'_>, id: String) -> MessageStructure {
+    let children = match bodystructure {
+        BodyStructure::Multipart { bodies, .. } => children(bodies, &id),
+        // The parts of an attached message are numbered within its body.
+        BodyStructure::Message { body, .. } => match body.as_ref() {
+            BodyStructure::Multipart { bodies, .. } => children(bodies, &id),
+            body => vec![part(body, format!("{}.1", id))],
+        },
+        _ => Vec::new(),
+    };
+    node(bodystructure, Some(MessagePartId::new(id)), children)
+}
+
+fn children(bodies: &[BodyStructure<'_>], prefix: &str) -> Vec<MessageStructure> {
+    bodies
+        .iter()
+        .enumerate()
+        .map(|(index, body)| {
+            let id = if prefix.is_empty() {
+                (index + 1).to_string()
+            } else {
+                format!("{}.{}", prefix, index + 1)
+            };
+            part(body, id)
+        })
+        .collect()
+}
+
+fn node(
+    bodystructure: &BodyStructure<'_>,
+    id: Option<MessagePartId>,
+    children: Vec<MessageStructure>,
+) -> MessageStructure {
+    let (content_type, filename, is_attachment) = describe(bodystructure);
+    let (size, content_id) = match bodystructure {
+        BodyStructure::Basic { other, .. }
+        | BodyStructure::Text { other, .. }
+        | BodyStructure::Message { other, .. } => {
+            (Some(u64::from(other.octets)), other.id.as_deref().map(content_id))
+        }
+        BodyStructure::Multipart { .. } => (None, None),
+    };
+    MessageStructure {
+        id,
+        content_type,
+        filename,
+        size,
+        is_attachment,
+        content_id,
+        children,
+    }
+}
+
+/// The `Content-ID` without its angle brackets.
+fn content_id(id: &str) -> String {
+    id.trim().trim_start_matches('<').trim_end_matches('>').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use imap_proto::types::{AttributeValue, Response};
+
+    use super::*;
+
+    #[test]
+    fn numbers_parts_like_sections() {
+        let response = b"* 1 FETCH (BODYSTRUCTURE (((\"TEXT\" \"PLAIN\" (\"CHARSET\" \"UTF-8\") NIL NIL \"7BIT\" 12 1)(\"TEXT\" \"HTML\" (\"CHARSET\" \"UTF-8\") NIL NIL \"7BIT\" 30 1) \"ALTERNATIVE\")(\"APPLICATION\" \"PDF\" (\"NAME\" \"report.pdf\") NIL NIL \"BASE64\" 4096 NIL (\"ATTACHMENT\" (\"FILENAME\" \"report.pdf\")) NIL) \"MIXED\"))\r\n";
+        let (_, response) = Response::from_bytes(response).unwrap();
+        let Response::Fetch(_, attributes) = response else {
+            panic!("expected a FETCH response");
+        };
+        let bodystructure = attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                AttributeValue::BodyStructure(bodystructure) => Some(bodystructure),
+                _ => None,
+            })
+            .unwrap();
+
+        let structure = message_structure(bodystructure);
+        assert_eq!(structure.id, None);
+        assert_eq!(structure.content_type, "multipart/mixed");
+        let html = structure.find("text/html").unwrap();
+        assert_eq!(html.id, Some(MessagePartId::new("1.2")));
+        assert_eq!(html.size, Some(30));
+        let attachments = structure.attachments();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].id, Some(MessagePartId::new("2")));
+        assert_eq!(attachments[0].filename.as_deref(), Some("report.pdf"));
+    }
+}
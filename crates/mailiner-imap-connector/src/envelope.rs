@@ -0,0 +1,216 @@
+//! Conversion of the IMAP ENVELOPE structure (RFC 3501, section 7.4.2).
+
+use chrono::{DateTime, Utc};
+use imap_proto::types::Address;
+use mail_parser::MessageParser;
+use mailiner_core::unsubscribe::Unsubscribe;
+use mailiner_core::{EmailAddr, EmailAddress, Group};
+
+/// Decodes an unstructured header value, such as a subject or a display
+/// name, which may contain RFC 2047 encoded words.
+pub(crate) fn decode_text(raw: &[u8]) -> String {
+    let raw = String::from_utf8_lossy(raw);
+    if !raw.contains("=?") {
+        return raw.into_owned();
+    }
+    let header = format!("Subject: {}\r\n\r\n", raw);
+    MessageParser::new()
+        .parse_headers(header.as_bytes())
+        .and_then(|headers| headers.subject().map(|s| s.to_string()))
+        .unwrap_or_else(|| raw.into_owned())
+}
+
+/// Parses the Date header of an envelope.
+pub(crate) fn parse_date(raw: &[u8]) -> Option<DateTime<Utc>> {
+    let raw = String::from_utf8_lossy(raw);
+    // Drop trailing comments like "(UTC)", which chrono doesn't accept.
+    let raw = raw.split('(').next().unwrap_or_default().trim();
+    DateTime::parse_from_rfc2822(raw)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+/// How to unsubscribe, from the `List-Unsubscribe` headers of a message,
+/// which the ENVELOPE doesn't include.
+pub(crate) fn unsubscribe(header: &[u8]) -> Option<Unsubscribe> {
+    let headers = MessageParser::new().parse_headers(header)?;
+    Unsubscribe::parse(
+        headers.header_raw("List-Unsubscribe")?,
+        headers.header_raw("List-Unsubscribe-Post"),
+    )
+}
+
+/// Converts an address list of an envelope. Groups are encoded in the list
+/// as an address without host whose mailbox is the group name, followed by
+/// the members and an address without mailbox and host.
+pub(crate) fn addresses(list: Option<&[Address<'_>]>) -> Option<EmailAddress> {
+    let list = list?;
+    let mut groups: Vec<Group> = Vec::new();
+    let mut ungrouped = Vec::new();
+    let mut current: Option<Group> = None;
+
+    for address in list {
+        match (&address.mailbox, &address.host) {
+            (Some(name), None) => {
+                current = Some(Group {
+                    name: Some(decode_text(name)),
+                    members: Vec::new(),
+                });
+            }
+            (None, None) => groups.extend(current.take()),
+            _ => {
+                let addr = EmailAddr {
+                    name: address.name.as_deref().map(decode_text),
+                    email: Some(format!(
+                        "{}@{}",
+                        String::from_utf8_lossy(address.mailbox.as_deref().unwrap_or_default()),
+                        String::from_utf8_lossy(address.host.as_deref().unwrap_or_default())
+                    )),
+                };
+                match &mut current {
+                    Some(group) => group.members.push(addr),
+                    None => ungrouped.push(addr),
+                }
+            }
+        }
+    }
+    groups.extend(current);
+
+    if groups.is_empty() {
+        Some(EmailAddress::List(ungrouped))
+    } else {
+        // Like mail-parser, addresses outside of a group go into an unnamed one.
+        if !ungrouped.is_empty() {
+            groups.insert(
+                0,
+                Group {
+                    name: None,
+                    members: ungrouped,
+                },
+            );
+        }
+        Some(EmailAddress::Group(groups))
+    }
+}
+
+/// Parses the message ids of a Message-ID or In-Reply-To field, e.g.
+/// `<1234@local.machine.example>`, without the angle brackets.
+pub(crate) fn message_ids(raw: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(raw)
+        .split('<')
+        .skip(1)
+        .filter_map(|id| id.split_once('>'))
+        .map(|(id, _)| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use imap_proto::types::{AttributeValue, Response};
+
+    use super::*;
+
+    fn address<'a>(
+        name: Option<&'a str>,
+        mailbox: Option<&'a str>,
+        host: Option<&'a str>,
+    ) -> Address<'a> {
+        Address {
+            name: name.map(|s| Cow::Borrowed(s.as_bytes())),
+            adl: None,
+            mailbox: mailbox.map(|s| Cow::Borrowed(s.as_bytes())),
+            host: host.map(|s| Cow::Borrowed(s.as_bytes())),
+        }
+    }
+
+    #[test]
+    fn envelope_fields() {
+        assert_eq!(decode_text(b"=?UTF-8?Q?Gr=C3=BC=C3=9Fe?="), "Grüße");
+        assert_eq!(decode_text(b"Plain subject"), "Plain subject");
+        assert_eq!(
+            parse_date(b"Tue, 1 Jul 2003 10:52:37 +0200 (CEST)").map(|d| d.to_rfc3339()),
+            Some("2003-07-01T08:52:37+00:00".to_string())
+        );
+        assert_eq!(
+            message_ids(b"<B27397-0100000@cac.washington.edu> <a@b>"),
+            ["B27397-0100000@cac.washington.edu", "a@b"]
+        );
+
+        let list = [
+            address(Some("Terry Gray"), Some("gray"), Some("cac.washington.edu")),
+            address(None, Some("team"), None),
+            address(None, Some("imap"), Some("cac.washington.edu")),
+            address(None, None, None),
+        ];
+        let Some(EmailAddress::Group(groups)) = addresses(Some(&list[..])) else {
+            panic!("expected groups");
+        };
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].name, None);
+        assert_eq!(groups[0].members[0].name.as_deref(), Some("Terry Gray"));
+        assert_eq!(
+            groups[0].members[0].email.as_deref(),
+            Some("gray@cac.washington.edu")
+        );
+        assert_eq!(groups[1].name.as_deref(), Some("team"));
+        assert_eq!(
+            groups[1].members[0].email.as_deref(),
+            Some("imap@cac.washington.edu")
+        );
+    }
+
+    #[test]
+    fn uid_fetch_response() {
+        let response = b"* 12 FETCH (UID 42 FLAGS (\\Seen) ENVELOPE (\"Mon, 7 Feb 1994 21:52:25 -0800 (PST)\" \"=?ISO-8859-1?Q?Caf=E9?=\" ((\"Fred Foobar\" NIL \"foobar\" \"Blurdybloop.example\")) NIL NIL ((NIL NIL \"mooch\" \"owatagu.example\")) NIL NIL \"<a@example>\" \"<B27397-0100000@Blurdybloop.example>\") BODYSTRUCTURE (\"TEXT\" \"PLAIN\" (\"CHARSET\" \"US-ASCII\") NIL NIL \"7BIT\" 3028 92))\r\n";
+        let (_, response) = Response::from_bytes(response).unwrap();
+        let Response::Fetch(12, attributes) = response else {
+            panic!("expected a FETCH response");
+        };
+        let envelope = attributes
+            .iter()
+            .find_map(|attribute| match attribute {
+                AttributeValue::Envelope(envelope) => Some(envelope),
+                _ => None,
+            })
+            .unwrap();
+
+        assert_eq!(envelope.subject.as_deref().map(decode_text).as_deref(), Some("Café"));
+        assert_eq!(
+            envelope.date.as_deref().and_then(parse_date).map(|d| d.to_rfc3339()),
+            Some("1994-02-08T05:52:25+00:00".to_string())
+        );
+        let Some(EmailAddress::List(from)) = addresses(envelope.from.as_deref()) else {
+            panic!("expected addresses");
+        };
+        assert_eq!(from[0].name.as_deref(), Some("Fred Foobar"));
+        assert_eq!(from[0].email.as_deref(), Some("foobar@Blurdybloop.example"));
+        let Some(EmailAddress::List(to)) = addresses(envelope.to.as_deref()) else {
+            panic!("expected addresses");
+        };
+        assert_eq!(to[0].name, None);
+        assert_eq!(to[0].email.as_deref(), Some("mooch@owatagu.example"));
+        assert!(addresses(envelope.cc.as_deref()).is_none());
+        assert_eq!(
+            envelope.message_id.as_deref().map(message_ids),
+            Some(vec!["B27397-0100000@Blurdybloop.example".to_string()])
+        );
+        assert_eq!(
+            envelope.in_reply_to.as_deref().map(message_ids),
+            Some(vec!["a@example".to_string()])
+        );
+        assert!(attributes.contains(&AttributeValue::Uid(42)));
+    }
+
+    #[test]
+    fn unsubscribe_headers() {
+        let header = b"List-Unsubscribe: <mailto:leave@example.com>,\r\n <https://example.com/u/42>\r\nList-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n\r\n";
+        let parsed = unsubscribe(header).unwrap();
+        assert_eq!(parsed.url.as_deref(), Some("https://example.com/u/42"));
+        assert_eq!(parsed.mailto.as_deref(), Some("mailto:leave@example.com"));
+        assert!(parsed.one_click);
+        assert_eq!(unsubscribe(b"Subject: Hi\r\n\r\n"), None);
+    }
+}
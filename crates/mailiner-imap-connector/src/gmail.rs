@@ -0,0 +1,63 @@
+//! Gmail's IMAP extensions (X-GM-EXT-1), which expose labels and stable
+//! message and thread ids.
+
+use imap_proto::types::AttributeValue;
+use mailiner_core::Envelope;
+
+/// The Gmail attributes of a message.
+#[derive(Debug, Default)]
+pub(crate) struct GmailAttributes {
+    pub(crate) labels: Vec<String>,
+    pub(crate) thread_id: Option<u64>,
+    pub(crate) message_id: Option<u64>,
+}
+
+impl GmailAttributes {
+    /// Collects the attributes of a FETCH response, together with the UID of
+    /// the message.
+    pub(crate) fn from_fetch(attributes: &[AttributeValue<'_>]) -> (Option<u32>, Self) {
+        let mut uid = None;
+        let mut gmail = Self::default();
+        for attribute in attributes {
+            match attribute {
+                AttributeValue::Uid(value) => uid = Some(*value),
+                AttributeValue::GmailLabels(labels) => {
+                    gmail.labels = labels.iter().map(|label| label.to_string()).collect();
+                }
+                AttributeValue::GmailThrId(id) => gmail.thread_id = Some(*id),
+                AttributeValue::GmailMsgId(id) => gmail.message_id = Some(*id),
+                _ => {}
+            }
+        }
+        (uid, gmail)
+    }
+
+    pub(crate) fn apply(self, envelope: &mut Envelope) {
+        envelope.labels = self.labels.into_iter().collect();
+        // Gmail shows ids in hex, e.g. in the URL of a thread.
+        envelope.thread_id = self.thread_id.map(|id| format!("{:x}", id));
+        envelope.remote_id = self.message_id.map(|id| format!("{:x}", id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use imap_proto::types::Response;
+
+    use super::*;
+
+    #[test]
+    fn fetch_attributes() {
+        let response = b"* 1 FETCH (X-GM-THRID 1278455344230334865 X-GM-MSGID 1278455344230334865 X-GM-LABELS (\\Inbox \\Sent Important \"Muy Importante\") UID 42)\r\n";
+        let (_, response) = Response::from_bytes(response).unwrap();
+        let Response::Fetch(_, attributes) = response else {
+            panic!("expected a FETCH response");
+        };
+
+        let (uid, gmail) = GmailAttributes::from_fetch(&attributes);
+        assert_eq!(uid, Some(42));
+        assert_eq!(gmail.labels, ["\\Inbox", "\\Sent", "Important", "Muy Importante"]);
+        assert_eq!(gmail.thread_id, Some(1278455344230334865));
+        assert_eq!(gmail.message_id, Some(1278455344230334865));
+    }
+}
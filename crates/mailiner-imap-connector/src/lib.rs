@@ -1,27 +1,184 @@
+use std::collections::BTreeSet;
 use std::fmt::Debug;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
-use async_imap::types::Flag;
+use async_imap::extensions::idle::{Handle, IdleResponse};
+use async_imap::error::Error as AsyncImapError;
+use async_imap::types::{Fetch, Flag, Mailbox, NameAttribute, UnsolicitedResponse};
 use async_imap::{Client, Session};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use futures::{StreamExt, TryStreamExt};
-use imap_proto::types::BodyStructure;
-use mail_parser::{Address, MessageParser};
+use imap_proto::types::{
+    AclRight, AttributeValue, BodyStructure, Capability, MailboxDatum, Response, ResponseCode,
+    SectionPath, Status, UidSetMember,
+};
+use mail_parser::{Address, HeaderValue, MessageParser};
 use thiserror::Error;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio_rustls::rustls::pki_types::ServerName;
-use tokio_rustls::rustls::{ClientConfig, RootCertStore};
-use tokio_rustls::{client::TlsStream, TlsConnector};
-use tracing::info;
+use tracing::{info, warn};
 
 use mailiner_core::{
-    Account, AccountId, EmailAddr, EmailAddress, EmailConnector, Envelope, Folder, FolderId, Group,
-    MailinerError, MessageContent, MessageId, MessagePart, MessagePartId, Result as MailinerResult,
+    Account, AccountId, AuthMethod, BackendUri, CertificateInfo, ConnectionSecurity, ConnectorFactory, ConnectorRegistry, EmailAddr, EmailAddress, EmailConnector, Envelope, FlagUpdate, Folder, FolderDelta,
+    FolderEvent, FolderId, FolderRights, FolderSyncState, Group,
+    MailinerError, MessageContent, MessageId, MessagePart, MessagePartId, MessageStructure,
+    Result as MailinerResult,
+    SearchQuery,
 };
 
-use tokio::sync::Mutex;
+mod auth;
+mod bodystructure;
+mod envelope;
+mod gmail;
+mod mime;
+mod namespace;
+mod search;
+mod starttls;
+mod timeout;
+mod tls;
+mod utf7;
+
+use mailiner_core::platform::Timer;
+use namespace::{Namespace, NamespaceCapture, NamespaceStream};
+use timeout::{StreamControl, TimeoutStream};
+pub use tls::TlsBackend;
+use tls::ImapStream;
+
+/// The stream of a session: TLS on top of the caller's stream, whose reads
+/// and writes time out, with NAMESPACE responses taken out.
+type Stream<S> = NamespaceStream<ImapStream<TimeoutStream<S>>>;
+
+/// Builds the id of a message, which is the folder and the UID in the form of
+/// an IMAP URL path (RFC 5092), e.g. `INBOX;UID=42`.
+fn to_message_id(folder_id: &FolderId, uid: u32) -> MessageId {
+    MessageId::new(format!("{};UID={}", folder_id.as_str(), uid))
+}
+
+/// The sequence set of the messages in `range`, which counts from the newest
+/// of the `exists` messages, e.g. `51:100` for the first 50 of 100. `None`
+/// if the range starts past the oldest message.
+fn sequence_range(exists: usize, range: &std::ops::Range<usize>) -> Option<String> {
+    if range.start >= exists || range.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "{}:{}",
+        exists.saturating_sub(range.end) + 1,
+        exists - range.start
+    ))
+}
+
+/// Splits a message id into the folder and the UID of the message.
+fn parse_message_id(message_id: &MessageId) -> Result<(FolderId, u32), ImapError> {
+    message_id
+        .as_str()
+        .rsplit_once(";UID=")
+        .and_then(|(folder, uid)| Some((FolderId::new(folder), uid.parse().ok()?)))
+        .ok_or_else(|| ImapError::InvalidData(format!("Invalid message id: {}", message_id)))
+}
+
+/// Splits the ids of messages in the same folder into the folder and the
+/// UIDs of the messages.
+fn parse_message_ids(message_ids: &[MessageId]) -> Result<(FolderId, Vec<u32>), ImapError> {
+    let mut folder_id = None;
+    let mut uids = Vec::new();
+    for message_id in message_ids {
+        let (folder, uid) = parse_message_id(message_id)?;
+        if folder_id.get_or_insert_with(|| folder.clone()) != &folder {
+            return Err(ImapError::InvalidData(
+                "Messages must be in the same folder".to_string(),
+            ));
+        }
+        uids.push(uid);
+    }
+    let folder_id =
+        folder_id.ok_or_else(|| ImapError::InvalidData("No messages given".to_string()))?;
+    Ok((folder_id, uids))
+}
+
+/// The IMAP system flag of an envelope's flag field, e.g. `\\Seen` for
+/// `is_read`.
+fn imap_flag(name: &str) -> Result<&'static str, ImapError> {
+    Ok(match name {
+        "is_read" => "\\Seen",
+        "is_flagged" => "\\Flagged",
+        "is_draft" => "\\Draft",
+        "is_deleted" => "\\Deleted",
+        "is_starred" => "\\Starred",
+        _ => return Err(ImapError::InvalidData(format!("Unknown flag: {}", name))),
+    })
+}
+
+/// Whether `value` can be used as a keyword, which must be an atom.
+fn is_keyword(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_graphic() && !"(){%*\"\\]".contains(c))
+}
+
+/// Builds a compact UID set, e.g. `1:3,7`.
+fn uid_set(uids: &[u32]) -> String {
+    let mut uids = uids.to_vec();
+    uids.sort_unstable();
+    uids.dedup();
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for uid in uids {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == uid => *end = uid,
+            _ => ranges.push((uid, uid)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}:{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The UIDs in the MODIFIED response code (RFC 7162) at the start of a
+/// response's text, e.g. `[MODIFIED 7,9:11] Conditional STORE failed`.
+/// imap-proto doesn't know the code and leaves it in the text.
+fn modified_uids(information: &str) -> Vec<u32> {
+    let Some(set) = information
+        .strip_prefix('[')
+        .and_then(|code| code.split_once(']'))
+        .and_then(|(code, _)| {
+            let (name, set) = code.split_once(' ')?;
+            name.eq_ignore_ascii_case("MODIFIED").then_some(set)
+        })
+    else {
+        return Vec::new();
+    };
+    set.split(',')
+        .filter_map(|member| {
+            let (start, end) = member.split_once(':').unwrap_or((member, member));
+            let (start, end) = (start.parse::<u32>().ok()?, end.parse::<u32>().ok()?);
+            Some(start.min(end)..=start.max(end))
+        })
+        .flatten()
+        .collect()
+}
+
+/// Quotes a string argument of an IMAP command.
+fn quote(value: &str) -> String {
+    // Line breaks can't be part of a quoted string.
+    let value = value.replace(['\r', '\n'], " ");
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+use tokio::sync::{Mutex, MutexGuard};
+use stop_token::StopSource;
+
+const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
 
 #[derive(Error, Debug)]
 pub enum ImapError {
@@ -35,18 +192,26 @@ pub enum ImapError {
     InvalidData(String),
     #[error("Not authenticated")]
     NotAuthenticated,
+    #[error("TLS error: {0}")]
+    Tls(String),
+    #[error("Untrusted certificate {}: {}", .0.fingerprint, .0.reason)]
+    UntrustedCertificate(CertificateInfo),
 }
 
 impl From<ImapError> for MailinerError {
     fn from(err: ImapError) -> Self {
         match err {
             ImapError::Connection(msg) => MailinerError::Connector(msg),
-            ImapError::Authentication(msg) => MailinerError::Connector(msg),
+            ImapError::Authentication(msg) => MailinerError::Authentication(msg),
             ImapError::NotAuthenticated => {
                 MailinerError::Connector("Not authenticated".to_string())
             }
             ImapError::Imap(msg) => MailinerError::Connector(msg),
             ImapError::InvalidData(msg) => MailinerError::InvalidData(msg),
+            ImapError::Tls(msg) => MailinerError::Tls(msg),
+            ImapError::UntrustedCertificate(certificate) => {
+                MailinerError::UntrustedCertificate(certificate)
+            }
         }
     }
 }
@@ -55,8 +220,8 @@ struct ImapClient<S>
 where
     S: AsyncRead + AsyncWrite + Unpin + Debug
 {
-    client: Client<TlsStream<S>>,
-    session: Option<Session<TlsStream<S>>>,
+    client: Client<Stream<S>>,
+    session: Option<Session<Stream<S>>>,
 }
 
 #[derive(Debug)]
@@ -65,9 +230,42 @@ where
     S: AsyncRead + AsyncWrite + Unpin + Debug
 {
     Disconnected,
-    Unauthenticated(Client<TlsStream<S>>),
+    Unauthenticated(Client<Stream<S>>),
     Authenticating,
-    Authenticated(Session<TlsStream<S>>),
+    Authenticated(Session<Stream<S>>),
+    /// In IDLE, until DONE gives the session back, see
+    /// [`ImapConnector::finish_idle`].
+    Idle(Handle<Stream<S>>),
+}
+
+/// Lets the operations waiting for the session end an IDLE holding it.
+#[derive(Default)]
+struct IdleInterrupt {
+    /// Dropping it ends the IDLE in progress.
+    stop: Option<StopSource>,
+    /// How many operations are waiting for the session, in case the IDLE
+    /// is still starting and has no stop source yet.
+    waiting: usize,
+}
+
+/// Counts an operation as waiting for the session until it's dropped, also
+/// when the operation is cancelled while waiting.
+struct Waiting<'a>(&'a std::sync::Mutex<IdleInterrupt>);
+
+impl<'a> Waiting<'a> {
+    fn new(interrupt: &'a std::sync::Mutex<IdleInterrupt>) -> Self {
+        let mut state = interrupt.lock().unwrap();
+        state.waiting += 1;
+        state.stop = None;
+        drop(state);
+        Self(interrupt)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().waiting -= 1;
+    }
 }
 
 pub struct ImapConnector<S> 
@@ -78,7 +276,38 @@ where
     port: u16,
     username: String,
     password: String,
+    /// `None` picks the strongest password method the server supports.
+    auth_method: Option<AuthMethod>,
+    security: ConnectionSecurity,
+    tls_backend: TlsBackend,
+    /// Fingerprints of certificates to accept although they are invalid.
+    trusted_certificates: Vec<String>,
+    /// The command timeout and the activity of the connection.
+    stream_control: Arc<StreamControl>,
+    /// Size of the chunks large message parts are downloaded in.
+    chunk_size: usize,
+    /// Whether folders are opened with EXAMINE instead of SELECT.
+    read_only: bool,
+    /// Whether to build envelopes from the message headers instead of the
+    /// server's ENVELOPE.
+    fetch_headers: bool,
     imap: Mutex<ImapSession<S>>,
+    /// The server's folder prefixes and their hierarchy delimiters.
+    namespaces: std::sync::Mutex<Vec<Namespace>>,
+    namespace_capture: Arc<NamespaceCapture>,
+    /// The folder currently selected in the session.
+    selected: std::sync::Mutex<Option<FolderId>>,
+    /// The PERMANENTFLAGS of the selected folder.
+    permanent_flags: std::sync::Mutex<Vec<String>>,
+    /// The capabilities of the authenticated session.
+    capabilities: std::sync::Mutex<Vec<String>>,
+    /// Whether UTF8=ACCEPT is enabled, so that folder names are sent as UTF-8
+    /// instead of modified UTF-7 (RFC 6855).
+    utf8: std::sync::Mutex<bool>,
+    idle_interrupt: std::sync::Mutex<IdleInterrupt>,
+    /// Unsolicited responses that commands read past while looking for
+    /// their own, e.g. EXISTS during MYRIGHTS, kept for the next IDLE.
+    unsolicited: std::sync::Mutex<Vec<UnsolicitedResponse>>,
 }
 
 impl<S> ImapConnector<S>
@@ -91,31 +320,123 @@ where
             port,
             username,
             password,
+            auth_method: None,
+            security: ConnectionSecurity::Tls,
+            tls_backend: TlsBackend::default(),
+            trusted_certificates: Vec::new(),
+            stream_control: Arc::new(StreamControl::default()),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            read_only: false,
+            fetch_headers: false,
             imap: Mutex::new(ImapSession::Disconnected),
+            namespaces: std::sync::Mutex::new(vec![Namespace::root(Some("/".to_string()))]),
+            namespace_capture: Arc::new(NamespaceCapture::default()),
+            selected: std::sync::Mutex::new(None),
+            permanent_flags: std::sync::Mutex::new(Vec::new()),
+            capabilities: std::sync::Mutex::new(Vec::new()),
+            utf8: std::sync::Mutex::new(false),
+            idle_interrupt: std::sync::Mutex::new(IdleInterrupt::default()),
+            unsolicited: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Sets how to log in. For OAuth methods the credentials passed to
+    /// `authenticate` are the access token.
+    pub fn with_auth_method(mut self, auth_method: AuthMethod) -> Self {
+        self.auth_method = Some(auth_method);
+        self
+    }
+
+    /// Sets how the connection is secured. With STARTTLS the stream passed to
+    /// `connect` is plaintext and gets upgraded before logging in.
+    pub fn with_security(mut self, security: ConnectionSecurity) -> Self {
+        self.security = security;
+        self
+    }
+
+    /// Sets how TLS is done, see [`TlsBackend`].
+    pub fn with_tls_backend(mut self, tls_backend: TlsBackend) -> Self {
+        self.tls_backend = tls_backend;
+        self
+    }
+
+    /// Sets the fingerprints of the certificates the user trusts, see
+    /// `AccountPreferences::trusted_certificates`. Certificates that fail
+    /// validation otherwise fail to connect with
+    /// [`ImapError::UntrustedCertificate`].
+    pub fn with_trusted_certificates(mut self, fingerprints: Vec<String>) -> Self {
+        self.trusted_certificates = fingerprints;
+        self
+    }
+
+    /// Fails commands when the server doesn't respond for `timeout`, e.g.
+    /// because the connection was dropped by a NAT or a firewall. Without it,
+    /// commands wait for as long as the connection stays open.
+    pub fn with_command_timeout(self, timer: Arc<dyn Timer>, timeout: Duration) -> Self {
+        self.stream_control.set_timeout(timer, timeout);
+        self
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Opens folders with EXAMINE instead of SELECT, e.g. for previews or
+    /// search-only sessions. Messages aren't marked as seen or no longer
+    /// recent, and the server rejects all changes to the folders.
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Fetches the message headers for envelopes instead of the much smaller
+    /// ENVELOPE, for servers that send broken ENVELOPE data (e.g. unencoded
+    /// 8-bit subjects).
+    pub fn with_header_fetch(mut self, fetch_headers: bool) -> Self {
+        self.fetch_headers = fetch_headers;
+        self
+    }
+
+    /// The FETCH items envelopes are built from.
+    fn envelope_items(&self) -> &'static str {
+        if self.fetch_headers {
+            "UID RFC822.HEADER RFC822.SIZE FLAGS BODYSTRUCTURE"
+        } else {
+            "UID ENVELOPE INTERNALDATE RFC822.SIZE FLAGS BODYSTRUCTURE \
+             BODY.PEEK[HEADER.FIELDS (LIST-UNSUBSCRIBE LIST-UNSUBSCRIBE-POST)]"
         }
     }
 
     async fn ensure_connected(&self, stream: S) -> Result<(), ImapError> {
-        let mut imap = self.imap.lock().await;
+        let mut imap = self.lock_session().await;
         match *imap {
             ImapSession::Disconnected => {
-                let root_store = RootCertStore {
-                    roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
-                };
-                let config = ClientConfig::builder()
-                    .with_root_certificates(root_store)
-                    .with_no_client_auth();
-                let tls = TlsConnector::from(Arc::new(config));
-                let server_name = ServerName::try_from(self.host.clone())
-                    .map_err(|e| ImapError::Connection(format!("Invalid server name: {}", e)))?;
+                let mut stream = TimeoutStream::new(stream, self.stream_control.clone());
+                match self.security {
+                    ConnectionSecurity::Tls => {}
+                    ConnectionSecurity::StartTls => {
+                        info!("Negotiating STARTTLS...");
+                        starttls::negotiate(&mut stream).await?;
+                    }
+                    ConnectionSecurity::None => {
+                        return Err(ImapError::Connection(
+                            "Unencrypted connections are not supported".to_string(),
+                        ));
+                    }
+                }
 
                 info!("Establishing TLS connection...");
-                let tls_stream = tls.connect(server_name, stream).await.map_err(|e| {
-                    ImapError::Connection(format!("Failed to establish TLS: {}", e))
-                })?;
+                let tls_stream = ImapStream::connect(
+                    self.tls_backend,
+                    &self.host,
+                    &self.trusted_certificates,
+                    stream,
+                ).await?;
                 info!("TLS stream established");
 
-                *imap = ImapSession::Unauthenticated(Client::new(tls_stream));
+                let stream = NamespaceStream::new(tls_stream, self.namespace_capture.clone());
+                *imap = ImapSession::Unauthenticated(Client::new(stream));
             }
             _ => {
                 // Already connected
@@ -124,6 +445,13 @@ where
         Ok(())
     }
 
+    fn parse_id_list(value: &HeaderValue<'_>) -> Vec<String> {
+        value
+            .as_text_list()
+            .map(|ids| ids.into_iter().map(|id| id.to_string()).collect())
+            .unwrap_or_default()
+    }
+
     fn parse_email_address<'a>(addr: Option<&Address<'a>>) -> Option<EmailAddress> {
         addr.map(|addr| match addr {
             Address::Group(groups) => EmailAddress::Group(
@@ -183,14 +511,707 @@ where
         (is_read, is_starred, is_flagged, is_draft, is_deleted)
     }
 
-    fn parse_folder_hierarchy(name: &str) -> (String, Option<String>) {
-        let parts: Vec<&str> = name.split('/').collect();
-        if parts.len() > 1 {
-            let parent = parts[..parts.len() - 1].join("/");
-            let name = parts.last().unwrap().to_string();
-            (name, Some(parent))
+    /// Returns the keywords, i.e. the flags that aren't system flags.
+    fn parse_keywords<'a>(flags: impl Iterator<Item = Flag<'a>>) -> BTreeSet<String> {
+        flags
+            .filter_map(|flag| match flag {
+                Flag::Custom(name) if !name.starts_with('\\') => Some(name.into_owned()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the keywords that double as labels on servers without labels
+    /// of their own, i.e. all but the ones like `$Forwarded` (RFC 5788).
+    fn keyword_labels(keywords: &BTreeSet<String>) -> BTreeSet<String> {
+        keywords
+            .iter()
+            .filter(|keyword| !keyword.starts_with('$'))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether the selected folder stores `keyword` permanently.
+    fn keyword_allowed(&self, keyword: &str) -> bool {
+        let permanent_flags = self.permanent_flags.lock().unwrap();
+        // Without PERMANENTFLAGS all flags can be changed permanently.
+        permanent_flags.is_empty()
+            || permanent_flags
+                .iter()
+                .any(|flag| flag == "\\*" || flag.eq_ignore_ascii_case(keyword))
+    }
+
+    fn parse_folder_hierarchy(name: &str, delimiter: Option<&str>) -> (String, Option<String>) {
+        match delimiter.and_then(|delimiter| name.rsplit_once(delimiter)) {
+            Some((parent, name)) if !parent.is_empty() => (name.to_string(), Some(parent.to_string())),
+            _ => (name.to_string(), None),
+        }
+    }
+
+    /// Runs COPY, MOVE or APPEND and returns the UIDs the server assigned to
+    /// the messages in the target folder, taken from the COPYUID or APPENDUID
+    /// response code (RFC 4315). The command methods of async-imap discard
+    /// response codes. Servers without UIDPLUS don't report the UIDs.
+    async fn run_uidplus_command(
+        session: &mut Session<Stream<S>>,
+        command: &str,
+    ) -> Result<Vec<u32>, ImapError> {
+        let tag = session
+            .run_command(command)
+            .await
+            .map_err(|e| ImapError::Imap(format!("Failed to run {}: {}", command, e)))?;
+
+        let mut uids = Vec::new();
+        loop {
+            let response = session
+                .read_response()
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to run {}: {}", command, e)))?
+                .ok_or_else(|| ImapError::Connection("Connection closed".to_string()))?;
+            match response.parsed() {
+                Response::Done {
+                    tag: done,
+                    status,
+                    code,
+                    information,
+                } if *done == tag => {
+                    if *status != Status::Ok {
+                        return Err(ImapError::Imap(format!(
+                            "Failed to run {}: {}",
+                            command,
+                            information.as_deref().unwrap_or_default()
+                        )));
+                    }
+                    if let Some(code) = code {
+                        uids.extend(Self::uidplus_uids(code));
+                    }
+                    return Ok(uids);
+                }
+                // MOVE reports COPYUID in an untagged OK (RFC 6851).
+                Response::Data {
+                    status: Status::Ok,
+                    code: Some(code),
+                    ..
+                } => uids.extend(Self::uidplus_uids(code)),
+                _ => {}
+            }
+        }
+    }
+
+    /// Adds Gmail's labels and ids to envelopes of the selected folder. The
+    /// FETCH command of async-imap doesn't give access to these attributes.
+    async fn add_gmail_attributes(
+        &self,
+        session: &mut Session<Stream<S>>,
+        envelopes: &mut [Envelope],
+    ) -> Result<(), ImapError> {
+        if !self.has_capability("X-GM-EXT-1") || envelopes.is_empty() {
+            return Ok(());
+        }
+        let uids: Vec<u32> = envelopes
+            .iter()
+            .filter_map(|envelope| parse_message_id(&envelope.id).ok())
+            .map(|(_, uid)| uid)
+            .collect();
+        let command = format!(
+            "UID FETCH {} (UID X-GM-LABELS X-GM-THRID X-GM-MSGID)",
+            uid_set(&uids)
+        );
+        let tag = session
+            .run_command(&command)
+            .await
+            .map_err(|e| ImapError::Imap(format!("Failed to fetch Gmail attributes: {}", e)))?;
+
+        loop {
+            let response = session
+                .read_response()
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to fetch Gmail attributes: {}", e)))?
+                .ok_or_else(|| ImapError::Connection("Connection closed".to_string()))?;
+            match response.parsed() {
+                Response::Done {
+                    tag: done,
+                    status,
+                    information,
+                    ..
+                } if *done == tag => {
+                    if *status != Status::Ok {
+                        return Err(ImapError::Imap(format!(
+                            "Failed to fetch Gmail attributes: {}",
+                            information.as_deref().unwrap_or_default()
+                        )));
+                    }
+                    return Ok(());
+                }
+                Response::Fetch(_, attributes) => {
+                    let (Some(uid), mut gmail) = gmail::GmailAttributes::from_fetch(attributes)
+                    else {
+                        continue;
+                    };
+                    // Labels are named like folders.
+                    for label in &mut gmail.labels {
+                        *label = self.decode_folder_name(label);
+                    }
+                    let id = to_message_id(&envelopes[0].folder_id, uid);
+                    if let Some(envelope) = envelopes.iter_mut().find(|e| e.id == id) {
+                        gmail.apply(envelope);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn uidplus_uids(code: &ResponseCode<'_>) -> Vec<u32> {
+        let uids = match code {
+            ResponseCode::CopyUid(_, _, uids) | ResponseCode::AppendUid(_, uids) => uids,
+            _ => return Vec::new(),
+        };
+        uids.iter()
+            .flat_map(|member| match member {
+                UidSetMember::UidRange(range) => range.clone().collect(),
+                UidSetMember::Uid(uid) => vec![*uid],
+            })
+            .collect()
+    }
+
+    /// Queries the capabilities before logging in, when there is no session yet.
+    async fn client_capabilities(
+        client: &mut Client<Stream<S>>,
+    ) -> Result<Vec<String>, ImapError> {
+        let tag = client
+            .run_command("CAPABILITY")
+            .await
+            .map_err(|e| ImapError::Imap(format!("Failed to get capabilities: {}", e)))?;
+
+        let mut capabilities = Vec::new();
+        loop {
+            let response = client
+                .read_response()
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to get capabilities: {}", e)))?
+                .ok_or_else(|| ImapError::Connection("Connection closed".to_string()))?;
+            match response.parsed() {
+                Response::Capabilities(list) => {
+                    capabilities.extend(list.iter().map(Self::capability_name));
+                }
+                Response::Done { tag: done, status, .. } if *done == tag => {
+                    if *status != Status::Ok {
+                        return Err(ImapError::Imap("Failed to get capabilities".to_string()));
+                    }
+                    return Ok(capabilities);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn capability_name(capability: &Capability<'_>) -> String {
+        match capability {
+            Capability::Imap4rev1 => "IMAP4rev1".to_string(),
+            Capability::Auth(mechanism) => format!("AUTH={}", mechanism),
+            Capability::Atom(atom) => atom.to_string(),
+        }
+    }
+
+    /// Whether the authenticated session has a capability.
+    fn has_capability(&self, name: &str) -> bool {
+        self.capabilities
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|capability| capability.eq_ignore_ascii_case(name))
+    }
+
+    /// Selects a folder, unless it's already selected.
+    async fn select(
+        &self,
+        session: &mut Session<Stream<S>>,
+        folder_id: &FolderId,
+    ) -> Result<(), ImapError> {
+        if self.selected.lock().unwrap().as_ref() == Some(folder_id) {
+            return Ok(());
+        }
+        self.select_mailbox(session, folder_id, false).await?;
+        Ok(())
+    }
+
+    /// Selects a folder, even if it's selected already, e.g. for its current
+    /// message count. Any other selected folder is closed first.
+    async fn select_mailbox(
+        &self,
+        session: &mut Session<Stream<S>>,
+        folder_id: &FolderId,
+        condstore: bool,
+    ) -> Result<Mailbox, ImapError> {
+        let other_selected = self
+            .selected
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|selected| selected != folder_id);
+        if other_selected {
+            self.unselect(session).await?;
+        }
+
+        // A failed SELECT leaves no folder selected.
+        *self.selected.lock().unwrap() = None;
+        // There's no EXAMINE with CONDSTORE, but once QRESYNC is enabled the
+        // server reports the HIGHESTMODSEQ anyway.
+        let mailbox = if self.read_only {
+            session.examine(folder_id.as_str()).await
+        } else if condstore {
+            session.select_condstore(folder_id.as_str()).await
+        } else {
+            session.select(folder_id.as_str()).await
+        }
+        .map_err(|e| ImapError::Imap(format!("Failed to select folder: {}", e)))?;
+        self.set_selected(folder_id, &mailbox.permanent_flags);
+        Ok(mailbox)
+    }
+
+    /// Closes the selected folder without expunging messages marked as
+    /// deleted. Without UNSELECT (RFC 3691), CLOSE is only used when no
+    /// message is marked as deleted, since it expunges them; otherwise the
+    /// folder is left to the implicit close of the next SELECT.
+    async fn unselect(&self, session: &mut Session<Stream<S>>) -> Result<(), ImapError> {
+        if self.selected.lock().unwrap().is_none() {
+            return Ok(());
+        }
+        if self.has_capability("UNSELECT") {
+            *self.selected.lock().unwrap() = None;
+            session
+                .run_command_and_check_ok("UNSELECT")
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to unselect folder: {}", e)))?;
+        } else {
+            let deleted = session
+                .uid_search("DELETED")
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to search messages: {}", e)))?;
+            if deleted.is_empty() {
+                *self.selected.lock().unwrap() = None;
+                session
+                    .close()
+                    .await
+                    .map_err(|e| ImapError::Imap(format!("Failed to close folder: {}", e)))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records a successfully selected folder.
+    fn set_selected(&self, folder_id: &FolderId, permanent_flags: &[Flag<'_>]) {
+        *self.permanent_flags.lock().unwrap() =
+            permanent_flags.iter().map(Self::flag_name).collect();
+        *self.selected.lock().unwrap() = Some(folder_id.clone());
+    }
+
+    /// Converts a folder name as sent by the server for display.
+    fn decode_folder_name(&self, name: &str) -> String {
+        if *self.utf8.lock().unwrap() {
+            name.to_string()
+        } else {
+            utf7::decode(name)
+        }
+    }
+
+    /// Builds the full name of a folder from its display name and its parent's
+    /// full name.
+    fn full_folder_name(&self, name: &str, parent: Option<&str>) -> Result<String, ImapError> {
+        let name = if *self.utf8.lock().unwrap() {
+            name.to_string()
+        } else {
+            utf7::encode(name)
+        };
+        // Top-level folders go below the prefix of the user's folders, e.g.
+        // `INBOX.` on Cyrus.
+        let (prefix, delimiter) = match parent {
+            Some(parent) => (String::new(), self.delimiter(parent)),
+            None => {
+                let personal = self.personal_namespace();
+                (personal.prefix, personal.delimiter)
+            }
+        };
+        if let Some(delimiter) = &delimiter {
+            if name.contains(delimiter.as_str()) {
+                return Err(ImapError::InvalidData(format!(
+                    "Folder name must not contain \"{}\"",
+                    delimiter
+                )));
+            }
+        }
+        match (parent, delimiter) {
+            (Some(parent), Some(delimiter)) => Ok(format!("{}{}{}", parent, delimiter, name)),
+            (Some(_), None) => Err(ImapError::InvalidData(
+                "Server doesn't support nested folders".to_string(),
+            )),
+            (None, _) => Ok(format!("{}{}", prefix, name)),
+        }
+    }
+
+    /// The hierarchy delimiter of the namespace a folder is in.
+    fn delimiter(&self, name: &str) -> Option<String> {
+        let found = namespace::find(&self.namespaces.lock().unwrap(), name).cloned();
+        found.unwrap_or_else(|| self.personal_namespace()).delimiter
+    }
+
+    /// The namespace of the user's own folders.
+    fn personal_namespace(&self) -> Namespace {
+        self.namespaces
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|namespace| namespace.personal)
+            .cloned()
+            .unwrap_or_else(|| Namespace::root(None))
+    }
+
+    /// Queries the namespaces of the server with NAMESPACE (RFC 2342).
+    async fn query_namespaces(&self, session: &mut Session<Stream<S>>) -> Result<Vec<Namespace>, ImapError> {
+        self.namespace_capture.arm();
+        let result = session.run_command_and_check_ok("NAMESPACE").await;
+        let namespaces = self.namespace_capture.disarm();
+        result.map_err(|e| ImapError::Imap(format!("Failed to get namespaces: {}", e)))?;
+        Ok(namespaces)
+    }
+
+    /// Queries the hierarchy delimiter, which servers return for `LIST "" ""`
+    /// (RFC 3501, section 6.3.8). Dovecot and Cyrus commonly use `.`.
+    async fn hierarchy_delimiter(
+        session: &mut Session<Stream<S>>,
+    ) -> Result<Option<String>, ImapError> {
+        let root = session
+            .list(Some(""), Some(""))
+            .await
+            .map_err(|e| ImapError::Imap(format!("Failed to get hierarchy delimiter: {}", e)))?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(|e| ImapError::Imap(format!("Failed to get hierarchy delimiter: {}", e)))?;
+        Ok(root
+            .first()
+            .and_then(|name| name.delimiter())
+            .map(|delimiter| delimiter.to_string()))
+    }
+
+    /// Reads the user's rights on a folder with MYRIGHTS (RFC 4314). `None`
+    /// if the server refuses, as it does for folders the user may only see
+    /// in the hierarchy.
+    async fn my_rights(
+        &self,
+        session: &mut Session<Stream<S>>,
+        folder_id: &FolderId,
+    ) -> Result<Option<FolderRights>, ImapError> {
+        match session
+            .run_command_and_check_ok(format!("MYRIGHTS {}", quote(folder_id.as_str())))
+            .await
+        {
+            Ok(()) => {}
+            Err(AsyncImapError::No(_)) => return Ok(None),
+            Err(e) => return Err(ImapError::Imap(format!("Failed to get folder rights: {}", e))),
+        }
+
+        Ok(self
+            .take_unsolicited(session, |response| match response {
+                Response::MyRights(my_rights) if my_rights.mailbox == folder_id.as_str() => {
+                    Some(Self::folder_rights(&my_rights.rights))
+                }
+                _ => None,
+            })
+            .pop())
+    }
+
+    /// Takes the untagged responses to the last command that `take` maps,
+    /// e.g. the ACL of GETACL. The others, like EXISTS for new mail, are
+    /// kept for IDLE to report.
+    fn take_unsolicited<T>(
+        &self,
+        session: &mut Session<Stream<S>>,
+        mut take: impl FnMut(&Response<'_>) -> Option<T>,
+    ) -> Vec<T> {
+        let mut taken = Vec::new();
+        while let Ok(response) = session.unsolicited_responses.try_recv() {
+            let value = match &response {
+                UnsolicitedResponse::Other(data) => take(data.parsed()),
+                _ => None,
+            };
+            match value {
+                Some(value) => taken.push(value),
+                None => self.unsolicited.lock().unwrap().push(response),
+            }
+        }
+        taken
+    }
+
+    fn folder_rights(rights: &[AclRight]) -> FolderRights {
+        FolderRights::new(rights.iter().map(|right| char::from(*right)).collect::<String>())
+    }
+
+    /// Adds (`add`) or removes flags on messages of one folder with a single
+    /// STORE. With `unchanged_since` (CONDSTORE, RFC 7162), messages modified
+    /// after that mod-sequence are left alone; their ids are returned so the
+    /// caller can refresh them before trying again.
+    pub async fn store_flags(
+        &self,
+        message_ids: &[MessageId],
+        flags: &[&str],
+        add: bool,
+        unchanged_since: Option<u64>,
+    ) -> MailinerResult<Vec<MessageId>> {
+        if message_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (folder_id, uids) = parse_message_ids(message_ids)?;
+
+        let mut imap = self.lock_session().await;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            self.select(session, &folder_id).await?;
+
+            let modifier = unchanged_since
+                .map(|modseq| format!("(UNCHANGEDSINCE {}) ", modseq))
+                .unwrap_or_default();
+            let command = format!(
+                "UID STORE {} {}{}FLAGS ({})",
+                uid_set(&uids),
+                modifier,
+                if add { '+' } else { '-' },
+                flags.join(" ")
+            );
+            let tag = session
+                .run_command(&command)
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to store flags: {}", e)))?;
+
+            // The FETCH responses with the new flags aren't needed, the
+            // messages that weren't changed are listed in the tagged one.
+            loop {
+                let response = session
+                    .read_response()
+                    .await
+                    .map_err(|e| ImapError::Imap(format!("Failed to store flags: {}", e)))?
+                    .ok_or_else(|| ImapError::Connection("Connection closed".to_string()))?;
+                if let Response::Done {
+                    tag: done,
+                    status,
+                    information,
+                    ..
+                } = response.parsed()
+                {
+                    if *done != tag {
+                        continue;
+                    }
+                    let information = information.as_deref().unwrap_or_default();
+                    if *status != Status::Ok {
+                        return Err(ImapError::Imap(format!(
+                            "Failed to store flags: {}",
+                            information
+                        ))
+                        .into());
+                    }
+                    return Ok(modified_uids(information)
+                        .into_iter()
+                        .map(|uid| to_message_id(&folder_id, uid))
+                        .collect());
+                }
+            }
+        } else {
+            Err(ImapError::NotAuthenticated.into())
+        }
+    }
+
+    /// Stores a message in a folder, returning its id if the server reports
+    /// it (UIDPLUS). `flags` are IMAP flags, e.g. `\Seen` or `\Draft`, and
+    /// `internal_date` is the date the server sorts the message by, the time
+    /// of appending if `None`.
+    ///
+    /// With LITERAL+ (or LITERAL- for messages up to 4 KiB, RFC 7888) the
+    /// message is sent as a non-synchronizing literal, saving the round trip
+    /// of waiting for the server's continuation request. This needs the
+    /// message to be valid UTF-8, as async-imap only sends text commands.
+    pub async fn append_message(
+        &self,
+        folder_id: &FolderId,
+        flags: &[&str],
+        internal_date: Option<DateTime<Utc>>,
+        content: &[u8],
+    ) -> MailinerResult<Option<MessageId>> {
+        let mut imap = self.lock_session().await;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            let flags = flags.join(" ");
+            let internal_date =
+                internal_date.map(|date| date.format("%d-%b-%Y %H:%M:%S %z").to_string());
+            let non_synchronizing = self.has_capability("LITERAL+")
+                || (self.has_capability("LITERAL-") && content.len() <= 4096);
+            let uid = match std::str::from_utf8(content) {
+                Ok(text) if non_synchronizing => {
+                    let command = format!(
+                        "APPEND {} ({}){} {{{}+}}\r\n{}",
+                        quote(folder_id.as_str()),
+                        flags,
+                        internal_date
+                            .as_deref()
+                            .map(|date| format!(" {}", quote(date)))
+                            .unwrap_or_default(),
+                        content.len(),
+                        text
+                    );
+                    Self::run_uidplus_command(session, &command)
+                        .await?
+                        .first()
+                        .copied()
+                }
+                _ => {
+                    session
+                        .append(
+                            folder_id.as_str(),
+                            Some(&flags),
+                            internal_date.as_deref(),
+                            content,
+                        )
+                        .await
+                        .map_err(|e| ImapError::Imap(format!("Failed to append message: {}", e)))?;
+                    None
+                }
+            };
+            Ok(uid.map(|uid| to_message_id(folder_id, uid)))
+        } else {
+            Err(ImapError::NotAuthenticated.into())
+        }
+    }
+
+    /// Keeps the connection alive by sending NOOP whenever it was unused for
+    /// `interval`, which also detects dropped connections together with the
+    /// command timeout. Runs until the connector is disconnected or the
+    /// connection fails.
+    pub async fn keepalive(&self, timer: &dyn Timer, interval: Duration) -> MailinerResult<()> {
+        loop {
+            timer.sleep(interval).await;
+            if self.stream_control.take_activity() {
+                continue;
+            }
+            let mut imap = self.imap.lock().await;
+            match &mut *imap {
+                ImapSession::Authenticated(session) => session
+                    .noop()
+                    .await
+                    .map_err(|e| ImapError::Connection(format!("Keepalive failed: {}", e)))?,
+                ImapSession::Disconnected => return Ok(()),
+                // Still connecting, the next round will tell, or in IDLE,
+                // which the server doesn't time out.
+                _ => {}
+            }
+        }
+    }
+
+    /// Permanently removes all messages marked as deleted from a folder.
+    pub async fn expunge(&self, folder_id: &FolderId) -> MailinerResult<()> {
+        let mut imap = self.lock_session().await;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            self.select(session, folder_id).await?;
+            session
+                .expunge()
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to expunge folder: {}", e)))?
+                .try_collect::<Vec<_>>()
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to expunge folder: {}", e)))?;
+            Ok(())
+        } else {
+            Err(ImapError::NotAuthenticated.into())
+        }
+    }
+
+    /// Permanently removes the given messages, if they are marked as deleted,
+    /// leaving other deleted messages of the folder alone. Needs UIDPLUS.
+    pub async fn uid_expunge(&self, message_ids: &[MessageId]) -> MailinerResult<()> {
+        if message_ids.is_empty() {
+            return Ok(());
+        }
+        let (folder_id, uids) = parse_message_ids(message_ids)?;
+
+        let mut imap = self.lock_session().await;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            if !self.has_capability("UIDPLUS") {
+                return Err(ImapError::Imap("Server doesn't support UID EXPUNGE".to_string()).into());
+            }
+            self.select(session, &folder_id).await?;
+            session
+                .uid_expunge(uid_set(&uids))
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to expunge messages: {}", e)))?
+                .try_collect::<Vec<_>>()
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to expunge messages: {}", e)))?;
+            Ok(())
+        } else {
+            Err(ImapError::NotAuthenticated.into())
+        }
+    }
+
+    /// Lists the access control list of a folder, as pairs of identifiers
+    /// (users or groups) and their rights.
+    pub async fn get_acl(&self, folder_id: &FolderId) -> MailinerResult<Vec<(String, FolderRights)>> {
+        let mut imap = self.lock_session().await;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            session
+                .run_command_and_check_ok(format!("GETACL {}", quote(folder_id.as_str())))
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to get ACL: {}", e)))?;
+
+            let entries = self.take_unsolicited(session, |response| match response {
+                Response::Acl(acl) if acl.mailbox == folder_id.as_str() => Some(
+                    acl.acls
+                        .iter()
+                        .map(|entry| (entry.identifier.to_string(), Self::folder_rights(&entry.rights)))
+                        .collect::<Vec<_>>(),
+                ),
+                _ => None,
+            });
+            Ok(entries.into_iter().flatten().collect())
+        } else {
+            Err(ImapError::NotAuthenticated.into())
+        }
+    }
+
+    /// Replaces the rights of an identifier on a folder.
+    pub async fn set_acl(
+        &self,
+        folder_id: &FolderId,
+        identifier: &str,
+        rights: &FolderRights,
+    ) -> MailinerResult<()> {
+        let mut imap = self.lock_session().await;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            session
+                .run_command_and_check_ok(format!(
+                    "SETACL {} {} {}",
+                    quote(folder_id.as_str()),
+                    quote(identifier),
+                    quote(rights.as_str())
+                ))
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to set ACL: {}", e)))?;
+            Ok(())
+        } else {
+            Err(ImapError::NotAuthenticated.into())
+        }
+    }
+
+    /// Removes an identifier from the access control list of a folder.
+    pub async fn delete_acl(&self, folder_id: &FolderId, identifier: &str) -> MailinerResult<()> {
+        let mut imap = self.lock_session().await;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            session
+                .run_command_and_check_ok(format!(
+                    "DELETEACL {} {}",
+                    quote(folder_id.as_str()),
+                    quote(identifier)
+                ))
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to delete ACL: {}", e)))?;
+            Ok(())
         } else {
-            (name.to_string(), None)
+            Err(ImapError::NotAuthenticated.into())
         }
     }
 
@@ -223,40 +1244,333 @@ where
         }
     }
 
-    async fn fetch_message_part(
-        &self,
-        message_id: &MessageId,
+    fn envelope_from_fetch(&self, folder_id: &FolderId, fetch: &Fetch) -> Result<Envelope, ImapError> {
+        let uid = fetch
+            .uid
+            .ok_or_else(|| ImapError::InvalidData("No UID in FETCH response".to_string()))?;
+        let (is_read, is_starred, is_flagged, is_draft, is_deleted) =
+            Self::parse_flags(fetch.flags());
+        let keywords = Self::parse_keywords(fetch.flags());
+
+        let mut envelope = Envelope {
+            id: to_message_id(folder_id, uid),
+            account_id: AccountId::new(self.username.clone()),
+            folder_id: folder_id.clone(),
+            subject: None,
+            from: None,
+            to: None,
+            cc: None,
+            bcc: None,
+            reply_to: None,
+            date: Utc::now(),
+            is_read,
+            is_starred,
+            is_flagged,
+            is_draft,
+            is_deleted,
+            has_attachments: Self::has_attachments(fetch.bodystructure()),
+            size: fetch.size.map(u64::from),
+            labels: Self::keyword_labels(&keywords),
+            keywords,
+            snoozed_until: None,
+            thread_id: None,
+            remote_id: None,
+            message_id: None,
+            in_reply_to: Vec::new(),
+            references: Vec::new(),
+            unsubscribe: fetch.header().and_then(envelope::unsubscribe),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+
+        if let Some(imap_envelope) = fetch.envelope() {
+            envelope.subject = imap_envelope.subject.as_deref().map(envelope::decode_text);
+            envelope.from = envelope::addresses(imap_envelope.from.as_deref());
+            envelope.to = envelope::addresses(imap_envelope.to.as_deref());
+            envelope.cc = envelope::addresses(imap_envelope.cc.as_deref());
+            envelope.bcc = envelope::addresses(imap_envelope.bcc.as_deref());
+            envelope.reply_to = envelope::addresses(imap_envelope.reply_to.as_deref());
+            envelope.message_id = imap_envelope
+                .message_id
+                .as_deref()
+                .and_then(|raw| envelope::message_ids(raw).into_iter().next());
+            envelope.in_reply_to = imap_envelope
+                .in_reply_to
+                .as_deref()
+                .map(envelope::message_ids)
+                .unwrap_or_default();
+            // Fall back to the arrival time for a missing or malformed Date header.
+            if let Some(date) = imap_envelope
+                .date
+                .as_deref()
+                .and_then(envelope::parse_date)
+                .or_else(|| fetch.internal_date().map(|date| date.with_timezone(&Utc)))
+            {
+                envelope.date = date;
+            }
+        } else {
+            let header = fetch
+                .header()
+                .ok_or_else(|| ImapError::InvalidData("No header found".to_string()))?;
+            let parsed_headers = MessageParser::new()
+                .parse_headers(header)
+                .ok_or(ImapError::InvalidData("Failed to parse headers".to_string()))?;
+            envelope.subject = parsed_headers.subject().map(|s| s.to_string());
+            envelope.from = Self::parse_email_address(parsed_headers.from());
+            envelope.to = Self::parse_email_address(parsed_headers.to());
+            envelope.cc = Self::parse_email_address(parsed_headers.cc());
+            envelope.bcc = Self::parse_email_address(parsed_headers.bcc());
+            envelope.reply_to = Self::parse_email_address(parsed_headers.reply_to());
+            envelope.message_id = parsed_headers.message_id().map(|id| id.to_string());
+            envelope.in_reply_to = Self::parse_id_list(parsed_headers.in_reply_to());
+            envelope.references = Self::parse_id_list(parsed_headers.references());
+            envelope.date = Self::parse_date(parsed_headers.date())?;
+        }
+
+        Ok(envelope)
+    }
+
+    /// Enables QRESYNC (and thereby CONDSTORE) for the session, if the server
+    /// supports it. Must be called before a folder is selected.
+    async fn enable_qresync(session: &mut Session<Stream<S>>) -> Result<(), ImapError> {
+        let capabilities = session
+            .capabilities()
+            .await
+            .map_err(|e| ImapError::Imap(format!("Failed to get capabilities: {}", e)))?;
+        if capabilities.has_str("QRESYNC") {
+            session
+                .run_command_and_check_ok("ENABLE QRESYNC")
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to enable QRESYNC: {}", e)))?;
+        }
+        Ok(())
+    }
+
+    fn folder_event(folder_id: &FolderId, response: &Response<'_>) -> Option<FolderEvent> {
+        match response {
+            Response::MailboxData(MailboxDatum::Exists(exists)) => Some(FolderEvent::NewMessages {
+                folder_id: folder_id.clone(),
+                exists: *exists,
+            }),
+            Response::Expunge(sequence) => Some(FolderEvent::MessageExpunged {
+                folder_id: folder_id.clone(),
+                sequence: *sequence,
+            }),
+            Response::Fetch(sequence, attributes)
+                if attributes
+                    .iter()
+                    .any(|attr| matches!(attr, AttributeValue::Flags(_))) =>
+            {
+                Some(FolderEvent::FlagsChanged {
+                    folder_id: folder_id.clone(),
+                    sequence: *sequence,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Locks the session for a command. An IDLE holding it is told to end,
+    /// so that the command runs right away instead of after the IDLE's
+    /// timeout; whoever watches the folder starts the next IDLE.
+    async fn lock_session(&self) -> MutexGuard<'_, ImapSession<S>> {
+        let waiting = Waiting::new(&self.idle_interrupt);
+        let mut imap = self.imap.lock().await;
+        drop(waiting);
+        if let Err(e) = self.finish_idle(&mut imap).await {
+            warn!("{}", e);
+        }
+        imap
+    }
+
+    /// Ends the IDLE the session is in, if it is, with DONE. The session is
+    /// left in IDLE both by [`idle`](EmailConnector::idle) and when its future
+    /// is dropped while waiting. Should DONE fail, the session is lost and the
+    /// connector stays disconnected.
+    async fn finish_idle(&self, imap: &mut ImapSession<S>) -> Result<(), ImapError> {
+        if !matches!(imap, ImapSession::Idle(_)) {
+            return Ok(());
+        }
+        let ImapSession::Idle(handle) = std::mem::replace(imap, ImapSession::Disconnected) else {
+            unreachable!("session state checked above");
+        };
+        self.stream_control.set_suspended(false);
+        match handle.done().await {
+            Ok(session) => {
+                *imap = ImapSession::Authenticated(session);
+                Ok(())
+            }
+            Err(e) => {
+                *self.selected.lock().unwrap() = None;
+                Err(ImapError::Imap(format!("Failed to finish IDLE: {}", e)))
+            }
+        }
+    }
+
+    /// Fetches a message part in chunks of `chunk_size` bytes. The session is
+    /// released between the chunks, so that downloading a large attachment
+    /// doesn't block other operations. `total` is the part's size from
+    /// BODYSTRUCTURE, for the progress.
+    async fn fetch_message_part(
+        &self,
+        message_id: &MessageId,
+        part_number: &MessagePartId,
+        total: Option<u64>,
+        progress: &(dyn Fn(u64, Option<u64>) + Send + Sync),
+    ) -> Result<Vec<u8>, ImapError> {
+        let (folder_id, uid) = parse_message_id(message_id)?;
+        let section = Self::section_path(part_number)?;
+        let mut content = Vec::new();
+        loop {
+            let chunk = {
+                let mut imap_client = self.lock_session().await;
+                if let ImapSession::Authenticated(session) = &mut *imap_client {
+                    self.select(session, &folder_id).await?;
+
+                    let fetches = session
+                        .uid_fetch(
+                            uid.to_string(),
+                            &format!(
+                                "(BODY.PEEK[{}]<{}.{}>)",
+                                part_number.as_str(),
+                                content.len(),
+                                self.chunk_size
+                            ),
+                        )
+                        .await
+                        .map_err(|e| ImapError::Imap(format!("Failed to fetch message part: {}", e)))?
+                        .try_collect::<Vec<_>>()
+                        .await
+                        .map_err(|e| ImapError::Imap(format!("Failed to fetch message part: {}", e)))?;
+
+                    fetches
+                        .iter()
+                        .find_map(|fetch| fetch.section(&section))
+                        .map(|data| data.to_vec())
+                        .ok_or_else(|| ImapError::InvalidData("Message part not found".to_string()))?
+                } else {
+                    return Err(ImapError::NotAuthenticated);
+                }
+            };
+
+            content.extend_from_slice(&chunk);
+            let fetched = content.len() as u64;
+            progress(total.map_or(fetched, |total| fetched.min(total)), total);
+            // A short or empty chunk is the last one. The size from
+            // BODYSTRUCTURE can be off, so it doesn't end the download.
+            if chunk.len() < self.chunk_size {
+                return Ok(content);
+            }
+        }
+    }
+
+    fn section_path(part_number: &MessagePartId) -> Result<SectionPath, ImapError> {
+        part_number
+            .as_str()
+            .split('.')
+            .map(|part| part.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map(|parts| SectionPath::Part(parts, None))
+            .map_err(|_| {
+                ImapError::InvalidData(format!("Invalid part number: {}", part_number.as_str()))
+            })
+    }
+
+    /// Fetches the BODYSTRUCTURE of a message. The returned responses own the
+    /// data, so the session isn't kept locked while it's used.
+    async fn fetch_bodystructure(&self, message_id: &MessageId) -> Result<Vec<Fetch>, ImapError> {
+        let (folder_id, uid) = parse_message_id(message_id)?;
+        let mut imap = self.lock_session().await;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            self.select(session, &folder_id).await?;
+
+            session
+                .uid_fetch(uid.to_string(), "(BODYSTRUCTURE)")
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to fetch message structure: {}", e)))?
+                .try_collect::<Vec<_>>()
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to fetch message structure: {}", e)))
+        } else {
+            Err(ImapError::NotAuthenticated)
+        }
+    }
+
+    /// Finds a part, identified by its section number (e.g. `1.2`), in the
+    /// body structure of the message.
+    fn find_part<'a, 'b>(
+        bodystructure: &'b BodyStructure<'a>,
         part_number: &MessagePartId,
-    ) -> Result<Vec<u8>, ImapError> {
-        let mut imap_client = self.imap.lock().await;
-        if let ImapSession::Authenticated(session) = &mut *imap_client {
-            session
-                .select("INBOX")
-                .await
-                .map_err(|e| ImapError::Imap(format!("Failed to select folder: {}", e)))?;
-
-            let mut fetch = session
-                .fetch(
-                    message_id.as_str(),
-                    &format!("(BODY.PEEK[{}])", part_number.as_str()),
-                )
-                .await
-                .map_err(|e| ImapError::Imap(format!("Failed to fetch message part: {}", e)))?;
+    ) -> Option<&'b BodyStructure<'a>> {
+        let mut part = bodystructure;
+        for index in part_number.as_str().split('.') {
+            let index = index.parse::<usize>().ok()?.checked_sub(1)?;
+            // The parts of an attached message are numbered within its body.
+            let container = match part {
+                BodyStructure::Message { body, .. } => body.as_ref(),
+                _ => part,
+            };
+            part = match container {
+                BodyStructure::Multipart { bodies, .. } => bodies.get(index)?,
+                _ if index == 0 => container,
+                _ => return None,
+            };
+        }
+        Some(part)
+    }
 
-            let fetch = fetch
-                .next()
-                .await
-                .ok_or_else(|| ImapError::InvalidData("Message not found".to_string()))?
-                .map_err(|e| ImapError::Imap(format!("Failed to fetch message part: {}", e)))?;
+    /// The size of a part as sent by the server, `None` for multiparts.
+    fn part_size(part: &BodyStructure<'_>) -> Option<u64> {
+        match part {
+            BodyStructure::Basic { other, .. }
+            | BodyStructure::Text { other, .. }
+            | BodyStructure::Message { other, .. } => Some(u64::from(other.octets)),
+            BodyStructure::Multipart { .. } => None,
+        }
+    }
+}
 
-            fetch
-                .body()
-                .ok_or_else(|| ImapError::InvalidData("Message part not found".to_string()))
-                .map(|body| body.to_vec())
-        } else {
-            Err(ImapError::NotAuthenticated.into())
+/// Registers the `imaps` and `imap` (STARTTLS) schemes, e.g.
+/// `imaps://me%40example.com@imap.example.com`. The port defaults to 993 and
+/// 143, and the password is the one passed to `authenticate`. The `auth`
+/// parameter picks the authentication method, see [`BackendUri::imap`].
+/// Unencrypted connections are not supported, so `security=none` is an
+/// error.
+pub fn register<S>(registry: &mut ConnectorRegistry<S>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + std::fmt::Debug + Send + Sync + 'static,
+{
+    fn factory<S>(security: ConnectionSecurity, default_port: u16) -> impl ConnectorFactory<S>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + std::fmt::Debug + Send + Sync + 'static,
+    {
+        move |uri: &BackendUri| -> MailinerResult<Arc<dyn EmailConnector<S>>> {
+            let username = uri.username.clone().ok_or_else(|| {
+                ImapError::InvalidData(format!("No user name in {}", uri))
+            })?;
+            if uri.param("security") == Some("none") {
+                return Err(ImapError::InvalidData(format!(
+                    "Unencrypted connections are not supported: {}",
+                    uri
+                ))
+                .into());
+            }
+            let mut connector = ImapConnector::<S>::new(
+                uri.host.clone(),
+                uri.port.unwrap_or(default_port),
+                username,
+                String::new(),
+            )
+            .with_security(security);
+            if let Some(method) = uri.param("auth") {
+                connector = connector.with_auth_method(method.parse()?);
+            }
+            Ok(Arc::new(connector))
         }
     }
+
+    registry.register("imaps", factory(ConnectionSecurity::Tls, 993));
+    registry.register("imap", factory(ConnectionSecurity::StartTls, 143));
 }
 
 #[async_trait]
@@ -270,7 +1584,7 @@ where
     }
 
     async fn disconnect(&self) -> MailinerResult<()> {
-        let mut imap = self.imap.lock().await;
+        let mut imap = self.lock_session().await;
         if let ImapSession::Authenticated(session) = &mut *imap {
             session
                 .logout()
@@ -278,21 +1592,75 @@ where
                 .map_err(|e| ImapError::Connection(format!("Failed to logout: {}", e)))?;
         }
         *imap = ImapSession::Disconnected;
+        *self.selected.lock().unwrap() = None;
         Ok(())
     }
 
     async fn authenticate(&self, credentials: &str) -> MailinerResult<Account> {
-        let mut imap = self.imap.lock().await;
+        let mut imap = self.lock_session().await;
         if let ImapSession::Unauthenticated(_) = &*imap {
             // Temporarily transition to Authenticating state and consume the imap session,
             // that we know is in Unauthenticated state.
             let unauth_imap = std::mem::replace(&mut *imap, ImapSession::Authenticating);
-            if let ImapSession::Unauthenticated(client) = unauth_imap {
-                let authenticated = client.login(&self.username, credentials).await;
+            if let ImapSession::Unauthenticated(mut client) = unauth_imap {
+                let capabilities = Self::client_capabilities(&mut client).await?;
+                let auth_method = match self.auth_method {
+                    Some(auth_method) => auth_method,
+                    None => auth::password_method(&capabilities).ok_or_else(|| {
+                        ImapError::Authentication(
+                            "Server doesn't allow logging in with a password".to_string(),
+                        )
+                    })?,
+                };
+                let authenticated = match auth_method {
+                    AuthMethod::Login => client
+                        .login(&self.username, credentials)
+                        .await
+                        .map_err(|(e, _)| format!("Failed to login: {}", e)),
+                    method => {
+                        let mut authenticator = auth::SaslAuthenticator::new(
+                            method,
+                            &self.username,
+                            credentials,
+                            &self.host,
+                            self.port,
+                        );
+                        let sasl_ir = capabilities.iter().any(|c| c.eq_ignore_ascii_case("SASL-IR"));
+                        let command = authenticator.command(sasl_ir);
+                        let authenticated = client.authenticate(command, &mut authenticator).await;
+                        authenticated.map_err(|(e, _)| match &authenticator.error {
+                            Some(details) => format!("Failed to authenticate: {} ({})", e, details),
+                            None => format!("Failed to authenticate: {}", e),
+                        })
+                    }
+                };
+                let mut session = authenticated.map_err(ImapError::Authentication)?;
+                Self::enable_qresync(&mut session).await?;
+                let capabilities = session
+                    .capabilities()
+                    .await
+                    .map_err(|e| ImapError::Imap(format!("Failed to get capabilities: {}", e)))?;
+                *self.capabilities.lock().unwrap() =
+                    capabilities.iter().map(Self::capability_name).collect();
+                let mut namespaces = Vec::new();
+                if self.has_capability("NAMESPACE") {
+                    namespaces = self.query_namespaces(&mut session).await?;
+                }
+                if namespaces.is_empty() {
+                    namespaces.push(Namespace::root(Self::hierarchy_delimiter(&mut session).await?));
+                }
+                *self.namespaces.lock().unwrap() = namespaces;
+                let utf8 = self.has_capability("UTF8=ACCEPT");
+                if utf8 {
+                    session
+                        .run_command_and_check_ok("ENABLE UTF8=ACCEPT")
+                        .await
+                        .map_err(|e| ImapError::Imap(format!("Failed to enable UTF8=ACCEPT: {}", e)))?;
+                }
+                *self.utf8.lock().unwrap() = utf8;
+                *self.selected.lock().unwrap() = None;
                 // Transition from the temporary Authenticating state to the Authenticated state.
-                *imap = ImapSession::Authenticated(authenticated.map_err(|(e, _)| {
-                    ImapError::Authentication(format!("Failed to login: {}", e))
-                })?);
+                *imap = ImapSession::Authenticated(session);
             } else {
                 return Err(MailinerError::Connector(
                     "IMAP session in invalid state".to_string(),
@@ -319,9 +1687,11 @@ where
     }
 
     async fn list_folders(&self, account_id: &AccountId) -> MailinerResult<Vec<Folder>> {
-        let mut imap = self.imap.lock().await;
+        let mut imap = self.lock_session().await;
         if let ImapSession::Authenticated(session) = &mut *imap {
             let mut mailboxes = Vec::new();
+            // Folders that only exist in the hierarchy, which have no rights.
+            let mut unselectable = Vec::new();
             let mut list = session
                 .lsub(Some(""), Some("*"))
                 .await
@@ -330,21 +1700,42 @@ where
             while let Some(result) = list.next().await {
                 let mailbox =
                     result.map_err(|e| ImapError::Imap(format!("Failed to get mailbox: {}", e)))?;
-                let full_name = mailbox.name().to_string();
-                let name_chunked = full_name.split(mailbox.delimiter().unwrap_or("/")).collect::<Vec<&str>>();
+                let delimiter = match mailbox.delimiter() {
+                    Some(delimiter) => Some(delimiter.to_string()),
+                    None => self.delimiter(mailbox.name()),
+                };
+                let (name, parent) = Self::parse_folder_hierarchy(mailbox.name(), delimiter.as_deref());
+                let id = FolderId::new(mailbox.name().to_string());
+                if mailbox.attributes().iter().any(|attribute| match attribute {
+                    NameAttribute::NoSelect => true,
+                    NameAttribute::Extension(name) => name.eq_ignore_ascii_case("\\NonExistent"),
+                    _ => false,
+                }) {
+                    unselectable.push(id.clone());
+                }
                 mailboxes.push(Folder {
-                    id: FolderId::new(mailbox.name().to_string()),
+                    id,
                     account_id: account_id.clone(),
-                    name: name_chunked.last().unwrap_or(&mailbox.name()).to_string(),
-                    parent_id: if name_chunked.len() > 1 {
-                        Some(FolderId::new(name_chunked[..name_chunked.len() - 1].join(mailbox.delimiter().unwrap_or("/"))))
-                    } else {
-                        None
-                    },
+                    name: self.decode_folder_name(&name),
+                    parent_id: parent.map(FolderId::new),
+                    rights: None,
                     created_at: Utc::now(),
                     updated_at: Utc::now(),
                 });
             }
+            drop(list);
+
+            let capabilities = session
+                .capabilities()
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to get capabilities: {}", e)))?;
+            if capabilities.has_str("ACL") {
+                for mailbox in &mut mailboxes {
+                    if !unselectable.contains(&mailbox.id) {
+                        mailbox.rights = self.my_rights(session, &mailbox.id).await?;
+                    }
+                }
+            }
 
             Ok(mailboxes)
         } else {
@@ -358,13 +1749,9 @@ where
         name: &str,
         parent_id: Option<&FolderId>,
     ) -> MailinerResult<Folder> {
-        let mut imap = self.imap.lock().await;
+        let mut imap = self.lock_session().await;
         if let ImapSession::Authenticated(session) = &mut *imap {
-            let full_name = if let Some(parent) = parent_id {
-                format!("{}/{}", parent.as_str(), name)
-            } else {
-                name.to_string()
-            };
+            let full_name = self.full_folder_name(name, parent_id.map(|id| id.as_str()))?;
 
             session
                 .create(&full_name)
@@ -376,6 +1763,7 @@ where
                 account_id: account_id.clone(),
                 name: name.to_string(),
                 parent_id: parent_id.cloned(),
+                rights: None,
                 created_at: Utc::now(),
                 updated_at: Utc::now(),
             })
@@ -385,8 +1773,13 @@ where
     }
 
     async fn delete_folder(&self, folder_id: &FolderId) -> MailinerResult<()> {
-        let mut imap = self.imap.lock().await;
+        let mut imap = self.lock_session().await;
         if let ImapSession::Authenticated(session) = &mut *imap {
+            // Servers may refuse to delete the selected folder.
+            if self.selected.lock().unwrap().as_ref() == Some(folder_id) {
+                self.unselect(session).await?;
+            }
+            *self.selected.lock().unwrap() = None;
             session
                 .delete(folder_id.as_str())
                 .await
@@ -397,126 +1790,243 @@ where
         }
     }
 
+    async fn rename_folder(&self, folder_id: &FolderId, new_name: &str) -> MailinerResult<FolderId> {
+        let mut imap = self.lock_session().await;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            let delimiter = self.delimiter(folder_id.as_str());
+            let (_, parent) = Self::parse_folder_hierarchy(folder_id.as_str(), delimiter.as_deref());
+            let full_name = self.full_folder_name(new_name, parent.as_deref())?;
+
+            // The server renames the subfolders along with the folder, which may
+            // be the selected one.
+            let affected = self.selected.lock().unwrap().as_ref().is_some_and(|selected| {
+                selected == folder_id
+                    || delimiter.as_deref().is_some_and(|delimiter| {
+                        selected
+                            .as_str()
+                            .starts_with(&format!("{}{}", folder_id.as_str(), delimiter))
+                    })
+            });
+            if affected {
+                self.unselect(session).await?;
+            }
+            *self.selected.lock().unwrap() = None;
+            session
+                .rename(folder_id.as_str(), &full_name)
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to rename folder: {}", e)))?;
+            Ok(FolderId::new(full_name))
+        } else {
+            Err(ImapError::NotAuthenticated.into())
+        }
+    }
+
     async fn list_envelopes(&self, folder_id: &FolderId) -> MailinerResult<Vec<Envelope>> {
         self.list_envelopes_range(folder_id, 0..usize::MAX).await
     }
 
     async fn list_envelopes_range(&self, folder_id: &FolderId, range: std::ops::Range<usize>) -> MailinerResult<Vec<Envelope>> {
-        let mut imap = self.imap.lock().await;
+        let mut imap = self.lock_session().await;
         if let ImapSession::Authenticated(session) = &mut *imap {
-            session
-                .select(folder_id.as_str())
-                .await
-                .map_err(|e| ImapError::Imap(format!("Failed to select folder: {}", e)))?;
+            // Always select, even if selected already, for the current message count.
+            let mailbox = self.select_mailbox(session, folder_id, false).await?;
 
-            let mut envelopes = Vec::new();
-
-            // Convert range to IMAP sequence set format
-            let sequence_set = if range.end == usize::MAX {
-                format!("{}:*", range.start + 1)  // IMAP uses 1-based indexing
-            } else {
-                format!("{}:{}", range.start + 1, range.end)
+            // The range counts from the newest message, which has the highest
+            // sequence number.
+            let Some(sequence_set) = sequence_range(mailbox.exists as usize, &range) else {
+                return Ok(Vec::new());
             };
 
+            let mut envelopes = Vec::new();
             let mut fetch = session
-                .uid_fetch(&sequence_set, "(RFC822.HEADER FLAGS BODYSTRUCTURE)")
+                .fetch(&sequence_set, format!("({})", self.envelope_items()))
                 .await
                 .map_err(|e| ImapError::Imap(format!("Failed to fetch messages: {}", e)))?;
 
             while let Some(result) = fetch.next().await {
                 let fetch = result
                     .map_err(|e| ImapError::Imap(format!("Failed to fetch message: {}", e)))?;
-                let header = fetch
-                    .header()
-                    .ok_or_else(|| ImapError::InvalidData("No header found".to_string()))?;
-                let (is_read, is_starred, is_flagged, is_draft, is_deleted) =
-                    Self::parse_flags(fetch.flags());
-                assert!(fetch.uid.is_some());
-
-                let parser = MessageParser::new();
-                let parsed_headers = parser.parse_headers(header).ok_or::<MailinerError>(
-                    ImapError::InvalidData("Failed to parse headers".to_string()).into(),
-                )?;
-
-                envelopes.push(Envelope {
-                    id: MessageId::new(fetch.uid.unwrap().to_string()),
-                    account_id: AccountId::new(self.username.clone()),
-                    folder_id: folder_id.clone(),
-                    subject: parsed_headers.subject().map(|s| s.to_string()),
-                    from: Self::parse_email_address(parsed_headers.from()),
-                    to: Self::parse_email_address(parsed_headers.to()),
-                    cc: Self::parse_email_address(parsed_headers.cc()),
-                    bcc: Self::parse_email_address(parsed_headers.bcc()),
-                    date: Self::parse_date(parsed_headers.date())?,
-                    is_read,
-                    is_starred,
-                    is_flagged,
-                    is_draft,
-                    is_deleted,
-                    has_attachments: Self::has_attachments(fetch.bodystructure()),
-                    created_at: Utc::now(),
-                    updated_at: Utc::now(),
-                });
+                envelopes.push((fetch.message, self.envelope_from_fetch(folder_id, &fetch)?));
             }
+            drop(fetch);
+            // Responses may come in any order.
+            envelopes.sort_by(|a, b| b.0.cmp(&a.0));
 
+            let mut envelopes: Vec<Envelope> =
+                envelopes.into_iter().map(|(_, envelope)| envelope).collect();
+            self.add_gmail_attributes(session, &mut envelopes).await?;
             Ok(envelopes)
         } else {
             Err(ImapError::NotAuthenticated.into())
         }
     }
 
-    async fn get_envelope(&self, message_id: &MessageId) -> MailinerResult<Envelope> {
-        let mut imap = self.imap.lock().await;
+    async fn sync_folder(
+        &self,
+        folder_id: &FolderId,
+        state: Option<&FolderSyncState>,
+        known: &[MessageId],
+    ) -> MailinerResult<FolderDelta> {
+        let mut imap = self.lock_session().await;
         if let ImapSession::Authenticated(session) = &mut *imap {
-            session
-                .select("INBOX")
+            let capabilities = session
+                .capabilities()
                 .await
-                .map_err(|e| ImapError::Imap(format!("Failed to select folder: {}", e)))?;
+                .map_err(|e| ImapError::Imap(format!("Failed to get capabilities: {}", e)))?;
+            // QRESYNC is enabled right after login when available, see `enable_qresync()`.
+            let qresync = capabilities.has_str("QRESYNC");
+            let condstore = qresync || capabilities.has_str("CONDSTORE");
 
-            let mut fetch = session
-                .fetch(message_id.as_str(), "(RFC822.HEADER FLAGS BODYSTRUCTURE)")
+            // Drop leftovers from earlier commands, so that only VANISHED responses
+            // to this sync are collected below. The sync reports the folder's
+            // state, which supersedes the ones kept for IDLE too.
+            while session.unsolicited_responses.try_recv().is_ok() {}
+            self.unsolicited.lock().unwrap().clear();
+
+            // Always select, even if selected already, for the current state of the folder.
+            let mailbox = self.select_mailbox(session, folder_id, condstore).await?;
+
+            let sync_state = match (mailbox.uid_validity, mailbox.highest_modseq) {
+                (Some(uid_validity), Some(highest_modseq)) => Some(FolderSyncState {
+                    uid_validity,
+                    highest_modseq,
+                }),
+                _ => None,
+            };
+            // Only a state with the same UIDVALIDITY refers to the same messages.
+            let since = state
+                .filter(|state| sync_state.is_some_and(|s| s.uid_validity == state.uid_validity))
+                .map(|state| state.highest_modseq);
+
+            let mut changed = Vec::new();
+            let unchanged = since.is_some() && since == sync_state.map(|s| s.highest_modseq);
+            if mailbox.exists > 0 && !unchanged {
+                let items = self.envelope_items();
+                let query = match since {
+                    Some(modseq) if qresync => {
+                        format!("({}) (CHANGEDSINCE {} VANISHED)", items, modseq)
+                    }
+                    Some(modseq) => format!("({}) (CHANGEDSINCE {})", items, modseq),
+                    None => format!("({})", items),
+                };
+                let mut fetch = session
+                    .uid_fetch("1:*", &query)
+                    .await
+                    .map_err(|e| ImapError::Imap(format!("Failed to fetch messages: {}", e)))?;
+                while let Some(result) = fetch.next().await {
+                    let fetch = result
+                        .map_err(|e| ImapError::Imap(format!("Failed to fetch message: {}", e)))?;
+                    changed.push(self.envelope_from_fetch(folder_id, &fetch)?);
+                }
+            }
+
+            let vanished = if since.is_some() && qresync {
+                // With QRESYNC the server reports expunged messages in VANISHED responses.
+                let mut vanished = Vec::new();
+                while let Ok(response) = session.unsolicited_responses.try_recv() {
+                    if let UnsolicitedResponse::Other(data) = response {
+                        if let Response::Vanished { uids, .. } = data.parsed() {
+                            vanished.extend(
+                                uids.iter()
+                                    .flat_map(|range| range.clone())
+                                    .map(|uid| to_message_id(folder_id, uid)),
+                            );
+                        }
+                    }
+                }
+                vanished
+            } else {
+                let existing = session
+                    .uid_search("ALL")
+                    .await
+                    .map_err(|e| ImapError::Imap(format!("Failed to search messages: {}", e)))?;
+                known
+                    .iter()
+                    .filter(|id| {
+                        parse_message_id(id).map_or(true, |(_, uid)| !existing.contains(&uid))
+                    })
+                    .cloned()
+                    .collect()
+            };
+
+            self.add_gmail_attributes(session, &mut changed).await?;
+
+            Ok(FolderDelta {
+                sync_state,
+                changed,
+                vanished,
+                read_only: self.read_only,
+            })
+        } else {
+            Err(ImapError::NotAuthenticated.into())
+        }
+    }
+
+    async fn get_envelope(&self, message_id: &MessageId) -> MailinerResult<Envelope> {
+        let (folder_id, uid) = parse_message_id(message_id)?;
+        let mut imap = self.lock_session().await;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            self.select(session, &folder_id).await?;
+
+            let fetches = session
+                .uid_fetch(uid.to_string(), format!("({})", self.envelope_items()))
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to fetch message: {}", e)))?
+                .try_collect::<Vec<_>>()
                 .await
                 .map_err(|e| ImapError::Imap(format!("Failed to fetch message: {}", e)))?;
+            let fetch = fetches
+                .first()
+                .ok_or_else(|| ImapError::InvalidData("Message not found".to_string()))?;
+
+            let mut envelope = self.envelope_from_fetch(&folder_id, fetch)?;
+            self.add_gmail_attributes(session, std::slice::from_mut(&mut envelope))
+                .await?;
+            Ok(envelope)
+        } else {
+            Err(ImapError::NotAuthenticated.into())
+        }
+    }
 
-            let fetch = fetch
-                .next()
+    async fn resync_flags(
+        &self,
+        folder_id: &FolderId,
+        since_modseq: u64,
+    ) -> MailinerResult<Vec<FlagUpdate>> {
+        let mut imap = self.lock_session().await;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            self.select(session, folder_id).await?;
+
+            let condstore = self.has_capability("CONDSTORE") || self.has_capability("QRESYNC");
+            let query = if condstore && since_modseq > 0 {
+                format!("(UID FLAGS) (CHANGEDSINCE {})", since_modseq)
+            } else {
+                "(UID FLAGS)".to_string()
+            };
+            let fetches = session
+                .uid_fetch("1:*", &query)
                 .await
-                .ok_or_else(|| ImapError::InvalidData("Message not found".to_string()))?
-                .map_err(|e| ImapError::Imap(format!("Failed to fetch message: {}", e)))?;
+                .map_err(|e| ImapError::Imap(format!("Failed to fetch flags: {}", e)))?
+                .try_collect::<Vec<_>>()
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to fetch flags: {}", e)))?;
 
-            let header = fetch
-                .header()
-                .ok_or_else(|| ImapError::InvalidData("Invalid message header".to_string()))?;
-
-            let (is_read, is_starred, is_flagged, is_draft, is_deleted) =
-                Self::parse_flags(fetch.flags());
-
-            let parsed_headers =
-                MessageParser::new()
-                    .parse_headers(header)
-                    .ok_or(ImapError::InvalidData(
-                        "Failed to parse headers".to_string(),
-                    ))?;
-
-            Ok(Envelope {
-                id: message_id.clone(),
-                account_id: AccountId::new(self.username.clone()),
-                folder_id: FolderId::new("INBOX"),
-                subject: parsed_headers.subject().map(|s| s.to_string()),
-                from: Self::parse_email_address(parsed_headers.from()),
-                to: Self::parse_email_address(parsed_headers.to()),
-                cc: Self::parse_email_address(parsed_headers.cc()),
-                bcc: Self::parse_email_address(parsed_headers.bcc()),
-                date: Self::parse_date(parsed_headers.date())?,
-                is_read,
-                is_starred,
-                is_flagged,
-                is_draft,
-                is_deleted,
-                has_attachments: Self::has_attachments(fetch.bodystructure()),
-                created_at: Utc::now(),
-                updated_at: Utc::now(),
-            })
+            Ok(fetches
+                .iter()
+                .filter_map(|fetch| {
+                    let (is_read, is_starred, is_flagged, is_draft, is_deleted) =
+                        Self::parse_flags(fetch.flags());
+                    Some(FlagUpdate {
+                        message_id: to_message_id(folder_id, fetch.uid?),
+                        is_read,
+                        is_starred,
+                        is_flagged,
+                        is_draft,
+                        is_deleted,
+                        keywords: Self::parse_keywords(fetch.flags()),
+                    })
+                })
+                .collect())
         } else {
             Err(ImapError::NotAuthenticated.into())
         }
@@ -527,33 +2037,21 @@ where
         message_id: &MessageId,
         flags: &[(&str, bool)],
     ) -> MailinerResult<()> {
-        let mut imap = self.imap.lock().await;
+        let (folder_id, uid) = parse_message_id(message_id)?;
+        let mut imap = self.lock_session().await;
         if let ImapSession::Authenticated(session) = &mut *imap {
-            session
-                .select("INBOX")
-                .await
-                .map_err(|e| ImapError::Imap(format!("Failed to select folder: {}", e)))?;
+            self.select(session, &folder_id).await?;
 
             for (flag, value) in flags {
-                let flag = match *flag {
-                    "is_read" => Flag::Seen,
-                    "is_flagged" => Flag::Flagged,
-                    "is_draft" => Flag::Draft,
-                    "is_deleted" => Flag::Deleted,
-                    "is_starred" => Flag::Custom("\\Starred".into()),
-                    _ => {
-                        return Err(ImapError::InvalidData(format!("Unknown flag: {}", flag)).into())
-                    }
-                };
-
+                let flag = imap_flag(flag)?;
                 let stream = if *value {
                     session
-                        .store(message_id.as_str(), format!("+FLAGS ({:?})", flag))
+                        .uid_store(uid.to_string(), format!("+FLAGS ({})", flag))
                         .await
                         .map_err(|e| ImapError::Imap(format!("Failed to set flag: {}", e)))?
                 } else {
                     session
-                        .store(message_id.as_str(), format!("-FLAGS ({:?})", flag))
+                        .uid_store(uid.to_string(), format!("-FLAGS ({})", flag))
                         .await
                         .map_err(|e| ImapError::Imap(format!("Failed to remove flag: {}", e)))?
                 };
@@ -568,52 +2066,449 @@ where
         }
     }
 
-    async fn get_message_part(
+    async fn bulk_update_envelope_flags(
+        &self,
+        message_ids: &[MessageId],
+        flags: &[(&str, bool)],
+    ) -> MailinerResult<()> {
+        let names = |value: bool| {
+            flags
+                .iter()
+                .filter(|(_, set)| *set == value)
+                .map(|(flag, _)| imap_flag(flag))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let (added, removed) = (names(true)?, names(false)?);
+
+        // One STORE per folder and direction, not one per message and flag.
+        let mut folders: Vec<(FolderId, Vec<MessageId>)> = Vec::new();
+        for message_id in message_ids {
+            let (folder_id, _) = parse_message_id(message_id)?;
+            match folders.iter_mut().find(|(folder, _)| *folder == folder_id) {
+                Some((_, ids)) => ids.push(message_id.clone()),
+                None => folders.push((folder_id, vec![message_id.clone()])),
+            }
+        }
+        for (_, ids) in folders {
+            if !added.is_empty() {
+                self.store_flags(&ids, &added, true, None).await?;
+            }
+            if !removed.is_empty() {
+                self.store_flags(&ids, &removed, false, None).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn update_envelope_keywords(
         &self,
         message_id: &MessageId,
-        part_id: &MessagePartId,
-    ) -> MailinerResult<MessagePart> {
-        let mut imap = self.imap.lock().await;
+        keywords: &[(&str, bool)],
+    ) -> MailinerResult<()> {
+        let (folder_id, uid) = parse_message_id(message_id)?;
+        let mut imap = self.lock_session().await;
         if let ImapSession::Authenticated(session) = &mut *imap {
-            session
-                .select("INBOX")
-                .await
-                .map_err(|e| ImapError::Imap(format!("Failed to select folder: {}", e)))?;
+            self.select(session, &folder_id).await?;
 
-            let mut fetch = session
-                .fetch(message_id.as_str(), "(RFC822.HEADER FLAGS BODYSTRUCTURE)")
-                .await
-                .map_err(|e| {
-                    ImapError::Imap(format!("Failed to fetch message structure: {}", e))
-                })?;
+            for (keyword, value) in keywords {
+                if !is_keyword(keyword) {
+                    return Err(ImapError::InvalidData(format!("Invalid keyword: {}", keyword)).into());
+                }
+                if !self.keyword_allowed(keyword) {
+                    return Err(ImapError::InvalidData(format!(
+                        "Folder doesn't allow keyword: {}",
+                        keyword
+                    ))
+                    .into());
+                }
+
+                let sign = if *value { '+' } else { '-' };
+                session
+                    .uid_store(uid.to_string(), format!("{}FLAGS ({})", sign, keyword))
+                    .await
+                    .map_err(|e| ImapError::Imap(format!("Failed to update keyword: {}", e)))?
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .map_err(|e| ImapError::Imap(format!("Failed to update keyword: {}", e)))?;
+            }
+
+            Ok(())
+        } else {
+            Err(ImapError::NotAuthenticated.into())
+        }
+    }
+
+    /// Uses Gmail's labels where there are, keywords elsewhere.
+    async fn update_envelope_labels(
+        &self,
+        message_id: &MessageId,
+        labels: &[(&str, bool)],
+    ) -> MailinerResult<()> {
+        if !self.has_capability("X-GM-EXT-1") {
+            return self.update_envelope_keywords(message_id, labels).await;
+        }
+
+        let (folder_id, uid) = parse_message_id(message_id)?;
+        let mut imap = self.lock_session().await;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            self.select(session, &folder_id).await?;
 
-            let fetch = fetch
-                .next()
+            for (label, value) in labels {
+                // Labels are named like folders.
+                let name = if *self.utf8.lock().unwrap() {
+                    label.to_string()
+                } else {
+                    utf7::encode(label)
+                };
+                let sign = if *value { '+' } else { '-' };
+                session
+                    .uid_store(uid.to_string(), format!("{}X-GM-LABELS ({})", sign, quote(&name)))
+                    .await
+                    .map_err(|e| ImapError::Imap(format!("Failed to update label: {}", e)))?
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .map_err(|e| ImapError::Imap(format!("Failed to update label: {}", e)))?;
+            }
+
+            Ok(())
+        } else {
+            Err(ImapError::NotAuthenticated.into())
+        }
+    }
+
+    async fn move_message(
+        &self,
+        message_id: &MessageId,
+        target_folder_id: &FolderId,
+    ) -> MailinerResult<Option<MessageId>> {
+        let (folder_id, uid) = parse_message_id(message_id)?;
+        let mut imap = self.lock_session().await;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            let capabilities = session
+                .capabilities()
                 .await
-                .ok_or_else(|| ImapError::InvalidData("Message not found".to_string()))?
-                .map_err(|e| {
-                    ImapError::Imap(format!("Failed to fetch message structure: {}", e))
-                })?;
+                .map_err(|e| ImapError::Imap(format!("Failed to get capabilities: {}", e)))?;
+            self.select(session, &folder_id).await?;
 
-            // Fetch the actual content
-            let content = self
-                .fetch_message_part(&message_id, part_id)
+            if capabilities.has_str("MOVE") {
+                let uids = Self::run_uidplus_command(
+                    session,
+                    &format!("UID MOVE {} {}", uid, quote(target_folder_id.as_str())),
+                )
                 .await?;
+                return Ok(uids.first().map(|uid| to_message_id(target_folder_id, *uid)));
+            }
 
-            // TODO: Parse the content
-            Ok(MessagePart {
-                id: part_id.clone(),
-                envelope_id: message_id.clone(),
-                content_type: "text/plain".to_string(),
-                filename: None,
-                size: content.len() as u64,
-                is_attachment: false,
-                content: MessageContent::Text(String::from_utf8_lossy(&content).to_string()),
-                created_at: Utc::now(),
-                updated_at: Utc::now(),
-            })
+            // Without MOVE, copy the message and remove the original.
+            let uids = Self::run_uidplus_command(
+                session,
+                &format!("UID COPY {} {}", uid, quote(target_folder_id.as_str())),
+            )
+            .await?;
+            session
+                .uid_store(uid.to_string(), "+FLAGS.SILENT (\\Deleted)")
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to delete message: {}", e)))?
+                .try_collect::<Vec<_>>()
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to delete message: {}", e)))?;
+            // A plain EXPUNGE would also remove other messages marked as deleted by the
+            // user, so without UIDPLUS the original is only left marked as deleted.
+            if capabilities.has_str("UIDPLUS") {
+                session
+                    .uid_expunge(uid.to_string())
+                    .await
+                    .map_err(|e| ImapError::Imap(format!("Failed to expunge message: {}", e)))?
+                    .try_collect::<Vec<_>>()
+                    .await
+                    .map_err(|e| ImapError::Imap(format!("Failed to expunge message: {}", e)))?;
+            }
+            Ok(uids.first().map(|uid| to_message_id(target_folder_id, *uid)))
+        } else {
+            Err(ImapError::NotAuthenticated.into())
+        }
+    }
+
+    async fn copy_message(
+        &self,
+        message_id: &MessageId,
+        target_folder_id: &FolderId,
+    ) -> MailinerResult<Option<MessageId>> {
+        let (folder_id, uid) = parse_message_id(message_id)?;
+        let mut imap = self.lock_session().await;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            self.select(session, &folder_id).await?;
+            let uids = Self::run_uidplus_command(
+                session,
+                &format!("UID COPY {} {}", uid, quote(target_folder_id.as_str())),
+            )
+            .await?;
+            Ok(uids.first().map(|uid| to_message_id(target_folder_id, *uid)))
+        } else {
+            Err(ImapError::NotAuthenticated.into())
+        }
+    }
+
+    async fn append_message(
+        &self,
+        folder_id: &FolderId,
+        flags: &[&str],
+        content: &[u8],
+    ) -> MailinerResult<Option<MessageId>> {
+        ImapConnector::append_message(self, folder_id, flags, None, content).await
+    }
+
+    async fn delete_message(&self, message_id: &MessageId) -> MailinerResult<()> {
+        let message_ids = std::slice::from_ref(message_id);
+        self.store_flags(message_ids, &["\\Deleted"], true, None)
+            .await?;
+        // Without UIDPLUS, the message is expunged along with the others the
+        // next time the folder is.
+        if self.has_capability("UIDPLUS") {
+            self.uid_expunge(message_ids).await?;
+        }
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        folder_id: &FolderId,
+        query: &SearchQuery,
+    ) -> MailinerResult<Vec<MessageId>> {
+        let mut imap = self.lock_session().await;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            self.select(session, folder_id).await?;
+
+            let mut uids: Vec<u32> = session
+                .uid_search(search::search_criteria(query))
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to search messages: {}", e)))?
+                .into_iter()
+                .collect();
+            uids.sort_unstable();
+            Ok(uids
+                .into_iter()
+                .map(|uid| to_message_id(folder_id, uid))
+                .collect())
         } else {
             Err(ImapError::NotAuthenticated.into())
         }
     }
+
+    async fn get_message_structure(&self, message_id: &MessageId) -> MailinerResult<MessageStructure> {
+        let fetches = self.fetch_bodystructure(message_id).await?;
+        let bodystructure = fetches
+            .iter()
+            .find_map(|fetch| fetch.bodystructure())
+            .ok_or_else(|| ImapError::InvalidData("No BODYSTRUCTURE in FETCH response".to_string()))?;
+        Ok(bodystructure::message_structure(bodystructure))
+    }
+
+    async fn fetch_raw_message(&self, message_id: &MessageId) -> MailinerResult<Vec<u8>> {
+        let (folder_id, uid) = parse_message_id(message_id)?;
+        let mut imap = self.lock_session().await;
+        let ImapSession::Authenticated(session) = &mut *imap else {
+            return Err(ImapError::NotAuthenticated.into());
+        };
+        self.select(session, &folder_id).await?;
+
+        let fetches = session
+            .uid_fetch(uid.to_string(), "(BODY.PEEK[])")
+            .await
+            .map_err(|e| ImapError::Imap(format!("Failed to fetch message: {}", e)))?
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(|e| ImapError::Imap(format!("Failed to fetch message: {}", e)))?;
+        let raw = fetches
+            .iter()
+            .find_map(|fetch| fetch.body())
+            .ok_or_else(|| MailinerError::NotFound(format!("No message {}", message_id)))?;
+        Ok(raw.to_vec())
+    }
+
+    async fn get_message_part(
+        &self,
+        message_id: &MessageId,
+        part_id: &MessagePartId,
+    ) -> MailinerResult<MessagePart> {
+        <Self as EmailConnector<S>>::get_message_part_with_progress(
+            self,
+            message_id,
+            part_id,
+            &|_, _| {},
+        )
+        .await
+    }
+
+    async fn get_message_part_with_progress(
+        &self,
+        message_id: &MessageId,
+        part_id: &MessagePartId,
+        progress: &(dyn Fn(u64, Option<u64>) + Send + Sync),
+    ) -> MailinerResult<MessagePart> {
+        let fetches = self.fetch_bodystructure(message_id).await?;
+        let part = fetches
+            .iter()
+            .find_map(|fetch| fetch.bodystructure())
+            .and_then(|bodystructure| Self::find_part(bodystructure, part_id))
+            .ok_or_else(|| {
+                ImapError::InvalidData(format!("No part {} in message", part_id.as_str()))
+            })?;
+        let total = Self::part_size(part);
+
+        // Fetch the actual content
+        let content = self
+            .fetch_message_part(message_id, part_id, total, progress)
+            .await?;
+
+        let decoded = mime::decode_part(part, &content);
+        Ok(MessagePart {
+            id: part_id.clone(),
+            envelope_id: message_id.clone(),
+            content_type: decoded.content_type,
+            filename: decoded.filename,
+            size: content.len() as u64,
+            is_attachment: decoded.is_attachment,
+            content: decoded.content,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        })
+    }
+
+    async fn idle(&self, folder_id: &FolderId, timeout: Duration) -> MailinerResult<Vec<FolderEvent>> {
+        let mut imap = self.imap.lock().await;
+        // Left behind by a dropped IDLE.
+        self.finish_idle(&mut imap).await?;
+        if let ImapSession::Authenticated(session) = &mut *imap {
+            let capabilities = session
+                .capabilities()
+                .await
+                .map_err(|e| ImapError::Imap(format!("Failed to get capabilities: {}", e)))?;
+            if !capabilities.has_str("IDLE") {
+                return Err(ImapError::Imap("Server does not support IDLE".to_string()).into());
+            }
+            self.select(session, folder_id).await?;
+        } else {
+            return Err(ImapError::NotAuthenticated.into());
+        }
+
+        // IDLE takes ownership of the session. Should it fail to start, the
+        // session is lost and the connector stays disconnected.
+        let ImapSession::Authenticated(session) =
+            std::mem::replace(&mut *imap, ImapSession::Disconnected)
+        else {
+            unreachable!("session state checked above");
+        };
+        let mut handle = session.idle();
+        handle
+            .init()
+            .await
+            .map_err(|e| ImapError::Imap(format!("Failed to start IDLE: {}", e)))?;
+        // Kept in the connector while waiting, so that the session survives
+        // this future being dropped: the next operation ends the IDLE.
+        *imap = ImapSession::Idle(handle);
+        let ImapSession::Idle(handle) = &mut *imap else {
+            unreachable!("session put in IDLE above");
+        };
+
+        let response = {
+            let (wait, stop) = handle.wait_with_timeout(timeout);
+            {
+                let mut interrupt = self.idle_interrupt.lock().unwrap();
+                // An operation waiting since before IDLE started ends it
+                // right away, by dropping the stop source.
+                if interrupt.waiting == 0 {
+                    interrupt.stop = Some(stop);
+                }
+            }
+            // The server stays silent until something changes.
+            self.stream_control.set_suspended(true);
+            let response = wait.await;
+            self.idle_interrupt.lock().unwrap().stop = None;
+            response
+        };
+        self.finish_idle(&mut imap).await?;
+        let response = response.map_err(|e| ImapError::Imap(format!("IDLE failed: {}", e)))?;
+        let ImapSession::Authenticated(session) = &mut *imap else {
+            unreachable!("IDLE finished above");
+        };
+
+        let mut events = Vec::new();
+        if let IdleResponse::NewData(data) = response {
+            events.extend(Self::folder_event(folder_id, data.parsed()));
+        }
+        // IDLE wakes up on the first response, the rest are queued as
+        // unsolicited responses, after the ones earlier commands read past.
+        let kept = std::mem::take(&mut *self.unsolicited.lock().unwrap());
+        let queued = std::iter::from_fn(|| session.unsolicited_responses.try_recv().ok());
+        for response in kept.into_iter().chain(queued) {
+            events.extend(match response {
+                UnsolicitedResponse::Exists(exists) => Some(FolderEvent::NewMessages {
+                    folder_id: folder_id.clone(),
+                    exists,
+                }),
+                UnsolicitedResponse::Expunge(sequence) => Some(FolderEvent::MessageExpunged {
+                    folder_id: folder_id.clone(),
+                    sequence,
+                }),
+                UnsolicitedResponse::Other(data) => Self::folder_event(folder_id, data.parsed()),
+                _ => None,
+            });
+        }
+
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use tokio::io::DuplexStream;
+
+    use super::*;
+
+    type Connector = ImapConnector<DuplexStream>;
+
+    #[test]
+    fn pages_count_from_newest() {
+        assert_eq!(sequence_range(100, &(0..50)).as_deref(), Some("51:100"));
+        assert_eq!(sequence_range(100, &(50..100)).as_deref(), Some("1:50"));
+        // The last page may be short.
+        assert_eq!(sequence_range(60, &(50..100)).as_deref(), Some("1:10"));
+        assert_eq!(sequence_range(50, &(50..100)), None);
+        assert_eq!(sequence_range(0, &(0..50)), None);
+    }
+
+    #[test]
+    fn reads_modified_uids() {
+        assert_eq!(
+            modified_uids("[MODIFIED 7,9:11,20:18] Conditional STORE failed"),
+            vec![7, 9, 10, 11, 18, 19, 20]
+        );
+        assert_eq!(modified_uids("[modified 3] Some not stored"), vec![3]);
+        assert_eq!(modified_uids("[HIGHESTMODSEQ 12] Done"), Vec::<u32>::new());
+        assert_eq!(modified_uids("STORE completed"), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn maps_flags() {
+        let flags = || {
+            vec![
+                Flag::Seen,
+                Flag::Draft,
+                Flag::Custom(Cow::Borrowed("\\Starred")),
+                Flag::Custom(Cow::Borrowed("$Forwarded")),
+                Flag::Custom(Cow::Borrowed("Work")),
+            ]
+            .into_iter()
+        };
+        assert_eq!(Connector::parse_flags(flags()), (true, true, false, true, false));
+        let keywords = Connector::parse_keywords(flags());
+        assert_eq!(
+            keywords,
+            BTreeSet::from(["$Forwarded".to_string(), "Work".to_string()])
+        );
+        assert_eq!(Connector::keyword_labels(&keywords), BTreeSet::from(["Work".to_string()]));
+    }
 }
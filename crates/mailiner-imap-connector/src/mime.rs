@@ -0,0 +1,137 @@
+//! Decoding of fetched message parts, based on their BODYSTRUCTURE.
+
+use std::borrow::Cow;
+
+use imap_proto::types::{BodyContentCommon, BodyStructure, ContentEncoding};
+use mail_parser::{MessageParser, PartType};
+use mailiner_core::MessageContent;
+
+use crate::envelope::decode_text;
+
+/// A message part with its transfer encoding and charset decoded.
+pub(crate) struct DecodedPart {
+    pub(crate) content_type: String,
+    pub(crate) filename: Option<String>,
+    pub(crate) is_attachment: bool,
+    pub(crate) content: MessageContent,
+}
+
+/// The content type, file name and disposition of a part.
+pub(crate) fn describe(part: &BodyStructure<'_>) -> (String, Option<String>, bool) {
+    let common = common(part);
+    let content_type = format!("{}/{}", common.ty.ty, common.ty.subtype).to_ascii_lowercase();
+    let filename = common
+        .disposition
+        .as_ref()
+        .and_then(|d| param(&d.params, "filename"))
+        .or_else(|| param(&common.ty.params, "name"))
+        .map(|name| decode_text(name.as_bytes()));
+    let is_attachment = common
+        .disposition
+        .as_ref()
+        .is_some_and(|d| d.ty.eq_ignore_ascii_case("attachment"));
+    (content_type, filename, is_attachment)
+}
+
+/// Decodes the raw content of `part`. Text parts are converted to UTF-8, all
+/// other parts are returned as binary.
+pub(crate) fn decode_part(part: &BodyStructure<'_>, raw: &[u8]) -> DecodedPart {
+    let (content_type, filename, is_attachment) = describe(part);
+    let content = match part {
+        BodyStructure::Basic { common, other, .. } | BodyStructure::Text { common, other, .. } => {
+            decode_content(&content_type, &common.ty.params, &other.transfer_encoding, raw)
+        }
+        // Attached messages and multiparts are passed on as they are.
+        BodyStructure::Message { .. } | BodyStructure::Multipart { .. } => {
+            MessageContent::Binary(raw.to_vec())
+        }
+    };
+
+    DecodedPart {
+        content_type,
+        filename,
+        is_attachment,
+        content,
+    }
+}
+
+fn common<'a, 'b>(part: &'b BodyStructure<'a>) -> &'b BodyContentCommon<'a> {
+    match part {
+        BodyStructure::Basic { common, .. }
+        | BodyStructure::Text { common, .. }
+        | BodyStructure::Message { common, .. }
+        | BodyStructure::Multipart { common, .. } => common,
+    }
+}
+
+fn param<'a>(params: &Option<Vec<(Cow<'a, str>, Cow<'a, str>)>>, name: &str) -> Option<String> {
+    params
+        .iter()
+        .flatten()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.to_string())
+}
+
+/// Lets mail-parser decode the content by giving it the part's headers.
+fn decode_content(
+    content_type: &str,
+    params: &Option<Vec<(Cow<'_, str>, Cow<'_, str>)>>,
+    encoding: &ContentEncoding<'_>,
+    raw: &[u8],
+) -> MessageContent {
+    let encoding = match encoding {
+        ContentEncoding::Base64 => "base64",
+        ContentEncoding::QuotedPrintable => "quoted-printable",
+        _ => "8bit",
+    };
+    let mut message = format!("Content-Type: {}", content_type);
+    if let Some(charset) = param(params, "charset") {
+        message.push_str(&format!("; charset=\"{}\"", charset.replace(['"', '\\'], "")));
+    }
+    message.push_str(&format!("\r\nContent-Transfer-Encoding: {}\r\n\r\n", encoding));
+    let mut message = message.into_bytes();
+    message.extend_from_slice(raw);
+
+    let body = MessageParser::new()
+        .parse(&message)
+        .and_then(|message| message.parts.into_iter().next())
+        .map(|part| part.body);
+    match body {
+        Some(PartType::Text(text)) if content_type.starts_with("text/") => {
+            MessageContent::Text(text.into_owned())
+        }
+        Some(PartType::Html(html)) if content_type.starts_with("text/") => {
+            MessageContent::Html(html.into_owned())
+        }
+        Some(PartType::Binary(data)) | Some(PartType::InlineBinary(data)) => {
+            MessageContent::Binary(data.into_owned())
+        }
+        Some(PartType::Text(text)) | Some(PartType::Html(text)) => {
+            MessageContent::Binary(text.into_owned().into_bytes())
+        }
+        _ => MessageContent::Binary(raw.to_vec()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_transfer_encoding_and_charset() {
+        let latin1 = vec![(Cow::Borrowed("charset"), Cow::Borrowed("iso-8859-1"))];
+        match decode_content(
+            "text/plain",
+            &Some(latin1),
+            &ContentEncoding::QuotedPrintable,
+            b"Gr=FC=DFe",
+        ) {
+            MessageContent::Text(text) => assert_eq!(text, "Grüße"),
+            other => panic!("unexpected content: {:?}", other),
+        }
+        match decode_content("image/png", &None, &ContentEncoding::Base64, b"iVBORw0K\r\nGgo=") {
+            MessageContent::Binary(data) => assert_eq!(data, b"\x89PNG\r\n\x1a\n"),
+            other => panic!("unexpected content: {:?}", other),
+        }
+    }
+}
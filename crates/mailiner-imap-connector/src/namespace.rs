@@ -0,0 +1,346 @@
+//! NAMESPACE (RFC 2342), which tells the folder prefixes of the server and
+//! their hierarchy delimiters, e.g. `INBOX.` with `.` on Cyrus.
+//!
+//! imap-proto doesn't parse the NAMESPACE response, and async-imap fails
+//! the session on a response it can't parse. While the command runs, the
+//! response is taken out of the stream before async-imap reads it.
+
+use std::fmt::{self, Debug};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+const RESPONSE: &[u8] = b"* NAMESPACE ";
+
+/// A folder prefix and its hierarchy delimiter, `None` if it's flat.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Namespace {
+    pub(crate) prefix: String,
+    pub(crate) delimiter: Option<String>,
+    /// Whether it's one of the user's own folders, rather than other
+    /// users' or shared ones.
+    pub(crate) personal: bool,
+}
+
+impl Namespace {
+    /// The namespace of servers without NAMESPACE: all folders, with the
+    /// delimiter returned for `LIST "" ""`.
+    pub(crate) fn root(delimiter: Option<String>) -> Self {
+        Self {
+            prefix: String::new(),
+            delimiter,
+            personal: true,
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        name.starts_with(&self.prefix)
+            // The prefix's own folder, e.g. `#shared` for `#shared/`.
+            || self
+                .delimiter
+                .as_deref()
+                .and_then(|delimiter| self.prefix.strip_suffix(delimiter))
+                .is_some_and(|root| root == name)
+    }
+}
+
+/// The namespace a folder is in, the one with the longest matching prefix.
+pub(crate) fn find<'a>(namespaces: &'a [Namespace], name: &str) -> Option<&'a Namespace> {
+    namespaces
+        .iter()
+        .filter(|namespace| namespace.contains(name))
+        .max_by_key(|namespace| namespace.prefix.len())
+}
+
+/// Collects the NAMESPACE responses while armed, shared between the
+/// connector and its stream.
+#[derive(Default)]
+pub(crate) struct NamespaceCapture {
+    armed: AtomicBool,
+    lines: Mutex<Vec<Vec<u8>>>,
+}
+
+impl NamespaceCapture {
+    /// Starts taking NAMESPACE responses out of the stream, right before
+    /// the command is sent.
+    pub(crate) fn arm(&self) {
+        self.lines.lock().unwrap().clear();
+        self.armed.store(true, Ordering::Relaxed);
+    }
+
+    /// Stops taking responses out, and returns the namespaces of the ones
+    /// taken.
+    pub(crate) fn disarm(&self) -> Vec<Namespace> {
+        self.armed.store(false, Ordering::Relaxed);
+        std::mem::take(&mut *self.lines.lock().unwrap())
+            .iter()
+            .flat_map(|line| parse(line))
+            .collect()
+    }
+}
+
+/// A stream that takes NAMESPACE responses out of what is read while its
+/// capture is armed. Responses are only looked for then, so that a line of
+/// a message body can't pass for one.
+pub(crate) struct NamespaceStream<S> {
+    inner: S,
+    capture: Arc<NamespaceCapture>,
+    /// The start of a line not read completely yet.
+    partial: Vec<u8>,
+    /// What was read and is to be passed on.
+    output: Vec<u8>,
+}
+
+impl<S> NamespaceStream<S> {
+    pub(crate) fn new(inner: S, capture: Arc<NamespaceCapture>) -> Self {
+        Self {
+            inner,
+            capture,
+            partial: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl<S: Debug> Debug for NamespaceStream<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NamespaceStream")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> AsyncRead for NamespaceStream<S>
+where
+    S: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if !this.output.is_empty() {
+                let len = this.output.len().min(buf.remaining());
+                buf.put_slice(&this.output[..len]);
+                this.output.drain(..len);
+                return Poll::Ready(Ok(()));
+            }
+            if !this.capture.armed.load(Ordering::Relaxed) {
+                if this.partial.is_empty() {
+                    return Pin::new(&mut this.inner).poll_read(cx, buf);
+                }
+                this.output.append(&mut this.partial);
+                continue;
+            }
+
+            let mut chunk = [0; 1024];
+            let mut read = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read))?;
+            if read.filled().is_empty() {
+                // Closed, which the reader finds out after the rest.
+                if this.partial.is_empty() {
+                    return Poll::Ready(Ok(()));
+                }
+                this.output.append(&mut this.partial);
+                continue;
+            }
+            this.partial.extend_from_slice(read.filled());
+            while let Some(end) = this.partial.iter().position(|&byte| byte == b'\n') {
+                let line: Vec<u8> = this.partial.drain(..=end).collect();
+                if line.len() >= RESPONSE.len() && line[..RESPONSE.len()].eq_ignore_ascii_case(RESPONSE) {
+                    this.capture.lines.lock().unwrap().push(line);
+                } else {
+                    this.output.extend_from_slice(&line);
+                }
+            }
+        }
+    }
+}
+
+impl<S> AsyncWrite for NamespaceStream<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+enum Token {
+    Open,
+    Close,
+    /// A string, or `None` for NIL.
+    String(Option<String>),
+}
+
+fn tokens(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '"' => {
+                let mut string = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => string.extend(chars.next()),
+                        c => string.push(c),
+                    }
+                }
+                tokens.push(Token::String(Some(string)));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut atom = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    atom.push(c);
+                    chars.next();
+                }
+                let nil = atom.eq_ignore_ascii_case("NIL");
+                tokens.push(Token::String((!nil).then_some(atom)));
+            }
+        }
+    }
+    tokens
+}
+
+/// Parses a NAMESPACE response, e.g.
+/// `* NAMESPACE (("" "/")) NIL (("#shared/" "/"))`: the personal, other
+/// users' and shared namespaces, each NIL or a list of prefixes and
+/// delimiters, optionally followed by extensions.
+pub(crate) fn parse(line: &[u8]) -> Vec<Namespace> {
+    let line = String::from_utf8_lossy(line);
+    let Some(rest) = line.get(RESPONSE.len()..) else {
+        return Vec::new();
+    };
+    let mut namespaces = Vec::new();
+    // Personal, other users' and shared, in that order.
+    let mut group = 0;
+    let mut depth = 0;
+    let mut fields: Vec<Option<String>> = Vec::new();
+    for token in tokens(rest) {
+        match token {
+            Token::Open => {
+                depth += 1;
+                if depth == 2 {
+                    fields.clear();
+                }
+            }
+            Token::Close => {
+                if depth == 2 {
+                    if let [Some(prefix), delimiter, ..] = fields.as_slice() {
+                        namespaces.push(Namespace {
+                            prefix: prefix.clone(),
+                            delimiter: delimiter.clone(),
+                            personal: group == 0,
+                        });
+                    }
+                }
+                depth -= 1;
+                if depth == 0 {
+                    group += 1;
+                }
+            }
+            Token::String(_) if depth == 0 => group += 1,
+            // Extension values are at depth 2 after the delimiter, or
+            // deeper.
+            Token::String(string) if depth == 2 => fields.push(string),
+            Token::String(_) => {}
+        }
+    }
+    namespaces
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    fn namespace(prefix: &str, delimiter: &str, personal: bool) -> Namespace {
+        Namespace {
+            prefix: prefix.to_string(),
+            delimiter: Some(delimiter.to_string()),
+            personal,
+        }
+    }
+
+    #[test]
+    fn parses_namespaces() {
+        assert_eq!(
+            parse(br##"* NAMESPACE (("INBOX." ".")) NIL (("#shared/" "/" "X-PARAM" ("a" "b"))("Public." "."))"##),
+            vec![
+                namespace("INBOX.", ".", true),
+                namespace("#shared/", "/", false),
+                namespace("Public.", ".", false),
+            ]
+        );
+        assert_eq!(
+            parse(b"* NAMESPACE ((\"\" NIL)) ((\"Other Users\\\\\" \"\\\\\")) NIL\r\n"),
+            vec![
+                Namespace::root(None),
+                namespace("Other Users\\", "\\", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_namespace_of_folder() {
+        let namespaces = [namespace("INBOX.", ".", true), namespace("#shared/", "/", false)];
+        assert_eq!(find(&namespaces, "INBOX.Sent"), Some(&namespaces[0]));
+        assert_eq!(find(&namespaces, "#shared/team"), Some(&namespaces[1]));
+        assert_eq!(find(&namespaces, "#shared"), Some(&namespaces[1]));
+        assert_eq!(find(&namespaces, "INBOX"), Some(&namespaces[0]));
+        assert_eq!(find(&namespaces, "Archive"), None);
+
+        let namespaces = [Namespace::root(Some("/".to_string())), namespace("#shared/", "/", false)];
+        assert_eq!(find(&namespaces, "Sent"), Some(&namespaces[0]));
+        assert_eq!(find(&namespaces, "#shared/team"), Some(&namespaces[1]));
+    }
+
+    #[tokio::test]
+    async fn takes_response_out_while_armed() {
+        let (client, mut server) = tokio::io::duplex(1024);
+        let capture = Arc::new(NamespaceCapture::default());
+        let mut stream = NamespaceStream::new(client, capture.clone());
+
+        capture.arm();
+        server
+            .write_all(b"* 3 EXISTS\r\n* NAMESPACE ((\"\" \".\")) NIL NIL\r\nA1 OK done\r\n")
+            .await
+            .unwrap();
+        let mut read = vec![0; 24];
+        stream.read_exact(&mut read).await.unwrap();
+        assert_eq!(read, b"* 3 EXISTS\r\nA1 OK done\r\n");
+        assert_eq!(capture.disarm(), vec![namespace("", ".", true)]);
+
+        // A line of a message isn't a response.
+        server.write_all(b"* NAMESPACE\r\n").await.unwrap();
+        let mut read = vec![0; 13];
+        stream.read_exact(&mut read).await.unwrap();
+        assert_eq!(read, b"* NAMESPACE\r\n");
+    }
+}
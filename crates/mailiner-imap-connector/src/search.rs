@@ -0,0 +1,96 @@
+//! Translation of [`SearchQuery`] into IMAP SEARCH criteria (RFC 3501, section 6.4.4).
+
+use mailiner_core::SearchQuery;
+
+use crate::quote;
+
+/// Builds the search criteria for `UID SEARCH`.
+///
+/// Non-ASCII strings are sent as UTF-8 quoted strings together with
+/// `CHARSET UTF-8`, which servers generally accept even though the RFC
+/// asks for literals.
+pub(crate) fn search_criteria(query: &SearchQuery) -> String {
+    let mut criteria = Vec::new();
+
+    for (key, value) in [
+        ("FROM", &query.from),
+        ("TO", &query.to),
+        ("SUBJECT", &query.subject),
+        ("BODY", &query.body),
+    ] {
+        if let Some(value) = value {
+            criteria.push(format!("{} {}", key, quote(value)));
+        }
+    }
+    if let Some(since) = query.since {
+        criteria.push(format!("SINCE {}", since.format("%-d-%b-%Y")));
+    }
+    if let Some(before) = query.before {
+        criteria.push(format!("BEFORE {}", before.format("%-d-%b-%Y")));
+    }
+    for (flag, set, unset) in [
+        (query.is_read, "SEEN", "UNSEEN"),
+        (query.is_flagged, "FLAGGED", "UNFLAGGED"),
+        (query.is_draft, "DRAFT", "UNDRAFT"),
+        (query.is_deleted, "DELETED", "UNDELETED"),
+    ] {
+        match flag {
+            Some(true) => criteria.push(set.to_string()),
+            Some(false) => criteria.push(unset.to_string()),
+            None => {}
+        }
+    }
+    // IMAP can't search for attachments, so this finds the messages with
+    // parts to download, as most mail clients send attachments.
+    match query.has_attachment {
+        Some(true) => criteria.push("HEADER Content-Type \"multipart/mixed\"".to_string()),
+        Some(false) => criteria.push("NOT HEADER Content-Type \"multipart/mixed\"".to_string()),
+        None => {}
+    }
+
+    if criteria.is_empty() {
+        return "ALL".to_string();
+    }
+    let criteria = criteria.join(" ");
+    if criteria.is_ascii() {
+        criteria
+    } else {
+        format!("CHARSET UTF-8 {}", criteria)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn query_to_criteria() {
+        assert_eq!(search_criteria(&SearchQuery::default()), "ALL");
+
+        let query = SearchQuery {
+            from: Some("alice@example.com".to_string()),
+            subject: Some("say \"hi\"".to_string()),
+            since: NaiveDate::from_ymd_opt(2024, 3, 5),
+            is_read: Some(false),
+            is_deleted: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            search_criteria(&query),
+            "FROM \"alice@example.com\" SUBJECT \"say \\\"hi\\\"\" SINCE 5-Mar-2024 UNSEEN UNDELETED"
+        );
+
+        let query = SearchQuery {
+            body: Some("příloha".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(search_criteria(&query), "CHARSET UTF-8 BODY \"příloha\"");
+
+        let query = SearchQuery {
+            has_attachment: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(search_criteria(&query), "HEADER Content-Type \"multipart/mixed\"");
+    }
+}
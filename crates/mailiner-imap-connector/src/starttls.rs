@@ -0,0 +1,89 @@
+//! STARTTLS negotiation (RFC 3501, section 6.2.1) on a plaintext connection.
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::ImapError;
+
+const TAG: &str = "S1";
+
+/// Reads the greeting and issues STARTTLS, after which the TLS handshake can
+/// start on the same stream.
+pub(crate) async fn negotiate<S>(stream: &mut S) -> Result<(), ImapError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let greeting = read_line(stream).await?;
+    // A PREAUTH greeting would leave no chance to upgrade the connection.
+    if !greeting.starts_with("* OK") {
+        return Err(ImapError::Connection(format!(
+            "Unexpected greeting: {}",
+            greeting.trim_end()
+        )));
+    }
+
+    stream
+        .write_all(format!("{} STARTTLS\r\n", TAG).as_bytes())
+        .await
+        .map_err(|e| ImapError::Connection(format!("Failed to send STARTTLS: {}", e)))?;
+    stream
+        .flush()
+        .await
+        .map_err(|e| ImapError::Connection(format!("Failed to send STARTTLS: {}", e)))?;
+
+    loop {
+        let line = read_line(stream).await?;
+        // Untagged responses, e.g. CAPABILITY, are skipped.
+        if let Some(status) = line.strip_prefix(TAG).and_then(|s| s.strip_prefix(' ')) {
+            return if status.starts_with("OK") {
+                Ok(())
+            } else {
+                Err(ImapError::Connection(format!(
+                    "STARTTLS failed: {}",
+                    status.trim_end()
+                )))
+            };
+        }
+    }
+}
+
+/// Reads a single line, byte by byte so that nothing past the line is
+/// consumed from the stream.
+async fn read_line<S>(stream: &mut S) -> Result<String, ImapError>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut line = Vec::new();
+    while !line.ends_with(b"\r\n") {
+        let byte = stream
+            .read_u8()
+            .await
+            .map_err(|e| ImapError::Connection(format!("Failed to read from server: {}", e)))?;
+        line.push(byte);
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn starttls() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let handshake = tokio::spawn(async move { negotiate(&mut client).await });
+
+        server
+            .write_all(b"* OK [CAPABILITY IMAP4rev1 STARTTLS LOGINDISABLED] ready\r\n")
+            .await
+            .unwrap();
+        let mut command = [0u8; 13];
+        server.read_exact(&mut command).await.unwrap();
+        assert_eq!(&command, b"S1 STARTTLS\r\n");
+        server
+            .write_all(b"S1 OK Begin TLS negotiation now\r\n")
+            .await
+            .unwrap();
+
+        assert!(handshake.await.unwrap().is_ok());
+    }
+}
@@ -0,0 +1,181 @@
+//! Command timeouts. A connection silently dropped by a NAT or a firewall
+//! would otherwise leave the next command waiting for a response forever.
+
+use std::fmt::{self, Debug};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use mailiner_core::platform::Timer;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Settings and state shared between the connector and its stream.
+#[derive(Default)]
+pub(crate) struct StreamControl {
+    timeout: Mutex<Option<(Arc<dyn Timer>, Duration)>>,
+    /// Whether the stream was used since the last `take_activity`.
+    active: AtomicBool,
+    /// Set while in IDLE, where the server may legitimately stay silent.
+    suspended: AtomicBool,
+}
+
+impl StreamControl {
+    pub(crate) fn set_timeout(&self, timer: Arc<dyn Timer>, timeout: Duration) {
+        *self.timeout.lock().unwrap() = Some((timer, timeout));
+    }
+
+    pub(crate) fn set_suspended(&self, suspended: bool) {
+        self.suspended.store(suspended, Ordering::Relaxed);
+    }
+
+    /// Returns whether the stream was used since the last call.
+    pub(crate) fn take_activity(&self) -> bool {
+        self.active.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// A stream whose reads and writes fail with [`io::ErrorKind::TimedOut`]
+/// when they make no progress within the timeout. Once timed out, the
+/// stream stays failed, as the state of the session is unknown.
+pub(crate) struct TimeoutStream<S> {
+    inner: S,
+    control: Arc<StreamControl>,
+    deadline: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    timed_out: bool,
+}
+
+impl<S> TimeoutStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    pub(crate) fn new(inner: S, control: Arc<StreamControl>) -> Self {
+        Self {
+            inner,
+            control,
+            deadline: None,
+            timed_out: false,
+        }
+    }
+
+    fn poll_timed<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll: impl FnOnce(Pin<&mut S>, &mut Context<'_>) -> Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        if self.timed_out {
+            return Poll::Ready(Err(timed_out()));
+        }
+        if let Poll::Ready(result) = poll(Pin::new(&mut self.inner), cx) {
+            self.deadline = None;
+            self.control.active.store(true, Ordering::Relaxed);
+            return Poll::Ready(result);
+        }
+        if self.control.suspended.load(Ordering::Relaxed) {
+            self.deadline = None;
+            return Poll::Pending;
+        }
+
+        if self.deadline.is_none() {
+            let Some((timer, timeout)) = self.control.timeout.lock().unwrap().clone() else {
+                return Poll::Pending;
+            };
+            self.deadline = Some(Box::pin(async move { timer.sleep(timeout).await }));
+        }
+        match self.deadline.as_mut().map(|deadline| deadline.as_mut().poll(cx)) {
+            Some(Poll::Ready(())) => {
+                self.deadline = None;
+                self.timed_out = true;
+                Poll::Ready(Err(timed_out()))
+            }
+            _ => Poll::Pending,
+        }
+    }
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "the server did not respond in time")
+}
+
+impl<S: Debug> Debug for TimeoutStream<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimeoutStream")
+            .field("inner", &self.inner)
+            .field("timed_out", &self.timed_out)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> AsyncRead for TimeoutStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.get_mut()
+            .poll_timed(cx, |stream, cx| stream.poll_read(cx, buf))
+    }
+}
+
+impl<S> AsyncWrite for TimeoutStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_timed(cx, |stream, cx| stream.poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut().poll_timed(cx, |stream, cx| stream.poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut()
+            .poll_timed(cx, |stream, cx| stream.poll_shutdown(cx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    struct ImmediateTimer;
+
+    #[async_trait]
+    impl Timer for ImmediateTimer {
+        async fn sleep(&self, _duration: Duration) {}
+    }
+
+    #[tokio::test]
+    async fn silent_server_times_out() {
+        let (client, mut server) = tokio::io::duplex(64);
+        let control = Arc::new(StreamControl::default());
+        control.set_timeout(Arc::new(ImmediateTimer), Duration::from_secs(30));
+        let mut stream = TimeoutStream::new(client, control.clone());
+
+        server.write_all(b"* OK\r\n").await.unwrap();
+        let mut buf = [0; 6];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert!(control.take_activity());
+
+        let error = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        // The stream stays failed even if the server responds eventually.
+        server.write_all(b"* OK\r\n").await.unwrap();
+        assert!(stream.read(&mut buf).await.is_err());
+    }
+}
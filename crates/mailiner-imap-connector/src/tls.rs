@@ -0,0 +1,290 @@
+//! TLS backends. `rustls` (the default, with the Mozilla root certificates)
+//! and `native-tls` (the operating system's TLS library and trust store)
+//! are selected with the cargo features of the same names.
+//!
+//! With rustls, certificates that fail validation are reported as
+//! [`ImapError::UntrustedCertificate`], and are accepted on the next attempt
+//! once their fingerprint is trusted.
+
+use std::fmt::Debug;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use mailiner_core::CertificateInfo;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::ImapError;
+
+/// How the connection to the server is encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsBackend {
+    /// The stream passed to `connect` is encrypted already, e.g. a WebSocket
+    /// to a proxy that connects to the server with TLS.
+    External,
+    #[cfg(feature = "rustls")]
+    Rustls,
+    #[cfg(feature = "native-tls")]
+    NativeTls,
+}
+
+#[cfg(feature = "rustls")]
+const DEFAULT_BACKEND: TlsBackend = TlsBackend::Rustls;
+#[cfg(all(not(feature = "rustls"), feature = "native-tls"))]
+const DEFAULT_BACKEND: TlsBackend = TlsBackend::NativeTls;
+#[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+const DEFAULT_BACKEND: TlsBackend = TlsBackend::External;
+
+impl Default for TlsBackend {
+    fn default() -> Self {
+        DEFAULT_BACKEND
+    }
+}
+
+/// The stream the IMAP session runs on.
+#[derive(Debug)]
+pub(crate) enum ImapStream<S> {
+    External(S),
+    #[cfg(feature = "rustls")]
+    Rustls(tokio_rustls::client::TlsStream<S>),
+    #[cfg(feature = "native-tls")]
+    NativeTls(tokio_native_tls::TlsStream<S>),
+}
+
+impl<S> ImapStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug,
+{
+    /// Runs the TLS handshake with `host` on `stream`. Certificates whose
+    /// fingerprint is in `trusted` are accepted even if they are invalid.
+    #[cfg_attr(not(feature = "rustls"), allow(unused_variables))]
+    pub(crate) async fn connect(
+        backend: TlsBackend,
+        host: &str,
+        trusted: &[String],
+        stream: S,
+    ) -> Result<Self, ImapError> {
+        match backend {
+            TlsBackend::External => Ok(ImapStream::External(stream)),
+            #[cfg(feature = "rustls")]
+            TlsBackend::Rustls => {
+                use std::sync::Arc;
+                use tokio_rustls::rustls::pki_types::ServerName;
+                use tokio_rustls::rustls::ClientConfig;
+                use tokio_rustls::TlsConnector;
+
+                let verifier = Arc::new(verifier::ExceptionVerifier::new(trusted)?);
+                let config = ClientConfig::builder()
+                    .dangerous()
+                    .with_custom_certificate_verifier(verifier.clone())
+                    .with_no_client_auth();
+                let tls = TlsConnector::from(Arc::new(config));
+                let server_name = ServerName::try_from(host.to_string())
+                    .map_err(|e| ImapError::Connection(format!("Invalid server name: {}", e)))?;
+                let stream = tls
+                    .connect(server_name, stream)
+                    .await
+                    .map_err(|e| match verifier.rejected() {
+                        Some(certificate) => ImapError::UntrustedCertificate(certificate),
+                        None => ImapError::Tls(format!("Failed to establish TLS: {}", e)),
+                    })?;
+                Ok(ImapStream::Rustls(stream))
+            }
+            // native-tls doesn't let us inspect rejected certificates, so
+            // exceptions are not supported.
+            #[cfg(feature = "native-tls")]
+            TlsBackend::NativeTls => {
+                let connector = native_tls::TlsConnector::new()
+                    .map_err(|e| ImapError::Tls(format!("Failed to set up TLS: {}", e)))?;
+                let stream = tokio_native_tls::TlsConnector::from(connector)
+                    .connect(host, stream)
+                    .await
+                    .map_err(|e| ImapError::Tls(format!("Failed to establish TLS: {}", e)))?;
+                Ok(ImapStream::NativeTls(stream))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "rustls")]
+mod verifier {
+    use std::sync::{Arc, Mutex};
+
+    use tokio_rustls::rustls::client::danger::{
+        HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+    };
+    use tokio_rustls::rustls::client::WebPkiServerVerifier;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+    use tokio_rustls::rustls::{DigitallySignedStruct, Error, RootCertStore, SignatureScheme};
+
+    use super::CertificateInfo;
+    use crate::ImapError;
+
+    /// Validates certificates against the Mozilla root certificates, but
+    /// accepts trusted ones regardless and remembers the rejected one.
+    #[derive(Debug)]
+    pub(super) struct ExceptionVerifier {
+        inner: Arc<WebPkiServerVerifier>,
+        trusted: Vec<String>,
+        rejected: Mutex<Option<CertificateInfo>>,
+    }
+
+    impl ExceptionVerifier {
+        pub(super) fn new(trusted: &[String]) -> Result<Self, ImapError> {
+            let roots = RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            };
+            let inner = WebPkiServerVerifier::builder(Arc::new(roots))
+                .build()
+                .map_err(|e| ImapError::Tls(format!("Failed to set up TLS: {}", e)))?;
+            Ok(Self {
+                inner,
+                trusted: trusted.to_vec(),
+                rejected: Mutex::new(None),
+            })
+        }
+
+        pub(super) fn rejected(&self) -> Option<CertificateInfo> {
+            self.rejected.lock().unwrap().take()
+        }
+    }
+
+    /// The SHA-256 fingerprint of a DER encoded certificate.
+    pub(super) fn fingerprint(der: &[u8]) -> String {
+        ring::digest::digest(&ring::digest::SHA256, der)
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+
+    impl ServerCertVerifier for ExceptionVerifier {
+        fn verify_server_cert(
+            &self,
+            end_entity: &CertificateDer<'_>,
+            intermediates: &[CertificateDer<'_>],
+            server_name: &ServerName<'_>,
+            ocsp_response: &[u8],
+            now: UnixTime,
+        ) -> Result<ServerCertVerified, Error> {
+            let error = match self.inner.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            ) {
+                Ok(verified) => return Ok(verified),
+                Err(error) => error,
+            };
+
+            let fingerprint = fingerprint(end_entity);
+            if self.trusted.contains(&fingerprint) {
+                return Ok(ServerCertVerified::assertion());
+            }
+            *self.rejected.lock().unwrap() = Some(CertificateInfo {
+                fingerprint,
+                chain: std::iter::once(end_entity)
+                    .chain(intermediates)
+                    .map(|certificate| certificate.to_vec())
+                    .collect(),
+                reason: error.to_string(),
+            });
+            Err(error)
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, Error> {
+            self.inner.verify_tls12_signature(message, cert, dss)
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, Error> {
+            self.inner.verify_tls13_signature(message, cert, dss)
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.inner.supported_verify_schemes()
+        }
+    }
+}
+
+impl<S> AsyncRead for ImapStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ImapStream::External(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "rustls")]
+            ImapStream::Rustls(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "native-tls")]
+            ImapStream::NativeTls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<S> AsyncWrite for ImapStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ImapStream::External(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "rustls")]
+            ImapStream::Rustls(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "native-tls")]
+            ImapStream::NativeTls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ImapStream::External(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "rustls")]
+            ImapStream::Rustls(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "native-tls")]
+            ImapStream::NativeTls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ImapStream::External(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "rustls")]
+            ImapStream::Rustls(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "native-tls")]
+            ImapStream::NativeTls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "rustls"))]
+mod tests {
+    use super::verifier::fingerprint;
+
+    #[test]
+    fn fingerprint_format() {
+        assert_eq!(
+            fingerprint(b"abc"),
+            "BA:78:16:BF:8F:01:CF:EA:41:41:40:DE:5D:AE:22:23:B0:03:61:A3:96:17:7A:9C:B4:10:FF:61:F2:00:15:AD"
+        );
+    }
+}
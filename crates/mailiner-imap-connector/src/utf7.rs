@@ -0,0 +1,120 @@
+//! Modified UTF-7 encoding of mailbox names (RFC 3501, section 5.1.3).
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+,";
+
+/// Decodes a mailbox name. Malformed names are returned as they are, since
+/// they are still valid names on the server.
+pub(crate) fn decode(name: &str) -> String {
+    try_decode(name).unwrap_or_else(|| name.to_string())
+}
+
+fn try_decode(name: &str) -> Option<String> {
+    let mut decoded = String::new();
+    let mut rest = name;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        let end = start + rest[start..].find('-')?;
+        let encoded = &rest[start + 1..end];
+        if encoded.is_empty() {
+            decoded.push('&');
+        } else {
+            let bytes = decode_base64(encoded)?;
+            if bytes.len() % 2 != 0 {
+                return None;
+            }
+            let units: Vec<u16> = bytes
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            decoded.push_str(&String::from_utf16(&units).ok()?);
+        }
+        rest = &rest[end + 1..];
+    }
+    decoded.push_str(rest);
+    Some(decoded)
+}
+
+/// Encodes a mailbox name.
+pub(crate) fn encode(name: &str) -> String {
+    let mut encoded = String::new();
+    let mut pending: Vec<u16> = Vec::new();
+    for c in name.chars() {
+        if (' '..='~').contains(&c) {
+            flush(&mut encoded, &mut pending);
+            if c == '&' {
+                encoded.push_str("&-");
+            } else {
+                encoded.push(c);
+            }
+        } else {
+            let mut units = [0; 2];
+            pending.extend_from_slice(c.encode_utf16(&mut units));
+        }
+    }
+    flush(&mut encoded, &mut pending);
+    encoded
+}
+
+fn flush(encoded: &mut String, pending: &mut Vec<u16>) {
+    if pending.is_empty() {
+        return;
+    }
+    let bytes: Vec<u8> = pending.iter().flat_map(|unit| unit.to_be_bytes()).collect();
+    encoded.push('&');
+    encoded.push_str(&encode_base64(&bytes));
+    encoded.push('-');
+    pending.clear();
+}
+
+/// Base64 with `,` instead of `/` and without padding.
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let mut buffer = [0u8; 3];
+        buffer[..chunk.len()].copy_from_slice(chunk);
+        let bits = u32::from_be_bytes([0, buffer[0], buffer[1], buffer[2]]);
+        for i in 0..=chunk.len() {
+            let index = (bits >> (18 - 6 * i)) & 0x3f;
+            encoded.push(ALPHABET[index as usize] as char);
+        }
+    }
+    encoded
+}
+
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in encoded.bytes() {
+        let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+        bits = (bits << 6) | value;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for (decoded, encoded) in [
+            ("Entwürfe", "Entw&APw-rfe"),
+            ("~peter/mail/台北/日本語", "~peter/mail/&U,BTFw-/&ZeVnLIqe-"),
+            ("Tom & Jerry", "Tom &- Jerry"),
+            ("📧", "&2D3c5w-"),
+            ("INBOX", "INBOX"),
+        ] {
+            assert_eq!(decode(encoded), decoded);
+            assert_eq!(encode(decoded), encoded);
+        }
+        // Not valid modified UTF-7, but a valid name.
+        assert_eq!(decode("Foo&Bar"), "Foo&Bar");
+    }
+}
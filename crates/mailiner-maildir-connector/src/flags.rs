@@ -0,0 +1,211 @@
+//! The flags of a message, which Maildir keeps in its file name after the
+//! unique part, e.g. `1700000000.R1a2b.host:2,FS` (see
+//! <https://cr.yp.to/proto/maildir.html>). Lower-case letters stand for
+//! keywords, numbered in the folder's `dovecot-keywords` file.
+
+use std::collections::BTreeSet;
+
+use mailiner_core::{FlagUpdate, MessageId};
+
+pub(crate) const DRAFT: char = 'D';
+pub(crate) const FLAGGED: char = 'F';
+/// Forwarded, or "passed" in the Maildir spec.
+pub(crate) const PASSED: char = 'P';
+pub(crate) const REPLIED: char = 'R';
+pub(crate) const SEEN: char = 'S';
+pub(crate) const TRASHED: char = 'T';
+
+/// The keyword the `P` flag stands for.
+pub(crate) const FORWARDED: &str = "$Forwarded";
+
+/// The file name of a message in `cur` or `new`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FileName {
+    /// The part that identifies the message, the same in `new` and `cur`.
+    pub(crate) unique: String,
+    pub(crate) flags: BTreeSet<char>,
+}
+
+impl FileName {
+    /// Splits a file name at the info separator. Names without flags, as in
+    /// `new`, have no flags.
+    pub(crate) fn parse(name: &str, separator: char) -> Self {
+        match name.rsplit_once(separator) {
+            Some((unique, info)) if info.starts_with("2,") => Self {
+                unique: unique.to_string(),
+                flags: info[2..]
+                    .chars()
+                    .filter(char::is_ascii_alphabetic)
+                    .collect(),
+            },
+            _ => Self {
+                unique: name.to_string(),
+                flags: BTreeSet::new(),
+            },
+        }
+    }
+
+    /// The name in `cur`, with the flags in ASCII order as the spec asks.
+    pub(crate) fn to_name(&self, separator: char) -> String {
+        let flags: String = self.flags.iter().collect();
+        format!("{}{}2,{}", self.unique, separator, flags)
+    }
+
+    /// When the message was delivered, taken from the seconds that unique
+    /// names start with.
+    pub(crate) fn timestamp(&self) -> Option<i64> {
+        let digits = self
+            .unique
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.unique.len());
+        self.unique[..digits].parse().ok()
+    }
+
+    pub(crate) fn flag_update(&self, message_id: MessageId, keywords: &Keywords) -> FlagUpdate {
+        let mut names: BTreeSet<String> = self
+            .flags
+            .iter()
+            .filter_map(|flag| keywords.name(*flag))
+            .map(str::to_string)
+            .collect();
+        if self.flags.contains(&PASSED) {
+            names.insert(FORWARDED.to_string());
+        }
+        FlagUpdate {
+            message_id,
+            is_read: self.flags.contains(&SEEN),
+            is_starred: self.flags.contains(&FLAGGED),
+            is_flagged: self.flags.contains(&FLAGGED),
+            is_draft: self.flags.contains(&DRAFT),
+            is_deleted: self.flags.contains(&TRASHED),
+            keywords: names,
+        }
+    }
+}
+
+/// The flag letter of one of the flags of
+/// [`EmailConnector::update_envelope_flags`](mailiner_core::EmailConnector::update_envelope_flags).
+pub(crate) fn flag_letter(flag: &str) -> Option<char> {
+    match flag {
+        "is_read" => Some(SEEN),
+        "is_flagged" | "is_starred" => Some(FLAGGED),
+        "is_draft" => Some(DRAFT),
+        "is_deleted" => Some(TRASHED),
+        _ => None,
+    }
+}
+
+/// The flag letter of an IMAP system flag, e.g. `\Seen`.
+pub(crate) fn system_flag_letter(flag: &str) -> Option<char> {
+    match flag.to_ascii_lowercase().as_str() {
+        "\\seen" => Some(SEEN),
+        "\\flagged" => Some(FLAGGED),
+        "\\draft" => Some(DRAFT),
+        "\\deleted" => Some(TRASHED),
+        "\\answered" => Some(REPLIED),
+        _ => None,
+    }
+}
+
+/// The keywords of a folder, in the format of Dovecot's `dovecot-keywords`
+/// file: one `<number> <keyword>` line per keyword, where 0 is `a`, 1 is `b`
+/// and so on up to `z`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Keywords {
+    names: Vec<Option<String>>,
+}
+
+impl Keywords {
+    pub(crate) fn parse(content: &str) -> Self {
+        let mut keywords = Self::default();
+        for line in content.lines() {
+            let Some((number, name)) = line.split_once(' ') else {
+                continue;
+            };
+            let Ok(number) = number.parse::<usize>() else {
+                continue;
+            };
+            if number < 26 && !name.is_empty() {
+                if keywords.names.len() <= number {
+                    keywords.names.resize(number + 1, None);
+                }
+                keywords.names[number] = Some(name.to_string());
+            }
+        }
+        keywords
+    }
+
+    pub(crate) fn name(&self, letter: char) -> Option<&str> {
+        let index = (letter as usize).checked_sub('a' as usize)?;
+        self.names.get(index)?.as_deref()
+    }
+
+    pub(crate) fn letter(&self, name: &str) -> Option<char> {
+        self.names
+            .iter()
+            .position(|keyword| keyword.as_deref() == Some(name))
+            .map(|index| (b'a' + index as u8) as char)
+    }
+
+    /// Returns the letter of a keyword, assigning the next free one if it
+    /// has none. `None` once all 26 letters are taken.
+    pub(crate) fn assign(&mut self, name: &str) -> Option<char> {
+        if let Some(letter) = self.letter(name) {
+            return Some(letter);
+        }
+        let index = match self.names.iter().position(Option::is_none) {
+            Some(index) => index,
+            None if self.names.len() < 26 => {
+                self.names.push(None);
+                self.names.len() - 1
+            }
+            None => return None,
+        };
+        self.names[index] = Some(name.to_string());
+        Some((b'a' + index as u8) as char)
+    }
+
+    pub(crate) fn to_file(&self) -> String {
+        self.names
+            .iter()
+            .enumerate()
+            .filter_map(|(index, name)| Some(format!("{} {}\n", index, name.as_ref()?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        let name = FileName::parse("1700000000.R1a2b.host,S=1234:2,SFa", ':');
+        assert_eq!(name.unique, "1700000000.R1a2b.host,S=1234");
+        assert_eq!(name.flags, BTreeSet::from(['F', 'S', 'a']));
+        assert_eq!(name.timestamp(), Some(1_700_000_000));
+        assert_eq!(name.to_name(':'), "1700000000.R1a2b.host,S=1234:2,FSa");
+
+        let new = FileName::parse("1700000000.R1a2b.host", ':');
+        assert_eq!(new.unique, "1700000000.R1a2b.host");
+        assert!(new.flags.is_empty());
+        assert_eq!(FileName::parse("1.x;2,T", ';').flags, BTreeSet::from(['T']));
+    }
+
+    #[test]
+    fn keywords() {
+        let mut keywords = Keywords::parse("0 $Junk\n2 Work\n");
+        let name = FileName::parse("1.x:2,PSac", ':');
+        let update = name.flag_update(MessageId::new("INBOX;FILE=1.x"), &keywords);
+        assert!(update.is_read && !update.is_flagged);
+        assert_eq!(
+            update.keywords,
+            BTreeSet::from(["$Forwarded", "$Junk", "Work"].map(String::from))
+        );
+
+        assert_eq!(keywords.assign("Work"), Some('c'));
+        assert_eq!(keywords.assign("Later"), Some('b'));
+        assert_eq!(keywords.assign("Other"), Some('d'));
+        assert_eq!(keywords.to_file(), "0 $Junk\n1 Later\n2 Work\n3 Other\n");
+    }
+}
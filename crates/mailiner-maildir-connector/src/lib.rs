@@ -0,0 +1,1031 @@
+//! Access to a local Maildir++ tree, e.g. one kept in sync by offlineimap or
+//! mbsync, or filled by local delivery. There is no server: the stream passed
+//! to `connect` is ignored and everything goes through a [`FileSystem`].
+//!
+//! The root of the tree is the INBOX, other folders are the directories
+//! named `.Name` next to its `cur`, `new` and `tmp`, with nested folders
+//! separated by dots, e.g. `.Work.Projects`.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Debug;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use mail_parser::{Message, MessageParser};
+use mailiner_core::mime;
+use mailiner_core::platform::{FileSystem, Timer};
+use mailiner_core::{
+    Account, AccountId, BackendUri, ConnectorRegistry, EmailConnector, Envelope, FlagUpdate,
+    Folder, FolderDelta, FolderEvent, FolderId, FolderSyncState, MailinerError, MessageId,
+    MessagePart, MessagePartId, MessageStructure, Result as MailinerResult, SearchQuery,
+};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::{debug, info};
+
+use crate::flags::{FileName, Keywords};
+
+mod flags;
+mod message;
+
+const INBOX: &str = "INBOX";
+/// The file that lists the keywords of a folder, as Dovecot names it.
+const KEYWORDS_FILE: &str = "dovecot-keywords";
+/// Marks a directory as a Maildir++ folder.
+const FOLDER_FILE: &str = "maildirfolder";
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Error, Debug)]
+pub enum MaildirError {
+    #[error("Invalid data: {0}")]
+    InvalidData(String),
+    #[error("Not found: {0}")]
+    NotFound(String),
+}
+
+impl From<MaildirError> for MailinerError {
+    fn from(err: MaildirError) -> Self {
+        match err {
+            MaildirError::InvalidData(msg) => MailinerError::InvalidData(msg),
+            MaildirError::NotFound(msg) => MailinerError::NotFound(msg),
+        }
+    }
+}
+
+/// Builds the id of a message from its unique name, e.g.
+/// `Work;FILE=1700000000.R1a2b.host`. Unlike the whole file name, it doesn't
+/// change with the flags.
+pub(crate) fn to_message_id(folder_id: &FolderId, unique: &str) -> MessageId {
+    MessageId::new(format!("{};FILE={}", folder_id.as_str(), unique))
+}
+
+fn parse_message_id(message_id: &MessageId) -> Result<(FolderId, String), MaildirError> {
+    message_id
+        .as_str()
+        .rsplit_once(";FILE=")
+        .filter(|(_, unique)| !unique.is_empty() && !unique.contains('/'))
+        .map(|(folder, unique)| (FolderId::new(folder), unique.to_string()))
+        .ok_or_else(|| MaildirError::InvalidData(format!("Invalid message id: {}", message_id)))
+}
+
+/// The directory of a folder, relative to the root of the tree.
+fn folder_dir(folder_id: &FolderId) -> String {
+    if folder_id.as_str() == INBOX {
+        String::new()
+    } else {
+        format!(".{}", folder_id.as_str())
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// A message file in `cur` or `new`.
+#[derive(Debug, Clone)]
+struct Entry {
+    /// `cur` or `new`.
+    subdir: &'static str,
+    file_name: String,
+    name: FileName,
+}
+
+pub struct MaildirConnector {
+    files: Arc<dyn FileSystem>,
+    email: String,
+    /// The character between the unique name and the flags, `:` except on
+    /// file systems that don't allow it, where mbsync and others use `;` or
+    /// `!`.
+    separator: char,
+    /// Waits between the checks of `idle`, since there is nothing to watch
+    /// the directories with through a [`FileSystem`].
+    timer: Option<Arc<dyn Timer>>,
+    poll_interval: Duration,
+    /// The file names of the messages of each folder at the last sync, by
+    /// unique name, to tell which of them changed since.
+    synced: Mutex<HashMap<FolderId, HashMap<String, String>>>,
+    /// Makes the unique names of messages delivered in the same second differ.
+    deliveries: AtomicU64,
+}
+
+impl MaildirConnector {
+    /// Opens the tree at the root of `files`. `email` is the address of the
+    /// account, which the tree itself doesn't know.
+    pub fn new(files: Arc<dyn FileSystem>, email: impl Into<String>) -> Self {
+        Self {
+            files,
+            email: email.into(),
+            separator: ':',
+            timer: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            synced: Mutex::new(HashMap::new()),
+            deliveries: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the character between the unique name and the flags of message
+    /// files.
+    pub fn with_info_separator(mut self, separator: char) -> Self {
+        self.separator = separator;
+        self
+    }
+
+    /// Sets the timer `idle` waits with between checking for changes, every
+    /// `poll_interval`.
+    pub fn with_timer(mut self, timer: Arc<dyn Timer>, poll_interval: Duration) -> Self {
+        self.timer = Some(timer);
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    fn account_id(&self) -> AccountId {
+        AccountId::new(format!("maildir-{}", self.email))
+    }
+
+    /// A new unique name, in the format the Maildir spec suggests:
+    /// `<seconds>.<delivery>.<host>`.
+    fn unique_name(&self) -> String {
+        let now = Utc::now();
+        format!(
+            "{}.M{}Q{}R{}.mailiner",
+            now.timestamp(),
+            now.timestamp_subsec_micros(),
+            self.deliveries.fetch_add(1, Ordering::Relaxed),
+            &uuid::Uuid::new_v4().simple().to_string()[..8]
+        )
+    }
+
+    async fn is_folder(&self, dir: &str) -> MailinerResult<bool> {
+        self.files.exists(&join(dir, "cur")).await
+    }
+
+    async fn check_folder(&self, folder_id: &FolderId) -> MailinerResult<String> {
+        let dir = folder_dir(folder_id);
+        if !self.is_folder(&dir).await? {
+            return Err(MaildirError::NotFound(format!("No folder {}", folder_id)).into());
+        }
+        Ok(dir)
+    }
+
+    /// Lists the messages of a folder.
+    async fn entries(&self, folder_id: &FolderId) -> MailinerResult<Vec<Entry>> {
+        let dir = self.check_folder(folder_id).await?;
+        let mut entries = Vec::new();
+        for subdir in ["cur", "new"] {
+            let names = match self.files.list(&join(&dir, subdir)).await {
+                Ok(names) => names,
+                Err(MailinerError::NotFound(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            entries.extend(
+                names
+                    .into_iter()
+                    // Hidden files are temporary files of other programs.
+                    .filter(|name| !name.starts_with('.'))
+                    .map(|file_name| Entry {
+                        subdir,
+                        name: FileName::parse(&file_name, self.separator),
+                        file_name,
+                    }),
+            );
+        }
+        Ok(entries)
+    }
+
+    /// Finds the file of a message, whose flags may have changed since the
+    /// id was handed out.
+    async fn locate(&self, message_id: &MessageId) -> MailinerResult<(FolderId, String, Entry)> {
+        let (folder_id, unique) = parse_message_id(message_id)?;
+        let entry = self
+            .entries(&folder_id)
+            .await?
+            .into_iter()
+            .find(|entry| entry.name.unique == unique)
+            .ok_or_else(|| MaildirError::NotFound(format!("No message {}", message_id)))?;
+        let dir = folder_dir(&folder_id);
+        Ok((folder_id, dir, entry))
+    }
+
+    async fn keywords(&self, dir: &str) -> MailinerResult<Keywords> {
+        match self.files.read(&join(dir, KEYWORDS_FILE)).await {
+            Ok(content) => Ok(Keywords::parse(&String::from_utf8_lossy(&content))),
+            Err(MailinerError::NotFound(_)) => Ok(Keywords::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn read_message(&self, dir: &str, entry: &Entry) -> MailinerResult<Vec<u8>> {
+        self.files
+            .read(&join(dir, &format!("{}/{}", entry.subdir, entry.file_name)))
+            .await
+    }
+
+    async fn envelope(
+        &self,
+        folder_id: &FolderId,
+        dir: &str,
+        entry: &Entry,
+        keywords: &Keywords,
+    ) -> MailinerResult<Envelope> {
+        let raw = self.read_message(dir, entry).await?;
+        let message = parse(&raw, &entry.file_name)?;
+        Ok(message::envelope(
+            &message,
+            to_message_id(folder_id, &entry.name.unique),
+            &self.account_id(),
+            folder_id,
+            &entry.name,
+            keywords,
+        ))
+    }
+
+    /// Gives a message new flags by renaming its file, which also moves it
+    /// from `new` to `cur`.
+    async fn set_flags(
+        &self,
+        dir: &str,
+        entry: &Entry,
+        flags: BTreeSet<char>,
+    ) -> MailinerResult<()> {
+        let name = FileName {
+            unique: entry.name.unique.clone(),
+            flags,
+        };
+        let file_name = name.to_name(self.separator);
+        if entry.subdir == "cur" && file_name == entry.file_name {
+            return Ok(());
+        }
+        self.files
+            .rename(
+                &join(dir, &format!("{}/{}", entry.subdir, entry.file_name)),
+                &join(dir, &format!("cur/{}", file_name)),
+            )
+            .await
+    }
+
+    /// Delivers a message into a folder: written to `tmp` first and then
+    /// moved to `cur`, so that readers never see half a message.
+    async fn deliver(
+        &self,
+        folder_id: &FolderId,
+        flags: BTreeSet<char>,
+        content: &[u8],
+    ) -> MailinerResult<MessageId> {
+        let dir = self.check_folder(folder_id).await?;
+        let name = FileName {
+            unique: self.unique_name(),
+            flags,
+        };
+        let tmp = join(&dir, &format!("tmp/{}", name.unique));
+        self.files.write(&tmp, content).await?;
+        self.files
+            .rename(
+                &tmp,
+                &join(&dir, &format!("cur/{}", name.to_name(self.separator))),
+            )
+            .await?;
+        Ok(to_message_id(folder_id, &name.unique))
+    }
+
+    /// The file names of a folder's messages, by unique name.
+    async fn snapshot(&self, folder_id: &FolderId) -> MailinerResult<HashMap<String, String>> {
+        Ok(self
+            .entries(folder_id)
+            .await?
+            .into_iter()
+            .map(|entry| (entry.name.unique, entry.file_name))
+            .collect())
+    }
+}
+
+fn parse<'x>(raw: &'x [u8], file_name: &str) -> Result<Message<'x>, MaildirError> {
+    MessageParser::new()
+        .parse(raw)
+        .ok_or_else(|| MaildirError::InvalidData(format!("Invalid message file: {}", file_name)))
+}
+
+/// Newest first, by the delivery time at the start of unique names.
+fn sort_newest_first(entries: &mut [Entry]) {
+    entries.sort_by(|a, b| {
+        b.name
+            .timestamp()
+            .cmp(&a.name.timestamp())
+            .then_with(|| b.name.unique.cmp(&a.name.unique))
+    });
+}
+
+/// Registers the `maildir` scheme, e.g.
+/// `maildir:///home/me/Mail?email=me%40example.com`. `open` gives the
+/// [`FileSystem`] rooted at the path of the URI.
+pub fn register<S, F>(registry: &mut ConnectorRegistry<S>, open: F)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync + 'static,
+    F: Fn(&str) -> MailinerResult<Arc<dyn FileSystem>> + Send + Sync + 'static,
+{
+    registry.register(
+        "maildir",
+        move |uri: &BackendUri| -> MailinerResult<Arc<dyn EmailConnector<S>>> {
+            let email = uri.param("email").ok_or_else(|| {
+                MaildirError::InvalidData(format!("No email parameter in {}", uri))
+            })?;
+            Ok(Arc::new(MaildirConnector::new(open(&uri.path)?, email)))
+        },
+    );
+}
+
+#[async_trait]
+impl<S> EmailConnector<S> for MaildirConnector
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync + 'static,
+{
+    async fn connect(&self, _stream: S) -> MailinerResult<()> {
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> MailinerResult<()> {
+        Ok(())
+    }
+
+    /// There is nothing to log in to, `credentials` are ignored.
+    async fn authenticate(&self, _credentials: &str) -> MailinerResult<Account> {
+        if !self.is_folder("").await? {
+            return Err(MaildirError::NotFound("No Maildir at the root".to_string()).into());
+        }
+        Ok(Account {
+            id: self.account_id(),
+            name: self.email.clone(),
+            email: self.email.clone(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        })
+    }
+
+    async fn list_folders(&self, account_id: &AccountId) -> MailinerResult<Vec<Folder>> {
+        let mut ids = vec![INBOX.to_string()];
+        for name in self.files.list("").await? {
+            if let Some(id) = name.strip_prefix('.') {
+                if !id.is_empty() && !id.starts_with('.') && self.is_folder(&name).await? {
+                    ids.push(id.to_string());
+                }
+            }
+        }
+        let existing: HashSet<&str> = ids.iter().map(String::as_str).collect();
+        let mut folders: Vec<Folder> = ids
+            .iter()
+            .map(|id| {
+                let parent = id
+                    .rsplit_once('.')
+                    .filter(|(parent, _)| existing.contains(parent));
+                Folder {
+                    id: FolderId::new(id.clone()),
+                    account_id: account_id.clone(),
+                    name: parent.map_or(id.as_str(), |(_, name)| name).to_string(),
+                    parent_id: parent.map(|(parent, _)| FolderId::new(parent)),
+                    rights: None,
+                    created_at: Utc::now(),
+                    updated_at: Utc::now(),
+                }
+            })
+            .collect();
+        folders.sort_by(|a, b| a.id.as_str().cmp(b.id.as_str()));
+        Ok(folders)
+    }
+
+    /// Folders in the INBOX are created at the top level, as the INBOX is
+    /// the root of the tree.
+    async fn create_folder(
+        &self,
+        account_id: &AccountId,
+        name: &str,
+        parent_id: Option<&FolderId>,
+    ) -> MailinerResult<Folder> {
+        if name.is_empty() || name.contains(['.', '/']) {
+            return Err(MaildirError::InvalidData(
+                "Folder names must not be empty or contain \".\" or \"/\"".to_string(),
+            )
+            .into());
+        }
+        let parent_id = parent_id.filter(|parent| parent.as_str() != INBOX);
+        let id = match parent_id {
+            Some(parent_id) => {
+                self.check_folder(parent_id).await?;
+                FolderId::new(format!("{}.{}", parent_id.as_str(), name))
+            }
+            None => FolderId::new(name),
+        };
+        let dir = folder_dir(&id);
+        for subdir in ["cur", "new", "tmp"] {
+            self.files.create_dir(&join(&dir, subdir)).await?;
+        }
+        self.files.write(&join(&dir, FOLDER_FILE), b"").await?;
+        Ok(Folder {
+            id,
+            account_id: account_id.clone(),
+            name: name.to_string(),
+            parent_id: parent_id.cloned(),
+            rights: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        })
+    }
+
+    /// Nested folders are separate directories and are left alone, like
+    /// Dovecot does.
+    async fn delete_folder(&self, folder_id: &FolderId) -> MailinerResult<()> {
+        if folder_id.as_str() == INBOX {
+            return Err(MaildirError::InvalidData("The INBOX can't be deleted".to_string()).into());
+        }
+        let dir = self.check_folder(folder_id).await?;
+        self.files.remove_dir(&dir).await?;
+        self.synced.lock().unwrap().remove(folder_id);
+        Ok(())
+    }
+
+    /// Nested folders are renamed along, since their names start with the
+    /// name of the folder.
+    async fn rename_folder(
+        &self,
+        folder_id: &FolderId,
+        new_name: &str,
+    ) -> MailinerResult<FolderId> {
+        if folder_id.as_str() == INBOX {
+            return Err(MaildirError::InvalidData("The INBOX can't be renamed".to_string()).into());
+        }
+        if new_name.is_empty() || new_name.contains(['.', '/']) {
+            return Err(MaildirError::InvalidData(
+                "Folder names must not be empty or contain \".\" or \"/\"".to_string(),
+            )
+            .into());
+        }
+        let dir = self.check_folder(folder_id).await?;
+        let new_id = match folder_id.as_str().rsplit_once('.') {
+            Some((parent, _)) => FolderId::new(format!("{}.{}", parent, new_name)),
+            None => FolderId::new(new_name),
+        };
+        let new_dir = folder_dir(&new_id);
+        if self.files.exists(&new_dir).await? {
+            return Err(
+                MaildirError::InvalidData(format!("Folder {} exists already", new_id)).into(),
+            );
+        }
+
+        let prefix = format!("{}.", dir);
+        let mut renames = vec![(dir.clone(), new_dir.clone())];
+        for name in self.files.list("").await? {
+            if let Some(rest) = name.strip_prefix(&prefix) {
+                renames.push((name.clone(), format!("{}.{}", new_dir, rest)));
+            }
+        }
+        for (from, to) in renames {
+            debug!("Renaming {} to {}", from, to);
+            self.files.rename(&from, &to).await?;
+        }
+        self.synced
+            .lock()
+            .unwrap()
+            .retain(|id, _| id != folder_id && !id.as_str().starts_with(&prefix[1..]));
+        Ok(new_id)
+    }
+
+    async fn list_envelopes(&self, folder_id: &FolderId) -> MailinerResult<Vec<Envelope>> {
+        EmailConnector::<S>::list_envelopes_range(self, folder_id, 0..usize::MAX).await
+    }
+
+    async fn list_envelopes_range(
+        &self,
+        folder_id: &FolderId,
+        range: Range<usize>,
+    ) -> MailinerResult<Vec<Envelope>> {
+        let mut entries = self.entries(folder_id).await?;
+        sort_newest_first(&mut entries);
+        let dir = folder_dir(folder_id);
+        let keywords = self.keywords(&dir).await?;
+        let mut envelopes = Vec::new();
+        for entry in entries
+            .iter()
+            .skip(range.start)
+            .take(range.end.saturating_sub(range.start))
+        {
+            match self.envelope(folder_id, &dir, entry, &keywords).await {
+                Ok(envelope) => envelopes.push(envelope),
+                // Moved or deleted by another program in the meantime.
+                Err(MailinerError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(envelopes)
+    }
+
+    async fn get_envelope(&self, message_id: &MessageId) -> MailinerResult<Envelope> {
+        let (folder_id, dir, entry) = self.locate(message_id).await?;
+        let keywords = self.keywords(&dir).await?;
+        self.envelope(&folder_id, &dir, &entry, &keywords).await
+    }
+
+    /// Messages in `new` are moved to `cur`, as they have been seen by a
+    /// client now. Only the files that are new or were renamed since the
+    /// last sync are read, the rest is known to be unchanged.
+    async fn sync_folder(
+        &self,
+        folder_id: &FolderId,
+        _state: Option<&FolderSyncState>,
+        known: &[MessageId],
+    ) -> MailinerResult<FolderDelta> {
+        let dir = folder_dir(folder_id);
+        let mut entries = self.entries(folder_id).await?;
+        for entry in entries.iter_mut().filter(|entry| entry.subdir == "new") {
+            self.set_flags(&dir, entry, entry.name.flags.clone())
+                .await?;
+            entry.subdir = "cur";
+            entry.file_name = entry.name.to_name(self.separator);
+        }
+
+        let previous = self.synced.lock().unwrap().remove(folder_id);
+        let known: HashSet<&MessageId> = known.iter().collect();
+        let keywords = self.keywords(&dir).await?;
+        let mut changed = Vec::new();
+        let mut current = HashSet::new();
+        for entry in &entries {
+            let id = to_message_id(folder_id, &entry.name.unique);
+            let unchanged = known.contains(&id)
+                && previous.as_ref().is_some_and(|previous| {
+                    previous.get(&entry.name.unique) == Some(&entry.file_name)
+                });
+            if !unchanged {
+                match self.envelope(folder_id, &dir, entry, &keywords).await {
+                    Ok(envelope) => changed.push(envelope),
+                    Err(MailinerError::NotFound(_)) => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+            current.insert(id);
+        }
+        let vanished = known
+            .into_iter()
+            .filter(|id| !current.contains(*id))
+            .cloned()
+            .collect();
+
+        self.synced.lock().unwrap().insert(
+            folder_id.clone(),
+            entries
+                .into_iter()
+                .map(|entry| (entry.name.unique, entry.file_name))
+                .collect(),
+        );
+        info!("Synced {}: {} changed", folder_id, changed.len());
+        Ok(FolderDelta {
+            sync_state: None,
+            changed,
+            vanished,
+            read_only: false,
+        })
+    }
+
+    /// The flags are in the file names, so this only lists the folder.
+    async fn resync_flags(
+        &self,
+        folder_id: &FolderId,
+        _since_modseq: u64,
+    ) -> MailinerResult<Vec<FlagUpdate>> {
+        let keywords = self.keywords(&folder_dir(folder_id)).await?;
+        Ok(self
+            .entries(folder_id)
+            .await?
+            .iter()
+            .map(|entry| {
+                entry
+                    .name
+                    .flag_update(to_message_id(folder_id, &entry.name.unique), &keywords)
+            })
+            .collect())
+    }
+
+    async fn update_envelope_flags(
+        &self,
+        message_id: &MessageId,
+        flags: &[(&str, bool)],
+    ) -> MailinerResult<()> {
+        let (_, dir, entry) = self.locate(message_id).await?;
+        let mut letters = entry.name.flags.clone();
+        for (flag, value) in flags {
+            let letter = flags::flag_letter(flag)
+                .ok_or_else(|| MaildirError::InvalidData(format!("Unknown flag: {}", flag)))?;
+            if *value {
+                letters.insert(letter);
+            } else {
+                letters.remove(&letter);
+            }
+        }
+        self.set_flags(&dir, &entry, letters).await
+    }
+
+    /// Keywords get the next free letter of the folder when first used.
+    async fn update_envelope_keywords(
+        &self,
+        message_id: &MessageId,
+        keywords: &[(&str, bool)],
+    ) -> MailinerResult<()> {
+        let (_, dir, entry) = self.locate(message_id).await?;
+        let mut names = self.keywords(&dir).await?;
+        let before = names.clone();
+        let mut letters = entry.name.flags.clone();
+        for (keyword, value) in keywords {
+            let letter = if *keyword == flags::FORWARDED {
+                Some(flags::PASSED)
+            } else if *value {
+                Some(names.assign(keyword).ok_or_else(|| {
+                    MaildirError::InvalidData(
+                        "A folder can't have more than 26 keywords".to_string(),
+                    )
+                })?)
+            } else {
+                names.letter(keyword)
+            };
+            match (letter, value) {
+                (Some(letter), true) => letters.insert(letter),
+                (Some(letter), false) => letters.remove(&letter),
+                (None, _) => false,
+            };
+        }
+        if names != before {
+            self.files
+                .write(&join(&dir, KEYWORDS_FILE), names.to_file().as_bytes())
+                .await?;
+        }
+        self.set_flags(&dir, &entry, letters).await
+    }
+
+    /// Keyword letters are only meaningful within a folder and are dropped.
+    async fn move_message(
+        &self,
+        message_id: &MessageId,
+        target_folder_id: &FolderId,
+    ) -> MailinerResult<Option<MessageId>> {
+        let (folder_id, dir, entry) = self.locate(message_id).await?;
+        if &folder_id == target_folder_id {
+            return Ok(Some(message_id.clone()));
+        }
+        let target_dir = self.check_folder(target_folder_id).await?;
+        let name = FileName {
+            unique: entry.name.unique.clone(),
+            flags: entry
+                .name
+                .flags
+                .iter()
+                .copied()
+                .filter(char::is_ascii_uppercase)
+                .collect(),
+        };
+        self.files
+            .rename(
+                &join(&dir, &format!("{}/{}", entry.subdir, entry.file_name)),
+                &join(
+                    &target_dir,
+                    &format!("cur/{}", name.to_name(self.separator)),
+                ),
+            )
+            .await?;
+        Ok(Some(to_message_id(target_folder_id, &name.unique)))
+    }
+
+    async fn copy_message(
+        &self,
+        message_id: &MessageId,
+        target_folder_id: &FolderId,
+    ) -> MailinerResult<Option<MessageId>> {
+        let (_, dir, entry) = self.locate(message_id).await?;
+        let content = self.read_message(&dir, &entry).await?;
+        let flags = entry
+            .name
+            .flags
+            .iter()
+            .copied()
+            .filter(char::is_ascii_uppercase)
+            .collect();
+        Ok(Some(self.deliver(target_folder_id, flags, &content).await?))
+    }
+
+    async fn append_message(
+        &self,
+        folder_id: &FolderId,
+        flags: &[&str],
+        content: &[u8],
+    ) -> MailinerResult<Option<MessageId>> {
+        let letters = flags
+            .iter()
+            .filter_map(|flag| flags::system_flag_letter(flag))
+            .collect();
+        Ok(Some(self.deliver(folder_id, letters, content).await?))
+    }
+
+    async fn delete_message(&self, message_id: &MessageId) -> MailinerResult<()> {
+        let (_, dir, entry) = self.locate(message_id).await?;
+        self.files
+            .remove(&join(
+                &dir,
+                &format!("{}/{}", entry.subdir, entry.file_name),
+            ))
+            .await
+    }
+
+    /// Reads every message of the folder, there is no index to search.
+    async fn search(
+        &self,
+        folder_id: &FolderId,
+        query: &SearchQuery,
+    ) -> MailinerResult<Vec<MessageId>> {
+        let mut entries = self.entries(folder_id).await?;
+        sort_newest_first(&mut entries);
+        let dir = folder_dir(folder_id);
+        let keywords = self.keywords(&dir).await?;
+        let mut ids = Vec::new();
+        for entry in &entries {
+            let raw = match self.read_message(&dir, entry).await {
+                Ok(raw) => raw,
+                Err(MailinerError::NotFound(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            let Ok(message) = parse(&raw, &entry.file_name) else {
+                continue;
+            };
+            let id = to_message_id(folder_id, &entry.name.unique);
+            let envelope = message::envelope(
+                &message,
+                id.clone(),
+                &self.account_id(),
+                folder_id,
+                &entry.name,
+                &keywords,
+            );
+            if query.matches(&envelope)
+                && query
+                    .body
+                    .as_ref()
+                    .is_none_or(|body| mime::body_contains(&message, body))
+            {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+
+    async fn get_message_structure(
+        &self,
+        message_id: &MessageId,
+    ) -> MailinerResult<MessageStructure> {
+        let (_, dir, entry) = self.locate(message_id).await?;
+        let raw = self.read_message(&dir, &entry).await?;
+        Ok(mime::structure(&parse(&raw, &entry.file_name)?))
+    }
+
+    async fn fetch_raw_message(&self, message_id: &MessageId) -> MailinerResult<Vec<u8>> {
+        let (_, dir, entry) = self.locate(message_id).await?;
+        self.read_message(&dir, &entry).await
+    }
+
+    async fn get_message_part(
+        &self,
+        message_id: &MessageId,
+        part_id: &MessagePartId,
+    ) -> MailinerResult<MessagePart> {
+        let (_, dir, entry) = self.locate(message_id).await?;
+        let raw = self.read_message(&dir, &entry).await?;
+        let part =
+            mime::find_part(&parse(&raw, &entry.file_name)?, part_id).ok_or_else(|| {
+                MaildirError::NotFound(format!("No part {} in {}", part_id, message_id))
+            })?;
+        let size = match &part.content {
+            mailiner_core::MessageContent::Text(text)
+            | mailiner_core::MessageContent::Html(text) => text.len(),
+            mailiner_core::MessageContent::Binary(data) => data.len(),
+        };
+        Ok(MessagePart {
+            id: part_id.clone(),
+            envelope_id: message_id.clone(),
+            content_type: part.content_type,
+            filename: part.filename,
+            size: size as u64,
+            is_attachment: part.is_attachment,
+            content: part.content,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        })
+    }
+
+    /// Polls the folder for files that appeared, disappeared or were
+    /// renamed. There are no sequence numbers, so events carry 0 instead.
+    async fn idle(
+        &self,
+        folder_id: &FolderId,
+        timeout: Duration,
+    ) -> MailinerResult<Vec<FolderEvent>> {
+        let timer = self.timer.clone().ok_or_else(|| {
+            MaildirError::InvalidData("Watching a folder needs a timer, see with_timer".to_string())
+        })?;
+        let mut before = self.snapshot(folder_id).await?;
+        let mut waited = Duration::ZERO;
+        while waited < timeout {
+            let interval = self.poll_interval.min(timeout - waited);
+            timer.sleep(interval).await;
+            waited += interval;
+
+            let now = self.snapshot(folder_id).await?;
+            let mut events = Vec::new();
+            if now.keys().any(|unique| !before.contains_key(unique)) {
+                events.push(FolderEvent::NewMessages {
+                    folder_id: folder_id.clone(),
+                    exists: now.len().try_into().unwrap_or(u32::MAX),
+                });
+            }
+            for (unique, file_name) in &before {
+                match now.get(unique) {
+                    None => events.push(FolderEvent::MessageExpunged {
+                        folder_id: folder_id.clone(),
+                        sequence: 0,
+                    }),
+                    Some(now) if now != file_name => events.push(FolderEvent::FlagsChanged {
+                        folder_id: folder_id.clone(),
+                        sequence: 0,
+                    }),
+                    Some(_) => {}
+                }
+            }
+            if !events.is_empty() {
+                return Ok(events);
+            }
+            before = now;
+        }
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mailiner_core::platform::native::NativeFileSystem;
+    use tokio::io::DuplexStream;
+
+    use super::*;
+
+    const MESSAGE: &[u8] = b"From: jan@example.com\r\n\
+        Subject: Hello\r\n\
+        Date: Tue, 14 Nov 2023 22:13:20 +0000\r\n\
+        \r\n\
+        See you tomorrow\r\n";
+
+    fn connector(root: &std::path::Path) -> (Arc<NativeFileSystem>, MaildirConnector) {
+        let files = Arc::new(NativeFileSystem::new(root));
+        (
+            files.clone(),
+            MaildirConnector::new(files, "jan@example.com"),
+        )
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "mailiner-maildir-{}-{}",
+            name,
+            uuid::Uuid::new_v4()
+        ));
+        std::fs::create_dir_all(dir.join("cur")).unwrap();
+        std::fs::create_dir_all(dir.join("new")).unwrap();
+        std::fs::create_dir_all(dir.join("tmp")).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn syncs_the_inbox() {
+        let root = temp_dir("sync");
+        std::fs::write(root.join("new/1700000000.R1.host"), MESSAGE).unwrap();
+        std::fs::write(root.join("cur/1600000000.R2.host:2,S"), MESSAGE).unwrap();
+        let (_, connector) = connector(&root);
+        let connector: &dyn EmailConnector<DuplexStream> = &connector;
+        let inbox = FolderId::new(INBOX);
+
+        let delta = connector.sync_folder(&inbox, None, &[]).await.unwrap();
+        assert_eq!(delta.changed.len(), 2);
+        assert!(root.join("cur/1700000000.R1.host:2,").exists());
+        let ids: Vec<MessageId> = delta.changed.iter().map(|e| e.id.clone()).collect();
+
+        // Only the renamed file is read again.
+        let id = MessageId::new("INBOX;FILE=1700000000.R1.host");
+        connector
+            .update_envelope_flags(&id, &[("is_read", true), ("is_flagged", true)])
+            .await
+            .unwrap();
+        connector
+            .update_envelope_keywords(&id, &[("$Junk", true)])
+            .await
+            .unwrap();
+        std::fs::remove_file(root.join("cur/1600000000.R2.host:2,S")).unwrap();
+        let delta = connector.sync_folder(&inbox, None, &ids).await.unwrap();
+        assert_eq!(delta.changed.len(), 1);
+        let envelope = &delta.changed[0];
+        assert_eq!(envelope.id, id);
+        assert!(envelope.is_read && envelope.is_flagged);
+        assert_eq!(envelope.keywords, BTreeSet::from(["$Junk".to_string()]));
+        assert_eq!(envelope.subject.as_deref(), Some("Hello"));
+        assert_eq!(
+            delta.vanished,
+            [MessageId::new("INBOX;FILE=1600000000.R2.host")]
+        );
+        assert!(root.join("cur/1700000000.R1.host:2,FSa").exists());
+        assert_eq!(
+            std::fs::read_to_string(root.join(KEYWORDS_FILE)).unwrap(),
+            "0 $Junk\n"
+        );
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn folders_and_messages() {
+        let root = temp_dir("folders");
+        let (_, connector) = connector(&root);
+        let connector: &dyn EmailConnector<DuplexStream> = &connector;
+        let account = connector.authenticate("").await.unwrap();
+
+        let work = connector
+            .create_folder(&account.id, "Work", None)
+            .await
+            .unwrap();
+        let projects = connector
+            .create_folder(&account.id, "Projects", Some(&work.id))
+            .await
+            .unwrap();
+        assert_eq!(projects.id.as_str(), "Work.Projects");
+        assert!(root.join(".Work.Projects/maildirfolder").exists());
+
+        let id = connector
+            .append_message(&projects.id, &["\\Seen"], MESSAGE)
+            .await
+            .unwrap()
+            .unwrap();
+        let renamed = connector.rename_folder(&work.id, "Jobs").await.unwrap();
+        assert_eq!(renamed.as_str(), "Jobs");
+        let folders = connector.list_folders(&account.id).await.unwrap();
+        let names: Vec<(&str, &str, Option<&str>)> = folders
+            .iter()
+            .map(|f| {
+                (
+                    f.id.as_str(),
+                    f.name.as_str(),
+                    f.parent_id.as_ref().map(|p| p.as_str()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("INBOX", "INBOX", None),
+                ("Jobs", "Jobs", None),
+                ("Jobs.Projects", "Projects", Some("Jobs"))
+            ]
+        );
+
+        let id = MessageId::new(id.as_str().replace("Work.", "Jobs."));
+        let moved = connector
+            .move_message(&id, &FolderId::new(INBOX))
+            .await
+            .unwrap()
+            .unwrap();
+        let query = SearchQuery {
+            body: Some("TOMORROW".to_string()),
+            is_read: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            connector
+                .search(&FolderId::new(INBOX), &query)
+                .await
+                .unwrap(),
+            std::slice::from_ref(&moved)
+        );
+        let part = connector
+            .get_message_part(&moved, &MessagePartId::new("1"))
+            .await
+            .unwrap();
+        assert!(
+            matches!(part.content, mailiner_core::MessageContent::Text(text) if text.starts_with("See you"))
+        );
+
+        connector.delete_message(&moved).await.unwrap();
+        assert!(connector
+            .list_envelopes(&FolderId::new(INBOX))
+            .await
+            .unwrap()
+            .is_empty());
+        connector.delete_folder(&renamed).await.unwrap();
+        assert!(!root.join(".Jobs").exists());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
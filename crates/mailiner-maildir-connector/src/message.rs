@@ -0,0 +1,100 @@
+//! Envelopes of message files, from their headers and file names.
+
+use chrono::{DateTime, Utc};
+use mail_parser::Message;
+use mailiner_core::mime;
+use mailiner_core::{AccountId, Envelope, FolderId, MessageId};
+
+use crate::flags::{FileName, Keywords};
+
+/// Builds the envelope from the headers, with the flags and delivery time
+/// from the file name, the time used where there is no `Date` header.
+pub(crate) fn envelope(
+    message: &Message<'_>,
+    id: MessageId,
+    account_id: &AccountId,
+    folder_id: &FolderId,
+    name: &FileName,
+    keywords: &Keywords,
+) -> Envelope {
+    let flags = name.flag_update(id.clone(), keywords);
+    let delivered = name
+        .timestamp()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .unwrap_or_else(Utc::now);
+
+    Envelope {
+        date: mime::date(message).unwrap_or(delivered),
+        is_read: flags.is_read,
+        is_starred: flags.is_starred,
+        is_flagged: flags.is_flagged,
+        is_draft: flags.is_draft,
+        is_deleted: flags.is_deleted,
+        size: Some(message.raw_message().len() as u64),
+        keywords: flags.keywords,
+        ..mime::envelope(message, id, account_id, folder_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use mail_parser::MessageParser;
+
+    use super::*;
+
+    const MESSAGE: &[u8] = b"From: Jan <jan@example.com>\r\n\
+        To: ana@example.com\r\n\
+        Subject: =?UTF-8?Q?Gr=C3=BC=C3=9Fe?=\r\n\
+        Message-ID: <b@example.com>\r\n\
+        In-Reply-To: <a@example.com>\r\n\
+        Content-Type: multipart/mixed; boundary=\"outer\"\r\n\
+        \r\n\
+        --outer\r\n\
+        Content-Type: multipart/alternative; boundary=\"inner\"\r\n\
+        \r\n\
+        --inner\r\n\
+        Content-Type: text/plain; charset=iso-8859-1\r\n\
+        Content-Transfer-Encoding: quoted-printable\r\n\
+        \r\n\
+        Gr=FC=DFe\r\n\
+        --inner\r\n\
+        Content-Type: text/html\r\n\
+        \r\n\
+        <p>Hi</p>\r\n\
+        --inner--\r\n\
+        --outer\r\n\
+        Content-Type: application/pdf; name=\"report.pdf\"\r\n\
+        Content-Disposition: attachment; filename=\"report.pdf\"\r\n\
+        Content-Transfer-Encoding: base64\r\n\
+        \r\n\
+        JVBERi0=\r\n\
+        --outer--\r\n";
+
+    #[test]
+    fn envelope_from_file() {
+        let message = MessageParser::new().parse(MESSAGE).unwrap();
+        let name = FileName::parse("1700000000.R1.host:2,Sa", ':');
+        let envelope = envelope(
+            &message,
+            MessageId::new("INBOX;FILE=1700000000.R1.host"),
+            &AccountId::new("maildir-jan@example.com"),
+            &FolderId::new("INBOX"),
+            &name,
+            &Keywords::parse("0 Work\n"),
+        );
+        assert_eq!(envelope.subject.as_deref(), Some("Grüße"));
+        assert_eq!(
+            envelope.from.map(|from| from.to_string()).as_deref(),
+            Some("Jan <jan@example.com>")
+        );
+        assert_eq!(envelope.message_id.as_deref(), Some("b@example.com"));
+        assert_eq!(envelope.in_reply_to, ["a@example.com"]);
+        // There is no Date header.
+        assert_eq!(envelope.date.timestamp(), 1_700_000_000);
+        assert!(envelope.is_read && !envelope.is_flagged && envelope.has_attachments);
+        assert_eq!(envelope.keywords, BTreeSet::from(["Work".to_string()]));
+        assert_eq!(envelope.size, Some(MESSAGE.len() as u64));
+    }
+}
@@ -0,0 +1,108 @@
+//! The index of an mbox file: where each message starts and ends, found by
+//! reading the file in chunks so that large archives never have to fit in
+//! memory.
+
+use std::ops::Range;
+
+/// The byte ranges of the messages in a file, without their `From `
+/// separator lines, in the order of the file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Index {
+    pub(crate) messages: Vec<Range<u64>>,
+    /// The size of the file when it was indexed.
+    pub(crate) len: u64,
+}
+
+impl Index {
+    /// Finds a message by the offset it starts at.
+    pub(crate) fn find(&self, start: u64) -> Option<Range<u64>> {
+        self.messages
+            .binary_search_by_key(&start, |message| message.start)
+            .ok()
+            .map(|index| self.messages[index].clone())
+    }
+}
+
+/// Builds an [`Index`] from the chunks of a file, which may split lines.
+#[derive(Debug, Default)]
+pub(crate) struct Indexer {
+    /// The offset in the file of `pending`.
+    offset: u64,
+    /// The start of a line that continues in the next chunk.
+    pending: Vec<u8>,
+    /// Where the message being read started.
+    current: Option<u64>,
+    messages: Vec<Range<u64>>,
+}
+
+impl Indexer {
+    pub(crate) fn feed(&mut self, chunk: &[u8]) {
+        self.pending.extend_from_slice(chunk);
+        let mut consumed = 0;
+        while let Some(end) = self.pending[consumed..].iter().position(|b| *b == b'\n') {
+            let line_end = consumed + end + 1;
+            if self.pending[consumed..line_end].starts_with(b"From ") {
+                self.separator(self.offset + consumed as u64, self.offset + line_end as u64);
+            }
+            consumed = line_end;
+        }
+        self.pending.drain(..consumed);
+        self.offset += consumed as u64;
+    }
+
+    pub(crate) fn finish(mut self) -> Index {
+        let len = self.offset + self.pending.len() as u64;
+        // A separator without a message after it.
+        if self.pending.starts_with(b"From ") {
+            self.separator(self.offset, len);
+        } else if let Some(start) = self.current.take() {
+            self.messages.push(start..len);
+        }
+        self.current = None;
+        Index {
+            messages: self.messages,
+            len,
+        }
+    }
+
+    /// A `From ` line ends the message before it and starts a new one.
+    fn separator(&mut self, start: u64, end: u64) {
+        if let Some(previous) = self.current.replace(end) {
+            self.messages.push(previous..start);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indexes_across_chunks() {
+        let file = b"From a@example.com Mon Jan  1 00:00:00 2024\n\
+            Subject: One\n\n>From here\n\n\
+            From b@example.com Tue Jan  2 00:00:00 2024\n\
+            Subject: Two\n\nBody";
+        for chunk_size in [1, 7, file.len()] {
+            let mut indexer = Indexer::default();
+            for chunk in file.chunks(chunk_size) {
+                indexer.feed(chunk);
+            }
+            let index = indexer.finish();
+            assert_eq!(index.len, file.len() as u64);
+            assert_eq!(index.messages.len(), 2);
+            let first = &index.messages[0];
+            assert_eq!(
+                &file[first.start as usize..first.end as usize],
+                b"Subject: One\n\n>From here\n\n"
+            );
+            let second = &index.messages[1];
+            assert_eq!(
+                &file[second.start as usize..second.end as usize],
+                b"Subject: Two\n\nBody"
+            );
+            assert_eq!(index.find(second.start), Some(second.clone()));
+            assert_eq!(index.find(1), None);
+        }
+    }
+}
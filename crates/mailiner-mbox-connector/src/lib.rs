@@ -0,0 +1,671 @@
+//! Read-only access to an mbox file, e.g. a Google Takeout export, to browse
+//! an archive without importing it. The file is one folder; messages are
+//! found through an index of their offsets and read one at a time.
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use mail_parser::{Message, MessageParser};
+use mailiner_core::import::mbox::unescape;
+use mailiner_core::mime;
+use mailiner_core::platform::{FileSystem, Timer};
+use mailiner_core::{
+    Account, AccountId, BackendUri, ConnectorRegistry, EmailConnector, Envelope, FlagUpdate,
+    Folder, FolderDelta, FolderEvent, FolderId, FolderRights, FolderSyncState, MailinerError,
+    MessageContent, MessageId, MessagePart, MessagePartId, MessageStructure,
+    Result as MailinerResult, SearchQuery,
+};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::info;
+
+use crate::index::{Index, Indexer};
+
+mod index;
+mod message;
+
+/// How much of the file is read at once while indexing.
+const CHUNK_SIZE: u64 = 1024 * 1024;
+/// How much of a message is read to find its headers, more is only read if
+/// they don't fit.
+const HEADER_SIZE: u64 = 16 * 1024;
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Error, Debug)]
+pub enum MboxError {
+    #[error("Invalid data: {0}")]
+    InvalidData(String),
+    #[error("Not found: {0}")]
+    NotFound(String),
+    #[error("{0} is read-only")]
+    ReadOnly(String),
+}
+
+impl From<MboxError> for MailinerError {
+    fn from(err: MboxError) -> Self {
+        match err {
+            MboxError::InvalidData(msg) => MailinerError::InvalidData(msg),
+            MboxError::NotFound(msg) => MailinerError::NotFound(msg),
+            MboxError::ReadOnly(path) => MailinerError::Rejected(format!("{} is read-only", path)),
+        }
+    }
+}
+
+/// Builds the id of a message from the offset it starts at, e.g.
+/// `archive;OFFSET=5120`. Offsets stay the same as long as messages are only
+/// appended to the file.
+pub(crate) fn to_message_id(folder_id: &FolderId, start: u64) -> MessageId {
+    MessageId::new(format!("{};OFFSET={}", folder_id.as_str(), start))
+}
+
+fn parse_message_id(message_id: &MessageId) -> Result<(FolderId, u64), MboxError> {
+    message_id
+        .as_str()
+        .rsplit_once(";OFFSET=")
+        .and_then(|(folder, start)| Some((FolderId::new(folder), start.parse().ok()?)))
+        .ok_or_else(|| MboxError::InvalidData(format!("Invalid message id: {}", message_id)))
+}
+
+/// The end of the headers, including the empty line after them.
+fn header_end(data: &[u8]) -> Option<usize> {
+    let lf = data
+        .windows(2)
+        .position(|window| window == b"\n\n")
+        .map(|position| position + 2);
+    let crlf = data
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|position| position + 4);
+    lf.into_iter().chain(crlf).min()
+}
+
+fn parse<'x>(raw: &'x [u8], message_id: &MessageId) -> Result<Message<'x>, MboxError> {
+    MessageParser::new()
+        .parse(raw)
+        .ok_or_else(|| MboxError::InvalidData(format!("Invalid message: {}", message_id)))
+}
+
+pub struct MboxConnector {
+    files: Arc<dyn FileSystem>,
+    path: String,
+    /// The folder of the file, named after it.
+    folder_id: FolderId,
+    /// Waits between the checks of `idle` for mail appended to the file.
+    timer: Option<Arc<dyn Timer>>,
+    poll_interval: Duration,
+    /// The index as of the last sync, built on first use.
+    index: Mutex<Option<Arc<Index>>>,
+}
+
+impl MboxConnector {
+    /// Opens the mbox file at `path` in `files`.
+    pub fn new(files: Arc<dyn FileSystem>, path: impl Into<String>) -> Self {
+        let path = path.into();
+        let name = path.rsplit('/').next().unwrap_or(&path);
+        let name = name.strip_suffix(".mbox").unwrap_or(name);
+        Self {
+            files,
+            folder_id: FolderId::new(name),
+            path,
+            timer: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            index: Mutex::new(None),
+        }
+    }
+
+    /// Sets the timer `idle` waits with between checking whether the file
+    /// grew, every `poll_interval`.
+    pub fn with_timer(mut self, timer: Arc<dyn Timer>, poll_interval: Duration) -> Self {
+        self.timer = Some(timer);
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn folder_id(&self) -> &FolderId {
+        &self.folder_id
+    }
+
+    fn account_id(&self) -> AccountId {
+        AccountId::new(format!("mbox-{}", self.folder_id))
+    }
+
+    fn read_only(&self) -> MailinerError {
+        MboxError::ReadOnly(self.path.clone()).into()
+    }
+
+    fn check_folder(&self, folder_id: &FolderId) -> Result<(), MboxError> {
+        if folder_id != &self.folder_id {
+            return Err(MboxError::NotFound(format!("No folder {}", folder_id)));
+        }
+        Ok(())
+    }
+
+    /// Reads the whole file in chunks to find its messages.
+    async fn build_index(&self) -> MailinerResult<Arc<Index>> {
+        let mut indexer = Indexer::default();
+        let mut offset = 0;
+        loop {
+            let chunk = self
+                .files
+                .read_range(&self.path, offset..offset + CHUNK_SIZE)
+                .await?;
+            indexer.feed(&chunk);
+            offset += chunk.len() as u64;
+            if (chunk.len() as u64) < CHUNK_SIZE {
+                break;
+            }
+        }
+        let index = Arc::new(indexer.finish());
+        info!("Indexed {} messages in {}", index.messages.len(), self.path);
+        *self.index.lock().unwrap() = Some(index.clone());
+        Ok(index)
+    }
+
+    async fn index(&self) -> MailinerResult<Arc<Index>> {
+        let index = self.index.lock().unwrap().clone();
+        match index {
+            Some(index) => Ok(index),
+            None => self.build_index().await,
+        }
+    }
+
+    async fn locate(&self, message_id: &MessageId) -> MailinerResult<Range<u64>> {
+        let (folder_id, start) = parse_message_id(message_id)?;
+        self.check_folder(&folder_id)?;
+        Ok(self
+            .index()
+            .await?
+            .find(start)
+            .ok_or_else(|| MboxError::NotFound(format!("No message {}", message_id)))?)
+    }
+
+    async fn read_message(&self, range: Range<u64>) -> MailinerResult<Vec<u8>> {
+        Ok(unescape(&self.files.read_range(&self.path, range).await?))
+    }
+
+    /// Reads only as much of a message as its headers need.
+    async fn envelope(&self, range: Range<u64>) -> MailinerResult<Envelope> {
+        let id = to_message_id(&self.folder_id, range.start);
+        let size = range.end - range.start;
+        let head_range = range.start..range.end.min(range.start + HEADER_SIZE);
+        let mut raw = self.read_message(head_range).await?;
+        match header_end(&raw) {
+            Some(end) => raw.truncate(end),
+            None => raw = self.read_message(range).await?,
+        }
+        let headers = MessageParser::new()
+            .parse_headers(&raw[..])
+            .ok_or_else(|| MboxError::InvalidData(format!("Invalid message: {}", id)))?;
+        Ok(Envelope {
+            size: Some(size),
+            ..message::envelope(&headers, id, &self.account_id(), &self.folder_id)
+        })
+    }
+
+    async fn envelopes(&self, ranges: &[Range<u64>]) -> MailinerResult<Vec<Envelope>> {
+        let mut envelopes = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            envelopes.push(self.envelope(range.clone()).await?);
+        }
+        Ok(envelopes)
+    }
+}
+
+/// Registers the `mbox` scheme, e.g. `mbox:///home/me/archive.mbox`. `open`
+/// gives the [`FileSystem`] rooted at the directory of the file.
+pub fn register<S, F>(registry: &mut ConnectorRegistry<S>, open: F)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync + 'static,
+    F: Fn(&str) -> MailinerResult<Arc<dyn FileSystem>> + Send + Sync + 'static,
+{
+    registry.register(
+        "mbox",
+        move |uri: &BackendUri| -> MailinerResult<Arc<dyn EmailConnector<S>>> {
+            let (dir, file) = match uri.path.rsplit_once('/') {
+                Some(("", file)) => ("/", file),
+                Some((dir, file)) => (dir, file),
+                None => (".", uri.path.as_str()),
+            };
+            if file.is_empty() {
+                return Err(MboxError::InvalidData(format!("No file in {}", uri)).into());
+            }
+            Ok(Arc::new(MboxConnector::new(open(dir)?, file)))
+        },
+    );
+}
+
+#[async_trait]
+impl<S> EmailConnector<S> for MboxConnector
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync + 'static,
+{
+    async fn connect(&self, _stream: S) -> MailinerResult<()> {
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> MailinerResult<()> {
+        Ok(())
+    }
+
+    /// There is nothing to log in to, `credentials` are ignored. The account
+    /// is named after the file and has no address.
+    async fn authenticate(&self, _credentials: &str) -> MailinerResult<Account> {
+        if !self.files.exists(&self.path).await? {
+            return Err(MboxError::NotFound(format!("No file {}", self.path)).into());
+        }
+        Ok(Account {
+            id: self.account_id(),
+            name: self.folder_id.to_string(),
+            email: String::new(),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        })
+    }
+
+    async fn list_folders(&self, account_id: &AccountId) -> MailinerResult<Vec<Folder>> {
+        Ok(vec![Folder {
+            id: self.folder_id.clone(),
+            account_id: account_id.clone(),
+            name: self.folder_id.to_string(),
+            parent_id: None,
+            // Lookup and read only.
+            rights: Some(FolderRights::new("lr")),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }])
+    }
+
+    async fn create_folder(
+        &self,
+        _account_id: &AccountId,
+        _name: &str,
+        _parent_id: Option<&FolderId>,
+    ) -> MailinerResult<Folder> {
+        Err(self.read_only())
+    }
+
+    async fn delete_folder(&self, _folder_id: &FolderId) -> MailinerResult<()> {
+        Err(self.read_only())
+    }
+
+    async fn rename_folder(
+        &self,
+        _folder_id: &FolderId,
+        _new_name: &str,
+    ) -> MailinerResult<FolderId> {
+        Err(self.read_only())
+    }
+
+    async fn list_envelopes(&self, folder_id: &FolderId) -> MailinerResult<Vec<Envelope>> {
+        EmailConnector::<S>::list_envelopes_range(self, folder_id, 0..usize::MAX).await
+    }
+
+    /// Newest first, taking messages to be appended in the order they
+    /// arrived.
+    async fn list_envelopes_range(
+        &self,
+        folder_id: &FolderId,
+        range: Range<usize>,
+    ) -> MailinerResult<Vec<Envelope>> {
+        self.check_folder(folder_id)?;
+        let index = self.index().await?;
+        let ranges: Vec<Range<u64>> = index
+            .messages
+            .iter()
+            .rev()
+            .skip(range.start)
+            .take(range.end.saturating_sub(range.start))
+            .cloned()
+            .collect();
+        self.envelopes(&ranges).await
+    }
+
+    async fn get_envelope(&self, message_id: &MessageId) -> MailinerResult<Envelope> {
+        let range = self.locate(message_id).await?;
+        self.envelope(range).await
+    }
+
+    /// Indexes the file again. Only messages that aren't known yet are read,
+    /// as the flags of the others can't have changed without the file being
+    /// rewritten, which moves them to new offsets.
+    async fn sync_folder(
+        &self,
+        folder_id: &FolderId,
+        _state: Option<&FolderSyncState>,
+        known: &[MessageId],
+    ) -> MailinerResult<FolderDelta> {
+        self.check_folder(folder_id)?;
+        let index = self.build_index().await?;
+        let known: HashSet<&MessageId> = known.iter().collect();
+        let mut current = HashSet::new();
+        let mut new = Vec::new();
+        for range in &index.messages {
+            let id = to_message_id(folder_id, range.start);
+            if !known.contains(&id) {
+                new.push(range.clone());
+            }
+            current.insert(id);
+        }
+        Ok(FolderDelta {
+            sync_state: None,
+            changed: self.envelopes(&new).await?,
+            vanished: known
+                .into_iter()
+                .filter(|id| !current.contains(*id))
+                .cloned()
+                .collect(),
+            read_only: true,
+        })
+    }
+
+    /// Flags can't change in a read-only file.
+    async fn resync_flags(
+        &self,
+        folder_id: &FolderId,
+        _since_modseq: u64,
+    ) -> MailinerResult<Vec<FlagUpdate>> {
+        self.check_folder(folder_id)?;
+        Ok(Vec::new())
+    }
+
+    async fn update_envelope_flags(
+        &self,
+        _message_id: &MessageId,
+        _flags: &[(&str, bool)],
+    ) -> MailinerResult<()> {
+        Err(self.read_only())
+    }
+
+    async fn update_envelope_keywords(
+        &self,
+        _message_id: &MessageId,
+        _keywords: &[(&str, bool)],
+    ) -> MailinerResult<()> {
+        Err(self.read_only())
+    }
+
+    async fn move_message(
+        &self,
+        _message_id: &MessageId,
+        _target_folder_id: &FolderId,
+    ) -> MailinerResult<Option<MessageId>> {
+        Err(self.read_only())
+    }
+
+    async fn copy_message(
+        &self,
+        _message_id: &MessageId,
+        _target_folder_id: &FolderId,
+    ) -> MailinerResult<Option<MessageId>> {
+        Err(self.read_only())
+    }
+
+    async fn append_message(
+        &self,
+        _folder_id: &FolderId,
+        _flags: &[&str],
+        _content: &[u8],
+    ) -> MailinerResult<Option<MessageId>> {
+        Err(self.read_only())
+    }
+
+    async fn delete_message(&self, _message_id: &MessageId) -> MailinerResult<()> {
+        Err(self.read_only())
+    }
+
+    /// Reads every message, there is no index of the contents.
+    async fn search(
+        &self,
+        folder_id: &FolderId,
+        query: &SearchQuery,
+    ) -> MailinerResult<Vec<MessageId>> {
+        self.check_folder(folder_id)?;
+        let index = self.index().await?;
+        let mut ids = Vec::new();
+        for range in index.messages.iter().rev() {
+            let id = to_message_id(folder_id, range.start);
+            let raw = self.read_message(range.clone()).await?;
+            let Ok(message) = parse(&raw, &id) else {
+                continue;
+            };
+            let envelope = message::envelope(&message, id.clone(), &self.account_id(), folder_id);
+            if query.matches(&envelope)
+                && query
+                    .body
+                    .as_ref()
+                    .is_none_or(|body| mime::body_contains(&message, body))
+            {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+
+    async fn get_message_structure(
+        &self,
+        message_id: &MessageId,
+    ) -> MailinerResult<MessageStructure> {
+        let range = self.locate(message_id).await?;
+        let raw = self.read_message(range).await?;
+        Ok(mime::structure(&parse(&raw, message_id)?))
+    }
+
+    /// The message without its `From ` line, with `>From ` lines
+    /// unescaped.
+    async fn fetch_raw_message(&self, message_id: &MessageId) -> MailinerResult<Vec<u8>> {
+        let range = self.locate(message_id).await?;
+        self.read_message(range).await
+    }
+
+    async fn get_message_part(
+        &self,
+        message_id: &MessageId,
+        part_id: &MessagePartId,
+    ) -> MailinerResult<MessagePart> {
+        let range = self.locate(message_id).await?;
+        let raw = self.read_message(range).await?;
+        let part = mime::find_part(&parse(&raw, message_id)?, part_id)
+            .ok_or_else(|| MboxError::NotFound(format!("No part {} in {}", part_id, message_id)))?;
+        let size = match &part.content {
+            MessageContent::Text(text) | MessageContent::Html(text) => text.len(),
+            MessageContent::Binary(data) => data.len(),
+        };
+        Ok(MessagePart {
+            id: part_id.clone(),
+            envelope_id: message_id.clone(),
+            content_type: part.content_type,
+            filename: part.filename,
+            size: size as u64,
+            is_attachment: part.is_attachment,
+            content: part.content,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        })
+    }
+
+    /// Polls whether mail was appended to the file, e.g. by local delivery.
+    async fn idle(
+        &self,
+        folder_id: &FolderId,
+        timeout: Duration,
+    ) -> MailinerResult<Vec<FolderEvent>> {
+        self.check_folder(folder_id)?;
+        let timer = self.timer.clone().ok_or_else(|| {
+            MboxError::InvalidData("Watching a folder needs a timer, see with_timer".to_string())
+        })?;
+        let len = self.index().await?.len;
+        let mut waited = Duration::ZERO;
+        while waited < timeout {
+            let interval = self.poll_interval.min(timeout - waited);
+            timer.sleep(interval).await;
+            waited += interval;
+
+            let grown = !self
+                .files
+                .read_range(&self.path, len..len + 1)
+                .await?
+                .is_empty();
+            if grown {
+                let index = self.build_index().await?;
+                return Ok(vec![FolderEvent::NewMessages {
+                    folder_id: folder_id.clone(),
+                    exists: index.messages.len().try_into().unwrap_or(u32::MAX),
+                }]);
+            }
+        }
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use tokio::io::DuplexStream;
+
+    use super::*;
+
+    /// Files in memory, read through the default `read_range`.
+    #[derive(Default)]
+    struct MemoryFiles(Mutex<HashMap<String, Vec<u8>>>);
+
+    #[async_trait]
+    impl FileSystem for MemoryFiles {
+        async fn read(&self, path: &str) -> MailinerResult<Vec<u8>> {
+            self.0
+                .lock()
+                .unwrap()
+                .get(path)
+                .cloned()
+                .ok_or_else(|| MailinerError::NotFound(path.to_string()))
+        }
+
+        async fn write(&self, path: &str, data: &[u8]) -> MailinerResult<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(path.to_string(), data.to_vec());
+            Ok(())
+        }
+
+        async fn remove(&self, path: &str) -> MailinerResult<()> {
+            self.0.lock().unwrap().remove(path);
+            Ok(())
+        }
+
+        async fn exists(&self, path: &str) -> MailinerResult<bool> {
+            Ok(self.0.lock().unwrap().contains_key(path))
+        }
+
+        async fn list(&self, _path: &str) -> MailinerResult<Vec<String>> {
+            Ok(self.0.lock().unwrap().keys().cloned().collect())
+        }
+    }
+
+    const ARCHIVE: &[u8] = b"From 1780@xxx Mon Jan 01 00:00:00 +0000 2024\n\
+        X-Gmail-Labels: Inbox,Unread\n\
+        From: jan@example.com\n\
+        Subject: First\n\
+        Date: Mon, 1 Jan 2024 00:00:00 +0000\n\
+        \n\
+        >From the archive\n\
+        \n\
+        From 1781@xxx Tue Jan 02 00:00:00 +0000 2024\n\
+        X-Gmail-Labels: Inbox\n\
+        From: ana@example.com\n\
+        Subject: Second\n\
+        Date: Tue, 2 Jan 2024 00:00:00 +0000\n\
+        \n\
+        Hello\n";
+
+    #[tokio::test]
+    async fn browses_the_archive() {
+        let files = Arc::new(MemoryFiles::default());
+        files.write("Takeout/All mail.mbox", ARCHIVE).await.unwrap();
+        let connector = MboxConnector::new(files.clone(), "Takeout/All mail.mbox");
+        let connector: &dyn EmailConnector<DuplexStream> = &connector;
+        let account = connector.authenticate("").await.unwrap();
+        let folders = connector.list_folders(&account.id).await.unwrap();
+        let folder_id = &folders[0].id;
+        assert_eq!(folder_id.as_str(), "All mail");
+
+        let delta = connector.sync_folder(folder_id, None, &[]).await.unwrap();
+        assert!(delta.read_only);
+        let subjects: Vec<_> = delta
+            .changed
+            .iter()
+            .map(|envelope| envelope.subject.as_deref().unwrap())
+            .collect();
+        assert_eq!(subjects, ["First", "Second"]);
+        assert!(!delta.changed[0].is_read && delta.changed[1].is_read);
+
+        let newest = connector
+            .list_envelopes_range(folder_id, 0..1)
+            .await
+            .unwrap();
+        assert_eq!(newest[0].subject.as_deref(), Some("Second"));
+
+        let first = &delta.changed[0].id;
+        let part = connector
+            .get_message_part(first, &MessagePartId::new("1"))
+            .await
+            .unwrap();
+        assert!(
+            matches!(part.content, MessageContent::Text(text) if text.trim() == "From the archive")
+        );
+        let query = SearchQuery {
+            body: Some("hello".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            connector.search(folder_id, &query).await.unwrap(),
+            [delta.changed[1].id.clone()]
+        );
+        assert!(matches!(
+            connector.delete_message(first).await,
+            Err(MailinerError::Rejected(_))
+        ));
+
+        // Appended mail shows up on the next sync.
+        let known: Vec<MessageId> = delta.changed.iter().map(|e| e.id.clone()).collect();
+        let mut grown = ARCHIVE.to_vec();
+        grown.extend_from_slice(b"From x Wed Jan 03 00:00:00 2024\nSubject: Third\n\nBye\n");
+        files.write("Takeout/All mail.mbox", &grown).await.unwrap();
+        let delta = connector
+            .sync_folder(folder_id, None, &known)
+            .await
+            .unwrap();
+        assert_eq!(delta.changed.len(), 1);
+        assert_eq!(delta.changed[0].subject.as_deref(), Some("Third"));
+        assert!(delta.vanished.is_empty());
+    }
+
+    #[tokio::test]
+    async fn opens_backend_uris() {
+        let files = Arc::new(MemoryFiles::default());
+        files.write("All mail.mbox", ARCHIVE).await.unwrap();
+        let opened = Arc::new(Mutex::new(Vec::new()));
+        let mut registry = ConnectorRegistry::<DuplexStream>::new();
+        register(&mut registry, {
+            let opened = opened.clone();
+            move |dir: &str| -> MailinerResult<Arc<dyn FileSystem>> {
+                opened.lock().unwrap().push(dir.to_string());
+                Ok(files.clone())
+            }
+        });
+
+        let connector = registry
+            .create("mbox:///home/me/Takeout/All%20mail.mbox")
+            .unwrap();
+        let account = connector.authenticate("").await.unwrap();
+        let folders = connector.list_folders(&account.id).await.unwrap();
+        assert_eq!(folders[0].id.as_str(), "All mail");
+        assert_eq!(*opened.lock().unwrap(), ["/home/me/Takeout"]);
+        assert!(registry.create("mbox:///home/me/").is_err());
+    }
+}
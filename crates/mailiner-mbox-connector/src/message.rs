@@ -0,0 +1,126 @@
+//! Envelopes of the messages in an mbox file, with the flags and labels
+//! from the headers mail clients and exports write.
+
+use std::collections::BTreeSet;
+
+use mail_parser::{Message, MimeHeaders};
+use mailiner_core::mime;
+use mailiner_core::{AccountId, Envelope, FlagUpdate, FolderId, MessageId};
+
+/// The labels of messages in a Google Takeout export.
+const GMAIL_LABELS: &str = "X-Gmail-Labels";
+
+fn header<'a>(message: &'a Message<'_>, name: &'static str) -> Option<&'a str> {
+    message.header(name).and_then(|value| value.as_text())
+}
+
+/// The flags of a message, from the `Status` and `X-Status` headers that
+/// mutt, Pine and Thunderbird write (`R` for read, `F` flagged, `D`
+/// deleted, `T` draft), or the labels of a Takeout export. Keywords are in
+/// `X-Keywords`, separated by spaces or commas.
+pub(crate) fn flag_update(message: &Message<'_>, message_id: MessageId) -> FlagUpdate {
+    let status = header(message, "Status").unwrap_or_default();
+    let x_status = header(message, "X-Status").unwrap_or_default();
+    let labels = labels(message);
+    let (is_read, is_flagged) = if header(message, GMAIL_LABELS).is_some() {
+        (!labels.contains("Unread"), labels.contains("Starred"))
+    } else {
+        (status.contains('R'), x_status.contains('F'))
+    };
+    FlagUpdate {
+        message_id,
+        is_read,
+        is_starred: is_flagged,
+        is_flagged,
+        is_draft: x_status.contains('T') || labels.contains("Drafts"),
+        is_deleted: x_status.contains('D'),
+        keywords: header(message, "X-Keywords")
+            .map(|keywords| {
+                keywords
+                    .split([' ', ','])
+                    .filter(|keyword| !keyword.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    }
+}
+
+fn labels(message: &Message<'_>) -> BTreeSet<String> {
+    header(message, GMAIL_LABELS)
+        .map(|labels| {
+            labels
+                .split(',')
+                .map(str::trim)
+                .filter(|label| !label.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Builds the envelope from the headers of a message. Without the body, a
+/// `multipart/mixed` message is taken to have attachments.
+pub(crate) fn envelope(
+    message: &Message<'_>,
+    id: MessageId,
+    account_id: &AccountId,
+    folder_id: &FolderId,
+) -> Envelope {
+    let flags = flag_update(message, id.clone());
+    Envelope {
+        is_read: flags.is_read,
+        is_starred: flags.is_starred,
+        is_flagged: flags.is_flagged,
+        is_draft: flags.is_draft,
+        is_deleted: flags.is_deleted,
+        has_attachments: message.attachment_count() > 0
+            || message.is_content_type("multipart", "mixed"),
+        keywords: flags.keywords,
+        labels: labels(message),
+        thread_id: header(message, "X-GM-THRID").map(str::to_string),
+        ..mime::envelope(message, id, account_id, folder_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mail_parser::MessageParser;
+
+    use super::*;
+
+    #[test]
+    fn flags_from_headers() {
+        let headers = b"From: jan@example.com\r\n\
+            Subject: Hi\r\n\
+            Status: RO\r\n\
+            X-Status: F\r\n\
+            X-Keywords: $Forwarded, Work\r\n\
+            Content-Type: multipart/mixed; boundary=x\r\n\r\n";
+        let message = MessageParser::new().parse_headers(&headers[..]).unwrap();
+        let envelope = envelope(
+            &message,
+            MessageId::new("archive;OFFSET=0"),
+            &AccountId::new("mbox-archive"),
+            &FolderId::new("archive"),
+        );
+        assert!(envelope.is_read && envelope.is_flagged && !envelope.is_deleted);
+        assert!(envelope.has_attachments);
+        assert_eq!(
+            envelope.keywords,
+            BTreeSet::from(["$Forwarded", "Work"].map(String::from))
+        );
+        assert_eq!(envelope.subject.as_deref(), Some("Hi"));
+
+        let takeout = b"X-GM-THRID: 1780\r\n\
+            X-Gmail-Labels: Inbox,Starred,Unread,Work\r\n\r\n";
+        let message = MessageParser::new().parse_headers(&takeout[..]).unwrap();
+        let flags = flag_update(&message, MessageId::new("archive;OFFSET=0"));
+        assert!(!flags.is_read && flags.is_starred);
+        assert_eq!(
+            labels(&message),
+            BTreeSet::from(["Inbox", "Starred", "Unread", "Work"].map(String::from))
+        );
+        assert_eq!(header(&message, "X-GM-THRID"), Some("1780"));
+    }
+}
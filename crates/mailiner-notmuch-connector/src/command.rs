@@ -0,0 +1,70 @@
+//! Running the `notmuch` command line tool, which has a stable JSON output
+//! and keeps the database locking and Xapian details out of the connector.
+
+use std::path::PathBuf;
+use std::process::Stdio;
+
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::NotmuchError;
+
+/// Runs notmuch with arguments, optionally piping `input` to it, and
+/// returns what it printed.
+#[async_trait]
+pub(crate) trait Runner: Send + Sync {
+    async fn run(&self, args: Vec<String>, input: Option<Vec<u8>>)
+        -> Result<Vec<u8>, NotmuchError>;
+}
+
+pub(crate) struct NotmuchCommand {
+    pub(crate) program: PathBuf,
+    /// The configuration file, instead of the one notmuch finds itself.
+    pub(crate) config: Option<PathBuf>,
+}
+
+#[async_trait]
+impl Runner for NotmuchCommand {
+    async fn run(
+        &self,
+        args: Vec<String>,
+        input: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, NotmuchError> {
+        let mut command = Command::new(&self.program);
+        if let Some(config) = &self.config {
+            command.arg(format!("--config={}", config.display()));
+        }
+        let mut child = command
+            .args(&args)
+            .stdin(if input.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                NotmuchError::Command(format!("Failed to run {}: {}", self.program.display(), e))
+            })?;
+
+        let mut written = Ok(());
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            written = stdin.write_all(&input).await;
+        }
+        let output = child
+            .wait_with_output()
+            .await
+            .map_err(|e| NotmuchError::Command(format!("Failed to run notmuch: {}", e)))?;
+        if !output.status.success() {
+            return Err(NotmuchError::Command(format!(
+                "notmuch {} failed: {}",
+                args.first().map_or("", String::as_str),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        written.map_err(|e| NotmuchError::Command(format!("Failed to write to notmuch: {}", e)))?;
+        Ok(output.stdout)
+    }
+}
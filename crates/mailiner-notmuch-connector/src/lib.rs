@@ -0,0 +1,891 @@
+//! Access to a notmuch database through the `notmuch` command line tool,
+//! for people who keep their mail in a local store indexed by notmuch.
+//!
+//! notmuch has tags instead of folders, so every tag is shown as a folder
+//! and the INBOX is the `inbox` tag. Tags are also the labels and keywords
+//! of messages, and searches take notmuch queries as their raw query.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use mail_parser::MessageParser;
+use mailiner_core::platform::Timer;
+use mailiner_core::{
+    Account, AccountId, BackendUri, ConnectorRegistry, EmailConnector, Envelope, FlagUpdate,
+    Folder, FolderDelta, FolderEvent, FolderId, FolderSyncState, Label, LabelKind, MailinerError,
+    MessageContent, MessageId, MessagePart, MessagePartId, MessageStructure,
+    Result as MailinerResult, SearchQuery,
+};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::info;
+
+use crate::command::{NotmuchCommand, Runner};
+use crate::query::{folder_query, folder_tag, message_query};
+use crate::show::{ShowMessage, DELETED, DRAFT, FLAGGED, UNREAD};
+
+mod command;
+mod query;
+mod show;
+
+pub(crate) const INBOX: &str = "INBOX";
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Whether a tag is a message state, e.g. `unread`, rather than something
+/// the user tagged messages with.
+fn is_state(tag: &str) -> bool {
+    show::AUTOMATIC_TAGS.contains(&tag) || [FLAGGED, DRAFT, DELETED].contains(&tag)
+}
+
+/// Builds the id of a message as seen in a folder, e.g.
+/// `work;ID=1234@example.com`.
+pub(crate) fn to_message_id(folder_id: &FolderId, id: &str) -> MessageId {
+    MessageId::new(format!("{};ID={}", folder_id.as_str(), id))
+}
+
+/// Splits a message id into the folder and the Message-ID.
+fn parse_message_id(message_id: &MessageId) -> Result<(FolderId, String), NotmuchError> {
+    message_id
+        .as_str()
+        .split_once(";ID=")
+        .filter(|(_, id)| !id.is_empty())
+        .map(|(folder, id)| (FolderId::new(folder), id.to_string()))
+        .ok_or_else(|| NotmuchError::InvalidData(format!("Invalid message id: {}", message_id)))
+}
+
+/// A number that changes when the database is rebuilt, after which its
+/// revisions start over.
+fn uid_validity(uuid: &str) -> u32 {
+    // FNV-1a, stable across builds unlike the standard hasher.
+    uuid.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[derive(Error, Debug)]
+pub enum NotmuchError {
+    /// notmuch couldn't be run or failed.
+    #[error("notmuch error: {0}")]
+    Command(String),
+    #[error("Invalid data: {0}")]
+    InvalidData(String),
+    #[error("Not found: {0}")]
+    NotFound(String),
+}
+
+impl From<NotmuchError> for MailinerError {
+    fn from(err: NotmuchError) -> Self {
+        match err {
+            NotmuchError::Command(msg) => MailinerError::Connector(msg),
+            NotmuchError::InvalidData(msg) => MailinerError::InvalidData(msg),
+            NotmuchError::NotFound(msg) => MailinerError::NotFound(msg),
+        }
+    }
+}
+
+pub struct NotmuchConnector {
+    runner: Arc<dyn Runner>,
+    /// Waits between the checks of `idle` for changes to the database.
+    timer: Option<Arc<dyn Timer>>,
+    poll_interval: Duration,
+}
+
+impl Default for NotmuchConnector {
+    fn default() -> Self {
+        Self::new("notmuch")
+    }
+}
+
+impl NotmuchConnector {
+    /// Uses the notmuch at `program`, with the configuration notmuch finds
+    /// itself, e.g. through `NOTMUCH_CONFIG`.
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self::with_runner(Arc::new(NotmuchCommand {
+            program: program.into(),
+            config: None,
+        }))
+    }
+
+    /// Uses the notmuch configuration file at `config`.
+    pub fn with_config(program: impl Into<PathBuf>, config: impl Into<PathBuf>) -> Self {
+        Self::with_runner(Arc::new(NotmuchCommand {
+            program: program.into(),
+            config: Some(config.into()),
+        }))
+    }
+
+    pub(crate) fn with_runner(runner: Arc<dyn Runner>) -> Self {
+        Self {
+            runner,
+            timer: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+
+    /// Sets the timer `idle` waits with between checking for changes, every
+    /// `poll_interval`.
+    pub fn with_timer(mut self, timer: Arc<dyn Timer>, poll_interval: Duration) -> Self {
+        self.timer = Some(timer);
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    async fn run(&self, args: &[&str]) -> Result<Vec<u8>, NotmuchError> {
+        self.runner
+            .run(args.iter().map(|arg| arg.to_string()).collect(), None)
+            .await
+    }
+
+    async fn account_id(&self) -> MailinerResult<AccountId> {
+        Ok(AccountId::new(format!(
+            "notmuch-{}",
+            self.primary_email().await?
+        )))
+    }
+
+    async fn primary_email(&self) -> MailinerResult<String> {
+        let output = self.run(&["config", "get", "user.primary_email"]).await?;
+        Ok(String::from_utf8_lossy(&output).trim().to_string())
+    }
+
+    /// The ids of the messages matching `query`, newest first.
+    async fn search_ids(
+        &self,
+        query: &str,
+        range: Option<Range<usize>>,
+    ) -> MailinerResult<Vec<String>> {
+        let mut args = vec![
+            "search".to_string(),
+            "--format=json".to_string(),
+            "--output=messages".to_string(),
+            "--sort=newest-first".to_string(),
+        ];
+        if let Some(range) = range {
+            args.push(format!("--offset={}", range.start));
+            args.push(format!("--limit={}", range.end.saturating_sub(range.start)));
+        }
+        args.push(query.to_string());
+        let output = self.runner.run(args, None).await?;
+        Ok(serde_json::from_slice(&output)?)
+    }
+
+    /// Shows the messages matching `query`, with or without their MIME tree.
+    async fn show(&self, query: &str, body: bool) -> MailinerResult<Vec<ShowMessage>> {
+        let body = format!("--body={}", body);
+        let output = self
+            .run(&[
+                "show",
+                "--format=json",
+                "--entire-thread=false",
+                "--include-html",
+                &body,
+                query,
+            ])
+            .await?;
+        Ok(show::parse_messages(&output)?)
+    }
+
+    async fn show_one(&self, id: &str, body: bool) -> MailinerResult<ShowMessage> {
+        self.show(&message_query(id), body)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| NotmuchError::NotFound(format!("No message {}", id)).into())
+    }
+
+    /// The envelopes of messages, in the order of `ids`. A few hundred ids
+    /// are shown at a time to keep the command line short.
+    async fn envelopes(
+        &self,
+        folder_id: &FolderId,
+        ids: &[String],
+    ) -> MailinerResult<Vec<Envelope>> {
+        let account_id = self.account_id().await?;
+        let mut messages = HashMap::new();
+        for batch in ids.chunks(200) {
+            let query = batch
+                .iter()
+                .map(|id| message_query(id))
+                .collect::<Vec<_>>()
+                .join(" or ");
+            for message in self.show(&query, false).await? {
+                messages.insert(message.id.clone(), message);
+            }
+        }
+        Ok(ids
+            .iter()
+            .filter_map(|id| messages.get(id))
+            .map(|message| {
+                show::envelope(
+                    message,
+                    to_message_id(folder_id, &message.id),
+                    &account_id,
+                    folder_id,
+                )
+            })
+            .collect())
+    }
+
+    /// Adds and removes tags of the messages matching `query`.
+    /// All tags in the database.
+    async fn tags(&self) -> MailinerResult<Vec<String>> {
+        let output = self
+            .run(&["search", "--format=json", "--output=tags", "*"])
+            .await?;
+        Ok(serde_json::from_slice(&output)?)
+    }
+
+    async fn tag(&self, query: &str, add: &[&str], remove: &[&str]) -> MailinerResult<()> {
+        let mut args = vec!["tag".to_string()];
+        args.extend(add.iter().map(|tag| format!("+{}", tag)));
+        args.extend(remove.iter().map(|tag| format!("-{}", tag)));
+        args.push("--".to_string());
+        args.push(query.to_string());
+        self.runner.run(args, None).await?;
+        Ok(())
+    }
+
+    /// The revision of the database and its UUID (`notmuch count --lastmod`
+    /// prints `<count> <uuid> <revision>`).
+    async fn revision(&self) -> MailinerResult<(String, u64)> {
+        let output = self.run(&["count", "--lastmod", "*"]).await?;
+        let output = String::from_utf8_lossy(&output);
+        let mut fields = output.split_whitespace().skip(1);
+        match (fields.next(), fields.next().and_then(|r| r.parse().ok())) {
+            (Some(uuid), Some(revision)) => Ok((uuid.to_string(), revision)),
+            _ => Err(NotmuchError::InvalidData(format!(
+                "Invalid output of notmuch count: {}",
+                output.trim()
+            ))
+            .into()),
+        }
+    }
+
+    async fn count(&self, query: &str) -> MailinerResult<u64> {
+        let output = self.run(&["count", query]).await?;
+        String::from_utf8_lossy(&output)
+            .trim()
+            .parse()
+            .map_err(|_| {
+                NotmuchError::InvalidData("Invalid output of notmuch count".to_string()).into()
+            })
+    }
+}
+
+/// The tag for one of the flags of `update_envelope_flags`, and whether
+/// setting the flag removes the tag.
+fn flag_tag(flag: &str) -> Option<(&'static str, bool)> {
+    match flag {
+        "is_read" => Some((UNREAD, true)),
+        "is_flagged" | "is_starred" => Some((FLAGGED, false)),
+        "is_draft" => Some((DRAFT, false)),
+        "is_deleted" => Some((DELETED, false)),
+        _ => None,
+    }
+}
+
+/// Registers the `notmuch` scheme. `notmuch://` uses the configuration
+/// notmuch finds itself, `notmuch:///home/me/.notmuch-config` the file at
+/// the path.
+pub fn register<S>(registry: &mut ConnectorRegistry<S>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync + 'static,
+{
+    registry.register(
+        "notmuch",
+        |uri: &BackendUri| -> MailinerResult<Arc<dyn EmailConnector<S>>> {
+            Ok(Arc::new(match uri.path.as_str() {
+                "" | "/" => NotmuchConnector::default(),
+                config => NotmuchConnector::with_config("notmuch", config),
+            }))
+        },
+    );
+}
+
+#[async_trait]
+impl<S> EmailConnector<S> for NotmuchConnector
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync + 'static,
+{
+    async fn connect(&self, _stream: S) -> MailinerResult<()> {
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> MailinerResult<()> {
+        Ok(())
+    }
+
+    /// There is nothing to log in to, `credentials` are ignored. The account
+    /// is the primary address in the notmuch configuration.
+    async fn authenticate(&self, _credentials: &str) -> MailinerResult<Account> {
+        let email = self.primary_email().await?;
+        Ok(Account {
+            id: AccountId::new(format!("notmuch-{}", email)),
+            name: email.clone(),
+            email,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        })
+    }
+
+    /// Every tag is a folder, except the ones that are message states.
+    async fn list_folders(&self, account_id: &AccountId) -> MailinerResult<Vec<Folder>> {
+        let mut ids: Vec<String> = self
+            .tags()
+            .await?
+            .into_iter()
+            .filter(|tag| !is_state(tag))
+            .map(|tag| {
+                if tag == "inbox" {
+                    INBOX.to_string()
+                } else {
+                    tag
+                }
+            })
+            .collect();
+        if !ids.iter().any(|id| id == INBOX) {
+            ids.push(INBOX.to_string());
+        }
+        ids.sort();
+        Ok(ids
+            .into_iter()
+            .map(|id| Folder {
+                name: id.clone(),
+                id: FolderId::new(id),
+                account_id: account_id.clone(),
+                parent_id: None,
+                rights: None,
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            })
+            .collect())
+    }
+
+    /// Tags exist as long as a message has them, so the folder only shows
+    /// up in `list_folders` once something is moved or copied to it.
+    async fn create_folder(
+        &self,
+        account_id: &AccountId,
+        name: &str,
+        parent_id: Option<&FolderId>,
+    ) -> MailinerResult<Folder> {
+        if name.is_empty() || name.contains(char::is_whitespace) || parent_id.is_some() {
+            return Err(NotmuchError::InvalidData(
+                "Tags can't be nested or contain spaces".to_string(),
+            )
+            .into());
+        }
+        Ok(Folder {
+            id: FolderId::new(name),
+            account_id: account_id.clone(),
+            name: name.to_string(),
+            parent_id: None,
+            rights: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        })
+    }
+
+    /// Removes the tag from all messages, the messages themselves stay.
+    async fn delete_folder(&self, folder_id: &FolderId) -> MailinerResult<()> {
+        self.tag(&folder_query(folder_id), &[], &[folder_tag(folder_id)])
+            .await
+    }
+
+    async fn rename_folder(
+        &self,
+        folder_id: &FolderId,
+        new_name: &str,
+    ) -> MailinerResult<FolderId> {
+        if folder_id.as_str() == INBOX {
+            return Err(NotmuchError::InvalidData("The INBOX can't be renamed".to_string()).into());
+        }
+        if new_name.is_empty() || new_name.contains(char::is_whitespace) {
+            return Err(NotmuchError::InvalidData("Tags can't contain spaces".to_string()).into());
+        }
+        self.tag(
+            &folder_query(folder_id),
+            &[new_name],
+            &[folder_tag(folder_id)],
+        )
+        .await?;
+        Ok(FolderId::new(new_name))
+    }
+
+    async fn list_envelopes(&self, folder_id: &FolderId) -> MailinerResult<Vec<Envelope>> {
+        let ids = self.search_ids(&folder_query(folder_id), None).await?;
+        self.envelopes(folder_id, &ids).await
+    }
+
+    async fn list_envelopes_range(
+        &self,
+        folder_id: &FolderId,
+        range: Range<usize>,
+    ) -> MailinerResult<Vec<Envelope>> {
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let ids = self
+            .search_ids(&folder_query(folder_id), Some(range))
+            .await?;
+        self.envelopes(folder_id, &ids).await
+    }
+
+    async fn get_envelope(&self, message_id: &MessageId) -> MailinerResult<Envelope> {
+        let (folder_id, id) = parse_message_id(message_id)?;
+        let message = self.show_one(&id, false).await?;
+        Ok(show::envelope(
+            &message,
+            message_id.clone(),
+            &self.account_id().await?,
+            &folder_id,
+        ))
+    }
+
+    /// The database revision is the modseq, so only messages changed since
+    /// the last sync are shown again.
+    async fn sync_folder(
+        &self,
+        folder_id: &FolderId,
+        state: Option<&FolderSyncState>,
+        known: &[MessageId],
+    ) -> MailinerResult<FolderDelta> {
+        // Taken first, so that changes made meanwhile are synced again next
+        // time rather than missed.
+        let (uuid, revision) = self.revision().await?;
+        let sync_state = FolderSyncState {
+            uid_validity: uid_validity(&uuid),
+            highest_modseq: revision,
+        };
+        let query = folder_query(folder_id);
+        let ids = self.search_ids(&query, None).await?;
+        let known: HashSet<&MessageId> = known.iter().collect();
+        let mut changed: Vec<String> =
+            match state.filter(|state| state.uid_validity == sync_state.uid_validity) {
+                Some(state) => {
+                    let modified = format!("{} and lastmod:{}..", query, state.highest_modseq + 1);
+                    self.search_ids(&modified, None).await?
+                }
+                None => Vec::new(),
+            };
+        let previously_changed: HashSet<String> = changed.iter().cloned().collect();
+        changed.extend(
+            ids.iter()
+                .filter(|id| {
+                    let all =
+                        state.is_none_or(|state| state.uid_validity != sync_state.uid_validity);
+                    (all || !known.contains(&to_message_id(folder_id, id)))
+                        && !previously_changed.contains(*id)
+                })
+                .cloned(),
+        );
+
+        let current: HashSet<MessageId> =
+            ids.iter().map(|id| to_message_id(folder_id, id)).collect();
+        let vanished = known
+            .into_iter()
+            .filter(|id| !current.contains(*id))
+            .cloned()
+            .collect();
+        let changed = self.envelopes(folder_id, &changed).await?;
+        info!("Synced {}: {} changed", folder_id, changed.len());
+        Ok(FolderDelta {
+            sync_state: Some(sync_state),
+            changed,
+            vanished,
+            read_only: false,
+        })
+    }
+
+    async fn resync_flags(
+        &self,
+        folder_id: &FolderId,
+        since_modseq: u64,
+    ) -> MailinerResult<Vec<FlagUpdate>> {
+        let query = format!(
+            "{} and lastmod:{}..",
+            folder_query(folder_id),
+            since_modseq + 1
+        );
+        Ok(self
+            .show(&query, false)
+            .await?
+            .iter()
+            .map(|message| show::flag_update(message, to_message_id(folder_id, &message.id)))
+            .collect())
+    }
+
+    async fn update_envelope_flags(
+        &self,
+        message_id: &MessageId,
+        flags: &[(&str, bool)],
+    ) -> MailinerResult<()> {
+        let (_, id) = parse_message_id(message_id)?;
+        let mut add = Vec::new();
+        let mut remove = Vec::new();
+        for (flag, value) in flags {
+            let (tag, inverted) = flag_tag(flag)
+                .ok_or_else(|| NotmuchError::InvalidData(format!("Unknown flag: {}", flag)))?;
+            if *value != inverted {
+                add.push(tag);
+            } else {
+                remove.push(tag);
+            }
+        }
+        self.tag(&message_query(&id), &add, &remove).await
+    }
+
+    async fn update_envelope_keywords(
+        &self,
+        message_id: &MessageId,
+        keywords: &[(&str, bool)],
+    ) -> MailinerResult<()> {
+        let (_, id) = parse_message_id(message_id)?;
+        let add: Vec<&str> = keywords
+            .iter()
+            .filter(|(_, value)| *value)
+            .map(|(keyword, _)| *keyword)
+            .collect();
+        let remove: Vec<&str> = keywords
+            .iter()
+            .filter(|(_, value)| !*value)
+            .map(|(keyword, _)| *keyword)
+            .collect();
+        self.tag(&message_query(&id), &add, &remove).await
+    }
+
+    /// Every tag is a label, the states and `inbox` are system labels.
+    async fn list_labels(&self, account_id: &AccountId) -> MailinerResult<Vec<Label>> {
+        Ok(self
+            .tags()
+            .await?
+            .into_iter()
+            .map(|tag| Label {
+                account_id: account_id.clone(),
+                kind: if is_state(&tag) || tag == "inbox" {
+                    LabelKind::System
+                } else {
+                    LabelKind::User
+                },
+                name: tag,
+                color: None,
+            })
+            .collect())
+    }
+
+    async fn move_message(
+        &self,
+        message_id: &MessageId,
+        target_folder_id: &FolderId,
+    ) -> MailinerResult<Option<MessageId>> {
+        let (folder_id, id) = parse_message_id(message_id)?;
+        if &folder_id != target_folder_id {
+            self.tag(
+                &message_query(&id),
+                &[folder_tag(target_folder_id)],
+                &[folder_tag(&folder_id)],
+            )
+            .await?;
+        }
+        Ok(Some(to_message_id(target_folder_id, &id)))
+    }
+
+    async fn copy_message(
+        &self,
+        message_id: &MessageId,
+        target_folder_id: &FolderId,
+    ) -> MailinerResult<Option<MessageId>> {
+        let (_, id) = parse_message_id(message_id)?;
+        self.tag(&message_query(&id), &[folder_tag(target_folder_id)], &[])
+            .await?;
+        Ok(Some(to_message_id(target_folder_id, &id)))
+    }
+
+    /// Delivers the message with `notmuch insert`, into the folder's tag
+    /// rather than the INBOX.
+    async fn append_message(
+        &self,
+        folder_id: &FolderId,
+        flags: &[&str],
+        content: &[u8],
+    ) -> MailinerResult<Option<MessageId>> {
+        let tag = folder_tag(folder_id);
+        let mut args = vec!["insert".to_string(), format!("+{}", tag)];
+        if tag != "inbox" {
+            args.push("-inbox".to_string());
+        }
+        for flag in flags {
+            match flag.to_ascii_lowercase().as_str() {
+                "\\seen" => args.push(format!("-{}", UNREAD)),
+                "\\flagged" => args.push(format!("+{}", FLAGGED)),
+                "\\draft" => args.push(format!("+{}", DRAFT)),
+                "\\deleted" => args.push(format!("+{}", DELETED)),
+                "\\answered" => args.push("+replied".to_string()),
+                _ => {}
+            }
+        }
+        self.runner.run(args, Some(content.to_vec())).await?;
+        let id = MessageParser::new()
+            .parse_headers(content)
+            .and_then(|message| message.message_id().map(str::to_string));
+        Ok(id.map(|id| to_message_id(folder_id, &id)))
+    }
+
+    /// notmuch doesn't delete files, so the message gets the `deleted` tag
+    /// and leaves the folder.
+    async fn delete_message(&self, message_id: &MessageId) -> MailinerResult<()> {
+        let (folder_id, id) = parse_message_id(message_id)?;
+        self.tag(&message_query(&id), &[DELETED], &[folder_tag(&folder_id)])
+            .await
+    }
+
+    /// `query.raw` takes any notmuch query, e.g.
+    /// `folder:Archive and date:1y..`.
+    async fn search(
+        &self,
+        folder_id: &FolderId,
+        query: &SearchQuery,
+    ) -> MailinerResult<Vec<MessageId>> {
+        let ids = self
+            .search_ids(&query::search_query(folder_id, query), None)
+            .await?;
+        Ok(ids.iter().map(|id| to_message_id(folder_id, id)).collect())
+    }
+
+    async fn get_message_structure(
+        &self,
+        message_id: &MessageId,
+    ) -> MailinerResult<MessageStructure> {
+        let (_, id) = parse_message_id(message_id)?;
+        Ok(show::structure(&self.show_one(&id, true).await?.body))
+    }
+
+    async fn fetch_raw_message(&self, message_id: &MessageId) -> MailinerResult<Vec<u8>> {
+        let (_, id) = parse_message_id(message_id)?;
+        Ok(self
+            .run(&["show", "--format=raw", &message_query(&id)])
+            .await?)
+    }
+
+    /// Text parts come from the JSON output, which notmuch converts to
+    /// UTF-8. Other parts are fetched raw, with the transfer encoding
+    /// decoded.
+    async fn get_message_part(
+        &self,
+        message_id: &MessageId,
+        part_id: &MessagePartId,
+    ) -> MailinerResult<MessagePart> {
+        let (_, id) = parse_message_id(message_id)?;
+        let part_arg = format!("--part={}", part_id);
+        let query = message_query(&id);
+        let output = self
+            .run(&["show", "--format=json", "--include-html", &part_arg, &query])
+            .await?;
+        let part: show::Part = serde_json::from_slice(&output).map_err(|_| {
+            NotmuchError::NotFound(format!("No part {} in {}", part_id, message_id))
+        })?;
+        let content = match show::text_content(&part) {
+            Some(content) => content,
+            None => MessageContent::Binary(
+                self.run(&["show", "--format=raw", &part_arg, &query])
+                    .await?,
+            ),
+        };
+        let structure = show::structure(std::slice::from_ref(&part));
+        let size = match &content {
+            MessageContent::Text(text) | MessageContent::Html(text) => text.len(),
+            MessageContent::Binary(data) => data.len(),
+        };
+        Ok(MessagePart {
+            id: part_id.clone(),
+            envelope_id: message_id.clone(),
+            content_type: structure.content_type,
+            filename: structure.filename,
+            size: size as u64,
+            is_attachment: structure.is_attachment,
+            content,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        })
+    }
+
+    /// Polls the database revision. notmuch has no sequence numbers, so
+    /// events carry 0 instead, and a change that doesn't alter the number
+    /// of messages in the folder is reported as a flag change.
+    async fn idle(
+        &self,
+        folder_id: &FolderId,
+        timeout: Duration,
+    ) -> MailinerResult<Vec<FolderEvent>> {
+        let timer = self.timer.clone().ok_or_else(|| {
+            NotmuchError::InvalidData("Watching a folder needs a timer, see with_timer".to_string())
+        })?;
+        let query = folder_query(folder_id);
+        let (_, mut revision) = self.revision().await?;
+        let count = self.count(&query).await?;
+        let mut waited = Duration::ZERO;
+        while waited < timeout {
+            let interval = self.poll_interval.min(timeout - waited);
+            timer.sleep(interval).await;
+            waited += interval;
+
+            let (_, now) = self.revision().await?;
+            if now == revision {
+                continue;
+            }
+            let changed = self
+                .count(&format!("{} and lastmod:{}..", query, revision + 1))
+                .await?;
+            let now_count = self.count(&query).await?;
+            revision = now;
+            let event = if now_count > count {
+                FolderEvent::NewMessages {
+                    folder_id: folder_id.clone(),
+                    exists: now_count.try_into().unwrap_or(u32::MAX),
+                }
+            } else if now_count < count {
+                FolderEvent::MessageExpunged {
+                    folder_id: folder_id.clone(),
+                    sequence: 0,
+                }
+            } else if changed > 0 {
+                FolderEvent::FlagsChanged {
+                    folder_id: folder_id.clone(),
+                    sequence: 0,
+                }
+            } else {
+                continue;
+            };
+            return Ok(vec![event]);
+        }
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::io::DuplexStream;
+
+    use super::*;
+
+    /// Answers commands with the output of the first prefix they start
+    /// with, and records them.
+    #[derive(Default)]
+    struct FakeNotmuch {
+        outputs: Vec<(&'static str, &'static str)>,
+        commands: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Runner for FakeNotmuch {
+        async fn run(
+            &self,
+            args: Vec<String>,
+            _input: Option<Vec<u8>>,
+        ) -> Result<Vec<u8>, NotmuchError> {
+            let command = args.join(" ");
+            self.commands.lock().unwrap().push(command.clone());
+            let output = self
+                .outputs
+                .iter()
+                .find(|(prefix, _)| command.starts_with(*prefix))
+                .map_or("", |(_, output)| output);
+            Ok(output.as_bytes().to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn syncs_changes_since_the_revision() {
+        let notmuch = Arc::new(FakeNotmuch {
+            outputs: Vec::from([
+                ("config get", "jan@example.com\n"),
+                ("count --lastmod", "12\t0d3c9f2a-uuid\t1400\n"),
+                (
+                    "search --format=json --output=messages --sort=newest-first tag:\"inbox\" and lastmod",
+                    r#"["b@example.com"]"#,
+                ),
+                (
+                    "search --format=json --output=messages --sort=newest-first tag:\"inbox\"",
+                    r#"["c@example.com", "b@example.com", "a@example.com"]"#,
+                ),
+                (
+                    "show",
+                    r#"[[[{"id": "b@example.com", "timestamp": 1700000000, "tags": ["inbox"],
+                          "headers": {"Subject": "Read now"}}, []]],
+                        [[{"id": "c@example.com", "timestamp": 1700000100, "tags": ["inbox", "unread"],
+                          "headers": {"Subject": "New"}}, []]]]"#,
+                ),
+            ]),
+            ..Default::default()
+        });
+        let connector = NotmuchConnector::with_runner(notmuch.clone());
+        let connector: &dyn EmailConnector<DuplexStream> = &connector;
+        let inbox = FolderId::new(INBOX);
+        let state = FolderSyncState {
+            uid_validity: uid_validity("0d3c9f2a-uuid"),
+            highest_modseq: 1300,
+        };
+        let known = [
+            to_message_id(&inbox, "a@example.com"),
+            to_message_id(&inbox, "b@example.com"),
+            to_message_id(&inbox, "gone@example.com"),
+        ];
+
+        let delta = connector
+            .sync_folder(&inbox, Some(&state), &known)
+            .await
+            .unwrap();
+        assert_eq!(delta.sync_state.unwrap().highest_modseq, 1400);
+        let subjects: Vec<_> = delta
+            .changed
+            .iter()
+            .map(|envelope| envelope.subject.as_deref().unwrap())
+            .collect();
+        assert_eq!(subjects, ["Read now", "New"]);
+        assert!(delta.changed[0].is_read && !delta.changed[1].is_read);
+        assert_eq!(delta.vanished, [to_message_id(&inbox, "gone@example.com")]);
+        let commands = notmuch.commands.lock().unwrap();
+        assert!(commands
+            .iter()
+            .any(|command| command.ends_with("tag:\"inbox\" and lastmod:1301..")));
+        assert!(commands
+            .iter()
+            .any(|command| command.ends_with("id:\"b@example.com\" or id:\"c@example.com\"")));
+    }
+
+    #[tokio::test]
+    async fn flags_are_tags() {
+        let notmuch = Arc::new(FakeNotmuch::default());
+        let connector = NotmuchConnector::with_runner(notmuch.clone());
+        let connector: &dyn EmailConnector<DuplexStream> = &connector;
+        let id = MessageId::new("INBOX;ID=b@example.com");
+        connector
+            .update_envelope_flags(&id, &[("is_read", true), ("is_flagged", true)])
+            .await
+            .unwrap();
+        connector
+            .move_message(&id, &FolderId::new("archive"))
+            .await
+            .unwrap();
+        connector
+            .update_envelope_labels(&id, &[("work", true), ("todo", false)])
+            .await
+            .unwrap();
+        assert_eq!(
+            *notmuch.commands.lock().unwrap(),
+            [
+                "tag +flagged -unread -- id:\"b@example.com\"",
+                "tag +archive -inbox -- id:\"b@example.com\"",
+                "tag +work -todo -- id:\"b@example.com\"",
+            ]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! Translation of [`SearchQuery`] and folders into notmuch's query language
+//! (see notmuch-search-terms(7)).
+
+use mailiner_core::{FolderId, SearchQuery};
+
+use crate::INBOX;
+
+/// Quotes a term as a phrase, doubling the quotes in it.
+pub(crate) fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+/// The tag a folder stands for. The INBOX is the `inbox` tag that `notmuch
+/// new` adds by default.
+pub(crate) fn folder_tag(folder_id: &FolderId) -> &str {
+    if folder_id.as_str() == INBOX {
+        "inbox"
+    } else {
+        folder_id.as_str()
+    }
+}
+
+pub(crate) fn folder_query(folder_id: &FolderId) -> String {
+    format!("tag:{}", quote(folder_tag(folder_id)))
+}
+
+pub(crate) fn message_query(id: &str) -> String {
+    format!("id:{}", quote(id))
+}
+
+/// Builds the query for a search in a folder. The raw query is added as it
+/// is, in parentheses.
+pub(crate) fn search_query(folder_id: &FolderId, query: &SearchQuery) -> String {
+    let mut terms = vec![folder_query(folder_id)];
+
+    for (prefix, value) in [
+        ("from:", &query.from),
+        ("to:", &query.to),
+        ("subject:", &query.subject),
+        ("", &query.body),
+    ] {
+        if let Some(value) = value {
+            terms.push(format!("{}{}", prefix, quote(value)));
+        }
+    }
+    match (query.since, query.before) {
+        (None, None) => {}
+        (since, before) => terms.push(format!(
+            "date:{}..{}",
+            since.map(|date| date.to_string()).unwrap_or_default(),
+            // The end of a date range is inclusive.
+            before
+                .and_then(|date| date.pred_opt())
+                .map(|date| date.to_string())
+                .unwrap_or_default()
+        )),
+    }
+    for (flag, tag, inverted) in [
+        (query.is_read, "unread", true),
+        (query.is_flagged, "flagged", false),
+        (query.is_draft, "draft", false),
+        (query.is_deleted, "deleted", false),
+        // Added by `notmuch new` to messages with attachments.
+        (query.has_attachment, "attachment", false),
+    ] {
+        match flag {
+            Some(value) if value != inverted => terms.push(format!("tag:{}", tag)),
+            Some(_) => terms.push(format!("not tag:{}", tag)),
+            None => {}
+        }
+    }
+    if let Some(raw) = &query.raw {
+        terms.push(format!("({})", raw));
+    }
+    terms.join(" and ")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    #[test]
+    fn query_terms() {
+        let query = SearchQuery {
+            from: Some("Jan \"JD\" Doe".to_string()),
+            body: Some("invoice".to_string()),
+            since: NaiveDate::from_ymd_opt(2024, 3, 1),
+            before: NaiveDate::from_ymd_opt(2024, 4, 1),
+            is_read: Some(false),
+            raw: Some("tag:work or folder:Archive".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            search_query(&FolderId::new(INBOX), &query),
+            "tag:\"inbox\" and from:\"Jan \"\"JD\"\" Doe\" and \"invoice\" and \
+             date:2024-03-01..2024-03-31 and tag:unread and (tag:work or folder:Archive)"
+        );
+        assert_eq!(
+            search_query(&FolderId::new("lists"), &SearchQuery::default()),
+            "tag:\"lists\""
+        );
+    }
+}
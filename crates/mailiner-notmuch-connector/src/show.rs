@@ -0,0 +1,324 @@
+//! The JSON output of `notmuch show` (see notmuch-show(1)), and envelopes
+//! and MIME trees built from it.
+
+use std::collections::{BTreeSet, HashMap};
+
+use chrono::{DateTime, Utc};
+use mail_parser::MessageParser;
+use mailiner_core::mime;
+use mailiner_core::unsubscribe::Unsubscribe;
+use mailiner_core::{
+    AccountId, Envelope, FlagUpdate, FolderId, MessageContent, MessageId, MessagePartId,
+    MessageStructure,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::NotmuchError;
+
+pub(crate) const UNREAD: &str = "unread";
+pub(crate) const FLAGGED: &str = "flagged";
+pub(crate) const DRAFT: &str = "draft";
+pub(crate) const DELETED: &str = "deleted";
+/// Tags that `notmuch new` adds on its own, which aren't folders.
+pub(crate) const AUTOMATIC_TAGS: [&str; 6] = [
+    UNREAD,
+    "attachment",
+    "encrypted",
+    "signed",
+    "replied",
+    "passed",
+];
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ShowMessage {
+    /// The Message-ID, without angle brackets.
+    pub(crate) id: String,
+    /// Messages of the thread that don't match the query are included with
+    /// `false`.
+    #[serde(rename = "match", default = "matches")]
+    pub(crate) is_match: bool,
+    #[serde(default)]
+    pub(crate) timestamp: i64,
+    #[serde(default)]
+    pub(crate) tags: Vec<String>,
+    #[serde(default)]
+    pub(crate) headers: HashMap<String, String>,
+    /// Left out with `--body=false`.
+    #[serde(default)]
+    pub(crate) body: Vec<Part>,
+}
+
+fn matches() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Part {
+    /// Numbered depth first from 1, the number `--part` takes.
+    pub(crate) id: Value,
+    #[serde(rename = "content-type", default)]
+    pub(crate) content_type: String,
+    #[serde(rename = "content-disposition")]
+    pub(crate) content_disposition: Option<String>,
+    pub(crate) filename: Option<String>,
+    /// Without its angle brackets.
+    #[serde(rename = "content-id")]
+    pub(crate) content_id: Option<String>,
+    #[serde(rename = "content-length")]
+    pub(crate) content_length: Option<u64>,
+    /// A string for text parts, the parts of a multipart, or the
+    /// `{headers, body}` of an attached message.
+    pub(crate) content: Option<Value>,
+}
+
+impl Part {
+    pub(crate) fn part_id(&self) -> MessagePartId {
+        match &self.id {
+            Value::String(id) => MessagePartId::new(id.clone()),
+            id => MessagePartId::new(id.to_string()),
+        }
+    }
+
+    fn is_multipart(&self) -> bool {
+        self.content_type
+            .to_ascii_lowercase()
+            .starts_with("multipart/")
+    }
+
+    /// The parts of a multipart or of the body of an attached message.
+    fn children(&self) -> Vec<Part> {
+        let parts = match &self.content {
+            Some(Value::Array(parts)) if self.is_multipart() => parts.clone(),
+            Some(Value::Array(messages)) => messages
+                .iter()
+                .filter_map(|message| message.get("body"))
+                .filter_map(Value::as_array)
+                .flatten()
+                .cloned()
+                .collect(),
+            _ => Vec::new(),
+        };
+        parts
+            .into_iter()
+            .filter_map(|part| serde_json::from_value(part).ok())
+            .collect()
+    }
+}
+
+/// Collects the matching messages from the nested threads of the output.
+pub(crate) fn parse_messages(output: &[u8]) -> Result<Vec<ShowMessage>, NotmuchError> {
+    fn collect(value: Value, messages: &mut Vec<ShowMessage>) {
+        match value {
+            Value::Array(values) => values
+                .into_iter()
+                .for_each(|value| collect(value, messages)),
+            Value::Object(ref object) if object.contains_key("id") => {
+                if let Ok(message) = serde_json::from_value::<ShowMessage>(value) {
+                    if message.is_match {
+                        messages.push(message);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let value: Value = serde_json::from_slice(output)
+        .map_err(|e| NotmuchError::InvalidData(format!("Invalid notmuch output: {}", e)))?;
+    let mut messages = Vec::new();
+    collect(value, &mut messages);
+    Ok(messages)
+}
+
+pub(crate) fn flag_update(message: &ShowMessage, message_id: MessageId) -> FlagUpdate {
+    let has = |tag: &str| message.tags.iter().any(|t| t == tag);
+    FlagUpdate {
+        message_id,
+        is_read: !has(UNREAD),
+        is_starred: has(FLAGGED),
+        is_flagged: has(FLAGGED),
+        is_draft: has(DRAFT),
+        is_deleted: has(DELETED),
+        keywords: message
+            .tags
+            .iter()
+            .filter(|tag| ![UNREAD, FLAGGED, DRAFT, DELETED].contains(&tag.as_str()))
+            .cloned()
+            .collect(),
+    }
+}
+
+pub(crate) fn envelope(
+    message: &ShowMessage,
+    message_id: MessageId,
+    account_id: &AccountId,
+    folder_id: &FolderId,
+) -> Envelope {
+    // The headers are decoded already, mail-parser only splits addresses.
+    let mut headers: String = message
+        .headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}\r\n", name, value.replace(['\r', '\n'], " ")))
+        .collect();
+    headers.push_str("\r\n");
+    let parsed = MessageParser::new().parse_headers(headers.as_bytes());
+    let flags = flag_update(message, message_id);
+
+    Envelope {
+        id: flags.message_id,
+        account_id: account_id.clone(),
+        folder_id: folder_id.clone(),
+        subject: message.headers.get("Subject").cloned(),
+        from: parsed.as_ref().and_then(|m| mime::email_address(m.from())),
+        to: parsed.as_ref().and_then(|m| mime::email_address(m.to())),
+        cc: parsed.as_ref().and_then(|m| mime::email_address(m.cc())),
+        bcc: parsed.as_ref().and_then(|m| mime::email_address(m.bcc())),
+        reply_to: parsed.as_ref().and_then(|m| mime::email_address(m.reply_to())),
+        date: DateTime::from_timestamp(message.timestamp, 0).unwrap_or_else(Utc::now),
+        is_read: flags.is_read,
+        is_starred: flags.is_starred,
+        is_flagged: flags.is_flagged,
+        is_draft: flags.is_draft,
+        is_deleted: flags.is_deleted,
+        has_attachments: message.tags.iter().any(|tag| tag == "attachment"),
+        size: None,
+        keywords: flags.keywords,
+        labels: message.tags.iter().cloned().collect::<BTreeSet<_>>(),
+        snoozed_until: None,
+        thread_id: None,
+        remote_id: None,
+        message_id: Some(message.id.clone()),
+        in_reply_to: Vec::new(),
+        references: Vec::new(),
+        unsubscribe: message.headers.get("List-Unsubscribe").and_then(|header| {
+            Unsubscribe::parse(header, message.headers.get("List-Unsubscribe-Post").map(String::as_str))
+        }),
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    }
+}
+
+/// Builds the MIME tree with notmuch's part numbers as ids, which
+/// `get_message_part` passes to `--part`.
+pub(crate) fn structure(body: &[Part]) -> MessageStructure {
+    match body {
+        [root] if root.is_multipart() => MessageStructure {
+            id: None,
+            ..node(root)
+        },
+        [root] => node(root),
+        // Not a MIME tree notmuch produces, but keep whatever is there.
+        parts => MessageStructure {
+            id: None,
+            content_type: "multipart/mixed".to_string(),
+            filename: None,
+            size: None,
+            is_attachment: false,
+            content_id: None,
+            children: parts.iter().map(node).collect(),
+        },
+    }
+}
+
+fn node(part: &Part) -> MessageStructure {
+    let multipart = part.is_multipart();
+    let size = match &part.content {
+        Some(Value::String(text)) => part.content_length.or(Some(text.len() as u64)),
+        _ => part.content_length,
+    };
+    MessageStructure {
+        id: Some(part.part_id()),
+        content_type: part.content_type.to_ascii_lowercase(),
+        filename: part.filename.clone(),
+        size: if multipart { None } else { size },
+        is_attachment: part
+            .content_disposition
+            .as_deref()
+            .is_some_and(|disposition| disposition.eq_ignore_ascii_case("attachment")),
+        content_id: part.content_id.clone(),
+        children: part.children().iter().map(node).collect(),
+    }
+}
+
+/// The content of a text part, which notmuch converts to UTF-8. `None` for
+/// other parts, whose content has to be fetched raw.
+pub(crate) fn text_content(part: &Part) -> Option<MessageContent> {
+    let Some(Value::String(text)) = &part.content else {
+        return None;
+    };
+    Some(if part.content_type.eq_ignore_ascii_case("text/html") {
+        MessageContent::Html(text.clone())
+    } else {
+        MessageContent::Text(text.clone())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use mailiner_core::EmailAddress;
+
+    use super::*;
+
+    const SHOW: &str = r#"[[[
+        {"id": "a@example.com", "match": false, "timestamp": 1699999000, "tags": ["inbox"],
+         "headers": {"Subject": "Earlier"}},
+        [[
+            {"id": "b@example.com", "match": true, "timestamp": 1700000000,
+             "tags": ["attachment", "flagged", "inbox", "unread", "work"],
+             "headers": {"Subject": "Grüße", "From": "Jan <jan@example.com>",
+                         "To": "ana@example.com, Bo <bo@example.com>"},
+             "body": [{"id": 1, "content-type": "multipart/mixed", "content": [
+                {"id": 2, "content-type": "text/plain", "content": "Hi\n"},
+                {"id": 3, "content-type": "application/pdf", "content-disposition": "attachment",
+                 "filename": "report.pdf", "content-length": 4096},
+                {"id": 4, "content-type": "message/rfc822", "content": [
+                    {"headers": {"Subject": "Fwd"}, "body": [
+                        {"id": 5, "content-type": "text/html", "content": "<p>Hi</p>"}
+                    ]}
+                ]}
+             ]}]},
+            []
+        ]]
+    ]]]"#;
+
+    #[test]
+    fn messages_from_show() {
+        let messages = parse_messages(SHOW.as_bytes()).unwrap();
+        assert_eq!(messages.len(), 1);
+        let message = &messages[0];
+        let envelope = envelope(
+            message,
+            MessageId::new("INBOX;ID=b@example.com"),
+            &AccountId::new("notmuch-jan@example.com"),
+            &FolderId::new("INBOX"),
+        );
+        assert_eq!(envelope.subject.as_deref(), Some("Grüße"));
+        assert!(matches!(
+            envelope.to,
+            Some(EmailAddress::List(to)) if to.len() == 2 && to[1].name.as_deref() == Some("Bo")
+        ));
+        assert_eq!(envelope.date.timestamp(), 1_700_000_000);
+        assert!(!envelope.is_read && envelope.is_flagged && envelope.has_attachments);
+        assert_eq!(
+            envelope.keywords,
+            BTreeSet::from(["attachment", "inbox", "work"].map(String::from))
+        );
+        assert!(envelope.labels.contains("flagged"));
+
+        let tree = structure(&message.body);
+        assert_eq!(tree.id, None);
+        assert_eq!(
+            tree.find("text/plain").unwrap().id,
+            Some(MessagePartId::new("2"))
+        );
+        let attachments = tree.attachments();
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(attachments[0].size, Some(4096));
+        assert_eq!(attachments[1].children[0].id, Some(MessagePartId::new("5")));
+        assert!(matches!(
+            text_content(&message.body[0].children()[0]),
+            Some(MessageContent::Text(text)) if text == "Hi\n"
+        ));
+    }
+}
@@ -0,0 +1,56 @@
+//! SMTP AUTH (RFC 4954).
+
+use base64::prelude::*;
+use mailiner_core::AuthMethod;
+
+/// The SASL mechanisms the server advertises in its EHLO reply.
+pub(crate) fn mechanisms(extensions: &[String]) -> Vec<String> {
+    extensions
+        .iter()
+        .filter_map(|extension| {
+            let (keyword, params) = extension.split_once(' ')?;
+            keyword.eq_ignore_ascii_case("AUTH").then_some(params)
+        })
+        .flat_map(|params| params.split_whitespace())
+        .map(|mechanism| mechanism.to_ascii_uppercase())
+        .collect()
+}
+
+/// Picks the method for logging in with a password, preferring PLAIN.
+pub(crate) fn password_method(mechanisms: &[String]) -> Option<AuthMethod> {
+    if mechanisms.iter().any(|m| m == "PLAIN") {
+        Some(AuthMethod::Plain)
+    } else if mechanisms.iter().any(|m| m == "LOGIN") {
+        Some(AuthMethod::Login)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn plain_response(username: &str, password: &str) -> String {
+    BASE64_STANDARD.encode(format!("\0{}\0{}", username, password))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advertised_mechanisms() {
+        let extensions = [
+            "smtp.example.com",
+            "SIZE 35882577",
+            "AUTH LOGIN XOAUTH2 PLAIN",
+        ]
+        .map(String::from);
+        let mechanisms = mechanisms(&extensions);
+        assert_eq!(mechanisms, ["LOGIN", "XOAUTH2", "PLAIN"]);
+        assert_eq!(password_method(&mechanisms), Some(AuthMethod::Plain));
+        assert_eq!(
+            password_method(&["LOGIN".to_string()]),
+            Some(AuthMethod::Login)
+        );
+        assert_eq!(password_method(&[]), None);
+        assert_eq!(plain_response("tim", "tanstaaf"), "AHRpbQB0YW5zdGFhZg==");
+    }
+}
@@ -0,0 +1,572 @@
+//! Message submission over SMTP (RFC 6409).
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use base64::prelude::*;
+use mailiner_core::oauth::{oauthbearer_response, xoauth2_response, TokenProvider};
+use mailiner_core::tls::MaybeTlsStream;
+use mailiner_core::{
+    AuthMethod, ConnectionSecurity, MailinerError, MessageSender, Result as MailinerResult,
+};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::protocol::{data_payload, Connection};
+
+mod auth;
+mod protocol;
+
+#[derive(Error, Debug)]
+pub enum SmtpError {
+    #[error("Connection error: {0}")]
+    Connection(String),
+    #[error("Authentication error: {0}")]
+    Authentication(String),
+    /// A 5xx reply: sending the same again fails again.
+    #[error("Rejected by the server: {0}")]
+    Rejected(String),
+    /// A 4xx reply: the server may accept it later.
+    #[error("Temporarily refused by the server: {0}")]
+    Temporary(String),
+    #[error("Invalid data: {0}")]
+    InvalidData(String),
+    #[error("Not connected")]
+    NotConnected,
+    #[error("TLS error: {0}")]
+    Tls(String),
+}
+
+impl From<SmtpError> for MailinerError {
+    fn from(err: SmtpError) -> Self {
+        match err {
+            SmtpError::Connection(msg) => MailinerError::Connector(msg),
+            SmtpError::Authentication(msg) => MailinerError::Authentication(msg),
+            SmtpError::Rejected(msg) => MailinerError::Rejected(msg),
+            SmtpError::Temporary(msg) => MailinerError::Connector(msg),
+            SmtpError::InvalidData(msg) => MailinerError::InvalidData(msg),
+            SmtpError::NotConnected => MailinerError::Connector("Not connected".to_string()),
+            SmtpError::Tls(msg) => MailinerError::Tls(msg),
+        }
+    }
+}
+
+/// For the errors of the shared parts, e.g. the TLS handshake.
+impl From<MailinerError> for SmtpError {
+    fn from(err: MailinerError) -> Self {
+        match err {
+            MailinerError::Connector(msg) => SmtpError::Connection(msg),
+            MailinerError::Authentication(msg) => SmtpError::Authentication(msg),
+            MailinerError::Tls(msg) => SmtpError::Tls(msg),
+            err => SmtpError::Connection(err.to_string()),
+        }
+    }
+}
+
+pub struct SmtpConnector<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send,
+{
+    host: String,
+    port: u16,
+    username: String,
+    /// `None` picks PLAIN or LOGIN, whichever the server supports.
+    auth_method: Option<AuthMethod>,
+    /// Where access tokens come from, for the OAuth methods.
+    tokens: Option<Arc<TokenProvider>>,
+    security: ConnectionSecurity,
+    /// Whether the stream passed to `connect` is encrypted already, e.g. a
+    /// WebSocket to a proxy that connects to the server with TLS.
+    external_tls: bool,
+    /// The name the client introduces itself with in EHLO.
+    client_name: String,
+    connection: Mutex<Option<Connection<MaybeTlsStream<S>>>>,
+    /// The extensions from the server's EHLO reply, e.g. `SIZE 35882577`.
+    extensions: std::sync::Mutex<Vec<String>>,
+}
+
+impl<S> SmtpConnector<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send,
+{
+    pub fn new(host: String, port: u16, username: String) -> Self {
+        Self {
+            host,
+            port,
+            username,
+            auth_method: None,
+            tokens: None,
+            security: ConnectionSecurity::Tls,
+            external_tls: false,
+            client_name: "localhost".to_string(),
+            connection: Mutex::new(None),
+            extensions: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Sets how to log in. With XOAUTH2 and OAUTHBEARER the credentials
+    /// passed to `authenticate` are the access token.
+    pub fn with_auth_method(mut self, auth_method: AuthMethod) -> Self {
+        self.auth_method = Some(auth_method);
+        self
+    }
+
+    /// Logs in with access tokens from `tokens` instead of the credentials
+    /// passed to `authenticate`, with XOAUTH2 unless OAUTHBEARER is set. A
+    /// token the server refuses is refreshed and the login retried once.
+    pub fn with_token_provider(mut self, tokens: Arc<TokenProvider>) -> Self {
+        self.tokens = Some(tokens);
+        self
+    }
+
+    /// Sets how the connection is secured: implicit TLS (usually port 465)
+    /// or STARTTLS (usually port 587).
+    pub fn with_security(mut self, security: ConnectionSecurity) -> Self {
+        self.security = security;
+        self
+    }
+
+    /// Uses the stream passed to `connect` as it is, because it is encrypted
+    /// already.
+    pub fn with_external_tls(mut self, external_tls: bool) -> Self {
+        self.external_tls = external_tls;
+        self
+    }
+
+    /// Sets the domain name sent in EHLO.
+    pub fn with_client_name(mut self, client_name: String) -> Self {
+        self.client_name = client_name;
+        self
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    fn has_extension(&self, keyword: &str) -> bool {
+        self.extensions.lock().unwrap().iter().any(|extension| {
+            let name = extension.split(' ').next().unwrap_or_default();
+            name.eq_ignore_ascii_case(keyword)
+        })
+    }
+
+    /// Greets the server and stores the extensions it supports.
+    async fn ehlo(&self, connection: &mut Connection<MaybeTlsStream<S>>) -> Result<(), SmtpError> {
+        let reply = connection
+            .command(&format!("EHLO {}", self.client_name))
+            .await?
+            .expect(250, "EHLO failed")?;
+        // The first line is the server's name.
+        *self.extensions.lock().unwrap() = reply.lines.into_iter().skip(1).collect();
+        Ok(())
+    }
+
+    async fn open(&self, stream: S) -> Result<Connection<MaybeTlsStream<S>>, SmtpError> {
+        let mut stream = MaybeTlsStream::Plain(stream);
+        match self.security {
+            ConnectionSecurity::Tls if !self.external_tls => {
+                info!("Establishing TLS connection...");
+                stream = stream.start_tls(&self.host).await?;
+            }
+            ConnectionSecurity::Tls | ConnectionSecurity::StartTls => {}
+            ConnectionSecurity::None => {
+                return Err(SmtpError::Connection(
+                    "Unencrypted connections are not supported".to_string(),
+                ));
+            }
+        }
+
+        let mut connection = Connection::new(stream);
+        connection
+            .read_reply()
+            .await?
+            .expect(220, "Unexpected greeting")?;
+        self.ehlo(&mut connection).await?;
+
+        if self.security == ConnectionSecurity::StartTls && !self.external_tls {
+            if !self.has_extension("STARTTLS") {
+                return Err(SmtpError::Connection(
+                    "Server does not support STARTTLS".to_string(),
+                ));
+            }
+            info!("Negotiating STARTTLS...");
+            connection
+                .command("STARTTLS")
+                .await?
+                .expect(220, "STARTTLS failed")?;
+            let stream = connection.into_inner().start_tls(&self.host).await?;
+            connection = Connection::new(stream);
+            // The extensions may differ once the connection is secure.
+            self.ehlo(&mut connection).await?;
+        }
+        Ok(connection)
+    }
+
+    async fn login(
+        &self,
+        connection: &mut Connection<MaybeTlsStream<S>>,
+        credentials: &str,
+    ) -> Result<(), SmtpError> {
+        let method = match self.auth_method {
+            Some(method) => method,
+            None if self.tokens.is_some() => AuthMethod::XOAuth2,
+            None => {
+                let mechanisms = auth::mechanisms(&self.extensions.lock().unwrap());
+                auth::password_method(&mechanisms).ok_or_else(|| {
+                    SmtpError::Authentication(
+                        "Server offers no supported authentication method".to_string(),
+                    )
+                })?
+            }
+        };
+
+        let reply = match method {
+            AuthMethod::Plain => {
+                connection
+                    .command(&format!(
+                        "AUTH PLAIN {}",
+                        auth::plain_response(&self.username, credentials)
+                    ))
+                    .await?
+            }
+            AuthMethod::Login => {
+                let reply = connection.command("AUTH LOGIN").await?;
+                if reply.code != 334 {
+                    return Err(SmtpError::Authentication(reply.to_string()));
+                }
+                let reply = connection
+                    .command(&BASE64_STANDARD.encode(&self.username))
+                    .await?;
+                if reply.code != 334 {
+                    return Err(SmtpError::Authentication(reply.to_string()));
+                }
+                connection
+                    .command(&BASE64_STANDARD.encode(credentials))
+                    .await?
+            }
+            AuthMethod::XOAuth2 | AuthMethod::OAuthBearer => {
+                let command = if method == AuthMethod::XOAuth2 {
+                    format!(
+                        "AUTH XOAUTH2 {}",
+                        BASE64_STANDARD.encode(xoauth2_response(&self.username, credentials))
+                    )
+                } else {
+                    format!(
+                        "AUTH OAUTHBEARER {}",
+                        BASE64_STANDARD.encode(oauthbearer_response(
+                            &self.username,
+                            &self.host,
+                            self.port,
+                            credentials
+                        ))
+                    )
+                };
+                let reply = connection.command(&command).await?;
+                if reply.code == 334 {
+                    // The error details, which have to be answered before
+                    // the server fails the command: with an empty line for
+                    // XOAUTH2, with ^A for OAUTHBEARER (RFC 7628).
+                    let details = reply.lines.join("");
+                    let details = BASE64_STANDARD
+                        .decode(details.trim())
+                        .map(|details| String::from_utf8_lossy(&details).into_owned())
+                        .unwrap_or(details);
+                    let answer = if method == AuthMethod::XOAuth2 {
+                        ""
+                    } else {
+                        "AQ=="
+                    };
+                    connection.command(answer).await?;
+                    return Err(SmtpError::Authentication(details));
+                }
+                reply
+            }
+            AuthMethod::CramMd5 => {
+                return Err(SmtpError::Authentication(format!(
+                    "{:?} is not supported for SMTP",
+                    method
+                )));
+            }
+        };
+        if reply.code != 235 {
+            return Err(SmtpError::Authentication(reply.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Logs in with a token from `tokens`, and once more with a refreshed
+    /// one if the server refuses it, e.g. because it was revoked early.
+    async fn login_with_token(
+        &self,
+        connection: &mut Connection<MaybeTlsStream<S>>,
+        tokens: &TokenProvider,
+    ) -> MailinerResult<()> {
+        let token = tokens.access_token(false).await?;
+        match self.login(connection, &token).await {
+            Err(SmtpError::Authentication(reason)) => {
+                info!("Access token refused, refreshing it: {}", reason);
+                let token = tokens.access_token(true).await?;
+                Ok(self.login(connection, &token).await?)
+            }
+            result => Ok(result?),
+        }
+    }
+
+    async fn submit(
+        &self,
+        connection: &mut Connection<MaybeTlsStream<S>>,
+        from: &str,
+        recipients: &[String],
+        message: &[u8],
+    ) -> Result<(), SmtpError> {
+        let mut mail_from = format!("MAIL FROM:<{}>", from);
+        if self.has_extension("SIZE") {
+            mail_from.push_str(&format!(" SIZE={}", message.len()));
+        }
+        if !message.is_ascii() {
+            if self.has_extension("8BITMIME") {
+                mail_from.push_str(" BODY=8BITMIME");
+            }
+            if self.has_extension("SMTPUTF8") {
+                mail_from.push_str(" SMTPUTF8");
+            }
+        }
+        let reply = connection.command(&mail_from).await?;
+        // 530: authentication required, 535: the credentials are not valid
+        // anymore, e.g. an expired token.
+        if reply.code == 530 || reply.code == 535 {
+            return Err(SmtpError::Authentication(reply.to_string()));
+        }
+        reply.expect(250, "Sender rejected")?;
+
+        for recipient in recipients {
+            let reply = connection
+                .command(&format!("RCPT TO:<{}>", recipient))
+                .await?;
+            // 251: the server forwards the message.
+            if reply.code != 250 && reply.code != 251 {
+                return Err(reply.refused(&format!("Recipient {} rejected", recipient)));
+            }
+        }
+
+        connection
+            .command("DATA")
+            .await?
+            .expect(354, "DATA failed")?;
+        connection.write(&data_payload(message)).await?;
+        connection
+            .read_reply()
+            .await?
+            .expect(250, "Message rejected")?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S> MessageSender<S> for SmtpConnector<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync + 'static,
+{
+    async fn connect(&self, stream: S) -> MailinerResult<()> {
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() {
+            *connection = Some(self.open(stream).await?);
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> MailinerResult<()> {
+        let mut connection = self.connection.lock().await;
+        if let Some(mut connection) = connection.take() {
+            // The connection is closed either way.
+            let _ = connection.command("QUIT").await;
+        }
+        Ok(())
+    }
+
+    async fn authenticate(&self, credentials: &str) -> MailinerResult<()> {
+        let mut connection = self.connection.lock().await;
+        let connection = connection.as_mut().ok_or(SmtpError::NotConnected)?;
+        match &self.tokens {
+            Some(tokens) => self.login_with_token(connection, tokens).await,
+            None => Ok(self.login(connection, credentials).await?),
+        }
+    }
+
+    async fn send(&self, from: &str, recipients: &[String], message: &[u8]) -> MailinerResult<()> {
+        if recipients.is_empty() {
+            return Err(SmtpError::InvalidData("The message has no recipients".to_string()).into());
+        }
+        let mut connection = self.connection.lock().await;
+        let connection = connection.as_mut().ok_or(SmtpError::NotConnected)?;
+        let mut result = self.submit(connection, from, recipients, message).await;
+        if let (Err(SmtpError::Authentication(_)), Some(tokens)) = (&result, &self.tokens) {
+            // The server dropped the login, so log in again with a fresh token.
+            self.login_with_token(connection, tokens).await?;
+            result = self.submit(connection, from, recipients, message).await;
+        }
+        if let Err(SmtpError::Rejected(_) | SmtpError::Temporary(_)) = result {
+            // Abort the transaction, so that the connection can be reused.
+            connection.command("RSET").await?;
+        }
+        Ok(result?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mailiner_core::oauth::OAuthToken;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream};
+
+    use super::*;
+
+    /// Plays the server side: for each step, reads the expected lines from
+    /// the client and sends the reply.
+    async fn serve(server: DuplexStream, script: Vec<(&'static str, &'static str)>) {
+        let mut server = BufReader::new(server);
+        for (expected, reply) in script {
+            for expected in expected.lines() {
+                let mut line = String::new();
+                server.read_line(&mut line).await.unwrap();
+                assert_eq!(line.trim_end_matches("\r\n"), expected);
+            }
+            server.get_mut().write_all(reply.as_bytes()).await.unwrap();
+        }
+    }
+
+    fn connector() -> SmtpConnector<DuplexStream> {
+        SmtpConnector::new(
+            "smtp.example.com".to_string(),
+            465,
+            "jan@example.com".to_string(),
+        )
+        .with_external_tls(true)
+    }
+
+    #[tokio::test]
+    async fn submits_a_message() {
+        let (client, server) = tokio::io::duplex(1024);
+        let server = tokio::spawn(serve(
+            server,
+            vec![
+                ("", "220 smtp.example.com ESMTP\r\n"),
+                (
+                    "EHLO localhost",
+                    "250-smtp.example.com\r\n250-SIZE 1000\r\n250 AUTH LOGIN PLAIN\r\n",
+                ),
+                (
+                    "AUTH PLAIN AGphbkBleGFtcGxlLmNvbQBzZWNyZXQ=",
+                    "235 2.7.0 Accepted\r\n",
+                ),
+                ("MAIL FROM:<jan@example.com> SIZE=21", "250 OK\r\n"),
+                ("RCPT TO:<eva@example.com>", "250 OK\r\n"),
+                ("DATA", "354 Go ahead\r\n"),
+                ("Subject: Hi\n\n..dot\n.", "250 Queued\r\n"),
+                ("QUIT", "221 Bye\r\n"),
+            ],
+        ));
+
+        let smtp = connector();
+        smtp.connect(client).await.unwrap();
+        smtp.authenticate("secret").await.unwrap();
+        smtp.send(
+            "jan@example.com",
+            &["eva@example.com".to_string()],
+            b"Subject: Hi\r\n\r\n.dot\r\n",
+        )
+        .await
+        .unwrap();
+        smtp.disconnect().await.unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejected_recipient_resets_the_transaction() {
+        let (client, server) = tokio::io::duplex(1024);
+        let server = tokio::spawn(serve(
+            server,
+            vec![
+                ("", "220 smtp.example.com ESMTP\r\n"),
+                ("EHLO localhost", "250 smtp.example.com\r\n"),
+                ("MAIL FROM:<jan@example.com>", "250 OK\r\n"),
+                ("RCPT TO:<nobody@example.com>", "550 5.1.1 No such user\r\n"),
+                ("RSET", "250 OK\r\n"),
+            ],
+        ));
+
+        let smtp = connector();
+        smtp.connect(client).await.unwrap();
+        let error = smtp
+            .send(
+                "jan@example.com",
+                &["nobody@example.com".to_string()],
+                b"Hi\r\n",
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(error, MailinerError::Rejected(_)), "{}", error);
+        assert!(error.to_string().contains("No such user"), "{}", error);
+        server.await.unwrap();
+    }
+
+    struct Refresher;
+
+    #[async_trait]
+    impl mailiner_core::oauth::TokenRefresher for Refresher {
+        async fn refresh(&self, refresh_token: &str) -> MailinerResult<OAuthToken> {
+            assert_eq!(refresh_token, "refresh");
+            Ok(OAuthToken {
+                access_token: "new".to_string(),
+                refresh_token: None,
+                expires_at: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn refreshes_a_refused_token() {
+        let (client, server) = tokio::io::duplex(1024);
+        let server = tokio::spawn(serve(
+            server,
+            vec![
+                ("", "220 smtp.example.com ESMTP\r\n"),
+                ("EHLO localhost", "250 smtp.example.com\r\n"),
+                (
+                    "AUTH XOAUTH2 dXNlcj1qYW5AZXhhbXBsZS5jb20BYXV0aD1CZWFyZXIgb2xkAQE=",
+                    "334 eyJzdGF0dXMiOiI0MDEifQ==\r\n",
+                ),
+                // The empty line answering the error details.
+                ("\n", "535 5.7.8 Invalid credentials\r\n"),
+                (
+                    "AUTH XOAUTH2 dXNlcj1qYW5AZXhhbXBsZS5jb20BYXV0aD1CZWFyZXIgbmV3AQE=",
+                    "235 2.7.0 Accepted\r\n",
+                ),
+            ],
+        ));
+
+        let tokens = Arc::new(TokenProvider::new(
+            OAuthToken {
+                access_token: "old".to_string(),
+                refresh_token: Some("refresh".to_string()),
+                expires_at: None,
+            },
+            Arc::new(Refresher),
+        ));
+        let smtp = connector().with_token_provider(tokens.clone());
+        smtp.connect(client).await.unwrap();
+        smtp.authenticate("").await.unwrap();
+        assert_eq!(tokens.token().await.access_token, "new");
+        assert_eq!(
+            tokens.token().await.refresh_token.as_deref(),
+            Some("refresh")
+        );
+        server.await.unwrap();
+    }
+}
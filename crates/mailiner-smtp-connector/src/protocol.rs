@@ -0,0 +1,172 @@
+//! The SMTP command/reply exchange (RFC 5321).
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufStream};
+
+use crate::SmtpError;
+
+/// A reply of the server, e.g. `250-smtp.example.com` followed by
+/// `250 AUTH PLAIN LOGIN`.
+#[derive(Debug)]
+pub(crate) struct Reply {
+    pub(crate) code: u16,
+    /// The text of each line, without the code.
+    pub(crate) lines: Vec<String>,
+}
+
+impl Reply {
+    /// Fails unless the reply has the expected code.
+    pub(crate) fn expect(self, code: u16, context: &str) -> Result<Self, SmtpError> {
+        if self.code == code {
+            Ok(self)
+        } else {
+            Err(self.refused(context))
+        }
+    }
+
+    /// The error for an unexpected reply, which is temporary for 4xx codes.
+    pub(crate) fn refused(&self, context: &str) -> SmtpError {
+        let msg = format!("{}: {}", context, self);
+        if (400..500).contains(&self.code) {
+            SmtpError::Temporary(msg)
+        } else {
+            SmtpError::Rejected(msg)
+        }
+    }
+}
+
+impl std::fmt::Display for Reply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.code, self.lines.join(" "))
+    }
+}
+
+/// A connection to the server, after the greeting.
+pub(crate) struct Connection<T> {
+    stream: BufStream<T>,
+}
+
+impl<T> Connection<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    pub(crate) fn new(stream: T) -> Self {
+        Self {
+            stream: BufStream::new(stream),
+        }
+    }
+
+    /// Returns the underlying stream, e.g. to start TLS after STARTTLS. The
+    /// server sends nothing until the handshake, so nothing is buffered.
+    pub(crate) fn into_inner(self) -> T {
+        self.stream.into_inner()
+    }
+
+    pub(crate) async fn read_reply(&mut self) -> Result<Reply, SmtpError> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            let read = self
+                .stream
+                .read_line(&mut line)
+                .await
+                .map_err(|e| SmtpError::Connection(format!("Failed to read reply: {}", e)))?;
+            if read == 0 {
+                return Err(SmtpError::Connection(
+                    "Connection closed by the server".to_string(),
+                ));
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            let code = line
+                .get(..3)
+                .and_then(|code| code.parse().ok())
+                .ok_or_else(|| SmtpError::InvalidData(format!("Invalid reply: {}", line)))?;
+            lines.push(line.get(4..).unwrap_or_default().to_string());
+            // The last line has a space after the code, the others a dash.
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(Reply { code, lines });
+            }
+        }
+    }
+
+    pub(crate) async fn write(&mut self, data: &[u8]) -> Result<(), SmtpError> {
+        self.stream
+            .write_all(data)
+            .await
+            .map_err(|e| SmtpError::Connection(format!("Failed to send: {}", e)))?;
+        self.stream
+            .flush()
+            .await
+            .map_err(|e| SmtpError::Connection(format!("Failed to send: {}", e)))
+    }
+
+    /// Sends a command and reads the reply.
+    pub(crate) async fn command(&mut self, command: &str) -> Result<Reply, SmtpError> {
+        self.write(format!("{}\r\n", command).as_bytes()).await?;
+        self.read_reply().await
+    }
+}
+
+/// Prepares a message for DATA: line endings are normalized to CRLF, lines
+/// starting with a dot get another one, and the terminating `.` is added.
+pub(crate) fn data_payload(message: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(message.len() + 5);
+    let mut lines = message.split(|&byte| byte == b'\n').peekable();
+    while let Some(line) = lines.next() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if lines.peek().is_none() && line.is_empty() {
+            break;
+        }
+        if line.starts_with(b".") {
+            payload.push(b'.');
+        }
+        payload.extend_from_slice(line);
+        payload.extend_from_slice(b"\r\n");
+    }
+    payload.extend_from_slice(b".\r\n");
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dot_stuffing() {
+        assert_eq!(
+            data_payload(b"Subject: Hi\r\n\r\n.\n..leading dots\nlast line"),
+            b"Subject: Hi\r\n\r\n..\r\n...leading dots\r\nlast line\r\n.\r\n"
+        );
+        assert_eq!(data_payload(b"Body\r\n"), b"Body\r\n.\r\n");
+    }
+
+    #[tokio::test]
+    async fn multiline_reply() {
+        let (client, mut server) = tokio::io::duplex(256);
+        server
+            .write_all(b"250-smtp.example.com\r\n250-SIZE 35882577\r\n250 AUTH PLAIN LOGIN\r\n")
+            .await
+            .unwrap();
+        let reply = Connection::new(client).read_reply().await.unwrap();
+        assert_eq!(reply.code, 250);
+        assert_eq!(
+            reply.lines,
+            ["smtp.example.com", "SIZE 35882577", "AUTH PLAIN LOGIN"]
+        );
+    }
+
+    #[test]
+    fn temporary_replies() {
+        let reply = |code| Reply {
+            code,
+            lines: vec!["Try again later".to_string()],
+        };
+        assert!(matches!(
+            reply(451).expect(250, "DATA"),
+            Err(SmtpError::Temporary(_))
+        ));
+        assert!(matches!(
+            reply(554).expect(250, "DATA"),
+            Err(SmtpError::Rejected(_))
+        ));
+    }
+}